@@ -1,25 +1,31 @@
 use crate::{
-    components::select::{Select, SelectOption, SelectOptionProps},
+    components::bulk_user_actions::{add_users_to_group, AddUsersToGroup},
     infra::api::HostService,
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
 use std::collections::HashSet;
 use yew::{
+    html::InputData,
     prelude::*,
-    services::{fetch::FetchTask, ConsoleService},
+    services::{
+        fetch::FetchTask,
+        timeout::{TimeoutService, TimeoutTask},
+        ConsoleService,
+    },
 };
 use yewtil::NeqAssign;
 
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "../schema.graphql",
-    query_path = "queries/add_user_to_group.graphql",
+    query_path = "queries/add_group_manager.graphql",
     response_derives = "Debug",
     variables_derives = "Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
-pub struct AddUserToGroup;
+pub struct AddGroupManager;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -27,125 +33,333 @@ pub struct AddUserToGroup;
     query_path = "queries/list_users.graphql",
     response_derives = "Debug,Clone,PartialEq,Eq,Hash",
     variables_derives = "Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct ListUserNames;
 pub type User = list_user_names::ListUserNamesUsers;
+use list_user_names::RequestFilter;
 
+/// How long to wait after the last key press before searching.
+const SEARCH_DELAY_MS: u64 = 300;
+/// The most users listed for a search: the search should be refined past that.
+const MAX_SEARCH_RESULTS: i64 = 20;
+
+fn empty_filter() -> RequestFilter {
+    RequestFilter {
+        any: None,
+        all: None,
+        not: None,
+        eq: None,
+        member_of: None,
+        member_of_id: None,
+        expires_before: None,
+        search: None,
+    }
+}
+
+/// A list of the users matching a search, as it's typed, to pick several of them and add them all
+/// to the group at once.
 pub struct AddGroupMemberComponent {
     link: ComponentLink<Self>,
     props: Props,
-    /// The list of existing users, initially not loaded.
+    /// The users matching the search, if one was made.
     user_list: Option<Vec<User>>,
-    /// The currently selected user.
-    selected_user: Option<User>,
+    /// The users whose ID, email or name contains it are listed, ignoring the case.
+    search: String,
+    /// Waits for the user to stop typing before searching.
+    search_timeout: Option<TimeoutTask>,
+    search_task: Option<FetchTask>,
+    /// The users picked, in the order they were picked.
+    selected_users: Vec<User>,
+    /// The managers still to add: there's no mutation to add several at once.
+    pending_managers: Vec<User>,
+    /// The users that could not be added, with the reason.
+    failures: Vec<(String, String)>,
     // Used to keep the request alive long enough.
     task: Option<FetchTask>,
 }
 
 pub enum Msg {
-    UserListResponse(Result<list_user_names::ResponseData>),
-    SubmitAddMember,
-    AddMemberResponse(Result<add_user_to_group::ResponseData>),
-    SelectionChanged(Option<SelectOptionProps>),
+    SearchChanged(String),
+    Search,
+    /// The users matching the search, which may have changed since.
+    UserListResponse(String, Result<list_user_names::ResponseData>),
+    ToggleUser(User),
+    SubmitAddMembers,
+    AddMembersResponse(Result<add_users_to_group::ResponseData>),
+    AddManagerResponse(User, Result<add_group_manager::ResponseData>),
+    ClearFailures,
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
 pub struct Props {
     pub group_id: i64,
+    /// Adds the users to the managers of the group instead of the members.
+    #[prop_or_default]
+    pub manager: bool,
+    /// The users already in the group (or managing it), hidden from the list.
     pub users: Vec<User>,
+    /// Called for each user added.
     pub on_user_added_to_group: Callback<User>,
     pub on_error: Callback<Error>,
 }
 
 impl AddGroupMemberComponent {
-    fn get_user_list(&mut self) {
-        self.task = HostService::graphql_query::<ListUserNames>(
-            list_user_names::Variables { filters: None },
-            self.link.callback(Msg::UserListResponse),
-            "Error trying to fetch user list",
-        )
-        .map_err(|e| {
-            ConsoleService::log(&e.to_string());
-            e
-        })
-        .ok();
+    fn search_users(&mut self) -> Result<()> {
+        let search = self.search.trim().to_string();
+        let mut filters = vec![RequestFilter {
+            search: Some(search.clone()),
+            ..empty_filter()
+        }];
+        // Only the users that are not already members. The managers can be any user.
+        if !self.props.manager {
+            filters.push(RequestFilter {
+                not: Some(Box::new(RequestFilter {
+                    member_of_id: Some(self.props.group_id),
+                    ..empty_filter()
+                })),
+                ..empty_filter()
+            });
+        }
+        self.search_task = Some(HostService::graphql_query::<ListUserNames>(
+            list_user_names::Variables {
+                filters: Some(RequestFilter {
+                    all: Some(filters),
+                    ..empty_filter()
+                }),
+                // One more, to tell whether there are more.
+                limit: Some(MAX_SEARCH_RESULTS + 1),
+            },
+            self.link
+                .callback(move |response| Msg::UserListResponse(search.clone(), response)),
+            "Error trying to search users",
+        )?);
+        Ok(())
+    }
+
+    fn submit_add_members(&mut self) -> Result<bool> {
+        if self.selected_users.is_empty() {
+            return Ok(false);
+        }
+        self.failures.clear();
+        if self.props.manager {
+            self.pending_managers = std::mem::take(&mut self.selected_users);
+            self.pending_managers.reverse();
+            self.add_next_manager()?;
+            return Ok(true);
+        }
+        self.task = Some(HostService::graphql_query::<AddUsersToGroup>(
+            add_users_to_group::Variables {
+                user_ids: self.selected_users.iter().map(|u| u.id.clone()).collect(),
+                group_id: self.props.group_id,
+            },
+            self.link.callback(Msg::AddMembersResponse),
+            "Error trying to add the users to the group",
+        )?);
+        Ok(true)
     }
 
-    fn submit_add_member(&mut self) -> Result<bool> {
-        let user_id = match self.selected_user.clone() {
-            None => return Ok(false),
-            Some(user) => user.id,
+    /// Adds the managers one after the other, until there are none left.
+    fn add_next_manager(&mut self) -> Result<()> {
+        let user = match self.pending_managers.pop() {
+            None => {
+                self.task = None;
+                return Ok(());
+            }
+            Some(user) => user,
         };
-        self.task = HostService::graphql_query::<AddUserToGroup>(
-            add_user_to_group::Variables {
-                user: user_id,
+        self.task = Some(HostService::graphql_query::<AddGroupManager>(
+            add_group_manager::Variables {
+                user: user.id.clone(),
                 group: self.props.group_id,
             },
-            self.link.callback(Msg::AddMemberResponse),
-            "Error trying to initiate adding the user to a group",
-        )
-        .map_err(|e| {
-            ConsoleService::log(&e.to_string());
-            e
-        })
-        .ok();
-        Ok(true)
+            self.link
+                .callback(move |response| Msg::AddManagerResponse(user.clone(), response)),
+            "Error trying to add the manager to the group",
+        )?);
+        Ok(())
     }
 
     fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
         match msg {
-            Msg::UserListResponse(response) => {
+            Msg::SearchChanged(search) => {
+                self.search = search;
+                if self.search.trim().is_empty() {
+                    self.search_timeout = None;
+                    self.search_task = None;
+                    self.user_list = None;
+                } else {
+                    self.search_timeout = Some(TimeoutService::spawn(
+                        std::time::Duration::from_millis(SEARCH_DELAY_MS),
+                        self.link.callback(|_| Msg::Search),
+                    ));
+                }
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                self.search_users()?;
+                return Ok(false);
+            }
+            Msg::UserListResponse(search, response) => {
+                if search != self.search.trim() {
+                    // A newer search is coming.
+                    return Ok(false);
+                }
+                self.search_task = None;
                 self.user_list = Some(response?.users);
-                self.task = None;
             }
-            Msg::SubmitAddMember => return self.submit_add_member(),
-            Msg::AddMemberResponse(response) => {
-                response?;
+            Msg::ToggleUser(user) => {
+                if self.selected_users.contains(&user) {
+                    self.selected_users.retain(|u| *u != user);
+                } else {
+                    self.selected_users.push(user);
+                }
+            }
+            Msg::SubmitAddMembers => return self.submit_add_members(),
+            Msg::AddMembersResponse(response) => {
                 self.task = None;
-                let user = self
-                    .selected_user
-                    .as_ref()
-                    .expect("Could not get selected user")
-                    .clone();
-                // Remove the user from the dropdown.
-                self.props.on_user_added_to_group.emit(user);
+                let results = response?.add_users_to_group;
+                let selected_users = std::mem::take(&mut self.selected_users);
+                for result in results {
+                    match result.error {
+                        Some(error) => self.failures.push((result.user_id, error)),
+                        None => {
+                            if let Some(user) =
+                                selected_users.iter().find(|u| u.id == result.user_id)
+                            {
+                                self.props.on_user_added_to_group.emit(user.clone());
+                            }
+                        }
+                    }
+                }
             }
-            Msg::SelectionChanged(option_props) => {
-                let was_some = self.selected_user.is_some();
-                self.selected_user = option_props.map(|u| User {
-                    id: u.value,
-                    display_name: u.text,
-                });
-                return Ok(self.selected_user.is_some() != was_some);
+            Msg::AddManagerResponse(user, response) => {
+                match response {
+                    Ok(_) => self.props.on_user_added_to_group.emit(user),
+                    Err(e) => self.failures.push((user.id, e.to_string())),
+                }
+                self.add_next_manager()?;
             }
+            Msg::ClearFailures => self.failures.clear(),
         }
         Ok(true)
     }
 
-    fn get_selectable_user_list(&self, user_list: &[User]) -> Vec<User> {
-        let user_groups = self.props.users.iter().collect::<HashSet<_>>();
+    /// The users found that can be picked.
+    fn get_selectable_user_list<'a>(&self, user_list: &'a [User]) -> Vec<&'a User> {
+        let group_users = self.props.users.iter().collect::<HashSet<_>>();
         user_list
             .iter()
-            .filter(|u| !user_groups.contains(u))
-            .map(Clone::clone)
+            .take(MAX_SEARCH_RESULTS as usize)
+            .filter(|u| !group_users.contains(u))
             .collect()
     }
+
+    fn view_search_results(&self) -> Html {
+        let user_list = match &self.user_list {
+            None if self.search_task.is_some() || self.search_timeout.is_some() => {
+                return html! {
+                  <span class="list-group-item text-muted">{"Searching..."}</span>
+                }
+            }
+            None => {
+                return html! {
+                  <span class="list-group-item text-muted">{"Type to search users"}</span>
+                }
+            }
+            Some(user_list) => user_list,
+        };
+        let to_add_user_list = self.get_selectable_user_list(user_list);
+        if to_add_user_list.is_empty() {
+            return html! {
+              <span class="list-group-item text-muted">{"No matching user"}</span>
+            };
+        }
+        html! {
+          <>
+            {to_add_user_list.into_iter().map(|user| {
+              let toggled = user.clone();
+              html! {
+                <label class="list-group-item" key=user.id.clone()>
+                  <input
+                    type="checkbox"
+                    class="form-check-input me-2"
+                    checked=self.selected_users.contains(user)
+                    onchange=self.link.callback(move |_| Msg::ToggleUser(toggled.clone())) />
+                  {&user.id}
+                  {if user.display_name.is_empty() { html! {} } else { html! {
+                    <span class="text-muted">{" ("}{&user.display_name}{")"}</span>
+                  } } }
+                </label>
+              }
+            }).collect::<Vec<_>>()}
+            {if user_list.len() as i64 > MAX_SEARCH_RESULTS { html! {
+              <span class="list-group-item text-muted">{"More users match: refine the search"}</span>
+            } } else { html! {} } }
+          </>
+        }
+    }
+
+    fn view_selected_users(&self) -> Html {
+        html! {
+          <div class="mb-2">
+            {self.selected_users.iter().map(|user| {
+              let toggled = user.clone();
+              html! {
+                <span class="badge bg-secondary me-1" key=user.id.clone()>
+                  {&user.id}
+                  <button
+                    type="button"
+                    class="btn-close btn-close-white ms-1"
+                    style="font-size: 0.5rem"
+                    aria-label="Unselect"
+                    onclick=self.link.callback(move |_| Msg::ToggleUser(toggled.clone())) />
+                </span>
+              }
+            }).collect::<Vec<_>>()}
+          </div>
+        }
+    }
+
+    fn view_failures(&self) -> Html {
+        if self.failures.is_empty() {
+            return html! {};
+        }
+        html! {
+          <div class="alert alert-warning mt-2">
+            <button
+              type="button"
+              class="btn-close float-end"
+              aria-label="Close"
+              onclick=self.link.callback(|_| Msg::ClearFailures) />
+            {format!("{} users could not be added:", self.failures.len())}
+            <ul class="mb-0">
+              {self.failures.iter().map(|(user_id, error)| html! {
+                <li key=user_id.clone()><b>{user_id}</b>{": "}{error}</li>
+              }).collect::<Vec<_>>()}
+            </ul>
+          </div>
+        }
+    }
 }
 
 impl Component for AddGroupMemberComponent {
     type Message = Msg;
     type Properties = Props;
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
-        let mut res = Self {
+        Self {
             link,
             props,
             user_list: None,
-            selected_user: None,
+            search: String::new(),
+            search_timeout: None,
+            search_task: None,
+            selected_users: Vec::new(),
+            pending_managers: Vec::new(),
+            failures: Vec::new(),
             task: None,
-        };
-        res.get_user_list();
-        res
+        }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
@@ -154,6 +368,7 @@ impl Component for AddGroupMemberComponent {
                 ConsoleService::error(&e.to_string());
                 self.props.on_error.emit(e);
                 self.task = None;
+                self.search_task = None;
                 true
             }
             Ok(b) => b,
@@ -165,40 +380,29 @@ impl Component for AddGroupMemberComponent {
     }
 
     fn view(&self) -> Html {
-        if let Some(user_list) = &self.user_list {
-            let to_add_user_list = self.get_selectable_user_list(user_list);
-            #[allow(unused_braces)]
-            let make_select_option = |user: User| {
-                html_nested! {
-                    <SelectOption value=user.id.clone() text=user.display_name.clone() key=user.id />
-                }
-            };
-            html! {
-            <div class="row">
-              <div class="col-sm-3">
-                <Select on_selection_change=self.link.callback(Msg::SelectionChanged)>
-                  {
-                    to_add_user_list
-                        .into_iter()
-                        .map(make_select_option)
-                        .collect::<Vec<_>>()
-                  }
-                </Select>
-              </div>
-              <div class="col-sm-1">
-                <button
-                  class="btn btn-success"
-                  disabled=self.selected_user.is_none() || self.task.is_some()
-                  onclick=self.link.callback(|_| Msg::SubmitAddMember)>
-                  {"Add"}
-                </button>
-              </div>
+        html! {
+          <div style="max-width: 500px">
+            {self.view_selected_users()}
+            <input
+              type="search"
+              class="form-control"
+              placeholder="Search users"
+              value=self.search.clone()
+              oninput=self.link.callback(|e: InputData| Msg::SearchChanged(e.value)) />
+            <div class="list-group my-2" style="max-height: 200px; overflow-y: auto">
+              {self.view_search_results()}
             </div>
-            }
-        } else {
-            html! {
-              {"Loading groups"}
-            }
+            <button
+              class="btn btn-success"
+              disabled=self.selected_users.is_empty() || self.task.is_some()
+              onclick=self.link.callback(|_| Msg::SubmitAddMembers)>
+              {match self.selected_users.len() {
+                0 | 1 => "Add".to_string(),
+                n => format!("Add {} users", n),
+              }}
+            </button>
+            {self.view_failures()}
+          </div>
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::{
     components::{
+        loading,
         select::{Select, SelectOption, SelectOptionProps},
         user_details::Group,
     },
@@ -20,7 +21,8 @@ use yewtil::NeqAssign;
     query_path = "queries/add_user_to_group.graphql",
     response_derives = "Debug",
     variables_derives = "Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct AddUserToGroup;
 
@@ -30,7 +32,8 @@ pub struct AddUserToGroup;
     query_path = "queries/get_group_list.graphql",
     response_derives = "Debug",
     variables_derives = "Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct GetGroupList;
 type GroupListGroup = get_group_list::GetGroupListGroups;
@@ -189,7 +192,7 @@ impl Component for AddUserToGroupComponent {
             html! {
             <div class="row">
               <div class="col-sm-3">
-                <Select on_selection_change=self.link.callback(Msg::SelectionChanged)>
+                <Select label="Group" on_selection_change=self.link.callback(Msg::SelectionChanged)>
                   {
                     to_add_group_list
                         .into_iter()
@@ -209,9 +212,7 @@ impl Component for AddUserToGroupComponent {
             </div>
             }
         } else {
-            html! {
-              {"Loading groups"}
-            }
+            loading::view_spinner("Loading groups...")
         }
     }
 }
@@ -1,20 +1,42 @@
 use crate::{
     components::{
         change_password::ChangePasswordForm,
+        command_palette::CommandPalette,
         create_group::CreateGroupForm,
         create_user::CreateUserForm,
+        deleted_user_table::DeletedUserTable,
+        error_page::{view_access_denied, view_not_found},
         group_details::GroupDetails,
         group_table::GroupTable,
+        invite::InviteForm,
+        job_table::JobTable,
+        ldap_search::LdapSearch,
         login::LoginForm,
         logout::LogoutButton,
-        router::{AppRoute, Link, NavButton},
+        oidc_consent::OidcConsent,
+        router::{set_document_title, AppRoute, Link, NavButton},
         user_details::UserDetails,
         user_table::UserTable,
     },
-    infra::cookies::get_cookie,
+    infra::{
+        api::{is_outdated, HostService, LoginInfo},
+        branding::{apply_branding, view_login_message, Branding},
+        cookies::get_cookie,
+        date::format_local_time,
+        oidc::{OidcClient, OidcRequest},
+        shortcuts::{Shortcut, ShortcutAgent},
+        theme::{get_theme, set_theme, Theme},
+    },
 };
+use anyhow::Result;
+use graphql_client::GraphQLQuery;
+use wasm_bindgen::JsCast;
 use yew::prelude::*;
-use yew::services::ConsoleService;
+use yew::services::{
+    fetch::FetchTask,
+    timeout::{TimeoutService, TimeoutTask},
+    ConsoleService,
+};
 use yew_router::{
     agent::{RouteAgentDispatcher, RouteRequest},
     route::Route,
@@ -22,23 +44,81 @@ use yew_router::{
     service::RouteService,
 };
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_server_version.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct GetServerVersion;
+
 pub struct App {
     link: ComponentLink<Self>,
     user_info: Option<(String, bool)>,
+    /// Whether the user can browse the users and groups without modifying them.
+    is_read_only: bool,
     redirect_to: Option<AppRoute>,
+    /// The query of the page to go to, e.g. the search of a bookmarked list of users.
+    redirect_query: String,
+    /// Set when the login page was opened by the OpenID Connect provider.
+    oidc_request: Option<OidcRequest>,
+    oidc_task: Option<FetchTask>,
     route_dispatcher: RouteAgentDispatcher,
+    /// Whether the server runs a different version than this app.
+    outdated: bool,
+    // Used to keep the request alive long enough.
+    task: Option<FetchTask>,
+    /// Fires shortly before the JWT expires, then when it expires.
+    expiry_timeout: Option<TimeoutTask>,
+    refresh_task: Option<FetchTask>,
+    /// Whether the user did anything on the page since the session was last renewed.
+    active: bool,
+    session_warning: Option<SessionWarning>,
+    theme: Theme,
+    branding: Branding,
+    branding_task: Option<FetchTask>,
+    _shortcuts: Box<dyn Bridge<ShortcutAgent>>,
+    command_palette_open: bool,
+}
+
+/// The state of the session shown in the banner, when the user was idle as it came to an end.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SessionWarning {
+    /// The session ends at the given time, unless it's renewed before.
+    Expiring(chrono::DateTime<chrono::Utc>),
+    /// The JWT expired: the session can still be renewed if the refresh token is valid.
+    Expired,
 }
 
 pub enum Msg {
-    Login((String, bool)),
+    Login(LoginInfo),
     Logout,
+    ServerVersionResponse(Result<get_server_version::ResponseData>),
+    /// A click or key press anywhere on the page.
+    UserActivity,
+    SessionExpiring(chrono::DateTime<chrono::Utc>),
+    SessionExpired,
+    RefreshSession,
+    RefreshResponse(Result<LoginInfo>),
+    ToggleTheme,
+    BrandingResponse(Result<Branding>),
+    OidcClientResponse(Result<OidcClient>),
+    Shortcut(Shortcut),
+    CloseCommandPalette,
 }
 
+/// How long before the expiry of the JWT the session is renewed if the user was active, or the
+/// user is warned otherwise. Short-lived JWTs use a quarter of their lifetime instead.
+const EXPIRY_WARNING_SECONDS: i64 = 5 * 60;
+
 impl Component for App {
     type Message = Msg;
     type Properties = ();
 
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let shortcuts = ShortcutAgent::bridge(link.callback(Msg::Shortcut));
         let mut app = Self {
             link,
             user_info: get_cookie("user_id")
@@ -54,31 +134,170 @@ impl Component for App {
                             None
                         })
                 }),
+            is_read_only: get_cookie("is_read_only")
+                .unwrap_or_else(|e| {
+                    ConsoleService::error(&e.to_string());
+                    None
+                })
+                .map(|s| s == "true")
+                .unwrap_or(false),
             redirect_to: Self::get_redirect_route(),
+            redirect_query: RouteService::<()>::new().get_query(),
+            oidc_request: OidcRequest::from_location(),
+            oidc_task: None,
             route_dispatcher: RouteAgentDispatcher::new(),
+            outdated: false,
+            task: None,
+            expiry_timeout: None,
+            refresh_task: None,
+            active: false,
+            session_warning: None,
+            theme: get_theme(),
+            branding: Branding::default(),
+            branding_task: None,
+            _shortcuts: shortcuts,
+            command_palette_open: false,
         };
+        app.branding_task = HostService::get_branding(app.link.callback(Msg::BrandingResponse))
+            .map_err(|e| ConsoleService::error(&e.to_string()))
+            .ok();
+        if let Some(request) = &app.oidc_request {
+            app.oidc_task = HostService::get_oidc_client(
+                &request.client_id,
+                app.link.callback(Msg::OidcClientResponse),
+            )
+            .map_err(|e| ConsoleService::error(&e.to_string()))
+            .ok();
+        }
         app.apply_initial_redirections();
+        if app.user_info.is_some() {
+            app.check_server_version();
+        }
+        // The JWT may have expired while the page was closed, while the refresh token is still
+        // valid: this logs the user back in silently.
+        app.refresh_session();
         app
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
-            Msg::Login((user_name, is_admin)) => {
+            Msg::Login(LoginInfo {
+                user_id: user_name,
+                is_admin,
+                is_read_only,
+                expiry,
+            }) => {
                 self.user_info = Some((user_name.clone(), is_admin));
-                self.route_dispatcher
-                    .send(RouteRequest::ChangeRoute(Route::from(
-                        self.redirect_to.take().unwrap_or_else(|| {
-                            if is_admin {
-                                AppRoute::ListUsers
-                            } else {
-                                AppRoute::UserDetails(user_name.clone())
-                            }
-                        }),
-                    )));
+                self.is_read_only = is_read_only;
+                self.schedule_expiry_warning(expiry);
+                // The login page then asks whether to continue to the OpenID Connect client.
+                if self.oidc_request.is_none() {
+                    let route = match self.redirect_to.take() {
+                        Some(route) => self.with_redirect_query(route),
+                        None if is_admin || is_read_only => Route::from(AppRoute::ListUsers),
+                        None => Route::from(AppRoute::UserDetails(user_name.clone())),
+                    };
+                    self.route_dispatcher.send(RouteRequest::ChangeRoute(route));
+                }
+                self.check_server_version();
             }
             Msg::Logout => {
                 self.user_info = None;
+                self.is_read_only = false;
                 self.redirect_to = None;
+                self.redirect_query.clear();
+                self.command_palette_open = false;
+                self.expiry_timeout = None;
+                self.session_warning = None;
+            }
+            Msg::ServerVersionResponse(response) => {
+                self.task = None;
+                match response {
+                    Ok(data) => self.outdated = is_outdated(data.schema_version),
+                    Err(e) => ConsoleService::error(&e.to_string()),
+                }
+                return true;
+            }
+            Msg::UserActivity => {
+                // Renew the session as soon as the user comes back to the page.
+                if self.session_warning.is_some() && self.refresh_task.is_none() {
+                    self.refresh_session();
+                }
+                self.active = true;
+                return false;
+            }
+            Msg::SessionExpiring(expiry) => {
+                if self.active {
+                    self.refresh_session();
+                    return false;
+                }
+                self.session_warning = Some(SessionWarning::Expiring(expiry));
+                let remaining = (expiry - chrono::Utc::now()).to_std().unwrap_or_default();
+                self.expiry_timeout = Some(TimeoutService::spawn(
+                    remaining,
+                    self.link.callback(|_| Msg::SessionExpired),
+                ));
+                return true;
+            }
+            Msg::SessionExpired => {
+                self.session_warning = Some(SessionWarning::Expired);
+                return true;
+            }
+            Msg::RefreshSession => {
+                self.refresh_session();
+                return true;
+            }
+            Msg::RefreshResponse(response) => {
+                self.refresh_task = None;
+                match response {
+                    Ok(login_info) if self.user_info.is_none() => {
+                        return self.update(Msg::Login(login_info))
+                    }
+                    Ok(login_info) => {
+                        self.user_info = Some((login_info.user_id, login_info.is_admin));
+                        self.is_read_only = login_info.is_read_only;
+                        self.schedule_expiry_warning(login_info.expiry);
+                    }
+                    // Not logged in, or the refresh token was revoked: the current JWT, if any,
+                    // stays valid until it expires.
+                    Err(e) => ConsoleService::log(&e.to_string()),
+                }
+                return true;
+            }
+            Msg::ToggleTheme => {
+                self.theme = self.theme.toggled();
+                set_theme(self.theme);
+                return true;
+            }
+            Msg::BrandingResponse(response) => {
+                self.branding_task = None;
+                match response {
+                    Ok(branding) => {
+                        apply_branding(&branding);
+                        self.branding = branding;
+                    }
+                    // The LLDAP branding is kept.
+                    Err(e) => ConsoleService::error(&e.to_string()),
+                }
+                return true;
+            }
+            Msg::OidcClientResponse(response) => {
+                self.oidc_task = None;
+                match response {
+                    Ok(client) => {
+                        if let Some(request) = &mut self.oidc_request {
+                            request.client = Some(client.display_name);
+                        }
+                    }
+                    // The page names no application then.
+                    Err(e) => ConsoleService::error(&e.to_string()),
+                }
+                return true;
+            }
+            Msg::Shortcut(shortcut) => return self.handle_shortcut(shortcut),
+            Msg::CloseCommandPalette => {
+                self.command_palette_open = false;
+                return true;
             }
         }
         if self.user_info.is_none() {
@@ -95,44 +314,122 @@ impl Component for App {
     fn view(&self) -> Html {
         let link = self.link.clone();
         let is_admin = self.is_admin();
+        let is_read_only = self.is_read_only;
+        let current_user = self.user_info.as_ref().map(|(u, _)| u.clone());
+        let oidc_request = self.oidc_request.clone();
+        // Shown on the login page, unless it's already above all the pages.
+        let login_message = match &self.branding.login_message {
+            Some(message) if !self.branding.login_message_everywhere => view_login_message(message),
+            _ => html! {},
+        };
         html! {
-            <div class="container shadow-sm py-3">
+            <div
+              class="container shadow-sm py-3"
+              onclick=self.link.callback(|_| Msg::UserActivity)
+              onkeydown=self.link.callback(|_| Msg::UserActivity)>
               {self.view_banner()}
+              {if self.command_palette_open { html! {
+                <CommandPalette
+                  is_admin=is_admin
+                  can_read_all=is_admin || is_read_only
+                  on_close=self.link.callback(|_| Msg::CloseCommandPalette) />
+              } } else { html! {} } }
+              {self.view_session_warning()}
+              {if self.outdated { html! {
+                <div class="alert alert-warning">
+                  {"The server was upgraded and this page is outdated: please hard-refresh it (Ctrl+Shift+R)."}
+                </div>
+              } } else { html!{} } }
+              {match &self.branding.login_message {
+                Some(message) if self.branding.login_message_everywhere => view_login_message(message),
+                _ => html! {},
+              }}
               <div class="row justify-content-center">
                 <div class="shadow-sm py-3" style="max-width: 1000px">
                   <Router<AppRoute>
                     render = Router::render(move |switch: AppRoute| {
+                        set_document_title(&switch);
                         match switch {
-                            AppRoute::Login => html! {
-                                <LoginForm on_logged_in=link.callback(Msg::Login)/>
+                            AppRoute::Login => match (&oidc_request, &current_user) {
+                                (Some(request), Some(user_id)) => html! {
+                                    <OidcConsent request=request.clone() user_id=user_id.clone() />
+                                },
+                                (Some(request), None) => html! {
+                                    <>
+                                      {login_message.clone()}
+                                      <h5>{format!("Log in to continue to {}", request.client_name())}</h5>
+                                      <LoginForm on_logged_in=link.callback(Msg::Login)/>
+                                    </>
+                                },
+                                (None, _) => html! {
+                                    <>
+                                      {login_message.clone()}
+                                      <LoginForm on_logged_in=link.callback(Msg::Login)/>
+                                    </>
+                                },
                             },
                             AppRoute::CreateUser => html! {
                                 <CreateUserForm/>
                             },
                             AppRoute::Index | AppRoute::ListUsers => html! {
                                 <div>
-                                  <UserTable />
-                                  <NavButton classes="btn btn-primary" route=AppRoute::CreateUser>{"Create a user"}</NavButton>
+                                  <UserTable read_only=is_read_only />
+                                  {if is_read_only { html! {} } else { html! {
+                                    <NavButton classes="btn btn-primary" route=AppRoute::CreateUser>{"Create a user"}</NavButton>
+                                  } } }
                                 </div>
                             },
-                            AppRoute::CreateGroup => html! {
+                            AppRoute::CreateGroup if is_admin => html! {
                                 <CreateGroupForm/>
                             },
                             AppRoute::ListGroups => html! {
                                 <div>
-                                  <GroupTable />
-                                  <NavButton classes="btn btn-primary" route=AppRoute::CreateGroup>{"Create a group"}</NavButton>
+                                  <GroupTable read_only=is_read_only />
+                                  {if is_read_only { html! {} } else { html! {
+                                    <NavButton classes="btn btn-primary" route=AppRoute::CreateGroup>{"Create a group"}</NavButton>
+                                  } } }
                                 </div>
                             },
                             AppRoute::GroupDetails(group_id) => html! {
-                                <GroupDetails group_id=group_id />
+                                <GroupDetails group_id=group_id is_admin=is_admin read_only=is_read_only />
+                            },
+                            AppRoute::ListJobs if is_admin => html! {
+                                <JobTable />
+                            },
+                            AppRoute::ListDeletedUsers if is_admin => html! {
+                                <DeletedUserTable />
+                            },
+                            AppRoute::LdapSearch if is_admin => html! {
+                                <LdapSearch />
+                            },
+                            // GraphiQL, served by the server and authenticated by the session
+                            // cookie.
+                            AppRoute::GraphQLExplorer if is_admin => html! {
+                                <iframe
+                                  class="graphql-explorer"
+                                  title="GraphQL explorer"
+                                  src="/api/graphql/graphiql" />
                             },
                             AppRoute::UserDetails(username) => html! {
-                                <UserDetails username=username.clone() is_admin=is_admin />
+                                <UserDetails
+                                  username=username.clone()
+                                  is_admin=is_admin
+                                  read_only=is_read_only && current_user.as_ref() != Some(&username)
+                                  is_current_user=current_user.as_ref() == Some(&username) />
                             },
                             AppRoute::ChangePassword(username) => html! {
                                 <ChangePasswordForm username=username.clone() is_admin=is_admin />
-                            }
+                            },
+                            AppRoute::Invite(token) => html! {
+                                <InviteForm token=token />
+                            },
+                            // The pages of the admins, for the other users.
+                            AppRoute::CreateGroup
+                            | AppRoute::ListJobs
+                            | AppRoute::ListDeletedUsers
+                            | AppRoute::LdapSearch
+                            | AppRoute::GraphQLExplorer => view_access_denied(),
+                            AppRoute::NotFound(_) => view_not_found("There is no page at this address."),
                         }
                     })
                   />
@@ -144,6 +441,99 @@ impl Component for App {
 }
 
 impl App {
+    fn handle_shortcut(&mut self, shortcut: Shortcut) -> ShouldRender {
+        // The login and invite pages have no shortcuts.
+        if self.user_info.is_none() {
+            return false;
+        }
+        match shortcut {
+            Shortcut::CommandPalette => {
+                self.command_palette_open = !self.command_palette_open;
+                return true;
+            }
+            Shortcut::FocusSearch => {
+                if let Some(search) = web_sys::window()
+                    .and_then(|window| window.document())
+                    .and_then(|document| document.query_selector("input[type=search]").ok())
+                    .flatten()
+                    .and_then(|element| element.dyn_into::<web_sys::HtmlElement>().ok())
+                {
+                    let _ = search.focus();
+                }
+            }
+            Shortcut::NewUser if self.is_admin() => self
+                .route_dispatcher
+                .send(RouteRequest::ChangeRoute(Route::from(AppRoute::CreateUser))),
+            Shortcut::NewGroup if self.is_admin() => {
+                self.route_dispatcher
+                    .send(RouteRequest::ChangeRoute(Route::from(
+                        AppRoute::CreateGroup,
+                    )))
+            }
+            Shortcut::NewUser | Shortcut::NewGroup => (),
+        }
+        false
+    }
+
+    fn check_server_version(&mut self) {
+        self.task = HostService::graphql_query::<GetServerVersion>(
+            get_server_version::Variables {},
+            self.link.callback(Msg::ServerVersionResponse),
+            "Error trying to get the server version",
+        )
+        .map_err(|e| ConsoleService::error(&e.to_string()))
+        .ok();
+    }
+
+    fn refresh_session(&mut self) {
+        self.refresh_task = HostService::refresh(self.link.callback(Msg::RefreshResponse))
+            .map_err(|e| ConsoleService::error(&e.to_string()))
+            .ok();
+    }
+
+    /// Checks the session a bit before the JWT expires: it's renewed if the user was active since
+    /// the last renewal, so that it doesn't end mid-edit, and the user is warned otherwise.
+    fn schedule_expiry_warning(&mut self, expiry: chrono::DateTime<chrono::Utc>) {
+        self.active = false;
+        self.session_warning = None;
+        let remaining = expiry - chrono::Utc::now();
+        let margin = std::cmp::min(
+            remaining / 4,
+            chrono::Duration::seconds(EXPIRY_WARNING_SECONDS),
+        );
+        let delay = (remaining - margin).to_std().unwrap_or_default();
+        self.expiry_timeout = Some(TimeoutService::spawn(
+            delay,
+            self.link.callback(move |_| Msg::SessionExpiring(expiry)),
+        ));
+    }
+
+    fn view_session_warning(&self) -> Html {
+        let message = match self.session_warning {
+            None => return html! {},
+            Some(SessionWarning::Expiring(expiry)) => format!(
+                "Your session ends at {}. Anything you do on the page renews it.",
+                format_local_time(&expiry)
+            ),
+            Some(SessionWarning::Expired) => {
+                "Your session has ended: the changes can't be saved until it is renewed. If that \
+                 fails, log in again in another tab to keep this page."
+                    .to_string()
+            }
+        };
+        html! {
+          <div class="alert alert-warning d-flex align-items-center">
+            <span class="me-auto">{message}</span>
+            <button
+              class="btn btn-warning ms-3 text-nowrap"
+              disabled=self.refresh_task.is_some()
+              onclick=self.link.callback(|_| Msg::RefreshSession)>
+              {"Stay logged in"}
+            </button>
+          </div>
+        }
+    }
+
     fn get_redirect_route() -> Option<AppRoute> {
         let route_service = RouteService::<()>::new();
         let current_route = route_service.get_path();
@@ -155,19 +545,29 @@ impl App {
         }
     }
 
+    fn with_redirect_query(&self, route: AppRoute) -> Route {
+        let route = Route::from(route);
+        Route::new_no_state(&format!("{}{}", route.route, self.redirect_query))
+    }
+
     fn apply_initial_redirections(&mut self) {
         match &self.user_info {
+            // The invite links are for users who can't log in yet.
+            None if matches!(self.redirect_to, Some(AppRoute::Invite(_))) => (),
             None => {
                 self.route_dispatcher
                     .send(RouteRequest::ReplaceRoute(Route::new_no_state("/login")));
             }
+            // Stay on the login page, to continue to the OpenID Connect client.
+            Some(_) if self.oidc_request.is_some() => (),
             Some((user_name, is_admin)) => match &self.redirect_to {
                 Some(url) => {
+                    let route = self.with_redirect_query(url.clone());
                     self.route_dispatcher
-                        .send(RouteRequest::ReplaceRoute(Route::from(url.clone())));
+                        .send(RouteRequest::ReplaceRoute(route));
                 }
                 None => {
-                    if *is_admin {
+                    if *is_admin || self.is_read_only {
                         self.route_dispatcher
                             .send(RouteRequest::ReplaceRoute(Route::new_no_state("/users")));
                     } else {
@@ -182,38 +582,83 @@ impl App {
     }
 
     fn view_banner(&self) -> Html {
+        let (theme_title, theme_icon) = match self.theme {
+            Theme::Light => ("Switch to the dark theme", "bi-moon"),
+            Theme::Dark => ("Switch to the light theme", "bi-sun"),
+        };
         html! {
           <header class="p-3 mb-4 border-bottom shadow-sm">
             <div class="container">
               <div class="d-flex flex-wrap align-items-center justify-content-center justify-content-lg-start">
-                <a href="/" class="d-flex align-items-center mb-2 mb-lg-0 me-md-5 text-dark text-decoration-none">
-                  <h1>{"LLDAP"}</h1>
+                <a href="/" class="d-flex align-items-center mb-2 mb-lg-0 me-md-5 text-reset text-decoration-none">
+                  {if let Some(logo_url) = &self.branding.logo_url { html! {
+                    <img class="brand-logo me-2" src=logo_url.clone() alt="" />
+                  } } else { html! {} } }
+                  <h1>{&self.branding.product_name}</h1>
                 </a>
 
                 <ul class="nav col-12 col-lg-auto me-lg-auto mb-2 justify-content-center mb-md-0">
-                  {if self.is_admin() { html! {
+                  {if self.is_admin() || self.is_read_only { html! {
                     <>
                       <li>
                         <Link
-                          classes="nav-link px-2 link-dark h4"
+                          classes="nav-link px-2 text-reset h4"
                           route=AppRoute::ListUsers>
                           {"Users"}
                         </Link>
                       </li>
                       <li>
                         <Link
-                          classes="nav-link px-2 link-dark h4"
+                          classes="nav-link px-2 text-reset h4"
                           route=AppRoute::ListGroups>
                           {"Groups"}
                         </Link>
                       </li>
                     </>
                   } } else { html!{} } }
+                  {if self.is_admin() { html! {
+                    <>
+                      <li>
+                        <Link
+                          classes="nav-link px-2 text-reset h4"
+                          route=AppRoute::ListJobs>
+                          {"Jobs"}
+                        </Link>
+                      </li>
+                      <li>
+                        <Link
+                          classes="nav-link px-2 text-reset h4"
+                          route=AppRoute::ListDeletedUsers>
+                          {"Deleted users"}
+                        </Link>
+                      </li>
+                      <li>
+                        <Link
+                          classes="nav-link px-2 text-reset h4"
+                          route=AppRoute::LdapSearch>
+                          {"LDAP"}
+                        </Link>
+                      </li>
+                      <li>
+                        <Link
+                          classes="nav-link px-2 text-reset h4"
+                          route=AppRoute::GraphQLExplorer>
+                          {"GraphQL"}
+                        </Link>
+                      </li>
+                    </>
+                  } } else { html!{} } }
                 </ul>
 
+                <button
+                  class="btn btn-link text-reset me-3"
+                  title=theme_title
+                  onclick=self.link.callback(|_| Msg::ToggleTheme)>
+                  <i class=theme_icon></i>
+                </button>
                 <div class="dropdown text-end">
                   <a href="#"
-                    class="d-block link-dark text-decoration-none dropdown-toggle"
+                    class="d-block text-reset text-decoration-none dropdown-toggle"
                     id="dropdownUser"
                     data-bs-toggle="dropdown"
                     aria-expanded="false">
@@ -0,0 +1,338 @@
+use crate::{
+    components::user_details_form::{update_user, UpdateUser},
+    infra::api::HostService,
+};
+use anyhow::{anyhow, Error, Result};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, HtmlImageElement, Url};
+use yew::{
+    html::{ChangeData, InputData},
+    prelude::*,
+    services::fetch::FetchTask,
+};
+
+/// The side of the uploaded picture, in pixels, as expected by the server.
+const AVATAR_SIZE: u32 = 512;
+/// The prefix of the data URL of the cropped picture, before the base64 JPEG.
+const JPEG_DATA_URL_PREFIX: &str = "data:image/jpeg;base64,";
+
+/// The picture of a user, with the controls to upload a new one: the chosen image is cropped to a
+/// square in the browser, previewed, then sent to the server as a 512x512 JPEG.
+pub struct Avatar {
+    link: ComponentLink<Self>,
+    props: Props,
+    /// The object URL of the image being cropped, if any.
+    image_url: Option<String>,
+    image_ref: NodeRef,
+    canvas_ref: NodeRef,
+    /// How much the image is zoomed in, from 1 (the largest square) to 3.
+    zoom: f64,
+    /// The position of the square in the image, as a percentage of the free space.
+    x: f64,
+    y: f64,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// Empty for a user not created yet, with `on_change`.
+    #[prop_or_default]
+    pub username: String,
+    /// The base64-encoded JPEG of the current picture.
+    pub avatar: Option<String>,
+    /// Hides the upload controls.
+    #[prop_or_default]
+    pub read_only: bool,
+    /// Set when the picture is saved by the parent, e.g. with the rest of a new user: it's given
+    /// the new picture (empty when removed) instead of sending it to the server.
+    #[prop_or_default]
+    pub on_change: Option<Callback<String>>,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    FileChosen(ChangeData),
+    ImageLoaded,
+    ZoomChanged(InputData),
+    XChanged(InputData),
+    YChanged(InputData),
+    Save,
+    Remove,
+    Cancel,
+    /// The response of the server, with the new picture.
+    UpdateResponse(Result<update_user::ResponseData>, String),
+}
+
+impl Avatar {
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::FileChosen(ChangeData::Files(files)) => {
+                if let Some(file) = files.get(0) {
+                    self.clear_image();
+                    self.image_url = Some(
+                        Url::create_object_url_with_blob(&file)
+                            .map_err(|_| anyhow!("Could not read the image"))?,
+                    );
+                    self.zoom = 1.0;
+                    self.x = 50.0;
+                    self.y = 50.0;
+                }
+            }
+            Msg::FileChosen(_) => return Ok(false),
+            // The image was rendered, hidden, and the preview is drawn from it.
+            Msg::ImageLoaded => self.draw_preview()?,
+            Msg::ZoomChanged(data) => {
+                self.zoom = data.value.parse().unwrap_or(1.0);
+                self.draw_preview()?;
+            }
+            Msg::XChanged(data) => {
+                self.x = data.value.parse().unwrap_or(50.0);
+                self.draw_preview()?;
+            }
+            Msg::YChanged(data) => {
+                self.y = data.value.parse().unwrap_or(50.0);
+                self.draw_preview()?;
+            }
+            Msg::Save => {
+                let data_url = self
+                    .canvas()?
+                    .to_data_url_with_type("image/jpeg")
+                    .map_err(|_| anyhow!("Could not encode the picture"))?;
+                let avatar = data_url
+                    .strip_prefix(JPEG_DATA_URL_PREFIX)
+                    .ok_or_else(|| anyhow!("The browser could not encode the picture as JPEG"))?
+                    .to_string();
+                self.update_avatar(avatar)?;
+            }
+            Msg::Remove => self.update_avatar(String::new())?,
+            Msg::Cancel => self.clear_image(),
+            Msg::UpdateResponse(response, avatar) => {
+                self.task = None;
+                response?;
+                self.set_avatar(avatar);
+            }
+        }
+        Ok(true)
+    }
+
+    fn set_avatar(&mut self, avatar: String) {
+        self.props.avatar = if avatar.is_empty() {
+            None
+        } else {
+            Some(avatar)
+        };
+        self.clear_image();
+    }
+
+    fn update_avatar(&mut self, avatar: String) -> Result<()> {
+        if let Some(on_change) = &self.props.on_change {
+            on_change.emit(avatar.clone());
+            self.set_avatar(avatar);
+            return Ok(());
+        }
+        let user = update_user::UpdateUserInput {
+            id: self.props.username.clone(),
+            email: None,
+            displayName: None,
+            firstName: None,
+            lastName: None,
+            avatar: Some(avatar.clone()),
+        };
+        self.task = Some(HostService::graphql_query::<UpdateUser>(
+            update_user::Variables { user },
+            self.link
+                .callback(move |response| Msg::UpdateResponse(response, avatar.clone())),
+            "Error trying to update the avatar",
+        )?);
+        Ok(())
+    }
+
+    fn clear_image(&mut self) {
+        if let Some(url) = self.image_url.take() {
+            let _ = Url::revoke_object_url(&url);
+        }
+    }
+
+    fn canvas(&self) -> Result<HtmlCanvasElement> {
+        self.canvas_ref
+            .cast::<HtmlCanvasElement>()
+            .ok_or_else(|| anyhow!("Could not find the preview canvas"))
+    }
+
+    /// Draws the selected square of the image on the canvas, scaled to the avatar size.
+    fn draw_preview(&self) -> Result<()> {
+        let image = match self.image_ref.cast::<HtmlImageElement>() {
+            Some(image) if image.complete() && image.natural_width() > 0 => image,
+            _ => return Ok(()),
+        };
+        let context = self
+            .canvas()?
+            .get_context("2d")
+            .ok()
+            .flatten()
+            .and_then(|c| c.dyn_into::<CanvasRenderingContext2d>().ok())
+            .ok_or_else(|| anyhow!("Could not draw the preview"))?;
+        let (width, height) = (image.natural_width() as f64, image.natural_height() as f64);
+        let side = width.min(height) / self.zoom;
+        let size = AVATAR_SIZE as f64;
+        // The transparent parts of the image would turn black in the JPEG.
+        context.set_fill_style(&JsValue::from_str("#fff"));
+        context.fill_rect(0.0, 0.0, size, size);
+        context
+            .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                &image,
+                (width - side) * self.x / 100.0,
+                (height - side) * self.y / 100.0,
+                side,
+                side,
+                0.0,
+                0.0,
+                size,
+                size,
+            )
+            .map_err(|_| anyhow!("Could not draw the preview"))
+    }
+
+    fn view_picture(&self) -> Html {
+        match &self.props.avatar {
+            Some(avatar) => html! {
+              <img
+                class="rounded-circle"
+                width="128"
+                height="128"
+                alt="Avatar"
+                src=format!("{}{}", JPEG_DATA_URL_PREFIX, avatar) />
+            },
+            None => html! {
+              <i class="bi bi-person-circle" style="font-size: 96px"></i>
+            },
+        }
+    }
+
+    fn view_upload(&self) -> Html {
+        if self.props.read_only {
+            return html! {};
+        }
+        match &self.image_url {
+            None => html! {
+              <div class="d-flex align-items-center mt-2">
+                <input
+                  type="file"
+                  accept="image/*"
+                  class="form-control"
+                  disabled=self.task.is_some()
+                  onchange=self.link.callback(Msg::FileChosen) />
+                {if self.props.avatar.is_some() { html! {
+                  <button
+                    type="button"
+                    class="btn btn-danger ms-2"
+                    disabled=self.task.is_some()
+                    onclick=self.link.callback(|_| Msg::Remove)>
+                    {"Remove"}
+                  </button>
+                } } else { html! {} } }
+              </div>
+            },
+            Some(url) => html! {
+              <div class="mt-2">
+                <img ref=self.image_ref.clone() src=url.clone() hidden=true
+                  onload=self.link.callback(|_| Msg::ImageLoaded) />
+                <canvas
+                  ref=self.canvas_ref.clone()
+                  class="rounded-circle border"
+                  width=AVATAR_SIZE.to_string()
+                  height=AVATAR_SIZE.to_string()
+                  style="width: 192px; height: 192px" />
+                {self.view_slider("Zoom", "1", "3", "0.05", self.zoom, Msg::ZoomChanged)}
+                {self.view_slider("Horizontal", "0", "100", "1", self.x, Msg::XChanged)}
+                {self.view_slider("Vertical", "0", "100", "1", self.y, Msg::YChanged)}
+                <button
+                  type="button"
+                  class="btn btn-primary"
+                  disabled=self.task.is_some()
+                  onclick=self.link.callback(|_| Msg::Save)>
+                  {"Save"}
+                </button>
+                <button
+                  type="button"
+                  class="btn btn-secondary ms-2"
+                  disabled=self.task.is_some()
+                  onclick=self.link.callback(|_| Msg::Cancel)>
+                  {"Cancel"}
+                </button>
+              </div>
+            },
+        }
+    }
+
+    fn view_slider(
+        &self,
+        label: &str,
+        min: &str,
+        max: &str,
+        step: &str,
+        value: f64,
+        msg: fn(InputData) -> Msg,
+    ) -> Html {
+        html! {
+          <div class="row mb-2" style="max-width: 400px">
+            <label class="form-label col-4">{label}</label>
+            <input
+              type="range"
+              class="form-range col-8"
+              min=min.to_string()
+              max=max.to_string()
+              step=step.to_string()
+              value=value.to_string()
+              oninput=self.link.callback(msg) />
+          </div>
+        }
+    }
+}
+
+impl Component for Avatar {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            image_url: None,
+            image_ref: NodeRef::default(),
+            canvas_ref: NodeRef::default(),
+            zoom: 1.0,
+            x: 50.0,
+            y: 50.0,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                self.task = None;
+                self.props.on_error.emit(e);
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <div class="mb-3">
+            {self.view_picture()}
+            {self.view_upload()}
+          </div>
+        }
+    }
+
+    fn destroy(&mut self) {
+        self.clear_image();
+    }
+}
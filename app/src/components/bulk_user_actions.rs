@@ -0,0 +1,326 @@
+use crate::{
+    components::{
+        add_user_to_group::{get_group_list, GetGroupList},
+        confirm_modal::ConfirmModal,
+        select::{Select, SelectOption, SelectOptionProps},
+    },
+    infra::api::HostService,
+};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::{
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/bulk_user_actions.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct AddUsersToGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/bulk_user_actions.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct RemoveUsersFromGroup;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/bulk_user_actions.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct DisableUsers;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/bulk_user_actions.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct DeleteUsers;
+
+/// The outcome of the action for one user: their ID, and the error if it failed.
+type BulkResult = (String, Option<String>);
+
+/// The bar of actions applied to all the users selected in the user table, with the outcome of
+/// the last action for each user.
+pub struct BulkUserActions {
+    link: ComponentLink<Self>,
+    props: Props,
+    group_list: Vec<(i64, String)>,
+    /// The group to add the users to or remove them from.
+    selected_group: Option<i64>,
+    /// The name of the last action, with its outcome for each user.
+    results: Option<(&'static str, Vec<BulkResult>)>,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub user_ids: Vec<String>,
+    /// Called after an action, for the table to reload the users.
+    pub on_done: Callback<()>,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    GroupListResponse(Result<get_group_list::ResponseData>),
+    SelectionChanged(Option<SelectOptionProps>),
+    AddToGroup,
+    RemoveFromGroup,
+    Disable,
+    ConfirmDelete,
+    ClearResults,
+    Response(&'static str, Result<Vec<BulkResult>>),
+}
+
+impl BulkUserActions {
+    fn get_group_list(&mut self) {
+        self.task = HostService::graphql_query::<GetGroupList>(
+            get_group_list::Variables,
+            self.link.callback(Msg::GroupListResponse),
+            "Error trying to fetch group list",
+        )
+        .map_err(|e| {
+            ConsoleService::log(&e.to_string());
+            e
+        })
+        .ok();
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        let user_ids = self.props.user_ids.clone();
+        match msg {
+            Msg::GroupListResponse(response) => {
+                self.task = None;
+                self.group_list = response?
+                    .groups
+                    .into_iter()
+                    .map(|g| (g.id, g.display_name))
+                    .collect();
+            }
+            Msg::SelectionChanged(option_props) => {
+                self.selected_group = option_props.and_then(|props| props.value.parse().ok());
+            }
+            Msg::AddToGroup => {
+                let group_id = match self.selected_group {
+                    None => return Ok(false),
+                    Some(group_id) => group_id,
+                };
+                self.task = Some(HostService::graphql_query::<AddUsersToGroup>(
+                    add_users_to_group::Variables { user_ids, group_id },
+                    self.link
+                        .callback(|response: Result<add_users_to_group::ResponseData>| {
+                            Msg::Response(
+                                "Added to the group",
+                                response.map(|r| {
+                                    r.add_users_to_group
+                                        .into_iter()
+                                        .map(|r| (r.user_id, r.error))
+                                        .collect()
+                                }),
+                            )
+                        }),
+                    "Error trying to add the users to the group",
+                )?);
+            }
+            Msg::RemoveFromGroup => {
+                let group_id = match self.selected_group {
+                    None => return Ok(false),
+                    Some(group_id) => group_id,
+                };
+                self.task = Some(HostService::graphql_query::<RemoveUsersFromGroup>(
+                    remove_users_from_group::Variables { user_ids, group_id },
+                    self.link.callback(
+                        |response: Result<remove_users_from_group::ResponseData>| {
+                            Msg::Response(
+                                "Removed from the group",
+                                response.map(|r| {
+                                    r.remove_users_from_group
+                                        .into_iter()
+                                        .map(|r| (r.user_id, r.error))
+                                        .collect()
+                                }),
+                            )
+                        },
+                    ),
+                    "Error trying to remove the users from the group",
+                )?);
+            }
+            Msg::Disable => {
+                self.task = Some(HostService::graphql_query::<DisableUsers>(
+                    disable_users::Variables { user_ids },
+                    self.link
+                        .callback(|response: Result<disable_users::ResponseData>| {
+                            Msg::Response(
+                                "Disabled",
+                                response.map(|r| {
+                                    r.disable_users
+                                        .into_iter()
+                                        .map(|r| (r.user_id, r.error))
+                                        .collect()
+                                }),
+                            )
+                        }),
+                    "Error trying to disable the users",
+                )?);
+            }
+            Msg::ConfirmDelete => {
+                self.task = Some(HostService::graphql_query::<DeleteUsers>(
+                    delete_users::Variables { user_ids },
+                    self.link
+                        .callback(|response: Result<delete_users::ResponseData>| {
+                            Msg::Response(
+                                "Deleted",
+                                response.map(|r| {
+                                    r.delete_users
+                                        .into_iter()
+                                        .map(|r| (r.user_id, r.error))
+                                        .collect()
+                                }),
+                            )
+                        }),
+                    "Error trying to delete the users",
+                )?);
+            }
+            Msg::ClearResults => self.results = None,
+            Msg::Response(action, response) => {
+                self.task = None;
+                self.results = Some((action, response?));
+                self.props.on_done.emit(());
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_results(&self) -> Html {
+        let (action, results) = match &self.results {
+            None => return html! {},
+            Some(results) => results,
+        };
+        let failures: Vec<_> = results
+            .iter()
+            .filter_map(|(user_id, error)| error.as_ref().map(|e| (user_id, e)))
+            .collect();
+        let class = if failures.is_empty() {
+            "alert alert-success"
+        } else {
+            "alert alert-warning"
+        };
+        html! {
+          <div class=class>
+            <button
+              type="button"
+              class="btn-close float-end"
+              aria-label="Close"
+              onclick=self.link.callback(|_| Msg::ClearResults) />
+            {format!("{}: {} of {} users.", action, results.len() - failures.len(), results.len())}
+            {if failures.is_empty() { html! {} } else { html! {
+              <ul class="mb-0">
+                {failures.iter().map(|(user_id, error)| html! {
+                  <li key=user_id.to_string()><b>{user_id}</b>{": "}{error}</li>
+                }).collect::<Vec<_>>()}
+              </ul>
+            } } }
+          </div>
+        }
+    }
+}
+
+impl Component for BulkUserActions {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut actions = Self {
+            link,
+            props,
+            group_list: Vec::new(),
+            selected_group: None,
+            results: None,
+            task: None,
+        };
+        actions.get_group_list();
+        actions
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let disabled = self.task.is_some() || self.props.user_ids.is_empty();
+        html! {
+          <div>
+            <div class="d-flex flex-wrap align-items-center mb-3">
+              <span class="me-3">{format!("{} selected", self.props.user_ids.len())}</span>
+              <Select label="Group" on_selection_change=self.link.callback(Msg::SelectionChanged)>
+                {self.group_list.iter().map(|(id, name)| html_nested! {
+                  <SelectOption value=id.to_string() text=name.clone() key=id.to_string() />
+                }).collect::<Vec<_>>()}
+              </Select>
+              <button
+                class="btn btn-secondary ms-2"
+                disabled=disabled || self.selected_group.is_none()
+                onclick=self.link.callback(|_| Msg::AddToGroup)>
+                {"Add to group"}
+              </button>
+              <button
+                class="btn btn-secondary ms-2"
+                disabled=disabled || self.selected_group.is_none()
+                onclick=self.link.callback(|_| Msg::RemoveFromGroup)>
+                {"Remove from group"}
+              </button>
+              <button
+                class="btn btn-outline-secondary ms-2"
+                disabled=disabled
+                onclick=self.link.callback(|_| Msg::Disable)>
+                {"Disable"}
+              </button>
+              <ConfirmModal
+                id="deleteUsersModal"
+                title="Delete users?"
+                message=html! {
+                  {format!(
+                    "Are you sure you want to delete the {} selected users?",
+                    self.props.user_ids.len(),
+                  )}
+                }
+                button_class="btn btn-danger ms-2"
+                disabled=disabled
+                on_confirm=self.link.callback(|_| Msg::ConfirmDelete)>
+                {"Delete"}
+              </ConfirmModal>
+            </div>
+            {self.view_results()}
+          </div>
+        }
+    }
+}
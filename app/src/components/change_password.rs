@@ -1,6 +1,10 @@
 use crate::{
-    components::router::{AppRoute, NavButton},
-    infra::api::HostService,
+    components::{
+        password_field,
+        password_strength::PasswordStrength,
+        router::{AppRoute, NavButton},
+    },
+    infra::{api::HostService, clipboard::copy_to_clipboard, password::generate_password},
 };
 use anyhow::{anyhow, bail, Context, Result};
 use lldap_auth::*;
@@ -63,6 +67,8 @@ pub struct ChangePasswordForm {
     error: Option<anyhow::Error>,
     form: Form<FormModel>,
     opaque_data: OpaqueData,
+    /// Whether the passwords are shown in clear.
+    show_passwords: bool,
     // Used to keep the request alive long enough.
     task: Option<FetchTask>,
     route_dispatcher: RouteAgentDispatcher,
@@ -76,6 +82,9 @@ pub struct Props {
 
 pub enum Msg {
     FormUpdate,
+    TogglePasswords,
+    GeneratePassword,
+    CopyPassword,
     Submit,
     AuthenticationStartResponse(Result<Box<login::ServerLoginStartResponse>>),
     SubmitNewPassword,
@@ -96,6 +105,21 @@ impl ChangePasswordForm {
     fn handle_message(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
         match msg {
             Msg::FormUpdate => Ok(true),
+            Msg::TogglePasswords => {
+                self.show_passwords = !self.show_passwords;
+                Ok(true)
+            }
+            Msg::GeneratePassword => {
+                let password = generate_password();
+                self.form.set_field_value("password", &password);
+                self.form.set_field_value("confirm_password", &password);
+                self.show_passwords = true;
+                Ok(true)
+            }
+            Msg::CopyPassword => {
+                copy_to_clipboard(&self.form.model().password)?;
+                Ok(false)
+            }
             Msg::Submit => {
                 if !self.form.validate() {
                     bail!("Check the form for errors");
@@ -214,6 +238,7 @@ impl Component for ChangePasswordForm {
             error: None,
             form: yew_form::Form::<FormModel>::new(FormModel::default()),
             opaque_data: OpaqueData::None,
+            show_passwords: false,
             task: None,
             route_dispatcher: RouteAgentDispatcher::new(),
         }
@@ -238,6 +263,13 @@ impl Component for ChangePasswordForm {
 
     fn view(&self) -> Html {
         let is_admin = self.props.is_admin;
+        let input_type = password_field::input_type(self.show_passwords);
+        let reveal_button = || {
+            password_field::view_reveal_button(
+                self.show_passwords,
+                self.link.callback(|_| Msg::TogglePasswords),
+            )
+        };
         type Field = yew_form::Field<FormModel>;
         html! {
           <>
@@ -249,15 +281,17 @@ impl Component for ChangePasswordForm {
                     class="form-label col-sm-2 col-form-label">
                     {"Current password*:"}
                   </label>
-                  <div class="col-sm-10">
+                  <div class="col-sm-10 input-group has-validation">
                     <Field
                       form=&self.form
+                      input_type=input_type
                       field_name="old_password"
                       class="form-control"
                       class_invalid="is-invalid has-error"
                       class_valid="has-success"
                       autocomplete="current-password"
                       oninput=self.link.callback(|_| Msg::FormUpdate) />
+                    {reveal_button()}
                     <div class="invalid-feedback">
                       {&self.form.field_message("old_password")}
                     </div>
@@ -265,19 +299,25 @@ impl Component for ChangePasswordForm {
                 </div>
               }} else { html! {} }}
               <div class="form-group row">
-                <label for="new_password"
+                <label for="password"
                   class="form-label col-sm-2 col-form-label">
                   {"New password*:"}
                 </label>
-                <div class="col-sm-10">
+                <div class="col-sm-10 input-group has-validation">
                   <Field
                     form=&self.form
+                    input_type=input_type
                     field_name="password"
                     class="form-control"
                     class_invalid="is-invalid has-error"
                     class_valid="has-success"
                     autocomplete="new-password"
                     oninput=self.link.callback(|_| Msg::FormUpdate) />
+                  {reveal_button()}
+                  {password_field::view_generator_buttons(
+                    self.link.callback(|_| Msg::GeneratePassword),
+                    self.link.callback(|_| Msg::CopyPassword),
+                  )}
                   <div class="invalid-feedback">
                     {&self.form.field_message("password")}
                   </div>
@@ -291,6 +331,7 @@ impl Component for ChangePasswordForm {
                 <div class="col-sm-10">
                   <Field
                     form=&self.form
+                    input_type=input_type
                     field_name="confirm_password"
                     class="form-control"
                     class_invalid="is-invalid has-error"
@@ -300,6 +341,10 @@ impl Component for ChangePasswordForm {
                   <div class="invalid-feedback">
                     {&self.form.field_message("confirm_password")}
                   </div>
+                  <PasswordStrength
+                    password=self.form.model().password
+                    confirm_password=self.form.model().confirm_password
+                    user_inputs=vec![self.props.username.clone()] />
                 </div>
               </div>
               <div class="form-group row">
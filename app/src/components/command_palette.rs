@@ -0,0 +1,351 @@
+use crate::{
+    components::{
+        add_group_member::{list_user_names, ListUserNames},
+        group_table::{list_groups_query, ListGroupsQuery},
+        router::AppRoute,
+    },
+    infra::api::HostService,
+};
+use anyhow::Result;
+use std::time::Duration;
+use yew::{
+    html::InputData,
+    prelude::*,
+    services::{
+        fetch::FetchTask,
+        timeout::{TimeoutService, TimeoutTask},
+        ConsoleService,
+    },
+};
+use yew_router::{
+    agent::{RouteAgentDispatcher, RouteRequest},
+    route::Route,
+};
+
+/// How long to wait after the last key press before searching the users.
+const SEARCH_DELAY: Duration = Duration::from_millis(200);
+/// The most users and groups listed: the search should be refined past that.
+const MAX_RESULTS: usize = 8;
+
+/// Something to jump to.
+#[derive(Clone, PartialEq, Debug)]
+struct Item {
+    icon: &'static str,
+    label: String,
+    /// Shown after the label, e.g. the display name of a user.
+    detail: String,
+    route: AppRoute,
+}
+
+/// The command palette opened with Ctrl+K: jumps to a page, or to a user or group by name.
+pub struct CommandPalette {
+    link: ComponentLink<Self>,
+    props: Props,
+    route_dispatcher: RouteAgentDispatcher,
+    input_ref: NodeRef,
+    query: String,
+    /// The index of the highlighted item.
+    active: usize,
+    users: Vec<list_user_names::ListUserNamesUsers>,
+    /// All the groups, searched locally.
+    groups: Vec<list_groups_query::ListGroupsQueryGroups>,
+    search_timeout: Option<TimeoutTask>,
+    search_task: Option<FetchTask>,
+    groups_task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub is_admin: bool,
+    /// Whether the user can browse the users and groups.
+    pub can_read_all: bool,
+    pub on_close: Callback<()>,
+}
+
+pub enum Msg {
+    QueryChanged(String),
+    Search,
+    UsersResponse(String, Result<list_user_names::ResponseData>),
+    GroupsResponse(Result<list_groups_query::ResponseData>),
+    KeyDown(KeyboardEvent),
+    Highlight(usize),
+    Choose(usize),
+    Close,
+}
+
+fn contains_ignore_case(text: &str, query: &str) -> bool {
+    text.to_lowercase().contains(&query.to_lowercase())
+}
+
+impl CommandPalette {
+    fn pages(&self) -> Vec<Item> {
+        let page = |icon, label: &str, route| Item {
+            icon,
+            label: label.to_string(),
+            detail: String::new(),
+            route,
+        };
+        let mut pages = Vec::new();
+        if self.props.can_read_all {
+            pages.push(page("bi-people", "Users", AppRoute::ListUsers));
+            pages.push(page("bi-collection", "Groups", AppRoute::ListGroups));
+        }
+        if self.props.is_admin {
+            pages.extend(vec![
+                page("bi-person-plus", "Create a user", AppRoute::CreateUser),
+                page("bi-plus-square", "Create a group", AppRoute::CreateGroup),
+                page("bi-clock-history", "Jobs", AppRoute::ListJobs),
+                page("bi-trash", "Deleted users", AppRoute::ListDeletedUsers),
+                page("bi-search", "LDAP query tester", AppRoute::LdapSearch),
+                page("bi-braces", "GraphQL explorer", AppRoute::GraphQLExplorer),
+            ]);
+        }
+        pages
+    }
+
+    /// The items matching the query: the pages, then the users and the groups.
+    fn items(&self) -> Vec<Item> {
+        let query = self.query.trim();
+        let mut items: Vec<Item> = self
+            .pages()
+            .into_iter()
+            .filter(|item| contains_ignore_case(&item.label, query))
+            .collect();
+        if query.is_empty() {
+            return items;
+        }
+        items.extend(self.users.iter().map(|user| Item {
+            icon: "bi-person",
+            label: user.id.clone(),
+            detail: user.display_name.clone(),
+            route: AppRoute::UserDetails(user.id.clone()),
+        }));
+        items.extend(
+            self.groups
+                .iter()
+                .filter(|group| contains_ignore_case(&group.display_name, query))
+                .take(MAX_RESULTS)
+                .map(|group| Item {
+                    icon: "bi-collection",
+                    label: group.display_name.clone(),
+                    detail: format!("{} members", group.member_count),
+                    route: AppRoute::GroupDetails(group.id),
+                }),
+        );
+        items
+    }
+
+    fn search_users(&mut self) -> Result<()> {
+        let search = self.query.trim().to_string();
+        self.search_task = Some(HostService::graphql_query::<ListUserNames>(
+            list_user_names::Variables {
+                filters: Some(list_user_names::RequestFilter {
+                    any: None,
+                    all: None,
+                    not: None,
+                    eq: None,
+                    member_of: None,
+                    member_of_id: None,
+                    expires_before: None,
+                    search: Some(search.clone()),
+                }),
+                limit: Some(MAX_RESULTS as i64),
+            },
+            self.link
+                .callback(move |response| Msg::UsersResponse(search.clone(), response)),
+            "Error trying to search users",
+        )?);
+        Ok(())
+    }
+
+    fn choose(&mut self, index: usize) {
+        if let Some(item) = self.items().into_iter().nth(index) {
+            self.route_dispatcher
+                .send(RouteRequest::ChangeRoute(Route::from(item.route)));
+            self.props.on_close.emit(());
+        }
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::QueryChanged(query) => {
+                self.query = query;
+                self.active = 0;
+                self.users.clear();
+                self.search_task = None;
+                self.search_timeout = if self.props.can_read_all && !self.query.trim().is_empty() {
+                    Some(TimeoutService::spawn(
+                        SEARCH_DELAY,
+                        self.link.callback(|_| Msg::Search),
+                    ))
+                } else {
+                    None
+                };
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                self.search_users()?;
+                return Ok(false);
+            }
+            Msg::UsersResponse(search, response) => {
+                self.search_task = None;
+                // A response to an older search.
+                if search != self.query.trim() {
+                    return Ok(false);
+                }
+                self.users = response?.users;
+            }
+            Msg::GroupsResponse(response) => {
+                self.groups_task = None;
+                self.groups = response?.groups;
+            }
+            Msg::KeyDown(event) => {
+                let count = self.items().len();
+                match event.key().as_str() {
+                    "ArrowDown" if count > 0 => self.active = (self.active + 1) % count,
+                    "ArrowUp" if count > 0 => self.active = (self.active + count - 1) % count,
+                    "Enter" => self.choose(self.active),
+                    "Escape" => self.props.on_close.emit(()),
+                    _ => return Ok(false),
+                }
+                event.prevent_default();
+            }
+            Msg::Highlight(index) => {
+                if self.active == index {
+                    return Ok(false);
+                }
+                self.active = index;
+            }
+            Msg::Choose(index) => self.choose(index),
+            Msg::Close => self.props.on_close.emit(()),
+        }
+        Ok(true)
+    }
+
+    fn view_item(&self, index: usize, item: &Item) -> Html {
+        let class = if index == self.active {
+            "list-group-item list-group-item-action d-flex align-items-center active"
+        } else {
+            "list-group-item list-group-item-action d-flex align-items-center"
+        };
+        html! {
+          <button
+            type="button"
+            id=format!("command-palette-item-{}", index)
+            class=class
+            role="option"
+            aria-selected=(index == self.active).to_string()
+            tabindex="-1"
+            onmousemove=self.link.callback(move |_| Msg::Highlight(index))
+            onclick=self.link.callback(move |_| Msg::Choose(index))>
+            <i class=format!("{} me-2", item.icon) aria-hidden="true"></i>
+            <span>{&item.label}</span>
+            <small class="ms-auto text-muted">{&item.detail}</small>
+          </button>
+        }
+    }
+}
+
+impl Component for CommandPalette {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut palette = Self {
+            link,
+            props,
+            route_dispatcher: RouteAgentDispatcher::new(),
+            input_ref: NodeRef::default(),
+            query: String::new(),
+            active: 0,
+            users: Vec::new(),
+            groups: Vec::new(),
+            search_timeout: None,
+            search_task: None,
+            groups_task: None,
+        };
+        if palette.props.can_read_all {
+            palette.groups_task = HostService::graphql_query::<ListGroupsQuery>(
+                list_groups_query::Variables {},
+                palette.link.callback(Msg::GroupsResponse),
+                "Error trying to fetch groups",
+            )
+            .map_err(|e| ConsoleService::error(&e.to_string()))
+            .ok();
+        }
+        palette
+    }
+
+    fn rendered(&mut self, first_render: bool) {
+        if first_render {
+            if let Some(input) = self.input_ref.cast::<web_sys::HtmlInputElement>() {
+                let _ = input.focus();
+            }
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        false
+    }
+
+    fn view(&self) -> Html {
+        let items = self.items();
+        let active_descendant = if items.is_empty() {
+            String::new()
+        } else {
+            format!("command-palette-item-{}", self.active)
+        };
+        html! {
+          <>
+            <div class="modal-backdrop show" onclick=self.link.callback(|_| Msg::Close)></div>
+            <div
+              class="modal d-block command-palette"
+              role="dialog"
+              aria-modal="true"
+              aria-label="Command palette"
+              onclick=self.link.callback(|_| Msg::Close)>
+              <div class="modal-dialog" onclick=Callback::from(|e: MouseEvent| e.stop_propagation())>
+                <div class="modal-content">
+                  <div class="modal-header">
+                    <input
+                      type="text"
+                      ref=self.input_ref.clone()
+                      class="form-control"
+                      placeholder="Go to a page, user or group..."
+                      role="combobox"
+                      aria-expanded="true"
+                      aria-controls="command-palette-items"
+                      aria-activedescendant=active_descendant
+                      value=self.query.clone()
+                      oninput=self.link.callback(|e: InputData| Msg::QueryChanged(e.value))
+                      onkeydown=self.link.callback(Msg::KeyDown) />
+                  </div>
+                  <div id="command-palette-items" class="list-group list-group-flush" role="listbox">
+                    {if items.is_empty() { html! {
+                      <div class="list-group-item text-muted">{"No match."}</div>
+                    } } else {
+                      items.iter().enumerate().map(|(i, item)| self.view_item(i, item)).collect::<Html>()
+                    } }
+                  </div>
+                  <div class="modal-footer small text-muted justify-content-start">
+                    {"\u{2191}\u{2193} to move, Enter to go, Esc to close. "}
+                    {"Elsewhere: / to search, n for a new user, Shift+N for a new group."}
+                  </div>
+                </div>
+              </div>
+            </div>
+          </>
+        }
+    }
+}
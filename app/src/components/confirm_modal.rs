@@ -0,0 +1,178 @@
+use crate::infra::modal::Modal;
+use yew::{html::InputData, prelude::*};
+use yewtil::NeqAssign;
+
+/// A button that asks for a confirmation in a dialog before running a destructive action. For the
+/// most dangerous ones, the admin can be made to type the name of what is affected.
+pub struct ConfirmModal {
+    link: ComponentLink<Self>,
+    props: Props,
+    node_ref: NodeRef,
+    modal: Option<Modal>,
+    /// What the admin typed so far, when a name is required.
+    typed_name: String,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// Unique in the page, to label the dialog.
+    pub id: String,
+    pub title: String,
+    /// The question asked in the body of the dialog.
+    pub message: Html,
+    /// The name to type to enable the confirmation button, if any.
+    #[prop_or_default]
+    pub confirm_name: Option<String>,
+    #[prop_or("btn btn-danger".to_string())]
+    pub button_class: String,
+    #[prop_or_default]
+    pub disabled: bool,
+    pub on_confirm: Callback<()>,
+    /// The content of the button opening the dialog.
+    pub children: Children,
+}
+
+pub enum Msg {
+    Clicked,
+    NameInput(InputData),
+    Confirm,
+    Dismiss,
+}
+
+impl ConfirmModal {
+    fn modal(&self) -> &Modal {
+        self.modal.as_ref().expect("modal not initialized")
+    }
+
+    fn is_confirmed(&self) -> bool {
+        match &self.props.confirm_name {
+            None => true,
+            Some(name) => *name == self.typed_name,
+        }
+    }
+
+    fn view_name_input(&self) -> Html {
+        match &self.props.confirm_name {
+            None => html! {},
+            Some(name) => html! {
+              <div class="mt-3">
+                <label for=format!("{}Name", self.props.id) class="form-label">
+                  {"Type "}<b>{name}</b>{" to confirm:"}
+                </label>
+                <input
+                  type="text"
+                  class="form-control"
+                  id=format!("{}Name", self.props.id)
+                  autocomplete="off"
+                  value=self.typed_name.clone()
+                  oninput=self.link.callback(Msg::NameInput) />
+              </div>
+            },
+        }
+    }
+
+    fn view_modal(&self) -> Html {
+        let label_id = format!("{}Label", self.props.id);
+        html! {
+          <div
+            class="modal fade"
+            id=self.props.id.clone()
+            tabindex="-1"
+            aria-labelledby=label_id.clone()
+            aria-hidden="true"
+            ref=self.node_ref.clone()>
+            <div class="modal-dialog">
+              <div class="modal-content">
+                <div class="modal-header">
+                  <h5 class="modal-title" id=label_id>{&self.props.title}</h5>
+                  <button
+                    type="button"
+                    class="btn-close"
+                    aria-label="Close"
+                    onclick=self.link.callback(|_| Msg::Dismiss) />
+                </div>
+                <div class="modal-body">
+                  <span>{self.props.message.clone()}</span>
+                  {self.view_name_input()}
+                </div>
+                <div class="modal-footer">
+                  <button
+                    type="button"
+                    class="btn btn-secondary"
+                    onclick=self.link.callback(|_| Msg::Dismiss)>
+                      {"Cancel"}
+                  </button>
+                  <button
+                    type="button"
+                    disabled=!self.is_confirmed()
+                    onclick=self.link.callback(|_| Msg::Confirm)
+                    class="btn btn-danger">{"Yes, I'm sure"}</button>
+                </div>
+              </div>
+            </div>
+          </div>
+        }
+    }
+}
+
+impl Component for ConfirmModal {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            node_ref: NodeRef::default(),
+            modal: None,
+            typed_name: String::new(),
+        }
+    }
+
+    fn rendered(&mut self, first_render: bool) {
+        if first_render {
+            self.modal = Some(Modal::new(
+                self.node_ref
+                    .cast::<web_sys::Element>()
+                    .expect("Modal node is not an element"),
+            ));
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Clicked => {
+                self.typed_name.clear();
+                self.modal().show();
+            }
+            Msg::NameInput(data) => self.typed_name = data.value,
+            Msg::Confirm => {
+                if !self.is_confirmed() {
+                    return false;
+                }
+                self.modal().hide();
+                self.props.on_confirm.emit(());
+            }
+            Msg::Dismiss => self.modal().hide(),
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <>
+          <button
+            class=self.props.button_class.clone()
+            disabled=self.props.disabled
+            onclick=self.link.callback(|_| Msg::Clicked)>
+            {self.props.children.clone()}
+          </button>
+          {self.view_modal()}
+          </>
+        }
+    }
+}
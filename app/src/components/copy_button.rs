@@ -0,0 +1,98 @@
+use crate::infra::clipboard::copy_to_clipboard;
+use yew::{
+    prelude::*,
+    services::{
+        timeout::{TimeoutService, TimeoutTask},
+        ConsoleService,
+    },
+};
+use yewtil::NeqAssign;
+
+/// How long the outcome of the copy is shown, before the button goes back to normal.
+const FEEDBACK_DELAY_MS: u64 = 2000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Feedback {
+    Copied,
+    Failed,
+}
+
+/// A small button copying a text to the clipboard, which shows for a moment whether it worked.
+pub struct CopyButton {
+    link: ComponentLink<Self>,
+    props: Props,
+    feedback: Option<Feedback>,
+    _reset_timeout: Option<TimeoutTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub text: String,
+    /// What is copied, for the tooltip and screen readers.
+    #[prop_or("Copy".to_string())]
+    pub title: String,
+}
+
+pub enum Msg {
+    Copy,
+    Reset,
+}
+
+impl Component for CopyButton {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            feedback: None,
+            _reset_timeout: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Copy => {
+                self.feedback = Some(match copy_to_clipboard(&self.props.text) {
+                    Ok(()) => Feedback::Copied,
+                    Err(e) => {
+                        ConsoleService::error(&e.to_string());
+                        Feedback::Failed
+                    }
+                });
+                self._reset_timeout = Some(TimeoutService::spawn(
+                    std::time::Duration::from_millis(FEEDBACK_DELAY_MS),
+                    self.link.callback(|_| Msg::Reset),
+                ));
+            }
+            Msg::Reset => {
+                self.feedback = None;
+                self._reset_timeout = None;
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let (icon, title) = match self.feedback {
+            None => ("bi-clipboard", self.props.title.clone()),
+            Some(Feedback::Copied) => ("bi-clipboard-check text-success", "Copied".to_string()),
+            Some(Feedback::Failed) => ("bi-clipboard-x text-danger", "Could not copy".to_string()),
+        };
+        html! {
+          <button
+            type="button"
+            class="btn btn-link btn-sm text-reset py-0"
+            title=title.clone()
+            aria-label=title
+            onclick=self.link.callback(|_| Msg::Copy)>
+            <i class=icon></i>
+          </button>
+        }
+    }
+}
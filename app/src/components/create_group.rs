@@ -15,7 +15,8 @@ use yew_router::{
     schema_path = "../schema.graphql",
     query_path = "queries/create_group.graphql",
     response_derives = "Debug",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct CreateGroup;
 
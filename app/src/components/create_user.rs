@@ -1,7 +1,17 @@
-use crate::{components::router::AppRoute, infra::api::HostService};
+use crate::{
+    components::{
+        add_user_to_group::{get_group_list, GetGroupList},
+        avatar::Avatar,
+        copy_button::CopyButton,
+        password_field,
+        router::{AppRoute, NavButton},
+    },
+    infra::{api::HostService, clipboard::copy_to_clipboard, password::generate_password},
+};
 use anyhow::{bail, Context, Result};
 use graphql_client::GraphQLQuery;
 use lldap_auth::{opaque, registration};
+use std::collections::BTreeSet;
 use validator_derive::Validate;
 use yew::prelude::*;
 use yew::services::{fetch::FetchTask, ConsoleService};
@@ -16,17 +26,39 @@ use yew_router::{
     schema_path = "../schema.graphql",
     query_path = "queries/create_user.graphql",
     response_derives = "Debug",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct CreateUser;
 
+/// How the new user gets their password.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PasswordMode {
+    /// Typed in the form, or none if left empty.
+    Set,
+    /// A random one, shown once the user is created to be shared with them.
+    Generate,
+    /// An invite link, shown once the user is created, for them to choose it.
+    Invite,
+}
+
 pub struct CreateUserForm {
     link: ComponentLink<Self>,
     route_dispatcher: RouteAgentDispatcher,
     form: yew_form::Form<CreateUserModel>,
+    password_mode: PasswordMode,
+    /// The base64-encoded JPEG of the picture, if one was chosen.
+    avatar: Option<String>,
+    groups: Vec<get_group_list::GetGroupListGroups>,
+    selected_groups: BTreeSet<i64>,
     error: Option<anyhow::Error>,
+    /// Whether the passwords are shown in clear.
+    show_passwords: bool,
+    /// The generated password or the invite link, once the user is created.
+    created_secret: Option<String>,
     // Used to keep the request alive long enough.
     task: Option<FetchTask>,
+    groups_task: Option<FetchTask>,
 }
 
 #[derive(Model, Validate, PartialEq, Clone, Default)]
@@ -35,7 +67,7 @@ pub struct CreateUserModel {
     username: String,
     #[validate(email(message = "A valid email is required"))]
     email: String,
-    #[validate(length(min = 1, message = "Display name is required"))]
+    /// Defaults to the first and last names.
     display_name: String,
     first_name: String,
     last_name: String,
@@ -58,8 +90,17 @@ fn empty_or_long(value: &str) -> Result<(), validator::ValidationError> {
 
 pub enum Msg {
     Update,
+    SetPasswordMode(PasswordMode),
+    TogglePasswords,
+    GeneratePassword,
+    CopyPassword,
+    AvatarChanged(String),
+    AvatarError(anyhow::Error),
+    GroupListResponse(Result<get_group_list::ResponseData>),
+    ToggleGroup(i64),
     SubmitForm,
     CreateUserResponse(Result<create_user::ResponseData>),
+    InviteResponse(Result<String>),
     SuccessfulCreation,
     RegistrationStartResponse(
         (
@@ -74,6 +115,52 @@ impl CreateUserForm {
     fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
         match msg {
             Msg::Update => Ok(true),
+            Msg::SetPasswordMode(mode) => {
+                self.password_mode = mode;
+                let password = match mode {
+                    PasswordMode::Generate => generate_password(),
+                    PasswordMode::Set | PasswordMode::Invite => String::new(),
+                };
+                self.form.set_field_value("password", &password);
+                self.form.set_field_value("confirm_password", &password);
+                self.show_passwords = mode == PasswordMode::Generate;
+                Ok(true)
+            }
+            Msg::TogglePasswords => {
+                self.show_passwords = !self.show_passwords;
+                Ok(true)
+            }
+            Msg::GeneratePassword => {
+                let password = generate_password();
+                self.form.set_field_value("password", &password);
+                self.form.set_field_value("confirm_password", &password);
+                self.show_passwords = true;
+                Ok(true)
+            }
+            Msg::CopyPassword => {
+                copy_to_clipboard(&self.form.model().password)?;
+                Ok(false)
+            }
+            Msg::AvatarChanged(avatar) => {
+                self.avatar = if avatar.is_empty() {
+                    None
+                } else {
+                    Some(avatar)
+                };
+                Ok(false)
+            }
+            Msg::AvatarError(e) => Err(e),
+            Msg::GroupListResponse(response) => {
+                self.groups_task = None;
+                self.groups = response?.groups;
+                Ok(true)
+            }
+            Msg::ToggleGroup(group_id) => {
+                if !self.selected_groups.remove(&group_id) {
+                    self.selected_groups.insert(group_id);
+                }
+                Ok(true)
+            }
             Msg::SubmitForm => {
                 if !self.form.validate() {
                     bail!("Check the form for errors");
@@ -87,6 +174,12 @@ impl CreateUserForm {
                         displayName: to_option(model.display_name),
                         firstName: to_option(model.first_name),
                         lastName: to_option(model.last_name),
+                        avatar: self.avatar.clone(),
+                        groupIds: if self.selected_groups.is_empty() {
+                            None
+                        } else {
+                            Some(self.selected_groups.iter().copied().collect())
+                        },
                     },
                 };
                 self.task = Some(HostService::graphql_query::<CreateUser>(
@@ -107,7 +200,12 @@ impl CreateUserForm {
                 let model = self.form.model();
                 let user_id = model.username;
                 let password = model.password;
-                if !password.is_empty() {
+                if self.password_mode == PasswordMode::Invite {
+                    self.task = Some(HostService::create_invite(
+                        user_id,
+                        self.link.callback(Msg::InviteResponse),
+                    )?);
+                } else if !password.is_empty() {
                     // User was successfully created, let's register the password.
                     let mut rng = rand::rngs::OsRng;
                     let opaque::client::registration::ClientRegistrationStartResult {
@@ -156,13 +254,218 @@ impl CreateUserForm {
                 response?;
                 self.handle_msg(Msg::SuccessfulCreation)
             }
+            Msg::InviteResponse(response) => {
+                let token = response.context("The user was created, but not the invite link")?;
+                self.created_secret = Some(format!("{}/invite/{}", yew::utils::origin()?, token));
+                self.task = None;
+                Ok(true)
+            }
             Msg::SuccessfulCreation => {
-                self.route_dispatcher
-                    .send(RouteRequest::ChangeRoute(Route::from(AppRoute::ListUsers)));
+                self.task = None;
+                if self.password_mode == PasswordMode::Generate {
+                    // Shown until the admin leaves, to be shared with the user.
+                    self.created_secret = Some(self.form.model().password);
+                } else {
+                    self.route_dispatcher
+                        .send(RouteRequest::ChangeRoute(Route::from(AppRoute::ListUsers)));
+                }
                 Ok(true)
             }
         }
     }
+    fn view_avatar(&self) -> Html {
+        html! {
+          <div class="form-group row mb-3">
+            <span class="form-label col-4 col-form-label">{"Avatar:"}</span>
+            <div class="col-8">
+              <Avatar
+                avatar=self.avatar.clone()
+                on_change=Some(self.link.callback(Msg::AvatarChanged))
+                on_error=self.link.callback(Msg::AvatarError) />
+            </div>
+          </div>
+        }
+    }
+
+    fn view_groups(&self) -> Html {
+        let view_group = |group: &get_group_list::GetGroupListGroups| {
+            let group_id = group.id;
+            let id = format!("group_{}", group_id);
+            html! {
+              <div class="form-check">
+                <input
+                  type="checkbox"
+                  class="form-check-input"
+                  id=id.clone()
+                  checked=self.selected_groups.contains(&group_id)
+                  onchange=self.link.callback(move |_| Msg::ToggleGroup(group_id)) />
+                <label class="form-check-label" for=id>{&group.display_name}</label>
+              </div>
+            }
+        };
+        html! {
+          <div class="form-group row mb-3">
+            <span class="form-label col-4 col-form-label">{"Groups:"}</span>
+            <div class="col-8">
+              {if self.groups.is_empty() { html! {
+                <span class="form-text">{"No groups."}</span>
+              } } else { html! {
+                <div class="border rounded p-2" style="max-height: 200px; overflow-y: auto">
+                  {self.groups.iter().map(view_group).collect::<Html>()}
+                </div>
+              } } }
+            </div>
+          </div>
+        }
+    }
+
+    fn view_password(&self) -> Html {
+        let mode = self.password_mode;
+        let view_mode = |value: PasswordMode, id: &str, label: &str| {
+            html! {
+              <div class="form-check">
+                <input
+                  type="radio"
+                  class="form-check-input"
+                  name="password_mode"
+                  id=id.to_string()
+                  checked=(mode == value)
+                  onchange=self.link.callback(move |_| Msg::SetPasswordMode(value)) />
+                <label class="form-check-label" for=id.to_string()>{label}</label>
+              </div>
+            }
+        };
+        html! {
+          <>
+            <div class="form-group row mb-3">
+              <span class="form-label col-4 col-form-label">{"Password:"}</span>
+              <div class="col-8 col-form-label">
+                {view_mode(PasswordMode::Set, "password_mode_set", "Set a password")}
+                {view_mode(PasswordMode::Generate, "password_mode_generate", "Generate a password")}
+                {view_mode(PasswordMode::Invite, "password_mode_invite", "Create an invite link")}
+              </div>
+            </div>
+            {match mode {
+              PasswordMode::Set => self.view_password_fields(),
+              PasswordMode::Generate => html! {
+                <div class="form-group row mb-3">
+                  <label for="password" class="form-label col-4 col-form-label">
+                    {"Generated password:"}
+                  </label>
+                  <div class="col-8 input-group">
+                    <input
+                      id="password"
+                      class="form-control font-monospace"
+                      readonly=true
+                      value=self.form.model().password />
+                    {password_field::view_generator_buttons(
+                      self.link.callback(|_| Msg::GeneratePassword),
+                      self.link.callback(|_| Msg::CopyPassword),
+                    )}
+                  </div>
+                </div>
+              },
+              PasswordMode::Invite => html! {
+                <div class="form-group row mb-3">
+                  <div class="offset-4 col-8 form-text">
+                    {"The link to share with the user, for them to set their password, is shown once the user is created."}
+                  </div>
+                </div>
+              },
+            }}
+          </>
+        }
+    }
+
+    fn view_password_fields(&self) -> Html {
+        let input_type = password_field::input_type(self.show_passwords);
+        type Field = yew_form::Field<CreateUserModel>;
+        html! {
+          <>
+            <div class="form-group row mb-3">
+              <label for="password"
+                class="form-label col-4 col-form-label">
+                {"Password:"}
+              </label>
+              <div class="col-8 input-group has-validation">
+                <Field
+                  form=&self.form
+                  input_type=input_type
+                  field_name="password"
+                  class="form-control"
+                  class_invalid="is-invalid has-error"
+                  class_valid="has-success"
+                  autocomplete="new-password"
+                  oninput=self.link.callback(|_| Msg::Update) />
+                {password_field::view_reveal_button(
+                  self.show_passwords,
+                  self.link.callback(|_| Msg::TogglePasswords),
+                )}
+                {password_field::view_generator_buttons(
+                  self.link.callback(|_| Msg::GeneratePassword),
+                  self.link.callback(|_| Msg::CopyPassword),
+                )}
+                <div class="invalid-feedback">
+                  {&self.form.field_message("password")}
+                </div>
+              </div>
+            </div>
+            <div class="form-group row mb-3">
+              <label for="confirm_password"
+                class="form-label col-4 col-form-label">
+                {"Confirm password:"}
+              </label>
+              <div class="col-8">
+                <Field
+                  form=&self.form
+                  input_type=input_type
+                  field_name="confirm_password"
+                  class="form-control"
+                  class_invalid="is-invalid has-error"
+                  class_valid="has-success"
+                  autocomplete="new-password"
+                  oninput=self.link.callback(|_| Msg::Update) />
+                <div class="invalid-feedback">
+                  {&self.form.field_message("confirm_password")}
+                </div>
+              </div>
+            </div>
+          </>
+        }
+    }
+
+    /// The user was created: the generated password or the invite link, to share with them.
+    fn view_created(&self, secret: &str) -> Html {
+        let (message, title) = match self.password_mode {
+            PasswordMode::Invite => (
+                "Send this link to the user to set their password:",
+                "Copy the invite link",
+            ),
+            PasswordMode::Set | PasswordMode::Generate => (
+                "Share this password with the user, it won't be shown again:",
+                "Copy the password",
+            ),
+        };
+        let user_id = self.form.model().username;
+        html! {
+          <div class="row justify-content-center">
+            <div class="shadow-sm py-3" style="max-width: 636px">
+              <h5 class="fw-bold">{format!("The user {} was created", user_id)}</h5>
+              <div class="alert alert-info d-flex align-items-center">
+                <span class="me-2">{message}</span>
+                <code class="me-2 text-break">{secret}</code>
+                <CopyButton text=secret.to_string() title=title.to_string() />
+              </div>
+              <NavButton classes="btn btn-primary" route=AppRoute::UserDetails(user_id)>
+                {"Go to the user"}
+              </NavButton>
+              <NavButton classes="btn btn-secondary ms-2" route=AppRoute::ListUsers>
+                {"Back to the users"}
+              </NavButton>
+            </div>
+          </div>
+        }
+    }
 }
 
 impl Component for CreateUserForm {
@@ -170,13 +473,28 @@ impl Component for CreateUserForm {
     type Properties = ();
 
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
-        Self {
+        let mut form = Self {
             link,
             route_dispatcher: RouteAgentDispatcher::new(),
             form: yew_form::Form::<CreateUserModel>::new(CreateUserModel::default()),
+            password_mode: PasswordMode::Set,
+            avatar: None,
+            groups: Vec::new(),
+            selected_groups: BTreeSet::new(),
             error: None,
+            show_passwords: false,
+            created_secret: None,
             task: None,
-        }
+            groups_task: None,
+        };
+        form.groups_task = HostService::graphql_query::<GetGroupList>(
+            get_group_list::Variables {},
+            form.link.callback(Msg::GroupListResponse),
+            "Error trying to fetch groups",
+        )
+        .map_err(|e| ConsoleService::error(&e.to_string()))
+        .ok();
+        form
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
@@ -197,6 +515,9 @@ impl Component for CreateUserForm {
     }
 
     fn view(&self) -> Html {
+        if let Some(secret) = &self.created_secret {
+            return self.view_created(secret);
+        }
         type Field = yew_form::Field<CreateUserModel>;
         html! {
           <div class="row justify-content-center">
@@ -244,9 +565,9 @@ impl Component for CreateUserForm {
                 </div>
               </div>
               <div class="form-group row mb-3">
-                <label for="display-name"
+                <label for="display_name"
                   class="form-label col-4 col-form-label">
-                  {"Display name*:"}
+                  {"Display name:"}
                 </label>
                 <div class="col-8">
                   <Field
@@ -263,7 +584,7 @@ impl Component for CreateUserForm {
                 </div>
               </div>
               <div class="form-group row mb-3">
-                <label for="first-name"
+                <label for="first_name"
                   class="form-label col-4 col-form-label">
                   {"First name:"}
                 </label>
@@ -282,7 +603,7 @@ impl Component for CreateUserForm {
                 </div>
               </div>
               <div class="form-group row mb-3">
-                <label for="last-name"
+                <label for="last_name"
                   class="form-label col-4 col-form-label">
                   {"Last name:"}
                 </label>
@@ -300,46 +621,9 @@ impl Component for CreateUserForm {
                   </div>
                 </div>
               </div>
-              <div class="form-group row mb-3">
-                <label for="password"
-                  class="form-label col-4 col-form-label">
-                  {"Password:"}
-                </label>
-                <div class="col-8">
-                  <Field
-                    form=&self.form
-                    input_type="password"
-                    field_name="password"
-                    class="form-control"
-                    class_invalid="is-invalid has-error"
-                    class_valid="has-success"
-                    autocomplete="new-password"
-                    oninput=self.link.callback(|_| Msg::Update) />
-                  <div class="invalid-feedback">
-                    {&self.form.field_message("password")}
-                  </div>
-                </div>
-              </div>
-              <div class="form-group row mb-3">
-                <label for="confirm_password"
-                  class="form-label col-4 col-form-label">
-                  {"Confirm password:"}
-                </label>
-                <div class="col-8">
-                  <Field
-                    form=&self.form
-                    input_type="password"
-                    field_name="confirm_password"
-                    class="form-control"
-                    class_invalid="is-invalid has-error"
-                    class_valid="has-success"
-                    autocomplete="new-password"
-                    oninput=self.link.callback(|_| Msg::Update) />
-                  <div class="invalid-feedback">
-                    {&self.form.field_message("confirm_password")}
-                  </div>
-                </div>
-              </div>
+              {self.view_avatar()}
+              {self.view_groups()}
+              {self.view_password()}
               <div class="form-group row justify-content-center">
                 <button
                   class="btn btn-primary col-auto col-form-label mt-4"
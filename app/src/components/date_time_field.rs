@@ -0,0 +1,228 @@
+//! The date fields of the forms: the browser's date (or date and time) picker, in the browser's
+//! timezone, for the ISO-8601 (RFC 3339) values in UTC that the server expects.
+//!
+//! `DateTimeInput` works on its own, and `DateTimeField` is its `yew_form` version, validated with
+//! the rest of the form.
+use crate::infra::graphql::DateTimeUtc;
+use chrono::{prelude::*, SecondsFormat};
+use yew::{html::InputData, prelude::*};
+use yew_form::{Form, Model};
+use yewtil::NeqAssign;
+
+/// Parses the value of a field, in RFC 3339.
+pub fn parse_date_time(value: &str) -> Option<DateTimeUtc> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// The value of a field, in RFC 3339, e.g. "2021-10-15T12:03:00Z".
+pub fn format_date_time(date: &DateTimeUtc) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// For the `#[validate(custom(...))]` of the date fields of the models: empty, or a date.
+pub fn validate_date_time(value: &str) -> Result<(), validator::ValidationError> {
+    if value.is_empty() || parse_date_time(value).is_some() {
+        Ok(())
+    } else {
+        Err(validator::ValidationError::new("date_time"))
+    }
+}
+
+/// The value of the `<input>`, from the RFC 3339 value. The dates without a time are at midnight
+/// UTC, to be the same day everywhere.
+fn to_input_value(value: &str, include_time: bool) -> String {
+    match parse_date_time(value) {
+        None => String::new(),
+        Some(date) if include_time => date
+            .with_timezone(&Local)
+            .format("%Y-%m-%dT%H:%M")
+            .to_string(),
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// The RFC 3339 value, from the value of the `<input>`. Empty when the input is, or incomplete.
+fn from_input_value(input: &str, include_time: bool) -> String {
+    if include_time {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M")
+            .ok()
+            .and_then(|date| Local.from_local_datetime(&date).earliest())
+            .map(|date| format_date_time(&date.with_timezone(&Utc)))
+            .unwrap_or_default()
+    } else {
+        NaiveDate::parse_from_str(input, "%Y-%m-%d")
+            .ok()
+            .map(|date| format_date_time(&Utc.from_utc_datetime(&date.and_hms(0, 0, 0))))
+            .unwrap_or_default()
+    }
+}
+
+pub struct DateTimeInput {
+    link: ComponentLink<Self>,
+    props: DateTimeInputProps,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct DateTimeInputProps {
+    #[prop_or_default]
+    pub id: String,
+    /// In RFC 3339, or empty.
+    pub value: String,
+    /// Picks a time of the day too, in the browser's timezone.
+    #[prop_or_default]
+    pub include_time: bool,
+    /// The earliest date that can be picked.
+    #[prop_or_default]
+    pub min: Option<DateTimeUtc>,
+    #[prop_or("form-control".to_string())]
+    pub class: String,
+    #[prop_or_default]
+    pub disabled: bool,
+    /// The new value, in RFC 3339, or empty.
+    pub on_change: Callback<String>,
+}
+
+pub enum DateTimeInputMsg {
+    Input(InputData),
+    Clear,
+}
+
+impl Component for DateTimeInput {
+    type Message = DateTimeInputMsg;
+    type Properties = DateTimeInputProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self { link, props }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        let value = match msg {
+            DateTimeInputMsg::Input(data) => from_input_value(&data.value, self.props.include_time),
+            DateTimeInputMsg::Clear => String::new(),
+        };
+        self.props.on_change.emit(value);
+        false
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let include_time = self.props.include_time;
+        let min = self
+            .props
+            .min
+            .as_ref()
+            .map(|min| to_input_value(&format_date_time(min), include_time))
+            .unwrap_or_default();
+        // For the `.invalid-feedback` next to the field, which is outside of the group.
+        let group_class = if self
+            .props
+            .class
+            .split_whitespace()
+            .any(|c| c == "is-invalid")
+        {
+            "input-group is-invalid"
+        } else {
+            "input-group"
+        };
+        let input_type = if include_time {
+            "datetime-local"
+        } else {
+            "date"
+        };
+        html! {
+          <div class=group_class>
+            <input
+              type=input_type
+              id=self.props.id.clone()
+              class=self.props.class.clone()
+              disabled=self.props.disabled
+              min=min
+              value=to_input_value(&self.props.value, include_time)
+              oninput=self.link.callback(DateTimeInputMsg::Input) />
+            {if self.props.value.is_empty() || self.props.disabled { html! {} } else { html! {
+              <button
+                type="button"
+                class="btn btn-outline-secondary"
+                title="Clear the date"
+                aria-label="Clear the date"
+                onclick=self.link.callback(|_| DateTimeInputMsg::Clear)>
+                <i class="bi-x-lg"></i>
+              </button>
+            } } }
+          </div>
+        }
+    }
+}
+
+/// A `DateTimeInput` for a field of a `yew_form` model, like `yew_form::Field`.
+pub struct DateTimeField<T: Model> {
+    link: ComponentLink<Self>,
+    props: DateTimeFieldProps<T>,
+}
+
+#[derive(yew::Properties, Clone)]
+pub struct DateTimeFieldProps<T: Model> {
+    pub form: Form<T>,
+    pub field_name: String,
+    #[prop_or_default]
+    pub include_time: bool,
+    #[prop_or_default]
+    pub min: Option<DateTimeUtc>,
+    #[prop_or("form-control".to_string())]
+    pub class: String,
+    #[prop_or_default]
+    pub class_invalid: String,
+    #[prop_or_default]
+    pub class_valid: String,
+    #[prop_or_default]
+    pub disabled: bool,
+    #[prop_or_else(Callback::noop)]
+    pub oninput: Callback<String>,
+}
+
+impl<T: Model> Component for DateTimeField<T> {
+    type Message = String;
+    type Properties = DateTimeFieldProps<T>;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self { link, props }
+    }
+
+    fn update(&mut self, value: Self::Message) -> ShouldRender {
+        self.props
+            .form
+            .set_field_value(&self.props.field_name, &value);
+        self.props.form.validate();
+        self.props.oninput.emit(value);
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn view(&self) -> Html {
+        let field_name = &self.props.field_name;
+        let class = if self.props.form.field_valid(field_name) {
+            format!("{} {}", self.props.class, self.props.class_valid)
+        } else {
+            format!("{} {}", self.props.class, self.props.class_invalid)
+        };
+        html! {
+          <DateTimeInput
+            id=field_name.clone()
+            value=self.props.form.field_value(field_name)
+            include_time=self.props.include_time
+            min=self.props.min.clone()
+            class=class
+            disabled=self.props.disabled
+            on_change=self.link.callback(|value| value) />
+        }
+    }
+}
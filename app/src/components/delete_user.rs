@@ -1,4 +1,4 @@
-use crate::infra::{api::HostService, modal::Modal};
+use crate::{components::confirm_modal::ConfirmModal, infra::api::HostService};
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
 use yew::prelude::*;
@@ -10,15 +10,14 @@ use yewtil::NeqAssign;
     schema_path = "../schema.graphql",
     query_path = "queries/delete_user.graphql",
     response_derives = "Debug",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct DeleteUserQuery;
 
 pub struct DeleteUser {
     link: ComponentLink<Self>,
     props: DeleteUserProps,
-    node_ref: NodeRef,
-    modal: Option<Modal>,
     task: Option<FetchTask>,
 }
 
@@ -30,9 +29,7 @@ pub struct DeleteUserProps {
 }
 
 pub enum Msg {
-    ClickedDeleteUser,
     ConfirmDeleteUser,
-    DismissModal,
     DeleteUserResponse(Result<delete_user_query::ResponseData>),
 }
 
@@ -44,29 +41,13 @@ impl Component for DeleteUser {
         Self {
             link,
             props,
-            node_ref: NodeRef::default(),
-            modal: None,
             task: None,
         }
     }
 
-    fn rendered(&mut self, first_render: bool) {
-        if first_render {
-            self.modal = Some(Modal::new(
-                self.node_ref
-                    .cast::<web_sys::Element>()
-                    .expect("Modal node is not an element"),
-            ));
-        }
-    }
-
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
-            Msg::ClickedDeleteUser => {
-                self.modal.as_ref().expect("modal not initialized").show();
-            }
             Msg::ConfirmDeleteUser => {
-                self.update(Msg::DismissModal);
                 self.task = HostService::graphql_query::<DeleteUserQuery>(
                     delete_user_query::Variables {
                         user: self.props.username.clone(),
@@ -77,9 +58,6 @@ impl Component for DeleteUser {
                 .map_err(|e| self.props.on_error.emit(e))
                 .ok();
             }
-            Msg::DismissModal => {
-                self.modal.as_ref().expect("modal not initialized").hide();
-            }
             Msg::DeleteUserResponse(response) => {
                 self.task = None;
                 if let Err(e) = response {
@@ -98,61 +76,19 @@ impl Component for DeleteUser {
 
     fn view(&self) -> Html {
         html! {
-          <>
-          <button
-            class="btn btn-danger"
+          <ConfirmModal
+            id="deleteUserModal".to_string() + &self.props.username
+            title="Delete user?"
+            message=html! {
+              <>
+                {"Are you sure you want to delete user "}
+                <b>{&self.props.username}</b>{"?"}
+              </>
+            }
             disabled=self.task.is_some()
-            onclick=self.link.callback(|_| Msg::ClickedDeleteUser)>
+            on_confirm=self.link.callback(|_| Msg::ConfirmDeleteUser)>
             <i class="bi-x-circle-fill" aria-label="Delete user" />
-          </button>
-          {self.show_modal()}
-          </>
-        }
-    }
-}
-
-impl DeleteUser {
-    fn show_modal(&self) -> Html {
-        html! {
-          <div
-            class="modal fade"
-            id="deleteUserModal".to_string() + &self.props.username
-            tabindex="-1"
-            //role="dialog"
-            aria-labelledby="deleteUserModalLabel"
-            aria-hidden="true"
-            ref=self.node_ref.clone()>
-            <div class="modal-dialog" /*role="document"*/>
-              <div class="modal-content">
-                <div class="modal-header">
-                  <h5 class="modal-title" id="deleteUserModalLabel">{"Delete user?"}</h5>
-                  <button
-                    type="button"
-                    class="btn-close"
-                    aria-label="Close"
-                    onclick=self.link.callback(|_| Msg::DismissModal) />
-                </div>
-                <div class="modal-body">
-                <span>
-                  {"Are you sure you want to delete user "}
-                  <b>{&self.props.username}</b>{"?"}
-                </span>
-                </div>
-                <div class="modal-footer">
-                  <button
-                    type="button"
-                    class="btn btn-secondary"
-                    onclick=self.link.callback(|_| Msg::DismissModal)>
-                      {"Cancel"}
-                  </button>
-                  <button
-                    type="button"
-                    onclick=self.link.callback(|_| Msg::ConfirmDeleteUser)
-                    class="btn btn-danger">{"Yes, I'm sure"}</button>
-                </div>
-              </div>
-            </div>
-          </div>
+          </ConfirmModal>
         }
     }
 }
@@ -0,0 +1,218 @@
+use crate::{
+    components::loading,
+    infra::{api::HostService, date},
+};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::{fetch::FetchTask, ConsoleService};
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/list_deleted_users.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct ListDeletedUsers;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/restore_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct RestoreUser;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/purge_deleted_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct PurgeDeletedUser;
+
+pub type DeletedUser = list_deleted_users::ListDeletedUsersDeletedUsers;
+
+/// The recently deleted users, with buttons to restore them or to delete them for good.
+pub struct DeletedUserTable {
+    link: ComponentLink<Self>,
+    users: Option<Vec<DeletedUser>>,
+    error: Option<Error>,
+    // Used to keep the request alive long enough.
+    task: Option<FetchTask>,
+}
+
+pub enum Msg {
+    ListDeletedUsersResponse(Result<list_deleted_users::ResponseData>),
+    /// Fetches the deleted users again, after an error.
+    Retry,
+    Restore(String),
+    Purge(String),
+    UserUpdated(Result<()>),
+}
+
+impl DeletedUserTable {
+    fn get_users(&mut self) -> Result<()> {
+        self.task = Some(HostService::graphql_query::<ListDeletedUsers>(
+            list_deleted_users::Variables {},
+            self.link.callback(Msg::ListDeletedUsersResponse),
+            "Error trying to fetch the deleted users",
+        )?);
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::ListDeletedUsersResponse(users) => {
+                self.task = None;
+                self.users = Some(users?.deleted_users);
+            }
+            Msg::Retry => self.get_users()?,
+            Msg::Restore(user_id) => {
+                self.task = Some(HostService::graphql_query::<RestoreUser>(
+                    restore_user::Variables { user_id },
+                    self.link
+                        .callback(|r: Result<_>| Msg::UserUpdated(r.map(|_| ()))),
+                    "Error trying to restore the user",
+                )?);
+            }
+            Msg::Purge(user_id) => {
+                self.task = Some(HostService::graphql_query::<PurgeDeletedUser>(
+                    purge_deleted_user::Variables { user_id },
+                    self.link
+                        .callback(|r: Result<_>| Msg::UserUpdated(r.map(|_| ()))),
+                    "Error trying to purge the user",
+                )?);
+            }
+            Msg::UserUpdated(response) => {
+                self.task = None;
+                response?;
+                self.get_users()?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_users(&self) -> Html {
+        match &self.users {
+            None if self.error.is_some() => html! {},
+            None => loading::view_skeleton_table(5),
+            Some(users) if users.is_empty() => loading::view_empty("No deleted users."),
+            Some(users) => html! {
+                <div class="table-responsive">
+                  <table class="table table-striped">
+                    <thead>
+                      <tr>
+                        <th>{"User ID"}</th>
+                        <th>{"Email"}</th>
+                        <th>{"Display name"}</th>
+                        <th>{"Deleted"}</th>
+                        <th>{"Actions"}</th>
+                      </tr>
+                    </thead>
+                    <tbody>
+                      {users.iter().map(|u| self.view_user(u)).collect::<Vec<_>>()}
+                    </tbody>
+                  </table>
+                </div>
+            },
+        }
+    }
+
+    fn view_user(&self, user: &DeletedUser) -> Html {
+        let user_id = user.id.clone();
+        let on_restore = self.link.callback(move |_| Msg::Restore(user_id.clone()));
+        let user_id = user.id.clone();
+        let on_purge = self.link.callback(move |_| Msg::Purge(user_id.clone()));
+        html! {
+          <tr key=user.id.clone()>
+            <td>{&user.id}</td>
+            <td>{&user.email}</td>
+            <td>{&user.display_name}</td>
+            <td>
+              <span title=date::format_local_date_time(&user.deleted_at)>
+                {date::format_relative(&user.deleted_at)}
+              </span>
+            </td>
+            <td>
+              <button
+                class="btn btn-outline-primary me-2"
+                title="Restore"
+                disabled=self.task.is_some()
+                onclick=on_restore>
+                <i class="bi-arrow-counterclockwise" aria-label="Restore" />
+              </button>
+              <button
+                class="btn btn-danger"
+                title="Delete permanently"
+                disabled=self.task.is_some()
+                onclick=on_purge>
+                <i class="bi-x-circle-fill" aria-label="Delete permanently" />
+              </button>
+            </td>
+          </tr>
+        }
+    }
+
+    fn view_errors(&self) -> Html {
+        match (&self.error, &self.users) {
+            (None, _) => html! {},
+            // The list could not be loaded at all.
+            (Some(e), None) => {
+                loading::view_error_with_retry(e, self.link.callback(|_| Msg::Retry))
+            }
+            (Some(e), Some(_)) => loading::view_error(e),
+        }
+    }
+}
+
+impl Component for DeletedUserTable {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut table = DeletedUserTable {
+            link,
+            users: None,
+            error: None,
+            task: None,
+        };
+        if let Err(e) = table.get_users() {
+            ConsoleService::error(&e.to_string());
+            table.error = Some(e);
+        }
+        table
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        self.error = None;
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.task = None;
+                self.error = Some(e);
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div>
+              {self.view_users()}
+              {self.view_errors()}
+            </div>
+        }
+    }
+}
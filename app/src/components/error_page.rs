@@ -0,0 +1,49 @@
+//! The pages shown instead of the requested one: when it doesn't exist, or when the user isn't
+//! allowed to see it.
+use crate::components::{
+    loading,
+    router::{AppRoute, Link},
+};
+use anyhow::Error;
+use yew::prelude::*;
+
+fn view_page(icon: &str, title: &str, message: &str) -> Html {
+    html! {
+      <div class="text-center py-5">
+        <i class=format!("{} display-4 text-muted", icon) aria-hidden="true"></i>
+        <h3 class="mt-3">{title}</h3>
+        <p class="text-muted">{message}</p>
+        <Link classes="btn btn-primary" route=AppRoute::Index>
+          <i class="bi-house me-2"></i>
+          {"Back to the home page"}
+        </Link>
+      </div>
+    }
+}
+
+/// For the unknown routes, and the users and groups that don't exist.
+pub fn view_not_found(message: &str) -> Html {
+    view_page("bi-question-circle", "Not found", message)
+}
+
+/// For the pages of the admins, and the data the server refused to show.
+pub fn view_access_denied() -> Html {
+    view_page(
+        "bi-shield-lock",
+        "Access denied",
+        "You don't have the permission to see this page.",
+    )
+}
+
+/// The error that prevented a page from loading: the missing entities and the refused accesses
+/// get their own page, since retrying wouldn't help.
+pub fn view_load_error(error: &Error, on_retry: Callback<MouseEvent>) -> Html {
+    let message = error.to_string();
+    if message.contains("not found") {
+        view_not_found("It may have been deleted or renamed.")
+    } else if message.contains("Unauthorized access") {
+        view_access_denied()
+    } else {
+        loading::view_error_with_retry(error, on_retry)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{
+    components::user_table::{list_users_query, search_filter, Column, ListUsersQuery, User},
+    infra::{
+        api::HostService,
+        csv::{download_csv, to_csv},
+    },
+};
+use anyhow::{Error, Result};
+use yew::{
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yewtil::NeqAssign;
+
+/// The columns that can be exported.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportColumn {
+    UserId,
+    Email,
+    DisplayName,
+    FirstName,
+    LastName,
+    CreationDate,
+    Enabled,
+}
+
+const EXPORT_COLUMNS: [ExportColumn; 7] = [
+    ExportColumn::UserId,
+    ExportColumn::Email,
+    ExportColumn::DisplayName,
+    ExportColumn::FirstName,
+    ExportColumn::LastName,
+    ExportColumn::CreationDate,
+    ExportColumn::Enabled,
+];
+
+impl ExportColumn {
+    fn header(self) -> &'static str {
+        match self {
+            ExportColumn::UserId => "User ID",
+            ExportColumn::Email => "Email",
+            ExportColumn::DisplayName => "Display name",
+            ExportColumn::FirstName => "First name",
+            ExportColumn::LastName => "Last name",
+            ExportColumn::CreationDate => "Creation date",
+            ExportColumn::Enabled => "Enabled",
+        }
+    }
+
+    fn value(self, user: &User) -> String {
+        match self {
+            ExportColumn::UserId => user.id.clone(),
+            ExportColumn::Email => user.email.clone(),
+            ExportColumn::DisplayName => user.display_name.clone(),
+            ExportColumn::FirstName => user.first_name.clone(),
+            ExportColumn::LastName => user.last_name.clone(),
+            ExportColumn::CreationDate => user.creation_date.to_rfc3339(),
+            ExportColumn::Enabled => user.enabled.to_string(),
+        }
+    }
+}
+
+/// A button to download the users matching the search of the user table as a CSV file, with a
+/// menu to pick the columns.
+pub struct ExportUsers {
+    link: ComponentLink<Self>,
+    props: Props,
+    columns: Vec<ExportColumn>,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// The search of the table, if any.
+    pub search: String,
+    pub sort: Column,
+    pub descending: bool,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    ToggleColumn(ExportColumn),
+    Export,
+    ListUsersResponse(Result<list_users_query::ResponseData>),
+}
+
+impl ExportUsers {
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::ToggleColumn(column) => {
+                if self.columns.contains(&column) {
+                    self.columns.retain(|c| *c != column);
+                } else {
+                    // Keep the order of the table.
+                    self.columns.push(column);
+                    self.columns.sort_by_key(|c| {
+                        EXPORT_COLUMNS.iter().position(|other| other == c).unwrap()
+                    });
+                }
+            }
+            Msg::Export => {
+                // All the matching users, not only the current page.
+                self.task = Some(HostService::graphql_query::<ListUsersQuery>(
+                    list_users_query::Variables {
+                        filters: search_filter(&self.props.search),
+                        offset: None,
+                        limit: None,
+                        sort: Some(self.props.sort.to_query()),
+                        descending: Some(self.props.descending),
+                    },
+                    self.link.callback(Msg::ListUsersResponse),
+                    "Error trying to export users",
+                )?);
+            }
+            Msg::ListUsersResponse(response) => {
+                self.task = None;
+                let users = response?.users;
+                let mut rows = vec![self
+                    .columns
+                    .iter()
+                    .map(|c| c.header().to_string())
+                    .collect::<Vec<_>>()];
+                rows.extend(
+                    users
+                        .iter()
+                        .map(|u| self.columns.iter().map(|c| c.value(u)).collect()),
+                );
+                download_csv("users.csv", &to_csv(&rows))?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Component for ExportUsers {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            columns: EXPORT_COLUMNS.to_vec(),
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <div class="btn-group ms-2">
+            <button
+              class="btn btn-secondary text-nowrap"
+              title="Download the users matching the search"
+              disabled=self.task.is_some() || self.columns.is_empty()
+              onclick=self.link.callback(|_| Msg::Export)>
+              <i class="bi-download me-2"></i>
+              {"Export CSV"}
+            </button>
+            <button
+              class="btn btn-secondary dropdown-toggle dropdown-toggle-split"
+              title="Columns to export"
+              data-bs-toggle="dropdown"
+              data-bs-auto-close="outside"
+              aria-expanded="false">
+            </button>
+            <ul class="dropdown-menu dropdown-menu-end">
+              {EXPORT_COLUMNS.iter().map(|column| {
+                let column = *column;
+                html! {
+                  <li class="dropdown-item">
+                    <label class="form-check-label">
+                      <input
+                        type="checkbox"
+                        class="form-check-input me-2"
+                        checked=self.columns.contains(&column)
+                        onchange=self.link.callback(move |_| Msg::ToggleColumn(column)) />
+                      {column.header()}
+                    </label>
+                  </li>
+                }
+              }).collect::<Vec<_>>()}
+            </ul>
+          </div>
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{
     components::{
         add_group_member::{self, AddGroupMemberComponent},
+        copy_button::CopyButton,
+        error_page,
+        group_details_form::{update_group, GroupDetailsForm, UpdateGroup},
+        loading,
         remove_user_from_group::RemoveUserFromGroupComponent,
         router::{AppRoute, Link},
     },
@@ -9,6 +13,7 @@ use crate::{
 use anyhow::{bail, Error, Result};
 use graphql_client::GraphQLQuery;
 use yew::{
+    html::InputData,
     prelude::*,
     services::{fetch::FetchTask, ConsoleService},
 };
@@ -18,14 +23,24 @@ use yew::{
     schema_path = "../schema.graphql",
     query_path = "queries/get_group_details.graphql",
     response_derives = "Debug, Hash, PartialEq, Eq, Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct GetGroupDetails;
 
 pub type Group = get_group_details::GetGroupDetailsGroup;
 pub type User = get_group_details::GetGroupDetailsGroupUsers;
+pub type Manager = get_group_details::GetGroupDetailsGroupManagers;
 pub type AddGroupMemberUser = add_group_member::User;
 
+/// The DN of the group once renamed, since it starts with the name of the group.
+fn renamed_dn(dn: &str, new_name: &str) -> String {
+    match dn.split_once(',') {
+        Some((_, base)) => format!("cn={},{}", new_name, base),
+        None => dn.to_string(),
+    }
+}
+
 pub struct GroupDetails {
     link: ComponentLink<Self>,
     props: Props,
@@ -34,8 +49,13 @@ pub struct GroupDetails {
     group: Option<Group>,
     /// Error message displayed to the user.
     error: Option<Error>,
+    /// The name being typed, while the group is renamed.
+    new_name: Option<String>,
+    /// The name before the rename, shown again if the server rejects the new one.
+    previous_name: Option<String>,
     // Used to keep the request alive long enough.
     _task: Option<FetchTask>,
+    rename_task: Option<FetchTask>,
 }
 
 /// State machine describing the possible transitions of the component state.
@@ -43,14 +63,30 @@ pub struct GroupDetails {
 pub enum Msg {
     /// Received the group details response, either the group data or an error.
     GroupDetailsResponse(Result<get_group_details::ResponseData>),
+    /// Fetches the group details again, after an error.
+    Retry,
     OnError(Error),
     OnUserAddedToGroup(AddGroupMemberUser),
     OnUserRemovedFromGroup((String, i64)),
+    OnManagerAdded(AddGroupMemberUser),
+    OnManagerRemoved((String, i64)),
+    StartRename,
+    RenameInput(InputData),
+    SubmitRename,
+    CancelRename,
+    RenameResponse(Result<update_group::ResponseData>),
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
 pub struct Props {
     pub group_id: i64,
+    /// Admins can edit the group details and its managers.
+    #[prop_or_default]
+    pub is_admin: bool,
+    /// Hides the form and the buttons to modify the group. Otherwise, the user can at least manage
+    /// the members, e.g. as a manager of the group.
+    #[prop_or_default]
+    pub read_only: bool,
 }
 
 impl GroupDetails {
@@ -78,6 +114,7 @@ impl GroupDetails {
                     bail!("Error getting user details: {}", e);
                 }
             },
+            Msg::Retry => self.get_group_details(),
             Msg::OnError(e) => return Err(e),
             Msg::OnUserAddedToGroup(user) => {
                 self.group.as_mut().unwrap().users.push(User {
@@ -92,19 +129,114 @@ impl GroupDetails {
                     .users
                     .retain(|u| u.id != user_id);
             }
+            Msg::OnManagerAdded(user) => {
+                self.group.as_mut().unwrap().managers.push(Manager {
+                    id: user.id,
+                    display_name: user.display_name,
+                });
+            }
+            Msg::OnManagerRemoved((user_id, _)) => {
+                self.group
+                    .as_mut()
+                    .unwrap()
+                    .managers
+                    .retain(|u| u.id != user_id);
+            }
+            Msg::StartRename => {
+                self.new_name = Some(self.group.as_ref().unwrap().display_name.clone());
+            }
+            Msg::RenameInput(data) => self.new_name = Some(data.value),
+            Msg::SubmitRename => return self.submit_rename(),
+            Msg::CancelRename => self.new_name = None,
+            Msg::RenameResponse(response) => {
+                self.rename_task = None;
+                let previous_name = self.previous_name.take();
+                if let Err(e) = response {
+                    if let Some(previous_name) = previous_name {
+                        let group = self.group.as_mut().unwrap();
+                        group.dn = renamed_dn(&group.dn, &previous_name);
+                        group.display_name = previous_name;
+                    }
+                    bail!("Error renaming the group: {}", e);
+                }
+            }
         }
         Ok(true)
     }
 
+    /// Shows the new name right away, and sends it to the server.
+    fn submit_rename(&mut self) -> Result<bool> {
+        let new_name = match self.new_name.take() {
+            None => return Ok(false),
+            Some(name) => name.trim().to_string(),
+        };
+        if new_name.is_empty() {
+            bail!("The group name can't be empty");
+        }
+        let group = self.group.as_mut().unwrap();
+        if new_name == group.display_name {
+            return Ok(true);
+        }
+        group.dn = renamed_dn(&group.dn, &new_name);
+        self.previous_name = Some(std::mem::replace(&mut group.display_name, new_name.clone()));
+        let group_input = update_group::UpdateGroupInput {
+            id: group.id,
+            displayName: Some(new_name),
+            description: None,
+            email: None,
+        };
+        self.rename_task = Some(HostService::graphql_query::<UpdateGroup>(
+            update_group::Variables { group: group_input },
+            self.link.callback(Msg::RenameResponse),
+            "Error trying to rename group",
+        )?);
+        Ok(true)
+    }
+
+    /// The name of the group, with a button to edit it in place for the admins.
+    fn view_name(&self, g: &Group) -> Html {
+        if self.props.read_only || !self.props.is_admin {
+            return html! { <h3>{g.display_name.to_string()}</h3> };
+        }
+        match &self.new_name {
+            None => html! {
+              <h3>
+                {g.display_name.to_string()}
+                <button
+                  class="btn btn-link text-reset"
+                  title="Rename the group"
+                  disabled=self.rename_task.is_some()
+                  onclick=self.link.callback(|_| Msg::StartRename)>
+                  <i class="bi-pencil"></i>
+                </button>
+              </h3>
+            },
+            Some(new_name) => html! {
+              <form
+                class="d-flex align-items-center mb-2"
+                onsubmit=self.link.callback(|e: FocusEvent| { e.prevent_default(); Msg::SubmitRename })>
+                <input
+                  type="text"
+                  class="form-control"
+                  aria-label="Group name"
+                  value=new_name.clone()
+                  oninput=self.link.callback(Msg::RenameInput) />
+                <button type="submit" class="btn btn-primary ms-2">{"Save"}</button>
+                <button
+                  type="button"
+                  class="btn btn-secondary ms-2"
+                  onclick=self.link.callback(|_| Msg::CancelRename)>
+                  {"Cancel"}
+                </button>
+              </form>
+            },
+        }
+    }
+
     fn view_messages(&self, error: &Option<Error>) -> Html {
-        if let Some(e) = error {
-            html! {
-              <div class="alert alert-danger">
-                <span>{"Error: "}{e.to_string()}</span>
-              </div>
-            }
-        } else {
-            html! {}
+        match error {
+            Some(e) => loading::view_error(e),
+            None => html! {},
         }
     }
 
@@ -120,19 +252,21 @@ impl GroupDetails {
                   </Link>
                 </td>
                 <td>{display_name}</td>
-                <td>
-                  <RemoveUserFromGroupComponent
-                    username=user_id
-                    group_id=g.id
-                    on_user_removed_from_group=self.link.callback(Msg::OnUserRemovedFromGroup)
-                    on_error=self.link.callback(Msg::OnError)/>
-                </td>
+                {if self.props.read_only { html! {} } else { html! {
+                  <td>
+                    <RemoveUserFromGroupComponent
+                      username=user_id
+                      group_id=g.id
+                      group_name=g.display_name.clone()
+                      on_user_removed_from_group=self.link.callback(Msg::OnUserRemovedFromGroup)
+                      on_error=self.link.callback(Msg::OnError)/>
+                  </td>
+                } } }
               </tr>
             }
         };
         html! {
           <>
-            <h3>{g.display_name.to_string()}</h3>
             <h5 class="fw-bold">{"Members"}</h5>
             <div class="table-responsive">
               <table class="table table-striped">
@@ -140,7 +274,7 @@ impl GroupDetails {
                   <tr key="headerRow">
                     <th>{"User Id"}</th>
                     <th>{"Display name"}</th>
-                    <th></th>
+                    {if self.props.read_only { html! {} } else { html! { <th></th> } } }
                   </tr>
                 </thead>
                 <tbody>
@@ -161,7 +295,69 @@ impl GroupDetails {
         }
     }
 
+    fn view_managers(&self, g: &Group) -> Html {
+        let can_edit = self.props.is_admin && !self.props.read_only;
+        let make_manager_row = |user: &Manager| {
+            html! {
+              <tr>
+                <td>{&user.id}</td>
+                <td>{&user.display_name}</td>
+                {if can_edit { html! {
+                  <td>
+                    <RemoveUserFromGroupComponent
+                      username=user.id.clone()
+                      group_id=g.id
+                      group_name=g.display_name.clone()
+                      manager=true
+                      on_user_removed_from_group=self.link.callback(Msg::OnManagerRemoved)
+                      on_error=self.link.callback(Msg::OnError)/>
+                  </td>
+                } } else { html! {} } }
+              </tr>
+            }
+        };
+        let managers: Vec<_> = g
+            .managers
+            .iter()
+            .map(|u| AddGroupMemberUser {
+                id: u.id.clone(),
+                display_name: u.display_name.clone(),
+            })
+            .collect();
+        html! {
+          <>
+            <h5 class="fw-bold">{"Managers"}</h5>
+            <div class="table-responsive">
+              <table class="table table-striped">
+                <tbody>
+                  {if g.managers.is_empty() {
+                    html! {
+                      <tr key="EmptyRow">
+                        <td>{"No managers"}</td>
+                      </tr>
+                    }
+                  } else {
+                    html! {<>{g.managers.iter().map(make_manager_row).collect::<Vec<_>>()}</>}
+                  }}
+                </tbody>
+              </table>
+            </div>
+            {if can_edit { html! {
+              <AddGroupMemberComponent
+                group_id=g.id
+                manager=true
+                users=managers
+                on_error=self.link.callback(Msg::OnError)
+                on_user_added_to_group=self.link.callback(Msg::OnManagerAdded)/>
+            } } else { html! {} } }
+          </>
+        }
+    }
+
     fn view_add_user_button(&self, g: &Group) -> Html {
+        if self.props.read_only {
+            return html! {};
+        }
         let users: Vec<_> = g
             .users
             .iter()
@@ -191,6 +387,9 @@ impl Component for GroupDetails {
             _task: None,
             group: None,
             error: None,
+            new_name: None,
+            previous_name: None,
+            rename_task: None,
         };
         table.get_group_details();
         table
@@ -214,13 +413,23 @@ impl Component for GroupDetails {
 
     fn view(&self) -> Html {
         match (&self.group, &self.error) {
-            (None, None) => html! {{"Loading..."}},
-            (None, Some(e)) => html! {<div>{"Error: "}{e.to_string()}</div>},
+            (None, None) => loading::view_spinner("Loading the group..."),
+            (None, Some(e)) => error_page::view_load_error(e, self.link.callback(|_| Msg::Retry)),
             (Some(u), error) => {
                 html! {
                     <div>
+                      {self.view_name(u)}
+                      <p class="small text-muted">
+                        {"DN: "}<code>{&u.dn}</code>
+                        <CopyButton text=u.dn.clone() title="Copy the DN" />
+                      </p>
+                      <GroupDetailsForm
+                        group=u.clone()
+                        read_only=self.props.read_only || !self.props.is_admin
+                        on_error=self.link.callback(Msg::OnError)/>
                       {self.view_user_list(u)}
                       {self.view_add_user_button(u)}
+                      {self.view_managers(u)}
                       {self.view_messages(error)}
                     </div>
                 }
@@ -0,0 +1,223 @@
+use crate::{
+    components::group_details::Group,
+    infra::{api::HostService, unsaved_changes},
+};
+use anyhow::{bail, Error, Result};
+use graphql_client::GraphQLQuery;
+use validator_derive::Validate;
+use yew::{
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yew_form_derive::Model;
+
+/// The fields of the form, with the editable details and the constraints.
+#[derive(Model, Validate, PartialEq, Clone)]
+pub struct GroupModel {
+    description: String,
+    email: String,
+}
+
+/// The GraphQL query sent to the server to update the group details.
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/update_group.graphql",
+    response_derives = "Debug",
+    variables_derives = "Clone,PartialEq",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct UpdateGroup;
+
+/// A [yew::Component] to display the group description and email, with a form allowing to edit
+/// them.
+pub struct GroupDetailsForm {
+    link: ComponentLink<Self>,
+    props: Props,
+    form: yew_form::Form<GroupModel>,
+    /// True if we just successfully updated the group, to display a success message.
+    just_updated: bool,
+    task: Option<FetchTask>,
+}
+
+pub enum Msg {
+    /// A form field changed.
+    Update,
+    /// The "Submit" button was clicked.
+    SubmitClicked,
+    /// We got the response from the server about our update message.
+    GroupUpdated(Result<update_group::ResponseData>),
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// The current group details.
+    pub group: Group,
+    /// Hides the "Update" button.
+    #[prop_or_default]
+    pub read_only: bool,
+    /// Callback to report errors (e.g. server error).
+    pub on_error: Callback<Error>,
+}
+
+impl Component for GroupDetailsForm {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let model = GroupModel {
+            description: props.group.description.clone().unwrap_or_default(),
+            email: props.group.email.clone().unwrap_or_default(),
+        };
+        Self {
+            link,
+            form: yew_form::Form::new(model),
+            props,
+            just_updated: false,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        self.just_updated = false;
+        let should_render = match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        };
+        unsaved_changes::set_dirty(&self.dirty_key(), self.is_dirty());
+        should_render
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn destroy(&mut self) {
+        unsaved_changes::set_dirty(&self.dirty_key(), false);
+    }
+
+    fn view(&self) -> Html {
+        type Field = yew_form::Field<GroupModel>;
+        html! {
+          <div class="py-3">
+            <form class="form">
+              <div class="form-group row mb-3">
+                <label for="description"
+                  class="form-label col-4 col-form-label">
+                  {"Description: "}
+                </label>
+                <div class="col-8">
+                  <Field
+                    class="form-control"
+                    form=&self.form
+                    field_name="description"
+                    oninput=self.link.callback(|_| Msg::Update) />
+                </div>
+              </div>
+              <div class="form-group row mb-3">
+                <label for="email"
+                  class="form-label col-4 col-form-label">
+                  {"Mailing list: "}
+                </label>
+                <div class="col-8">
+                  <Field
+                    class="form-control"
+                    form=&self.form
+                    field_name="email"
+                    autocomplete="email"
+                    oninput=self.link.callback(|_| Msg::Update) />
+                </div>
+              </div>
+              <div class="form-group row justify-content-center" hidden=self.props.read_only>
+                <button
+                  type="submit"
+                  class="btn btn-primary col-auto col-form-label"
+                  disabled=self.task.is_some()
+                  onclick=self.link.callback(|e: MouseEvent| {e.prevent_default(); Msg::SubmitClicked})>
+                  {"Update"}
+                </button>
+              </div>
+            </form>
+            <div hidden=!self.just_updated>
+              <span>{"Group successfully updated!"}</span>
+            </div>
+          </div>
+        }
+    }
+}
+
+impl GroupDetailsForm {
+    fn dirty_key(&self) -> String {
+        format!("group:{}", self.props.group.id)
+    }
+
+    /// Whether the form differs from the saved details.
+    fn is_dirty(&self) -> bool {
+        let model = self.form.model();
+        let group = &self.props.group;
+        model.description != group.description.clone().unwrap_or_default()
+            || model.email != group.email.clone().unwrap_or_default()
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::Update => Ok(true),
+            Msg::SubmitClicked => self.submit_group_update_form(),
+            Msg::GroupUpdated(response) => self.group_update_finished(response),
+        }
+    }
+
+    fn submit_group_update_form(&mut self) -> Result<bool> {
+        if !self.form.validate() {
+            bail!("Invalid inputs");
+        }
+        let base_group = &self.props.group;
+        let mut group_input = update_group::UpdateGroupInput {
+            id: base_group.id,
+            displayName: None,
+            description: None,
+            email: None,
+        };
+        let default_group_input = group_input.clone();
+        let model = self.form.model();
+        // An empty string removes the field on the server.
+        if base_group.description.clone().unwrap_or_default() != model.description {
+            group_input.description = Some(model.description);
+        }
+        if base_group.email.clone().unwrap_or_default() != model.email {
+            group_input.email = Some(model.email);
+        }
+        // Nothing changed.
+        if group_input == default_group_input {
+            return Ok(false);
+        }
+        let req = update_group::Variables { group: group_input };
+        self.task = Some(HostService::graphql_query::<UpdateGroup>(
+            req,
+            self.link.callback(Msg::GroupUpdated),
+            "Error trying to update group",
+        )?);
+        Ok(false)
+    }
+
+    fn group_update_finished(&mut self, r: Result<update_group::ResponseData>) -> Result<bool> {
+        self.task = None;
+        match r {
+            Err(e) => return Err(e),
+            Ok(_) => {
+                let model = self.form.model();
+                let non_empty = |s: String| Some(s).filter(|s| !s.is_empty());
+                self.props.group.description = non_empty(model.description);
+                self.props.group.email = non_empty(model.email);
+                self.just_updated = true;
+            }
+        };
+        Ok(true)
+    }
+}
@@ -1,46 +1,87 @@
 use crate::{
     components::{
         delete_group::DeleteGroup,
+        error_page, loading,
         router::{AppRoute, Link},
     },
-    infra::api::HostService,
+    infra::{
+        api::HostService,
+        change_events::{self, ChangeEvent, ChangeSubscription, ChangeType, EntryType},
+    },
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
-use yew::prelude::*;
-use yew::services::{fetch::FetchTask, ConsoleService};
+use yew::services::{
+    fetch::FetchTask,
+    timeout::{TimeoutService, TimeoutTask},
+    websocket::WebSocketStatus,
+    ConsoleService,
+};
+use yew::{html::InputData, prelude::*};
 
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "../schema.graphql",
-    query_path = "queries/get_group_list.graphql",
+    query_path = "queries/list_groups.graphql",
     response_derives = "Debug,Clone,PartialEq",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
-pub struct GetGroupList;
+pub struct ListGroupsQuery;
 
-use get_group_list::ResponseData;
+use list_groups_query::ResponseData;
 
-pub type Group = get_group_list::GetGroupListGroups;
+pub type Group = list_groups_query::ListGroupsQueryGroups;
+
+/// The columns the table can be sorted by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Column {
+    DisplayName,
+    MemberCount,
+}
 
 pub struct GroupTable {
     link: ComponentLink<Self>,
+    props: Props,
     groups: Option<Vec<Group>>,
+    /// Only the groups whose name contains it are shown, ignoring the case.
+    search: String,
+    sort: Column,
+    descending: bool,
     error: Option<Error>,
+    /// The changes made by the other admins.
+    changes: ChangeSubscription,
+    reload_timeout: Option<TimeoutTask>,
     // Used to keep the request alive long enough.
     _task: Option<FetchTask>,
 }
 
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// Hides the buttons to delete the groups.
+    #[prop_or_default]
+    pub read_only: bool,
+}
+
 pub enum Msg {
     ListGroupsResponse(Result<ResponseData>),
+    /// Fetches the groups again, after an error.
+    Retry,
+    SearchChanged(String),
+    SortBy(Column),
     OnGroupDeleted(i64),
     OnError(Error),
+    Change(ChangeEvent),
+    ChangesStatus(WebSocketStatus),
+    ReconnectChanges,
+    /// Fetches the groups again, after changes.
+    Reload,
 }
 
 impl GroupTable {
     fn get_groups(&mut self) {
-        self._task = HostService::graphql_query::<GetGroupList>(
-            get_group_list::Variables {},
+        self._task = HostService::graphql_query::<ListGroupsQuery>(
+            list_groups_query::Variables {},
             self.link.callback(Msg::ListGroupsResponse),
             "Error trying to fetch groups",
         )
@@ -54,14 +95,25 @@ impl GroupTable {
 
 impl Component for GroupTable {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
-    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let changes = ChangeSubscription::new(
+            link.callback(Msg::Change),
+            link.callback(Msg::ChangesStatus),
+            link.callback(|_| Msg::ReconnectChanges),
+        );
         let mut table = GroupTable {
             link,
+            props,
             _task: None,
             groups: None,
+            search: String::new(),
+            sort: Column::DisplayName,
+            descending: false,
             error: None,
+            changes,
+            reload_timeout: None,
         };
         table.get_groups();
         table
@@ -86,6 +138,12 @@ impl Component for GroupTable {
     fn view(&self) -> Html {
         html! {
             <div>
+              <input
+                type="search"
+                class="form-control mb-3"
+                placeholder="Search by name"
+                value=self.search.clone()
+                oninput=self.link.callback(|e: InputData| Msg::SearchChanged(e.value)) />
               {self.view_groups()}
               {self.view_errors()}
             </div>
@@ -100,35 +158,132 @@ impl GroupTable {
                 self.groups = Some(groups?.groups.into_iter().collect());
                 Ok(true)
             }
+            Msg::Retry => {
+                self.get_groups();
+                Ok(true)
+            }
+            Msg::SearchChanged(search) => {
+                self.search = search;
+                Ok(true)
+            }
+            Msg::SortBy(column) => {
+                self.descending = column == self.sort && !self.descending;
+                self.sort = column;
+                Ok(true)
+            }
             Msg::OnError(e) => Err(e),
             Msg::OnGroupDeleted(group_id) => {
                 debug_assert!(self.groups.is_some());
                 self.groups.as_mut().unwrap().retain(|u| u.id != group_id);
                 Ok(true)
             }
+            Msg::Change(change) => Ok(self.handle_change(change)),
+            Msg::ChangesStatus(status) => {
+                if self.changes.handle_status(status) {
+                    self.schedule_reload();
+                }
+                Ok(false)
+            }
+            Msg::ReconnectChanges => {
+                self.changes.connect();
+                Ok(false)
+            }
+            Msg::Reload => {
+                self.reload_timeout = None;
+                self.get_groups();
+                Ok(false)
+            }
+        }
+    }
+
+    /// Removes the deleted groups right away, and reloads the groups for the other changes. The
+    /// changes of the members are reported on the groups.
+    fn handle_change(&mut self, change: ChangeEvent) -> bool {
+        if !change.concerns(EntryType::Group) {
+            return false;
+        }
+        self.schedule_reload();
+        match (change.change_type, self.groups.as_mut()) {
+            (ChangeType::Delete, Some(groups)) => {
+                let count = groups.len();
+                groups.retain(|g| g.display_name != change.id);
+                groups.len() != count
+            }
+            _ => false,
+        }
+    }
+
+    fn schedule_reload(&mut self) {
+        self.reload_timeout = Some(TimeoutService::spawn(
+            change_events::RELOAD_DELAY,
+            self.link.callback(|_| Msg::Reload),
+        ));
+    }
+
+    /// The groups matching the search, in the order of the sorted column.
+    fn visible_groups<'a>(&self, groups: &'a [Group]) -> Vec<&'a Group> {
+        let search = self.search.to_lowercase();
+        let mut groups: Vec<&Group> = groups
+            .iter()
+            .filter(|g| g.display_name.to_lowercase().contains(&search))
+            .collect();
+        match self.sort {
+            Column::DisplayName => groups.sort_by_key(|g| g.display_name.to_lowercase()),
+            Column::MemberCount => {
+                groups.sort_by_key(|g| (g.member_count, g.display_name.to_lowercase()))
+            }
+        }
+        if self.descending {
+            groups.reverse();
+        }
+        groups
+    }
+
+    /// A column header that sorts the groups by the column, or reverses the order if it already
+    /// does.
+    fn view_sortable_header(&self, name: &str, column: Column) -> Html {
+        let arrow = match (self.sort == column, self.descending) {
+            (false, _) => "",
+            (true, false) => " \u{25b2}",
+            (true, true) => " \u{25bc}",
+        };
+        html! {
+          <th
+            style="cursor: pointer"
+            title="Sort by this column"
+            onclick=self.link.callback(move |_| Msg::SortBy(column))>
+            {name}{arrow}
+          </th>
         }
     }
 
     fn view_groups(&self) -> Html {
         let make_table = |groups: &Vec<Group>| {
+            let groups = self.visible_groups(groups);
             html! {
                 <div class="table-responsive">
                   <table class="table table-striped">
                     <thead>
                       <tr>
-                        <th>{"Groups"}</th>
-                        <th>{"Delete"}</th>
+                        {self.view_sortable_header("Groups", Column::DisplayName)}
+                        {self.view_sortable_header("Members", Column::MemberCount)}
+                        { if self.props.read_only { html! {} } else { html! { <th>{"Delete"}</th> } } }
                       </tr>
                     </thead>
                     <tbody>
-                      {groups.iter().map(|u| self.view_group(u)).collect::<Vec<_>>()}
+                      {if groups.is_empty() { html! {
+                        <tr><td colspan="3">{loading::view_empty("No matching group")}</td></tr>
+                      } } else { html! {
+                        <>{groups.iter().map(|g| self.view_group(g)).collect::<Vec<_>>()}</>
+                      } } }
                     </tbody>
                   </table>
                 </div>
             }
         };
         match &self.groups {
-            None => html! {{"Loading..."}},
+            None if self.error.is_some() => html! {},
+            None => loading::view_skeleton_table(3),
             Some(groups) => make_table(groups),
         }
     }
@@ -141,20 +296,25 @@ impl GroupTable {
                   {&group.display_name}
                 </Link>
               </td>
-              <td>
-                <DeleteGroup
-                  group=group.clone()
-                  on_group_deleted=self.link.callback(Msg::OnGroupDeleted)
-                  on_error=self.link.callback(Msg::OnError)/>
-              </td>
+              <td>{group.member_count}</td>
+              { if self.props.read_only { html! {} } else { html! {
+                <td>
+                  <DeleteGroup
+                    group=group.clone()
+                    on_group_deleted=self.link.callback(Msg::OnGroupDeleted)
+                    on_error=self.link.callback(Msg::OnError)/>
+                </td>
+              } } }
           </tr>
         }
     }
 
     fn view_errors(&self) -> Html {
-        match &self.error {
-            None => html! {},
-            Some(e) => html! {<div>{"Error: "}{e.to_string()}</div>},
+        match (&self.error, &self.groups) {
+            (None, _) => html! {},
+            // The list could not be loaded at all.
+            (Some(e), None) => error_page::view_load_error(e, self.link.callback(|_| Msg::Retry)),
+            (Some(e), Some(_)) => loading::view_error(e),
         }
     }
 }
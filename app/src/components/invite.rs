@@ -0,0 +1,251 @@
+use crate::{
+    components::{password_field, router::AppRoute},
+    infra::api::{get_claims_from_invite, HostService},
+};
+use anyhow::{bail, Context, Result};
+use lldap_auth::*;
+use validator_derive::Validate;
+use yew::{
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yew_form::Form;
+use yew_form_derive::Model;
+use yew_router::{
+    agent::{RouteAgentDispatcher, RouteRequest},
+    route::Route,
+};
+
+/// The fields of the form, with the constraints.
+#[derive(Model, Validate, PartialEq, Clone, Default)]
+pub struct FormModel {
+    #[validate(length(min = 8, message = "Invalid password. Min length: 8"))]
+    password: String,
+    #[validate(must_match(other = "password", message = "Passwords must match"))]
+    confirm_password: String,
+}
+
+/// The page of an invite link, where the new user sets their password.
+pub struct InviteForm {
+    link: ComponentLink<Self>,
+    props: Props,
+    /// The invited user, or the reason the link is invalid.
+    claims: Result<InviteClaims>,
+    error: Option<anyhow::Error>,
+    form: Form<FormModel>,
+    /// Whether the passwords are shown in clear.
+    show_passwords: bool,
+    registration: Option<opaque::client::registration::ClientRegistration>,
+    // Used to keep the request alive long enough.
+    task: Option<FetchTask>,
+    route_dispatcher: RouteAgentDispatcher,
+}
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub token: String,
+}
+
+pub enum Msg {
+    FormUpdate,
+    TogglePasswords,
+    Submit,
+    RegistrationStartResponse(Result<Box<registration::ServerRegistrationStartResponse>>),
+    RegistrationFinishResponse(Result<()>),
+}
+
+impl InviteForm {
+    fn handle_message(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::FormUpdate => Ok(true),
+            Msg::TogglePasswords => {
+                self.show_passwords = !self.show_passwords;
+                Ok(true)
+            }
+            Msg::Submit => {
+                if !self.form.validate() {
+                    bail!("Check the form for errors");
+                }
+                let username = match &self.claims {
+                    Ok(claims) => claims.user.clone(),
+                    Err(_) => bail!("Invalid invite link"),
+                };
+                let mut rng = rand::rngs::OsRng;
+                let registration_start_request = opaque::client::registration::start_registration(
+                    &self.form.model().password,
+                    &mut rng,
+                )
+                .context("Could not initiate registration")?;
+                let req = registration::ClientRegistrationStartRequest {
+                    username,
+                    registration_start_request: registration_start_request.message,
+                };
+                self.registration = Some(registration_start_request.state);
+                self.task = Some(HostService::invite_register_start(
+                    &self.props.token,
+                    req,
+                    self.link.callback(Msg::RegistrationStartResponse),
+                )?);
+                Ok(true)
+            }
+            Msg::RegistrationStartResponse(res) => {
+                let res = res.context("Could not initiate registration")?;
+                let registration = self
+                    .registration
+                    .take()
+                    .expect("Unexpected registration response");
+                let mut rng = rand::rngs::OsRng;
+                let registration_finish = opaque::client::registration::finish_registration(
+                    registration,
+                    res.registration_response,
+                    &mut rng,
+                )
+                .context("Error during registration")?;
+                let req = registration::ClientRegistrationFinishRequest {
+                    server_data: res.server_data,
+                    registration_upload: registration_finish.message,
+                };
+                self.task = Some(HostService::register_finish(
+                    req,
+                    self.link.callback(Msg::RegistrationFinishResponse),
+                )?);
+                Ok(false)
+            }
+            Msg::RegistrationFinishResponse(response) => {
+                self.task = None;
+                response?;
+                self.route_dispatcher
+                    .send(RouteRequest::ChangeRoute(Route::from(AppRoute::Login)));
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Component for InviteForm {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let claims = get_claims_from_invite(&props.token);
+        InviteForm {
+            link,
+            props,
+            claims,
+            error: None,
+            form: yew_form::Form::<FormModel>::new(FormModel::default()),
+            show_passwords: false,
+            registration: None,
+            task: None,
+            route_dispatcher: RouteAgentDispatcher::new(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        self.error = None;
+        match self.handle_message(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.error = Some(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        let claims = match &self.claims {
+            Ok(claims) => claims,
+            Err(_) => {
+                return html! {
+                  <div class="alert alert-danger">
+                    {"Invalid invite link"}
+                  </div>
+                }
+            }
+        };
+        type Field = yew_form::Field<FormModel>;
+        html! {
+          <>
+            <h5>{format!("Welcome {}! Choose your password to log in.", claims.user)}</h5>
+            { if claims.exp < chrono::Utc::now() {
+                html! {
+                  <div class="alert alert-warning">
+                    {format!("This link expired, ask {} for a new one.", claims.invited_by)}
+                  </div>
+                }
+              } else { html! {} }
+            }
+            <form
+              class="form">
+              <div class="form-group row">
+                <label for="password"
+                  class="form-label col-sm-2 col-form-label">
+                  {"Password*:"}
+                </label>
+                <div class="col-sm-10 input-group has-validation">
+                  <Field
+                    form=&self.form
+                    input_type=password_field::input_type(self.show_passwords)
+                    field_name="password"
+                    class="form-control"
+                    class_invalid="is-invalid has-error"
+                    class_valid="has-success"
+                    autocomplete="new-password"
+                    oninput=self.link.callback(|_| Msg::FormUpdate) />
+                  {password_field::view_reveal_button(
+                    self.show_passwords,
+                    self.link.callback(|_| Msg::TogglePasswords),
+                  )}
+                  <div class="invalid-feedback">
+                    {&self.form.field_message("password")}
+                  </div>
+                </div>
+              </div>
+              <div class="form-group row">
+                <label for="confirm_password"
+                  class="form-label col-sm-2 col-form-label">
+                  {"Confirm password*:"}
+                </label>
+                <div class="col-sm-10">
+                  <Field
+                    form=&self.form
+                    input_type=password_field::input_type(self.show_passwords)
+                    field_name="confirm_password"
+                    class="form-control"
+                    class_invalid="is-invalid has-error"
+                    class_valid="has-success"
+                    autocomplete="new-password"
+                    oninput=self.link.callback(|_| Msg::FormUpdate) />
+                  <div class="invalid-feedback">
+                    {&self.form.field_message("confirm_password")}
+                  </div>
+                </div>
+              </div>
+              <div class="form-group row">
+                <button
+                  class="btn btn-primary col-sm-1 col-form-label"
+                  type="submit"
+                  disabled=self.task.is_some()
+                  onclick=self.link.callback(|e: MouseEvent| {e.prevent_default(); Msg::Submit})>
+                  {"Submit"}
+                </button>
+              </div>
+            </form>
+            { if let Some(e) = &self.error {
+                html! {
+                  <div class="alert alert-danger">
+                    {e.to_string() }
+                  </div>
+                }
+              } else { html! {} }
+            }
+          </>
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    components::loading,
+    infra::{api::HostService, date, graphql::DateTimeUtc},
+};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::{fetch::FetchTask, ConsoleService};
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/list_jobs.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct ListJobs;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/trigger_job.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct TriggerJob;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/set_job_paused.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct SetJobPaused;
+
+pub type Job = list_jobs::ListJobsJobs;
+
+/// The background jobs of the server, with buttons to run them now or to pause them.
+pub struct JobTable {
+    link: ComponentLink<Self>,
+    jobs: Option<Vec<Job>>,
+    error: Option<Error>,
+    // Used to keep the request alive long enough.
+    task: Option<FetchTask>,
+}
+
+pub enum Msg {
+    ListJobsResponse(Result<list_jobs::ResponseData>),
+    Refresh,
+    Trigger(String),
+    SetPaused(String, bool),
+    JobUpdated(Result<()>),
+}
+
+impl JobTable {
+    fn get_jobs(&mut self) -> Result<()> {
+        self.task = Some(HostService::graphql_query::<ListJobs>(
+            list_jobs::Variables {},
+            self.link.callback(Msg::ListJobsResponse),
+            "Error trying to fetch the jobs",
+        )?);
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::ListJobsResponse(jobs) => {
+                self.task = None;
+                self.jobs = Some(jobs?.jobs);
+            }
+            Msg::Refresh => self.get_jobs()?,
+            Msg::Trigger(name) => {
+                self.task = Some(HostService::graphql_query::<TriggerJob>(
+                    trigger_job::Variables { name },
+                    self.link
+                        .callback(|r: Result<_>| Msg::JobUpdated(r.map(|_| ()))),
+                    "Error trying to run the job",
+                )?);
+            }
+            Msg::SetPaused(name, paused) => {
+                self.task = Some(HostService::graphql_query::<SetJobPaused>(
+                    set_job_paused::Variables { name, paused },
+                    self.link
+                        .callback(|r: Result<_>| Msg::JobUpdated(r.map(|_| ()))),
+                    "Error trying to update the job",
+                )?);
+            }
+            Msg::JobUpdated(response) => {
+                self.task = None;
+                response?;
+                self.get_jobs()?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_jobs(&self) -> Html {
+        match &self.jobs {
+            None if self.error.is_some() => html! {},
+            None => loading::view_skeleton_table(6),
+            Some(jobs) => html! {
+                <div class="table-responsive">
+                  <table class="table table-striped">
+                    <thead>
+                      <tr>
+                        <th>{"Job"}</th>
+                        <th>{"Last run"}</th>
+                        <th>{"Duration"}</th>
+                        <th>{"Status"}</th>
+                        <th>{"Next run"}</th>
+                        <th>{"Actions"}</th>
+                      </tr>
+                    </thead>
+                    <tbody>
+                      {jobs.iter().map(|j| self.view_job(j)).collect::<Vec<_>>()}
+                    </tbody>
+                  </table>
+                </div>
+            },
+        }
+    }
+
+    fn view_job(&self, job: &Job) -> Html {
+        let view_date = |d: &Option<DateTimeUtc>| match d {
+            None => html! {{"-"}},
+            Some(d) => html! {
+                <span title=date::format_relative(d)>{date::format_local_date_time(d)}</span>
+            },
+        };
+        let status = if job.running {
+            html! {<span class="text-primary">{"Running"}</span>}
+        } else if let Some(error) = &job.last_run_error {
+            html! {<span class="text-danger" title=error.clone()>{"Failed"}</span>}
+        } else if job.last_run_start.is_some() {
+            html! {<span class="text-success">{"Succeeded"}</span>}
+        } else {
+            html! {{"Not run yet"}}
+        };
+        let name = job.name.clone();
+        let on_trigger = self.link.callback(move |_| Msg::Trigger(name.clone()));
+        let name = job.name.clone();
+        let paused = !job.paused;
+        let on_toggle_paused = self
+            .link
+            .callback(move |_| Msg::SetPaused(name.clone(), paused));
+        let (pause_label, pause_icon) = if job.paused {
+            ("Resume", "bi-play-circle")
+        } else {
+            ("Pause", "bi-pause-circle")
+        };
+        html! {
+          <tr key=job.name.clone()>
+            <td>
+              {&job.name}
+              {if job.paused { html! {<span class="badge bg-secondary ms-2">{"Paused"}</span>} } else { html! {} }}
+            </td>
+            <td>{view_date(&job.last_run_start)}</td>
+            <td>{job.last_run_duration_ms.map(|d| format!("{} ms", d)).unwrap_or_else(|| "-".to_string())}</td>
+            <td>{status}</td>
+            <td>{if job.paused { html! {{"-"}} } else { view_date(&job.next_run) }}</td>
+            <td>
+              <button
+                class="btn btn-outline-primary me-2"
+                title="Run now"
+                disabled=self.task.is_some() || job.running
+                onclick=on_trigger>
+                <i class="bi-play-fill" aria-label="Run now" />
+              </button>
+              <button
+                class="btn btn-outline-secondary"
+                title=pause_label
+                disabled=self.task.is_some()
+                onclick=on_toggle_paused>
+                <i class=pause_icon aria-label=pause_label />
+              </button>
+            </td>
+          </tr>
+        }
+    }
+
+    fn view_errors(&self) -> Html {
+        match (&self.error, &self.jobs) {
+            (None, _) => html! {},
+            // The list could not be loaded at all.
+            (Some(e), None) => {
+                loading::view_error_with_retry(e, self.link.callback(|_| Msg::Refresh))
+            }
+            (Some(e), Some(_)) => loading::view_error(e),
+        }
+    }
+}
+
+impl Component for JobTable {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut table = JobTable {
+            link,
+            jobs: None,
+            error: None,
+            task: None,
+        };
+        if let Err(e) = table.get_jobs() {
+            ConsoleService::error(&e.to_string());
+            table.error = Some(e);
+        }
+        table
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        self.error = None;
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.task = None;
+                self.error = Some(e);
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div>
+              {self.view_jobs()}
+              <button
+                class="btn btn-primary"
+                disabled=self.task.is_some()
+                onclick=self.link.callback(|_| Msg::Refresh)>
+                {"Refresh"}
+              </button>
+              {self.view_errors()}
+            </div>
+        }
+    }
+}
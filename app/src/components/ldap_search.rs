@@ -0,0 +1,242 @@
+use crate::{
+    components::loading,
+    infra::api::{HostService, LdapSearchEntry, LdapSearchRequest, LdapSearchResponse},
+};
+use anyhow::{Error, Result};
+use yew::{
+    html::{ChangeData, InputData},
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+
+/// The LDAP query tester of the admins: runs a search through the LDAP server, as its admin, and
+/// shows the entries that an LDAP client would get with the same base DN and filter.
+pub struct LdapSearch {
+    link: ComponentLink<Self>,
+    base_dn: String,
+    filter: String,
+    scope: String,
+    /// The attributes to return, separated by spaces or commas. All of them when empty.
+    attributes: String,
+    response: Option<LdapSearchResponse>,
+    error: Option<Error>,
+    task: Option<FetchTask>,
+}
+
+pub enum Msg {
+    BaseDnInput(InputData),
+    FilterInput(InputData),
+    ScopeChanged(ChangeData),
+    AttributesInput(InputData),
+    Submit,
+    Response(Result<LdapSearchResponse>),
+}
+
+impl LdapSearch {
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::BaseDnInput(data) => self.base_dn = data.value,
+            Msg::FilterInput(data) => self.filter = data.value,
+            Msg::ScopeChanged(ChangeData::Select(select)) => self.scope = select.value(),
+            Msg::ScopeChanged(_) => return Ok(false),
+            Msg::AttributesInput(data) => self.attributes = data.value,
+            Msg::Submit => {
+                self.error = None;
+                self.task = Some(HostService::ldap_search(
+                    &LdapSearchRequest {
+                        base_dn: self.base_dn.trim().to_string(),
+                        filter: self.filter.trim().to_string(),
+                        scope: self.scope.clone(),
+                        attributes: self
+                            .attributes
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|a| !a.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    },
+                    self.link.callback(Msg::Response),
+                )?);
+            }
+            Msg::Response(response) => {
+                self.task = None;
+                self.response = Some(response?);
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_form(&self) -> Html {
+        html! {
+          <form
+            class="form"
+            onsubmit=self.link.callback(|e: FocusEvent| { e.prevent_default(); Msg::Submit })>
+            <div class="form-group row mb-3">
+              <label for="ldapBaseDn" class="form-label col-3 col-form-label">{"Base DN:"}</label>
+              <div class="col-9">
+                <input
+                  type="text"
+                  id="ldapBaseDn"
+                  class="form-control font-monospace"
+                  placeholder="ou=people,dc=example,dc=com"
+                  value=self.base_dn.clone()
+                  oninput=self.link.callback(Msg::BaseDnInput) />
+              </div>
+            </div>
+            <div class="form-group row mb-3">
+              <label for="ldapScope" class="form-label col-3 col-form-label">{"Scope:"}</label>
+              <div class="col-9">
+                <select
+                  id="ldapScope"
+                  class="form-select"
+                  onchange=self.link.callback(Msg::ScopeChanged)>
+                  <option value="sub" selected=self.scope == "sub">{"Subtree"}</option>
+                  <option value="one" selected=self.scope == "one">{"One level"}</option>
+                  <option value="base" selected=self.scope == "base">{"Base object"}</option>
+                </select>
+              </div>
+            </div>
+            <div class="form-group row mb-3">
+              <label for="ldapFilter" class="form-label col-3 col-form-label">{"Filter:"}</label>
+              <div class="col-9">
+                <input
+                  type="text"
+                  id="ldapFilter"
+                  class="form-control font-monospace"
+                  placeholder="(&(objectClass=person)(memberOf=cn=admins,ou=groups,dc=example,dc=com))"
+                  value=self.filter.clone()
+                  oninput=self.link.callback(Msg::FilterInput) />
+              </div>
+            </div>
+            <div class="form-group row mb-3">
+              <label for="ldapAttributes" class="form-label col-3 col-form-label">{"Attributes:"}</label>
+              <div class="col-9">
+                <input
+                  type="text"
+                  id="ldapAttributes"
+                  class="form-control font-monospace"
+                  placeholder="All of them, or e.g. uid mail memberOf"
+                  value=self.attributes.clone()
+                  oninput=self.link.callback(Msg::AttributesInput) />
+              </div>
+            </div>
+            <div class="form-group row justify-content-center mb-3">
+              <button
+                type="submit"
+                class="btn btn-primary col-auto"
+                disabled=self.task.is_some() || self.filter.trim().is_empty()>
+                <i class="bi-search me-2"></i>
+                {"Search"}
+              </button>
+            </div>
+          </form>
+        }
+    }
+
+    fn view_entry(entry: &LdapSearchEntry) -> Html {
+        html! {
+          <div class="card mb-3" key=entry.dn.clone()>
+            <div class="card-header font-monospace">{&entry.dn}</div>
+            <div class="card-body p-0">
+              <table class="table table-sm mb-0">
+                <tbody>
+                  {entry.attributes.iter().map(|a| html! {
+                    <tr>
+                      <th class="ps-3 w-25">{&a.name}</th>
+                      <td class="font-monospace text-break">
+                        {a.values.iter().map(|v| html! { <div>{v}</div> }).collect::<Vec<_>>()}
+                      </td>
+                    </tr>
+                  }).collect::<Vec<_>>()}
+                </tbody>
+              </table>
+            </div>
+          </div>
+        }
+    }
+
+    fn view_response(&self) -> Html {
+        if self.task.is_some() {
+            return loading::view_spinner("Searching");
+        }
+        let response = match &self.response {
+            None => return html! {},
+            Some(response) => response,
+        };
+        let (alert_class, summary) = if response.result_code == "Success" {
+            (
+                "alert alert-success",
+                match response.entries.len() {
+                    1 => "1 entry".to_string(),
+                    n => format!("{} entries", n),
+                },
+            )
+        } else {
+            ("alert alert-danger", response.result_code.clone())
+        };
+        html! {
+          <>
+            <div class=alert_class>
+              <strong>{summary}</strong>
+              {if response.message.is_empty() { html! {} } else { html! {
+                <span class="ms-2">{&response.message}</span>
+              } } }
+              {if response.truncated { html! {
+                <div>{"Only the first entries are shown."}</div>
+              } } else { html! {} } }
+            </div>
+            {response.entries.iter().map(Self::view_entry).collect::<Vec<_>>()}
+          </>
+        }
+    }
+}
+
+impl Component for LdapSearch {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            base_dn: String::new(),
+            filter: "(objectClass=*)".to_string(),
+            scope: "sub".to_string(),
+            attributes: String::new(),
+            response: None,
+            error: None,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.error = Some(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <div>
+            <h3>{"LDAP query tester"}</h3>
+            <p class="text-muted">
+              {"Runs the search through the LDAP server, as the LDAP admin, and shows the entries that a client would get."}
+            </p>
+            {self.view_form()}
+            {match &self.error {
+              Some(e) => loading::view_error(e),
+              None => html! {},
+            }}
+            {self.view_response()}
+          </div>
+        }
+    }
+}
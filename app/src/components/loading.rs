@@ -0,0 +1,74 @@
+//! What's shown in place of the data while it loads, when there is none, or when it failed to
+//! load, so that these cases look the same on every page and can't be mistaken for one another.
+use anyhow::Error;
+use yew::prelude::*;
+
+/// The number of rows of the skeleton tables.
+const SKELETON_ROWS: usize = 5;
+
+/// A spinner with what is being loaded, for a page loading as a whole.
+pub fn view_spinner(label: &str) -> Html {
+    html! {
+      <div class="d-flex align-items-center text-muted my-3">
+        <div class="spinner-border spinner-border-sm me-2" aria-hidden="true"></div>
+        <span role="status">{label}</span>
+      </div>
+    }
+}
+
+/// Grey rows in place of a table being loaded.
+pub fn view_skeleton_table(columns: usize) -> Html {
+    let view_row = |row: usize| {
+        html! {
+          <tr key=row>
+            {(0..columns).map(|_| html! {
+              <td><span class="skeleton"></span></td>
+            }).collect::<Vec<_>>()}
+          </tr>
+        }
+    };
+    html! {
+      <div class="table-responsive" aria-busy="true">
+        <span class="visually-hidden" role="status">{"Loading..."}</span>
+        <table class="table">
+          <tbody>
+            {(0..SKELETON_ROWS).map(view_row).collect::<Vec<_>>()}
+          </tbody>
+        </table>
+      </div>
+    }
+}
+
+/// The message shown when there is nothing to list.
+pub fn view_empty(message: &str) -> Html {
+    html! {
+      <div class="text-muted text-center py-4">
+        <i class="bi-inbox me-2"></i>
+        {message}
+      </div>
+    }
+}
+
+/// The error that prevented the data from loading, with a button to load it again.
+pub fn view_error_with_retry(error: &Error, on_retry: Callback<MouseEvent>) -> Html {
+    html! {
+      <div class="alert alert-danger d-flex align-items-center">
+        <i class="bi-exclamation-triangle-fill me-2"></i>
+        <span class="me-auto">{"Error: "}{error.to_string()}</span>
+        <button class="btn btn-outline-danger ms-3" onclick=on_retry>
+          <i class="bi-arrow-clockwise me-2"></i>
+          {"Retry"}
+        </button>
+      </div>
+    }
+}
+
+/// The error that prevented an action from completing.
+pub fn view_error(error: &Error) -> Html {
+    html! {
+      <div class="alert alert-danger">
+        <i class="bi-exclamation-triangle-fill me-2"></i>
+        {"Error: "}{error.to_string()}
+      </div>
+    }
+}
@@ -1,4 +1,7 @@
-use crate::infra::api::HostService;
+use crate::{
+    components::{password_field, proof_of_work::ProofOfWork},
+    infra::api::{HostService, LoginInfo},
+};
 use anyhow::{anyhow, bail, Context, Result};
 use lldap_auth::*;
 use validator_derive::Validate;
@@ -11,13 +14,25 @@ use yew_form_derive::Model;
 
 pub struct LoginForm {
     link: ComponentLink<Self>,
-    on_logged_in: Callback<(String, bool)>,
+    on_logged_in: Callback<LoginInfo>,
     error: Option<anyhow::Error>,
     form: Form<FormModel>,
+    /// Whether the password is shown in clear.
+    show_password: bool,
+    /// The login waiting for the proof of work.
+    pending_login: Option<PendingLogin>,
+    /// The proof of work being solved.
+    challenge: Option<proof_of_work::Challenge>,
     // Used to keep the request alive long enough.
     task: Option<FetchTask>,
 }
 
+enum PendingLogin {
+    Opaque,
+    /// Sending the password to the server, when the OPAQUE login failed.
+    Password,
+}
+
 /// The fields of the form, with the constraints.
 #[derive(Model, Validate, PartialEq, Clone, Default)]
 pub struct FormModel {
@@ -29,30 +44,40 @@ pub struct FormModel {
 
 #[derive(Clone, PartialEq, Properties)]
 pub struct Props {
-    pub on_logged_in: Callback<(String, bool)>,
+    pub on_logged_in: Callback<LoginInfo>,
 }
 
 pub enum Msg {
     Update,
+    TogglePassword,
     Submit,
+    ChallengeResponse(Result<proof_of_work::Challenge>),
+    ProofOfWorkSolved(String),
     AuthenticationStartResponse(
         (
             opaque::client::login::ClientLogin,
             Result<Box<login::ServerLoginStartResponse>>,
         ),
     ),
-    AuthenticationFinishResponse(Result<(String, bool)>),
+    AuthenticationFinishResponse(Result<LoginInfo>),
+    TrustedHeaderResponse(Result<LoginInfo>),
+    PasswordLoginResponse(Result<LoginInfo>),
 }
 
 impl LoginForm {
-    fn handle_message(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
-        match msg {
-            Msg::Update => Ok(true),
-            Msg::Submit => {
-                if !self.form.validate() {
-                    bail!("Check the form for errors");
-                }
-                let FormModel { username, password } = self.form.model();
+    /// Gets the proof of work to solve (if any) before the login.
+    fn request_proof_of_work(&mut self, login: PendingLogin) -> Result<()> {
+        self.pending_login = Some(login);
+        self.task = Some(HostService::get_proof_of_work_challenge(
+            self.link.callback_once(Msg::ChallengeResponse),
+        )?);
+        Ok(())
+    }
+
+    fn continue_login(&mut self, proof_of_work: Option<String>) -> Result<()> {
+        let FormModel { username, password } = self.form.model();
+        match self.pending_login.take() {
+            Some(PendingLogin::Opaque) => {
                 let mut rng = rand::rngs::OsRng;
                 let opaque::client::login::ClientLoginStartResult { state, message } =
                     opaque::client::login::start_login(&password, &mut rng)
@@ -61,11 +86,53 @@ impl LoginForm {
                     username,
                     login_start_request: message,
                 };
-                self.task = Some(HostService::login_start(
+                self.task = Some(HostService::login_start_with_proof_of_work(
                     req,
+                    proof_of_work,
                     self.link
                         .callback_once(move |r| Msg::AuthenticationStartResponse((state, r))),
                 )?);
+            }
+            Some(PendingLogin::Password) => {
+                self.task = Some(HostService::login_with_password(
+                    username,
+                    password,
+                    proof_of_work,
+                    self.link.callback_once(Msg::PasswordLoginResponse),
+                )?);
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::Update => Ok(true),
+            Msg::TogglePassword => {
+                self.show_password = !self.show_password;
+                Ok(true)
+            }
+            Msg::Submit => {
+                if !self.form.validate() {
+                    bail!("Check the form for errors");
+                }
+                self.request_proof_of_work(PendingLogin::Opaque)?;
+                Ok(true)
+            }
+            Msg::ChallengeResponse(challenge) => {
+                let challenge = challenge?;
+                if challenge.difficulty == 0 {
+                    self.continue_login(None)?;
+                } else {
+                    self.task = None;
+                    self.challenge = Some(challenge);
+                }
+                Ok(true)
+            }
+            Msg::ProofOfWorkSolved(proof_of_work) => {
+                self.challenge = None;
+                self.continue_login(Some(proof_of_work))?;
                 Ok(true)
             }
             Msg::AuthenticationStartResponse((login_start, res)) => {
@@ -74,12 +141,12 @@ impl LoginForm {
                     match opaque::client::login::finish_login(login_start, res.credential_response)
                     {
                         Err(e) => {
-                            // Common error, we want to print a full error to the console but only a
-                            // simple one to the user.
-                            ConsoleService::error(&format!("Invalid username or password: {}", e));
-                            self.error = Some(anyhow!("Invalid username or password"));
-                            self.task = None;
-                            return Ok(true);
+                            // Either a wrong password, or a password imported from another
+                            // directory: only the server can check the latter.
+                            ConsoleService::log(&format!("OPAQUE login failed: {}", e));
+                            // The proof of work was used by the OPAQUE login.
+                            self.request_proof_of_work(PendingLogin::Password)?;
+                            return Ok(false);
                         }
                         Ok(l) => l,
                     };
@@ -99,6 +166,28 @@ impl LoginForm {
                     .emit(user_info.context("Could not log in")?);
                 Ok(true)
             }
+            Msg::PasswordLoginResponse(user_info) => {
+                self.task = None;
+                match user_info {
+                    Ok(user_info) => self.on_logged_in.emit(user_info),
+                    Err(e) => {
+                        // Common error, we want to print a full error to the console but only a
+                        // simple one to the user.
+                        ConsoleService::error(&format!("Invalid username or password: {}", e));
+                        self.error = Some(anyhow!("Invalid username or password"));
+                    }
+                }
+                Ok(true)
+            }
+            Msg::TrustedHeaderResponse(user_info) => {
+                self.task = None;
+                match user_info {
+                    Ok(user_info) => self.on_logged_in.emit(user_info),
+                    // Not behind a trusted proxy: the user logs in with the form.
+                    Err(e) => ConsoleService::log(&e.to_string()),
+                }
+                Ok(true)
+            }
         }
     }
 }
@@ -108,12 +197,18 @@ impl Component for LoginForm {
     type Properties = Props;
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let task =
+            HostService::login_with_trusted_header(link.callback_once(Msg::TrustedHeaderResponse))
+                .ok();
         LoginForm {
             link,
             on_logged_in: props.on_logged_in,
             error: None,
             form: Form::<FormModel>::new(FormModel::default()),
-            task: None,
+            show_password: false,
+            pending_login: None,
+            challenge: None,
+            task,
         }
     }
 
@@ -124,6 +219,7 @@ impl Component for LoginForm {
                 ConsoleService::error(&e.to_string());
                 self.error = Some(e);
                 self.task = None;
+                self.challenge = None;
                 true
             }
             Ok(b) => b,
@@ -167,19 +263,31 @@ impl Component for LoginForm {
                     class_valid="has-success"
                     form=&self.form
                     field_name="password"
-                    input_type="password"
+                    input_type=password_field::input_type(self.show_password)
                     placeholder="Password"
                     autocomplete="current-password" />
+                  {password_field::view_reveal_button(
+                    self.show_password,
+                    self.link.callback(|_| Msg::TogglePassword),
+                  )}
                 </div>
                 <div class="form-group">
                   <button
                     type="submit"
                     class="btn btn-primary"
-                    disabled=self.task.is_some()
+                    disabled=self.task.is_some() || self.challenge.is_some()
                     onclick=self.link.callback(|e: MouseEvent| {e.prevent_default(); Msg::Submit})>
                     {"Login"}
                   </button>
                 </div>
+                { if let Some(challenge) = &self.challenge {
+                    html! {
+                      <ProofOfWork
+                        challenge=challenge.clone()
+                        on_solved=self.link.callback(Msg::ProofOfWorkSolved) />
+                    }
+                  } else { html! {} }
+                }
                 <div class="form-group">
                 { if let Some(e) = &self.error {
                     html! { e.to_string() }
@@ -0,0 +1,161 @@
+use crate::{
+    components::loading,
+    infra::{api::HostService, date},
+};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::{fetch::FetchTask, ConsoleService};
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/get_login_history.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct GetLoginHistory;
+
+pub type LoginHistoryEntry = get_login_history::GetLoginHistoryUserLoginHistory;
+
+/// The recent logins of the current user, to spot the ones they didn't make.
+pub struct LoginHistory {
+    link: ComponentLink<Self>,
+    props: Props,
+    entries: Option<Vec<LoginHistoryEntry>>,
+    error: Option<Error>,
+    // Used to keep the request alive long enough.
+    _task: Option<FetchTask>,
+}
+
+pub enum Msg {
+    LoginHistoryResponse(Result<get_login_history::ResponseData>),
+    /// Fetches the login history again, after an error.
+    Retry,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub username: String,
+}
+
+impl LoginHistory {
+    fn get_login_history(&mut self) -> Result<()> {
+        self._task = Some(HostService::graphql_query::<GetLoginHistory>(
+            get_login_history::Variables {
+                id: self.props.username.clone(),
+            },
+            self.link.callback(Msg::LoginHistoryResponse),
+            "Error trying to fetch the login history",
+        )?);
+        Ok(())
+    }
+
+    fn view_method(entry: &LoginHistoryEntry) -> &str {
+        match entry.method.as_str() {
+            "web" => "Web",
+            "trusted_header" => "Single sign-on",
+            "ldap" => "LDAP",
+            method => method,
+        }
+    }
+
+    fn view_entry(entry: &LoginHistoryEntry) -> Html {
+        html! {
+          <tr>
+            <td>
+              <span title=date::format_relative(&entry.timestamp)>
+                {date::format_local_date_time(&entry.timestamp)}
+              </span>
+            </td>
+            <td>{Self::view_method(entry)}</td>
+            <td>{&entry.ip}</td>
+            <td>{entry.user_agent.as_deref().unwrap_or("-")}</td>
+          </tr>
+        }
+    }
+
+    fn view_entries(&self) -> Html {
+        match &self.entries {
+            None => loading::view_skeleton_table(4),
+            Some(entries) if entries.is_empty() => loading::view_empty("No recent login"),
+            Some(entries) => html! {
+              <div class="table-responsive">
+                <table class="table table-striped">
+                  <thead>
+                    <tr>
+                      <th>{"Date"}</th>
+                      <th>{"Method"}</th>
+                      <th>{"IP address"}</th>
+                      <th>{"Browser"}</th>
+                    </tr>
+                  </thead>
+                  <tbody>
+                    {entries.iter().map(Self::view_entry).collect::<Vec<_>>()}
+                  </tbody>
+                </table>
+              </div>
+            },
+        }
+    }
+}
+
+impl Component for LoginHistory {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut history = LoginHistory {
+            link,
+            props,
+            entries: None,
+            error: None,
+            _task: None,
+        };
+        if let Err(e) = history.get_login_history() {
+            ConsoleService::error(&e.to_string());
+            history.error = Some(e);
+        }
+        history
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::LoginHistoryResponse(response) => match response {
+                Ok(response) => self.entries = Some(response.user.login_history),
+                Err(e) => {
+                    ConsoleService::error(&e.to_string());
+                    self.error = Some(e);
+                }
+            },
+            Msg::Retry => {
+                self.error = None;
+                if let Err(e) = self.get_login_history() {
+                    ConsoleService::error(&e.to_string());
+                    self.error = Some(e);
+                }
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <>
+            <h5 class="row m-3 fw-bold">{"Recent activity"}</h5>
+            <p class="text-muted">
+              {"Your last logins. If you don't recognize one of them, change your password."}
+            </p>
+            {match &self.error {
+              None => self.view_entries(),
+              Some(e) => loading::view_error_with_retry(e, self.link.callback(|_| Msg::Retry)),
+            }}
+          </>
+        }
+    }
+}
@@ -1,18 +1,44 @@
 pub mod add_group_member;
 pub mod add_user_to_group;
 pub mod app;
+pub mod avatar;
+pub mod bulk_user_actions;
 pub mod change_password;
+pub mod command_palette;
+pub mod confirm_modal;
+pub mod copy_button;
 pub mod create_group;
 pub mod create_user;
+pub mod date_time_field;
 pub mod delete_group;
 pub mod delete_user;
+pub mod deleted_user_table;
+pub mod error_page;
+pub mod export_users;
 pub mod group_details;
+pub mod group_details_form;
 pub mod group_table;
+pub mod invite;
+pub mod job_table;
+pub mod ldap_search;
+pub mod loading;
 pub mod login;
+pub mod login_history;
 pub mod logout;
+pub mod oidc_consent;
+pub mod password_field;
+pub mod password_strength;
+pub mod proof_of_work;
 pub mod remove_user_from_group;
+pub mod renew_user;
 pub mod router;
+pub mod security_keys;
 pub mod select;
+pub mod toggle_user_enabled;
+pub mod totp_settings;
+pub mod unlock_user;
+pub mod user_attributes_form;
 pub mod user_details;
 pub mod user_details_form;
+pub mod user_status;
 pub mod user_table;
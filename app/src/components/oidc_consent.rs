@@ -0,0 +1,116 @@
+use crate::infra::{api::HostService, oidc::OidcRequest};
+use anyhow::{Error, Result};
+use yew::{
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+
+/// Asks the logged in user whether to share their information with the application requesting
+/// to log them in. The provider only asks once per application, unless it requests more.
+pub struct OidcConsent {
+    link: ComponentLink<Self>,
+    props: Props,
+    task: Option<FetchTask>,
+    error: Option<Error>,
+}
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub request: OidcRequest,
+    pub user_id: String,
+}
+
+pub enum Msg {
+    Continue,
+    ConsentResponse(Result<()>),
+}
+
+impl Component for OidcConsent {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            task: None,
+            error: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Continue => {
+                self.error = None;
+                match HostService::oidc_consent(
+                    &self.props.request.client_id,
+                    &self.props.request.scope,
+                    self.link.callback(Msg::ConsentResponse),
+                ) {
+                    Ok(task) => self.task = Some(task),
+                    Err(e) => self.error = Some(e),
+                }
+                true
+            }
+            Msg::ConsentResponse(response) => {
+                self.task = None;
+                match response.and_then(|()| self.props.request.resume()) {
+                    Ok(()) => false,
+                    Err(e) => {
+                        ConsoleService::error(&e.to_string());
+                        self.error = Some(e);
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if props != self.props {
+            self.props = props;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let shared_scopes = self.props.request.shared_scopes();
+        html! {
+          <div>
+            <h5>
+              {format!(
+                  "{} wants to log you in as {}.",
+                  self.props.request.client.as_deref().unwrap_or("An application"),
+                  self.props.user_id
+              )}
+            </h5>
+            {if shared_scopes.is_empty() {
+                html! {}
+            } else {
+                html! {
+                  <p>
+                    {"It will also get your: "}
+                    <b>{shared_scopes.join(", ")}</b>
+                  </p>
+                }
+            }}
+            <button
+              class="btn btn-primary"
+              disabled=self.task.is_some()
+              onclick=self.link.callback(|_| Msg::Continue)>
+              {format!("Continue to {}", self.props.request.client_name())}
+            </button>
+            { if let Some(e) = &self.error {
+                html! {
+                  <div class="alert alert-danger mt-2">
+                    {e.to_string() }
+                  </div>
+                }
+              } else { html! {} }
+            }
+          </div>
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! The buttons shared by the password fields, placed in their input group.
+
+use yew::prelude::*;
+
+/// The type of the password inputs, depending on whether the passwords are shown.
+pub fn input_type(shown: bool) -> &'static str {
+    if shown {
+        "text"
+    } else {
+        "password"
+    }
+}
+
+/// A button to show the passwords in clear, or to hide them again.
+pub fn view_reveal_button(shown: bool, onclick: Callback<MouseEvent>) -> Html {
+    let (title, icon) = if shown {
+        ("Hide the password", "bi-eye-slash")
+    } else {
+        ("Show the password", "bi-eye")
+    };
+    html! {
+      <button type="button" class="btn btn-outline-secondary" title=title onclick=onclick>
+        <i class=icon></i>
+      </button>
+    }
+}
+
+/// The buttons to fill the new password with a random one, and to copy it to share it.
+pub fn view_generator_buttons(
+    on_generate: Callback<MouseEvent>,
+    on_copy: Callback<MouseEvent>,
+) -> Html {
+    html! {
+      <>
+        <button
+          type="button"
+          class="btn btn-outline-secondary"
+          title="Generate a secure password"
+          onclick=on_generate>
+          <i class="bi-shuffle"></i>
+        </button>
+        <button
+          type="button"
+          class="btn btn-outline-secondary"
+          title="Copy the password"
+          onclick=on_copy>
+          <i class="bi-clipboard"></i>
+        </button>
+      </>
+    }
+}
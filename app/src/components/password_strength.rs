@@ -0,0 +1,126 @@
+use yew::prelude::*;
+use yewtil::NeqAssign;
+
+/// The shortest password accepted by the password forms.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// A meter of how hard the new password is to guess, estimated by zxcvbn, above the list of the
+/// rules the password must follow to be accepted.
+pub struct PasswordStrength {
+    props: Props,
+}
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub password: String,
+    pub confirm_password: String,
+    /// The words the password shouldn't be based on, like the user name.
+    #[prop_or_default]
+    pub user_inputs: Vec<String>,
+}
+
+impl PasswordStrength {
+    fn view_meter(&self) -> Html {
+        let user_inputs: Vec<&str> = self.props.user_inputs.iter().map(String::as_str).collect();
+        // zxcvbn only fails on an empty password.
+        let entropy = match zxcvbn::zxcvbn(&self.props.password, &user_inputs) {
+            Ok(entropy) => entropy,
+            Err(_) => return html! {},
+        };
+        let score = entropy.score();
+        let (label, color) = match score {
+            0 => ("Very weak", "bg-danger"),
+            1 => ("Weak", "bg-danger"),
+            2 => ("Fair", "bg-warning"),
+            3 => ("Strong", "bg-success"),
+            _ => ("Very strong", "bg-success"),
+        };
+        let feedback = entropy.feedback().as_ref();
+        html! {
+          <div class="mt-2">
+            <div class="progress" style="height: 6px">
+              <div
+                class=format!("progress-bar {}", color)
+                role="progressbar"
+                style=format!("width: {}%", (score as usize + 1) * 20)>
+              </div>
+            </div>
+            <small>{label}</small>
+            {if let Some(warning) = feedback.and_then(|f| f.warning()) { html! {
+              <small class="text-danger">{" \u{2014} "}{warning.to_string()}</small>
+            } } else { html! {} } }
+            {if let Some(feedback) = feedback { html! {
+              <ul class="text-muted small mb-0">
+                {feedback.suggestions().iter().map(|s| html! {
+                  <li>{s.to_string()}</li>
+                }).collect::<Vec<_>>()}
+              </ul>
+            } } else { html! {} } }
+          </div>
+        }
+    }
+
+    fn view_rules(&self) -> Html {
+        let password = &self.props.password;
+        let lowercase_password = password.to_lowercase();
+        let rules =
+            [
+                (
+                    format!("At least {} characters", MIN_PASSWORD_LENGTH),
+                    password.chars().count() >= MIN_PASSWORD_LENGTH,
+                ),
+                (
+                    "Different from the user name".to_string(),
+                    !self.props.user_inputs.iter().any(|input| {
+                        !input.is_empty() && lowercase_password == input.to_lowercase()
+                    }),
+                ),
+                (
+                    "Typed the same twice".to_string(),
+                    !password.is_empty() && *password == self.props.confirm_password,
+                ),
+            ];
+        html! {
+          <ul class="list-unstyled small mt-2">
+            {rules.iter().map(|(rule, ok)| {
+              let (icon, color) = if *ok {
+                  ("bi-check-circle-fill", "text-success")
+              } else {
+                  ("bi-circle", "text-muted")
+              };
+              html! {
+                <li class=color>
+                  <i class=format!("{} me-2", icon)></i>{rule}
+                </li>
+              }
+            }).collect::<Vec<_>>()}
+          </ul>
+        }
+    }
+}
+
+impl Component for PasswordStrength {
+    type Message = ();
+    type Properties = Props;
+
+    fn create(props: Self::Properties, _: ComponentLink<Self>) -> Self {
+        Self { props }
+    }
+
+    fn update(&mut self, _: Self::Message) -> ShouldRender {
+        false
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <>
+            {self.view_meter()}
+            {self.view_rules()}
+          </>
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use lldap_auth::proof_of_work::{is_solution, to_header, Challenge};
+use std::time::Duration;
+use yew::{
+    prelude::*,
+    services::{timeout::TimeoutTask, TimeoutService},
+};
+
+/// Number of hashes between two renders, to keep the page responsive.
+const BATCH_SIZE: u64 = 5000;
+
+/// Solves the proof of work asked by the server before a login, showing the progress.
+pub struct ProofOfWork {
+    link: ComponentLink<Self>,
+    props: Props,
+    nonce: u64,
+    _timeout: Option<TimeoutTask>,
+}
+
+#[derive(Clone, PartialEq, Properties)]
+pub struct Props {
+    pub challenge: Challenge,
+    /// Called with the value of the proof of work header.
+    pub on_solved: Callback<String>,
+}
+
+pub enum Msg {
+    Work,
+}
+
+impl ProofOfWork {
+    fn schedule_work(&mut self) {
+        self._timeout = Some(TimeoutService::spawn(
+            Duration::from_millis(0),
+            self.link.callback(|_| Msg::Work),
+        ));
+    }
+}
+
+impl Component for ProofOfWork {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut proof_of_work = Self {
+            link,
+            props,
+            nonce: 0,
+            _timeout: None,
+        };
+        proof_of_work.schedule_work();
+        proof_of_work
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Work => {
+                let Challenge {
+                    challenge,
+                    difficulty,
+                } = &self.props.challenge;
+                let end = self.nonce + BATCH_SIZE;
+                while self.nonce < end {
+                    if is_solution(challenge, self.nonce, *difficulty) {
+                        self.props.on_solved.emit(to_header(challenge, self.nonce));
+                        return false;
+                    }
+                    self.nonce += 1;
+                }
+                self.schedule_work();
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if props != self.props {
+            self.props = props;
+            self.nonce = 0;
+            self.schedule_work();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        // It takes 2^difficulty tries on average, but it can take longer.
+        let expected = 1u64 << self.props.challenge.difficulty;
+        let progress = std::cmp::min(95, self.nonce * 100 / expected);
+        html! {
+          <div>
+            <small>{"Checking that you're not a robot..."}</small>
+            <div class="progress">
+              <div
+                class="progress-bar"
+                role="progressbar"
+                style=format!("width: {}%", progress)>
+              </div>
+            </div>
+          </div>
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::infra::api::HostService;
+use crate::{components::confirm_modal::ConfirmModal, infra::api::HostService};
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
 use yew::{
@@ -12,10 +12,25 @@ use yew::{
     query_path = "queries/remove_user_from_group.graphql",
     response_derives = "Debug",
     variables_derives = "Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct RemoveUserFromGroup;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/remove_group_manager.graphql",
+    response_derives = "Debug",
+    variables_derives = "Clone",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct RemoveGroupManager;
+
+/// Removing a member of this group takes away their admin rights, and can lock everyone out.
+const ADMIN_GROUP: &str = "lldap_admin";
+
 pub struct RemoveUserFromGroupComponent {
     link: ComponentLink<Self>,
     props: Props,
@@ -27,6 +42,10 @@ pub struct RemoveUserFromGroupComponent {
 pub struct Props {
     pub username: String,
     pub group_id: i64,
+    pub group_name: String,
+    /// Removes the user from the managers of the group, instead of the members.
+    #[prop_or_default]
+    pub manager: bool,
     pub on_user_removed_from_group: Callback<(String, i64)>,
     pub on_error: Callback<Error>,
 }
@@ -34,11 +53,28 @@ pub struct Props {
 pub enum Msg {
     SubmitRemoveGroup,
     RemoveGroupResponse(Result<remove_user_from_group::ResponseData>),
+    RemoveManagerResponse(Result<remove_group_manager::ResponseData>),
 }
 
 impl RemoveUserFromGroupComponent {
     fn submit_remove_group(&mut self) -> Result<bool> {
         let group = self.props.group_id;
+        if self.props.manager {
+            self.task = HostService::graphql_query::<RemoveGroupManager>(
+                remove_group_manager::Variables {
+                    user: self.props.username.clone(),
+                    group,
+                },
+                self.link.callback(Msg::RemoveManagerResponse),
+                "Error trying to initiate removing the manager of a group",
+            )
+            .map_err(|e| {
+                ConsoleService::log(&e.to_string());
+                e
+            })
+            .ok();
+            return Ok(true);
+        }
         self.task = HostService::graphql_query::<RemoveUserFromGroup>(
             remove_user_from_group::Variables {
                 user: self.props.username.clone(),
@@ -60,14 +96,22 @@ impl RemoveUserFromGroupComponent {
             Msg::SubmitRemoveGroup => return self.submit_remove_group(),
             Msg::RemoveGroupResponse(response) => {
                 response?;
-                self.task = None;
-                self.props
-                    .on_user_removed_from_group
-                    .emit((self.props.username.clone(), self.props.group_id));
+                self.on_removed();
+            }
+            Msg::RemoveManagerResponse(response) => {
+                response?;
+                self.on_removed();
             }
         }
         Ok(true)
     }
+
+    fn on_removed(&mut self) {
+        self.task = None;
+        self.props
+            .on_user_removed_from_group
+            .emit((self.props.username.clone(), self.props.group_id));
+    }
 }
 
 impl Component for RemoveUserFromGroupComponent {
@@ -98,6 +142,25 @@ impl Component for RemoveUserFromGroupComponent {
     }
 
     fn view(&self) -> Html {
+        if !self.props.manager && self.props.group_name == ADMIN_GROUP {
+            return html! {
+              <ConfirmModal
+                id=format!("removeAdminModal{}", self.props.username)
+                title="Remove admin?"
+                message=html! {
+                  <>
+                    {"Are you sure you want to remove "}
+                    <b>{&self.props.username}</b>
+                    {" from the admin group? They will lose all their admin rights."}
+                  </>
+                }
+                confirm_name=Some(self.props.username.clone())
+                disabled=self.task.is_some()
+                on_confirm=self.link.callback(|_| Msg::SubmitRemoveGroup)>
+                <i class="bi-x-circle-fill" aria-label="Remove user from group" />
+              </ConfirmModal>
+            };
+        }
         html! {
           <button
             class="btn btn-danger"
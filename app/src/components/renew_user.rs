@@ -0,0 +1,90 @@
+use crate::infra::api::HostService;
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::fetch::FetchTask;
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/renew_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct RenewUserQuery;
+
+/// A button to clear the expiry date of an expired user, so that they can log in again.
+pub struct RenewUser {
+    link: ComponentLink<Self>,
+    props: RenewUserProps,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq, Debug)]
+pub struct RenewUserProps {
+    pub username: String,
+    pub on_user_renewed: Callback<String>,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    ClickedRenew,
+    RenewResponse(Result<renew_user_query::ResponseData>),
+}
+
+impl Component for RenewUser {
+    type Message = Msg;
+    type Properties = RenewUserProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::ClickedRenew => {
+                self.task = HostService::graphql_query::<RenewUserQuery>(
+                    renew_user_query::Variables {
+                        user: self.props.username.clone(),
+                    },
+                    self.link.callback(Msg::RenewResponse),
+                    "Error trying to renew user",
+                )
+                .map_err(|e| self.props.on_error.emit(e))
+                .ok();
+            }
+            Msg::RenewResponse(response) => {
+                self.task = None;
+                if let Err(e) = response {
+                    self.props.on_error.emit(e);
+                } else {
+                    self.props.on_user_renewed.emit(self.props.username.clone());
+                }
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let label = "Expired, click to remove the expiry date";
+        html! {
+          <button
+            class="btn btn-outline-danger ms-1"
+            title=label
+            disabled=self.task.is_some()
+            onclick=self.link.callback(|_| Msg::ClickedRenew)>
+            <i class="bi-calendar-x" aria-label=label />
+          </button>
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::infra::branding;
 use yew_router::{
     components::{RouterAnchor, RouterButton},
     Switch,
@@ -21,8 +22,62 @@ pub enum AppRoute {
     ListGroups,
     #[to = "/group/{group_id}"]
     GroupDetails(i64),
-    #[to = "/"]
+    #[to = "/jobs"]
+    ListJobs,
+    #[to = "/deleted-users"]
+    ListDeletedUsers,
+    #[to = "/ldap-search"]
+    LdapSearch,
+    #[to = "/graphql"]
+    GraphQLExplorer,
+    #[to = "/invite/{token}"]
+    Invite(String),
+    #[to = "/!"]
     Index,
+    #[to = "/{*:path}"]
+    NotFound(String),
+}
+
+impl AppRoute {
+    /// The title of the page, from the most specific part to the name of the product.
+    pub fn title(&self) -> String {
+        let parts: Vec<String> = match self {
+            AppRoute::Login => vec!["Log in".to_string()],
+            AppRoute::CreateUser => vec!["Create a user".to_string(), "Users".to_string()],
+            AppRoute::Index | AppRoute::ListUsers => vec!["Users".to_string()],
+            AppRoute::ChangePassword(user_id) => vec![
+                "Change password".to_string(),
+                user_id.clone(),
+                "Users".to_string(),
+            ],
+            AppRoute::UserDetails(user_id) => vec![user_id.clone(), "Users".to_string()],
+            AppRoute::CreateGroup => vec!["Create a group".to_string(), "Groups".to_string()],
+            AppRoute::ListGroups => vec!["Groups".to_string()],
+            AppRoute::GroupDetails(group_id) => {
+                vec![format!("Group {}", group_id), "Groups".to_string()]
+            }
+            AppRoute::ListJobs => vec!["Jobs".to_string()],
+            AppRoute::ListDeletedUsers => vec!["Deleted users".to_string()],
+            AppRoute::LdapSearch => vec!["LDAP query tester".to_string()],
+            AppRoute::GraphQLExplorer => vec!["GraphQL explorer".to_string()],
+            AppRoute::NotFound(_) => vec!["Page not found".to_string()],
+            // The token is a secret: keep it out of the title.
+            AppRoute::Invite(_) => vec!["Choose your password".to_string()],
+        };
+        parts
+            .into_iter()
+            .chain(std::iter::once(branding::product_name()))
+            .collect::<Vec<_>>()
+            .join(" \u{2013} ")
+    }
+}
+
+/// Shows the title of the route in the browser tab. Called by the router on every navigation, so
+/// that the pages don't have to.
+pub fn set_document_title(route: &AppRoute) {
+    if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+        document.set_title(&route.title());
+    }
 }
 
 pub type Link = RouterAnchor<AppRoute>;
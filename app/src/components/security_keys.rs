@@ -0,0 +1,282 @@
+use crate::{
+    components::{confirm_modal::ConfirmModal, loading},
+    infra::{api::HostService, date, webauthn::create_credential},
+};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::{
+    html::InputData,
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/webauthn.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct GetWebauthnCredentials;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/webauthn.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct DeleteWebauthnCredential;
+
+type SecurityKey = get_webauthn_credentials::GetWebauthnCredentialsUserWebauthnCredentials;
+
+/// The security keys section of the user page: the user can register their keys, and the user or
+/// an admin can remove them.
+pub struct SecurityKeys {
+    link: ComponentLink<Self>,
+    props: Props,
+    keys: Option<Vec<SecurityKey>>,
+    /// The name of the key to register.
+    name: String,
+    /// Waiting for the user to touch their key.
+    waiting_for_key: bool,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub username: String,
+    /// Only the user can register their keys.
+    pub is_current_user: bool,
+    #[prop_or_default]
+    pub read_only: bool,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    ListResponse(Result<get_webauthn_credentials::ResponseData>),
+    NameInput(InputData),
+    Register,
+    RegisterStartResponse(Result<serde_json::Value>),
+    KeyCreated(Result<serde_json::Value>),
+    RegisterFinishResponse(Result<()>),
+    Delete(String),
+    DeleteResponse(String, Result<delete_webauthn_credential::ResponseData>),
+}
+
+impl SecurityKeys {
+    fn get_keys(&mut self) -> Result<()> {
+        self.task = Some(HostService::graphql_query::<GetWebauthnCredentials>(
+            get_webauthn_credentials::Variables {
+                user_id: self.props.username.clone(),
+            },
+            self.link.callback(Msg::ListResponse),
+            "Error trying to fetch the security keys",
+        )?);
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::ListResponse(response) => {
+                self.task = None;
+                self.keys = Some(response?.user.webauthn_credentials);
+            }
+            Msg::NameInput(data) => self.name = data.value,
+            Msg::Register => {
+                self.task = Some(HostService::webauthn_register_start(
+                    self.name.trim().to_string(),
+                    self.link.callback(Msg::RegisterStartResponse),
+                )?);
+            }
+            Msg::RegisterStartResponse(response) => {
+                self.task = None;
+                let options = response?;
+                self.waiting_for_key = true;
+                self.link
+                    .send_future(async move { Msg::KeyCreated(create_credential(options).await) });
+            }
+            Msg::KeyCreated(credential) => {
+                self.waiting_for_key = false;
+                self.task = Some(HostService::webauthn_register_finish(
+                    credential?,
+                    self.link.callback(Msg::RegisterFinishResponse),
+                )?);
+            }
+            Msg::RegisterFinishResponse(response) => {
+                self.task = None;
+                response?;
+                self.name.clear();
+                self.get_keys()?;
+            }
+            Msg::Delete(credential_id) => {
+                self.task = Some(HostService::graphql_query::<DeleteWebauthnCredential>(
+                    delete_webauthn_credential::Variables {
+                        user_id: self.props.username.clone(),
+                        credential_id: credential_id.clone(),
+                    },
+                    self.link.callback(move |response| {
+                        Msg::DeleteResponse(credential_id.clone(), response)
+                    }),
+                    "Error trying to remove the security key",
+                )?);
+            }
+            Msg::DeleteResponse(credential_id, response) => {
+                self.task = None;
+                response?;
+                if let Some(keys) = &mut self.keys {
+                    keys.retain(|k| k.id != credential_id);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_key(&self, key: &SecurityKey, index: usize) -> Html {
+        let credential_id = key.id.clone();
+        html! {
+          <tr key=key.id.clone()>
+            <td>{&key.name}</td>
+            <td>
+              <span title=date::format_relative(&key.creation_date)>
+                {date::format_local_date(&key.creation_date)}
+              </span>
+            </td>
+            <td>
+              {if self.props.read_only { html! {} } else { html! {
+                <ConfirmModal
+                  id=format!("deleteSecurityKeyModal{}", index)
+                  title="Remove the security key?"
+                  message=html! {
+                    {format!("\"{}\" will be removed from the account.", key.name)}
+                  }
+                  button_class="btn btn-outline-danger btn-sm"
+                  disabled=self.task.is_some()
+                  on_confirm=self.link.callback(move |_| Msg::Delete(credential_id.clone()))>
+                  <i class="bi-x-circle-fill" aria-label="Remove the security key" />
+                </ConfirmModal>
+              } } }
+            </td>
+          </tr>
+        }
+    }
+
+    fn view_keys(&self) -> Html {
+        match &self.keys {
+            None => loading::view_skeleton_table(3),
+            Some(keys) if keys.is_empty() => loading::view_empty("No security key"),
+            Some(keys) => html! {
+              <div class="table-responsive">
+                <table class="table table-striped">
+                  <thead>
+                    <tr>
+                      <th>{"Name"}</th>
+                      <th>{"Added"}</th>
+                      <th></th>
+                    </tr>
+                  </thead>
+                  <tbody>
+                    {keys.iter().enumerate().map(|(i, k)| self.view_key(k, i)).collect::<Vec<_>>()}
+                  </tbody>
+                </table>
+              </div>
+            },
+        }
+    }
+
+    fn view_register_form(&self) -> Html {
+        if !self.props.is_current_user || self.props.read_only {
+            return html! {};
+        }
+        if self.waiting_for_key {
+            return loading::view_spinner("Touch your security key to register it");
+        }
+        html! {
+          <form
+            class="row g-2 align-items-center"
+            onsubmit=self.link.callback(|e: FocusEvent| { e.prevent_default(); Msg::Register })>
+            <div class="col-auto">
+              <label for="securityKeyName" class="col-form-label">{"New key:"}</label>
+            </div>
+            <div class="col-auto">
+              <input
+                type="text"
+                id="securityKeyName"
+                class="form-control"
+                placeholder="e.g. Yubikey on my keychain"
+                maxlength="64"
+                autocomplete="off"
+                value=self.name.clone()
+                oninput=self.link.callback(Msg::NameInput) />
+            </div>
+            <div class="col-auto">
+              <button
+                type="submit"
+                class="btn btn-primary"
+                disabled=self.task.is_some() || self.name.trim().is_empty()>
+                <i class="bi-key me-2"></i>
+                {"Register"}
+              </button>
+            </div>
+          </form>
+        }
+    }
+}
+
+impl Component for SecurityKeys {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut security_keys = Self {
+            link,
+            props,
+            keys: None,
+            name: String::new(),
+            waiting_for_key: false,
+            task: None,
+        };
+        if let Err(e) = security_keys.get_keys() {
+            ConsoleService::error(&e.to_string());
+            security_keys.props.on_error.emit(e);
+        }
+        security_keys
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <>
+            <h5 class="row m-3 fw-bold">{"Security keys"}</h5>
+            {if self.props.is_current_user { html! {
+              <p class="text-muted">
+                {"The hardware keys, or fingerprint readers of your devices, registered to your account."}
+              </p>
+            } } else { html! {} } }
+            <p class="small text-muted">
+              {"The keys can't be used to log in yet: they are only registered for now."}
+            </p>
+            {self.view_keys()}
+            {self.view_register_form()}
+          </>
+        }
+    }
+}
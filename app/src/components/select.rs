@@ -1,39 +1,178 @@
-use yew::{html::ChangeData, prelude::*};
+//! A drop-down list of options, following the ARIA "select-only combobox" pattern: the focus stays
+//! on the button, which points to the highlighted option of the list with
+//! `aria-activedescendant`.
+//!
+//! Keyboard: the arrows, Home and End move in the list (and open it), Enter and Space choose the
+//! highlighted option, Escape and Tab close the list, and typing the start of an option
+//! highlights it.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use yew::{
+    prelude::*,
+    services::timeout::{TimeoutService, TimeoutTask},
+};
 use yewtil::NeqAssign;
 
+/// The typed characters are forgotten after this delay without typing.
+const TYPE_AHEAD_DELAY: Duration = Duration::from_millis(500);
+
+/// For the ids of the list and options of each instance.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Select {
     link: ComponentLink<Self>,
     props: SelectProps,
-    node_ref: NodeRef,
+    id: String,
+    button_ref: NodeRef,
+    list_ref: NodeRef,
+    expanded: bool,
+    /// The index of the chosen option.
+    selected: usize,
+    /// The index of the option highlighted in the open list.
+    active: usize,
+    /// The value of the option last sent to `on_selection_change`.
+    last_emitted: Option<Option<String>>,
+    type_ahead: String,
+    type_ahead_timeout: Option<TimeoutTask>,
 }
 
 #[derive(yew::Properties, Clone, PartialEq, Debug)]
 pub struct SelectProps {
     pub children: ChildrenWithProps<SelectOption>,
     pub on_selection_change: Callback<Option<SelectOptionProps>>,
+    /// The accessible name of the list, for the screen readers.
+    #[prop_or_default]
+    pub label: String,
 }
 
 pub enum SelectMsg {
-    OnSelectChange(ChangeData),
+    Toggle,
+    Close,
+    KeyDown(KeyboardEvent),
+    Choose(usize),
+    Highlight(usize),
+    ClearTypeAhead,
 }
 
 impl Select {
-    fn get_nth_child_props(&self, nth: i32) -> Option<SelectOptionProps> {
-        if nth == -1 {
-            return None;
-        }
+    fn options(&self) -> Vec<SelectOptionProps> {
         self.props
             .children
             .iter()
-            .nth(nth as usize)
             .map(|child| child.props)
+            .collect()
     }
 
-    fn send_selection_update(&self) {
-        let select_node = self.node_ref.cast::<web_sys::HtmlSelectElement>().unwrap();
-        self.props
-            .on_selection_change
-            .emit(self.get_nth_child_props(select_node.selected_index()))
+    fn option_id(&self, index: usize) -> String {
+        format!("{}-option-{}", self.id, index)
+    }
+
+    /// Tells the parent about the chosen option, when it changed.
+    fn send_selection_update(&mut self) {
+        let selected = self.options().into_iter().nth(self.selected);
+        let value = selected.as_ref().map(|option| option.value.clone());
+        if self.last_emitted.as_ref() != Some(&value) {
+            self.last_emitted = Some(value);
+            self.props.on_selection_change.emit(selected);
+        }
+    }
+
+    fn open(&mut self) {
+        self.expanded = true;
+        self.active = self.selected;
+    }
+
+    fn close(&mut self) {
+        self.expanded = false;
+        self.type_ahead.clear();
+        self.type_ahead_timeout = None;
+    }
+
+    /// Highlights the next option starting with the typed characters.
+    fn type_ahead(&mut self, key: &str) {
+        self.type_ahead.push_str(&key.to_lowercase());
+        self.type_ahead_timeout = Some(TimeoutService::spawn(
+            TYPE_AHEAD_DELAY,
+            self.link.callback(|_| SelectMsg::ClearTypeAhead),
+        ));
+        let options = self.options();
+        let count = options.len();
+        // Typing the same letter again cycles through the options starting with it.
+        let start = if self.type_ahead.chars().count() == 1 {
+            self.active + 1
+        } else {
+            self.active
+        };
+        if let Some(index) = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&i| options[i].text.to_lowercase().starts_with(&self.type_ahead))
+        {
+            self.active = index;
+            if !self.expanded {
+                self.selected = index;
+            }
+        }
+    }
+
+    fn handle_key(&mut self, event: KeyboardEvent) -> ShouldRender {
+        let count = self.props.children.len();
+        if count == 0 {
+            return false;
+        }
+        let last = count - 1;
+        let key = event.key();
+        match key.as_str() {
+            "ArrowDown" | "ArrowUp" | "Home" | "End" if !self.expanded => {
+                self.open();
+                if key == "Home" {
+                    self.active = 0;
+                } else if key == "End" {
+                    self.active = last;
+                }
+            }
+            "ArrowDown" => self.active = (self.active + 1).min(last),
+            "ArrowUp" => self.active = self.active.saturating_sub(1),
+            "Home" => self.active = 0,
+            "End" => self.active = last,
+            "PageDown" if self.expanded => self.active = (self.active + 10).min(last),
+            "PageUp" if self.expanded => self.active = self.active.saturating_sub(10),
+            "Enter" | " " if self.expanded && self.type_ahead.is_empty() => {
+                self.selected = self.active;
+                self.close();
+            }
+            "Enter" | " " if self.type_ahead.is_empty() => self.open(),
+            "Escape" if self.expanded => self.close(),
+            // Tab moves the focus as usual, once the list is closed.
+            "Tab" => {
+                if self.expanded {
+                    self.close();
+                    return true;
+                }
+                return false;
+            }
+            _ if key.chars().count() == 1 && !event.ctrl_key() && !event.meta_key() => {
+                self.type_ahead(&key);
+            }
+            _ => return false,
+        }
+        event.prevent_default();
+        true
+    }
+
+    /// Keeps the highlighted option in view in the open list.
+    fn scroll_to_active(&self) {
+        if !self.expanded {
+            return;
+        }
+        let option = self.list_ref.cast::<web_sys::Element>().and_then(|list| {
+            list.query_selector(&format!("#{}", self.option_id(self.active)))
+                .ok()
+                .flatten()
+        });
+        if let Some(option) = option {
+            option.scroll_into_view_with_bool(false);
+        }
     }
 }
 
@@ -44,39 +183,153 @@ impl Component for Select {
         Self {
             link,
             props,
-            node_ref: NodeRef::default(),
+            id: format!("select-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            button_ref: NodeRef::default(),
+            list_ref: NodeRef::default(),
+            expanded: false,
+            selected: 0,
+            active: 0,
+            last_emitted: None,
+            type_ahead: String::new(),
+            type_ahead_timeout: None,
         }
     }
 
     fn rendered(&mut self, _first_render: bool) {
         self.send_selection_update();
+        self.scroll_to_active();
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
-        let SelectMsg::OnSelectChange(data) = msg;
-        match data {
-            ChangeData::Select(_) => self.send_selection_update(),
-            _ => unreachable!(),
+        match msg {
+            SelectMsg::Toggle => {
+                if self.expanded {
+                    self.close();
+                } else if !self.props.children.is_empty() {
+                    self.open();
+                }
+            }
+            SelectMsg::Close => {
+                if !self.expanded {
+                    return false;
+                }
+                self.close();
+            }
+            SelectMsg::KeyDown(event) => return self.handle_key(event),
+            SelectMsg::Choose(index) => {
+                self.selected = index;
+                self.close();
+                // Back to the button, for the keyboard users.
+                if let Some(button) = self.button_ref.cast::<web_sys::HtmlElement>() {
+                    let _ = button.focus();
+                }
+            }
+            SelectMsg::Highlight(index) => {
+                if self.active == index {
+                    return false;
+                }
+                self.active = index;
+            }
+            SelectMsg::ClearTypeAhead => {
+                self.type_ahead.clear();
+                self.type_ahead_timeout = None;
+                return false;
+            }
         }
-        false
+        true
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
-        self.props.children.neq_assign(props.children)
+        self.props.on_selection_change = props.on_selection_change;
+        let changed = self.props.label.neq_assign(props.label)
+            | self.props.children.neq_assign(props.children);
+        if changed {
+            // The options changed: keep the chosen one if it's still there.
+            let options = self.options();
+            let selected_value = self.last_emitted.clone().flatten();
+            self.selected = options
+                .iter()
+                .position(|option| Some(&option.value) == selected_value.as_ref())
+                .unwrap_or(0);
+            self.active = self.active.min(options.len().saturating_sub(1));
+        }
+        changed
     }
 
     fn view(&self) -> Html {
+        let options = self.options();
+        let list_id = format!("{}-list", self.id);
+        let text = options
+            .get(self.selected)
+            .map(|option| option.text.clone())
+            .unwrap_or_default();
+        let list_class = if self.expanded {
+            "lldap-select-list shadow"
+        } else {
+            "lldap-select-list d-none"
+        };
+        let active_descendant = if self.expanded {
+            self.option_id(self.active)
+        } else {
+            String::new()
+        };
         html! {
-            <select
-              ref=self.node_ref.clone()
-              disabled=self.props.children.is_empty()
-              onchange=self.link.callback(SelectMsg::OnSelectChange)>
-            { self.props.children.clone() }
-            </select>
+          <div class="lldap-select">
+            <button
+              type="button"
+              ref=self.button_ref.clone()
+              class="form-select text-start"
+              role="combobox"
+              aria-label=self.props.label.clone()
+              aria-haspopup="listbox"
+              aria-expanded=self.expanded.to_string()
+              aria-controls=list_id.clone()
+              aria-activedescendant=active_descendant
+              disabled=options.is_empty()
+              onclick=self.link.callback(|_| SelectMsg::Toggle)
+              onkeydown=self.link.callback(SelectMsg::KeyDown)
+              onblur=self.link.callback(|_| SelectMsg::Close)>
+              {text}
+            </button>
+            <ul
+              id=list_id
+              ref=self.list_ref.clone()
+              class=list_class
+              role="listbox"
+              aria-label=self.props.label.clone()
+              tabindex="-1">
+              {options.iter().enumerate().map(|(index, option)| {
+                  let class = if self.expanded && index == self.active {
+                      "lldap-select-option active"
+                  } else {
+                      "lldap-select-option"
+                  };
+                  // The mouse down would take the focus from the button, and close the list.
+                  let on_mouse_down = self.link.callback(move |e: MouseEvent| {
+                      e.prevent_default();
+                      SelectMsg::Highlight(index)
+                  });
+                  html! {
+                    <li
+                      id=self.option_id(index)
+                      key=option.value.clone()
+                      class=class
+                      role="option"
+                      aria-selected=(index == self.selected).to_string()
+                      onmousedown=on_mouse_down
+                      onmousemove=self.link.callback(move |_| SelectMsg::Highlight(index))
+                      onclick=self.link.callback(move |_| SelectMsg::Choose(index))>
+                      {&option.text}
+                    </li>
+                  }
+              }).collect::<Vec<_>>()}
+            </ul>
+          </div>
         }
     }
 }
 
+/// An option of a `Select`: only its properties are used.
 pub struct SelectOption {
     props: SelectOptionProps,
 }
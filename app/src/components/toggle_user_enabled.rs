@@ -0,0 +1,120 @@
+use crate::infra::api::HostService;
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::fetch::FetchTask;
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/disable_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct DisableUserQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/enable_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct EnableUserQuery;
+
+/// A button to disable an enabled user, or re-enable a disabled one.
+pub struct ToggleUserEnabled {
+    link: ComponentLink<Self>,
+    props: ToggleUserEnabledProps,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq, Debug)]
+pub struct ToggleUserEnabledProps {
+    pub username: String,
+    pub enabled: bool,
+    /// Called with the username and the new state once the change is done.
+    pub on_user_toggled: Callback<(String, bool)>,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    ClickedToggle,
+    ToggleResponse(Result<()>),
+}
+
+impl Component for ToggleUserEnabled {
+    type Message = Msg;
+    type Properties = ToggleUserEnabledProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::ClickedToggle => {
+                let user = self.props.username.clone();
+                let task = if self.props.enabled {
+                    HostService::graphql_query::<DisableUserQuery>(
+                        disable_user_query::Variables { user },
+                        self.link
+                            .callback(|r: Result<_>| Msg::ToggleResponse(r.map(|_| ()))),
+                        "Error trying to disable user",
+                    )
+                } else {
+                    HostService::graphql_query::<EnableUserQuery>(
+                        enable_user_query::Variables { user },
+                        self.link
+                            .callback(|r: Result<_>| Msg::ToggleResponse(r.map(|_| ()))),
+                        "Error trying to enable user",
+                    )
+                };
+                self.task = task.map_err(|e| self.props.on_error.emit(e)).ok();
+            }
+            Msg::ToggleResponse(response) => {
+                self.task = None;
+                if let Err(e) = response {
+                    self.props.on_error.emit(e);
+                } else {
+                    self.props
+                        .on_user_toggled
+                        .emit((self.props.username.clone(), !self.props.enabled));
+                }
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let (class, label, icon) = if self.props.enabled {
+            (
+                "btn btn-outline-secondary",
+                "Disable user",
+                "bi-pause-circle",
+            )
+        } else {
+            ("btn btn-outline-success", "Enable user", "bi-play-circle")
+        };
+        html! {
+          <button
+            class=class
+            title=label
+            disabled=self.task.is_some()
+            onclick=self.link.callback(|_| Msg::ClickedToggle)>
+            <i class=icon aria-label=label />
+          </button>
+        }
+    }
+}
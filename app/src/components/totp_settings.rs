@@ -0,0 +1,354 @@
+use crate::{
+    components::{confirm_modal::ConfirmModal, copy_button::CopyButton},
+    infra::{api::HostService, download::download_file},
+};
+use anyhow::{anyhow, Error, Result};
+use graphql_client::GraphQLQuery;
+use qrcode::{Color, QrCode};
+use yew::{
+    html::InputData,
+    prelude::*,
+    services::{fetch::FetchTask, ConsoleService},
+};
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/totp.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct StartTotpEnrollment;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/totp.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct ConfirmTotpEnrollment;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/totp.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct DisableTotp;
+
+type TotpEnrollment = start_totp_enrollment::StartTotpEnrollmentStartTotpEnrollment;
+
+/// The empty modules around the QR code, which the scanners need to find it.
+const QR_CODE_QUIET_ZONE: usize = 4;
+
+/// Renders the data as a QR code, in a single SVG path to keep the DOM small.
+fn view_qr_code(data: &str) -> Result<Html> {
+    let code =
+        QrCode::new(data.as_bytes()).map_err(|e| anyhow!("Could not draw the QR code: {}", e))?;
+    let width = code.width();
+    let path: String = code
+        .to_colors()
+        .iter()
+        .enumerate()
+        .filter(|(_, color)| **color == Color::Dark)
+        .map(|(i, _)| format!("M{},{}h1v1h-1z", i % width, i / width))
+        .collect();
+    let size = width + 2 * QR_CODE_QUIET_ZONE;
+    Ok(html! {
+      <svg
+        xmlns="http://www.w3.org/2000/svg"
+        viewBox=format!("-{0} -{0} {1} {1}", QR_CODE_QUIET_ZONE, size)
+        width="200"
+        height="200"
+        shape-rendering="crispEdges"
+        role="img"
+        aria-label="QR code of the secret">
+        <rect
+          x=format!("-{}", QR_CODE_QUIET_ZONE)
+          y=format!("-{}", QR_CODE_QUIET_ZONE)
+          width=size.to_string()
+          height=size.to_string()
+          fill="#fff" />
+        <path d=path fill="#000" />
+      </svg>
+    })
+}
+
+/// The two-factor authentication section of the user page: the user can set up an authenticator
+/// app, and the user or an admin can turn it off.
+pub struct TotpSettings {
+    link: ComponentLink<Self>,
+    props: Props,
+    enabled: bool,
+    /// The secret to add to the app, until a code from it is confirmed.
+    enrollment: Option<TotpEnrollment>,
+    code: String,
+    /// Shown once, right after the enrollment.
+    recovery_codes: Option<Vec<String>>,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub username: String,
+    pub enabled: bool,
+    /// Only the user can set up their authenticator app.
+    pub is_current_user: bool,
+    #[prop_or_default]
+    pub read_only: bool,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    Start,
+    StartResponse(Result<start_totp_enrollment::ResponseData>),
+    CodeInput(InputData),
+    Confirm,
+    ConfirmResponse(Result<confirm_totp_enrollment::ResponseData>),
+    Cancel,
+    DownloadRecoveryCodes,
+    DismissRecoveryCodes,
+    Disable,
+    DisableResponse(Result<disable_totp::ResponseData>),
+}
+
+impl TotpSettings {
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::Start => {
+                self.task = Some(HostService::graphql_query::<StartTotpEnrollment>(
+                    start_totp_enrollment::Variables,
+                    self.link.callback(Msg::StartResponse),
+                    "Error trying to start the two-factor authentication setup",
+                )?);
+            }
+            Msg::StartResponse(response) => {
+                self.task = None;
+                self.code.clear();
+                self.enrollment = Some(response?.start_totp_enrollment);
+            }
+            Msg::CodeInput(data) => {
+                self.code = data.value;
+                return Ok(false);
+            }
+            Msg::Confirm => {
+                self.task = Some(HostService::graphql_query::<ConfirmTotpEnrollment>(
+                    confirm_totp_enrollment::Variables {
+                        code: self.code.trim().to_string(),
+                    },
+                    self.link.callback(Msg::ConfirmResponse),
+                    "Error trying to enable the two-factor authentication",
+                )?);
+            }
+            Msg::ConfirmResponse(response) => {
+                self.task = None;
+                self.recovery_codes = Some(response?.confirm_totp_enrollment);
+                self.enrollment = None;
+                self.enabled = true;
+            }
+            Msg::Cancel => self.enrollment = None,
+            Msg::DownloadRecoveryCodes => {
+                if let Some(codes) = &self.recovery_codes {
+                    download_file(
+                        "lldap-recovery-codes.txt",
+                        &(codes.join("\n") + "\n"),
+                        "text/plain;charset=utf-8",
+                    )?;
+                }
+                return Ok(false);
+            }
+            Msg::DismissRecoveryCodes => self.recovery_codes = None,
+            Msg::Disable => {
+                self.task = Some(HostService::graphql_query::<DisableTotp>(
+                    disable_totp::Variables {
+                        user_id: self.props.username.clone(),
+                    },
+                    self.link.callback(Msg::DisableResponse),
+                    "Error trying to disable the two-factor authentication",
+                )?);
+            }
+            Msg::DisableResponse(response) => {
+                self.task = None;
+                response?;
+                self.enabled = false;
+            }
+        }
+        Ok(true)
+    }
+
+    fn view_recovery_codes(&self, codes: &[String]) -> Html {
+        html! {
+          <div class="alert alert-success">
+            <p>
+              {"The authenticator app is set up. Keep these recovery codes somewhere safe, for \
+                when the codes are asked at login. They won't be shown again."}
+            </p>
+            <ul class="list-unstyled row row-cols-2 font-monospace">
+              {codes.iter().map(|code| html! {
+                <li class="col" key=code.clone()>{code}</li>
+              }).collect::<Vec<_>>()}
+            </ul>
+            <button
+              class="btn btn-secondary"
+              onclick=self.link.callback(|_| Msg::DownloadRecoveryCodes)>
+              <i class="bi-download me-2"></i>
+              {"Download"}
+            </button>
+            <CopyButton text=codes.join("\n") title="Copy the recovery codes" />
+            <button
+              class="btn btn-primary ms-2"
+              onclick=self.link.callback(|_| Msg::DismissRecoveryCodes)>
+              {"I saved them"}
+            </button>
+          </div>
+        }
+    }
+
+    fn view_enrollment(&self, enrollment: &TotpEnrollment) -> Html {
+        let qr_code = view_qr_code(&enrollment.uri).unwrap_or_else(|e| {
+            ConsoleService::error(&e.to_string());
+            html! {}
+        });
+        html! {
+          <div>
+            <p>{"Scan this QR code with an authenticator app:"}</p>
+            {qr_code}
+            <p class="small text-muted mt-2">
+              {"Or type this key in the app: "}
+              <code>{&enrollment.secret}</code>
+              <CopyButton text=enrollment.secret.clone() title="Copy the key" />
+            </p>
+            <form
+              class="row g-2 align-items-center"
+              onsubmit=self.link.callback(|e: FocusEvent| { e.prevent_default(); Msg::Confirm })>
+              <div class="col-auto">
+                <label for="totpCode" class="col-form-label">{"Code from the app:"}</label>
+              </div>
+              <div class="col-auto">
+                <input
+                  type="text"
+                  id="totpCode"
+                  class="form-control"
+                  inputmode="numeric"
+                  autocomplete="one-time-code"
+                  maxlength="6"
+                  value=self.code.clone()
+                  oninput=self.link.callback(Msg::CodeInput) />
+              </div>
+              <div class="col-auto">
+                <button type="submit" class="btn btn-primary" disabled=self.task.is_some()>
+                  {"Verify and enable"}
+                </button>
+                <button
+                  type="button"
+                  class="btn btn-secondary ms-2"
+                  onclick=self.link.callback(|_| Msg::Cancel)>
+                  {"Cancel"}
+                </button>
+              </div>
+            </form>
+          </div>
+        }
+    }
+
+    fn view_status(&self) -> Html {
+        if self.enabled {
+            html! {
+              <div class="d-flex align-items-center">
+                <span class="badge bg-secondary me-3">{"Set up"}</span>
+                {if self.props.read_only { html! {} } else { html! {
+                  <ConfirmModal
+                    id="disableTotpModal"
+                    title="Remove the authenticator app?"
+                    message=html! {
+                      {if self.props.is_current_user {
+                        "You will have to set it up again.".to_string()
+                      } else {
+                        format!("{} will have to set it up again.", self.props.username)
+                      }}
+                    }
+                    button_class="btn btn-outline-danger btn-sm"
+                    disabled=self.task.is_some()
+                    on_confirm=self.link.callback(|_| Msg::Disable)>
+                    {"Remove"}
+                  </ConfirmModal>
+                } } }
+              </div>
+            }
+        } else if self.props.is_current_user {
+            html! {
+              <div class="d-flex align-items-center">
+                <span class="text-muted me-3">
+                  {"Link an authenticator app to your account."}
+                </span>
+                <button
+                  class="btn btn-primary btn-sm"
+                  disabled=self.task.is_some()
+                  onclick=self.link.callback(|_| Msg::Start)>
+                  {"Set up"}
+                </button>
+              </div>
+            }
+        } else {
+            html! { <span class="text-muted">{"Not set up"}</span> }
+        }
+    }
+}
+
+impl Component for TotpSettings {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            enabled: props.enabled,
+            props,
+            enrollment: None,
+            code: String::new(),
+            recovery_codes: None,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                true
+            }
+            Ok(b) => b,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if props.enabled != self.props.enabled {
+            self.enabled = props.enabled;
+        }
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        html! {
+          <>
+            <h5 class="row m-3 fw-bold">{"Two-factor authentication"}</h5>
+            <p class="small text-muted">
+              {"Not enforced yet: the codes are not asked at login, the password alone logs in."}
+            </p>
+            {match (&self.recovery_codes, &self.enrollment) {
+              (Some(codes), _) => self.view_recovery_codes(codes),
+              (None, Some(enrollment)) => self.view_enrollment(enrollment),
+              (None, None) => self.view_status(),
+            }}
+          </>
+        }
+    }
+}
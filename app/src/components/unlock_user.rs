@@ -0,0 +1,96 @@
+use crate::infra::{api::HostService, date, graphql::DateTimeUtc};
+use anyhow::{Error, Result};
+use graphql_client::GraphQLQuery;
+use yew::prelude::*;
+use yew::services::fetch::FetchTask;
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/unlock_user.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct UnlockUserQuery;
+
+/// A button to clear the lock of a user after too many failed logins.
+pub struct UnlockUser {
+    link: ComponentLink<Self>,
+    props: UnlockUserProps,
+    task: Option<FetchTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq, Debug)]
+pub struct UnlockUserProps {
+    pub username: String,
+    pub locked_until: DateTimeUtc,
+    pub on_user_unlocked: Callback<String>,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    ClickedUnlock,
+    UnlockResponse(Result<unlock_user_query::ResponseData>),
+}
+
+impl Component for UnlockUser {
+    type Message = Msg;
+    type Properties = UnlockUserProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            task: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::ClickedUnlock => {
+                self.task = HostService::graphql_query::<UnlockUserQuery>(
+                    unlock_user_query::Variables {
+                        user: self.props.username.clone(),
+                    },
+                    self.link.callback(Msg::UnlockResponse),
+                    "Error trying to unlock user",
+                )
+                .map_err(|e| self.props.on_error.emit(e))
+                .ok();
+            }
+            Msg::UnlockResponse(response) => {
+                self.task = None;
+                if let Err(e) = response {
+                    self.props.on_error.emit(e);
+                } else {
+                    self.props
+                        .on_user_unlocked
+                        .emit(self.props.username.clone());
+                }
+            }
+        }
+        true
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn view(&self) -> Html {
+        let label = format!(
+            "Locked until {}, click to unlock",
+            date::format_local_date_time(&self.props.locked_until)
+        );
+        html! {
+          <button
+            class="btn btn-outline-warning ms-1"
+            title=label.clone()
+            disabled=self.task.is_some()
+            onclick=self.link.callback(|_| Msg::ClickedUnlock)>
+            <i class="bi-unlock" aria-label=label />
+          </button>
+        }
+    }
+}
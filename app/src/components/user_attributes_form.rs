@@ -0,0 +1,457 @@
+use crate::{
+    components::{
+        date_time_field::{self, DateTimeInput},
+        loading,
+    },
+    infra::{api::HostService, unsaved_changes},
+};
+use anyhow::{anyhow, Error, Result};
+use graphql_client::GraphQLQuery;
+use std::collections::BTreeMap;
+use yew::{
+    html::{ChangeData, InputData},
+    prelude::*,
+    services::{
+        fetch::FetchTask,
+        reader::{FileData, ReaderService, ReaderTask},
+        ConsoleService,
+    },
+};
+use yewtil::NeqAssign;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/user_attributes.graphql",
+    response_derives = "Debug, Clone, PartialEq",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct GetUserAttributes;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/user_attributes.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct SetUserAttribute;
+
+type AttributeSchema = get_user_attributes::GetUserAttributesUserAttributeSchema;
+use get_user_attributes::AttributeType;
+
+/// The pictures are re-encoded by the server, but the uploads are limited like the avatars.
+const MAX_PICTURE_SIZE: usize = 10 * 1024 * 1024;
+const JPEG_MAGIC_BYTES: &[u8] = &[0xff, 0xd8, 0xff];
+
+/// Checks a value before sending it, for the message to show next to the field. The server checks
+/// them again.
+fn validate_value(attribute_type: &AttributeType, value: &str) -> Option<String> {
+    match attribute_type {
+        AttributeType::INTEGER if value.trim().parse::<i64>().is_err() => {
+            Some("Not an integer".to_string())
+        }
+        AttributeType::DATE_TIME if date_time_field::validate_date_time(value).is_err() => {
+            Some("Not a date".to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The custom attributes of the user, defined by the admins: one field per attribute, with the
+/// input matching its type, and a list of them for the attributes with several values.
+pub struct UserAttributesForm {
+    link: ComponentLink<Self>,
+    props: Props,
+    schema: Option<Vec<AttributeSchema>>,
+    /// The values being edited, by attribute name. Empty values are dropped when saved.
+    values: BTreeMap<String, Vec<String>>,
+    /// The values on the server, to send only the changed attributes.
+    saved_values: BTreeMap<String, Vec<String>>,
+    /// The attributes left to save, one request at a time.
+    pending: Vec<(String, Vec<String>)>,
+    just_updated: bool,
+    task: Option<FetchTask>,
+    reader_task: Option<ReaderTask>,
+}
+
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    pub username: String,
+    /// Whether the current user can change all the attributes, and not only the editable ones.
+    pub can_manage: bool,
+    #[prop_or_default]
+    pub read_only: bool,
+    pub on_error: Callback<Error>,
+}
+
+pub enum Msg {
+    Response(Result<get_user_attributes::ResponseData>),
+    Input(String, usize, String),
+    AddValue(String),
+    RemoveValue(String, usize),
+    FileChosen(String, usize, ChangeData),
+    FileLoaded(String, usize, FileData),
+    Save,
+    SaveResponse(Result<set_user_attribute::ResponseData>),
+}
+
+impl UserAttributesForm {
+    fn get_attributes(&mut self) -> Result<()> {
+        self.task = Some(HostService::graphql_query::<GetUserAttributes>(
+            get_user_attributes::Variables {
+                id: self.props.username.clone(),
+            },
+            self.link.callback(Msg::Response),
+            "Error trying to fetch the user attributes",
+        )?);
+        Ok(())
+    }
+
+    fn set_value(&mut self, name: String, index: usize, value: String) {
+        let values = self.values.entry(name).or_default();
+        if index < values.len() {
+            values[index] = value;
+        } else {
+            values.push(value);
+        }
+        self.just_updated = false;
+    }
+
+    fn save_next(&mut self) -> Result<()> {
+        match self.pending.pop() {
+            None => {
+                self.task = None;
+                self.just_updated = true;
+            }
+            Some((name, values)) => {
+                self.task = Some(HostService::graphql_query::<SetUserAttribute>(
+                    set_user_attribute::Variables {
+                        user_id: self.props.username.clone(),
+                        name,
+                        values,
+                    },
+                    self.link.callback(Msg::SaveResponse),
+                    "Error trying to update the user attributes",
+                )?);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
+        match msg {
+            Msg::Response(response) => {
+                self.task = None;
+                let response = response?;
+                self.saved_values = response
+                    .user
+                    .attributes
+                    .into_iter()
+                    .map(|a| (a.name, a.values))
+                    .collect();
+                self.values = self.saved_values.clone();
+                self.schema = Some(response.user_attribute_schema);
+            }
+            Msg::Input(name, index, value) => self.set_value(name, index, value),
+            Msg::AddValue(name) => {
+                self.values.entry(name).or_default().push(String::new());
+            }
+            Msg::RemoveValue(name, index) => {
+                if let Some(values) = self.values.get_mut(&name) {
+                    if index < values.len() {
+                        values.remove(index);
+                    }
+                }
+                self.just_updated = false;
+            }
+            Msg::FileChosen(name, index, ChangeData::Files(files)) => {
+                if let Some(file) = files.get(0) {
+                    self.reader_task = Some(
+                        ReaderService::read_file(
+                            file,
+                            self.link
+                                .callback(move |data| Msg::FileLoaded(name.clone(), index, data)),
+                        )
+                        .map_err(|e| anyhow!("Could not read the picture: {}", e))?,
+                    );
+                }
+                return Ok(false);
+            }
+            Msg::FileChosen(..) => return Ok(false),
+            Msg::FileLoaded(name, index, data) => {
+                self.reader_task = None;
+                if data.content.len() > MAX_PICTURE_SIZE {
+                    return Err(anyhow!("The picture is too big"));
+                }
+                if !data.content.starts_with(JPEG_MAGIC_BYTES) {
+                    return Err(anyhow!("The picture must be a JPEG image"));
+                }
+                self.set_value(name, index, base64::encode(&data.content));
+            }
+            Msg::Save => {
+                if self.has_errors() {
+                    return Err(anyhow!("Invalid attribute values"));
+                }
+                self.just_updated = false;
+                self.pending = self.changed_values();
+                self.save_next()?;
+            }
+            Msg::SaveResponse(response) => {
+                response?;
+                // The server may have normalized the values: they are fetched again at the end.
+                if self.pending.is_empty() {
+                    self.get_attributes()?;
+                    self.just_updated = true;
+                } else {
+                    self.save_next()?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// The attributes whose values differ from the saved ones, without the empty values.
+    fn changed_values(&self) -> Vec<(String, Vec<String>)> {
+        self.values
+            .iter()
+            .map(|(name, values)| {
+                let values: Vec<String> =
+                    values.iter().filter(|v| !v.is_empty()).cloned().collect();
+                (name.clone(), values)
+            })
+            .filter(|(name, values)| {
+                self.saved_values.get(name).cloned().unwrap_or_default() != *values
+            })
+            .collect()
+    }
+
+    fn dirty_key(&self) -> String {
+        format!("user-attributes:{}", self.props.username)
+    }
+
+    fn has_errors(&self) -> bool {
+        self.schema.iter().flatten().any(|schema| {
+            self.values
+                .get(&schema.name)
+                .into_iter()
+                .flatten()
+                .any(|v| !v.is_empty() && validate_value(&schema.attribute_type, v).is_some())
+        })
+    }
+
+    fn is_editable(&self, schema: &AttributeSchema) -> bool {
+        !self.props.read_only && (self.props.can_manage || schema.is_editable)
+    }
+
+    fn view_input(&self, schema: &AttributeSchema, index: usize, value: &str) -> Html {
+        let name = schema.name.clone();
+        let disabled = !self.is_editable(schema);
+        let id = format!("attribute-{}-{}", schema.name, index);
+        let error = if value.is_empty() {
+            None
+        } else {
+            validate_value(&schema.attribute_type, value)
+        };
+        let class = if error.is_some() {
+            "form-control is-invalid"
+        } else {
+            "form-control"
+        };
+        let input = match schema.attribute_type {
+            AttributeType::JPEG_PHOTO => html! {
+              <div class="d-flex align-items-center">
+                {if value.is_empty() { html! {
+                  <span class="text-muted me-3">{"No picture"}</span>
+                } } else { html! {
+                  <img
+                    class="img-thumbnail me-3"
+                    width="64"
+                    height="64"
+                    alt=schema.name.clone()
+                    src=format!("data:image/jpeg;base64,{}", value) />
+                } } }
+                <input
+                  type="file"
+                  id=id
+                  class="form-control"
+                  accept="image/jpeg"
+                  hidden=disabled
+                  onchange=self.link.callback(move |data| Msg::FileChosen(name.clone(), index, data)) />
+              </div>
+            },
+            AttributeType::DATE_TIME => html! {
+              <DateTimeInput
+                id=id
+                class=class
+                disabled=disabled
+                value=value.to_string()
+                on_change=self.link.callback(move |value| Msg::Input(name.clone(), index, value)) />
+            },
+            AttributeType::INTEGER => html! {
+              <input
+                type="number"
+                step="1"
+                id=id
+                class=class
+                disabled=disabled
+                value=value.to_string()
+                oninput=self.link.callback(move |data: InputData| {
+                  Msg::Input(name.clone(), index, data.value)
+                }) />
+            },
+            _ => html! {
+              <input
+                type="text"
+                id=id
+                class=class
+                disabled=disabled
+                value=value.to_string()
+                oninput=self.link.callback(move |data: InputData| {
+                  Msg::Input(name.clone(), index, data.value)
+                }) />
+            },
+        };
+        let remove_name = schema.name.clone();
+        // A single picture can be removed too, as it cannot be emptied like a text field.
+        let can_remove = !disabled
+            && (schema.is_list
+                || (schema.attribute_type == AttributeType::JPEG_PHOTO && !value.is_empty()));
+        html! {
+          <div class="input-group mb-1 has-validation">
+            {input}
+            {if can_remove { html! {
+              <button
+                type="button"
+                class="btn btn-outline-secondary"
+                title="Remove this value"
+                onclick=self.link.callback(move |_| Msg::RemoveValue(remove_name.clone(), index))>
+                <i class="bi-x" aria-label="Remove this value" />
+              </button>
+            } } else { html! {} } }
+            {match error {
+              Some(error) => html! { <div class="invalid-feedback">{error}</div> },
+              None => html! {},
+            }}
+          </div>
+        }
+    }
+
+    fn view_attribute(&self, schema: &AttributeSchema) -> Html {
+        let empty = Vec::new();
+        let values = self.values.get(&schema.name).unwrap_or(&empty);
+        let name = schema.name.clone();
+        let inputs = if schema.is_list {
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| self.view_input(schema, i, v))
+                .collect::<Vec<_>>()
+        } else {
+            vec![self.view_input(schema, 0, values.first().map(String::as_str).unwrap_or(""))]
+        };
+        html! {
+          <div class="form-group row mb-3" key=schema.name.clone()>
+            <label
+              for=format!("attribute-{}-0", schema.name)
+              class="form-label col-4 col-form-label">
+              {format!("{}: ", schema.name)}
+            </label>
+            <div class="col-8">
+              {inputs}
+              {if schema.is_list && self.is_editable(schema) { html! {
+                <button
+                  type="button"
+                  class="btn btn-link btn-sm"
+                  onclick=self.link.callback(move |_| Msg::AddValue(name.clone()))>
+                  <i class="bi-plus me-1"></i>
+                  {"Add a value"}
+                </button>
+              } } else { html! {} } }
+            </div>
+          </div>
+        }
+    }
+}
+
+impl Component for UserAttributesForm {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut form = Self {
+            link,
+            props,
+            schema: None,
+            values: BTreeMap::new(),
+            saved_values: BTreeMap::new(),
+            pending: Vec::new(),
+            just_updated: false,
+            task: None,
+            reader_task: None,
+        };
+        if let Err(e) = form.get_attributes() {
+            ConsoleService::error(&e.to_string());
+            form.props.on_error.emit(e);
+        }
+        form
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        let should_render = match self.handle_msg(msg) {
+            Err(e) => {
+                ConsoleService::error(&e.to_string());
+                self.props.on_error.emit(e);
+                self.task = None;
+                self.pending.clear();
+                true
+            }
+            Ok(b) => b,
+        };
+        unsaved_changes::set_dirty(&self.dirty_key(), !self.changed_values().is_empty());
+        should_render
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props.neq_assign(props)
+    }
+
+    fn destroy(&mut self) {
+        unsaved_changes::set_dirty(&self.dirty_key(), false);
+    }
+
+    fn view(&self) -> Html {
+        let schema = match &self.schema {
+            None => return loading::view_skeleton_table(2),
+            // No custom attribute defined: nothing to show.
+            Some(schema) if schema.is_empty() => return html! {},
+            Some(schema) => schema,
+        };
+        let can_save = schema.iter().any(|s| self.is_editable(s));
+        html! {
+          <div class="py-3">
+            <h5 class="row m-3 fw-bold">{"Other attributes"}</h5>
+            <form
+              class="form"
+              onsubmit=self.link.callback(|e: FocusEvent| { e.prevent_default(); Msg::Save })>
+              {schema.iter().map(|s| self.view_attribute(s)).collect::<Vec<_>>()}
+              {if can_save { html! {
+                <div class="form-group row justify-content-center">
+                  <button
+                    type="submit"
+                    class="btn btn-primary col-auto col-form-label"
+                    disabled=self.task.is_some() || self.has_errors()>
+                    {"Save the attributes"}
+                  </button>
+                </div>
+              } } else { html! {} } }
+            </form>
+            <div hidden=!self.just_updated>
+              <span>{"Attributes successfully updated!"}</span>
+            </div>
+          </div>
+        }
+    }
+}
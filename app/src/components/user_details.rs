@@ -1,9 +1,20 @@
 use crate::{
     components::{
         add_user_to_group::AddUserToGroupComponent,
+        avatar::Avatar,
+        copy_button::CopyButton,
+        error_page, loading,
+        login_history::LoginHistory,
         remove_user_from_group::RemoveUserFromGroupComponent,
+        renew_user::RenewUser,
         router::{AppRoute, Link, NavButton},
+        security_keys::SecurityKeys,
+        toggle_user_enabled::ToggleUserEnabled,
+        totp_settings::TotpSettings,
+        unlock_user::UnlockUser,
+        user_attributes_form::UserAttributesForm,
         user_details_form::UserDetailsForm,
+        user_status,
     },
     infra::api::HostService,
 };
@@ -19,13 +30,21 @@ use yew::{
     schema_path = "../schema.graphql",
     query_path = "queries/get_user_details.graphql",
     response_derives = "Debug, Hash, PartialEq, Eq, Clone",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct GetUserDetails;
 
 pub type User = get_user_details::GetUserDetailsUser;
 pub type Group = get_user_details::GetUserDetailsUserGroups;
 
+/// The tabs of the page.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tab {
+    Details,
+    Groups,
+}
+
 pub struct UserDetails {
     link: ComponentLink<Self>,
     props: Props,
@@ -34,8 +53,12 @@ pub struct UserDetails {
     user: Option<User>,
     /// Error message displayed to the user.
     error: Option<Error>,
+    /// The last invite link created for the user.
+    invite_link: Option<String>,
+    tab: Tab,
     // Used to keep the request alive long enough.
     _task: Option<FetchTask>,
+    invite_task: Option<FetchTask>,
 }
 
 /// State machine describing the possible transitions of the component state.
@@ -43,15 +66,29 @@ pub struct UserDetails {
 pub enum Msg {
     /// Received the user details response, either the user data or an error.
     UserDetailsResponse(Result<get_user_details::ResponseData>),
+    /// Fetches the user details again, after an error.
+    Retry,
     OnError(Error),
     OnUserAddedToGroup(Group),
     OnUserRemovedFromGroup((String, i64)),
+    OnUserToggled((String, bool)),
+    OnUserUnlocked(String),
+    OnUserRenewed(String),
+    CreateInvite,
+    InviteResponse(Result<String>),
+    SelectTab(Tab),
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
 pub struct Props {
     pub username: String,
     pub is_admin: bool,
+    /// Whether the current user can see the user without modifying them.
+    #[prop_or_default]
+    pub read_only: bool,
+    /// Whether it's the profile of the logged in user, who can see their recent logins.
+    #[prop_or_default]
+    pub is_current_user: bool,
 }
 
 impl UserDetails {
@@ -79,6 +116,7 @@ impl UserDetails {
                     bail!("Error getting user details: {}", e);
                 }
             },
+            Msg::Retry => self.get_user_details(),
             Msg::OnError(e) => return Err(e),
             Msg::OnUserAddedToGroup(group) => {
                 self.user.as_mut().unwrap().groups.push(group);
@@ -90,19 +128,29 @@ impl UserDetails {
                     .groups
                     .retain(|g| g.id != group_id);
             }
+            Msg::OnUserToggled((_, enabled)) => self.user.as_mut().unwrap().enabled = enabled,
+            Msg::OnUserUnlocked(_) => self.user.as_mut().unwrap().locked_until = None,
+            Msg::OnUserRenewed(_) => self.user.as_mut().unwrap().valid_until = None,
+            Msg::CreateInvite => {
+                self.invite_task = Some(HostService::create_invite(
+                    self.props.username.clone(),
+                    self.link.callback(Msg::InviteResponse),
+                )?);
+            }
+            Msg::InviteResponse(response) => {
+                self.invite_task = None;
+                let token = response?;
+                self.invite_link = Some(format!("{}/invite/{}", yew::utils::origin()?, token));
+            }
+            Msg::SelectTab(tab) => self.tab = tab,
         }
         Ok(true)
     }
 
     fn view_messages(&self, error: &Option<Error>) -> Html {
-        if let Some(e) = error {
-            html! {
-              <div class="alert alert-danger">
-                <span>{"Error: "}{e.to_string()}</span>
-              </div>
-            }
-        } else {
-            html! {}
+        match error {
+            Some(e) => loading::view_error(e),
+            None => html! {},
         }
     }
 
@@ -111,7 +159,13 @@ impl UserDetails {
             let display_name = group.display_name.clone();
             html! {
               <tr key="groupRow_".to_string() + &display_name>
-                {if self.props.is_admin { html! {
+                {if self.props.read_only { html! {
+                  <td>
+                    <Link route=AppRoute::GroupDetails(group.id)>
+                      {&group.display_name}
+                    </Link>
+                  </td>
+                } } else if self.props.is_admin { html! {
                   <>
                     <td>
                       <Link route=AppRoute::GroupDetails(group.id)>
@@ -122,6 +176,7 @@ impl UserDetails {
                       <RemoveUserFromGroupComponent
                         username=u.id.clone()
                         group_id=group.id
+                        group_name=group.display_name.clone()
                         on_user_removed_from_group=self.link.callback(Msg::OnUserRemovedFromGroup)
                         on_error=self.link.callback(Msg::OnError)/>
                     </td>
@@ -134,7 +189,6 @@ impl UserDetails {
         };
         html! {
           <>
-            <h5 class="row m-3 fw-bold">{"Group memberships"}</h5>
             <div class="table-responsive">
               <table class="table table-striped">
                 <thead>
@@ -160,6 +214,27 @@ impl UserDetails {
         }
     }
 
+    /// The groups whose members the user can manage, with links to do so.
+    fn view_managed_groups(&self, u: &User) -> Html {
+        if u.managed_groups.is_empty() {
+            return html! {};
+        }
+        html! {
+          <>
+            <h5 class="row m-3 fw-bold">{"Managed groups"}</h5>
+            <ul>
+              {u.managed_groups.iter().map(|group| html! {
+                <li key=group.id>
+                  <Link route=AppRoute::GroupDetails(group.id)>
+                    {&group.display_name}
+                  </Link>
+                </li>
+              }).collect::<Vec<_>>()}
+            </ul>
+          </>
+        }
+    }
+
     fn view_add_group_button(&self, u: &User) -> Html {
         if self.props.is_admin {
             html! {
@@ -173,6 +248,163 @@ impl UserDetails {
             html! {}
         }
     }
+
+    fn view_tabs(&self, u: &User) -> Html {
+        let view_tab = |tab: Tab, name: String| {
+            let class = if self.tab == tab {
+                "nav-link active"
+            } else {
+                "nav-link"
+            };
+            html! {
+              <li class="nav-item">
+                <button
+                  type="button"
+                  class=class
+                  onclick=self.link.callback(move |_| Msg::SelectTab(tab))>
+                  {name}
+                </button>
+              </li>
+            }
+        };
+        html! {
+          <ul class="nav nav-tabs mb-3">
+            {view_tab(Tab::Details, "Details".to_string())}
+            {view_tab(Tab::Groups, format!("Groups ({})", u.groups.len()))}
+          </ul>
+        }
+    }
+
+    /// The buttons to disable or re-enable the user, and to lift their lock or their expiry.
+    fn view_status_actions(&self, u: &User) -> Html {
+        if !self.props.is_admin || self.props.read_only {
+            return html! {};
+        }
+        html! {
+          <div class="mb-3">
+            <ToggleUserEnabled
+              username=u.id.clone()
+              enabled=u.enabled
+              on_user_toggled=self.link.callback(Msg::OnUserToggled)
+              on_error=self.link.callback(Msg::OnError)/>
+            {match &u.locked_until {
+              Some(locked_until) => html! {
+                <UnlockUser
+                  username=u.id.clone()
+                  locked_until=*locked_until
+                  on_user_unlocked=self.link.callback(Msg::OnUserUnlocked)
+                  on_error=self.link.callback(Msg::OnError)/>
+              },
+              None => html! {},
+            }}
+            {if user_status::is_expired(u.valid_until.as_ref()) { html! {
+              <RenewUser
+                username=u.id.clone()
+                on_user_renewed=self.link.callback(Msg::OnUserRenewed)
+                on_error=self.link.callback(Msg::OnError)/>
+            } } else { html! {} } }
+          </div>
+        }
+    }
+
+    /// The email and the DN of the user, to paste in the configuration of other services.
+    fn view_identifiers(&self, u: &User) -> Html {
+        html! {
+          <dl class="row small text-muted">
+            <dt class="col-sm-1">{"Email"}</dt>
+            <dd class="col-sm-11">
+              {&u.email}
+              <CopyButton text=u.email.clone() title="Copy the email" />
+            </dd>
+            <dt class="col-sm-1">{"DN"}</dt>
+            <dd class="col-sm-11">
+              <code>{&u.dn}</code>
+              <CopyButton text=u.dn.clone() title="Copy the DN" />
+            </dd>
+          </dl>
+        }
+    }
+
+    fn view_details_tab(&self, u: &User) -> Html {
+        html! {
+          <>
+            <Avatar
+              username=u.id.clone()
+              avatar=u.avatar.clone()
+              read_only=self.props.read_only
+              on_error=self.link.callback(Msg::OnError)/>
+            <UserDetailsForm
+              user=u.clone()
+              read_only=self.props.read_only
+              can_set_expiry=self.props.is_admin && !self.props.is_current_user
+              on_error=self.link.callback(Msg::OnError)/>
+            <UserAttributesForm
+              username=u.id.clone()
+              can_manage=self.props.is_admin
+              read_only=self.props.read_only
+              on_error=self.link.callback(Msg::OnError)/>
+            <div class="row justify-content-center" hidden=self.props.read_only>
+              <NavButton
+                route=AppRoute::ChangePassword(u.id.clone())
+                classes="btn btn-primary col-auto">
+                  {"Change password"}
+              </NavButton>
+            </div>
+            {self.view_invite()}
+            {if self.props.is_current_user || self.props.is_admin { html! {
+              <TotpSettings
+                username=u.id.clone()
+                enabled=u.totp_enabled
+                is_current_user=self.props.is_current_user
+                read_only=self.props.read_only
+                on_error=self.link.callback(Msg::OnError)/>
+            } } else { html! {} } }
+            {if self.props.is_current_user || self.props.is_admin { html! {
+              <SecurityKeys
+                username=u.id.clone()
+                is_current_user=self.props.is_current_user
+                read_only=self.props.read_only
+                on_error=self.link.callback(Msg::OnError)/>
+            } } else { html! {} } }
+            {if self.props.is_current_user { html! {
+              <LoginHistory username=u.id.clone() />
+            } } else { html! {} } }
+          </>
+        }
+    }
+
+    /// The memberships of the user, which can be managed from here as well as from the groups.
+    fn view_groups_tab(&self, u: &User) -> Html {
+        html! {
+          <>
+            {self.view_group_memberships(u)}
+            {self.view_add_group_button(u)}
+            {self.view_managed_groups(u)}
+          </>
+        }
+    }
+
+    fn view_invite(&self) -> Html {
+        if !self.props.is_admin {
+            return html! {};
+        }
+        html! {
+          <div class="row justify-content-center mt-2">
+            <button
+              class="btn btn-secondary col-auto"
+              disabled=self.invite_task.is_some()
+              onclick=self.link.callback(|_| Msg::CreateInvite)>
+              {"Create an invite link"}
+            </button>
+            {if let Some(invite_link) = &self.invite_link { html! {
+              <div class="alert alert-info mt-2">
+                {"Send this link to the user to set their password: "}
+                <code>{invite_link}</code>
+              </div>
+            } } else { html! {} } }
+          </div>
+        }
+    }
 }
 
 impl Component for UserDetails {
@@ -186,6 +418,9 @@ impl Component for UserDetails {
             _task: None,
             user: None,
             error: None,
+            invite_link: None,
+            tab: Tab::Details,
+            invite_task: None,
         };
         table.get_user_details();
         table
@@ -197,6 +432,7 @@ impl Component for UserDetails {
             Err(e) => {
                 ConsoleService::error(&e.to_string());
                 self.error = Some(e);
+                self.invite_task = None;
                 true
             }
             Ok(b) => b,
@@ -209,24 +445,28 @@ impl Component for UserDetails {
 
     fn view(&self) -> Html {
         match (&self.user, &self.error) {
-            (None, None) => html! {{"Loading..."}},
-            (None, Some(e)) => html! {<div>{"Error: "}{e.to_string()}</div>},
+            (None, None) => loading::view_spinner("Loading the user..."),
+            (None, Some(e)) => error_page::view_load_error(e, self.link.callback(|_| Msg::Retry)),
             (Some(u), error) => {
                 html! {
                   <>
-                    <h3>{u.id.to_string()}</h3>
-                    <UserDetailsForm
-                      user=u.clone()
-                      on_error=self.link.callback(Msg::OnError)/>
-                    <div class="row justify-content-center">
-                      <NavButton
-                        route=AppRoute::ChangePassword(u.id.clone())
-                        classes="btn btn-primary col-auto">
-                          {"Change password"}
-                      </NavButton>
-                    </div>
-                    {self.view_group_memberships(u)}
-                    {self.view_add_group_button(u)}
+                    <h3>
+                      {u.id.to_string()}
+                      <CopyButton text=u.id.clone() title="Copy the user ID" />
+                      <small>
+                        {user_status::view_status_badges(
+                          u.enabled,
+                          u.locked_until.as_ref(),
+                          u.valid_until.as_ref(),
+                        )}
+                      </small>
+                    </h3>
+                    {self.view_status_actions(u)}
+                    {self.view_identifiers(u)}
+                    {self.view_tabs(u)}
+                    // Both tabs stay rendered, to keep what's being typed in the form.
+                    <div hidden=self.tab != Tab::Details>{self.view_details_tab(u)}</div>
+                    <div hidden=self.tab != Tab::Groups>{self.view_groups_tab(u)}</div>
                     {self.view_messages(error)}
                   </>
                 }
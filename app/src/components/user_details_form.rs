@@ -1,4 +1,10 @@
-use crate::{components::user_details::User, infra::api::HostService};
+use crate::{
+    components::{
+        date_time_field::{self, validate_date_time, DateTimeField},
+        user_details::User,
+    },
+    infra::{api::HostService, date, graphql::DateTimeUtc, unsaved_changes},
+};
 use anyhow::{bail, Error, Result};
 use graphql_client::GraphQLQuery;
 use validator_derive::Validate;
@@ -17,6 +23,9 @@ pub struct UserModel {
     display_name: String,
     first_name: String,
     last_name: String,
+    /// In RFC 3339, or empty for no expiry.
+    #[validate(custom(function = "validate_date_time", message = "Not a valid date"))]
+    valid_until: String,
 }
 
 /// The GraphQL query sent to the server to update the user details.
@@ -26,10 +35,21 @@ pub struct UserModel {
     query_path = "queries/update_user.graphql",
     response_derives = "Debug",
     variables_derives = "Clone,PartialEq",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct UpdateUser;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "../schema.graphql",
+    query_path = "queries/set_user_valid_until.graphql",
+    response_derives = "Debug",
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
+)]
+pub struct SetUserValidUntil;
+
 /// A [yew::Component] to display the user details, with a form allowing to edit them.
 pub struct UserDetailsForm {
     link: ComponentLink<Self>,
@@ -47,12 +67,19 @@ pub enum Msg {
     SubmitClicked,
     /// We got the response from the server about our update message.
     UserUpdated(Result<update_user::ResponseData>),
+    ValidUntilUpdated(Result<set_user_valid_until::ResponseData>),
 }
 
 #[derive(yew::Properties, Clone, PartialEq)]
 pub struct Props {
     /// The current user details.
     pub user: User,
+    /// Hides the "Update" button.
+    #[prop_or_default]
+    pub read_only: bool,
+    /// Shows the field to change the expiry date, instead of the date.
+    #[prop_or_default]
+    pub can_set_expiry: bool,
     /// Callback to report errors (e.g. server error).
     pub on_error: Callback<Error>,
 }
@@ -67,6 +94,12 @@ impl Component for UserDetailsForm {
             display_name: props.user.display_name.clone(),
             first_name: props.user.first_name.clone(),
             last_name: props.user.last_name.clone(),
+            valid_until: props
+                .user
+                .valid_until
+                .as_ref()
+                .map(date_time_field::format_date_time)
+                .unwrap_or_default(),
         };
         Self {
             link,
@@ -79,7 +112,7 @@ impl Component for UserDetailsForm {
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         self.just_updated = false;
-        match self.handle_msg(msg) {
+        let should_render = match self.handle_msg(msg) {
             Err(e) => {
                 ConsoleService::error(&e.to_string());
                 self.props.on_error.emit(e);
@@ -87,13 +120,19 @@ impl Component for UserDetailsForm {
                 true
             }
             Ok(b) => b,
-        }
+        };
+        unsaved_changes::set_dirty(&self.dirty_key(), self.is_dirty());
+        should_render
     }
 
     fn change(&mut self, _: Self::Properties) -> ShouldRender {
         false
     }
 
+    fn destroy(&mut self) {
+        unsaved_changes::set_dirty(&self.dirty_key(), false);
+    }
+
     fn view(&self) -> Html {
         type Field = yew_form::Field<UserModel>;
         html! {
@@ -180,16 +219,22 @@ impl Component for UserDetailsForm {
                   </div>
                 </div>
               </div>
+              {self.view_expiry()}
               <div class="form-group row mb-3">
                 <label for="creationDate"
                 class="form-label col-4 col-form-label">
                 {"Creation date: "}
                 </label>
                 <div class="col-8">
-                  <span id="creationDate" class="form-constrol-static">{&self.props.user.creation_date.date().naive_local()}</span>
+                  <span
+                    id="creationDate"
+                    class="form-constrol-static"
+                    title=date::format_relative(&self.props.user.creation_date)>
+                    {date::format_local_date_time(&self.props.user.creation_date)}
+                  </span>
                 </div>
               </div>
-              <div class="form-group row justify-content-center">
+              <div class="form-group row justify-content-center" hidden=self.props.read_only>
                 <button
                   type="submit"
                   class="btn btn-primary col-auto col-form-label"
@@ -208,11 +253,79 @@ impl Component for UserDetailsForm {
 }
 
 impl UserDetailsForm {
+    fn dirty_key(&self) -> String {
+        format!("user:{}", self.props.user.id)
+    }
+
+    /// Whether the form differs from the saved details.
+    fn is_dirty(&self) -> bool {
+        let model = self.form.model();
+        let user = &self.props.user;
+        model.email != user.email
+            || model.display_name != user.display_name
+            || model.first_name != user.first_name
+            || model.last_name != user.last_name
+            || self.valid_until_changed()
+    }
+
+    /// The expiry date of the form, if different from the saved one.
+    fn valid_until_changed(&self) -> bool {
+        self.form_valid_until() != self.props.user.valid_until
+    }
+
+    fn form_valid_until(&self) -> Option<DateTimeUtc> {
+        date_time_field::parse_date_time(&self.form.model().valid_until)
+    }
+
+    fn view_expiry(&self) -> Html {
+        type DateField = DateTimeField<UserModel>;
+        let editable = self.props.can_set_expiry && !self.props.read_only;
+        if !editable && self.props.user.valid_until.is_none() {
+            return html! {};
+        }
+        html! {
+          <div class="form-group row mb-3">
+            <label for="valid_until"
+              class="form-label col-4 col-form-label">
+              {"Expires on: "}
+            </label>
+            <div class="col-8">
+              {if editable { html! {
+                <>
+                  <DateField
+                    form=&self.form
+                    field_name="valid_until"
+                    min=chrono::Utc::now()
+                    class_invalid="is-invalid has-error"
+                    class_valid="has-success"
+                    oninput=self.link.callback(|_| Msg::Update) />
+                  <div class="invalid-feedback">
+                    {&self.form.field_message("valid_until")}
+                  </div>
+                  <div class="form-text">{"The user can't log in after this date. Empty to never expire."}</div>
+                </>
+              } } else { html! {
+                <span id="valid_until" class="form-constrol-static">
+                  {self.props.user.valid_until.as_ref().map(date::format_local_date).unwrap_or_default()}
+                </span>
+              } } }
+            </div>
+          </div>
+        }
+    }
+
     fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
         match msg {
             Msg::Update => Ok(true),
             Msg::SubmitClicked => self.submit_user_update_form(),
             Msg::UserUpdated(response) => self.user_update_finished(response),
+            Msg::ValidUntilUpdated(response) => {
+                self.task = None;
+                response?;
+                self.props.user.valid_until = self.form_valid_until();
+                self.just_updated = true;
+                Ok(true)
+            }
         }
     }
 
@@ -227,6 +340,7 @@ impl UserDetailsForm {
             displayName: None,
             firstName: None,
             lastName: None,
+            avatar: None,
         };
         let default_user_input = user_input.clone();
         let model = self.form.model();
@@ -243,9 +357,9 @@ impl UserDetailsForm {
         if base_user.last_name != model.last_name {
             user_input.lastName = Some(model.last_name);
         }
-        // Nothing changed.
         if user_input == default_user_input {
-            return Ok(false);
+            // Nothing else changed.
+            return self.submit_valid_until();
         }
         let req = update_user::Variables { user: user_input };
         self.task = Some(HostService::graphql_query::<UpdateUser>(
@@ -256,6 +370,21 @@ impl UserDetailsForm {
         Ok(false)
     }
 
+    fn submit_valid_until(&mut self) -> Result<bool> {
+        if !self.valid_until_changed() {
+            return Ok(false);
+        }
+        self.task = Some(HostService::graphql_query::<SetUserValidUntil>(
+            set_user_valid_until::Variables {
+                user: self.props.user.id.clone(),
+                valid_until: self.form_valid_until(),
+            },
+            self.link.callback(Msg::ValidUntilUpdated),
+            "Error trying to set the expiry date of the user",
+        )?);
+        Ok(false)
+    }
+
     fn user_update_finished(&mut self, r: Result<update_user::ResponseData>) -> Result<bool> {
         self.task = None;
         match r {
@@ -263,14 +392,15 @@ impl UserDetailsForm {
             Ok(_) => {
                 let model = self.form.model();
                 self.props.user = User {
-                    id: self.props.user.id.clone(),
                     email: model.email,
                     display_name: model.display_name,
                     first_name: model.first_name,
                     last_name: model.last_name,
-                    creation_date: self.props.user.creation_date,
-                    groups: self.props.user.groups.clone(),
+                    ..self.props.user.clone()
                 };
+                if self.valid_until_changed() {
+                    return self.submit_valid_until();
+                }
                 self.just_updated = true;
             }
         };
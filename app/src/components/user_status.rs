@@ -0,0 +1,47 @@
+//! The badges showing why a user can't log in, the same in the user list and on the user page.
+use crate::infra::{date, graphql::DateTimeUtc};
+use yew::prelude::*;
+
+/// Whether the user is past the date they were allowed to log in until.
+pub fn is_expired(valid_until: Option<&DateTimeUtc>) -> bool {
+    valid_until
+        .map(|date| *date <= chrono::Utc::now())
+        .unwrap_or(false)
+}
+
+/// A badge for each reason the user can't log in: disabled, locked out after too many failed
+/// logins, or expired. Nothing for an active user.
+pub fn view_status_badges(
+    enabled: bool,
+    locked_until: Option<&DateTimeUtc>,
+    valid_until: Option<&DateTimeUtc>,
+) -> Html {
+    let view_badge = |class: &'static str, text: &'static str, title: String| {
+        html! {
+          <span class=format!("badge ms-1 {}", class) title=title>{text}</span>
+        }
+    };
+    html! {
+      <>
+        {if enabled { html! {} } else {
+          view_badge("bg-secondary", "Disabled", "Can't log in until re-enabled".to_string())
+        }}
+        {match locked_until {
+          Some(date) => view_badge(
+            "bg-warning text-dark",
+            "Locked",
+            format!("Too many failed logins, locked until {}", date::format_local_date_time(date)),
+          ),
+          None => html! {},
+        }}
+        {match valid_until {
+          Some(date) if is_expired(valid_until) => view_badge(
+            "bg-danger",
+            "Expired",
+            format!("Expired on {}", date::format_local_date_time(date)),
+          ),
+          _ => html! {},
+        }}
+      </>
+    }
+}
@@ -1,46 +1,284 @@
 use crate::{
     components::{
+        bulk_user_actions::BulkUserActions,
         delete_user::DeleteUser,
+        error_page,
+        export_users::ExportUsers,
+        loading,
+        renew_user::RenewUser,
         router::{AppRoute, Link},
+        toggle_user_enabled::ToggleUserEnabled,
+        unlock_user::UnlockUser,
+        user_status,
+    },
+    infra::{
+        api::HostService,
+        change_events::{self, ChangeEvent, ChangeSubscription, ChangeType, EntryType},
+        date,
     },
-    infra::api::HostService,
 };
 use anyhow::{Error, Result};
 use graphql_client::GraphQLQuery;
-use yew::prelude::*;
-use yew::services::{fetch::FetchTask, ConsoleService};
+use std::collections::BTreeSet;
+use yew::{
+    html::{ChangeData, InputData},
+    prelude::*,
+    services::{
+        fetch::FetchTask,
+        storage::{Area, StorageService},
+        timeout::{TimeoutService, TimeoutTask},
+        websocket::WebSocketStatus,
+        ConsoleService,
+    },
+};
+use yew_router::{
+    agent::{RouteAgentBridge, RouteAgentDispatcher, RouteRequest},
+    route::Route,
+    service::RouteService,
+};
 
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "../schema.graphql",
     query_path = "queries/list_users.graphql",
     response_derives = "Debug",
-    custom_scalars_module = "crate::infra::graphql"
+    custom_scalars_module = "crate::infra::graphql",
+    deprecated = "deny"
 )]
 pub struct ListUsersQuery;
 
 use list_users_query::{RequestFilter, ResponseData};
 
-type User = list_users_query::ListUsersQueryUsers;
+pub type User = list_users_query::ListUsersQueryUsers;
+
+/// The local storage key of the chosen page size.
+const PAGE_SIZE_KEY: &str = "lldap.user_table.page_size";
+const PAGE_SIZES: [i64; 6] = [20, 50, 100, 500, 1000, 5000];
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// The pages with more users only render the rows in view, to keep the page responsive.
+const VIRTUALIZATION_THRESHOLD: usize = 100;
+/// The height of the rows in pixels, fixed by `.virtual-table` for the rows that aren't rendered.
+const ROW_HEIGHT: i32 = 49;
+/// The rows rendered above and below the visible ones, to scroll without blanks.
+const OVERSCAN_ROWS: usize = 10;
+
+/// The columns the table can be sorted by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Column {
+    UserId,
+    Email,
+    DisplayName,
+    CreationDate,
+}
+
+impl Column {
+    pub fn to_query(self) -> list_users_query::UserColumn {
+        match self {
+            Column::UserId => list_users_query::UserColumn::USER_ID,
+            Column::Email => list_users_query::UserColumn::EMAIL,
+            Column::DisplayName => list_users_query::UserColumn::DISPLAY_NAME,
+            Column::CreationDate => list_users_query::UserColumn::CREATION_DATE,
+        }
+    }
+
+    /// The name of the column in the URL.
+    fn name(self) -> &'static str {
+        match self {
+            Column::UserId => "user_id",
+            Column::Email => "email",
+            Column::DisplayName => "display_name",
+            Column::CreationDate => "creation_date",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Column::UserId,
+            Column::Email,
+            Column::DisplayName,
+            Column::CreationDate,
+        ]
+        .iter()
+        .copied()
+        .find(|c| c.name() == name)
+    }
+}
+
+/// The search, sort and page of the list, kept in the query of the URL so that the filtered views
+/// can be bookmarked and shared, and restored with the back button.
+#[derive(Clone, PartialEq, Debug)]
+struct ListState {
+    search: String,
+    sort: Column,
+    descending: bool,
+    /// Starting at 0, but at 1 in the URL.
+    page: i64,
+}
+
+impl Default for ListState {
+    fn default() -> Self {
+        Self {
+            search: String::new(),
+            sort: Column::UserId,
+            descending: false,
+            page: 0,
+        }
+    }
+}
+
+impl ListState {
+    /// Parses the query of a route, e.g. `/users?search=bob&sort=email&order=desc&page=2`. The
+    /// invalid parameters are ignored.
+    fn from_route(route: &str) -> Self {
+        let mut state = Self::default();
+        let query = match route.split_once('?') {
+            Some((_, query)) => query.split('#').next().unwrap_or_default(),
+            None => return state,
+        };
+        let params = match web_sys::UrlSearchParams::new_with_str(query) {
+            Ok(params) => params,
+            Err(_) => return state,
+        };
+        if let Some(search) = params.get("search") {
+            state.search = search;
+        }
+        if let Some(sort) = params.get("sort").as_deref().and_then(Column::from_name) {
+            state.sort = sort;
+        }
+        state.descending = params.get("order").as_deref() == Some("desc");
+        if let Some(page) = params.get("page").and_then(|p| p.parse::<i64>().ok()) {
+            state.page = (page - 1).max(0);
+        }
+        state
+    }
+
+    /// The route of the list, with the parameters that aren't the default ones.
+    fn to_route(&self) -> String {
+        let params = match web_sys::UrlSearchParams::new() {
+            Ok(params) => params,
+            Err(_) => return "/users".to_string(),
+        };
+        if !self.search.is_empty() {
+            params.append("search", &self.search);
+        }
+        if self.sort != Column::UserId {
+            params.append("sort", self.sort.name());
+        }
+        if self.descending {
+            params.append("order", "desc");
+        }
+        if self.page > 0 {
+            params.append("page", &(self.page + 1).to_string());
+        }
+        let query = String::from(params.to_string());
+        if query.is_empty() {
+            "/users".to_string()
+        } else {
+            format!("/users?{}", query)
+        }
+    }
+}
+
+/// The filter on the users matching the search, if any.
+pub fn search_filter(search: &str) -> Option<RequestFilter> {
+    if search.is_empty() {
+        return None;
+    }
+    Some(RequestFilter {
+        any: None,
+        all: None,
+        not: None,
+        eq: None,
+        member_of: None,
+        member_of_id: None,
+        expires_before: None,
+        search: Some(search.to_string()),
+    })
+}
+
+/// The rows to render, out of `count`, for a table scrolled to `scroll_top` with a body of
+/// `viewport_height` pixels.
+fn visible_rows(count: usize, scroll_top: i32, viewport_height: i32) -> std::ops::Range<usize> {
+    let first = (scroll_top.max(0) / ROW_HEIGHT) as usize;
+    let visible = (viewport_height.max(0) / ROW_HEIGHT) as usize + 1;
+    let start = first.saturating_sub(OVERSCAN_ROWS).min(count);
+    let end = (first + visible + OVERSCAN_ROWS).min(count);
+    start..end
+}
 
 pub struct UserTable {
     link: ComponentLink<Self>,
+    props: Props,
     users: Option<Vec<User>>,
+    /// The number of users matching the search, over all the pages.
+    user_count: i64,
+    search: String,
+    sort: Column,
+    descending: bool,
+    /// The current page, starting at 0.
+    page: i64,
+    page_size: i64,
+    storage: Option<StorageService>,
+    /// The users selected for a bulk action, on any page.
+    selected: BTreeSet<String>,
     error: Option<Error>,
+    route_dispatcher: RouteAgentDispatcher,
+    /// The scrolling container of the large pages.
+    table_ref: NodeRef,
+    /// The rows currently rendered, for the large pages.
+    visible_rows: std::ops::Range<usize>,
+    /// Notifies of the navigations, to follow the back and forward buttons.
+    _route_bridge: RouteAgentBridge,
+    /// The changes made by the other admins.
+    changes: ChangeSubscription,
+    reload_timeout: Option<TimeoutTask>,
     // Used to keep the request alive long enough.
     _task: Option<FetchTask>,
 }
 
+#[derive(yew::Properties, Clone, PartialEq)]
+pub struct Props {
+    /// Hides the buttons to modify the users.
+    #[prop_or_default]
+    pub read_only: bool,
+}
+
 pub enum Msg {
     ListUsersResponse(Result<ResponseData>),
+    /// Fetches the users again, after an error.
+    Retry,
+    RouteChanged(Route),
+    TableScrolled,
+    Change(ChangeEvent),
+    ChangesStatus(WebSocketStatus),
+    ReconnectChanges,
+    /// Fetches the page again, after changes.
+    Reload,
+    SearchChanged(String),
+    SortBy(Column),
+    PageChanged(i64),
+    PageSizeChanged(ChangeData),
+    ToggleSelected(String),
+    /// Selects all the users of the page, or unselects them if they all are.
+    TogglePageSelected,
+    BulkActionDone,
     OnUserDeleted(String),
+    OnUserToggled((String, bool)),
+    OnUserUnlocked(String),
+    OnUserRenewed(String),
     OnError(Error),
 }
 
 impl UserTable {
-    fn get_users(&mut self, req: Option<RequestFilter>) {
+    fn get_users(&mut self) {
         self._task = HostService::graphql_query::<ListUsersQuery>(
-            list_users_query::Variables { filters: req },
+            list_users_query::Variables {
+                filters: search_filter(&self.search),
+                offset: Some(self.page * self.page_size),
+                limit: Some(self.page_size),
+                sort: Some(self.sort.to_query()),
+                descending: Some(self.descending),
+            },
             self.link.callback(Msg::ListUsersResponse),
             "Error trying to fetch users",
         )
@@ -50,20 +288,131 @@ impl UserTable {
         })
         .ok();
     }
+
+    /// Computes the rows to render from the scroll position of the table, and returns whether they
+    /// changed.
+    fn update_visible_rows(&mut self) -> bool {
+        let count = self.users.as_ref().map(Vec::len).unwrap_or_default();
+        let rows = if count <= VIRTUALIZATION_THRESHOLD {
+            0..count
+        } else {
+            match self.table_ref.cast::<web_sys::Element>() {
+                Some(table) => visible_rows(count, table.scroll_top(), table.client_height()),
+                // Not rendered yet: the first rows, for a viewport of a typical height.
+                None => visible_rows(count, 0, 20 * ROW_HEIGHT),
+            }
+        };
+        if rows == self.visible_rows {
+            return false;
+        }
+        self.visible_rows = rows;
+        true
+    }
+
+    /// Removes the deleted users right away, and reloads the page for the other changes, which may
+    /// move the users between the pages.
+    fn handle_change(&mut self, change: ChangeEvent) -> bool {
+        if !change.concerns(EntryType::User) {
+            return false;
+        }
+        self.schedule_reload();
+        if change.change_type != ChangeType::Delete {
+            return false;
+        }
+        let users = match self.users.as_mut() {
+            Some(users) => users,
+            None => return false,
+        };
+        let count = users.len();
+        users.retain(|user| user.id != change.id);
+        if users.len() == count {
+            return false;
+        }
+        self.user_count -= 1;
+        self.selected.remove(&change.id);
+        self.update_visible_rows();
+        true
+    }
+
+    fn schedule_reload(&mut self) {
+        self.reload_timeout = Some(TimeoutService::spawn(
+            change_events::RELOAD_DELAY,
+            self.link.callback(|_| Msg::Reload),
+        ));
+    }
+
+    fn list_state(&self) -> ListState {
+        ListState {
+            search: self.search.clone(),
+            sort: self.sort,
+            descending: self.descending,
+            page: self.page,
+        }
+    }
+
+    /// A new page starts at the top.
+    fn scroll_to_top(&self) {
+        if let Some(table) = self.table_ref.cast::<web_sys::Element>() {
+            table.set_scroll_top(0);
+        }
+    }
+
+    /// Shows the current search, sort and page in the URL. A new search replaces the entry of the
+    /// history instead of adding one per keystroke.
+    fn update_url(&mut self, replace: bool) {
+        self.scroll_to_top();
+        let route = Route::new_no_state(&self.list_state().to_route());
+        // The route stays the same for the router: no need to render the app again.
+        self.route_dispatcher.send(if replace {
+            RouteRequest::ReplaceRouteNoBroadcast(route)
+        } else {
+            RouteRequest::ChangeRouteNoBroadcast(route)
+        });
+    }
 }
 
 impl Component for UserTable {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
-    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let storage = StorageService::new(Area::Local).ok();
+        let page_size = storage
+            .as_ref()
+            .and_then(|s| s.restore::<Result<String>>(PAGE_SIZE_KEY).ok())
+            .and_then(|size| size.parse().ok())
+            .filter(|size| PAGE_SIZES.contains(size))
+            .unwrap_or(DEFAULT_PAGE_SIZE);
+        let state = RouteService::<()>::new().get_route();
+        let state = ListState::from_route(&state.route);
+        let route_bridge = RouteAgentBridge::new(link.callback(Msg::RouteChanged));
+        let changes = ChangeSubscription::new(
+            link.callback(Msg::Change),
+            link.callback(Msg::ChangesStatus),
+            link.callback(|_| Msg::ReconnectChanges),
+        );
         let mut table = UserTable {
             link,
+            props,
             _task: None,
             users: None,
+            user_count: 0,
+            search: state.search,
+            sort: state.sort,
+            descending: state.descending,
+            page: state.page,
+            page_size,
+            storage,
+            selected: BTreeSet::new(),
             error: None,
+            route_dispatcher: RouteAgentDispatcher::new(),
+            table_ref: NodeRef::default(),
+            visible_rows: 0..0,
+            _route_bridge: route_bridge,
+            changes,
+            reload_timeout: None,
         };
-        table.get_users(None);
+        table.get_users();
         table
     }
 
@@ -86,7 +435,27 @@ impl Component for UserTable {
     fn view(&self) -> Html {
         html! {
             <div>
+              <div class="d-flex mb-3">
+                <input
+                  type="search"
+                  class="form-control"
+                  placeholder="Search by user ID, email or display name"
+                  value=self.search.clone()
+                  oninput=self.link.callback(|e: InputData| Msg::SearchChanged(e.value)) />
+                <ExportUsers
+                  search=self.search.clone()
+                  sort=self.sort
+                  descending=self.descending
+                  on_error=self.link.callback(Msg::OnError) />
+              </div>
+              {if self.props.read_only { html! {} } else { html! {
+                <BulkUserActions
+                  user_ids=self.selected.iter().cloned().collect::<Vec<_>>()
+                  on_done=self.link.callback(|_| Msg::BulkActionDone)
+                  on_error=self.link.callback(Msg::OnError) />
+              } } }
               {self.view_users()}
+              {self.view_pagination()}
               {self.view_errors()}
             </div>
         }
@@ -96,14 +465,147 @@ impl Component for UserTable {
 impl UserTable {
     fn handle_msg(&mut self, msg: <Self as Component>::Message) -> Result<bool> {
         match msg {
-            Msg::ListUsersResponse(users) => {
-                self.users = Some(users?.users.into_iter().collect());
+            Msg::ListUsersResponse(response) => {
+                let response = response?;
+                self.user_count = response.user_count;
+                self.users = Some(response.users);
+                self.update_visible_rows();
+                // The last page may have been emptied, by a deletion or a new search.
+                if self.users.as_ref().unwrap().is_empty() && self.page > 0 {
+                    self.page = self.last_page();
+                    self.update_url(true);
+                    self.get_users();
+                }
+                Ok(true)
+            }
+            Msg::Retry => {
+                self.get_users();
+                Ok(true)
+            }
+            Msg::RouteChanged(route) => {
+                // The other pages are handled by the router.
+                let path = route.route.split(|c| c == '?' || c == '#').next();
+                if path != Some("/users") && path != Some("/") {
+                    return Ok(false);
+                }
+                let state = ListState::from_route(&route.route);
+                if state == self.list_state() {
+                    return Ok(false);
+                }
+                self.search = state.search;
+                self.sort = state.sort;
+                self.descending = state.descending;
+                self.page = state.page;
+                self.scroll_to_top();
+                self.get_users();
+                Ok(true)
+            }
+            Msg::TableScrolled => Ok(self.update_visible_rows()),
+            Msg::Change(change) => Ok(self.handle_change(change)),
+            Msg::ChangesStatus(status) => {
+                if self.changes.handle_status(status) {
+                    self.schedule_reload();
+                }
+                Ok(false)
+            }
+            Msg::ReconnectChanges => {
+                self.changes.connect();
+                Ok(false)
+            }
+            Msg::Reload => {
+                self.reload_timeout = None;
+                self.get_users();
+                Ok(false)
+            }
+            Msg::SearchChanged(search) => {
+                self.search = search;
+                self.page = 0;
+                self.update_url(true);
+                self.get_users();
+                Ok(true)
+            }
+            Msg::SortBy(column) => {
+                self.descending = column == self.sort && !self.descending;
+                self.sort = column;
+                self.page = 0;
+                self.update_url(false);
+                self.get_users();
+                Ok(true)
+            }
+            Msg::PageChanged(page) => {
+                self.page = page;
+                self.update_url(false);
+                self.get_users();
+                Ok(true)
+            }
+            Msg::PageSizeChanged(data) => {
+                if let ChangeData::Select(select) = data {
+                    if let Ok(size) = select.value().parse() {
+                        self.page_size = size;
+                        self.page = 0;
+                        self.update_url(true);
+                        if let Some(storage) = self.storage.as_mut() {
+                            let size: Result<String> = Ok(self.page_size.to_string());
+                            storage.store(PAGE_SIZE_KEY, size);
+                        }
+                        self.get_users();
+                    }
+                }
                 Ok(true)
             }
             Msg::OnError(e) => Err(e),
-            Msg::OnUserDeleted(user_id) => {
+            Msg::ToggleSelected(user_id) => {
+                if !self.selected.remove(&user_id) {
+                    self.selected.insert(user_id);
+                }
+                Ok(true)
+            }
+            Msg::TogglePageSelected => {
+                let page_ids = self.users.iter().flatten().map(|u| u.id.clone());
+                if self.is_page_selected() {
+                    for user_id in page_ids {
+                        self.selected.remove(&user_id);
+                    }
+                } else {
+                    self.selected.extend(page_ids);
+                }
+                Ok(true)
+            }
+            Msg::BulkActionDone => {
+                self.selected.clear();
+                self.get_users();
+                Ok(true)
+            }
+            Msg::OnUserDeleted(_) => {
+                // Reload the page, to fill the hole with the next user.
+                self.get_users();
+                Ok(false)
+            }
+            Msg::OnUserToggled((user_id, enabled)) => {
+                debug_assert!(self.users.is_some());
+                for user in self.users.as_mut().unwrap() {
+                    if user.id == user_id {
+                        user.enabled = enabled;
+                    }
+                }
+                Ok(true)
+            }
+            Msg::OnUserUnlocked(user_id) => {
+                debug_assert!(self.users.is_some());
+                for user in self.users.as_mut().unwrap() {
+                    if user.id == user_id {
+                        user.locked_until = None;
+                    }
+                }
+                Ok(true)
+            }
+            Msg::OnUserRenewed(user_id) => {
                 debug_assert!(self.users.is_some());
-                self.users.as_mut().unwrap().retain(|u| u.id != user_id);
+                for user in self.users.as_mut().unwrap() {
+                    if user.id == user_id {
+                        user.valid_until = None;
+                    }
+                }
                 Ok(true)
             }
         }
@@ -111,56 +613,239 @@ impl UserTable {
 
     fn view_users(&self) -> Html {
         let make_table = |users: &Vec<User>| {
+            let virtualized = users.len() > VIRTUALIZATION_THRESHOLD;
+            // The stripes would move with the first rendered row.
+            let (container_class, table_class) = if virtualized {
+                ("table-responsive virtual-table", "table")
+            } else {
+                ("table-responsive", "table table-striped")
+            };
             html! {
-                <div class="table-responsive">
-                  <table class="table table-striped">
+                <div
+                  class=container_class
+                  ref=self.table_ref.clone()
+                  onscroll=self.link.callback(|_| Msg::TableScrolled)>
+                  <table class=table_class>
                     <thead>
                       <tr>
-                        <th>{"User ID"}</th>
-                        <th>{"Email"}</th>
-                        <th>{"Display name"}</th>
+                        {if self.props.read_only { html! {} } else { html! {
+                          <th>
+                            <input
+                              type="checkbox"
+                              class="form-check-input"
+                              title="Select the users of the page"
+                              checked=self.is_page_selected()
+                              onchange=self.link.callback(|_| Msg::TogglePageSelected) />
+                          </th>
+                        } } }
+                        {self.view_sortable_header("User ID", Column::UserId)}
+                        {self.view_sortable_header("Email", Column::Email)}
+                        {self.view_sortable_header("Display name", Column::DisplayName)}
                         <th>{"First name"}</th>
                         <th>{"Last name"}</th>
-                        <th>{"Creation date"}</th>
-                        <th>{"Delete"}</th>
+                        {self.view_sortable_header("Creation date", Column::CreationDate)}
+                        <th>{"Enabled"}</th>
+                        { if self.props.read_only { html! {} } else { html! { <th>{"Delete"}</th> } } }
                       </tr>
                     </thead>
                     <tbody>
-                      {users.iter().map(|u| self.view_user(u)).collect::<Vec<_>>()}
+                      {if virtualized {
+                          self.view_virtualized_rows(users)
+                      } else {
+                          users.iter().map(|u| self.view_user(u)).collect::<Html>()
+                      }}
                     </tbody>
                   </table>
                 </div>
             }
         };
         match &self.users {
-            None => html! {{"Loading..."}},
+            None if self.error.is_some() => html! {},
+            None => loading::view_skeleton_table(8),
+            Some(users) if users.is_empty() && self.search.is_empty() => {
+                loading::view_empty("No users.")
+            }
+            Some(users) if users.is_empty() => loading::view_empty("No user matches the search."),
             Some(users) => make_table(users),
         }
     }
 
+    /// The rows in view, between empty rows with the height of the others.
+    fn view_virtualized_rows(&self, users: &[User]) -> Html {
+        let columns = if self.props.read_only { 8 } else { 10 };
+        // Always there, to keep the keys of the rows stable.
+        let spacer = |key: &str, rows: usize| {
+            html! {
+              <tr key=key.to_string() class="virtual-spacer" aria-hidden="true">
+                <td
+                  colspan=columns.to_string()
+                  style=format!("height: {}px", rows as i32 * ROW_HEIGHT)></td>
+              </tr>
+            }
+        };
+        let rows = self.visible_rows.start.min(users.len())..self.visible_rows.end.min(users.len());
+        std::iter::once(spacer("spacer-top", rows.start))
+            .chain(users[rows.clone()].iter().map(|u| self.view_user(u)))
+            .chain(std::iter::once(spacer(
+                "spacer-bottom",
+                users.len() - rows.end,
+            )))
+            .collect()
+    }
+
+    /// A column header that sorts the users by the column, or reverses the order if it already
+    /// does.
+    fn view_sortable_header(&self, name: &str, column: Column) -> Html {
+        let arrow = match (self.sort == column, self.descending) {
+            (false, _) => "",
+            (true, false) => " \u{25b2}",
+            (true, true) => " \u{25bc}",
+        };
+        html! {
+          <th
+            style="cursor: pointer"
+            title="Sort by this column"
+            onclick=self.link.callback(move |_| Msg::SortBy(column))>
+            {name}{arrow}
+          </th>
+        }
+    }
+
+    fn last_page(&self) -> i64 {
+        ((self.user_count - 1) / self.page_size).max(0)
+    }
+
+    /// The range of users shown, the buttons to change the page, and the page size.
+    fn view_pagination(&self) -> Html {
+        if self.users.is_none() {
+            return html! {};
+        }
+        let first = (self.page * self.page_size + 1).min(self.user_count);
+        let last = ((self.page + 1) * self.page_size).min(self.user_count);
+        let page = self.page;
+        html! {
+          <div class="d-flex align-items-center justify-content-between">
+            <span>{format!("Users {}-{} of {}", first, last, self.user_count)}</span>
+            <div class="d-flex align-items-center">
+              <button
+                class="btn btn-secondary"
+                disabled=page == 0
+                onclick=self.link.callback(move |_| Msg::PageChanged(page - 1))>
+                {"Previous"}
+              </button>
+              <button
+                class="btn btn-secondary ms-1"
+                disabled=page >= self.last_page()
+                onclick=self.link.callback(move |_| Msg::PageChanged(page + 1))>
+                {"Next"}
+              </button>
+              <select
+                class="form-select ms-3"
+                title="Users per page"
+                onchange=self.link.callback(Msg::PageSizeChanged)>
+                {
+                  PAGE_SIZES.iter().map(|size| html! {
+                    <option value=size.to_string() selected=*size == self.page_size>
+                      {format!("{} per page", size)}
+                    </option>
+                  }).collect::<Vec<_>>()
+                }
+              </select>
+            </div>
+          </div>
+        }
+    }
+
+    fn is_page_selected(&self) -> bool {
+        match &self.users {
+            Some(users) if !users.is_empty() => users.iter().all(|u| self.selected.contains(&u.id)),
+            _ => false,
+        }
+    }
+
     fn view_user(&self, user: &User) -> Html {
+        let user_id = user.id.clone();
         html! {
           <tr key=user.id.clone()>
-              <td><Link route=AppRoute::UserDetails(user.id.clone())>{&user.id}</Link></td>
+              {if self.props.read_only { html! {} } else { html! {
+                <td>
+                  <input
+                    type="checkbox"
+                    class="form-check-input"
+                    checked=self.selected.contains(&user.id)
+                    onchange=self.link.callback(move |_| Msg::ToggleSelected(user_id.clone())) />
+                </td>
+              } } }
+              <td>
+                <Link route=AppRoute::UserDetails(user.id.clone())>{&user.id}</Link>
+                {user_status::view_status_badges(
+                  user.enabled,
+                  user.locked_until.as_ref(),
+                  user.valid_until.as_ref(),
+                )}
+              </td>
               <td>{&user.email}</td>
               <td>{&user.display_name}</td>
               <td>{&user.first_name}</td>
               <td>{&user.last_name}</td>
-              <td>{&user.creation_date.date().naive_local()}</td>
-              <td>
-                <DeleteUser
-                  username=user.id.clone()
-                  on_user_deleted=self.link.callback(Msg::OnUserDeleted)
-                  on_error=self.link.callback(Msg::OnError)/>
+              <td title=date::format_relative(&user.creation_date)>
+                {date::format_local_date(&user.creation_date)}
               </td>
+              {self.view_user_actions(user)}
           </tr>
         }
     }
 
+    /// The "Enabled" and "Delete" columns: buttons, unless the table is read-only.
+    fn view_user_actions(&self, user: &User) -> Html {
+        if self.props.read_only {
+            return html! {
+              <td>{if user.enabled { "Yes" } else { "No" }}</td>
+            };
+        }
+        html! {
+          <>
+            <td>
+              <ToggleUserEnabled
+                username=user.id.clone()
+                enabled=user.enabled
+                on_user_toggled=self.link.callback(Msg::OnUserToggled)
+                on_error=self.link.callback(Msg::OnError)/>
+              {
+                match &user.locked_until {
+                  Some(locked_until) => html! {
+                    <UnlockUser
+                      username=user.id.clone()
+                      locked_until=*locked_until
+                      on_user_unlocked=self.link.callback(Msg::OnUserUnlocked)
+                      on_error=self.link.callback(Msg::OnError)/>
+                  },
+                  None => html! {},
+                }
+              }
+              {if user_status::is_expired(user.valid_until.as_ref()) { html! {
+                <RenewUser
+                  username=user.id.clone()
+                  on_user_renewed=self.link.callback(Msg::OnUserRenewed)
+                  on_error=self.link.callback(Msg::OnError)/>
+              } } else { html! {} } }
+            </td>
+            <td>
+              <DeleteUser
+                username=user.id.clone()
+                on_user_deleted=self.link.callback(Msg::OnUserDeleted)
+                on_error=self.link.callback(Msg::OnError)/>
+            </td>
+          </>
+        }
+    }
+
     fn view_errors(&self) -> Html {
-        match &self.error {
-            None => html! {},
-            Some(e) => html! {<div>{"Error: "}{e.to_string()}</div>},
+        match (&self.error, &self.users) {
+            (None, _) => html! {},
+            // The list could not be loaded at all.
+            (Some(e), None) => error_page::view_load_error(e, self.link.callback(|_| Msg::Retry)),
+            (Some(e), Some(_)) => loading::view_error(e),
         }
     }
 }
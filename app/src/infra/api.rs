@@ -1,7 +1,8 @@
-use super::cookies::set_cookie;
+use super::{branding, cookies::set_cookie, oidc};
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use graphql_client::GraphQLQuery;
-use lldap_auth::{login, registration, JWTClaims};
+use lldap_auth::{login, proof_of_work, registration, InviteClaims, JWTClaims};
 
 use yew::callback::Callback;
 use yew::format::Json;
@@ -23,6 +24,93 @@ fn get_claims_from_jwt(jwt: &str) -> Result<JWTClaims> {
     Ok(token.claims().clone())
 }
 
+/// The claims of an invite link, to show who it's for. The server checks the signature.
+pub fn get_claims_from_invite(token: &str) -> Result<InviteClaims> {
+    use jwt::*;
+    let token = Token::<header::Header, InviteClaims, token::Unverified>::parse_unverified(token)?;
+    Ok(token.claims().clone())
+}
+
+/// The logged-in user, from the JWT returned on login.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoginInfo {
+    pub user_id: String,
+    pub is_admin: bool,
+    /// Whether the user can browse all the users and groups, without modifying them.
+    pub is_read_only: bool,
+    /// When the JWT expires: the session has to be refreshed before that.
+    pub expiry: DateTime<Utc>,
+}
+
+/// A search of the LDAP query tester.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdapSearchRequest {
+    pub base_dn: String,
+    pub filter: String,
+    /// "base", "one" or "sub".
+    pub scope: String,
+    /// All the attributes when empty.
+    pub attributes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct LdapSearchAttribute {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct LdapSearchEntry {
+    pub dn: String,
+    pub attributes: Vec<LdapSearchAttribute>,
+}
+
+/// The entries returned by the LDAP server, as a client would get them.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdapSearchResponse {
+    pub entries: Vec<LdapSearchEntry>,
+    /// Whether the server dropped entries, past the first 500.
+    pub truncated: bool,
+    /// The LDAP result code, e.g. "Success".
+    pub result_code: String,
+    pub message: String,
+}
+
+/// The groups whose members can see all the users and groups, but not modify them: the auditors
+/// and the read-only viewers.
+const READ_ONLY_GROUPS: [&str; 2] = ["lldap_auditor", "lldap_readonly"];
+
+/// Parse the JWT returned on login, and store the user info in cookies.
+fn parse_login_token(data: String) -> Result<LoginInfo> {
+    let jwt_claims = get_claims_from_jwt(&data).context("Could not parse response")?;
+    let is_admin = jwt_claims.groups.contains("lldap_admin");
+    let is_read_only = !is_admin
+        && READ_ONLY_GROUPS
+            .iter()
+            .any(|&g| jwt_claims.groups.contains(g));
+    set_cookie("user_id", &jwt_claims.user, &jwt_claims.exp)
+        .map(|_| set_cookie("is_admin", &is_admin.to_string(), &jwt_claims.exp))
+        .map(|_| set_cookie("is_read_only", &is_read_only.to_string(), &jwt_claims.exp))
+        .map(|_| LoginInfo {
+            user_id: jwt_claims.user.clone(),
+            is_admin,
+            is_read_only,
+            expiry: jwt_claims.exp,
+        })
+        .context("Error clearing cookie")
+}
+
+/// Header containing the schema version of the server, sent with every response.
+const VERSION_HEADER: &str = "X-LLDAP-Schema-Version";
+
+/// Whether the server has another schema than the one this app was built for, e.g. after an
+/// upgrade.
+pub fn is_outdated(schema_version: i64) -> bool {
+    schema_version != i64::from(lldap_auth::SCHEMA_VERSION)
+}
+
 fn create_handler<Resp, CallbackResult, F>(
     callback: Callback<Result<CallbackResult>>,
     handler: F,
@@ -33,10 +121,25 @@ where
 {
     Callback::once(move |response: Response<Result<Resp>>| {
         let (meta, maybe_data) = response.into_parts();
+        let outdated = meta
+            .headers
+            .get(VERSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .map(is_outdated)
+            .unwrap_or(false);
         let message = maybe_data
             .context("Could not reach server")
             .and_then(|data| handler(meta.status, data));
-        callback.emit(message)
+        if outdated {
+            callback.emit(
+                message.context(
+                    "The server was upgraded, please hard-refresh the page (Ctrl+Shift+R)",
+                ),
+            )
+        } else {
+            callback.emit(message)
+        }
     })
 }
 
@@ -70,7 +173,25 @@ where
     RB: Into<RequestBody<Req>>,
     Req: Into<yew::format::Text>,
 {
-    let request = {
+    call_server_with_proof_of_work(url, request, None, callback, error_message, parse_response)
+}
+
+/// Same as `call_server`, with the solution of the proof of work asked before the logins.
+fn call_server_with_proof_of_work<Req, CallbackResult, F, RB>(
+    url: &str,
+    request: RB,
+    proof_of_work: Option<String>,
+    callback: Callback<Result<CallbackResult>>,
+    error_message: &'static str,
+    parse_response: F,
+) -> Result<FetchTask>
+where
+    F: Fn(String) -> Result<CallbackResult> + 'static,
+    CallbackResult: 'static,
+    RB: Into<RequestBody<Req>>,
+    Req: Into<yew::format::Text>,
+{
+    let mut builder = {
         // If the request type is empty (if the size is 0), it's a get.
         if std::mem::size_of::<RB>() == 0 {
             Request::get(url)
@@ -78,8 +199,11 @@ where
             Request::post(url)
         }
     }
-    .header("Content-Type", "application/json")
-    .body(request.into().0)?;
+    .header("Content-Type", "application/json");
+    if let Some(proof_of_work) = proof_of_work {
+        builder = builder.header(proof_of_work::HEADER, proof_of_work);
+    }
+    let request = builder.body(request.into().0)?;
     let handler = create_handler(callback, move |status: http::StatusCode, data: String| {
         if status.is_success() {
             parse_response(data)
@@ -162,40 +286,91 @@ impl HostService {
         )
     }
 
+    /// The name, logo and color of the product, for the login page as for the others.
+    pub fn get_branding(callback: Callback<Result<branding::Branding>>) -> Result<FetchTask> {
+        call_server_json_with_error_message(
+            "/api/branding",
+            yew::format::Nothing,
+            callback,
+            "Could not get the branding: ",
+        )
+    }
+
+    /// The proof of work to solve before logging in, if the server asks for one.
+    pub fn get_proof_of_work_challenge(
+        callback: Callback<Result<proof_of_work::Challenge>>,
+    ) -> Result<FetchTask> {
+        call_server_json_with_error_message(
+            "/auth/proof_of_work",
+            yew::format::Nothing,
+            callback,
+            "Could not get the proof of work challenge: ",
+        )
+    }
+
+    /// Starts the login of a logged in user, e.g. to check their old password: no proof of work
+    /// is needed.
     pub fn login_start(
         request: login::ClientLoginStartRequest,
         callback: Callback<Result<Box<login::ServerLoginStartResponse>>>,
     ) -> Result<FetchTask> {
-        call_server_json_with_error_message(
+        Self::login_start_with_proof_of_work(request, None, callback)
+    }
+
+    pub fn login_start_with_proof_of_work(
+        request: login::ClientLoginStartRequest,
+        proof_of_work: Option<String>,
+        callback: Callback<Result<Box<login::ServerLoginStartResponse>>>,
+    ) -> Result<FetchTask> {
+        call_server_with_proof_of_work(
             "/auth/opaque/login/start",
             &request,
+            proof_of_work,
             callback,
             "Could not start authentication: ",
+            |data: String| serde_json::from_str(&data).context("Could not parse response"),
         )
     }
 
     pub fn login_finish(
         request: login::ClientLoginFinishRequest,
-        callback: Callback<Result<(String, bool)>>,
+        callback: Callback<Result<LoginInfo>>,
     ) -> Result<FetchTask> {
-        let set_cookies = |jwt_claims: JWTClaims| {
-            let is_admin = jwt_claims.groups.contains("lldap_admin");
-            set_cookie("user_id", &jwt_claims.user, &jwt_claims.exp)
-                .map(|_| set_cookie("is_admin", &is_admin.to_string(), &jwt_claims.exp))
-                .map(|_| (jwt_claims.user.clone(), is_admin))
-                .context("Error clearing cookie")
-        };
-        let parse_token = move |data: String| {
-            get_claims_from_jwt(&data)
-                .context("Could not parse response")
-                .and_then(set_cookies)
-        };
         call_server(
             "/auth/opaque/login/finish",
             &request,
             callback,
             "Could not finish authentication",
-            parse_token,
+            parse_login_token,
+        )
+    }
+
+    /// Log in by sending the password to the server, for the users whose password was imported
+    /// from another directory and doesn't work with OPAQUE yet.
+    pub fn login_with_password(
+        username: String,
+        password: String,
+        proof_of_work: Option<String>,
+        callback: Callback<Result<LoginInfo>>,
+    ) -> Result<FetchTask> {
+        call_server_with_proof_of_work(
+            "/auth",
+            &serde_json::json!({ "name": username, "password": password }),
+            proof_of_work,
+            callback,
+            "Could not log in",
+            parse_login_token,
+        )
+    }
+
+    /// Log in with the identity set by the authenticating reverse proxy, if the server trusts it.
+    pub fn login_with_trusted_header(callback: Callback<Result<LoginInfo>>) -> Result<FetchTask> {
+        call_server(
+            "/auth/trusted_header",
+            yew::format::Nothing,
+            callback,
+            "Could not log in with the trusted header",
+            parse_login_token,
         )
     }
 
@@ -223,6 +398,112 @@ impl HostService {
         )
     }
 
+    /// Creates an invite link for the user to set their password, and returns its token.
+    pub fn create_invite(
+        username: String,
+        callback: Callback<Result<String>>,
+    ) -> Result<FetchTask> {
+        call_server(
+            "/auth/invite",
+            &serde_json::json!({ "username": username }),
+            callback,
+            "Could not create the invite",
+            Ok,
+        )
+    }
+
+    /// Same as `register_start`, authorized by the invite token instead of the session.
+    pub fn invite_register_start(
+        token: &str,
+        request: registration::ClientRegistrationStartRequest,
+        callback: Callback<Result<Box<registration::ServerRegistrationStartResponse>>>,
+    ) -> Result<FetchTask> {
+        call_server_json_with_error_message(
+            &format!("/auth/invite/{}/register/start", token),
+            &request,
+            callback,
+            "Could not start registration: ",
+        )
+    }
+
+    /// The options to create a security key named `name`, to pass to the browser.
+    pub fn webauthn_register_start(
+        name: String,
+        callback: Callback<Result<serde_json::Value>>,
+    ) -> Result<FetchTask> {
+        call_server_json_with_error_message(
+            "/api/webauthn/register/start",
+            &serde_json::json!({ "name": name }),
+            callback,
+            "Could not start the registration of the security key",
+        )
+    }
+
+    /// Stores the security key created by the browser.
+    pub fn webauthn_register_finish(
+        credential: serde_json::Value,
+        callback: Callback<Result<()>>,
+    ) -> Result<FetchTask> {
+        call_server_empty_response_with_error_message(
+            "/api/webauthn/register/finish",
+            &credential,
+            callback,
+            "Could not register the security key",
+        )
+    }
+
+    /// Runs the search through the LDAP server, for the admins.
+    pub fn ldap_search(
+        request: &LdapSearchRequest,
+        callback: Callback<Result<LdapSearchResponse>>,
+    ) -> Result<FetchTask> {
+        call_server_json_with_error_message(
+            "/api/ldap/search",
+            request,
+            callback,
+            "Could not run the search",
+        )
+    }
+
+    /// The name of the OpenID Connect client, to show on the login and consent pages.
+    pub fn get_oidc_client(
+        client_id: &str,
+        callback: Callback<Result<oidc::OidcClient>>,
+    ) -> Result<FetchTask> {
+        let client_id: String = js_sys::encode_uri_component(client_id).into();
+        call_server_json_with_error_message(
+            &format!("/oidc/client?client_id={}", client_id),
+            yew::format::Nothing,
+            callback,
+            "Could not get the application: ",
+        )
+    }
+
+    /// Records that the user agreed to share the scopes with the OpenID Connect client.
+    pub fn oidc_consent(
+        client_id: &str,
+        scope: &str,
+        callback: Callback<Result<()>>,
+    ) -> Result<FetchTask> {
+        call_server_empty_response_with_error_message(
+            "/oidc/consent",
+            &serde_json::json!({ "client_id": client_id, "scope": scope }),
+            callback,
+            "Could not record the consent",
+        )
+    }
+
+    /// Gets a new JWT with the refresh token, to extend the session.
+    pub fn refresh(callback: Callback<Result<LoginInfo>>) -> Result<FetchTask> {
+        call_server(
+            "/auth/refresh",
+            yew::format::Nothing,
+            callback,
+            "Could not refresh the session",
+            parse_login_token,
+        )
+    }
+
     pub fn logout(callback: Callback<Result<()>>) -> Result<FetchTask> {
         call_server_empty_response_with_error_message(
             "/auth/logout",
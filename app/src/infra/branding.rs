@@ -0,0 +1,73 @@
+//! The branding configured on the server: the name of the product replaces "LLDAP" in the header
+//! and the titles of the pages, and the accent color is set as the `--lldap-accent` variable used
+//! by `style.css`.
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use wasm_bindgen::JsCast;
+use yew::{html, services::ConsoleService, Html};
+
+const DEFAULT_PRODUCT_NAME: &str = "LLDAP";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Branding {
+    pub product_name: String,
+    pub accent_color: Option<String>,
+    pub logo_url: Option<String>,
+    /// Plain text, shown on the login page.
+    #[serde(default)]
+    pub login_message: Option<String>,
+    /// Whether to show the login message above the other pages too.
+    #[serde(default)]
+    pub login_message_everywhere: bool,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            product_name: DEFAULT_PRODUCT_NAME.to_string(),
+            accent_color: None,
+            logo_url: None,
+            login_message: None,
+            login_message_everywhere: false,
+        }
+    }
+}
+
+thread_local! {
+    static PRODUCT_NAME: RefCell<String> = RefCell::new(DEFAULT_PRODUCT_NAME.to_string());
+}
+
+/// The name of the product, to end the titles of the pages.
+pub fn product_name() -> String {
+    PRODUCT_NAME.with(|name| name.borrow().clone())
+}
+
+/// The message of the operator, with its line breaks.
+pub fn view_login_message(message: &str) -> Html {
+    html! {
+      <div class="alert alert-secondary login-message" role="note">{message}</div>
+    }
+}
+
+/// Switches the page to the branding.
+pub fn apply_branding(branding: &Branding) {
+    PRODUCT_NAME.with(|name| *name.borrow_mut() = branding.product_name.clone());
+    let color = match &branding.accent_color {
+        Some(color) => color,
+        None => return,
+    };
+    let root = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.document_element())
+        .and_then(|e| e.dyn_into::<web_sys::HtmlElement>().ok());
+    match root {
+        Some(root) => {
+            if root.style().set_property("--lldap-accent", color).is_err() {
+                ConsoleService::error("Could not set the accent color");
+            }
+        }
+        None => ConsoleService::error("Could not get the document root"),
+    }
+}
@@ -0,0 +1,158 @@
+//! The changes of the users and groups, pushed by the server on the `/api/changes` WebSocket, for
+//! the lists to stay up to date when other admins change the entries.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::time::Duration;
+use yew::{
+    callback::Callback,
+    format::Text,
+    services::{
+        timeout::{TimeoutService, TimeoutTask},
+        websocket::{WebSocketService, WebSocketStatus, WebSocketTask},
+        ConsoleService,
+    },
+};
+
+/// The lists wait for the changes to settle before reloading, e.g. during a bulk deletion.
+pub const RELOAD_DELAY: Duration = Duration::from_millis(500);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    Add,
+    Delete,
+    Modify,
+    Rename,
+    /// Some changes were missed: everything should be reloaded.
+    Reset,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    User,
+    Group,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeEvent {
+    pub change_type: ChangeType,
+    /// None for the resets.
+    pub entry_type: Option<EntryType>,
+    /// The ID of the user, or the name of the group.
+    #[serde(default)]
+    pub id: String,
+    /// For the renames, the ID before.
+    pub previous_id: Option<String>,
+}
+
+impl ChangeEvent {
+    /// Whether the change is about the entries of the type, or may be.
+    pub fn concerns(&self, entry_type: EntryType) -> bool {
+        self.change_type == ChangeType::Reset || self.entry_type == Some(entry_type)
+    }
+}
+
+fn changes_url() -> Result<String> {
+    let location = web_sys::window()
+        .ok_or_else(|| anyhow!("Could not get window"))?
+        .location();
+    let protocol = match location.protocol() {
+        Ok(protocol) if protocol == "https:" => "wss:",
+        _ => "ws:",
+    };
+    let host = location
+        .host()
+        .map_err(|_| anyhow!("Could not get the host"))?;
+    Ok(format!("{}//{}/api/changes", protocol, host))
+}
+
+/// The subscription of a component to the changes, which reconnects when the connection is lost,
+/// less and less often if the server keeps refusing it.
+///
+/// The component forwards the statuses of the connection to `handle_status`, and calls `connect`
+/// when `on_reconnect` is called.
+pub struct ChangeSubscription {
+    on_change: Callback<ChangeEvent>,
+    on_status: Callback<WebSocketStatus>,
+    on_reconnect: Callback<()>,
+    task: Option<WebSocketTask>,
+    reconnect_timeout: Option<TimeoutTask>,
+    reconnect_delay: Duration,
+    /// Whether the connection was lost since the last time it was open.
+    disconnected: bool,
+}
+
+impl ChangeSubscription {
+    pub fn new(
+        on_change: Callback<ChangeEvent>,
+        on_status: Callback<WebSocketStatus>,
+        on_reconnect: Callback<()>,
+    ) -> Self {
+        let mut subscription = Self {
+            on_change,
+            on_status,
+            on_reconnect,
+            task: None,
+            reconnect_timeout: None,
+            reconnect_delay: MIN_RECONNECT_DELAY,
+            disconnected: false,
+        };
+        subscription.connect();
+        subscription
+    }
+
+    pub fn connect(&mut self) {
+        self.reconnect_timeout = None;
+        let on_change = self.on_change.clone();
+        let on_message = Callback::from(move |text: Text| {
+            match text.and_then(|text| {
+                serde_json::from_str::<ChangeEvent>(&text).context("Could not parse the change")
+            }) {
+                Ok(change) => on_change.emit(change),
+                Err(e) => ConsoleService::error(&e.to_string()),
+            }
+        });
+        self.task = changes_url()
+            .and_then(|url| {
+                WebSocketService::connect_text(&url, on_message, self.on_status.clone())
+                    .map_err(|e| anyhow!("Could not follow the changes: {}", e))
+            })
+            .map_err(|e| ConsoleService::error(&e.to_string()))
+            .ok();
+        if self.task.is_none() {
+            self.schedule_reconnection();
+        }
+    }
+
+    /// Returns whether the connection is back after being lost, and the changes since then should
+    /// be loaded.
+    pub fn handle_status(&mut self, status: WebSocketStatus) -> bool {
+        match status {
+            WebSocketStatus::Opened => {
+                self.reconnect_delay = MIN_RECONNECT_DELAY;
+                std::mem::replace(&mut self.disconnected, false)
+            }
+            WebSocketStatus::Closed | WebSocketStatus::Error => {
+                // The error is followed by the closing.
+                if self.task.take().is_some() {
+                    self.disconnected = true;
+                    self.schedule_reconnection();
+                }
+                false
+            }
+        }
+    }
+
+    fn schedule_reconnection(&mut self) {
+        self.reconnect_timeout = Some(TimeoutService::spawn(
+            self.reconnect_delay,
+            self.on_reconnect.clone(),
+        ));
+        self.reconnect_delay = (self.reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
@@ -0,0 +1,30 @@
+use anyhow::{anyhow, Result};
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlDocument, HtmlTextAreaElement};
+
+/// Copies the text to the clipboard, through a temporary text area: the asynchronous clipboard
+/// API is still unstable in `web_sys`.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.dyn_into::<HtmlDocument>().ok())
+        .ok_or_else(|| anyhow!("Could not get window document"))?;
+    let body = document
+        .body()
+        .ok_or_else(|| anyhow!("Could not get document body"))?;
+    let text_area = document
+        .create_element("textarea")
+        .ok()
+        .and_then(|e| e.dyn_into::<HtmlTextAreaElement>().ok())
+        .ok_or_else(|| anyhow!("Could not copy to the clipboard"))?;
+    text_area.set_value(text);
+    body.append_child(&text_area)
+        .map_err(|_| anyhow!("Could not copy to the clipboard"))?;
+    text_area.select();
+    let copied = document.exec_command("copy");
+    let _ = body.remove_child(&text_area);
+    match copied {
+        Ok(true) => Ok(()),
+        _ => Err(anyhow!("Could not copy to the clipboard")),
+    }
+}
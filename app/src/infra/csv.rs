@@ -0,0 +1,35 @@
+use super::download::download_file;
+use anyhow::Result;
+
+/// Lets spreadsheets detect the encoding, instead of guessing a legacy one.
+const UTF8_BOM: &str = "\u{feff}";
+
+/// Formats the rows as CSV, with CRLF line endings as per RFC 4180.
+pub fn to_csv<Row: AsRef<[String]>>(rows: &[Row]) -> String {
+    let mut csv = String::from(UTF8_BOM);
+    for row in rows {
+        let fields: Vec<String> = row.as_ref().iter().map(|f| quote(f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn quote(field: &str) -> String {
+    // A leading sign would make spreadsheets evaluate the field as a formula.
+    let field = if field.starts_with(&['=', '+', '-', '@'][..]) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Makes the browser download the CSV content as a file.
+pub fn download_csv(file_name: &str, content: &str) -> Result<()> {
+    download_file(file_name, content, "text/csv;charset=utf-8")
+}
@@ -0,0 +1,51 @@
+//! Display of the dates sent by the server, which are all in UTC, in the browser's timezone.
+use super::graphql::DateTimeUtc;
+use chrono::prelude::*;
+
+/// The date in the browser's timezone, e.g. "2021-10-15".
+pub fn format_local_date(date: &DateTimeUtc) -> String {
+    date.with_timezone(&Local).format("%Y-%m-%d").to_string()
+}
+
+/// The date and time in the browser's timezone, with the offset to remove the ambiguity, e.g.
+/// "2021-10-15 14:03 (UTC+02:00)".
+pub fn format_local_date_time(date: &DateTimeUtc) -> String {
+    date.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M (UTC%:z)")
+        .to_string()
+}
+
+/// The time of the day in the browser's timezone, e.g. "14:03".
+pub fn format_local_time(date: &DateTimeUtc) -> String {
+    date.with_timezone(&Local).format("%H:%M").to_string()
+}
+
+/// How long ago (or in how long) the date is, e.g. "3 days ago".
+pub fn format_relative(date: &DateTimeUtc) -> String {
+    let delta = Utc::now().signed_duration_since(*date);
+    let (is_past, seconds) = if delta.num_seconds() >= 0 {
+        (true, delta.num_seconds())
+    } else {
+        (false, -delta.num_seconds())
+    };
+    if seconds < 60 {
+        return "just now".to_string();
+    }
+    let (count, unit) = [
+        (365 * 24 * 3600, "year"),
+        (30 * 24 * 3600, "month"),
+        (24 * 3600, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ]
+    .iter()
+    .find(|(unit_seconds, _)| seconds >= *unit_seconds)
+    .map(|(unit_seconds, unit)| (seconds / unit_seconds, *unit))
+    .unwrap();
+    let plural = if count > 1 { "s" } else { "" };
+    if is_past {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
@@ -0,0 +1,24 @@
+use anyhow::{anyhow, Result};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+/// Makes the browser download the content as a file, of the given MIME type.
+pub fn download_file(file_name: &str, content: &str, content_type: &str) -> Result<()> {
+    let parts = js_sys::Array::of1(&JsValue::from_str(content));
+    let blob =
+        Blob::new_with_str_sequence_and_options(&parts, BlobPropertyBag::new().type_(content_type))
+            .map_err(|_| anyhow!("Could not create the file"))?;
+    let link = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.create_element("a").ok())
+        .and_then(|a| a.dyn_into::<HtmlAnchorElement>().ok())
+        .ok_or_else(|| anyhow!("Could not get window document"))?;
+    // The URL is kept until the page is closed: revoking it right away can cancel the download
+    // in some browsers.
+    let url = Url::create_object_url_with_blob(&blob)
+        .map_err(|_| anyhow!("Could not create the file"))?;
+    link.set_href(&url);
+    link.set_download(file_name);
+    link.click();
+    Ok(())
+}
@@ -1,4 +1,16 @@
 pub mod api;
+pub mod branding;
+pub mod change_events;
+pub mod clipboard;
 pub mod cookies;
+pub mod csv;
+pub mod date;
+pub mod download;
 pub mod graphql;
 pub mod modal;
+pub mod oidc;
+pub mod password;
+pub mod shortcuts;
+pub mod theme;
+pub mod unsaved_changes;
+pub mod webauthn;
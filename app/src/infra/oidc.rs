@@ -0,0 +1,63 @@
+//! The login page can be opened by the OpenID Connect provider of the server, for another
+//! application to log the user in: once logged in and once they agreed to share their
+//! information with the application, the user goes back to `/oidc/authorize`.
+
+use anyhow::{anyhow, Result};
+
+/// The client application, as registered on the server.
+#[derive(Clone, PartialEq, Debug, serde::Deserialize)]
+pub struct OidcClient {
+    pub client_id: String,
+    pub display_name: String,
+}
+
+/// The authorization request of a client application, from the query of the login page.
+#[derive(Clone, PartialEq, Debug)]
+pub struct OidcRequest {
+    /// The name of the client application, once the server gave it: the link to the login page
+    /// can be written by anyone, it doesn't have the name.
+    pub client: Option<String>,
+    pub client_id: String,
+    /// The scopes requested by the client, separated by spaces.
+    pub scope: String,
+    /// The original authorization request, to resume once logged in.
+    continue_url: String,
+}
+
+impl OidcRequest {
+    pub fn from_location() -> Option<Self> {
+        let search = web_sys::window()?.location().search().ok()?;
+        let params = web_sys::UrlSearchParams::new_with_str(&search).ok()?;
+        let continue_url = params.get("oidc_continue")?;
+        // Only go back to the provider, not to any URL given in the link.
+        let request = continue_url.strip_prefix("/oidc/authorize?")?;
+        let request = web_sys::UrlSearchParams::new_with_str(request).ok()?;
+        Some(Self {
+            client: None,
+            client_id: request.get("client_id")?,
+            scope: request.get("scope").unwrap_or_default(),
+            continue_url,
+        })
+    }
+
+    pub fn client_name(&self) -> &str {
+        self.client.as_deref().unwrap_or("an application")
+    }
+
+    /// The scopes shared with the client besides the user ID, to show on the consent page.
+    pub fn shared_scopes(&self) -> Vec<&str> {
+        self.scope
+            .split_whitespace()
+            .filter(|s| *s != "openid")
+            .collect()
+    }
+
+    /// Sends the user back to the provider, which redirects them to the client application.
+    pub fn resume(&self) -> Result<()> {
+        web_sys::window()
+            .ok_or_else(|| anyhow!("Could not get window"))?
+            .location()
+            .set_href(&self.continue_url)
+            .map_err(|_| anyhow!("Could not go back to the authorization request"))
+    }
+}
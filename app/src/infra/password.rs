@@ -0,0 +1,14 @@
+use rand::{rngs::OsRng, seq::SliceRandom};
+
+/// The characters of the generated passwords, without the ones easily mistaken for each other.
+const PASSWORD_CHARACTERS: &[u8] =
+    b"abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789!#$%&*+-=?@_";
+const GENERATED_PASSWORD_LENGTH: usize = 20;
+
+/// A random password, about 120 bits strong.
+pub fn generate_password() -> String {
+    let mut rng = OsRng;
+    (0..GENERATED_PASSWORD_LENGTH)
+        .map(|_| *PASSWORD_CHARACTERS.choose(&mut rng).unwrap() as char)
+        .collect()
+}
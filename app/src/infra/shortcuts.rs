@@ -0,0 +1,138 @@
+//! The keyboard shortcuts of the app, handled in one place: the agent listens to the keys on the
+//! whole page, and sends the shortcuts to the components bridged to it.
+//!
+//! - Ctrl+K (or Cmd+K): opens the command palette, to jump to a user, a group or a page.
+//! - `/`: focuses the search of the page.
+//! - `n`: creates a user, and Shift+N a group.
+//!
+//! The shortcuts without modifier are ignored while typing in a field.
+
+use std::collections::HashSet;
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{Element, KeyboardEvent};
+use yew::{
+    agent::{Agent, AgentLink, Context, HandlerId},
+    services::ConsoleService,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shortcut {
+    CommandPalette,
+    FocusSearch,
+    NewUser,
+    NewGroup,
+}
+
+/// Whether the key is typed in a field, rather than meant for the app.
+fn is_typing(event: &KeyboardEvent) -> bool {
+    event
+        .target()
+        .and_then(|target| target.dyn_into::<Element>().ok())
+        .map(|element| {
+            matches!(
+                element.tag_name().to_ascii_lowercase().as_str(),
+                "input" | "textarea" | "select"
+            ) || element
+                .closest("[contenteditable]")
+                .ok()
+                .flatten()
+                .is_some()
+        })
+        .unwrap_or(false)
+}
+
+fn to_shortcut(event: &KeyboardEvent) -> Option<Shortcut> {
+    let key = event.key();
+    if (event.ctrl_key() || event.meta_key()) && key.eq_ignore_ascii_case("k") {
+        return Some(Shortcut::CommandPalette);
+    }
+    if event.ctrl_key() || event.meta_key() || event.alt_key() || is_typing(event) {
+        return None;
+    }
+    match key.as_str() {
+        "/" => Some(Shortcut::FocusSearch),
+        "n" => Some(Shortcut::NewUser),
+        "N" => Some(Shortcut::NewGroup),
+        _ => None,
+    }
+}
+
+pub struct ShortcutAgent {
+    link: AgentLink<Self>,
+    subscribers: HashSet<HandlerId>,
+    /// The listener of the page, removed with the last subscriber.
+    listener: Option<Closure<dyn FnMut(KeyboardEvent)>>,
+}
+
+impl ShortcutAgent {
+    fn add_listener(&mut self) {
+        let window = match web_sys::window() {
+            Some(window) => window,
+            None => return ConsoleService::error("Could not get window"),
+        };
+        let on_shortcut = self.link.callback(|shortcut: Shortcut| shortcut);
+        let listener = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if let Some(shortcut) = to_shortcut(&event) {
+                // Instead of the search of the browser, for instance.
+                event.prevent_default();
+                on_shortcut.emit(shortcut);
+            }
+        }) as Box<dyn FnMut(_)>);
+        if window
+            .add_event_listener_with_callback("keydown", listener.as_ref().unchecked_ref())
+            .is_err()
+        {
+            return ConsoleService::error("Could not listen to the keyboard shortcuts");
+        }
+        self.listener = Some(listener);
+    }
+
+    fn remove_listener(&mut self) {
+        if let (Some(window), Some(listener)) = (web_sys::window(), self.listener.take()) {
+            let _ = window
+                .remove_event_listener_with_callback("keydown", listener.as_ref().unchecked_ref());
+        }
+    }
+}
+
+impl Agent for ShortcutAgent {
+    /// A single instance, shared by the components of the page.
+    type Reach = Context<Self>;
+    type Message = Shortcut;
+    type Input = ();
+    type Output = Shortcut;
+
+    fn create(link: AgentLink<Self>) -> Self {
+        Self {
+            link,
+            subscribers: HashSet::new(),
+            listener: None,
+        }
+    }
+
+    fn update(&mut self, shortcut: Self::Message) {
+        for subscriber in &self.subscribers {
+            self.link.respond(*subscriber, shortcut);
+        }
+    }
+
+    fn connected(&mut self, id: HandlerId) {
+        if self.subscribers.is_empty() {
+            self.add_listener();
+        }
+        self.subscribers.insert(id);
+    }
+
+    fn handle_input(&mut self, _: Self::Input, _: HandlerId) {}
+
+    fn disconnected(&mut self, id: HandlerId) {
+        self.subscribers.remove(&id);
+        if self.subscribers.is_empty() {
+            self.remove_listener();
+        }
+    }
+
+    fn destroy(&mut self) {
+        self.remove_listener();
+    }
+}
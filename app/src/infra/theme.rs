@@ -0,0 +1,91 @@
+//! The light and dark themes. The theme chosen with the toggle is kept in the local storage;
+//! until one is chosen, the theme follows the `prefers-color-scheme` of the browser.
+//!
+//! The theme is set as the `data-theme` attribute of the root element, and `style.css` derives
+//! all the colors from it.
+
+use yew::services::{
+    storage::{Area, StorageService},
+    ConsoleService,
+};
+
+/// The local storage key of the chosen theme.
+const THEME_KEY: &str = "lldap.theme";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn as_str(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::Light,
+        }
+    }
+}
+
+/// The theme chosen by the user, or else the one of the browser.
+pub fn get_theme() -> Theme {
+    StorageService::new(Area::Local)
+        .ok()
+        .and_then(|storage| storage.restore::<anyhow::Result<String>>(THEME_KEY).ok())
+        .and_then(|name| Theme::parse(&name))
+        .unwrap_or_else(get_browser_theme)
+}
+
+fn get_browser_theme() -> Theme {
+    let prefers_dark = web_sys::window()
+        .and_then(|w| w.match_media("(prefers-color-scheme: dark)").ok().flatten())
+        .map(|query| query.matches())
+        .unwrap_or(false);
+    if prefers_dark {
+        Theme::Dark
+    } else {
+        Theme::Light
+    }
+}
+
+/// Switches the page to the theme.
+pub fn apply_theme(theme: Theme) {
+    let root = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.document_element());
+    match root {
+        Some(root) => {
+            if root.set_attribute("data-theme", theme.as_str()).is_err() {
+                ConsoleService::error("Could not set the theme");
+            }
+        }
+        None => ConsoleService::error("Could not get the document root"),
+    }
+}
+
+/// Switches the page to the theme, and remembers it for the next visits.
+pub fn set_theme(theme: Theme) {
+    apply_theme(theme);
+    match StorageService::new(Area::Local) {
+        Ok(mut storage) => {
+            let name: anyhow::Result<String> = Ok(theme.as_str().to_string());
+            storage.store(THEME_KEY, name)
+        }
+        Err(e) => ConsoleService::error(e),
+    }
+}
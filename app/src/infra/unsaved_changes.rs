@@ -0,0 +1,100 @@
+//! Keeps track of the forms with unsaved changes, and asks for a confirmation before leaving the
+//! page while there are some: before following a link of the app, or closing the tab.
+//!
+//! The forms report their state with `set_dirty`, and clear it when they are destroyed.
+
+use std::{cell::RefCell, collections::HashSet};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BeforeUnloadEvent, Element, MouseEvent};
+use yew::services::ConsoleService;
+
+const CONFIRMATION_MESSAGE: &str = "You have unsaved changes. Leave the page anyway?";
+
+thread_local! {
+    /// The keys of the forms with unsaved changes.
+    static DIRTY_FORMS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static LISTENERS_INSTALLED: RefCell<bool> = RefCell::new(false);
+}
+
+/// Records whether the form identified by `key` has unsaved changes.
+pub fn set_dirty(key: &str, dirty: bool) {
+    DIRTY_FORMS.with(|forms| {
+        let mut forms = forms.borrow_mut();
+        if dirty {
+            forms.insert(key.to_string());
+        } else {
+            forms.remove(key);
+        }
+    });
+    if dirty {
+        install_listeners();
+    }
+}
+
+pub fn has_unsaved_changes() -> bool {
+    DIRTY_FORMS.with(|forms| !forms.borrow().is_empty())
+}
+
+/// The browser shows its own message when closing the tab.
+fn on_before_unload(event: BeforeUnloadEvent) {
+    if has_unsaved_changes() {
+        event.prevent_default();
+        event.set_return_value(CONFIRMATION_MESSAGE);
+    }
+}
+
+/// Runs before the handlers of the links of the router, which change the route on click.
+fn on_click(event: MouseEvent) {
+    if !has_unsaved_changes() {
+        return;
+    }
+    let link = event
+        .target()
+        .and_then(|t| t.dyn_into::<Element>().ok())
+        .and_then(|e| e.closest("a[href]").ok().flatten());
+    let link = match link {
+        Some(link) => link,
+        None => return,
+    };
+    // The menus and the links opened elsewhere don't leave the page.
+    let href = link.get_attribute("href").unwrap_or_default();
+    if href.starts_with('#') || link.has_attribute("target") || link.has_attribute("download") {
+        return;
+    }
+    let confirmed = web_sys::window()
+        .and_then(|w| w.confirm_with_message(CONFIRMATION_MESSAGE).ok())
+        .unwrap_or(true);
+    if confirmed {
+        DIRTY_FORMS.with(|forms| forms.borrow_mut().clear());
+    } else {
+        event.prevent_default();
+        event.stop_propagation();
+    }
+}
+
+/// The listeners stay for the lifetime of the page, and do nothing without unsaved changes.
+fn install_listeners() {
+    if LISTENERS_INSTALLED.with(|installed| installed.replace(true)) {
+        return;
+    }
+    let window = match web_sys::window() {
+        Some(window) => window,
+        None => return ConsoleService::error("Could not get window"),
+    };
+    let before_unload = Closure::wrap(Box::new(on_before_unload) as Box<dyn FnMut(_)>);
+    let click = Closure::wrap(Box::new(on_click) as Box<dyn FnMut(_)>);
+    let installed = window
+        .add_event_listener_with_callback("beforeunload", before_unload.as_ref().unchecked_ref())
+        .and_then(|_| {
+            window.add_event_listener_with_callback_and_bool(
+                "click",
+                click.as_ref().unchecked_ref(),
+                true,
+            )
+        });
+    if installed.is_err() {
+        ConsoleService::error("Could not watch for unsaved changes");
+    }
+    before_unload.forget();
+    click.forget();
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use js_sys::{Array, ArrayBuffer, Object, Reflect, Uint8Array, JSON};
+use serde_json::{json, Value};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AuthenticatorAttestationResponse, CredentialCreationOptions, PublicKeyCredential};
+
+fn js_error(context: &str, error: JsValue) -> anyhow::Error {
+    let message = error
+        .dyn_ref::<js_sys::Error>()
+        .map(|e| String::from(e.message()))
+        .or_else(|| error.as_string())
+        .unwrap_or_else(|| format!("{:?}", error));
+    anyhow!("{}: {}", context, message)
+}
+
+fn get(object: &JsValue, key: &str) -> Result<JsValue> {
+    Reflect::get(object, &JsValue::from_str(key)).map_err(|e| js_error("Invalid options", e))
+}
+
+/// Replaces the base64url string at this key with its bytes, as the browser expects them.
+fn decode_field(object: &JsValue, key: &str) -> Result<()> {
+    let encoded = get(object, key)?
+        .as_string()
+        .ok_or_else(|| anyhow!("Missing `{}` in the options", key))?;
+    let bytes = base64::decode_config(&encoded, base64::URL_SAFE_NO_PAD)?;
+    Reflect::set(
+        object,
+        &JsValue::from_str(key),
+        &Uint8Array::from(bytes.as_slice()),
+    )
+    .map_err(|e| js_error("Invalid options", e))?;
+    Ok(())
+}
+
+fn encode_buffer(buffer: &ArrayBuffer) -> String {
+    base64::encode_config(Uint8Array::new(buffer).to_vec(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Asks the browser to create a credential on a security key, with the options returned by the
+/// server, and returns it in the JSON form expected by the server.
+///
+/// The user is prompted to touch their key: this only completes once they did, or cancelled.
+pub async fn create_credential(options: Value) -> Result<Value> {
+    let options = JSON::parse(&options.to_string()).map_err(|e| js_error("Invalid options", e))?;
+    let public_key = get(&options, "publicKey")?;
+    decode_field(&public_key, "challenge")?;
+    decode_field(&get(&public_key, "user")?, "id")?;
+    let excluded = get(&public_key, "excludeCredentials")?;
+    if let Some(excluded) = excluded.dyn_ref::<Array>() {
+        for credential in excluded.iter() {
+            decode_field(&credential, "id")?;
+        }
+    }
+    let options: CredentialCreationOptions = options.unchecked_into::<Object>().unchecked_into();
+    let promise = web_sys::window()
+        .ok_or_else(|| anyhow!("Could not get window"))?
+        .navigator()
+        .credentials()
+        .create_with_options(&options)
+        .map_err(|e| js_error("Security keys are not supported by this browser", e))?;
+    let credential: PublicKeyCredential = JsFuture::from(promise)
+        .await
+        .map_err(|e| js_error("The security key was not registered", e))?
+        .dyn_into()
+        .map_err(|_| anyhow!("The browser did not return a security key"))?;
+    let response: AuthenticatorAttestationResponse = credential
+        .response()
+        .dyn_into()
+        .map_err(|_| anyhow!("The browser did not return an attestation"))?;
+    Ok(json!({
+        "id": credential.id(),
+        "rawId": encode_buffer(&credential.raw_id()),
+        "type": credential.type_(),
+        "response": {
+            "attestationObject": encode_buffer(&response.attestation_object()),
+            "clientDataJSON": encode_buffer(&response.client_data_json()),
+        },
+    }))
+}
@@ -7,6 +7,8 @@ use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
 
 #[wasm_bindgen]
 pub fn run_app() -> Result<(), JsValue> {
+    // Before the first render, to avoid a flash of the wrong theme.
+    infra::theme::apply_theme(infra::theme::get_theme());
     yew::start_app::<components::app::App>();
 
     Ok(())
@@ -4,6 +4,13 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
 pub mod opaque;
+pub mod proof_of_work;
+
+/// Version of the GraphQL schema, to be bumped whenever the schema changes. Changes must stay
+/// compatible with the previous version (see `previous_schema.graphql`), so that a frontend that
+/// hasn't been reloaded yet keeps working against an updated server. The server sends it with
+/// every response, for the frontend to tell that it was built for another schema.
+pub const SCHEMA_VERSION: i32 = 2;
 
 /// The messages for the 3-step OPAQUE login process.
 pub mod login {
@@ -73,4 +80,19 @@ pub struct JWTClaims {
     pub iat: DateTime<Utc>,
     pub user: String,
     pub groups: HashSet<String>,
+    /// The `session_generation` of the user when the token was created: changing the password
+    /// invalidates the tokens created before.
+    #[serde(default)]
+    pub session_generation: i32,
+}
+
+/// The claims of an invite link, to let a new user set their password. It's signed like the
+/// JWTs, but it can't be used as one (and vice versa). Setting the password changes the
+/// `session_generation`, which invalidates the link.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub exp: DateTime<Utc>,
+    pub user: String,
+    pub invited_by: String,
+    pub session_generation: i32,
 }
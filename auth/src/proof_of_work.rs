@@ -0,0 +1,45 @@
+//! Proof of work asked before a login attempt, to slow down the brute-force attacks on the
+//! instances exposed to the internet: the browser has to find a nonce such that the SHA-256 hash
+//! of "<challenge>:<nonce>" starts with `difficulty` zero bits, which takes about
+//! 2^`difficulty` tries.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Header carrying the solution, as "<challenge>:<nonce>".
+pub const HEADER: &str = "X-LLDAP-Proof-Of-Work";
+
+/// A challenge from the server. A difficulty of 0 means that no proof is needed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+pub fn is_solution(challenge: &str, nonce: u64, difficulty: u32) -> bool {
+    leading_zero_bits(&Sha256::digest(
+        format!("{}:{}", challenge, nonce).as_bytes(),
+    )) >= difficulty
+}
+
+/// The value of the header for a solution.
+pub fn to_header(challenge: &str, nonce: u64) -> String {
+    format!("{}:{}", challenge, nonce)
+}
+
+/// Splits the value of the header into the challenge and the nonce.
+pub fn parse_header(value: &str) -> Option<(&str, u64)> {
+    let (challenge, nonce) = value.rsplit_once(':')?;
+    Some((challenge, nonce.parse().ok()?))
+}
@@ -0,0 +1,199 @@
+//! Validation of the custom attributes of the users, and conversion of their values between the
+//! form sent to the clients and the one stored in the database.
+
+use super::{
+    avatar::normalize_avatar,
+    error::*,
+    handler::{AttributeSchema, AttributeType},
+};
+
+/// The longest name of an attribute, in characters.
+const MAX_NAME_LENGTH: usize = 64;
+/// The most values of a list attribute for a user.
+pub const MAX_VALUES: usize = 100;
+/// The names of the built-in attributes, over LDAP and GraphQL, compared ignoring the case.
+const RESERVED_NAMES: &[&str] = &[
+    "avatar",
+    "cn",
+    "createtimestamp",
+    "creationdate",
+    "displayname",
+    "dn",
+    "email",
+    "enabled",
+    "entryuuid",
+    "firstname",
+    "givenname",
+    "id",
+    "jpegphoto",
+    "lastname",
+    "mail",
+    "memberof",
+    "objectclass",
+    "password",
+    "sn",
+    "uid",
+    "userpassword",
+];
+
+fn invalid(message: String) -> DomainError {
+    DomainError::InvalidRequest(message)
+}
+
+/// Checks that the name can be used as an LDAP attribute: a letter, then letters, digits and
+/// hyphens. The built-in names are reserved.
+pub fn validate_attribute_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let is_valid = name.len() <= MAX_NAME_LENGTH
+        && chars
+            .next()
+            .map(|c| c.is_ascii_alphabetic())
+            .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !is_valid {
+        return Err(invalid(format!(
+            "Invalid attribute name `{}`: it should start with a letter, followed by letters, \
+             digits or hyphens",
+            name
+        )));
+    }
+    if RESERVED_NAMES.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(invalid(format!(
+            "`{}` is the name of a built-in attribute",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the values against the schema of the attribute, and converts them to the stored form.
+pub fn parse_attribute_values(schema: &AttributeSchema, values: &[String]) -> Result<Vec<Vec<u8>>> {
+    if values.len() > 1 && !schema.is_list {
+        return Err(invalid(format!(
+            "The attribute `{}` has a single value",
+            schema.name
+        )));
+    }
+    if values.len() > MAX_VALUES {
+        return Err(invalid(format!(
+            "The attribute `{}` has at most {} values",
+            schema.name, MAX_VALUES
+        )));
+    }
+    values
+        .iter()
+        .map(|value| {
+            parse_attribute_value(schema.attribute_type, value).map_err(|e| {
+                invalid(format!(
+                    "Invalid value for the attribute `{}`: {}",
+                    schema.name, e
+                ))
+            })
+        })
+        .collect()
+}
+
+fn parse_attribute_value(attribute_type: AttributeType, value: &str) -> Result<Vec<u8>> {
+    match attribute_type {
+        AttributeType::String => Ok(value.as_bytes().to_vec()),
+        AttributeType::Integer => value
+            .trim()
+            .parse::<i64>()
+            .map(|n| n.to_string().into_bytes())
+            .map_err(|_| invalid(format!("`{}` is not an integer", value))),
+        AttributeType::DateTime => chrono::DateTime::parse_from_rfc3339(value.trim())
+            .map(|date| {
+                date.with_timezone(&chrono::Utc)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    .into_bytes()
+            })
+            .map_err(|_| invalid(format!("`{}` is not an RFC 3339 date", value))),
+        AttributeType::JpegPhoto => {
+            let data = base64::decode(value.trim())
+                .map_err(|e| invalid(format!("invalid base64: {}", e)))?;
+            normalize_avatar(&data)
+        }
+    }
+}
+
+/// The stored value, as sent to the clients.
+pub fn format_attribute_value(attribute_type: AttributeType, value: &[u8]) -> String {
+    match attribute_type {
+        AttributeType::JpegPhoto => base64::encode(value),
+        _ => String::from_utf8_lossy(value).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(attribute_type: AttributeType, is_list: bool) -> AttributeSchema {
+        AttributeSchema {
+            name: "attr".to_string(),
+            attribute_type,
+            is_list,
+            is_editable: false,
+        }
+    }
+
+    #[test]
+    fn test_validate_attribute_name() {
+        validate_attribute_name("employeeNumber").unwrap();
+        validate_attribute_name("x-badge-2").unwrap();
+        validate_attribute_name("").unwrap_err();
+        validate_attribute_name("2fa").unwrap_err();
+        validate_attribute_name("with space").unwrap_err();
+        validate_attribute_name("under_score").unwrap_err();
+        validate_attribute_name("GivenName").unwrap_err();
+        validate_attribute_name(&"a".repeat(65)).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_attribute_values() {
+        let values = |v: &[&str]| v.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            parse_attribute_values(&schema(AttributeType::Integer, false), &values(&[" 42 "]))
+                .unwrap(),
+            vec![b"42".to_vec()]
+        );
+        parse_attribute_values(&schema(AttributeType::Integer, false), &values(&["4.2"]))
+            .unwrap_err();
+        assert_eq!(
+            parse_attribute_values(
+                &schema(AttributeType::DateTime, false),
+                &values(&["2021-10-15T16:03:00+02:00"])
+            )
+            .unwrap(),
+            vec![b"2021-10-15T14:03:00Z".to_vec()]
+        );
+        parse_attribute_values(
+            &schema(AttributeType::DateTime, false),
+            &values(&["2021-10-15"]),
+        )
+        .unwrap_err();
+        parse_attribute_values(&schema(AttributeType::JpegPhoto, false), &values(&["AAAA"]))
+            .unwrap_err();
+        // Several values only for the lists.
+        parse_attribute_values(&schema(AttributeType::String, false), &values(&["a", "b"]))
+            .unwrap_err();
+        assert_eq!(
+            parse_attribute_values(&schema(AttributeType::String, true), &values(&["a", "b"]))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_format_attribute_value() {
+        assert_eq!(
+            format_attribute_value(AttributeType::String, b"hello"),
+            "hello"
+        );
+        assert_eq!(
+            format_attribute_value(AttributeType::JpegPhoto, &[0xff, 0xd8]),
+            "/9g="
+        );
+    }
+}
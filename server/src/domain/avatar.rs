@@ -0,0 +1,92 @@
+//! Validation of the user pictures. They are re-encoded to a bounded resolution, so that they stay
+//! small in the database and for the clients (some limit the size of the LDAP attributes).
+
+use super::error::*;
+use image::{imageops::FilterType, io::Reader, DynamicImage, GenericImageView, ImageFormat};
+use std::io::Cursor;
+
+/// Larger pictures are scaled down to fit in a square of this size.
+pub const MAX_AVATAR_DIMENSION: u32 = 512;
+/// Bigger uploads are rejected without decoding them.
+pub const MAX_UPLOAD_SIZE: usize = 10 * 1024 * 1024;
+/// Guards against the pictures that are small but take a lot of memory once decoded.
+const MAX_UPLOAD_DIMENSION: u32 = 10_000;
+const JPEG_QUALITY: u8 = 85;
+
+fn invalid(message: &str) -> DomainError {
+    DomainError::InvalidRequest(message.to_string())
+}
+
+/// Checks that the picture is a valid JPEG, and re-encodes it to fit in
+/// `MAX_AVATAR_DIMENSION`x`MAX_AVATAR_DIMENSION`. This also strips the metadata.
+pub fn normalize_avatar(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > MAX_UPLOAD_SIZE {
+        return Err(invalid("The avatar is too big"));
+    }
+    let reader = || Reader::with_format(Cursor::new(data), ImageFormat::Jpeg);
+    let (width, height) = reader()
+        .into_dimensions()
+        .map_err(|_| invalid("The avatar must be a JPEG image"))?;
+    if width > MAX_UPLOAD_DIMENSION || height > MAX_UPLOAD_DIMENSION {
+        return Err(invalid("The avatar resolution is too high"));
+    }
+    let image = reader()
+        .decode()
+        .map_err(|_| invalid("The avatar must be a JPEG image"))?;
+    let image = if width > MAX_AVATAR_DIMENSION || height > MAX_AVATAR_DIMENSION {
+        // Keeps the aspect ratio.
+        image.resize(
+            MAX_AVATAR_DIMENSION,
+            MAX_AVATAR_DIMENSION,
+            FilterType::Lanczos3,
+        )
+    } else {
+        image
+    };
+    let mut output = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut output, image::ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(|e| DomainError::InternalError(format!("Could not encode the avatar: {}", e)))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+pub(crate) fn make_test_jpeg(width: u32, height: u32) -> Vec<u8> {
+    let mut output = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::new(width, height))
+        .write_to(&mut output, image::ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dimensions(jpeg: &[u8]) -> (u32, u32) {
+        image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)
+            .unwrap()
+            .dimensions()
+    }
+
+    #[test]
+    fn test_normalize_avatar_scales_down() {
+        let avatar = normalize_avatar(&make_test_jpeg(1024, 768)).unwrap();
+        assert_eq!(dimensions(&avatar), (512, 384));
+        let avatar = normalize_avatar(&make_test_jpeg(256, 2048)).unwrap();
+        assert_eq!(dimensions(&avatar), (64, 512));
+    }
+
+    #[test]
+    fn test_normalize_avatar_keeps_small_pictures() {
+        let avatar = normalize_avatar(&make_test_jpeg(64, 32)).unwrap();
+        assert_eq!(dimensions(&avatar), (64, 32));
+    }
+
+    #[test]
+    fn test_normalize_avatar_rejects_invalid() {
+        normalize_avatar(b"GIF89a").unwrap_err();
+        normalize_avatar(&[0xff, 0xd8, 0xff, 0xe0, 0x42]).unwrap_err();
+        normalize_avatar(&vec![0; MAX_UPLOAD_SIZE + 1]).unwrap_err();
+    }
+}
@@ -5,6 +5,12 @@ use thiserror::Error;
 pub enum DomainError {
     #[error("Authentication error for `{0}`")]
     AuthenticationError(String),
+    /// The credentials are valid, but the user is disabled or their account expired.
+    #[error("User `{0}` is disabled")]
+    UserDisabled(String),
+    /// Too many failed logins: the user can't log in for a while, even with valid credentials.
+    #[error("User `{0}` is locked after too many failed logins")]
+    UserLocked(String),
     #[error("Database error: `{0}`")]
     DatabaseError(#[from] sqlx::Error),
     #[error("Authentication protocol error for `{0}`")]
@@ -15,6 +21,8 @@ pub enum DomainError {
     BinarySerializationError(#[from] bincode::Error),
     #[error("Invalid base64: `{0}`")]
     Base64DecodeError(#[from] base64::DecodeError),
+    #[error("Invalid request: `{0}`")]
+    InvalidRequest(String),
     #[error("Internal error: `{0}`")]
     InternalError(String),
 }
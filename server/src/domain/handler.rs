@@ -1,9 +1,13 @@
 use super::error::*;
 use async_trait::async_trait;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{BoxStream, StreamExt},
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(sqlx::FromRow))]
 pub struct User {
     pub user_id: String,
@@ -11,8 +15,26 @@ pub struct User {
     pub display_name: String,
     pub first_name: String,
     pub last_name: String,
-    // pub avatar: ?,
+    /// A JPEG picture of the user.
+    pub avatar: Option<Vec<u8>>,
     pub creation_date: chrono::DateTime<chrono::Utc>,
+    /// Disabled users can't log in (web or LDAP), but keep their details and memberships.
+    pub enabled: bool,
+    /// After this date, the user can't log in anymore, as if disabled.
+    pub valid_until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Set when the user got locked after too many failed logins, until this date.
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl User {
+    /// Whether the user can log in: enabled, and not expired.
+    pub fn is_active(&self) -> bool {
+        self.enabled
+            && self
+                .valid_until
+                .map(|date| date > chrono::Utc::now())
+                .unwrap_or(true)
+    }
 }
 
 impl Default for User {
@@ -24,7 +46,11 @@ impl Default for User {
             display_name: String::new(),
             first_name: String::new(),
             last_name: String::new(),
+            avatar: None,
             creation_date: chrono::Utc.timestamp(0, 0),
+            enabled: true,
+            valid_until: None,
+            locked_until: None,
         }
     }
 }
@@ -33,9 +59,54 @@ impl Default for User {
 pub struct Group {
     pub id: GroupId,
     pub display_name: String,
+    pub description: Option<String>,
+    /// The address of the group's mailing list.
+    pub email: Option<String>,
+    /// The members of the group, including the members of its subgroups.
     pub users: Vec<String>,
 }
 
+/// The built-in roles, each granted by the membership to the group of the same name.
+#[derive(PartialEq, Eq, Debug, Hash, Clone, Copy)]
+pub enum Role {
+    /// Can do everything.
+    Admin,
+    /// Can create and edit (non-admin) users, and manage the members of non-role groups.
+    UserManager,
+    /// Can only reset the passwords of non-admin users.
+    PasswordManager,
+    /// Can see all the users and groups, in the web app too, but not modify them.
+    Auditor,
+}
+
+/// A second built-in group granting the auditor role, for the read-only viewers.
+pub const READ_ONLY_GROUP_ALIAS: &str = "lldap_readonly";
+
+impl Role {
+    pub const ALL: [Role; 4] = [
+        Role::Admin,
+        Role::UserManager,
+        Role::PasswordManager,
+        Role::Auditor,
+    ];
+
+    pub fn group_name(&self) -> &'static str {
+        match self {
+            Role::Admin => "lldap_admin",
+            Role::UserManager => "lldap_user_manager",
+            Role::PasswordManager => "lldap_password_manager",
+            Role::Auditor => "lldap_auditor",
+        }
+    }
+
+    pub fn from_group_name(name: &str) -> Option<Role> {
+        if name == READ_ONLY_GROUP_ALIAS {
+            return Some(Role::Auditor);
+        }
+        Role::ALL.iter().copied().find(|r| r.group_name() == name)
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub struct BindRequest {
     pub name: String,
@@ -52,6 +123,42 @@ pub enum RequestFilter {
     MemberOf(String),
     // Same, by id.
     MemberOfId(GroupId),
+    // Users whose account expires before the date.
+    ValidUntilBefore(chrono::DateTime<chrono::Utc>),
+    // Users whose ID, email or display name contain the text, ignoring case.
+    Search(String),
+}
+
+/// The columns the users can be sorted by.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum UserColumn {
+    UserId,
+    Email,
+    DisplayName,
+    CreationDate,
+}
+
+impl UserColumn {
+    /// Orders the users by this column, then by ID.
+    pub fn compare(&self, a: &User, b: &User) -> std::cmp::Ordering {
+        match self {
+            UserColumn::UserId => std::cmp::Ordering::Equal,
+            UserColumn::Email => a.email.cmp(&b.email),
+            UserColumn::DisplayName => a.display_name.cmp(&b.display_name),
+            UserColumn::CreationDate => a.creation_date.cmp(&b.creation_date),
+        }
+        .then_with(|| a.user_id.cmp(&b.user_id))
+    }
+}
+
+/// A page of the users matching the filters, sorted by a column.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct UserPageRequest {
+    pub filters: Option<RequestFilter>,
+    pub sort: UserColumn,
+    pub descending: bool,
+    pub offset: i64,
+    pub limit: i64,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
@@ -72,17 +179,221 @@ pub struct UpdateUserRequest {
     pub display_name: Option<String>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    /// A JPEG picture, or an empty one to remove the avatar.
+    pub avatar: Option<Vec<u8>>,
+    pub enabled: Option<bool>,
+    /// Sets the expiry date, or removes it with `Some(None)`.
+    pub valid_until: Option<Option<chrono::DateTime<chrono::Utc>>>,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub struct UpdateGroupRequest {
     pub group_id: GroupId,
     pub display_name: Option<String>,
+    /// An empty string removes the description.
+    pub description: Option<String>,
+    /// An empty string removes the email.
+    pub email: Option<String>,
+}
+
+/// Prefix of all the API tokens, to distinguish them from JWTs.
+pub const API_TOKEN_PREFIX: &str = "lldap_";
+
+/// A long-lived token allowing a (service) user to call the API without logging in.
+/// The token itself is never stored, only its hash.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct ApiToken {
+    pub token_id: i32,
+    pub name: String,
+    pub user_id: String,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    pub expiry_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateApiTokenRequest {
+    pub user_id: String,
+    pub name: String,
+    pub expiry_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A client application of the OpenID Connect provider, registered through the API. Its secret
+/// is never stored, only its hash.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct OidcClient {
+    pub client_id: String,
+    pub display_name: String,
+    /// Where the users can be sent back after logging in, checked exactly.
+    pub redirect_uris: Vec<String>,
+}
+
+/// An administrative change, recorded for auditing.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct AuditLogEntry {
+    pub entry_id: i32,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The user who made the change.
+    pub actor: String,
+    /// Where the change came from, e.g. the IP address of the client.
+    pub source: String,
+    /// The name of the operation, e.g. "deleteUser".
+    pub action: String,
+    /// The modified entry, e.g. "user:bob" or "group:3".
+    pub target: String,
+    pub details: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateAuditLogEntryRequest {
+    pub actor: String,
+    pub source: String,
+    pub action: String,
+    pub target: String,
+    pub details: Option<String>,
+}
+
+/// A deleted user, which can be restored until `deleted_user_retention_days` have passed.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct DeletedUser {
+    pub user_id: String,
+    pub email: String,
+    pub display_name: String,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A successful login, shown to the user to spot the ones they didn't make.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct LoginHistoryEntry {
+    pub entry_id: i32,
+    pub user_id: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// How the user logged in: "web", "trusted_header" or "ldap".
+    pub method: String,
+    /// The IP address of the client.
+    pub ip: String,
+    /// The browser, for the web logins.
+    pub user_agent: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateLoginHistoryEntryRequest {
+    pub user_id: String,
+    pub method: String,
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+/// A security key (WebAuthn credential) registered by a user.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct WebauthnCredential {
+    /// Base64url-encoded, as sent by the browser.
+    pub credential_id: String,
+    pub user_id: String,
+    /// Given by the user, to tell their keys apart.
+    pub name: String,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateWebauthnCredentialRequest {
+    pub credential_id: String,
+    pub user_id: String,
+    pub name: String,
+    /// The public key and counter, serialized by the WebAuthn library.
+    pub credential: Vec<u8>,
+}
+
+/// The type of the values of a custom attribute.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum AttributeType {
+    String,
+    Integer,
+    /// RFC 3339, e.g. "2021-10-15T14:03:00Z".
+    DateTime,
+    /// Base64-encoded, re-encoded like the avatars.
+    JpegPhoto,
+}
+
+impl AttributeType {
+    /// The name stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::String => "string",
+            AttributeType::Integer => "integer",
+            AttributeType::DateTime => "date_time",
+            AttributeType::JpegPhoto => "jpeg_photo",
+        }
+    }
+
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(AttributeType::String),
+            "integer" => Some(AttributeType::Integer),
+            "date_time" => Some(AttributeType::DateTime),
+            "jpeg_photo" => Some(AttributeType::JpegPhoto),
+            _ => None,
+        }
+    }
+}
+
+/// A custom attribute of the users, defined by an admin on top of the built-in ones.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct AttributeSchema {
+    pub name: String,
+    pub attribute_type: AttributeType,
+    /// Whether a user can have several values.
+    pub is_list: bool,
+    /// Whether the users can change their own value, instead of only the admins.
+    pub is_editable: bool,
+}
+
+/// The values of a custom attribute for a user, as sent to the clients.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct AttributeValue {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+/// The kind of change of an entry. The values are the ones of the LDAP persistent search draft.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ChangeType {
+    Add = 1,
+    Delete = 2,
+    Modify = 4,
+    ModifyDn = 8,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ChangedEntry {
+    User(String),
+    /// A group, identified by its name.
+    Group(String),
+}
+
+/// A change of a user or group, broadcast to the interested parties (e.g. LDAP persistent
+/// searches). Group memberships changes are reported on the group and all the groups containing
+/// it, since their members change too.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ChangeEvent {
+    pub change_type: ChangeType,
+    pub entry: ChangedEntry,
+    /// For `ModifyDn`, the entry before it was renamed.
+    pub previous_entry: Option<ChangedEntry>,
+}
+
+pub trait ChangeNotifier {
+    /// Returns a receiver for all the changes happening after this call.
+    fn subscribe_to_changes(&self) -> tokio::sync::broadcast::Receiver<ChangeEvent>;
 }
 
 #[async_trait]
 pub trait LoginHandler: Clone + Send {
     async fn bind(&self, request: BindRequest) -> Result<()>;
+    /// The ID of the user logging in as `name`, which can also be their email if
+    /// `allow_email_login` is set. Fails if several users have that email.
+    async fn get_user_id_for_login(&self, name: &str) -> Result<String> {
+        Ok(name.to_string())
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -91,21 +402,139 @@ pub struct GroupId(pub i32);
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::FromRow)]
 pub struct GroupIdAndName(pub GroupId, pub String);
 
+/// A group without its members.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct GroupDetails {
+    pub group_id: GroupId,
+    pub display_name: String,
+    pub description: Option<String>,
+    pub email: Option<String>,
+}
+
 #[async_trait]
 pub trait BackendHandler: Clone + Send {
     async fn list_users(&self, filters: Option<RequestFilter>) -> Result<Vec<User>>;
+    /// Same as `list_users`, but yields the users as they are read from the database, without
+    /// holding them all in memory.
+    fn stream_users(&self, filters: Option<RequestFilter>) -> BoxStream<'_, Result<User>> {
+        futures::stream::once(self.list_users(filters))
+            .flat_map(|users| {
+                futures::stream::iter(match users {
+                    Ok(users) => users.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                })
+            })
+            .boxed()
+    }
+    /// Same as `list_users`, but only the requested page of the sorted users.
+    fn list_users_page(&self, request: UserPageRequest) -> BoxFuture<'_, Result<Vec<User>>> {
+        let users = self.list_users(request.filters.clone());
+        async move {
+            let mut users = users.await?;
+            users.sort_by(|a, b| request.sort.compare(a, b));
+            if request.descending {
+                users.reverse();
+            }
+            Ok(users
+                .into_iter()
+                .skip(request.offset.max(0) as usize)
+                .take(request.limit.max(0) as usize)
+                .collect())
+        }
+        .boxed()
+    }
+    async fn count_users(&self, filters: Option<RequestFilter>) -> Result<i64>;
+    /// Lists the users sharing their email (ignoring case) with another user, by email.
+    async fn list_users_with_duplicate_emails(&self) -> Result<Vec<User>>;
     async fn list_groups(&self) -> Result<Vec<Group>>;
+    async fn count_groups(&self) -> Result<i64>;
     async fn get_user_details(&self, user_id: &str) -> Result<User>;
-    async fn get_group_details(&self, group_id: GroupId) -> Result<GroupIdAndName>;
+    async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails>;
     async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
+    /// Changes the ID of a user, keeping their details, groups and API tokens. The sessions are
+    /// tied to the ID, so the user has to log in again.
+    async fn rename_user(&self, user_id: &str, new_user_id: &str) -> Result<()>;
+    /// Lifts the lock set after too many failed logins, and resets the count of failures.
+    async fn unlock_user(&self, user_id: &str) -> Result<()>;
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
+    /// Deletes the user, keeping them in the deleted users unless `deleted_user_retention_days`
+    /// is 0.
     async fn delete_user(&self, user_id: &str) -> Result<()>;
+    /// The deleted users that can still be restored, most recent first.
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+    /// Brings back a deleted user, with their password and the groups that still exist.
+    async fn restore_user(&self, user_id: &str) -> Result<()>;
+    /// Removes a deleted user for good.
+    async fn purge_deleted_user(&self, user_id: &str) -> Result<()>;
     async fn create_group(&self, group_name: &str) -> Result<GroupId>;
     async fn delete_group(&self, group_id: GroupId) -> Result<()>;
     async fn add_user_to_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
     async fn remove_user_from_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+    /// Returns the groups of the user, including the groups containing them (recursively).
     async fn get_user_groups(&self, user: &str) -> Result<HashSet<GroupIdAndName>>;
+    /// Makes `child` a subgroup of `parent`: its members become members of `parent`.
+    async fn add_group_to_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+    async fn remove_group_from_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+    /// Returns the direct subgroups of the group.
+    async fn list_subgroups(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+    /// Returns the group and all the groups it is (recursively) nested in, whose roles its members
+    /// get.
+    async fn get_group_ancestors(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+    /// Lets `user_id` manage the members of the group. The groups granting a role, or nested in
+    /// one, can't have managers.
+    async fn add_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+    async fn remove_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+    /// Returns the IDs of the managers of the group.
+    async fn list_group_managers(&self, group_id: GroupId) -> Result<Vec<String>>;
+    /// Returns the groups managed by the user.
+    async fn list_managed_groups(&self, user_id: &str) -> Result<Vec<GroupIdAndName>>;
+    /// Returns the new token, along with its secret value (only available at creation time).
+    async fn create_api_token(&self, request: CreateApiTokenRequest) -> Result<(ApiToken, String)>;
+    async fn list_api_tokens(&self) -> Result<Vec<ApiToken>>;
+    async fn delete_api_token(&self, token_id: i32) -> Result<()>;
+    /// Registers an OpenID Connect client, and returns its secret (only available at creation
+    /// time).
+    async fn create_oidc_client(&self, client: OidcClient) -> Result<String>;
+    async fn list_oidc_clients(&self) -> Result<Vec<OidcClient>>;
+    async fn delete_oidc_client(&self, client_id: &str) -> Result<()>;
+    /// Replaces the password of the user with a hash imported from another directory.
+    async fn import_password_hash(&self, user_id: &str, password_hash: &str) -> Result<()>;
+    async fn add_audit_log_entry(&self, request: CreateAuditLogEntryRequest) -> Result<()>;
+    /// Returns the audit log, most recent entries first.
+    async fn list_audit_log(&self, offset: i64, limit: i64) -> Result<Vec<AuditLogEntry>>;
+    async fn add_login_history_entry(&self, request: CreateLoginHistoryEntryRequest) -> Result<()>;
+    /// Returns the last logins of the user, most recent first.
+    async fn list_login_history(&self, user_id: &str, limit: i64)
+        -> Result<Vec<LoginHistoryEntry>>;
+    /// Whether the user confirmed a TOTP secret, to log in with a code from it.
+    async fn is_totp_enabled(&self, user_id: &str) -> Result<bool>;
+    /// Stores a new TOTP secret for the user and returns it, pending until a code from it is
+    /// confirmed.
+    async fn start_totp_enrollment(&self, user_id: &str) -> Result<String>;
+    /// Enables the pending TOTP secret if the code matches it, and returns new recovery codes.
+    async fn confirm_totp_enrollment(&self, user_id: &str, code: &str) -> Result<Vec<String>>;
+    /// Removes the TOTP secret and the recovery codes of the user.
+    async fn disable_totp(&self, user_id: &str) -> Result<()>;
+    async fn list_webauthn_credentials(&self, user_id: &str) -> Result<Vec<WebauthnCredential>>;
+    async fn add_webauthn_credential(&self, request: CreateWebauthnCredentialRequest)
+        -> Result<()>;
+    async fn delete_webauthn_credential(&self, user_id: &str, credential_id: &str) -> Result<()>;
+    /// The custom attributes of the users, sorted by name.
+    async fn list_user_attribute_schema(&self) -> Result<Vec<AttributeSchema>>;
+    async fn add_user_attribute(&self, schema: AttributeSchema) -> Result<()>;
+    /// Removes the attribute, with its values for all the users.
+    async fn delete_user_attribute(&self, name: &str) -> Result<()>;
+    /// The custom attributes the user has a value for, sorted by name.
+    async fn get_user_attributes(&self, user_id: &str) -> Result<Vec<AttributeValue>>;
+    /// Replaces the values of the attribute for the user, after checking them against its type.
+    /// No values removes the attribute from the user.
+    async fn set_user_attribute(
+        &self,
+        user_id: &str,
+        name: &str,
+        values: Vec<String>,
+    ) -> Result<()>;
 }
 
 #[cfg(test)]
@@ -117,18 +546,57 @@ mockall::mock! {
     #[async_trait]
     impl BackendHandler for TestBackendHandler {
         async fn list_users(&self, filters: Option<RequestFilter>) -> Result<Vec<User>>;
+        async fn count_users(&self, filters: Option<RequestFilter>) -> Result<i64>;
+        async fn list_users_with_duplicate_emails(&self) -> Result<Vec<User>>;
         async fn list_groups(&self) -> Result<Vec<Group>>;
+        async fn count_groups(&self) -> Result<i64>;
         async fn get_user_details(&self, user_id: &str) -> Result<User>;
-        async fn get_group_details(&self, group_id: GroupId) -> Result<GroupIdAndName>;
+        async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails>;
         async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
         async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
+        async fn rename_user(&self, user_id: &str, new_user_id: &str) -> Result<()>;
+        async fn unlock_user(&self, user_id: &str) -> Result<()>;
         async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
         async fn delete_user(&self, user_id: &str) -> Result<()>;
+        async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+        async fn restore_user(&self, user_id: &str) -> Result<()>;
+        async fn purge_deleted_user(&self, user_id: &str) -> Result<()>;
         async fn create_group(&self, group_name: &str) -> Result<GroupId>;
         async fn delete_group(&self, group_id: GroupId) -> Result<()>;
         async fn get_user_groups(&self, user: &str) -> Result<HashSet<GroupIdAndName>>;
         async fn add_user_to_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
         async fn remove_user_from_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+        async fn add_group_to_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+        async fn remove_group_from_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+        async fn list_subgroups(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+        async fn get_group_ancestors(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+        async fn add_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+        async fn remove_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+        async fn list_group_managers(&self, group_id: GroupId) -> Result<Vec<String>>;
+        async fn list_managed_groups(&self, user_id: &str) -> Result<Vec<GroupIdAndName>>;
+        async fn create_api_token(&self, request: CreateApiTokenRequest) -> Result<(ApiToken, String)>;
+        async fn list_api_tokens(&self) -> Result<Vec<ApiToken>>;
+        async fn delete_api_token(&self, token_id: i32) -> Result<()>;
+        async fn create_oidc_client(&self, client: OidcClient) -> Result<String>;
+        async fn list_oidc_clients(&self) -> Result<Vec<OidcClient>>;
+        async fn delete_oidc_client(&self, client_id: &str) -> Result<()>;
+        async fn import_password_hash(&self, user_id: &str, password_hash: &str) -> Result<()>;
+        async fn add_audit_log_entry(&self, request: CreateAuditLogEntryRequest) -> Result<()>;
+        async fn list_audit_log(&self, offset: i64, limit: i64) -> Result<Vec<AuditLogEntry>>;
+        async fn add_login_history_entry(&self, request: CreateLoginHistoryEntryRequest) -> Result<()>;
+        async fn list_login_history(&self, user_id: &str, limit: i64) -> Result<Vec<LoginHistoryEntry>>;
+        async fn is_totp_enabled(&self, user_id: &str) -> Result<bool>;
+        async fn start_totp_enrollment(&self, user_id: &str) -> Result<String>;
+        async fn confirm_totp_enrollment(&self, user_id: &str, code: &str) -> Result<Vec<String>>;
+        async fn disable_totp(&self, user_id: &str) -> Result<()>;
+        async fn list_webauthn_credentials(&self, user_id: &str) -> Result<Vec<WebauthnCredential>>;
+        async fn add_webauthn_credential(&self, request: CreateWebauthnCredentialRequest) -> Result<()>;
+        async fn delete_webauthn_credential(&self, user_id: &str, credential_id: &str) -> Result<()>;
+        async fn list_user_attribute_schema(&self) -> Result<Vec<AttributeSchema>>;
+        async fn add_user_attribute(&self, schema: AttributeSchema) -> Result<()>;
+        async fn delete_user_attribute(&self, name: &str) -> Result<()>;
+        async fn get_user_attributes(&self, user_id: &str) -> Result<Vec<AttributeValue>>;
+        async fn set_user_attribute(&self, user_id: &str, name: &str, values: Vec<String>) -> Result<()>;
     }
     #[async_trait]
     impl LoginHandler for TestBackendHandler {
@@ -0,0 +1,110 @@
+//! Password hashes imported from other directories (e.g. OpenLDAP). They can only be checked
+//! against a clear-text password, so they are used for the simple binds until the user logs in
+//! once and gets an OPAQUE password file.
+
+use super::error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Bcrypt,
+    Sha512Crypt,
+    Argon2,
+}
+
+/// The scheme prefixes used by OpenLDAP in `userPassword`, removed before storing the hash.
+const SCHEME_PREFIXES: [&str; 3] = ["{CRYPT}", "{BCRYPT}", "{ARGON2}"];
+
+fn strip_scheme_prefix(hash: &str) -> &str {
+    let hash = hash.trim();
+    SCHEME_PREFIXES
+        .iter()
+        .find(|prefix| {
+            hash.get(..prefix.len())
+                .map(|p| p.eq_ignore_ascii_case(prefix))
+                .unwrap_or(false)
+        })
+        .map(|prefix| &hash[prefix.len()..])
+        .unwrap_or(hash)
+}
+
+fn get_scheme(hash: &str) -> Option<Scheme> {
+    if ["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p)) {
+        Some(Scheme::Bcrypt)
+    } else if hash.starts_with("$6$") {
+        Some(Scheme::Sha512Crypt)
+    } else if hash.starts_with("$argon2") {
+        Some(Scheme::Argon2)
+    } else {
+        None
+    }
+}
+
+/// Checks that the hash is in a supported format (bcrypt, sha512-crypt or Argon2), and returns
+/// it in the form to store.
+pub fn normalize_hash(hash: &str) -> Result<String> {
+    let hash = strip_scheme_prefix(hash);
+    match get_scheme(hash) {
+        Some(_) => Ok(hash.to_string()),
+        None => Err(DomainError::InvalidRequest(
+            "Unsupported password hash, expected bcrypt, sha512-crypt or Argon2".to_string(),
+        )),
+    }
+}
+
+/// Whether the password matches the stored hash.
+pub fn verify(hash: &str, password: &str) -> bool {
+    match get_scheme(hash) {
+        Some(Scheme::Bcrypt) => pwhash::bcrypt::verify(password, hash),
+        Some(Scheme::Sha512Crypt) => pwhash::sha512_crypt::verify(password, hash),
+        Some(Scheme::Argon2) => argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_hash() {
+        assert_eq!(
+            normalize_hash("{CRYPT}$6$salt$hash").unwrap(),
+            "$6$salt$hash"
+        );
+        assert_eq!(
+            normalize_hash("{argon2}$argon2id$v=19$...").unwrap(),
+            "$argon2id$v=19$..."
+        );
+        assert_eq!(normalize_hash("$2b$04$hash").unwrap(), "$2b$04$hash");
+        normalize_hash("{SSHA}c2FsdGVkaGFzaA==").unwrap_err();
+        normalize_hash("password").unwrap_err();
+    }
+
+    #[test]
+    fn test_verify_sha512_crypt() {
+        let hash = "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1";
+        assert!(verify(hash, "Hello world!"));
+        assert!(!verify(hash, "Hello world"));
+    }
+
+    #[test]
+    fn test_verify_bcrypt() {
+        let hash = pwhash::bcrypt::hash_with(
+            pwhash::bcrypt::BcryptSetup {
+                cost: Some(4),
+                ..Default::default()
+            },
+            "bob00",
+        )
+        .unwrap();
+        assert!(verify(&hash, "bob00"));
+        assert!(!verify(&hash, "bob01"));
+    }
+
+    #[test]
+    fn test_verify_argon2() {
+        let hash = "$argon2i$v=19$m=65536,t=2,p=4$c29tZXNhbHQ$RdescudvJCsgt3ub+b+dWRWJTmaaJObG";
+        assert!(verify(hash, "password"));
+        assert!(!verify(hash, "passwore"));
+    }
+}
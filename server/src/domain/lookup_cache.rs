@@ -0,0 +1,134 @@
+//! In-memory cache of the lookups repeated by the LDAP clients that bind before every request: the
+//! password and status of the bind target, the user details and the user's groups. The entries
+//! expire after `ldap_cache_ttl_seconds`, and are all dropped on the writes made through LLDAP; the
+//! changes made directly in the database show up once they expire.
+
+use super::handler::{GroupIdAndName, User};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Above this many entries, the expired ones are dropped.
+const MAX_ENTRIES: usize = 10_000;
+
+/// What a bind needs to know about the user.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct BindTarget {
+    pub password_hash: Option<Vec<u8>>,
+    pub legacy_password_hash: Option<String>,
+    pub enabled: bool,
+    pub valid_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl BindTarget {
+    /// Whether the user can log in: enabled, and not expired.
+    pub fn is_active(&self) -> bool {
+        self.enabled
+            && self
+                .valid_until
+                .map(|date| date > chrono::Utc::now())
+                .unwrap_or(true)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .map(|date| date > chrono::Utc::now())
+            .unwrap_or(false)
+    }
+}
+
+/// Values by user ID, for a limited time.
+#[derive(Debug)]
+pub struct TtlCache<V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<V> {
+        match self.entries.lock().unwrap().get(key) {
+            Some((expiry, value)) if *expiry > now => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        if self.ttl == Duration::default() {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (expiry, _)| *expiry > now);
+        }
+        entries.insert(key.to_string(), (now + self.ttl, value));
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[derive(Debug)]
+pub struct LookupCache {
+    pub bind_targets: TtlCache<BindTarget>,
+    pub users: TtlCache<User>,
+    pub user_groups: TtlCache<HashSet<GroupIdAndName>>,
+}
+
+impl LookupCache {
+    /// A `ttl` of 0 disables the cache.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            bind_targets: TtlCache::new(ttl),
+            users: TtlCache::new(ttl),
+            user_groups: TtlCache::new(ttl),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.users.ttl != Duration::default()
+    }
+
+    /// Drops everything, after a write.
+    pub fn clear(&self) {
+        self.bind_targets.clear();
+        self.users.clear();
+        self.user_groups.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_cache() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        cache.insert("bob", 1);
+        assert_eq!(cache.get("bob"), Some(1));
+        assert_eq!(cache.get("john"), None);
+        assert_eq!(
+            cache.get_at("bob", Instant::now() + Duration::from_secs(61)),
+            None
+        );
+        cache.clear();
+        assert_eq!(cache.get("bob"), None);
+
+        let disabled = TtlCache::new(Duration::from_secs(0));
+        disabled.insert("bob", 1);
+        assert_eq!(disabled.get("bob"), None);
+    }
+}
@@ -1,6 +1,11 @@
+pub mod attributes;
+pub mod avatar;
 pub mod error;
 pub mod handler;
+pub mod legacy_password;
+pub mod lookup_cache;
 pub mod opaque_handler;
 pub mod sql_backend_handler;
 pub mod sql_opaque_handler;
 pub mod sql_tables;
+pub mod totp;
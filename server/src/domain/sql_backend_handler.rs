@@ -1,118 +1,590 @@
-use super::{error::*, handler::*, sql_tables::*};
-use crate::infra::configuration::Configuration;
+use super::{attributes, error::*, handler::*, lookup_cache::LookupCache, sql_tables::*, totp};
+use crate::infra::{configuration::Configuration, db_pool::PoolMonitor};
 use async_trait::async_trait;
+use futures::{future::BoxFuture, stream::BoxStream};
 use futures_util::StreamExt;
-use sea_query::{Expr, Iden, Order, Query, SimpleExpr};
+use sea_query::{Expr, Iden, Order, Query, SelectStatement, SimpleExpr};
+use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Number of changes kept for the slow subscribers before they start missing some.
+const CHANGE_QUEUE_SIZE: usize = 256;
+
+/// Number of users read ahead of the consumer of `stream_users`.
+const STREAM_BUFFER_SIZE: usize = 64;
+
+/// The columns of a user kept when they are deleted, to restore them. The failed logins are
+/// forgotten.
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+struct DeletedUserRow {
+    email: String,
+    display_name: String,
+    first_name: String,
+    last_name: String,
+    avatar: Option<Vec<u8>>,
+    creation_date: chrono::DateTime<chrono::Utc>,
+    password_hash: Option<Vec<u8>>,
+    legacy_password_hash: Option<String>,
+    totp_secret: Option<String>,
+    mfa_type: Option<String>,
+    enabled: bool,
+    valid_until: Option<chrono::DateTime<chrono::Utc>>,
+    session_generation: i32,
+}
+
+/// Serialized in `DeletedUsers::Data`.
+#[derive(Serialize, Deserialize)]
+struct DeletedUserData {
+    user: DeletedUserRow,
+    groups: Vec<GroupId>,
+    managed_groups: Vec<GroupId>,
+}
+
+fn optional_value<T: Into<sea_query::Value>>(value: Option<T>) -> sea_query::Value {
+    value.map(Into::into).unwrap_or(sea_query::Value::Null)
+}
 
 #[derive(Debug, Clone)]
 pub struct SqlBackendHandler {
     pub(crate) config: Configuration,
     pub(crate) sql_pool: Pool,
+    change_sender: broadcast::Sender<ChangeEvent>,
+    pub(crate) cache: Arc<LookupCache>,
+    pub(crate) pool_monitor: Arc<PoolMonitor>,
 }
 
 impl SqlBackendHandler {
     pub fn new(config: Configuration, sql_pool: Pool) -> Self {
-        SqlBackendHandler { config, sql_pool }
+        let (change_sender, _) = broadcast::channel(CHANGE_QUEUE_SIZE);
+        let cache = Arc::new(LookupCache::new(std::time::Duration::from_secs(
+            config.ldap_cache_ttl_seconds,
+        )));
+        SqlBackendHandler {
+            config,
+            sql_pool,
+            change_sender,
+            cache,
+            pool_monitor: Arc::default(),
+        }
+    }
+
+    fn has_change_subscribers(&self) -> bool {
+        self.change_sender.receiver_count() > 0
+    }
+
+    fn notify_change(&self, change_type: ChangeType, entry: ChangedEntry) {
+        self.cache.clear();
+        // Nobody might be listening, that's fine.
+        let _ = self.change_sender.send(ChangeEvent {
+            change_type,
+            entry,
+            previous_entry: None,
+        });
+    }
+
+    /// Fails if `enforce_unique_emails` is set and another user than `user_id` has the email.
+    async fn check_email_is_unique(&self, email: &str, user_id: &str) -> Result<()> {
+        if !self.config.enforce_unique_emails {
+            return Ok(());
+        }
+        let query = Query::select()
+            .column(Users::UserId)
+            .from(Users::Table)
+            .and_where(Expr::expr(Expr::cust(&lower_email())).eq(email.to_lowercase()))
+            .and_where(Expr::col(Users::UserId).ne(user_id))
+            .limit(1)
+            .to_string(DbQueryBuilder {});
+        if sqlx::query(&query)
+            .fetch_optional(&self.sql_pool)
+            .await?
+            .is_some()
+        {
+            return Err(DomainError::InvalidRequest(
+                "The email is already used by another user".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The groups in `column` of `table` for the user, e.g. their memberships.
+    async fn get_user_group_ids(
+        &self,
+        table: impl Iden + 'static,
+        user_column: impl Iden + 'static,
+        group_column: impl Iden + 'static,
+        user_id: &str,
+    ) -> Result<Vec<GroupId>> {
+        let group_column_name = group_column.to_string();
+        let query = Query::select()
+            .column(group_column)
+            .from(table)
+            .and_where(Expr::col(user_column).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| row.get::<GroupId, _>(&*group_column_name))
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    /// Copies the user and their groups to the deleted users, replacing a previous deleted user
+    /// with the same ID.
+    async fn keep_deleted_user(&self, user_id: &str) -> Result<()> {
+        let query = Query::select()
+            .columns(vec![
+                Users::Email,
+                Users::DisplayName,
+                Users::FirstName,
+                Users::LastName,
+                Users::Avatar,
+                Users::CreationDate,
+                Users::PasswordHash,
+                Users::LegacyPasswordHash,
+                Users::TotpSecret,
+                Users::MfaType,
+                Users::Enabled,
+                Users::ValidUntil,
+                Users::SessionGeneration,
+            ])
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let user = sqlx::query_as::<_, DeletedUserRow>(&query)
+            .fetch_one(&self.sql_pool)
+            .await?;
+        let data = DeletedUserData {
+            groups: self
+                .get_user_group_ids(
+                    Memberships::Table,
+                    Memberships::UserId,
+                    Memberships::GroupId,
+                    user_id,
+                )
+                .await?,
+            managed_groups: self
+                .get_user_group_ids(
+                    GroupManagers::Table,
+                    GroupManagers::UserId,
+                    GroupManagers::GroupId,
+                    user_id,
+                )
+                .await?,
+            user,
+        };
+        let query = Query::delete()
+            .from_table(DeletedUsers::Table)
+            .and_where(Expr::col(DeletedUsers::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let query = Query::insert()
+            .into_table(DeletedUsers::Table)
+            .columns(vec![
+                DeletedUsers::UserId,
+                DeletedUsers::Email,
+                DeletedUsers::DisplayName,
+                DeletedUsers::DeletedAt,
+                DeletedUsers::Data,
+            ])
+            .values_panic(vec![
+                user_id.into(),
+                data.user.email.clone().into(),
+                data.user.display_name.clone().into(),
+                chrono::Utc::now().naive_utc().into(),
+                bincode::serialize(&data)?.into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    /// Returns the names of the groups and of all the groups they are (recursively) nested in.
+    async fn get_group_and_ancestor_names(
+        &self,
+        groups: impl IntoIterator<Item = GroupId>,
+    ) -> Result<Vec<String>> {
+        let ancestors = get_ancestors(&self.get_subgroup_relations().await?, groups);
+        Ok(self
+            .get_group_ids_and_names()
+            .await?
+            .into_iter()
+            .filter(|g| ancestors.contains(&g.0))
+            .map(|g| g.1)
+            .collect())
+    }
+
+    /// Whether the group, or one of the groups it is (recursively) nested in, grants a role.
+    async fn grants_role(&self, group_id: GroupId) -> Result<bool> {
+        Ok(self
+            .get_group_ancestors(group_id)
+            .await?
+            .iter()
+            .any(|g| Role::from_group_name(&g.1).is_some()))
+    }
+
+    /// The groups that have at least one manager.
+    async fn get_managed_group_ids(&self) -> Result<HashSet<GroupId>> {
+        let query = Query::select()
+            .column(GroupManagers::GroupId)
+            .from(GroupManagers::Table)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| row.get::<GroupId, _>(&*GroupManagers::GroupId.to_string()))
+            .fetch_all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    /// Reports a change of members of the groups, and thus of all the groups containing them.
+    async fn notify_members_changed(
+        &self,
+        groups: impl IntoIterator<Item = GroupId>,
+    ) -> Result<()> {
+        if !self.has_change_subscribers() {
+            return Ok(());
+        }
+        for name in self.get_group_and_ancestor_names(groups).await? {
+            self.notify_change(ChangeType::Modify, ChangedEntry::Group(name));
+        }
+        Ok(())
+    }
+
+    /// Returns all the (parent, child) group nesting relations.
+    async fn get_subgroup_relations(&self) -> Result<Vec<(GroupId, GroupId)>> {
+        let query = Query::select()
+            .column(SubGroups::ParentGroupId)
+            .column(SubGroups::ChildGroupId)
+            .from(SubGroups::Table)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| {
+                (
+                    row.get::<GroupId, _>(&*SubGroups::ParentGroupId.to_string()),
+                    row.get::<GroupId, _>(&*SubGroups::ChildGroupId.to_string()),
+                )
+            })
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    /// The user ID to look up through the cache instead of running the query, when the filters
+    /// only match this ID, e.g. `(&(objectClass=person)(uid=bob))` over LDAP.
+    fn get_cacheable_user_id<'a>(&self, filters: &'a Option<RequestFilter>) -> Option<&'a str> {
+        if !self.cache.is_enabled() {
+            return None;
+        }
+        filters.as_ref().and_then(get_single_user_id)
+    }
+
+    /// The user, or `None` if they don't exist.
+    async fn find_user(&self, user_id: &str) -> Result<Option<User>> {
+        match self.get_user_details(user_id).await {
+            Ok(user) => Ok(Some(user)),
+            Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The query listing the users matching the filters, or `None` if none can match.
+    async fn get_list_users_query(&self, filters: Option<RequestFilter>) -> Result<Option<String>> {
+        Ok(match self.get_filtered_users_query(filters).await? {
+            None => None,
+            Some(mut query_builder) => Some(
+                select_user_columns(&mut query_builder)
+                    .order_by((Users::Table, Users::UserId), Order::Asc)
+                    .to_string(DbQueryBuilder {}),
+            ),
+        })
+    }
+
+    /// Returns the query selecting the users matching the filters, without any column, or None if
+    /// no user can match.
+    async fn get_filtered_users_query(
+        &self,
+        filters: Option<RequestFilter>,
+    ) -> Result<Option<SelectStatement>> {
+        let mut query_builder = Query::select().from(Users::Table).to_owned();
+        let filter = match filters {
+            None => return Ok(Some(query_builder)),
+            Some(filter) => filter,
+        };
+        if filter == RequestFilter::Not(Box::new(RequestFilter::And(Vec::new()))) {
+            return Ok(None);
+        }
+        if filter == RequestFilter::And(Vec::new()) || filter == RequestFilter::Or(Vec::new()) {
+            return Ok(Some(query_builder));
+        }
+        let relations = self.get_subgroup_relations().await?;
+        let filter = if relations.is_empty() {
+            filter
+        } else {
+            let groups = self.get_group_ids_and_names().await?;
+            expand_group_filter(filter, &relations, &groups)
+        };
+        query_builder.and_where(get_filter_expr(filter));
+        Ok(Some(query_builder))
+    }
+
+    async fn get_group_ids_and_names(&self) -> Result<Vec<GroupIdAndName>> {
+        let query = Query::select()
+            .column(Groups::GroupId)
+            .column(Groups::DisplayName)
+            .from(Groups::Table)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, GroupIdAndName>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    /// The TOTP secret of the user, confirmed or pending, and whether it is confirmed.
+    async fn get_totp_secret(&self, user_id: &str) -> Result<(Option<String>, bool)> {
+        let query = Query::select()
+            .column(Users::TotpSecret)
+            .column(Users::MfaType)
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let row = sqlx::query(&query).fetch_one(&self.sql_pool).await?;
+        let mfa_type = row.get::<Option<String>, _>(&*Users::MfaType.to_string());
+        Ok((
+            row.get::<Option<String>, _>(&*Users::TotpSecret.to_string()),
+            mfa_type.as_deref() == Some(totp::TOTP_MFA_TYPE),
+        ))
+    }
+
+    async fn delete_totp_recovery_codes(&self, user_id: &str) -> Result<()> {
+        let query = Query::delete()
+            .from_table(TotpRecoveryCodes::Table)
+            .and_where(Expr::col(TotpRecoveryCodes::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn get_user_attribute_schema(&self, name: &str) -> Result<AttributeSchema> {
+        self.list_user_attribute_schema()
+            .await?
+            .into_iter()
+            .find(|schema| schema.name == name)
+            .ok_or_else(|| DomainError::InvalidRequest(format!("Unknown attribute `{}`", name)))
+    }
+}
+
+/// Returns the `roots` and all the groups reachable from them through the `(from, to)` relations.
+fn follow_relations(
+    relations: &[(GroupId, GroupId)],
+    roots: impl IntoIterator<Item = GroupId>,
+) -> HashSet<GroupId> {
+    let mut result: HashSet<GroupId> = roots.into_iter().collect();
+    let mut to_visit: Vec<GroupId> = result.iter().copied().collect();
+    while let Some(group) = to_visit.pop() {
+        for (from, to) in relations {
+            if *from == group && result.insert(*to) {
+                to_visit.push(*to);
+            }
+        }
+    }
+    result
+}
+
+/// Returns the groups and all their (recursive) subgroups.
+fn get_descendants(
+    relations: &[(GroupId, GroupId)],
+    groups: impl IntoIterator<Item = GroupId>,
+) -> HashSet<GroupId> {
+    follow_relations(relations, groups)
+}
+
+/// Returns the groups and all the groups they are (recursively) nested in.
+fn get_ancestors(
+    relations: &[(GroupId, GroupId)],
+    groups: impl IntoIterator<Item = GroupId>,
+) -> HashSet<GroupId> {
+    let reversed = relations.iter().map(|(p, c)| (*c, *p)).collect::<Vec<_>>();
+    follow_relations(&reversed, groups)
+}
+
+/// Replaces the group membership filters with filters matching the group or any of its subgroups.
+fn expand_group_filter(
+    filter: RequestFilter,
+    relations: &[(GroupId, GroupId)],
+    groups: &[GroupIdAndName],
+) -> RequestFilter {
+    use RequestFilter::*;
+    let member_of_any = |ids: HashSet<GroupId>| {
+        let mut ids = ids.into_iter().collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.0);
+        ids.into_iter().map(MemberOfId)
+    };
+    match filter {
+        And(fs) => And(fs
+            .into_iter()
+            .map(|f| expand_group_filter(f, relations, groups))
+            .collect()),
+        Or(fs) => Or(fs
+            .into_iter()
+            .map(|f| expand_group_filter(f, relations, groups))
+            .collect()),
+        Not(f) => Not(Box::new(expand_group_filter(*f, relations, groups))),
+        MemberOf(name) => {
+            let ids = groups.iter().filter(|g| g.1 == name).map(|g| g.0);
+            Or(std::iter::once(MemberOf(name))
+                .chain(member_of_any(get_descendants(relations, ids)))
+                .collect())
+        }
+        MemberOfId(id) => Or(member_of_any(get_descendants(relations, vec![id])).collect()),
+        f => f,
     }
 }
 
-struct RequiresGroup(bool);
+/// Hash of an API token (or of the secret of an OpenID Connect client, or of a TOTP recovery
+/// code), as stored in the database.
+pub(crate) fn hash_api_token(token: &str) -> Vec<u8> {
+    use sha2::{Digest, Sha512};
+    Sha512::digest(token.as_bytes()).to_vec()
+}
 
-// Returns the condition for the SQL query, and whether it requires joining with the groups table.
-fn get_filter_expr(filter: RequestFilter) -> (RequiresGroup, SimpleExpr) {
+// Returns the condition for the SQL query.
+fn get_filter_expr(filter: RequestFilter) -> SimpleExpr {
     use RequestFilter::*;
     fn get_repeated_filter(
         fs: Vec<RequestFilter>,
         field: &dyn Fn(SimpleExpr, SimpleExpr) -> SimpleExpr,
-    ) -> (RequiresGroup, SimpleExpr) {
-        let mut requires_group = false;
+    ) -> SimpleExpr {
         let mut it = fs.into_iter();
         let first_expr = match it.next() {
-            None => return (RequiresGroup(false), Expr::value(true)),
-            Some(f) => {
-                let (group, filter) = get_filter_expr(f);
-                requires_group |= group.0;
-                filter
-            }
+            None => return Expr::value(true),
+            Some(f) => get_filter_expr(f),
         };
-        let filter = it.fold(first_expr, |e, f| {
-            let (group, filters) = get_filter_expr(f);
-            requires_group |= group.0;
-            field(e, filters)
-        });
-        (RequiresGroup(requires_group), filter)
+        it.fold(first_expr, |e, f| field(e, get_filter_expr(f)))
+    }
+    // The memberships are checked with a sub-query rather than by joining with the groups: that
+    // way, negating the filter also excludes the users belonging to several groups.
+    fn get_member_filter(members: &mut SelectStatement) -> SimpleExpr {
+        Expr::col((Users::Table, Users::UserId)).in_subquery(
+            members
+                .column((Memberships::Table, Memberships::UserId))
+                .from(Memberships::Table)
+                .to_owned(),
+        )
     }
     match filter {
         And(fs) => get_repeated_filter(fs, &SimpleExpr::and),
         Or(fs) => get_repeated_filter(fs, &SimpleExpr::or),
-        Not(f) => {
-            let (requires_group, filters) = get_filter_expr(*f);
-            (requires_group, Expr::not(Expr::expr(filters)))
-        }
-        Equality(s1, s2) => (
-            RequiresGroup(false),
+        Not(f) => Expr::not(Expr::expr(get_filter_expr(*f))),
+        Equality(s1, s2) => {
             if s1 == Users::DisplayName.to_string() {
                 Expr::col((Users::Table, Users::DisplayName)).eq(s2)
             } else if s1 == Users::UserId.to_string() {
                 Expr::col((Users::Table, Users::UserId)).eq(s2)
             } else {
                 Expr::expr(Expr::cust(&s1)).eq(s2)
-            },
-        ),
-        MemberOf(group) => (
-            RequiresGroup(true),
-            Expr::col((Groups::Table, Groups::DisplayName)).eq(group),
+            }
+        }
+        MemberOf(group) => get_member_filter(
+            Query::select()
+                .inner_join(
+                    Groups::Table,
+                    Expr::tbl(Memberships::Table, Memberships::GroupId)
+                        .equals(Groups::Table, Groups::GroupId),
+                )
+                .and_where(Expr::col((Groups::Table, Groups::DisplayName)).eq(group)),
         ),
-        MemberOfId(group_id) => (
-            RequiresGroup(true),
-            Expr::col((Groups::Table, Groups::GroupId)).eq(group_id),
+        MemberOfId(group_id) => get_member_filter(
+            Query::select()
+                .and_where(Expr::col((Memberships::Table, Memberships::GroupId)).eq(group_id)),
         ),
+        ValidUntilBefore(date) => Expr::col((Users::Table, Users::ValidUntil)).lt(date.naive_utc()),
+        Search(text) => {
+            let pattern = format!("%{}%", text.to_lowercase());
+            let matches = |column: Users| {
+                Expr::expr(Expr::cust(&format!(
+                    "LOWER({}.{})",
+                    Users::Table.to_string(),
+                    column.to_string()
+                )))
+                .like(&pattern)
+            };
+            matches(Users::UserId)
+                .or(matches(Users::Email))
+                .or(matches(Users::DisplayName))
+        }
+    }
+}
+
+/// Adds the columns of `User` to the query.
+fn select_user_columns(query_builder: &mut SelectStatement) -> &mut SelectStatement {
+    query_builder
+        .column((Users::Table, Users::UserId))
+        .column(Users::Email)
+        .column((Users::Table, Users::DisplayName))
+        .column(Users::FirstName)
+        .column(Users::LastName)
+        .column(Users::Avatar)
+        .column(Users::CreationDate)
+        .column(Users::Enabled)
+        .column(Users::ValidUntil)
+        .column(Users::LockedUntil)
+}
+
+/// The user ID, if the filter matches exactly this user ID.
+fn get_single_user_id(filter: &RequestFilter) -> Option<&str> {
+    match filter {
+        RequestFilter::Equality(field, value) if field == "user_id" => Some(value),
+        RequestFilter::And(filters) => {
+            // Without the conditions that are always true, e.g. `(objectClass=person)`.
+            let mut conditions = filters
+                .iter()
+                .filter(|f| **f != RequestFilter::And(Vec::new()));
+            match (conditions.next(), conditions.next()) {
+                (Some(filter), None) => get_single_user_id(filter),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Condition on the users who can log in: enabled, and not expired.
+pub(crate) fn is_active_user() -> SimpleExpr {
+    Expr::tbl(Users::Table, Users::Enabled).eq(true).and(
+        Expr::tbl(Users::Table, Users::ValidUntil)
+            .is_null()
+            .or(Expr::tbl(Users::Table, Users::ValidUntil).gt(chrono::Utc::now().naive_utc())),
+    )
+}
+
+/// Bumps the session generation of the user, for a password change: the sessions opened with
+/// the previous password become invalid.
+pub(crate) fn next_session_generation() -> SimpleExpr {
+    Expr::cust(&format!("{} + 1", Users::SessionGeneration.to_string()))
+}
+
+/// The display name of the users who don't set one: their full name, or else their ID.
+fn default_display_name(user_id: &str, first_name: &str, last_name: &str) -> String {
+    let full_name = format!("{} {}", first_name.trim(), last_name.trim());
+    match full_name.trim() {
+        "" => user_id.to_string(),
+        name => name.to_string(),
     }
 }
 
+/// The emails are compared ignoring case.
+pub(crate) fn lower_email() -> String {
+    format!("LOWER({})", Users::Email.to_string())
+}
+
 #[async_trait]
 impl BackendHandler for SqlBackendHandler {
     async fn list_users(&self, filters: Option<RequestFilter>) -> Result<Vec<User>> {
-        let query = {
-            let mut query_builder = Query::select()
-                .column((Users::Table, Users::UserId))
-                .column(Users::Email)
-                .column((Users::Table, Users::DisplayName))
-                .column(Users::FirstName)
-                .column(Users::LastName)
-                .column(Users::Avatar)
-                .column(Users::CreationDate)
-                .from(Users::Table)
-                .order_by((Users::Table, Users::UserId), Order::Asc)
-                .to_owned();
-            if let Some(filter) = filters {
-                if filter == RequestFilter::Not(Box::new(RequestFilter::And(Vec::new()))) {
-                    return Ok(Vec::new());
-                }
-                if filter != RequestFilter::And(Vec::new())
-                    && filter != RequestFilter::Or(Vec::new())
-                {
-                    let (RequiresGroup(requires_group), condition) = get_filter_expr(filter);
-                    query_builder.and_where(condition);
-                    if requires_group {
-                        query_builder
-                            .left_join(
-                                Memberships::Table,
-                                Expr::tbl(Users::Table, Users::UserId)
-                                    .equals(Memberships::Table, Memberships::UserId),
-                            )
-                            .left_join(
-                                Groups::Table,
-                                Expr::tbl(Memberships::Table, Memberships::GroupId)
-                                    .equals(Groups::Table, Groups::GroupId),
-                            );
-                    }
-                }
-            }
-
-            query_builder.to_string(DbQueryBuilder {})
+        if let Some(user_id) = self.get_cacheable_user_id(&filters) {
+            return Ok(self.find_user(user_id).await?.into_iter().collect());
+        }
+        let query = match self.get_list_users_query(filters).await? {
+            None => return Ok(Vec::new()),
+            Some(query) => query,
         };
 
         let results = sqlx::query_as::<_, User>(&query)
@@ -123,10 +595,128 @@ impl BackendHandler for SqlBackendHandler {
         Ok(results.into_iter().collect::<sqlx::Result<Vec<User>>>()?)
     }
 
+    fn stream_users(&self, filters: Option<RequestFilter>) -> BoxStream<'_, Result<User>> {
+        // The rows are read by a separate task, since the stream of rows borrows the query. The
+        // bounded channel keeps it from reading ahead of the consumer.
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER_SIZE);
+        let handler = self.clone();
+        tokio::spawn(async move {
+            if let Some(user_id) = handler.get_cacheable_user_id(&filters) {
+                if let Some(user) = handler.find_user(user_id).await.transpose() {
+                    let _ = sender.send(user).await;
+                }
+                return;
+            }
+            let query = match handler.get_list_users_query(filters).await {
+                Ok(Some(query)) => query,
+                Ok(None) => return,
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            };
+            let mut rows = sqlx::query_as::<_, User>(&query).fetch(&handler.sql_pool);
+            while let Some(row) = rows.next().await {
+                if sender.send(row.map_err(Into::into)).await.is_err() {
+                    // The consumer is gone.
+                    return;
+                }
+            }
+        });
+        tokio_stream::wrappers::ReceiverStream::new(receiver).boxed()
+    }
+
+    fn list_users_page(&self, request: UserPageRequest) -> BoxFuture<'_, Result<Vec<User>>> {
+        Box::pin(async move {
+            let mut query_builder = match self.get_filtered_users_query(request.filters).await? {
+                None => return Ok(Vec::new()),
+                Some(query_builder) => query_builder,
+            };
+            let order = if request.descending {
+                Order::Desc
+            } else {
+                Order::Asc
+            };
+            let column = match request.sort {
+                UserColumn::UserId => Users::UserId,
+                UserColumn::Email => Users::Email,
+                UserColumn::DisplayName => Users::DisplayName,
+                UserColumn::CreationDate => Users::CreationDate,
+            };
+            select_user_columns(&mut query_builder).order_by((Users::Table, column), order.clone());
+            // The ties are broken by user ID, for stable pages.
+            if request.sort != UserColumn::UserId {
+                query_builder.order_by((Users::Table, Users::UserId), order);
+            }
+            let query = query_builder
+                .offset(request.offset.max(0) as u64)
+                .limit(request.limit.max(0) as u64)
+                .to_string(DbQueryBuilder {});
+            Ok(sqlx::query_as::<_, User>(&query)
+                .fetch_all(&self.sql_pool)
+                .await?)
+        })
+    }
+
+    async fn count_users(&self, filters: Option<RequestFilter>) -> Result<i64> {
+        let query = match self.get_filtered_users_query(filters).await? {
+            None => return Ok(0),
+            Some(mut query_builder) => query_builder
+                .expr(Expr::cust(&format!(
+                    "COUNT(DISTINCT {}.{})",
+                    Users::Table.to_string(),
+                    Users::UserId.to_string()
+                )))
+                .to_string(DbQueryBuilder {}),
+        };
+        Ok(sqlx::query(&query)
+            .fetch_one(&self.sql_pool)
+            .await?
+            .get::<i64, _>(0))
+    }
+    async fn list_users_with_duplicate_emails(&self) -> Result<Vec<User>> {
+        let query = Query::select()
+            .column(Users::UserId)
+            .column(Users::Email)
+            .column(Users::DisplayName)
+            .column(Users::FirstName)
+            .column(Users::LastName)
+            .column(Users::Avatar)
+            .column(Users::CreationDate)
+            .column(Users::Enabled)
+            .column(Users::ValidUntil)
+            .column(Users::LockedUntil)
+            .from(Users::Table)
+            .and_where(Expr::cust(&format!(
+                "{email} IN (SELECT {email} FROM {users} GROUP BY {email} HAVING COUNT(*) > 1)",
+                email = lower_email(),
+                users = Users::Table.to_string(),
+            )))
+            .order_by_expr(Expr::cust(&lower_email()), Order::Asc)
+            .order_by(Users::UserId, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, User>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    async fn count_groups(&self) -> Result<i64> {
+        let query = Query::select()
+            .expr(Expr::cust("COUNT(*)"))
+            .from(Groups::Table)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .fetch_one(&self.sql_pool)
+            .await?
+            .get::<i64, _>(0))
+    }
+
     async fn list_groups(&self) -> Result<Vec<Group>> {
         let query: String = Query::select()
             .column((Groups::Table, Groups::GroupId))
             .column(Groups::DisplayName)
+            .column(Groups::Description)
+            .column(Groups::Email)
             .column(Memberships::UserId)
             .from(Groups::Table)
             .left_join(
@@ -143,7 +733,7 @@ impl BackendHandler for SqlBackendHandler {
         let mut groups = Vec::new();
         // The rows are returned sorted by display_name, equivalent to group_id. We group them by
         // this key which gives us one element (`rows`) per group.
-        for ((group_id, display_name), rows) in &sqlx::query(&query)
+        for ((group_id, display_name, description, email), rows) in &sqlx::query(&query)
             .fetch_all(&self.sql_pool)
             .await?
             .into_iter()
@@ -151,12 +741,16 @@ impl BackendHandler for SqlBackendHandler {
                 (
                     GroupId(row.get::<i32, _>(&*Groups::GroupId.to_string())),
                     row.get::<String, _>(&*Groups::DisplayName.to_string()),
+                    row.get::<Option<String>, _>(&*Groups::Description.to_string()),
+                    row.get::<Option<String>, _>(&*Groups::Email.to_string()),
                 )
             })
         {
             groups.push(Group {
                 id: group_id,
                 display_name,
+                description,
+                email,
                 users: rows
                     .map(|row| row.get::<String, _>(&*Memberships::UserId.to_string()))
                     // If a group has no users, an empty string is returned because of the left
@@ -165,6 +759,25 @@ impl BackendHandler for SqlBackendHandler {
                     .collect(),
             });
         }
+        let relations = self.get_subgroup_relations().await?;
+        if !relations.is_empty() {
+            // Add the members of the subgroups.
+            let direct_members = groups
+                .iter()
+                .map(|g| (g.id, g.users.clone()))
+                .collect::<std::collections::HashMap<_, _>>();
+            for group in groups.iter_mut() {
+                let mut users = get_descendants(&relations, vec![group.id])
+                    .iter()
+                    .filter_map(|id| direct_members.get(id))
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                users.sort();
+                users.dedup();
+                group.users = users;
+            }
+        }
         Ok(groups)
     }
 
@@ -177,24 +790,31 @@ impl BackendHandler for SqlBackendHandler {
             .column(Users::LastName)
             .column(Users::Avatar)
             .column(Users::CreationDate)
+            .column(Users::Enabled)
+            .column(Users::ValidUntil)
+            .column(Users::LockedUntil)
             .from(Users::Table)
             .and_where(Expr::col(Users::UserId).eq(user_id))
             .to_string(DbQueryBuilder {});
 
-        Ok(sqlx::query_as::<_, User>(&query)
+        let user = sqlx::query_as::<_, User>(&query)
             .fetch_one(&self.sql_pool)
-            .await?)
+            .await?;
+        self.cache.users.insert(user_id, user.clone());
+        Ok(user)
     }
 
-    async fn get_group_details(&self, group_id: GroupId) -> Result<GroupIdAndName> {
+    async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails> {
         let query = Query::select()
             .column(Groups::GroupId)
             .column(Groups::DisplayName)
+            .column(Groups::Description)
+            .column(Groups::Email)
             .from(Groups::Table)
             .and_where(Expr::col(Groups::GroupId).eq(group_id))
             .to_string(DbQueryBuilder {});
 
-        Ok(sqlx::query_as::<_, GroupIdAndName>(&query)
+        Ok(sqlx::query_as::<_, GroupDetails>(&query)
             .fetch_one(&self.sql_pool)
             .await?)
     }
@@ -202,7 +822,13 @@ impl BackendHandler for SqlBackendHandler {
     async fn get_user_groups(&self, user: &str) -> Result<HashSet<GroupIdAndName>> {
         if user == self.config.ldap_user_dn {
             let mut groups = HashSet::new();
-            groups.insert(GroupIdAndName(GroupId(1), "lldap_admin".to_string()));
+            groups.insert(GroupIdAndName(
+                GroupId(1),
+                Role::Admin.group_name().to_string(),
+            ));
+            return Ok(groups);
+        }
+        if let Some(groups) = self.cache.user_groups.get(user) {
             return Ok(groups);
         }
         let query: String = Query::select()
@@ -217,7 +843,7 @@ impl BackendHandler for SqlBackendHandler {
             .and_where(Expr::col(Memberships::UserId).eq(user))
             .to_string(DbQueryBuilder {});
 
-        sqlx::query(&query)
+        let groups = sqlx::query(&query)
             // Extract the group id from the row.
             .map(|row: DbRow| {
                 GroupIdAndName(
@@ -232,12 +858,26 @@ impl BackendHandler for SqlBackendHandler {
             .into_iter()
             // Transform it into a single result (the first error if any), and group the group_ids
             // into a HashSet.
-            .collect::<sqlx::Result<HashSet<_>>>()
-            // Map the sqlx::Error into a DomainError.
-            .map_err(DomainError::DatabaseError)
+            .collect::<sqlx::Result<HashSet<_>>>()?;
+        let relations = self.get_subgroup_relations().await?;
+        let groups = if relations.is_empty() {
+            groups
+        } else {
+            // Add the groups containing the user's groups.
+            let ancestors = get_ancestors(&relations, groups.iter().map(|g| g.0));
+            self.get_group_ids_and_names()
+                .await?
+                .into_iter()
+                .filter(|g| ancestors.contains(&g.0))
+                .collect()
+        };
+        self.cache.user_groups.insert(user, groups.clone());
+        Ok(groups)
     }
 
     async fn create_user(&self, request: CreateUserRequest) -> Result<()> {
+        self.check_email_is_unique(&request.email, &request.user_id)
+            .await?;
         let columns = vec![
             Users::UserId,
             Users::Email,
@@ -246,12 +886,18 @@ impl BackendHandler for SqlBackendHandler {
             Users::LastName,
             Users::CreationDate,
         ];
+        let first_name = request.first_name.unwrap_or_default();
+        let last_name = request.last_name.unwrap_or_default();
+        let display_name = request
+            .display_name
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| default_display_name(&request.user_id, &first_name, &last_name));
         let values = vec![
             request.user_id.clone().into(),
             request.email.into(),
-            request.display_name.unwrap_or_default().into(),
-            request.first_name.unwrap_or_default().into(),
-            request.last_name.unwrap_or_default().into(),
+            display_name.into(),
+            first_name.into(),
+            last_name.into(),
             chrono::Utc::now().naive_utc().into(),
         ];
         let query = Query::insert()
@@ -260,16 +906,36 @@ impl BackendHandler for SqlBackendHandler {
             .values_panic(values)
             .to_string(DbQueryBuilder {});
         sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.notify_change(ChangeType::Add, ChangedEntry::User(request.user_id));
         Ok(())
     }
 
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()> {
         let mut values = Vec::new();
         if let Some(email) = request.email {
+            self.check_email_is_unique(&email, &request.user_id).await?;
             values.push((Users::Email, email.into()));
         }
-        if let Some(display_name) = request.display_name {
-            values.push((Users::DisplayName, display_name.into()));
+        match request.display_name {
+            // Clearing the display name resets it to the default.
+            Some(display_name) if display_name.is_empty() => {
+                let (first_name, last_name) = match (&request.first_name, &request.last_name) {
+                    (Some(first_name), Some(last_name)) => (first_name.clone(), last_name.clone()),
+                    (first_name, last_name) => {
+                        let user = self.get_user_details(&request.user_id).await?;
+                        (
+                            first_name.clone().unwrap_or(user.first_name),
+                            last_name.clone().unwrap_or(user.last_name),
+                        )
+                    }
+                };
+                values.push((
+                    Users::DisplayName,
+                    default_display_name(&request.user_id, &first_name, &last_name).into(),
+                ));
+            }
+            Some(display_name) => values.push((Users::DisplayName, display_name.into())),
+            None => (),
         }
         if let Some(first_name) = request.first_name {
             values.push((Users::FirstName, first_name.into()));
@@ -277,41 +943,289 @@ impl BackendHandler for SqlBackendHandler {
         if let Some(last_name) = request.last_name {
             values.push((Users::LastName, last_name.into()));
         }
+        if let Some(avatar) = request.avatar {
+            if avatar.is_empty() {
+                values.push((Users::Avatar, sea_query::Value::Null));
+            } else {
+                values.push((
+                    Users::Avatar,
+                    super::avatar::normalize_avatar(&avatar)?.into(),
+                ));
+            }
+        }
+        if let Some(enabled) = request.enabled {
+            values.push((Users::Enabled, enabled.into()));
+        }
+        if let Some(valid_until) = request.valid_until {
+            values.push((
+                Users::ValidUntil,
+                valid_until
+                    .map(|date| date.naive_utc().into())
+                    .unwrap_or(sea_query::Value::Null),
+            ));
+        }
         if values.is_empty() {
             return Ok(());
         }
         let query = Query::update()
             .table(Users::Table)
             .values(values)
-            .and_where(Expr::col(Users::UserId).eq(request.user_id))
+            .and_where(Expr::col(Users::UserId).eq(request.user_id.as_str()))
             .to_string(DbQueryBuilder {});
         sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.notify_change(ChangeType::Modify, ChangedEntry::User(request.user_id));
         Ok(())
     }
 
-    async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
-        let mut values = Vec::new();
-        if let Some(display_name) = request.display_name {
-            values.push((Groups::DisplayName, display_name.into()));
+    async fn rename_user(&self, user_id: &str, new_user_id: &str) -> Result<()> {
+        if new_user_id.is_empty() {
+            return Err(DomainError::InvalidRequest(
+                "The user ID cannot be empty".to_string(),
+            ));
         }
-        if values.is_empty() {
-            return Ok(());
+        if user_id == self.config.ldap_user_dn {
+            return Err(DomainError::InvalidRequest(
+                "Cannot rename the admin user from the configuration".to_string(),
+            ));
+        }
+        let query = Query::select()
+            .column(Users::UserId)
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(new_user_id))
+            .to_string(DbQueryBuilder {});
+        if sqlx::query(&query)
+            .fetch_optional(&self.sql_pool)
+            .await?
+            .is_some()
+        {
+            return Err(DomainError::InvalidRequest(format!(
+                "User `{}` already exists",
+                new_user_id
+            )));
         }
+        // The memberships, API tokens, login history and sessions follow through the foreign keys,
+        // in the same statement.
         let query = Query::update()
-            .table(Groups::Table)
-            .values(values)
-            .and_where(Expr::col(Groups::GroupId).eq(request.group_id))
+            .table(Users::Table)
+            .values(vec![(Users::UserId, new_user_id.into())])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
             .to_string(DbQueryBuilder {});
-        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        if self.has_change_subscribers() {
+            self.notify_change(ChangeType::Delete, ChangedEntry::User(user_id.to_string()));
+            self.notify_change(ChangeType::Add, ChangedEntry::User(new_user_id.to_string()));
+            // The groups now list the new ID.
+            for group in self.get_user_groups(new_user_id).await? {
+                self.notify_change(ChangeType::Modify, ChangedEntry::Group(group.1));
+            }
+        }
         Ok(())
     }
 
-    async fn delete_user(&self, user_id: &str) -> Result<()> {
-        let delete_query = Query::delete()
-            .from_table(Users::Table)
+    async fn unlock_user(&self, user_id: &str) -> Result<()> {
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::FailedLoginAttempts, 0.into()),
+                (Users::LockedUntil, sea_query::Value::Null),
+            ])
             .and_where(Expr::col(Users::UserId).eq(user_id))
             .to_string(DbQueryBuilder {});
-        sqlx::query(&delete_query).execute(&self.sql_pool).await?;
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.cache.clear();
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        Ok(())
+    }
+
+    async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
+        let mut values = Vec::new();
+        if let Some(display_name) = &request.display_name {
+            values.push((Groups::DisplayName, display_name.clone().into()));
+        }
+        let optional_value = |value: &str| {
+            if value.is_empty() {
+                sea_query::Value::Null
+            } else {
+                value.into()
+            }
+        };
+        if let Some(description) = &request.description {
+            values.push((Groups::Description, optional_value(description)));
+        }
+        if let Some(email) = &request.email {
+            values.push((Groups::Email, optional_value(email)));
+        }
+        if values.is_empty() {
+            return Ok(());
+        }
+        let previous_name = if self.has_change_subscribers() {
+            Some(self.get_group_details(request.group_id).await?.display_name)
+        } else {
+            None
+        };
+        let query = Query::update()
+            .table(Groups::Table)
+            .values(values)
+            .and_where(Expr::col(Groups::GroupId).eq(request.group_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        // The names of the groups are cached with the users.
+        self.cache.clear();
+        if let (Some(previous_name), Some(display_name)) = (previous_name, request.display_name) {
+            // The name is the DN of the group.
+            let _ = self.change_sender.send(ChangeEvent {
+                change_type: ChangeType::ModifyDn,
+                entry: ChangedEntry::Group(display_name),
+                previous_entry: Some(ChangedEntry::Group(previous_name)),
+            });
+        }
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<()> {
+        // The groups of the user, including the ones containing them, lose a member.
+        let groups = if self.has_change_subscribers() {
+            self.get_user_groups(user_id).await?
+        } else {
+            HashSet::new()
+        };
+        if self.config.deleted_user_retention_days > 0 {
+            self.keep_deleted_user(user_id).await?;
+        }
+        let delete_query = Query::delete()
+            .from_table(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&delete_query).execute(&self.sql_pool).await?;
+        self.notify_change(ChangeType::Delete, ChangedEntry::User(user_id.to_string()));
+        for group in groups {
+            self.notify_change(ChangeType::Modify, ChangedEntry::Group(group.1));
+        }
+        Ok(())
+    }
+
+    async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>> {
+        let query = Query::select()
+            .column(DeletedUsers::UserId)
+            .column(DeletedUsers::Email)
+            .column(DeletedUsers::DisplayName)
+            .column(DeletedUsers::DeletedAt)
+            .from(DeletedUsers::Table)
+            .order_by(DeletedUsers::DeletedAt, Order::Desc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, DeletedUser>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    async fn restore_user(&self, user_id: &str) -> Result<()> {
+        let query = Query::select()
+            .column(DeletedUsers::Data)
+            .from(DeletedUsers::Table)
+            .and_where(Expr::col(DeletedUsers::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let data: DeletedUserData = bincode::deserialize(
+            &sqlx::query(&query)
+                .fetch_one(&self.sql_pool)
+                .await?
+                .get::<Vec<u8>, _>(&*DeletedUsers::Data.to_string()),
+        )?;
+        if self.get_user_details(user_id).await.is_ok() {
+            return Err(DomainError::InvalidRequest(format!(
+                "Another user `{}` was created since, rename or delete it first",
+                user_id
+            )));
+        }
+        self.check_email_is_unique(&data.user.email, user_id)
+            .await?;
+        let user = data.user;
+        let query = Query::insert()
+            .into_table(Users::Table)
+            .columns(vec![
+                Users::UserId,
+                Users::Email,
+                Users::DisplayName,
+                Users::FirstName,
+                Users::LastName,
+                Users::Avatar,
+                Users::CreationDate,
+                Users::PasswordHash,
+                Users::LegacyPasswordHash,
+                Users::TotpSecret,
+                Users::MfaType,
+                Users::Enabled,
+                Users::ValidUntil,
+                Users::SessionGeneration,
+            ])
+            .values_panic(vec![
+                user_id.into(),
+                user.email.into(),
+                user.display_name.into(),
+                user.first_name.into(),
+                user.last_name.into(),
+                optional_value(user.avatar),
+                user.creation_date.naive_utc().into(),
+                optional_value(user.password_hash),
+                optional_value(user.legacy_password_hash),
+                optional_value(user.totp_secret),
+                optional_value(user.mfa_type),
+                user.enabled.into(),
+                optional_value(user.valid_until.map(|date| date.naive_utc())),
+                user.session_generation.into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        // The groups deleted since are skipped.
+        let existing_groups: HashSet<GroupId> = self
+            .get_group_ids_and_names()
+            .await?
+            .into_iter()
+            .map(|g| g.0)
+            .collect();
+        let groups: Vec<GroupId> = data
+            .groups
+            .into_iter()
+            .filter(|g| existing_groups.contains(g))
+            .collect();
+        for group_id in &groups {
+            let query = Query::insert()
+                .into_table(Memberships::Table)
+                .columns(vec![Memberships::UserId, Memberships::GroupId])
+                .values_panic(vec![user_id.into(), (*group_id).into()])
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(&self.sql_pool).await?;
+        }
+        for group_id in data
+            .managed_groups
+            .into_iter()
+            .filter(|g| existing_groups.contains(g))
+        {
+            let query = Query::insert()
+                .into_table(GroupManagers::Table)
+                .columns(vec![GroupManagers::GroupId, GroupManagers::UserId])
+                .values_panic(vec![group_id.into(), user_id.into()])
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(&self.sql_pool).await?;
+        }
+        self.purge_deleted_user(user_id).await?;
+        self.notify_change(ChangeType::Add, ChangedEntry::User(user_id.to_string()));
+        self.notify_members_changed(groups).await
+    }
+
+    async fn purge_deleted_user(&self, user_id: &str) -> Result<()> {
+        let query = Query::delete()
+            .from_table(DeletedUsers::Table)
+            .and_where(Expr::col(DeletedUsers::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
         Ok(())
     }
 
@@ -328,15 +1242,30 @@ impl BackendHandler for SqlBackendHandler {
             .and_where(Expr::col(Groups::DisplayName).eq(group_name))
             .to_string(DbQueryBuilder {});
         let row = sqlx::query(&query).fetch_one(&self.sql_pool).await?;
+        self.notify_change(ChangeType::Add, ChangedEntry::Group(group_name.to_string()));
         Ok(GroupId(row.get::<i32, _>(&*Groups::GroupId.to_string())))
     }
 
     async fn delete_group(&self, group_id: GroupId) -> Result<()> {
+        // The groups containing this one lose its members.
+        let (name, ancestor_names) = if self.has_change_subscribers() {
+            let name = self.get_group_details(group_id).await?.display_name;
+            let ancestor_names = self.get_group_and_ancestor_names(vec![group_id]).await?;
+            (Some(name), ancestor_names)
+        } else {
+            (None, Vec::new())
+        };
         let delete_query = Query::delete()
             .from_table(Groups::Table)
             .and_where(Expr::col(Groups::GroupId).eq(group_id))
             .to_string(DbQueryBuilder {});
         sqlx::query(&delete_query).execute(&self.sql_pool).await?;
+        if let Some(name) = name {
+            for ancestor in ancestor_names.into_iter().filter(|n| n != &name) {
+                self.notify_change(ChangeType::Modify, ChangedEntry::Group(ancestor));
+            }
+            self.notify_change(ChangeType::Delete, ChangedEntry::Group(name));
+        }
         Ok(())
     }
 
@@ -347,7 +1276,7 @@ impl BackendHandler for SqlBackendHandler {
             .values_panic(vec![user_id.into(), group_id.into()])
             .to_string(DbQueryBuilder {});
         sqlx::query(&query).execute(&self.sql_pool).await?;
-        Ok(())
+        self.notify_members_changed(vec![group_id]).await
     }
 
     async fn remove_user_from_group(&self, user_id: &str, group_id: GroupId) -> Result<()> {
@@ -357,320 +1286,2359 @@ impl BackendHandler for SqlBackendHandler {
             .and_where(Expr::col(Memberships::UserId).eq(user_id))
             .to_string(DbQueryBuilder {});
         sqlx::query(&query).execute(&self.sql_pool).await?;
-        Ok(())
+        self.notify_members_changed(vec![group_id]).await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::domain::sql_tables::init_table;
-    use crate::infra::configuration::ConfigurationBuilder;
-    use lldap_auth::{opaque, registration};
+    async fn add_group_to_group(&self, child: GroupId, parent: GroupId) -> Result<()> {
+        let relations = self.get_subgroup_relations().await?;
+        let descendants = get_descendants(&relations, vec![child]);
+        if descendants.contains(&parent) {
+            return Err(DomainError::InvalidRequest(
+                "A group cannot be nested in itself or in one of its subgroups".to_string(),
+            ));
+        }
+        // The managers of the subgroups would be able to grant the role.
+        if self.grants_role(parent).await? {
+            let managed_groups = self.get_managed_group_ids().await?;
+            if descendants.iter().any(|g| managed_groups.contains(g)) {
+                return Err(DomainError::InvalidRequest(
+                    "The group grants a role: the groups nested in it can't have managers"
+                        .to_string(),
+                ));
+            }
+        }
+        let query = Query::insert()
+            .into_table(SubGroups::Table)
+            .columns(vec![SubGroups::ParentGroupId, SubGroups::ChildGroupId])
+            .values_panic(vec![parent.into(), child.into()])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.notify_members_changed(vec![parent]).await
+    }
 
-    fn get_default_config() -> Configuration {
-        ConfigurationBuilder::default()
-            .verbose(true)
-            .build()
-            .unwrap()
+    async fn remove_group_from_group(&self, child: GroupId, parent: GroupId) -> Result<()> {
+        let query = Query::delete()
+            .from_table(SubGroups::Table)
+            .and_where(Expr::col(SubGroups::ParentGroupId).eq(parent))
+            .and_where(Expr::col(SubGroups::ChildGroupId).eq(child))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.notify_members_changed(vec![parent]).await
     }
 
-    async fn get_in_memory_db() -> Pool {
-        PoolOptions::new().connect("sqlite::memory:").await.unwrap()
+    async fn list_subgroups(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>> {
+        let query = Query::select()
+            .column((Groups::Table, Groups::GroupId))
+            .column(Groups::DisplayName)
+            .from(Groups::Table)
+            .inner_join(
+                SubGroups::Table,
+                Expr::tbl(Groups::Table, Groups::GroupId)
+                    .equals(SubGroups::Table, SubGroups::ChildGroupId),
+            )
+            .and_where(Expr::col(SubGroups::ParentGroupId).eq(group_id))
+            .order_by(Groups::DisplayName, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, GroupIdAndName>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
     }
 
-    async fn get_initialized_db() -> Pool {
-        let sql_pool = get_in_memory_db().await;
-        init_table(&sql_pool).await.unwrap();
-        sql_pool
+    async fn get_group_ancestors(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>> {
+        let ancestors = get_ancestors(&self.get_subgroup_relations().await?, vec![group_id]);
+        let groups = self
+            .get_group_ids_and_names()
+            .await?
+            .into_iter()
+            .filter(|g| ancestors.contains(&g.0))
+            .collect::<Vec<_>>();
+        if !groups.iter().any(|g| g.0 == group_id) {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        Ok(groups)
     }
 
-    async fn insert_user(handler: &SqlBackendHandler, name: &str, pass: &str) {
-        use crate::domain::opaque_handler::OpaqueHandler;
-        insert_user_no_password(handler, name).await;
-        let mut rng = rand::rngs::OsRng;
-        let client_registration_start =
-            opaque::client::registration::start_registration(pass, &mut rng).unwrap();
-        let response = handler
-            .registration_start(registration::ClientRegistrationStartRequest {
-                username: name.to_string(),
-                registration_start_request: client_registration_start.message,
-            })
-            .await
-            .unwrap();
-        let registration_upload = opaque::client::registration::finish_registration(
-            client_registration_start.state,
-            response.registration_response,
-            &mut rng,
-        )
-        .unwrap();
-        handler
-            .registration_finish(registration::ClientRegistrationFinishRequest {
-                server_data: response.server_data,
-                registration_upload: registration_upload.message,
-            })
-            .await
-            .unwrap();
+    async fn add_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()> {
+        if self.grants_role(group_id).await? {
+            let group = self.get_group_details(group_id).await?;
+            return Err(DomainError::InvalidRequest(format!(
+                "The group `{}` grants a role, directly or through the groups it is nested in: it \
+                 can't have managers",
+                group.display_name
+            )));
+        }
+        if self
+            .list_group_managers(group_id)
+            .await?
+            .iter()
+            .any(|m| m == user_id)
+        {
+            return Ok(());
+        }
+        let query = Query::insert()
+            .into_table(GroupManagers::Table)
+            .columns(vec![GroupManagers::GroupId, GroupManagers::UserId])
+            .values_panic(vec![group_id.into(), user_id.into()])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
     }
 
-    async fn insert_user_no_password(handler: &SqlBackendHandler, name: &str) {
-        handler
-            .create_user(CreateUserRequest {
-                user_id: name.to_string(),
-                email: "bob@bob.bob".to_string(),
-                ..Default::default()
-            })
-            .await
-            .unwrap();
+    async fn remove_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()> {
+        let query = Query::delete()
+            .from_table(GroupManagers::Table)
+            .and_where(Expr::col(GroupManagers::GroupId).eq(group_id))
+            .and_where(Expr::col(GroupManagers::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
     }
 
-    async fn insert_group(handler: &SqlBackendHandler, name: &str) -> GroupId {
-        handler.create_group(name).await.unwrap()
+    async fn list_group_managers(&self, group_id: GroupId) -> Result<Vec<String>> {
+        let query = Query::select()
+            .column(GroupManagers::UserId)
+            .from(GroupManagers::Table)
+            .and_where(Expr::col(GroupManagers::GroupId).eq(group_id))
+            .order_by(GroupManagers::UserId, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .fetch_all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|row| row.get::<String, _>(&*GroupManagers::UserId.to_string()))
+            .collect())
     }
 
-    async fn insert_membership(handler: &SqlBackendHandler, group_id: GroupId, user_id: &str) {
-        handler.add_user_to_group(user_id, group_id).await.unwrap();
+    async fn list_managed_groups(&self, user_id: &str) -> Result<Vec<GroupIdAndName>> {
+        let query = Query::select()
+            .column((Groups::Table, Groups::GroupId))
+            .column(Groups::DisplayName)
+            .from(Groups::Table)
+            .inner_join(
+                GroupManagers::Table,
+                Expr::tbl(Groups::Table, Groups::GroupId)
+                    .equals(GroupManagers::Table, GroupManagers::GroupId),
+            )
+            .and_where(Expr::col(GroupManagers::UserId).eq(user_id))
+            .order_by(Groups::DisplayName, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, GroupIdAndName>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
     }
 
-    #[tokio::test]
-    async fn test_bind_admin() {
-        let sql_pool = get_in_memory_db().await;
-        let config = ConfigurationBuilder::default()
-            .ldap_user_dn("admin".to_string())
-            .ldap_user_pass("test".to_string())
-            .build()
-            .unwrap();
-        let handler = SqlBackendHandler::new(config, sql_pool);
-        handler
-            .bind(BindRequest {
-                name: "admin".to_string(),
-                password: "test".to_string(),
-            })
-            .await
-            .unwrap();
+    async fn create_api_token(&self, request: CreateApiTokenRequest) -> Result<(ApiToken, String)> {
+        use rand::{distributions::Alphanumeric, Rng};
+        let mut rng = rand::rngs::OsRng;
+        let token: String = API_TOKEN_PREFIX.to_string()
+            + &std::iter::repeat(())
+                .map(|()| rng.sample(Alphanumeric))
+                .map(char::from)
+                .take(40)
+                .collect::<String>();
+        let token_hash = hash_api_token(&token);
+        let query = Query::insert()
+            .into_table(ApiTokens::Table)
+            .columns(vec![
+                ApiTokens::TokenHash,
+                ApiTokens::Name,
+                ApiTokens::UserId,
+                ApiTokens::CreationDate,
+                ApiTokens::ExpiryDate,
+            ])
+            .values_panic(vec![
+                token_hash.clone().into(),
+                request.name.into(),
+                request.user_id.into(),
+                chrono::Utc::now().naive_utc().into(),
+                request
+                    .expiry_date
+                    .map(|d| d.naive_utc().into())
+                    .unwrap_or(sea_query::Value::Null),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let query = Query::select()
+            .column(ApiTokens::TokenId)
+            .column(ApiTokens::Name)
+            .column(ApiTokens::UserId)
+            .column(ApiTokens::CreationDate)
+            .column(ApiTokens::ExpiryDate)
+            .from(ApiTokens::Table)
+            .and_where(Expr::col(ApiTokens::TokenHash).eq(token_hash))
+            .to_string(DbQueryBuilder {});
+        let api_token = sqlx::query_as::<_, ApiToken>(&query)
+            .fetch_one(&self.sql_pool)
+            .await?;
+        Ok((api_token, token))
     }
 
-    #[tokio::test]
-    async fn test_bind_user() {
-        let sql_pool = get_initialized_db().await;
-        let config = get_default_config();
-        let handler = SqlBackendHandler::new(config, sql_pool.clone());
-        insert_user(&handler, "bob", "bob00").await;
+    async fn list_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        let query = Query::select()
+            .column(ApiTokens::TokenId)
+            .column(ApiTokens::Name)
+            .column(ApiTokens::UserId)
+            .column(ApiTokens::CreationDate)
+            .column(ApiTokens::ExpiryDate)
+            .from(ApiTokens::Table)
+            .order_by(ApiTokens::TokenId, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, ApiToken>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
 
-        handler
-            .bind(BindRequest {
-                name: "bob".to_string(),
-                password: "bob00".to_string(),
-            })
-            .await
-            .unwrap();
-        handler
-            .bind(BindRequest {
-                name: "andrew".to_string(),
-                password: "bob00".to_string(),
+    async fn delete_api_token(&self, token_id: i32) -> Result<()> {
+        let query = Query::delete()
+            .from_table(ApiTokens::Table)
+            .and_where(Expr::col(ApiTokens::TokenId).eq(token_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn create_oidc_client(&self, client: OidcClient) -> Result<String> {
+        use rand::{distributions::Alphanumeric, Rng};
+        if client.client_id.is_empty() {
+            return Err(DomainError::InvalidRequest(
+                "The client ID can't be empty".to_string(),
+            ));
+        }
+        if client.redirect_uris.is_empty()
+            || client.redirect_uris.iter().any(|uri| {
+                uri.contains('\n') || !(uri.starts_with("https://") || uri.starts_with("http://"))
             })
-            .await
-            .unwrap_err();
-        handler
-            .bind(BindRequest {
-                name: "bob".to_string(),
-                password: "wrong_password".to_string(),
+        {
+            return Err(DomainError::InvalidRequest(
+                "The redirect URIs must be absolute http(s) URLs".to_string(),
+            ));
+        }
+        let mut rng = rand::rngs::OsRng;
+        let secret: String = std::iter::repeat(())
+            .map(|()| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(40)
+            .collect();
+        let query = Query::insert()
+            .into_table(OidcClients::Table)
+            .columns(vec![
+                OidcClients::ClientId,
+                OidcClients::DisplayName,
+                OidcClients::RedirectUris,
+                OidcClients::SecretHash,
+            ])
+            .values_panic(vec![
+                client.client_id.into(),
+                client.display_name.into(),
+                client.redirect_uris.join("\n").into(),
+                hash_api_token(&secret).into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(secret)
+    }
+
+    async fn list_oidc_clients(&self) -> Result<Vec<OidcClient>> {
+        let query = Query::select()
+            .column(OidcClients::ClientId)
+            .column(OidcClients::DisplayName)
+            .column(OidcClients::RedirectUris)
+            .from(OidcClients::Table)
+            .order_by(OidcClients::ClientId, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| OidcClient {
+                client_id: row.get::<String, _>(&*OidcClients::ClientId.to_string()),
+                display_name: row.get::<String, _>(&*OidcClients::DisplayName.to_string()),
+                redirect_uris: row
+                    .get::<String, _>(&*OidcClients::RedirectUris.to_string())
+                    .lines()
+                    .map(str::to_string)
+                    .collect(),
             })
-            .await
-            .unwrap_err();
+            .fetch_all(&self.sql_pool)
+            .await?)
     }
 
-    #[tokio::test]
-    async fn test_user_no_password() {
-        let sql_pool = get_initialized_db().await;
-        let config = get_default_config();
+    async fn delete_oidc_client(&self, client_id: &str) -> Result<()> {
+        let query = Query::delete()
+            .from_table(OidcClients::Table)
+            .and_where(Expr::col(OidcClients::ClientId).eq(client_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        // A new client with the same ID will have to ask again.
+        let query = Query::delete()
+            .from_table(OidcConsents::Table)
+            .and_where(Expr::col(OidcConsents::ClientId).eq(client_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn import_password_hash(&self, user_id: &str, password_hash: &str) -> Result<()> {
+        let password_hash = super::legacy_password::normalize_hash(password_hash)?;
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::LegacyPasswordHash, password_hash.into()),
+                (Users::PasswordHash, sea_query::Value::Null),
+            ])
+            .value_expr(Users::SessionGeneration, next_session_generation())
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.cache.clear();
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        Ok(())
+    }
+
+    async fn add_audit_log_entry(&self, request: CreateAuditLogEntryRequest) -> Result<()> {
+        let query = Query::insert()
+            .into_table(AuditLog::Table)
+            .columns(vec![
+                AuditLog::Timestamp,
+                AuditLog::Actor,
+                AuditLog::Source,
+                AuditLog::Action,
+                AuditLog::Target,
+                AuditLog::Details,
+            ])
+            .values_panic(vec![
+                chrono::Utc::now().naive_utc().into(),
+                request.actor.into(),
+                request.source.into(),
+                request.action.into(),
+                request.target.into(),
+                request
+                    .details
+                    .map(Into::into)
+                    .unwrap_or(sea_query::Value::Null),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn list_audit_log(&self, offset: i64, limit: i64) -> Result<Vec<AuditLogEntry>> {
+        if offset < 0 || limit < 0 {
+            return Err(DomainError::InvalidRequest(
+                "The offset and limit can't be negative".to_string(),
+            ));
+        }
+        let query = Query::select()
+            .column(AuditLog::EntryId)
+            .column(AuditLog::Timestamp)
+            .column(AuditLog::Actor)
+            .column(AuditLog::Source)
+            .column(AuditLog::Action)
+            .column(AuditLog::Target)
+            .column(AuditLog::Details)
+            .from(AuditLog::Table)
+            .order_by(AuditLog::EntryId, Order::Desc)
+            .limit(limit as u64)
+            .offset(offset as u64)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, AuditLogEntry>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    async fn add_login_history_entry(&self, request: CreateLoginHistoryEntryRequest) -> Result<()> {
+        let query = Query::insert()
+            .into_table(LoginHistory::Table)
+            .columns(vec![
+                LoginHistory::UserId,
+                LoginHistory::Timestamp,
+                LoginHistory::Method,
+                LoginHistory::Ip,
+                LoginHistory::UserAgent,
+            ])
+            .values_panic(vec![
+                request.user_id.into(),
+                chrono::Utc::now().naive_utc().into(),
+                request.method.into(),
+                request.ip.into(),
+                request
+                    .user_agent
+                    .map(Into::into)
+                    .unwrap_or(sea_query::Value::Null),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn list_login_history(
+        &self,
+        user_id: &str,
+        limit: i64,
+    ) -> Result<Vec<LoginHistoryEntry>> {
+        if limit < 0 {
+            return Err(DomainError::InvalidRequest(
+                "The limit can't be negative".to_string(),
+            ));
+        }
+        let query = Query::select()
+            .column(LoginHistory::EntryId)
+            .column(LoginHistory::UserId)
+            .column(LoginHistory::Timestamp)
+            .column(LoginHistory::Method)
+            .column(LoginHistory::Ip)
+            .column(LoginHistory::UserAgent)
+            .from(LoginHistory::Table)
+            .and_where(Expr::col(LoginHistory::UserId).eq(user_id))
+            .order_by(LoginHistory::EntryId, Order::Desc)
+            .limit(limit as u64)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, LoginHistoryEntry>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    async fn is_totp_enabled(&self, user_id: &str) -> Result<bool> {
+        Ok(self.get_totp_secret(user_id).await?.1)
+    }
+
+    async fn start_totp_enrollment(&self, user_id: &str) -> Result<String> {
+        if self.get_totp_secret(user_id).await?.1 {
+            return Err(DomainError::InvalidRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+        let secret = totp::generate_secret();
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![(Users::TotpSecret, secret.clone().into())])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(secret)
+    }
+
+    async fn confirm_totp_enrollment(&self, user_id: &str, code: &str) -> Result<Vec<String>> {
+        let secret = match self.get_totp_secret(user_id).await? {
+            (_, true) => {
+                return Err(DomainError::InvalidRequest(
+                    "Two-factor authentication is already enabled".to_string(),
+                ))
+            }
+            (None, false) => {
+                return Err(DomainError::InvalidRequest(
+                    "No two-factor authentication enrollment was started".to_string(),
+                ))
+            }
+            (Some(secret), false) => secret,
+        };
+        if !totp::verify_code(&secret, code, chrono::Utc::now()) {
+            return Err(DomainError::InvalidRequest(
+                "Wrong code: check the time of the device".to_string(),
+            ));
+        }
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![(Users::MfaType, totp::TOTP_MFA_TYPE.into())])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.delete_totp_recovery_codes(user_id).await?;
+        let codes = totp::generate_recovery_codes();
+        for code in &codes {
+            let query = Query::insert()
+                .into_table(TotpRecoveryCodes::Table)
+                .columns(vec![TotpRecoveryCodes::UserId, TotpRecoveryCodes::CodeHash])
+                .values_panic(vec![user_id.into(), hash_api_token(code).into()])
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(&self.sql_pool).await?;
+        }
+        Ok(codes)
+    }
+
+    async fn disable_totp(&self, user_id: &str) -> Result<()> {
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::TotpSecret, sea_query::Value::Null),
+                (Users::MfaType, sea_query::Value::Null),
+            ])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        self.delete_totp_recovery_codes(user_id).await
+    }
+
+    async fn list_webauthn_credentials(&self, user_id: &str) -> Result<Vec<WebauthnCredential>> {
+        let query = Query::select()
+            .column(WebauthnCredentials::CredentialId)
+            .column(WebauthnCredentials::UserId)
+            .column(WebauthnCredentials::Name)
+            .column(WebauthnCredentials::CreationDate)
+            .from(WebauthnCredentials::Table)
+            .and_where(Expr::col(WebauthnCredentials::UserId).eq(user_id))
+            .order_by(WebauthnCredentials::CreationDate, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query_as::<_, WebauthnCredential>(&query)
+            .fetch_all(&self.sql_pool)
+            .await?)
+    }
+
+    async fn add_webauthn_credential(
+        &self,
+        request: CreateWebauthnCredentialRequest,
+    ) -> Result<()> {
+        let query = Query::insert()
+            .into_table(WebauthnCredentials::Table)
+            .columns(vec![
+                WebauthnCredentials::CredentialId,
+                WebauthnCredentials::UserId,
+                WebauthnCredentials::Name,
+                WebauthnCredentials::Credential,
+                WebauthnCredentials::CreationDate,
+            ])
+            .values_panic(vec![
+                request.credential_id.into(),
+                request.user_id.into(),
+                request.name.into(),
+                request.credential.into(),
+                chrono::Utc::now().naive_utc().into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn delete_webauthn_credential(&self, user_id: &str, credential_id: &str) -> Result<()> {
+        // Checking the user too, so that one can't delete the keys of others.
+        let query = Query::delete()
+            .from_table(WebauthnCredentials::Table)
+            .and_where(Expr::col(WebauthnCredentials::UserId).eq(user_id))
+            .and_where(Expr::col(WebauthnCredentials::CredentialId).eq(credential_id))
+            .to_string(DbQueryBuilder {});
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        Ok(())
+    }
+
+    async fn list_user_attribute_schema(&self) -> Result<Vec<AttributeSchema>> {
+        let query = Query::select()
+            .column(UserAttributeSchema::AttributeName)
+            .column(UserAttributeSchema::AttributeType)
+            .column(UserAttributeSchema::IsList)
+            .column(UserAttributeSchema::IsEditable)
+            .from(UserAttributeSchema::Table)
+            .order_by(UserAttributeSchema::AttributeName, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        let rows = sqlx::query(&query).fetch_all(&self.sql_pool).await?;
+        rows.into_iter()
+            .map(|row| {
+                let attribute_type =
+                    row.get::<String, _>(&*UserAttributeSchema::AttributeType.to_string());
+                Ok(AttributeSchema {
+                    name: row.get::<String, _>(&*UserAttributeSchema::AttributeName.to_string()),
+                    attribute_type: AttributeType::from_str(&attribute_type).ok_or_else(|| {
+                        DomainError::InternalError(format!(
+                            "Unknown attribute type `{}`",
+                            attribute_type
+                        ))
+                    })?,
+                    is_list: row.get::<bool, _>(&*UserAttributeSchema::IsList.to_string()),
+                    is_editable: row.get::<bool, _>(&*UserAttributeSchema::IsEditable.to_string()),
+                })
+            })
+            .collect()
+    }
+
+    async fn add_user_attribute(&self, schema: AttributeSchema) -> Result<()> {
+        attributes::validate_attribute_name(&schema.name)?;
+        if self.get_user_attribute_schema(&schema.name).await.is_ok() {
+            return Err(DomainError::InvalidRequest(format!(
+                "The attribute `{}` already exists",
+                schema.name
+            )));
+        }
+        let query = Query::insert()
+            .into_table(UserAttributeSchema::Table)
+            .columns(vec![
+                UserAttributeSchema::AttributeName,
+                UserAttributeSchema::AttributeType,
+                UserAttributeSchema::IsList,
+                UserAttributeSchema::IsEditable,
+            ])
+            .values_panic(vec![
+                schema.name.into(),
+                schema.attribute_type.as_str().into(),
+                schema.is_list.into(),
+                schema.is_editable.into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    async fn delete_user_attribute(&self, name: &str) -> Result<()> {
+        // The values first, for their foreign key.
+        let query = Query::delete()
+            .from_table(UserAttributes::Table)
+            .and_where(Expr::col(UserAttributes::AttributeName).eq(name))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let query = Query::delete()
+            .from_table(UserAttributeSchema::Table)
+            .and_where(Expr::col(UserAttributeSchema::AttributeName).eq(name))
+            .to_string(DbQueryBuilder {});
+        let result = sqlx::query(&query).execute(&self.sql_pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+        Ok(())
+    }
+
+    async fn get_user_attributes(&self, user_id: &str) -> Result<Vec<AttributeValue>> {
+        let query = Query::select()
+            .column((UserAttributes::Table, UserAttributes::AttributeName))
+            .column((UserAttributes::Table, UserAttributes::Value))
+            .column((
+                UserAttributeSchema::Table,
+                UserAttributeSchema::AttributeType,
+            ))
+            .from(UserAttributes::Table)
+            .inner_join(
+                UserAttributeSchema::Table,
+                Expr::tbl(UserAttributes::Table, UserAttributes::AttributeName).equals(
+                    UserAttributeSchema::Table,
+                    UserAttributeSchema::AttributeName,
+                ),
+            )
+            .and_where(Expr::col((UserAttributes::Table, UserAttributes::UserId)).eq(user_id))
+            .order_by(
+                (UserAttributes::Table, UserAttributes::AttributeName),
+                Order::Asc,
+            )
+            .order_by(
+                (UserAttributes::Table, UserAttributes::Position),
+                Order::Asc,
+            )
+            .to_string(DbQueryBuilder {});
+        let rows = sqlx::query(&query).fetch_all(&self.sql_pool).await?;
+        let mut attributes: Vec<AttributeValue> = Vec::new();
+        for row in rows {
+            let name = row.get::<String, _>(&*UserAttributes::AttributeName.to_string());
+            let value = match AttributeType::from_str(
+                &row.get::<String, _>(&*UserAttributeSchema::AttributeType.to_string()),
+            ) {
+                Some(attribute_type) => attributes::format_attribute_value(
+                    attribute_type,
+                    &row.get::<Vec<u8>, _>(&*UserAttributes::Value.to_string()),
+                ),
+                None => continue,
+            };
+            match attributes.last_mut() {
+                Some(attribute) if attribute.name == name => attribute.values.push(value),
+                _ => attributes.push(AttributeValue {
+                    name,
+                    values: vec![value],
+                }),
+            }
+        }
+        Ok(attributes)
+    }
+
+    async fn set_user_attribute(
+        &self,
+        user_id: &str,
+        name: &str,
+        values: Vec<String>,
+    ) -> Result<()> {
+        let schema = self.get_user_attribute_schema(name).await?;
+        let values = attributes::parse_attribute_values(&schema, &values)?;
+        let query = Query::delete()
+            .from_table(UserAttributes::Table)
+            .and_where(Expr::col(UserAttributes::UserId).eq(user_id))
+            .and_where(Expr::col(UserAttributes::AttributeName).eq(name))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut query = Query::insert();
+        query.into_table(UserAttributes::Table).columns(vec![
+            UserAttributes::UserId,
+            UserAttributes::AttributeName,
+            UserAttributes::Position,
+            UserAttributes::Value,
+        ]);
+        for (position, value) in values.into_iter().enumerate() {
+            query.values_panic(vec![
+                user_id.into(),
+                name.into(),
+                (position as i32).into(),
+                value.into(),
+            ]);
+        }
+        sqlx::query(&query.to_string(DbQueryBuilder {}))
+            .execute(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl ChangeNotifier for SqlBackendHandler {
+    fn subscribe_to_changes(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.change_sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_tables::init_table;
+    use crate::infra::configuration::ConfigurationBuilder;
+    use lldap_auth::{opaque, registration};
+
+    fn get_default_config() -> Configuration {
+        ConfigurationBuilder::default()
+            .verbose(true)
+            .build()
+            .unwrap()
+    }
+
+    async fn get_initialized_db() -> Pool {
+        let sql_pool = get_test_pool().await;
+        init_table(&sql_pool).await.unwrap();
+        init_deleted_users_table(&sql_pool).await.unwrap();
+        init_totp_recovery_codes_table(&sql_pool).await.unwrap();
+        init_webauthn_credentials_table(&sql_pool).await.unwrap();
+        init_user_attributes_tables(&sql_pool).await.unwrap();
+        init_oidc_consents_table(&sql_pool).await.unwrap();
+        sql_pool
+    }
+
+    async fn insert_user(handler: &SqlBackendHandler, name: &str, pass: &str) {
+        use crate::domain::opaque_handler::OpaqueHandler;
+        insert_user_no_password(handler, name).await;
+        let mut rng = rand::rngs::OsRng;
+        let client_registration_start =
+            opaque::client::registration::start_registration(pass, &mut rng).unwrap();
+        let response = handler
+            .registration_start(registration::ClientRegistrationStartRequest {
+                username: name.to_string(),
+                registration_start_request: client_registration_start.message,
+            })
+            .await
+            .unwrap();
+        let registration_upload = opaque::client::registration::finish_registration(
+            client_registration_start.state,
+            response.registration_response,
+            &mut rng,
+        )
+        .unwrap();
+        handler
+            .registration_finish(registration::ClientRegistrationFinishRequest {
+                server_data: response.server_data,
+                registration_upload: registration_upload.message,
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn insert_user_no_password(handler: &SqlBackendHandler, name: &str) {
+        handler
+            .create_user(CreateUserRequest {
+                user_id: name.to_string(),
+                email: "bob@bob.bob".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn insert_group(handler: &SqlBackendHandler, name: &str) -> GroupId {
+        handler.create_group(name).await.unwrap()
+    }
+
+    async fn insert_membership(handler: &SqlBackendHandler, group_id: GroupId, user_id: &str) {
+        handler.add_user_to_group(user_id, group_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_admin() {
+        let sql_pool = get_test_pool().await;
+        let config = ConfigurationBuilder::default()
+            .ldap_user_dn("admin".to_string())
+            .ldap_user_pass("test".to_string())
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        handler
+            .bind(BindRequest {
+                name: "admin".to_string(),
+                password: "test".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_user() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap();
+        handler
+            .bind(BindRequest {
+                name: "andrew".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap_err();
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "wrong_password".to_string(),
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_unique_emails() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "patrick").await;
+        insert_user_no_password(&handler, "John").await;
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "John".to_string(),
+                email: Some("john@bob.bob".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler
+                .list_users_with_duplicate_emails()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>(),
+            vec!["bob", "patrick"]
+        );
+
+        let config = ConfigurationBuilder::default()
+            .enforce_unique_emails(true)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: "alice".to_string(),
+                email: "JOHN@bob.bob".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        let update_email = |user_id: &str, email: &str| {
+            handler.update_user(UpdateUserRequest {
+                user_id: user_id.to_string(),
+                email: Some(email.to_string()),
+                ..Default::default()
+            })
+        };
+        update_email("patrick", "john@bob.bob").await.unwrap_err();
+        update_email("John", "John@bob.bob").await.unwrap();
+        update_email("patrick", "patrick@bob.bob").await.unwrap();
+        assert!(handler
+            .list_users_with_duplicate_emails()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_default_display_name() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: "bob".to_string(),
+                email: "bob@bob.bob".to_string(),
+                first_name: Some("Bob".to_string()),
+                last_name: Some("Bobberson".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        insert_user_no_password(&handler, "patrick").await;
+        let display_name = |user_id: &'static str| {
+            let handler = handler.clone();
+            async move {
+                handler
+                    .get_user_details(user_id)
+                    .await
+                    .unwrap()
+                    .display_name
+            }
+        };
+        assert_eq!(display_name("bob").await, "Bob Bobberson");
+        assert_eq!(display_name("patrick").await, "patrick");
+
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "patrick".to_string(),
+                display_name: Some("Pat".to_string()),
+                first_name: Some("Patrick".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(display_name("patrick").await, "Pat");
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "patrick".to_string(),
+                display_name: Some(String::new()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(display_name("patrick").await, "Patrick");
+    }
+
+    #[tokio::test]
+    async fn test_update_avatar() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let update_avatar = |avatar: Vec<u8>| {
+            handler.update_user(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                avatar: Some(avatar),
+                ..Default::default()
+            })
+        };
+        update_avatar(crate::domain::avatar::make_test_jpeg(1024, 1024))
+            .await
+            .unwrap();
+        let jpeg = handler
+            .get_user_details("bob")
+            .await
+            .unwrap()
+            .avatar
+            .unwrap();
+        // Re-encoded at a smaller resolution.
+        use image::GenericImageView;
+        assert_eq!(
+            image::load_from_memory(&jpeg).unwrap().dimensions(),
+            (512, 512)
+        );
+        update_avatar(b"GIF89a".to_vec()).await.unwrap_err();
+        update_avatar(vec![0xff, 0xd8, 0xff, 0xe0, 0x42])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            handler.list_users(None).await.unwrap()[0].avatar,
+            Some(jpeg)
+        );
+        update_avatar(Vec::new()).await.unwrap();
+        assert_eq!(handler.get_user_details("bob").await.unwrap().avatar, None);
+    }
+
+    #[tokio::test]
+    async fn test_bind_disabled_user() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                enabled: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(!handler.get_user_details("bob").await.unwrap().enabled);
+
+        assert!(matches!(
+            handler
+                .bind(BindRequest {
+                    name: "bob".to_string(),
+                    password: "bob00".to_string(),
+                })
+                .await,
+            Err(DomainError::UserDisabled(_))
+        ));
+        assert!(matches!(
+            handler
+                .bind(BindRequest {
+                    name: "bob".to_string(),
+                    password: "wrong".to_string(),
+                })
+                .await,
+            Err(DomainError::AuthenticationError(_))
+        ));
+        // The user is still listed.
+        assert_eq!(handler.list_users(None).await.unwrap().len(), 1);
+
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                enabled: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_login_with_email() {
+        let sql_pool = get_initialized_db().await;
+        let config = ConfigurationBuilder::default()
+            .allow_email_login(true)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        let bind = |name: &str, password: &str| {
+            handler.bind(BindRequest {
+                name: name.to_string(),
+                password: password.to_string(),
+            })
+        };
+        // Both users have the same email.
+        handler
+            .get_user_id_for_login("bob@bob.bob")
+            .await
+            .unwrap_err();
+        bind("bob@bob.bob", "bob00").await.unwrap_err();
+
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "patrick".to_string(),
+                email: Some("patrick@example.com".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler
+                .get_user_id_for_login("Patrick@Example.com")
+                .await
+                .unwrap(),
+            "patrick"
+        );
+        bind("Patrick@Example.com", "pass").await.unwrap();
+        bind("bob@bob.bob", "bob00").await.unwrap();
+        bind("bob@bob.bob", "pass").await.unwrap_err();
+        assert_eq!(handler.get_user_id_for_login("bob").await.unwrap(), "bob");
+
+        // Only with `allow_email_login`.
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        assert_eq!(
+            handler
+                .get_user_id_for_login("patrick@example.com")
+                .await
+                .unwrap(),
+            "patrick@example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_expired_user() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        let set_valid_until = |user_id: &str, date| {
+            handler.update_user(UpdateUserRequest {
+                user_id: user_id.to_string(),
+                valid_until: Some(date),
+                ..Default::default()
+            })
+        };
+        let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+        let next_week = chrono::Utc::now() + chrono::Duration::days(7);
+        set_valid_until("bob", Some(yesterday)).await.unwrap();
+        set_valid_until("patrick", Some(next_week)).await.unwrap();
+        let bob = handler.get_user_details("bob").await.unwrap();
+        assert!(bob.enabled);
+        assert!(!bob.is_active());
+
+        assert!(matches!(
+            handler
+                .bind(BindRequest {
+                    name: "bob".to_string(),
+                    password: "bob00".to_string(),
+                })
+                .await,
+            Err(DomainError::UserDisabled(_))
+        ));
+        handler
+            .bind(BindRequest {
+                name: "patrick".to_string(),
+                password: "pass".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let expiring_soon = handler
+            .list_users(Some(RequestFilter::ValidUntilBefore(
+                chrono::Utc::now() + chrono::Duration::days(30),
+            )))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.user_id)
+            .collect::<Vec<_>>();
+        assert_eq!(expiring_soon, vec!["bob", "patrick"]);
+
+        set_valid_until("bob", None).await.unwrap();
+        assert_eq!(
+            handler.get_user_details("bob").await.unwrap().valid_until,
+            None
+        );
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_login_lockout() {
+        let sql_pool = get_initialized_db().await;
+        let config = ConfigurationBuilder::default()
+            .login_lockout_threshold(3)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        let bind = |password: &str| {
+            handler.bind(BindRequest {
+                name: "bob".to_string(),
+                password: password.to_string(),
+            })
+        };
+        // A successful login resets the count.
+        bind("wrong").await.unwrap_err();
+        bind("wrong").await.unwrap_err();
+        bind("bob00").await.unwrap();
+        bind("wrong").await.unwrap_err();
+        bind("wrong").await.unwrap_err();
+        bind("bob00").await.unwrap();
+        assert_eq!(
+            handler.get_user_details("bob").await.unwrap().locked_until,
+            None
+        );
+
+        bind("wrong").await.unwrap_err();
+        bind("wrong").await.unwrap_err();
+        bind("wrong").await.unwrap_err();
+        assert!(matches!(
+            bind("bob00").await,
+            Err(DomainError::UserLocked(_))
+        ));
+        assert!(
+            handler
+                .get_user_details("bob")
+                .await
+                .unwrap()
+                .locked_until
+                .unwrap()
+                > chrono::Utc::now()
+        );
+        assert_eq!(
+            handler.list_audit_log(0, 10).await.unwrap()[0].action,
+            "lockUser"
+        );
+
+        handler.unlock_user("bob").await.unwrap();
+        bind("bob00").await.unwrap();
+        handler.unlock_user("patrick").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_user_no_password() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_list_users() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        insert_user(&handler, "John", "Pa33w0rd!").await;
+        {
+            let users = handler
+                .list_users(None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["John", "bob", "patrick"]);
+        }
+        {
+            let users = handler
+                .list_users(Some(RequestFilter::Equality(
+                    "user_id".to_string(),
+                    "bob".to_string(),
+                )))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["bob"]);
+        }
+        {
+            let users = handler
+                .list_users(Some(RequestFilter::Or(vec![
+                    RequestFilter::Equality("user_id".to_string(), "bob".to_string()),
+                    RequestFilter::Equality("user_id".to_string(), "John".to_string()),
+                ])))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["John", "bob"]);
+        }
+        {
+            let users = handler
+                .list_users(Some(RequestFilter::Not(Box::new(RequestFilter::Equality(
+                    "user_id".to_string(),
+                    "bob".to_string(),
+                )))))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["John", "patrick"]);
+        }
+        let group_1 = insert_group(&handler, "Best Group").await;
+        let group_2 = insert_group(&handler, "Worst Group").await;
+        insert_membership(&handler, group_1, "bob").await;
+        insert_membership(&handler, group_1, "patrick").await;
+        insert_membership(&handler, group_2, "patrick").await;
+        {
+            let users = handler
+                .list_users(Some(RequestFilter::MemberOf("Best Group".to_string())))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["bob", "patrick"]);
+        }
+        {
+            let users = handler
+                .list_users(Some(RequestFilter::Not(Box::new(
+                    RequestFilter::MemberOfId(group_2),
+                ))))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.user_id)
+                .collect::<Vec<_>>();
+            assert_eq!(users, vec!["John", "bob"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_users_page() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        for (user_id, display_name) in &[
+            ("alice", "Zoe"),
+            ("bob", "Mark"),
+            ("carol", "Anna"),
+            ("dave", "Bobby"),
+        ] {
+            handler
+                .create_user(CreateUserRequest {
+                    user_id: user_id.to_string(),
+                    email: format!("{}@example.com", user_id),
+                    display_name: Some(display_name.to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let get_page = |filters, sort, descending, offset, limit| {
+            let handler = &handler;
+            async move {
+                handler
+                    .list_users_page(UserPageRequest {
+                        filters,
+                        sort,
+                        descending,
+                        offset,
+                        limit,
+                    })
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|u| u.user_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            get_page(None, UserColumn::DisplayName, false, 0, 10).await,
+            vec!["carol", "dave", "bob", "alice"]
+        );
+        assert_eq!(
+            get_page(None, UserColumn::UserId, true, 1, 2).await,
+            vec!["carol", "bob"]
+        );
+        assert_eq!(
+            get_page(
+                Some(RequestFilter::Search("BOB".to_string())),
+                UserColumn::UserId,
+                false,
+                0,
+                10
+            )
+            .await,
+            vec!["bob", "dave"]
+        );
+        assert_eq!(
+            handler
+                .count_users(Some(RequestFilter::Search("example".to_string())))
+                .await
+                .unwrap(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_users() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        for i in 0..(STREAM_BUFFER_SIZE * 2) {
+            insert_user(&handler, &format!("user{:03}", i), "password").await;
+        }
+        let users = handler
+            .stream_users(None)
+            .map(|u| u.unwrap().user_id)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(users.len(), STREAM_BUFFER_SIZE * 2);
+        assert_eq!(users[0], "user000");
+        let users = handler
+            .stream_users(Some(RequestFilter::Equality(
+                "user_id".to_string(),
+                "user001".to_string(),
+            )))
+            .map(|u| u.unwrap().user_id)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(users, vec!["user001"]);
+        // Stopping early is fine.
+        assert!(handler.stream_users(None).next().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_cache() {
+        let sql_pool = get_initialized_db().await;
+        let config = ConfigurationBuilder::default()
+            .ldap_cache_ttl_seconds(60)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        let group = insert_group(&handler, "Bobbersons").await;
+        insert_membership(&handler, group, "bob").await;
+        let bob_filter = || {
+            Some(RequestFilter::And(vec![
+                RequestFilter::And(Vec::new()),
+                RequestFilter::Equality("user_id".to_string(), "bob".to_string()),
+            ]))
+        };
+        assert_eq!(
+            handler.get_user_details("bob").await.unwrap().email,
+            "bob@bob.bob"
+        );
+        assert_eq!(handler.get_user_groups("bob").await.unwrap().len(), 1);
+        // A change made directly in the database is not seen until the entries expire.
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![(Users::Email, "robert@bob.bob".into())])
+            .and_where(Expr::col(Users::UserId).eq("bob"))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&sql_pool).await.unwrap();
+        let query = Query::delete()
+            .from_table(Memberships::Table)
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&sql_pool).await.unwrap();
+        assert_eq!(
+            handler.list_users(bob_filter()).await.unwrap()[0].email,
+            "bob@bob.bob"
+        );
+        assert_eq!(handler.get_user_groups("bob").await.unwrap().len(), 1);
+        // The writes made through the handler empty the cache.
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                display_name: Some("Bob".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.list_users(bob_filter()).await.unwrap()[0].email,
+            "robert@bob.bob"
+        );
+        assert!(handler.get_user_groups("bob").await.unwrap().is_empty());
+        assert!(handler
+            .list_users(Some(RequestFilter::Equality(
+                "user_id".to_string(),
+                "nobody".to_string()
+            )))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_groups() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        insert_user(&handler, "John", "Pa33w0rd!").await;
+        let group_1 = insert_group(&handler, "Best Group").await;
+        let group_2 = insert_group(&handler, "Worst Group").await;
+        let group_3 = insert_group(&handler, "Empty Group").await;
+        insert_membership(&handler, group_1, "bob").await;
+        insert_membership(&handler, group_1, "patrick").await;
+        insert_membership(&handler, group_2, "patrick").await;
+        insert_membership(&handler, group_2, "John").await;
+        assert_eq!(
+            handler.list_groups().await.unwrap(),
+            vec![
+                Group {
+                    id: group_1,
+                    display_name: "Best Group".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["bob".to_string(), "patrick".to_string()]
+                },
+                Group {
+                    id: group_3,
+                    display_name: "Empty Group".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec![]
+                },
+                Group {
+                    id: group_2,
+                    display_name: "Worst Group".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["John".to_string(), "patrick".to_string()]
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_group_attributes() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        let group = insert_group(&handler, "Best Group").await;
+        handler
+            .update_group(UpdateGroupRequest {
+                group_id: group,
+                display_name: None,
+                description: Some("The best".to_string()),
+                email: Some("best@bob.bob".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_group_details(group).await.unwrap(),
+            GroupDetails {
+                group_id: group,
+                display_name: "Best Group".to_string(),
+                description: Some("The best".to_string()),
+                email: Some("best@bob.bob".to_string()),
+            }
+        );
+        let listed = handler.list_groups().await.unwrap().remove(0);
+        assert_eq!(listed.description, Some("The best".to_string()));
+        assert_eq!(listed.email, Some("best@bob.bob".to_string()));
+
+        handler
+            .update_group(UpdateGroupRequest {
+                group_id: group,
+                display_name: None,
+                description: Some(String::new()),
+                email: None,
+            })
+            .await
+            .unwrap();
+        let details = handler.get_group_details(group).await.unwrap();
+        assert_eq!(details.description, None);
+        assert_eq!(details.email, Some("best@bob.bob".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_details() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        let before = chrono::Utc::now();
+        insert_user(&handler, "bob", "bob00").await;
+        {
+            let user = handler.get_user_details("bob").await.unwrap();
+            assert_eq!(user.user_id, "bob".to_string());
+            // The creation date is stored and read back in UTC.
+            let delta = user.creation_date - before;
+            assert!(delta >= chrono::Duration::seconds(-1) && delta < chrono::Duration::minutes(1));
+        }
+        {
+            handler.get_user_details("John").await.unwrap_err();
+        }
+    }
+    #[tokio::test]
+    async fn test_get_user_groups() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        insert_user(&handler, "John", "Pa33w0rd!").await;
+        let group_1 = insert_group(&handler, "Group1").await;
+        let group_2 = insert_group(&handler, "Group2").await;
+        insert_membership(&handler, group_1, "bob").await;
+        insert_membership(&handler, group_1, "patrick").await;
+        insert_membership(&handler, group_2, "patrick").await;
+        let mut bob_groups = HashSet::new();
+        bob_groups.insert(GroupIdAndName(group_1, "Group1".to_string()));
+        let mut patrick_groups = HashSet::new();
+        patrick_groups.insert(GroupIdAndName(group_1, "Group1".to_string()));
+        patrick_groups.insert(GroupIdAndName(group_2, "Group2".to_string()));
+        assert_eq!(handler.get_user_groups("bob").await.unwrap(), bob_groups);
+        assert_eq!(
+            handler.get_user_groups("patrick").await.unwrap(),
+            patrick_groups
+        );
+        assert_eq!(
+            handler.get_user_groups("John").await.unwrap(),
+            HashSet::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nested_groups() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        let parent = insert_group(&handler, "Parent").await;
+        let child = insert_group(&handler, "Child").await;
+        let grandchild = insert_group(&handler, "Grandchild").await;
+        insert_membership(&handler, parent, "bob").await;
+        insert_membership(&handler, grandchild, "patrick").await;
+        handler.add_group_to_group(child, parent).await.unwrap();
+        handler.add_group_to_group(grandchild, child).await.unwrap();
+        // Cycles are rejected.
+        handler
+            .add_group_to_group(parent, grandchild)
+            .await
+            .unwrap_err();
+        handler.add_group_to_group(child, child).await.unwrap_err();
+
+        assert_eq!(
+            handler.list_subgroups(parent).await.unwrap(),
+            vec![GroupIdAndName(child, "Child".to_string())]
+        );
+        let patrick_groups = handler.get_user_groups("patrick").await.unwrap();
+        assert_eq!(
+            patrick_groups
+                .into_iter()
+                .map(|g| g.0)
+                .collect::<HashSet<_>>(),
+            [parent, child, grandchild]
+                .iter()
+                .copied()
+                .collect::<HashSet<_>>()
+        );
+        let users = handler
+            .list_users(Some(RequestFilter::MemberOf("Parent".to_string())))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.user_id)
+            .collect::<Vec<_>>();
+        assert_eq!(users, vec!["bob", "patrick"]);
+        let groups = handler.list_groups().await.unwrap();
+        assert_eq!(groups[0].display_name, "Child");
+        assert_eq!(groups[0].users, vec!["patrick"]);
+        assert_eq!(groups[2].display_name, "Parent");
+        assert_eq!(groups[2].users, vec!["bob", "patrick"]);
+
+        handler
+            .remove_group_from_group(child, parent)
+            .await
+            .unwrap();
+        assert_eq!(handler.get_user_groups("patrick").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_group_managers() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
         let handler = SqlBackendHandler::new(config, sql_pool.clone());
         insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "patrick").await;
+        let team = insert_group(&handler, "Team").await;
+        let admins = insert_group(&handler, "lldap_admin").await;
+        handler.add_group_manager("bob", team).await.unwrap();
+        // Adding a manager twice is a no-op.
+        handler.add_group_manager("bob", team).await.unwrap();
+        handler.add_group_manager("patrick", team).await.unwrap();
+        // The groups granting a role can't be delegated.
+        handler.add_group_manager("bob", admins).await.unwrap_err();
+        // Nor the groups nested in them.
+        let admin_team = insert_group(&handler, "Admin team").await;
+        handler
+            .add_group_to_group(admin_team, admins)
+            .await
+            .unwrap();
+        handler
+            .add_group_manager("bob", admin_team)
+            .await
+            .unwrap_err();
+        // And a managed group can't be nested in them.
+        handler.add_group_to_group(team, admins).await.unwrap_err();
+        handler
+            .add_group_manager("bob", GroupId(1000))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            handler.list_group_managers(team).await.unwrap(),
+            vec!["bob", "patrick"]
+        );
+        assert_eq!(
+            handler.list_managed_groups("bob").await.unwrap(),
+            vec![GroupIdAndName(team, "Team".to_string())]
+        );
+
+        handler.remove_group_manager("patrick", team).await.unwrap();
+        assert_eq!(
+            handler.list_group_managers(team).await.unwrap(),
+            vec!["bob"]
+        );
+        handler.delete_user("bob").await.unwrap();
+        assert!(handler.list_group_managers(team).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+
+        insert_user(&handler, "val", "s3np4i").await;
+        insert_user(&handler, "Hector", "Be$t").await;
+        insert_user(&handler, "Jennz", "boupBoup").await;
+
+        // Remove a user
+        let _request_result = handler.delete_user("Jennz").await.unwrap();
+
+        let users = handler
+            .list_users(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.user_id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(users, vec!["Hector", "val"]);
+
+        // Insert new user and remove two
+        insert_user(&handler, "NewBoi", "Joni").await;
+        let _request_result = handler.delete_user("Hector").await.unwrap();
+        let _request_result = handler.delete_user("NewBoi").await.unwrap();
+
+        let users = handler
+            .list_users(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.user_id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(users, vec!["val"]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_deleted_user() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        let group = insert_group(&handler, "Group1").await;
+        let deleted_group = insert_group(&handler, "Group2").await;
+        insert_membership(&handler, group, "bob").await;
+        insert_membership(&handler, deleted_group, "bob").await;
+
+        handler.delete_user("bob").await.unwrap();
+        handler.delete_group(deleted_group).await.unwrap();
+        handler.get_user_details("bob").await.unwrap_err();
+        let deleted = handler.list_deleted_users().await.unwrap();
+        assert_eq!(
+            deleted
+                .iter()
+                .map(|u| u.user_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["bob"]
+        );
+
+        handler.restore_user("bob").await.unwrap();
+        assert!(handler.list_deleted_users().await.unwrap().is_empty());
+        assert_eq!(
+            handler
+                .get_user_groups("bob")
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.0)
+                .collect::<Vec<_>>(),
+            vec![group]
+        );
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap();
+        handler.restore_user("bob").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_restore_deleted_user_taken_id() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        handler.delete_user("bob").await.unwrap();
+        insert_user(&handler, "bob", "other").await;
+        handler.restore_user("bob").await.unwrap_err();
+        assert_eq!(handler.list_deleted_users().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_purge_deleted_user() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        handler.delete_user("bob").await.unwrap();
+        handler.purge_deleted_user("bob").await.unwrap();
+        assert!(handler.list_deleted_users().await.unwrap().is_empty());
+        handler.purge_deleted_user("bob").await.unwrap_err();
+        handler.restore_user("bob").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_totp_enrollment() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        assert!(!handler.is_totp_enabled("bob").await.unwrap());
+        handler
+            .confirm_totp_enrollment("bob", "123456")
+            .await
+            .unwrap_err();
+
+        let secret = handler.start_totp_enrollment("bob").await.unwrap();
+        // Still pending.
+        assert!(!handler.is_totp_enabled("bob").await.unwrap());
+        let code = totp::code_at(&secret, chrono::Utc::now());
+        let wrong_code = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+        handler
+            .confirm_totp_enrollment("bob", &wrong_code)
+            .await
+            .unwrap_err();
+        let recovery_codes = handler.confirm_totp_enrollment("bob", &code).await.unwrap();
+        assert_eq!(recovery_codes.len(), totp::RECOVERY_CODE_COUNT);
+        assert!(handler.is_totp_enabled("bob").await.unwrap());
+        // A new secret can't replace the confirmed one without disabling it first.
+        handler.start_totp_enrollment("bob").await.unwrap_err();
+
+        handler.disable_totp("bob").await.unwrap();
+        assert!(!handler.is_totp_enabled("bob").await.unwrap());
+        handler.start_totp_enrollment("bob").await.unwrap();
+        handler.disable_totp("patrick").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_webauthn_credentials() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        assert!(handler
+            .list_webauthn_credentials("bob")
+            .await
+            .unwrap()
+            .is_empty());
+        handler
+            .add_webauthn_credential(CreateWebauthnCredentialRequest {
+                credential_id: "AAEC".to_string(),
+                user_id: "bob".to_string(),
+                name: "YubiKey".to_string(),
+                credential: vec![1, 2, 3],
+            })
+            .await
+            .unwrap();
+        let credentials = handler.list_webauthn_credentials("bob").await.unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].credential_id, "AAEC");
+        assert_eq!(credentials[0].name, "YubiKey");
+        assert!(handler
+            .list_webauthn_credentials("patrick")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Only the owner's keys can be deleted.
+        handler
+            .delete_webauthn_credential("patrick", "AAEC")
+            .await
+            .unwrap_err();
+        handler
+            .delete_webauthn_credential("bob", "AAEC")
+            .await
+            .unwrap();
+        assert!(handler
+            .list_webauthn_credentials("bob")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_attributes() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        insert_user(&handler, "patrick", "pass").await;
+        for (name, attribute_type, is_list) in [
+            ("employeeNumber", AttributeType::Integer, false),
+            ("phone", AttributeType::String, true),
+        ]
+        .iter()
+        {
+            handler
+                .add_user_attribute(AttributeSchema {
+                    name: name.to_string(),
+                    attribute_type: *attribute_type,
+                    is_list: *is_list,
+                    is_editable: false,
+                })
+                .await
+                .unwrap();
+        }
+        // Already there.
+        handler
+            .add_user_attribute(AttributeSchema {
+                name: "phone".to_string(),
+                attribute_type: AttributeType::String,
+                is_list: false,
+                is_editable: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            handler
+                .list_user_attribute_schema()
+                .await
+                .unwrap()
+                .iter()
+                .map(|s| (s.name.as_str(), s.attribute_type, s.is_list))
+                .collect::<Vec<_>>(),
+            vec![
+                ("employeeNumber", AttributeType::Integer, false),
+                ("phone", AttributeType::String, true)
+            ]
+        );
 
         handler
-            .bind(BindRequest {
-                name: "bob".to_string(),
-                password: "bob00".to_string(),
-            })
+            .set_user_attribute("bob", "phone", vec!["123".to_string(), "456".to_string()])
+            .await
+            .unwrap();
+        handler
+            .set_user_attribute("bob", "employeeNumber", vec!["42".to_string()])
+            .await
+            .unwrap();
+        handler
+            .set_user_attribute("bob", "employeeNumber", vec!["nope".to_string()])
+            .await
+            .unwrap_err();
+        handler
+            .set_user_attribute("bob", "unknown", vec!["1".to_string()])
             .await
             .unwrap_err();
+        assert_eq!(
+            handler.get_user_attributes("bob").await.unwrap(),
+            vec![
+                AttributeValue {
+                    name: "employeeNumber".to_string(),
+                    values: vec!["42".to_string()],
+                },
+                AttributeValue {
+                    name: "phone".to_string(),
+                    values: vec!["123".to_string(), "456".to_string()],
+                },
+            ]
+        );
+        assert!(handler
+            .get_user_attributes("patrick")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Replaced, then removed.
+        handler
+            .set_user_attribute("bob", "phone", vec!["789".to_string()])
+            .await
+            .unwrap();
+        handler
+            .set_user_attribute("bob", "employeeNumber", Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_user_attributes("bob").await.unwrap(),
+            vec![AttributeValue {
+                name: "phone".to_string(),
+                values: vec!["789".to_string()],
+            }]
+        );
+
+        // Deleting the attribute deletes the values.
+        handler.delete_user_attribute("phone").await.unwrap();
+        handler.delete_user_attribute("phone").await.unwrap_err();
+        assert!(handler.get_user_attributes("bob").await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_list_users() {
+    async fn test_rename_user() {
         let sql_pool = get_initialized_db().await;
-        let config = get_default_config();
-        let handler = SqlBackendHandler::new(config, sql_pool);
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
         insert_user(&handler, "bob", "bob00").await;
         insert_user(&handler, "patrick", "pass").await;
-        insert_user(&handler, "John", "Pa33w0rd!").await;
-        {
-            let users = handler
-                .list_users(None)
-                .await
-                .unwrap()
-                .into_iter()
-                .map(|u| u.user_id)
-                .collect::<Vec<_>>();
-            assert_eq!(users, vec!["John", "bob", "patrick"]);
-        }
-        {
-            let users = handler
-                .list_users(Some(RequestFilter::Equality(
-                    "user_id".to_string(),
-                    "bob".to_string(),
-                )))
-                .await
-                .unwrap()
-                .into_iter()
-                .map(|u| u.user_id)
-                .collect::<Vec<_>>();
-            assert_eq!(users, vec!["bob"]);
-        }
-        {
-            let users = handler
-                .list_users(Some(RequestFilter::Or(vec![
-                    RequestFilter::Equality("user_id".to_string(), "bob".to_string()),
-                    RequestFilter::Equality("user_id".to_string(), "John".to_string()),
-                ])))
-                .await
-                .unwrap()
-                .into_iter()
-                .map(|u| u.user_id)
-                .collect::<Vec<_>>();
-            assert_eq!(users, vec!["John", "bob"]);
-        }
-        {
-            let users = handler
-                .list_users(Some(RequestFilter::Not(Box::new(RequestFilter::Equality(
-                    "user_id".to_string(),
-                    "bob".to_string(),
-                )))))
+        let group = insert_group(&handler, "Group1").await;
+        insert_membership(&handler, group, "bob").await;
+        handler
+            .create_api_token(CreateApiTokenRequest {
+                user_id: "bob".to_string(),
+                name: "script".to_string(),
+                expiry_date: None,
+            })
+            .await
+            .unwrap();
+
+        handler.rename_user("bob", "robert").await.unwrap();
+        handler.get_user_details("bob").await.unwrap_err();
+        handler.get_user_details("robert").await.unwrap();
+        assert_eq!(
+            handler
+                .get_user_groups("robert")
                 .await
                 .unwrap()
                 .into_iter()
-                .map(|u| u.user_id)
-                .collect::<Vec<_>>();
-            assert_eq!(users, vec!["John", "patrick"]);
-        }
+                .map(|g| g.0)
+                .collect::<Vec<_>>(),
+            vec![group]
+        );
+        assert_eq!(
+            handler.list_api_tokens().await.unwrap()[0].user_id,
+            "robert"
+        );
+        handler
+            .bind(BindRequest {
+                name: "robert".to_string(),
+                password: "bob00".to_string(),
+            })
+            .await
+            .unwrap();
+
+        handler.rename_user("robert", "patrick").await.unwrap_err();
+        handler.rename_user("bob", "bobby").await.unwrap_err();
+        handler.rename_user("robert", "").await.unwrap_err();
+        handler.rename_user("admin", "root").await.unwrap_err();
     }
 
     #[tokio::test]
-    async fn test_list_groups() {
+    async fn test_api_tokens() {
         let sql_pool = get_initialized_db().await;
         let config = get_default_config();
         let handler = SqlBackendHandler::new(config, sql_pool.clone());
-        insert_user(&handler, "bob", "bob00").await;
-        insert_user(&handler, "patrick", "pass").await;
-        insert_user(&handler, "John", "Pa33w0rd!").await;
-        let group_1 = insert_group(&handler, "Best Group").await;
-        let group_2 = insert_group(&handler, "Worst Group").await;
-        let group_3 = insert_group(&handler, "Empty Group").await;
-        insert_membership(&handler, group_1, "bob").await;
-        insert_membership(&handler, group_1, "patrick").await;
-        insert_membership(&handler, group_2, "patrick").await;
-        insert_membership(&handler, group_2, "John").await;
+        insert_user_no_password(&handler, "robot").await;
+
+        let (api_token, token) = handler
+            .create_api_token(CreateApiTokenRequest {
+                user_id: "robot".to_string(),
+                name: "terraform".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(token.starts_with(API_TOKEN_PREFIX));
+        assert_eq!(api_token.name, "terraform");
+        assert_eq!(api_token.user_id, "robot");
+        assert_eq!(api_token.expiry_date, None);
         assert_eq!(
-            handler.list_groups().await.unwrap(),
-            vec![
-                Group {
-                    id: group_1,
-                    display_name: "Best Group".to_string(),
-                    users: vec!["bob".to_string(), "patrick".to_string()]
-                },
-                Group {
-                    id: group_3,
-                    display_name: "Empty Group".to_string(),
-                    users: vec![]
-                },
-                Group {
-                    id: group_2,
-                    display_name: "Worst Group".to_string(),
-                    users: vec!["John".to_string(), "patrick".to_string()]
-                },
-            ]
+            handler.list_api_tokens().await.unwrap(),
+            vec![api_token.clone()]
         );
+
+        handler.delete_api_token(api_token.token_id).await.unwrap();
+        assert_eq!(handler.list_api_tokens().await.unwrap(), vec![]);
     }
 
     #[tokio::test]
-    async fn test_get_user_details() {
+    async fn test_oidc_clients() {
+        use crate::infra::tcp_backend_handler::TcpBackendHandler;
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        let client = OidcClient {
+            client_id: "wiki".to_string(),
+            display_name: "The wiki".to_string(),
+            redirect_uris: vec![
+                "https://wiki.example.com/callback".to_string(),
+                "http://localhost:8080/callback".to_string(),
+            ],
+        };
+        let secret = handler.create_oidc_client(client.clone()).await.unwrap();
+        assert_eq!(handler.list_oidc_clients().await.unwrap(), vec![client]);
+        assert!(handler
+            .check_oidc_client_secret("wiki", &secret)
+            .await
+            .unwrap());
+        assert!(!handler
+            .check_oidc_client_secret("wiki", "wrong")
+            .await
+            .unwrap());
+        assert!(handler
+            .create_oidc_client(OidcClient {
+                client_id: "evil".to_string(),
+                display_name: String::new(),
+                redirect_uris: vec!["javascript:alert(1)".to_string()],
+            })
+            .await
+            .is_err());
+
+        insert_user_no_password(&handler, "bob").await;
+        assert_eq!(handler.get_oidc_consent("bob", "wiki").await.unwrap(), None);
+        let scopes = |scopes: &[&str]| scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        handler
+            .set_oidc_consent("bob", "wiki", &scopes(&["openid", "email"]))
+            .await
+            .unwrap();
+        handler
+            .set_oidc_consent("bob", "wiki", &scopes(&["openid", "groups"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_oidc_consent("bob", "wiki").await.unwrap(),
+            Some(scopes(&["openid", "groups"]))
+        );
+
+        handler.delete_oidc_client("wiki").await.unwrap();
+        assert_eq!(handler.list_oidc_clients().await.unwrap(), vec![]);
+        assert!(!handler
+            .check_oidc_client_secret("wiki", &secret)
+            .await
+            .unwrap());
+        assert_eq!(handler.get_oidc_consent("bob", "wiki").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
         let sql_pool = get_initialized_db().await;
         let config = get_default_config();
-        let handler = SqlBackendHandler::new(config, sql_pool);
-        insert_user(&handler, "bob", "bob00").await;
-        {
-            let user = handler.get_user_details("bob").await.unwrap();
-            assert_eq!(user.user_id, "bob".to_string());
-        }
-        {
-            handler.get_user_details("John").await.unwrap_err();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        for target in ["user:bob", "user:patrick", "group:3"] {
+            handler
+                .add_audit_log_entry(CreateAuditLogEntryRequest {
+                    actor: "admin".to_string(),
+                    source: "127.0.0.1".to_string(),
+                    action: "deleteUser".to_string(),
+                    target: target.to_string(),
+                    details: None,
+                })
+                .await
+                .unwrap();
         }
+        let targets =
+            |entries: Vec<AuditLogEntry>| entries.into_iter().map(|e| e.target).collect::<Vec<_>>();
+        assert_eq!(
+            targets(handler.list_audit_log(0, 10).await.unwrap()),
+            vec!["group:3", "user:patrick", "user:bob"]
+        );
+        assert_eq!(
+            targets(handler.list_audit_log(1, 1).await.unwrap()),
+            vec!["user:patrick"]
+        );
+        handler.list_audit_log(-1, 1).await.unwrap_err();
     }
+
     #[tokio::test]
-    async fn test_get_user_groups() {
+    async fn test_login_history() {
         let sql_pool = get_initialized_db().await;
         let config = get_default_config();
         let handler = SqlBackendHandler::new(config, sql_pool.clone());
-        insert_user(&handler, "bob", "bob00").await;
-        insert_user(&handler, "patrick", "pass").await;
-        insert_user(&handler, "John", "Pa33w0rd!").await;
-        let group_1 = insert_group(&handler, "Group1").await;
-        let group_2 = insert_group(&handler, "Group2").await;
-        insert_membership(&handler, group_1, "bob").await;
-        insert_membership(&handler, group_1, "patrick").await;
-        insert_membership(&handler, group_2, "patrick").await;
-        let mut bob_groups = HashSet::new();
-        bob_groups.insert(GroupIdAndName(group_1, "Group1".to_string()));
-        let mut patrick_groups = HashSet::new();
-        patrick_groups.insert(GroupIdAndName(group_1, "Group1".to_string()));
-        patrick_groups.insert(GroupIdAndName(group_2, "Group2".to_string()));
-        assert_eq!(handler.get_user_groups("bob").await.unwrap(), bob_groups);
+        insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "patrick").await;
+        for (user_id, method) in [("bob", "web"), ("patrick", "ldap"), ("bob", "ldap")] {
+            handler
+                .add_login_history_entry(CreateLoginHistoryEntryRequest {
+                    user_id: user_id.to_string(),
+                    method: method.to_string(),
+                    ip: "127.0.0.1".to_string(),
+                    user_agent: None,
+                })
+                .await
+                .unwrap();
+        }
+        let methods = |entries: Vec<LoginHistoryEntry>| {
+            entries.into_iter().map(|e| e.method).collect::<Vec<_>>()
+        };
         assert_eq!(
-            handler.get_user_groups("patrick").await.unwrap(),
-            patrick_groups
+            methods(handler.list_login_history("bob", 10).await.unwrap()),
+            vec!["ldap", "web"]
         );
         assert_eq!(
-            handler.get_user_groups("John").await.unwrap(),
-            HashSet::new()
+            methods(handler.list_login_history("bob", 1).await.unwrap()),
+            vec!["ldap"]
+        );
+        // The history follows the user when renamed, and goes away with them.
+        handler.rename_user("bob", "robert").await.unwrap();
+        assert_eq!(
+            handler
+                .list_login_history("robert", 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        handler.delete_user("robert").await.unwrap();
+        assert_eq!(
+            handler.list_login_history("robert", 10).await.unwrap(),
+            vec![]
         );
+        handler.list_login_history("patrick", -1).await.unwrap_err();
     }
 
     #[tokio::test]
-    async fn test_delete_user() {
+    async fn test_change_notifications() {
         let sql_pool = get_initialized_db().await;
         let config = get_default_config();
         let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let parent = insert_group(&handler, "Parent").await;
+        let child = insert_group(&handler, "Child").await;
+        handler.add_group_to_group(child, parent).await.unwrap();
 
-        insert_user(&handler, "val", "s3np4i").await;
-        insert_user(&handler, "Hector", "Be$t").await;
-        insert_user(&handler, "Jennz", "boupBoup").await;
-
-        // Remove a user
-        let _request_result = handler.delete_user("Jennz").await.unwrap();
+        let mut changes = handler.subscribe_to_changes();
+        insert_membership(&handler, child, "bob").await;
+        let mut modified_groups = vec![changes.try_recv().unwrap(), changes.try_recv().unwrap()];
+        modified_groups.sort_by_key(|c| format!("{:?}", c.entry));
+        assert_eq!(
+            modified_groups,
+            vec![
+                ChangeEvent {
+                    change_type: ChangeType::Modify,
+                    entry: ChangedEntry::Group("Child".to_string()),
+                    previous_entry: None,
+                },
+                ChangeEvent {
+                    change_type: ChangeType::Modify,
+                    entry: ChangedEntry::Group("Parent".to_string()),
+                    previous_entry: None,
+                },
+            ]
+        );
+        assert!(changes.try_recv().is_err());
 
-        let users = handler
-            .list_users(None)
+        handler
+            .update_group(UpdateGroupRequest {
+                group_id: child,
+                display_name: Some("Kid".to_string()),
+                description: None,
+                email: None,
+            })
             .await
-            .unwrap()
-            .into_iter()
-            .map(|u| u.user_id)
-            .collect::<Vec<_>>();
+            .unwrap();
+        assert_eq!(
+            changes.try_recv().unwrap(),
+            ChangeEvent {
+                change_type: ChangeType::ModifyDn,
+                entry: ChangedEntry::Group("Kid".to_string()),
+                previous_entry: Some(ChangedEntry::Group("Child".to_string())),
+            }
+        );
+    }
 
-        assert_eq!(users, vec!["Hector", "val"]);
+    /// Not run with the other tests: times the lookups on a directory of 50k users, to catch the
+    /// queries that scan the tables or run once per row. Run it with
+    /// `cargo test --release -p lldap -- --ignored bench_`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_large_directory() {
+        use std::time::{Duration, Instant};
+        const USERS: usize = 50_000;
+        const GROUPS: usize = 50;
 
-        // Insert new user and remove two
-        insert_user(&handler, "NewBoi", "Joni").await;
-        let _request_result = handler.delete_user("Hector").await.unwrap();
-        let _request_result = handler.delete_user("NewBoi").await.unwrap();
+        async fn timed<T>(
+            name: &str,
+            budget_ms: u64,
+            f: impl std::future::Future<Output = T>,
+        ) -> T {
+            let start = Instant::now();
+            let result = f.await;
+            let elapsed = start.elapsed();
+            println!("{}: {:?}", name, elapsed);
+            assert!(
+                elapsed < Duration::from_millis(budget_ms),
+                "{} took {:?}, more than {}ms",
+                name,
+                elapsed,
+                budget_ms
+            );
+            result
+        }
 
-        let users = handler
-            .list_users(None)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|u| u.user_id)
-            .collect::<Vec<_>>();
+        let sql_pool = get_initialized_db().await;
+        init_indexes(&sql_pool).await.unwrap();
+        let config = ConfigurationBuilder::default()
+            .enforce_unique_emails(true)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        let mut group_ids = Vec::new();
+        for i in 0..GROUPS {
+            group_ids.push(insert_group(&handler, &format!("group_{}", i)).await);
+        }
+        let now = chrono::Utc::now().naive_utc();
+        for chunk in (0..USERS).collect::<Vec<_>>().chunks(1000) {
+            let mut users = Query::insert()
+                .into_table(Users::Table)
+                .columns(vec![
+                    Users::UserId,
+                    Users::Email,
+                    Users::DisplayName,
+                    Users::FirstName,
+                    Users::LastName,
+                    Users::CreationDate,
+                ])
+                .to_owned();
+            let mut memberships = Query::insert()
+                .into_table(Memberships::Table)
+                .columns(vec![Memberships::UserId, Memberships::GroupId])
+                .to_owned();
+            for i in chunk {
+                let user_id = format!("user_{}", i);
+                users.values_panic(vec![
+                    user_id.clone().into(),
+                    format!("{}@example.com", user_id).into(),
+                    format!("User {}", i).into(),
+                    "".into(),
+                    "".into(),
+                    now.into(),
+                ]);
+                memberships.values_panic(vec![user_id.into(), group_ids[i % GROUPS].into()]);
+            }
+            sqlx::query(&users.to_string(DbQueryBuilder {}))
+                .execute(&sql_pool)
+                .await
+                .unwrap();
+            sqlx::query(&memberships.to_string(DbQueryBuilder {}))
+                .execute(&sql_pool)
+                .await
+                .unwrap();
+        }
 
-        assert_eq!(users, vec!["val"]);
+        let user = timed(
+            "get_user_details",
+            20,
+            handler.get_user_details("user_4242"),
+        )
+        .await;
+        assert_eq!(user.unwrap().display_name, "User 4242");
+        let groups = timed("get_user_groups", 50, handler.get_user_groups("user_4242")).await;
+        assert_eq!(groups.unwrap().len(), 1);
+        timed(
+            "update_user with a unique email",
+            50,
+            handler.update_user(UpdateUserRequest {
+                user_id: "user_4242".to_string(),
+                email: Some("User_4242@Example.com".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        let members = timed(
+            "list_users of a group",
+            500,
+            handler.list_users(Some(RequestFilter::MemberOf("group_7".to_string()))),
+        )
+        .await;
+        assert_eq!(members.unwrap().len(), USERS / GROUPS);
+        let groups = timed("list_groups", 2000, handler.list_groups()).await;
+        assert_eq!(groups.unwrap().len(), GROUPS);
+        let users = timed("list_users", 2000, handler.list_users(None)).await;
+        assert_eq!(users.unwrap().len(), USERS);
     }
 }
@@ -1,8 +1,12 @@
 use super::{
     error::*,
-    handler::{BindRequest, LoginHandler},
+    handler::{BackendHandler, BindRequest, CreateAuditLogEntryRequest, LoginHandler},
+    legacy_password,
+    lookup_cache::BindTarget,
     opaque_handler::*,
-    sql_backend_handler::SqlBackendHandler,
+    sql_backend_handler::{
+        is_active_user, lower_email, next_session_generation, SqlBackendHandler,
+    },
     sql_tables::*,
 };
 use async_trait::async_trait;
@@ -40,6 +44,109 @@ fn passwords_match(
 }
 
 impl SqlBackendHandler {
+    /// Fails with `UserLocked` if the user is locked after too many failed logins.
+    async fn check_not_locked(&self, user_id: &str) -> Result<()> {
+        let query = Query::select()
+            .column(Users::UserId)
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .and_where(Expr::col(Users::LockedUntil).gt(chrono::Utc::now().naive_utc()))
+            .to_string(DbQueryBuilder {});
+        if sqlx::query(&query)
+            .fetch_optional(&self.sql_pool)
+            .await?
+            .is_some()
+        {
+            debug!(r#"User "{}" is locked"#, user_id);
+            return Err(DomainError::UserLocked(user_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Counts a failed login, and locks the user once they reach `login_lockout_threshold`.
+    async fn record_failed_login(&self, user_id: &str) -> Result<()> {
+        let threshold = self.config.login_lockout_threshold;
+        if threshold == 0 {
+            return Ok(());
+        }
+        let query = Query::update()
+            .table(Users::Table)
+            .value_expr(
+                Users::FailedLoginAttempts,
+                Expr::cust(&format!("{} + 1", Users::FailedLoginAttempts.to_string())),
+            )
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let query = Query::select()
+            .column(Users::FailedLoginAttempts)
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        let attempts = match sqlx::query(&query).fetch_optional(&self.sql_pool).await? {
+            Some(row) => row.get::<i32, _>(&*Users::FailedLoginAttempts.to_string()),
+            // No such user.
+            None => return Ok(()),
+        };
+        if attempts < threshold {
+            return Ok(());
+        }
+        let locked_until = chrono::Utc::now()
+            + chrono::Duration::minutes(self.config.login_lockout_duration_minutes);
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::FailedLoginAttempts, 0.into()),
+                (Users::LockedUntil, locked_until.naive_utc().into()),
+            ])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        self.cache.clear();
+        warn!(
+            r#"User "{}" locked until {} after {} failed logins"#,
+            user_id, locked_until, attempts
+        );
+        self.add_audit_log_entry(CreateAuditLogEntryRequest {
+            actor: "lldap".to_string(),
+            source: String::new(),
+            action: "lockUser".to_string(),
+            target: format!("user:{}", user_id),
+            details: Some(format!("{} failed logins", attempts)),
+        })
+        .await
+    }
+
+    /// Resets the count of failed logins after a successful one.
+    async fn reset_failed_logins(&self, user_id: &str) -> Result<()> {
+        let query = Query::update()
+            .table(Users::Table)
+            .values(vec![(Users::FailedLoginAttempts, 0.into())])
+            .and_where(Expr::col(Users::UserId).eq(user_id))
+            .and_where(Expr::col(Users::FailedLoginAttempts).gt(0))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    /// Records the outcome of a login for the lockout, keeping the original error if it failed.
+    async fn record_login_result<T>(&self, user_id: &str, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.reset_failed_logins(user_id).await?,
+            Err(DomainError::AuthenticationError(_))
+            | Err(DomainError::AuthenticationProtocolError(_)) => {
+                if let Err(e) = self.record_failed_login(user_id).await {
+                    warn!(
+                        r#"Could not record the failed login of "{}": {}"#,
+                        user_id, e
+                    );
+                }
+            }
+            Err(_) => (),
+        }
+        result
+    }
+
     fn get_orion_secret_key(&self) -> Result<orion::aead::SecretKey> {
         Ok(orion::aead::SecretKey::from_slice(
             self.config.get_server_keys().private(),
@@ -56,6 +163,7 @@ impl SqlBackendHandler {
                 .column(Users::PasswordHash)
                 .from(Users::Table)
                 .and_where(Expr::col(Users::UserId).eq(username))
+                .and_where(is_active_user())
                 .to_string(DbQueryBuilder {});
             if let Some(row) = sqlx::query(&query).fetch_optional(&self.sql_pool).await? {
                 if let Some(bytes) =
@@ -67,7 +175,7 @@ impl SqlBackendHandler {
                     return Ok(None);
                 }
             } else {
-                // No such user.
+                // No such user, or disabled or expired.
                 return Ok(None);
             }
         };
@@ -79,33 +187,63 @@ impl SqlBackendHandler {
     }
 }
 
-#[async_trait]
-impl LoginHandler for SqlBackendHandler {
-    async fn bind(&self, request: BindRequest) -> Result<()> {
-        if request.name == self.config.ldap_user_dn {
-            if request.password == self.config.ldap_user_pass {
-                return Ok(());
-            } else {
-                debug!(r#"Invalid password for LDAP bind user"#);
-                return Err(DomainError::AuthenticationError(request.name));
-            }
+impl SqlBackendHandler {
+    /// The password hashes and status of a user, or None if there is no such user. Cached for the
+    /// LDAP clients binding before every request.
+    async fn get_bind_target(&self, user_id: &str) -> Result<Option<BindTarget>> {
+        if let Some(target) = self.cache.bind_targets.get(user_id) {
+            return Ok(Some(target));
         }
         let query = Query::select()
             .column(Users::PasswordHash)
+            .column(Users::LegacyPasswordHash)
+            .column(Users::Enabled)
+            .column(Users::ValidUntil)
+            .column(Users::LockedUntil)
             .from(Users::Table)
-            .and_where(Expr::col(Users::UserId).eq(request.name.as_str()))
+            .and_where(Expr::col(Users::UserId).eq(user_id))
             .to_string(DbQueryBuilder {});
-        if let Ok(row) = sqlx::query(&query).fetch_one(&self.sql_pool).await {
-            if let Some(password_hash) =
-                row.get::<Option<Vec<u8>>, _>(&*Users::PasswordHash.to_string())
-            {
+        let target = sqlx::query_as::<_, BindTarget>(&query)
+            .fetch_optional(&self.sql_pool)
+            .await?;
+        if let Some(target) = &target {
+            self.cache.bind_targets.insert(user_id, target.clone());
+        }
+        Ok(target)
+    }
+
+    async fn bind_user(&self, request: BindRequest, target: Option<BindTarget>) -> Result<()> {
+        if let Some(target) = target {
+            if let Some(legacy_hash) = &target.legacy_password_hash {
+                if !legacy_password::verify(legacy_hash, &request.password) {
+                    debug!(r#"Invalid password for "{}""#, request.name);
+                } else if !target.is_active() {
+                    debug!(r#"User "{}" is disabled or expired"#, request.name);
+                    return Err(DomainError::UserDisabled(request.name));
+                } else {
+                    // Now that we have the password, replace the imported hash.
+                    info!(r#"Migrating the imported password of "{}""#, request.name);
+                    if let Err(e) = register_password(self, &request.name, &request.password).await
+                    {
+                        warn!(
+                            r#"Could not migrate the password of "{}": {}"#,
+                            request.name, e
+                        );
+                    }
+                    return Ok(());
+                }
+            } else if let Some(password_hash) = &target.password_hash {
                 if let Err(e) = passwords_match(
-                    &password_hash,
+                    password_hash,
                     &request.password,
                     self.config.get_server_setup(),
                     &request.name,
                 ) {
                     debug!(r#"Invalid password for "{}": {}"#, request.name, e);
+                } else if !target.is_active() {
+                    // Only tell that the user is disabled to those who know the password.
+                    debug!(r#"User "{}" is disabled or expired"#, request.name);
+                    return Err(DomainError::UserDisabled(request.name));
                 } else {
                     return Ok(());
                 }
@@ -119,12 +257,75 @@ impl LoginHandler for SqlBackendHandler {
     }
 }
 
+#[async_trait]
+impl LoginHandler for SqlBackendHandler {
+    async fn bind(&self, mut request: BindRequest) -> Result<()> {
+        request.name = self.get_user_id_for_login(&request.name).await?;
+        if request.name == self.config.ldap_user_dn {
+            if request.password == self.config.ldap_user_pass {
+                return Ok(());
+            } else {
+                debug!(r#"Invalid password for LDAP bind user"#);
+                return Err(DomainError::AuthenticationError(request.name));
+            }
+        }
+        let target = self.get_bind_target(&request.name).await?;
+        if target.as_ref().map(BindTarget::is_locked).unwrap_or(false) {
+            debug!(r#"User "{}" is locked"#, request.name);
+            return Err(DomainError::UserLocked(request.name));
+        }
+        let user_id = request.name.clone();
+        let result = self.bind_user(request, target).await;
+        self.record_login_result(&user_id, result).await
+    }
+
+    async fn get_user_id_for_login(&self, name: &str) -> Result<String> {
+        if !self.config.allow_email_login || !name.contains('@') {
+            return Ok(name.to_string());
+        }
+        let query = Query::select()
+            .column(Users::UserId)
+            .from(Users::Table)
+            .and_where(
+                Expr::col(Users::UserId)
+                    .eq(name)
+                    .or(Expr::expr(Expr::cust(&lower_email())).eq(name.to_lowercase())),
+            )
+            .to_string(DbQueryBuilder {});
+        let user_ids = sqlx::query(&query)
+            .map(|row: DbRow| row.get::<String, _>(&*Users::UserId.to_string()))
+            .fetch_all(&self.sql_pool)
+            .await?;
+        // A user ID takes precedence over the emails.
+        if user_ids.iter().any(|id| id == name) {
+            return Ok(name.to_string());
+        }
+        match user_ids.as_slice() {
+            // Fails later, like any unknown user.
+            [] => Ok(name.to_string()),
+            [user_id] => Ok(user_id.clone()),
+            _ => {
+                warn!(
+                    r#"Login with the email "{}", shared by the users {}"#,
+                    name,
+                    user_ids.join(", ")
+                );
+                Err(DomainError::AuthenticationError(format!(
+                    "Several users have the email `{}`, log in with the user ID",
+                    name
+                )))
+            }
+        }
+    }
+}
+
 #[async_trait]
 impl OpaqueHandler for SqlOpaqueHandler {
     async fn login_start(
         &self,
-        request: login::ClientLoginStartRequest,
+        mut request: login::ClientLoginStartRequest,
     ) -> Result<login::ServerLoginStartResponse> {
+        request.username = self.get_user_id_for_login(&request.username).await?;
         let maybe_password_file = self.get_password_file_for_user(&request.username).await?;
 
         let mut rng = rand::rngs::OsRng;
@@ -158,11 +359,14 @@ impl OpaqueHandler for SqlOpaqueHandler {
             &secret_key,
             &base64::decode(&request.server_data)?,
         )?)?;
+        self.check_not_locked(&username).await?;
         // Finish the login: this makes sure the client data is correct, and gives a session key we
         // don't need.
-        let _session_key =
-            opaque::server::login::finish_login(server_login, request.credential_finalization)?
-                .session_key;
+        let result =
+            opaque::server::login::finish_login(server_login, request.credential_finalization)
+                .map(|r| r.session_key)
+                .map_err(DomainError::from);
+        let _session_key = self.record_login_result(&username, result).await?;
 
         Ok(username)
     }
@@ -204,21 +408,30 @@ impl OpaqueHandler for SqlOpaqueHandler {
             // Set the user password to the new password.
             let update_query = Query::update()
                 .table(Users::Table)
-                .values(vec![(
-                    Users::PasswordHash,
-                    password_file.serialize().into(),
-                )])
-                .and_where(Expr::col(Users::UserId).eq(username))
+                .values(vec![
+                    (Users::PasswordHash, password_file.serialize().into()),
+                    // The new password replaces any imported one.
+                    (Users::LegacyPasswordHash, sea_query::Value::Null),
+                ])
+                .value_expr(Users::SessionGeneration, next_session_generation())
+                .and_where(Expr::col(Users::UserId).eq(username.as_str()))
                 .to_string(DbQueryBuilder {});
-            sqlx::query(&update_query).execute(&self.sql_pool).await?;
+            let result = sqlx::query(&update_query).execute(&self.sql_pool).await?;
+            self.cache.clear();
+            if result.rows_affected() != 1 {
+                return Err(DomainError::InternalError(format!(
+                    "No user `{}` to set the password of",
+                    username
+                )));
+            }
         }
         Ok(())
     }
 }
 
 /// Convenience function to set a user's password.
-pub(crate) async fn register_password(
-    opaque_handler: &SqlOpaqueHandler,
+pub(crate) async fn register_password<Handler: OpaqueHandler>(
+    opaque_handler: &Handler,
     username: &str,
     password: &str,
 ) -> Result<()> {
@@ -263,12 +476,8 @@ mod tests {
             .unwrap()
     }
 
-    async fn get_in_memory_db() -> Pool {
-        PoolOptions::new().connect("sqlite::memory:").await.unwrap()
-    }
-
     async fn get_initialized_db() -> Pool {
-        let sql_pool = get_in_memory_db().await;
+        let sql_pool = get_test_pool().await;
         init_table(&sql_pool).await.unwrap();
         sql_pool
     }
@@ -326,6 +535,75 @@ mod tests {
             .await
             .unwrap_err();
         attempt_login(&opaque_handler, "bob", "bob00").await?;
+        // No user to set the password of.
+        register_password(&opaque_handler, "patrick", "bob00")
+            .await
+            .unwrap_err();
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_legacy_password_migration() -> Result<()> {
+        let sql_pool = get_initialized_db().await;
+        let config = get_default_config();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        insert_user_no_password(&handler, "bob").await;
+        handler
+            .import_password_hash(
+                "bob",
+                "{CRYPT}$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+            )
+            .await?;
+        // The web login needs the OPAQUE password file.
+        attempt_login(&handler, "bob", "Hello world!")
+            .await
+            .unwrap_err();
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "wrong".to_string(),
+            })
+            .await
+            .unwrap_err();
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "Hello world!".to_string(),
+            })
+            .await?;
+        attempt_login(&handler, "bob", "Hello world!").await?;
+        handler
+            .import_password_hash("patrick", "$6$salt$hash")
+            .await
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_password_change_revokes_sessions() -> Result<()> {
+        use crate::infra::tcp_backend_handler::TcpBackendHandler;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        let sql_pool = get_initialized_db().await;
+        crate::infra::jwt_sql_tables::init_table(&sql_pool).await?;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        insert_user_no_password(&handler, "bob").await;
+        register_password(&handler, "bob", "bob00").await?;
+        let generation = handler.get_session_generation("bob").await?;
+        let (refresh_token, _) = handler.create_refresh_token("bob").await?;
+        let refresh_token_hash = {
+            let mut s = DefaultHasher::new();
+            refresh_token.hash(&mut s);
+            s.finish()
+        };
+        assert!(handler.check_token(refresh_token_hash, "bob").await?);
+
+        register_password(&handler, "bob", "bob01").await?;
+        assert!(handler.get_session_generation("bob").await? > generation);
+        assert!(!handler.check_token(refresh_token_hash, "bob").await?);
+
+        let generation = handler.get_session_generation("bob").await?;
+        handler.import_password_hash("bob", "$6$salt$hash").await?;
+        assert!(handler.get_session_generation("bob").await? > generation);
         Ok(())
     }
 }
@@ -0,0 +1,233 @@
+//! Time-based one-time passwords (RFC 6238), as generated by the authenticator apps, for the
+//! two-factor authentication of the users.
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng, RngCore};
+use sha1::Sha1;
+
+/// The value of `MfaType` for the users with TOTP enabled.
+pub const TOTP_MFA_TYPE: &str = "totp";
+/// The name of the account in the authenticator apps, next to the user ID.
+pub const TOTP_ISSUER: &str = "LLDAP";
+/// The number of recovery codes given when enabling TOTP, each usable once instead of a code.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The defaults of the authenticator apps, which often ignore other values.
+const DIGITS: u32 = 6;
+const PERIOD_SECONDS: i64 = 30;
+/// 160 bits, as recommended for HMAC-SHA1.
+const SECRET_BYTES: usize = 20;
+/// How many periods before and after the current one are accepted, for the clock drift.
+const ALLOWED_DRIFT: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes in base 32 without padding, as expected in the `otpauth://` URIs.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes base 32, ignoring the case, the padding and the spaces.
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// A new random secret, base32-encoded.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+/// The code for the given number of periods since the epoch (HOTP, RFC 4226).
+fn code_for_counter(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// The code of the base32-encoded secret at this time, like the authenticator apps would show.
+#[cfg(test)]
+pub fn code_at(secret: &str, time: chrono::DateTime<chrono::Utc>) -> String {
+    code_for_counter(
+        &base32_decode(secret).unwrap(),
+        (time.timestamp() / PERIOD_SECONDS) as u64,
+    )
+}
+
+/// Whether the code is the one of the base32-encoded secret at this time, give or take the
+/// allowed drift.
+pub fn verify_code(secret: &str, code: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+    let secret = match base32_decode(secret) {
+        Some(secret) => secret,
+        None => return false,
+    };
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let counter = now.timestamp() / PERIOD_SECONDS;
+    (-ALLOWED_DRIFT..=ALLOWED_DRIFT)
+        .map(|drift| counter + drift)
+        .filter(|counter| *counter >= 0)
+        .any(|counter| code_for_counter(&secret, counter as u64) == code)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The `otpauth://` URI to scan in the authenticator apps, as a QR code.
+pub fn provisioning_uri(issuer: &str, user_id: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(user_id),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        PERIOD_SECONDS
+    )
+}
+
+/// New recovery codes, like "k3x9q-7bm2w", in lowercase to be typed easily.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = OsRng
+                .sample_iter(&Alphanumeric)
+                .map(char::from)
+                .take(10)
+                .collect::<String>()
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(
+            base32_encode(b"12345678901234567890"),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32_decode("my======").unwrap(), b"f");
+        assert_eq!(base32_decode("MZXW 6YTB").unwrap(), b"fooba");
+        assert_eq!(base32_decode("MZ1"), None);
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_BYTES);
+    }
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        // The SHA1 vectors of the RFC, truncated to 6 digits.
+        let secret = base32_encode(b"12345678901234567890");
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ]
+        .iter()
+        {
+            assert!(verify_code(&secret, code, chrono::Utc.timestamp(*time, 0)));
+        }
+    }
+
+    #[test]
+    fn test_verify_code_drift() {
+        let secret = base32_encode(b"12345678901234567890");
+        // The code of 59 is valid one period before and after.
+        assert!(verify_code(&secret, "287082", chrono::Utc.timestamp(89, 0)));
+        assert!(verify_code(
+            &secret,
+            "287 082",
+            chrono::Utc.timestamp(29, 0)
+        ));
+        assert!(!verify_code(
+            &secret,
+            "287082",
+            chrono::Utc.timestamp(120, 0)
+        ));
+        assert!(!verify_code(&secret, "", chrono::Utc.timestamp(59, 0)));
+        assert!(!verify_code(
+            "not base32!",
+            "287082",
+            chrono::Utc.timestamp(59, 0)
+        ));
+        let time = chrono::Utc.timestamp(1111111109, 0);
+        assert_eq!(code_at(&secret, time), "081804");
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        assert_eq!(
+            provisioning_uri("LLDAP", "bob smith", "MZXW6YTB"),
+            "otpauth://totp/LLDAP:bob%20smith?secret=MZXW6YTB&issuer=LLDAP&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(code.chars().nth(5), Some('-'));
+            assert_eq!(code, code.to_lowercase());
+        }
+    }
+}
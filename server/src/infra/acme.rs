@@ -0,0 +1,594 @@
+//! Obtains and renews the TLS certificate of `acme_domain` from an ACME server (RFC 8555), e.g.
+//! Let's Encrypt, with the HTTP-01 challenge: the ACME server fetches
+//! `http://<acme_domain>/.well-known/acme-challenge/<token>`, so the HTTP server must be reachable
+//! on port 80 of the domain. The account key, the certificate and its private key are kept in
+//! `acme_storage_directory`.
+
+use crate::infra::{
+    configuration::Configuration, encryption::write_private_file,
+    tcp_backend_handler::TcpBackendHandler, tcp_server::AppState, tls::TlsStore,
+};
+use actix_web::{web, HttpResponse};
+use anyhow::{bail, Context, Result};
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    sha::sha256,
+    stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509Req, X509},
+};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::info;
+
+/// The certificate is renewed when it expires in less than this.
+const RENEW_BEFORE_DAYS: i32 = 30;
+/// How many times, and how often, the status of an authorization or of an order is checked.
+const POLL_ATTEMPTS: u32 = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+const ACCOUNT_KEY_FILE: &str = "account_key.pem";
+const CERTIFICATE_FILE: &str = "certificate.pem";
+const PRIVATE_KEY_FILE: &str = "private_key.pem";
+
+/// The certificate chain and its private key, in `storage_directory`.
+pub fn certificate_files(storage_directory: &Path) -> (PathBuf, PathBuf) {
+    (
+        storage_directory.join(CERTIFICATE_FILE),
+        storage_directory.join(PRIVATE_KEY_FILE),
+    )
+}
+
+fn encode_base64(data: impl AsRef<[u8]>) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+}
+
+/// The error documents of the ACME server.
+#[derive(Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    detail: Option<String>,
+}
+
+/// The resources whose status changes on the ACME server.
+trait Status {
+    fn status(&self) -> &str;
+}
+
+impl Status for Order {
+    fn status(&self) -> &str {
+        &self.status
+    }
+}
+
+impl Status for Authorization {
+    fn status(&self) -> &str {
+        &self.status
+    }
+}
+
+/// The key of the ACME account, signing the requests (JWS with ES256).
+struct AccountKey {
+    key: EcKey<Private>,
+    /// The coordinates of the public key, for the JWK.
+    x: String,
+    y: String,
+}
+
+impl AccountKey {
+    fn load_or_create(path: &Path) -> Result<Self> {
+        let key = if path.exists() {
+            let pem = std::fs::read(path).context(format!("unable to read {:?}", path))?;
+            EcKey::private_key_from_pem(&pem)
+                .context(format!("Invalid ACME account key {:?}", path))?
+        } else {
+            let key = EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?)?;
+            write_private_file(path, &key.private_key_to_pem()?, None)?;
+            key
+        };
+        Self::new(key)
+    }
+
+    fn new(key: EcKey<Private>) -> Result<Self> {
+        let (mut x, mut y) = (BigNum::new()?, BigNum::new()?);
+        key.public_key().affine_coordinates_gfp(
+            key.group(),
+            &mut x,
+            &mut y,
+            &mut BigNumContext::new()?,
+        )?;
+        Ok(Self {
+            x: encode_base64(x.to_vec_padded(32)?),
+            y: encode_base64(y.to_vec_padded(32)?),
+            key,
+        })
+    }
+
+    fn jwk(&self) -> Value {
+        json!({"crv": "P-256", "kty": "EC", "x": self.x, "y": self.y})
+    }
+
+    /// The RFC 7638 thumbprint of the JWK: the members in lexicographic order, without spaces.
+    fn thumbprint(&self) -> String {
+        let jwk = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            self.x, self.y
+        );
+        encode_base64(sha256(jwk.as_bytes()))
+    }
+
+    /// The content served for the HTTP-01 challenge with this token.
+    fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    /// A JWS in the flattened JSON serialization, with the payload already encoded.
+    fn sign(&self, protected: &Value, payload: &str) -> Result<Value> {
+        let protected = encode_base64(serde_json::to_vec(protected)?);
+        let signing_input = format!("{}.{}", protected, payload);
+        let signature = EcdsaSig::sign(&sha256(signing_input.as_bytes()), &self.key)?;
+        // JWS wants the raw R and S, not the DER signature.
+        let mut raw_signature = signature.r().to_vec_padded(32)?;
+        raw_signature.extend(signature.s().to_vec_padded(32)?);
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": encode_base64(raw_signature),
+        }))
+    }
+}
+
+struct AcmeResponse {
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The requests of one run, signed with the account key.
+struct AcmeSession<'a> {
+    http: reqwest::Client,
+    account_key: &'a AccountKey,
+    directory: Directory,
+    nonce: Option<String>,
+    /// Set once the account is created, to identify the signer instead of the JWK.
+    account_url: Option<String>,
+}
+
+impl<'a> AcmeSession<'a> {
+    async fn new(directory_url: &str, account_key: &'a AccountKey) -> Result<AcmeSession<'a>> {
+        let http = reqwest::Client::new();
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context(format!(
+                "Could not fetch the ACME directory {}",
+                directory_url
+            ))?
+            .json()
+            .await
+            .context("Invalid ACME directory")?;
+        Ok(Self {
+            http,
+            account_key,
+            directory,
+            nonce: None,
+            account_url: None,
+        })
+    }
+
+    async fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("Could not get a nonce from the ACME server")?;
+        replay_nonce(&response).context("No nonce from the ACME server")
+    }
+
+    /// Sends a signed request. Without payload, it's a "POST-as-GET" fetching the resource.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<AcmeResponse> {
+        let payload = match payload {
+            Some(payload) => encode_base64(serde_json::to_vec(payload)?),
+            None => String::new(),
+        };
+        // A nonce can be rejected, e.g. if it expired: the error comes with a fresh one.
+        let mut retried = false;
+        loop {
+            let mut protected = json!({"alg": "ES256", "nonce": self.nonce().await?, "url": url});
+            match &self.account_url {
+                Some(account_url) => protected["kid"] = json!(account_url),
+                None => protected["jwk"] = self.account_key.jwk(),
+            }
+            let body = self.account_key.sign(&protected, &payload)?;
+            let response = self
+                .http
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(serde_json::to_vec(&body)?)
+                .send()
+                .await
+                .context(format!("Could not reach the ACME server at {}", url))?;
+            self.nonce = replay_nonce(&response);
+            let status = response.status();
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string);
+            let body = response.bytes().await?.to_vec();
+            if status.is_success() {
+                return Ok(AcmeResponse { location, body });
+            }
+            let problem: Problem = serde_json::from_slice(&body).unwrap_or_default();
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            bail!(
+                "The ACME server answered {} to {}: {}",
+                status,
+                url,
+                problem.detail.unwrap_or(problem.kind)
+            );
+        }
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<(Option<String>, T)> {
+        let response = self.post(url, payload).await?;
+        let body = serde_json::from_slice(&response.body)
+            .context(format!("Invalid response of the ACME server to {}", url))?;
+        Ok((response.location, body))
+    }
+
+    /// Creates the account of the key, or finds it if it exists.
+    async fn create_account(&mut self, email: Option<&str>) -> Result<()> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        self.account_url = Some(
+            response
+                .location
+                .context("No account URL from the ACME server")?,
+        );
+        Ok(())
+    }
+
+    /// Fetches the resource until its status is "valid".
+    async fn poll<T: DeserializeOwned + Status>(&mut self, url: &str) -> Result<T> {
+        for _ in 0..POLL_ATTEMPTS {
+            let response = self.post(url, None).await?;
+            let resource: T = serde_json::from_slice(&response.body)
+                .context(format!("Invalid response of the ACME server to {}", url))?;
+            match resource.status() {
+                "valid" => return Ok(resource),
+                "pending" | "ready" | "processing" => tokio::time::sleep(POLL_INTERVAL).await,
+                status => bail!(
+                    "The ACME server set {} to {}: {}",
+                    url,
+                    status,
+                    String::from_utf8_lossy(&response.body)
+                ),
+            }
+        }
+        bail!("The ACME server didn't validate {} in time", url)
+    }
+}
+
+/// A certificate signing request for the domain, in DER.
+fn make_csr(domain: &str, key: &PKey<Private>) -> Result<Vec<u8>> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", domain)?;
+    let mut builder = X509Req::builder()?;
+    builder.set_subject_name(&name.build())?;
+    builder.set_pubkey(key)?;
+    let mut extensions = Stack::new()?;
+    extensions.push(
+        SubjectAlternativeName::new()
+            .dns(domain)
+            .build(&builder.x509v3_context(None))?,
+    )?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(key, MessageDigest::sha256())?;
+    Ok(builder.build().to_der()?)
+}
+
+/// Writes the file through a temporary one, so that it's never half written.
+fn replace_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    let temporary = path.with_extension("new");
+    // Left over by an interrupted run.
+    let _ = std::fs::remove_file(&temporary);
+    write_private_file(&temporary, data, None)?;
+    std::fs::rename(&temporary, path).context(format!("unable to replace {:?}", path))
+}
+
+/// Obtains the certificate of `acme_domain`, and renews it before it expires.
+#[derive(Clone)]
+pub struct AcmeClient {
+    domain: String,
+    email: Option<String>,
+    directory_url: String,
+    storage_directory: PathBuf,
+    /// The key authorizations of the pending HTTP-01 challenges, by token.
+    challenges: Arc<RwLock<HashMap<String, String>>>,
+    tls: TlsStore,
+}
+
+impl AcmeClient {
+    pub fn from_config(config: &Configuration, tls: Option<&TlsStore>) -> Option<Self> {
+        Some(Self {
+            domain: config.acme_domain.clone()?,
+            email: config.acme_email.clone(),
+            directory_url: config.acme_directory_url.clone(),
+            storage_directory: PathBuf::from(&config.acme_storage_directory),
+            challenges: Arc::default(),
+            tls: tls?.clone(),
+        })
+    }
+
+    pub fn key_authorization(&self, token: &str) -> Option<String> {
+        self.challenges.read().unwrap().get(token).cloned()
+    }
+
+    /// Whether the certificate is missing, or expires soon.
+    fn renewal_due(&self) -> Result<bool> {
+        if !self.tls.is_loaded() {
+            return Ok(true);
+        }
+        let (certificate_file, _) = certificate_files(&self.storage_directory);
+        let certificate = X509::from_pem(&std::fs::read(&certificate_file)?)?;
+        let remaining = Asn1Time::days_from_now(0)?.diff(certificate.not_after())?;
+        Ok(remaining.days < RENEW_BEFORE_DAYS)
+    }
+
+    /// Renews the certificate when it's due. A manual run always renews it.
+    pub async fn renew_if_due(&self, manual: bool) -> Result<()> {
+        if !manual && !self.renewal_due()? {
+            return Ok(());
+        }
+        info!("Requesting a TLS certificate for {}", self.domain);
+        let (certificate, key) = self.obtain_certificate().await?;
+        self.tls
+            .load(&certificate, &key)
+            .context("The ACME server issued an unusable certificate")?;
+        let (certificate_file, key_file) = certificate_files(&self.storage_directory);
+        replace_private_file(&key_file, &key)?;
+        replace_private_file(&certificate_file, &certificate)?;
+        info!("New TLS certificate for {} installed", self.domain);
+        Ok(())
+    }
+
+    /// Returns the PEM certificate chain and its PEM private key.
+    async fn obtain_certificate(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        std::fs::create_dir_all(&self.storage_directory)
+            .context(format!("unable to create {:?}", self.storage_directory))?;
+        let account_key =
+            AccountKey::load_or_create(&self.storage_directory.join(ACCOUNT_KEY_FILE))?;
+        let mut session = AcmeSession::new(&self.directory_url, &account_key).await?;
+        session.create_account(self.email.as_deref()).await?;
+        let new_order = session.directory.new_order.clone();
+        let (order_url, order) = session
+            .post_json::<Order>(
+                &new_order,
+                Some(&json!({"identifiers": [{"type": "dns", "value": self.domain}]})),
+            )
+            .await?;
+        let order_url = order_url.context("No order URL from the ACME server")?;
+        for authorization_url in &order.authorizations {
+            self.authorize(&mut session, authorization_url).await?;
+        }
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let csr = make_csr(&self.domain, &key)?;
+        session
+            .post(&order.finalize, Some(&json!({ "csr": encode_base64(csr) })))
+            .await?;
+        let order: Order = session.poll(&order_url).await?;
+        let certificate_url = order
+            .certificate
+            .context("No certificate in the valid order")?;
+        let certificate = session.post(&certificate_url, None).await?.body;
+        Ok((certificate, key.private_key_to_pem_pkcs8()?))
+    }
+
+    /// Answers the HTTP-01 challenge of the authorization, and waits for the ACME server to check
+    /// it.
+    async fn authorize(&self, session: &mut AcmeSession<'_>, url: &str) -> Result<()> {
+        let (_, authorization) = session.post_json::<Authorization>(url, None).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|c| c.kind == "http-01")
+            .context("The ACME server didn't offer the http-01 challenge")?;
+        self.challenges.write().unwrap().insert(
+            challenge.token.clone(),
+            session.account_key.key_authorization(&challenge.token),
+        );
+        let result = async {
+            // Tells the ACME server that the challenge can be checked.
+            session.post(&challenge.url, Some(&json!({}))).await?;
+            session.poll::<Authorization>(url).await
+        }
+        .await;
+        self.challenges.write().unwrap().remove(&challenge.token);
+        result.map(|_| ())
+    }
+}
+
+async fn get_challenge<Backend>(
+    data: web::Data<AppState<Backend>>,
+    token: web::Path<String>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + 'static,
+{
+    match data
+        .acme
+        .as_ref()
+        .and_then(|acme| acme.key_authorization(&token.into_inner()))
+    {
+        Some(key_authorization) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + 'static,
+{
+    cfg.service(
+        web::resource("/.well-known/acme-challenge/{token}")
+            .route(web::get().to(get_challenge::<Backend>)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_key() {
+        let account_key = AccountKey::new(
+            EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(account_key.x.len(), 43);
+        let key_authorization = account_key.key_authorization("token");
+        assert_eq!(
+            key_authorization,
+            format!("token.{}", account_key.thumbprint())
+        );
+
+        let jws = account_key
+            .sign(&json!({"alg": "ES256", "nonce": "n", "url": "u"}), "e30")
+            .unwrap();
+        let signing_input = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        let signature =
+            base64::decode_config(jws["signature"].as_str().unwrap(), base64::URL_SAFE_NO_PAD)
+                .unwrap();
+        assert_eq!(signature.len(), 64);
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32]).unwrap(),
+            BigNum::from_slice(&signature[32..]).unwrap(),
+        )
+        .unwrap();
+        assert!(signature
+            .verify(&sha256(signing_input.as_bytes()), &account_key.key)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_make_csr() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let csr = X509Req::from_der(&make_csr("lldap.example.com", &key).unwrap()).unwrap();
+        assert!(csr.verify(&key).unwrap());
+        let common_name = csr
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap()
+            .data()
+            .as_utf8()
+            .unwrap()
+            .to_string();
+        assert_eq!(common_name, "lldap.example.com");
+    }
+
+    #[test]
+    fn test_renewal_due() {
+        let storage_directory =
+            std::env::temp_dir().join(format!("lldap_acme_test_{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&storage_directory).unwrap();
+        let config = crate::infra::configuration::ConfigurationBuilder::default()
+            .acme_domain(Some("lldap.example.com".to_string()))
+            .acme_storage_directory(storage_directory.to_str().unwrap().to_string())
+            .build()
+            .unwrap();
+        let tls = TlsStore::default();
+        let acme = AcmeClient::from_config(&config, Some(&tls)).unwrap();
+        // No certificate yet.
+        assert!(acme.renewal_due().unwrap());
+
+        let (certificate_file, key_file) = certificate_files(&storage_directory);
+        for (days, due) in [(90, false), (10, true)].iter() {
+            let (certificate, key) =
+                crate::infra::tls::tests::self_signed_certificate("lldap.example.com", *days);
+            replace_private_file(&certificate_file, &certificate).unwrap();
+            replace_private_file(&key_file, &key).unwrap();
+            tls.load_files(&certificate_file, &key_file).unwrap();
+            assert_eq!(acme.renewal_due().unwrap(), *due);
+        }
+        std::fs::remove_dir_all(&storage_directory).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! The `user` and `group` commands, which manage the directory through the GraphQL API of a running
+//! server, authenticated with an API token.
+
+use crate::infra::cli::{
+    ApiClientOpts, CommandOutput, GroupAction, GroupOpts, UserAction, UserOpts,
+};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+/// The environment variable read when there is no `--token`, to keep it out of the process list.
+const TOKEN_VARIABLE: &str = "LLDAP_API_TOKEN";
+
+/// An error of the server, classified for the exit code.
+#[derive(thiserror::Error, Debug)]
+pub enum ApiError {
+    #[error("Could not reach the server: {0}")]
+    Unreachable(String),
+    #[error("The API token was rejected: {0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("The server rejected the request: {0}")]
+    Rejected(String),
+}
+
+struct ApiClient {
+    url: String,
+    token: String,
+    client: reqwest::blocking::Client,
+}
+
+/// The token of `--token`, or else of the environment.
+pub(crate) fn get_token(opts: &ApiClientOpts) -> Result<String> {
+    match &opts.token {
+        Some(token) => Ok(token.clone()),
+        None => std::env::var(TOKEN_VARIABLE)
+            .with_context(|| format!("An API token is needed, with --token or {}", TOKEN_VARIABLE)),
+    }
+}
+
+impl ApiClient {
+    fn new(opts: ApiClientOpts) -> Result<Self> {
+        let token = get_token(&opts)?;
+        Ok(ApiClient {
+            url: format!("{}/api/graphql", opts.server_url.trim_end_matches('/')),
+            token,
+            client: reqwest::blocking::Client::new(),
+        })
+    }
+
+    /// Sends a GraphQL request, and returns its `data`.
+    fn query(&self, query: &str, variables: Value) -> Result<Value> {
+        let response = self
+            .client
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()
+            .map_err(|e| ApiError::Unreachable(e.to_string()))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ApiError::Unauthorized(response.text().unwrap_or_default()).into());
+        }
+        if !status.is_success() {
+            return Err(ApiError::Rejected(format!(
+                "{}: {}",
+                status,
+                response.text().unwrap_or_default()
+            ))
+            .into());
+        }
+        let body: Value = response
+            .json()
+            .context("Invalid response from the server")?;
+        Ok(parse_response(body)?)
+    }
+
+    fn group_id(&self, name: &str) -> Result<i64> {
+        let data = self.query("{ groups { id displayName } }", json!({}))?;
+        data["groups"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|g| g["displayName"] == name)
+            .and_then(|g| g["id"].as_i64())
+            .ok_or_else(|| ApiError::NotFound(format!("No group named \"{}\"", name)).into())
+    }
+}
+
+pub(crate) fn parse_response(mut body: Value) -> std::result::Result<Value, ApiError> {
+    if let Some(errors) = body["errors"].as_array().filter(|e| !e.is_empty()) {
+        let messages: Vec<&str> = errors
+            .iter()
+            .filter_map(|e| e["message"].as_str())
+            .collect();
+        let message = messages.join("; ");
+        return Err(if message.to_lowercase().contains("not found") {
+            ApiError::NotFound(message)
+        } else {
+            ApiError::Rejected(message)
+        });
+    }
+    Ok(body["data"].take())
+}
+
+/// One line per entry, for the text output.
+fn format_list(entries: &[Value], format_entry: impl Fn(&Value) -> String) -> String {
+    entries
+        .iter()
+        .map(format_entry)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn user_command(opts: UserOpts) -> Result<CommandOutput> {
+    let client = ApiClient::new(opts.api)?;
+    let mut result = CommandOutput::default();
+    match opts.action {
+        UserAction::Add {
+            user_id,
+            email,
+            display_name,
+            first_name,
+            last_name,
+        } => {
+            client.query(
+                "mutation($user: CreateUserInput!) { createUser(user: $user) { id } }",
+                json!({ "user": {
+                    "id": user_id,
+                    "email": email,
+                    "displayName": display_name,
+                    "firstName": first_name,
+                    "lastName": last_name,
+                }}),
+            )?;
+            result.text = Some(format!("Created the user {}", user_id));
+            result.data.insert("user_id".to_string(), user_id.into());
+        }
+        UserAction::Delete { user_id } => {
+            client.query(
+                "mutation($userId: String!) { deleteUser(userId: $userId) { ok } }",
+                json!({ "userId": user_id }),
+            )?;
+            result.text = Some(format!("Deleted the user {}", user_id));
+            result.data.insert("user_id".to_string(), user_id.into());
+        }
+        UserAction::List { group } => {
+            let filters = group.map(|group| json!({ "memberOf": group }));
+            let mut data = client.query(
+                "query($filters: RequestFilter) { users(filters: $filters) { id email displayName enabled } }",
+                json!({ "filters": filters }),
+            )?;
+            let users = data["users"].take();
+            result.text = Some(format_list(
+                users.as_array().map(Vec::as_slice).unwrap_or_default(),
+                |u| {
+                    format!(
+                        "{}\t{}\t{}{}",
+                        u["id"].as_str().unwrap_or_default(),
+                        u["email"].as_str().unwrap_or_default(),
+                        u["displayName"].as_str().unwrap_or_default(),
+                        if u["enabled"] == false {
+                            "\t(disabled)"
+                        } else {
+                            ""
+                        }
+                    )
+                },
+            ));
+            result.data.insert("users".to_string(), users);
+        }
+    }
+    Ok(result)
+}
+
+pub fn group_command(opts: GroupOpts) -> Result<CommandOutput> {
+    let client = ApiClient::new(opts.api)?;
+    let mut result = CommandOutput::default();
+    match opts.action {
+        GroupAction::Add { name } => {
+            let data = client.query(
+                "mutation($name: String!) { createGroup(name: $name) { id } }",
+                json!({ "name": name }),
+            )?;
+            let group_id = data["createGroup"]["id"].clone();
+            result.text = Some(format!("Created the group {} ({})", name, group_id));
+            result.data.insert("group_id".to_string(), group_id);
+        }
+        GroupAction::Delete { name } => {
+            let group_id = client.group_id(&name)?;
+            client.query(
+                "mutation($groupId: Int!) { deleteGroup(groupId: $groupId) { ok } }",
+                json!({ "groupId": group_id }),
+            )?;
+            result.text = Some(format!("Deleted the group {}", name));
+            result.data.insert("group_id".to_string(), group_id.into());
+        }
+        GroupAction::List => {
+            let mut data = client.query("{ groups { id displayName users { id } } }", json!({}))?;
+            let groups = data["groups"].take();
+            result.text = Some(format_list(
+                groups.as_array().map(Vec::as_slice).unwrap_or_default(),
+                |g| {
+                    format!(
+                        "{}\t{}\t{} members",
+                        g["id"],
+                        g["displayName"].as_str().unwrap_or_default(),
+                        g["users"].as_array().map(Vec::len).unwrap_or_default()
+                    )
+                },
+            ));
+            result.data.insert("groups".to_string(), groups);
+        }
+        GroupAction::AddMember { name, user_id } => {
+            let group_id = client.group_id(&name)?;
+            client.query(
+                "mutation($userId: String!, $groupId: Int!) { addUserToGroup(userId: $userId, groupId: $groupId) { ok } }",
+                json!({ "userId": user_id, "groupId": group_id }),
+            )?;
+            result.text = Some(format!("Added {} to the group {}", user_id, name));
+        }
+        GroupAction::RemoveMember { name, user_id } => {
+            let group_id = client.group_id(&name)?;
+            client.query(
+                "mutation($userId: String!, $groupId: Int!) { removeUserFromGroup(userId: $userId, groupId: $groupId) { ok } }",
+                json!({ "userId": user_id, "groupId": group_id }),
+            )?;
+            result.text = Some(format!("Removed {} from the group {}", user_id, name));
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response(json!({"data": {"users": []}})).unwrap(),
+            json!({"users": []})
+        );
+        assert!(matches!(
+            parse_response(json!({"data": null, "errors": [{"message": "Entity not found"}]})),
+            Err(ApiError::NotFound(_))
+        ));
+        match parse_response(json!({"errors": [{"message": "a"}, {"message": "b"}]})) {
+            Err(ApiError::Rejected(message)) => assert_eq!(message, "a; b"),
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_list() {
+        let entries = vec![json!({"id": "bob"}), json!({"id": "john"})];
+        assert_eq!(
+            format_list(&entries, |e| e["id"].as_str().unwrap().to_string()),
+            "bob\njohn"
+        );
+    }
+}
@@ -1,10 +1,16 @@
 use crate::{
     domain::{
         error::DomainError,
-        handler::{BackendHandler, BindRequest, GroupIdAndName, LoginHandler},
+        handler::{
+            BackendHandler, BindRequest, CreateAuditLogEntryRequest,
+            CreateLoginHistoryEntryRequest, GroupIdAndName, LoginHandler, Role,
+        },
         opaque_handler::OpaqueHandler,
     },
     infra::{
+        jwt_keys::JwtKeyStore,
+        logging,
+        proof_of_work::ProofOfWork,
         tcp_backend_handler::*,
         tcp_server::{error_to_http_response, AppState},
     },
@@ -15,35 +21,70 @@ use actix_web::{
     error::{ErrorBadRequest, ErrorUnauthorized},
     web, HttpRequest, HttpResponse,
 };
+use actix_web_httpauth::extractors::bearer::BearerAuth;
 use anyhow::Result;
 use chrono::prelude::*;
 use futures::future::{ok, Ready};
 use futures_util::{FutureExt, TryFutureExt};
-use hmac::Hmac;
-use jwt::{SignWithKey, VerifyWithKey};
-use lldap_auth::{login, registration, JWTClaims};
-use sha2::Sha512;
+use lldap_auth::{login, proof_of_work, registration, InviteClaims, JWTClaims};
 use std::collections::{hash_map::DefaultHasher, HashSet};
 use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use time::ext::NumericalDuration;
 
-type Token<S> = jwt::Token<jwt::Header, JWTClaims, S>;
-type SignedToken = Token<jwt::token::Signed>;
+/// Path of the `token` cookie: besides `/api`, the `/auth` endpoints that need a logged in user
+/// (changing a password, creating an invite) and the OpenID Connect provider read it.
+const TOKEN_COOKIE_PATH: &str = "/";
+
+/// Expires the `token` cookie of the older versions, restricted to `/api`: the browser would
+/// send it to `/api` before the current one, until it expired. It's a raw header, since the
+/// cookies of a response are kept by name and it would replace the current one.
+fn expire_legacy_token_cookie() -> (actix_web::http::header::HeaderName, String) {
+    (
+        actix_web::http::header::SET_COOKIE,
+        Cookie::build("token", "")
+            .max_age(0.days())
+            .path("/api")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .finish()
+            .to_string(),
+    )
+}
 
-fn create_jwt(key: &Hmac<Sha512>, user: String, groups: HashSet<GroupIdAndName>) -> SignedToken {
+fn create_jwt(
+    keys: &JwtKeyStore,
+    duration: chrono::Duration,
+    user: String,
+    groups: HashSet<GroupIdAndName>,
+    session_generation: i32,
+) -> String {
     let claims = JWTClaims {
-        exp: Utc::now() + chrono::Duration::days(1),
+        exp: Utc::now() + duration,
         iat: Utc::now(),
         user,
         groups: groups.into_iter().map(|g| g.1).collect(),
+        session_generation,
     };
-    let header = jwt::Header {
-        algorithm: jwt::AlgorithmType::Hs512,
-        ..Default::default()
+    keys.sign(&claims)
+}
+
+fn create_invite(
+    keys: &JwtKeyStore,
+    duration: chrono::Duration,
+    user: String,
+    invited_by: String,
+    session_generation: i32,
+) -> String {
+    let claims = InviteClaims {
+        exp: Utc::now() + duration,
+        user,
+        invited_by,
+        session_generation,
     };
-    jwt::Token::new(header, claims).sign_with_key(key).unwrap()
+    keys.sign(&claims)
 }
 
 fn get_refresh_token_from_cookie(
@@ -73,7 +114,8 @@ where
     Backend: TcpBackendHandler + BackendHandler + 'static,
 {
     let backend_handler = &data.backend_handler;
-    let jwt_key = &data.jwt_key;
+    let jwt_keys = &data.jwt_keys;
+    let jwt_duration = data.jwt_duration;
     let (refresh_token_hash, user) = match get_refresh_token_from_cookie(request) {
         Ok(t) => t,
         Err(http_response) => return http_response,
@@ -86,7 +128,12 @@ where
     match res_found {
         Ok(found) => {
             if found {
-                backend_handler.get_user_groups(&user).await
+                backend_handler
+                    .get_user_groups(&user)
+                    .and_then(|g| async {
+                        Ok((g, backend_handler.get_session_generation(&user).await?))
+                    })
+                    .await
             } else {
                 Err(DomainError::AuthenticationError(
                     "Invalid refresh token".to_string(),
@@ -95,13 +142,21 @@ where
         }
         Err(e) => Err(e),
     }
-    .map(|groups| create_jwt(jwt_key, user.to_string(), groups))
+    .map(|(groups, session_generation)| {
+        create_jwt(
+            jwt_keys,
+            jwt_duration,
+            user.to_string(),
+            groups,
+            session_generation,
+        )
+    })
     .map(|token| {
         HttpResponse::Ok()
             .cookie(
                 Cookie::build("token", token.as_str())
-                    .max_age(1.days())
-                    .path("/api")
+                    .max_age(jwt_duration.num_seconds().seconds())
+                    .path(TOKEN_COOKIE_PATH)
                     .http_only(true)
                     .same_site(SameSite::Strict)
                     .finish(),
@@ -148,11 +203,12 @@ where
         .cookie(
             Cookie::build("token", "")
                 .max_age(0.days())
-                .path("/api")
+                .path(TOKEN_COOKIE_PATH)
                 .http_only(true)
                 .same_site(SameSite::Strict)
                 .finish(),
         )
+        .append_header(expire_legacy_token_cookie())
         .cookie(
             Cookie::build("refresh_token", "")
                 .max_age(0.days())
@@ -170,13 +226,54 @@ pub(crate) fn error_to_api_response<T>(error: DomainError) -> ApiResult<T> {
 
 pub type ApiResult<M> = actix_web::Either<web::Json<M>, HttpResponse>;
 
+async fn get_proof_of_work<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse
+where
+    Backend: 'static,
+{
+    HttpResponse::Ok().json(data.proof_of_work.create_challenge())
+}
+
+/// Checks the proof of work of a login attempt. Logged in users (e.g. checking their old
+/// password before changing it) don't need one.
+async fn check_proof_of_work<Backend>(
+    data: &AppState<Backend>,
+    http_request: &HttpRequest,
+    bearer: Option<&BearerAuth>,
+) -> Result<(), DomainError>
+where
+    Backend: TcpBackendHandler,
+{
+    if let Some(bearer) = bearer {
+        if check_if_token_is_valid(data, bearer.token()).await.is_ok() {
+            return Ok(());
+        }
+    }
+    data.proof_of_work.check(
+        http_request
+            .headers()
+            .get(proof_of_work::HEADER)
+            .and_then(|h| h.to_str().ok()),
+    )
+}
+
 async fn opaque_login_start<Backend>(
     data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
+    bearer: Option<BearerAuth>,
     request: web::Json<login::ClientLoginStartRequest>,
 ) -> ApiResult<login::ServerLoginStartResponse>
 where
-    Backend: OpaqueHandler + 'static,
+    Backend: TcpBackendHandler + OpaqueHandler + 'static,
 {
+    if let Err(e) = check_proof_of_work(&data, &http_request, bearer.as_ref()).await {
+        return error_to_api_response(e);
+    }
+    if let Err(e) = data
+        .user_rate_limiter
+        .check(&request.username.to_lowercase())
+    {
+        return ApiResult::Right(HttpResponse::from_error(e.into()));
+    }
     data.backend_handler
         .login_start(request.into_inner())
         .await
@@ -195,19 +292,29 @@ where
     // token.
     data.backend_handler
         .get_user_groups(name)
-        .and_then(|g| async { Ok((g, data.backend_handler.create_refresh_token(name).await?)) })
+        .and_then(|g| async { Ok((g, data.backend_handler.get_session_generation(name).await?)) })
+        .and_then(|(g, s)| async {
+            Ok((g, s, data.backend_handler.create_refresh_token(name).await?))
+        })
         .await
-        .map(|(groups, (refresh_token, max_age))| {
-            let token = create_jwt(&data.jwt_key, name.to_string(), groups);
+        .map(|(groups, session_generation, (refresh_token, max_age))| {
+            let token = create_jwt(
+                &data.jwt_keys,
+                data.jwt_duration,
+                name.to_string(),
+                groups,
+                session_generation,
+            );
             HttpResponse::Ok()
                 .cookie(
                     Cookie::build("token", token.as_str())
-                        .max_age(1.days())
-                        .path("/api")
+                        .max_age(data.jwt_duration.num_seconds().seconds())
+                        .path(TOKEN_COOKIE_PATH)
                         .http_only(true)
                         .same_site(SameSite::Strict)
                         .finish(),
                 )
+                .append_header(expire_legacy_token_cookie())
                 .cookie(
                     Cookie::build("refresh_token", refresh_token + "+" + name)
                         .max_age(max_age.num_days().days())
@@ -221,8 +328,44 @@ where
         .unwrap_or_else(error_to_http_response)
 }
 
+/// Adds the login to the history of the user. A failure doesn't prevent the login.
+async fn record_login<Backend>(
+    data: &web::Data<AppState<Backend>>,
+    http_request: &HttpRequest,
+    user_id: &str,
+    method: &str,
+) where
+    Backend: BackendHandler,
+{
+    logging::record_user(user_id);
+    if let Err(e) = data
+        .backend_handler
+        .add_login_history_entry(CreateLoginHistoryEntryRequest {
+            user_id: user_id.to_string(),
+            method: method.to_string(),
+            ip: http_request
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            user_agent: http_request
+                .headers()
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string),
+        })
+        .await
+    {
+        log::error!(
+            "Could not add the login of {} to the history: {}",
+            user_id,
+            e
+        );
+    }
+}
+
 async fn opaque_login_finish<Backend>(
     data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
     request: web::Json<login::ClientLoginFinishRequest>,
 ) -> HttpResponse
 where
@@ -236,30 +379,129 @@ where
         Ok(n) => n,
         Err(e) => return error_to_http_response(e),
     };
+    record_login(&data, &http_request, &name, "web").await;
     get_login_successful_response(&data, &name).await
 }
 
 async fn post_authorize<Backend>(
     data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
+    bearer: Option<BearerAuth>,
     request: web::Json<BindRequest>,
 ) -> HttpResponse
 where
     Backend: TcpBackendHandler + BackendHandler + LoginHandler + 'static,
 {
-    let name = request.name.clone();
-    if let Err(e) = data.backend_handler.bind(request.into_inner()).await {
+    if let Err(e) = check_proof_of_work(&data, &http_request, bearer.as_ref()).await {
+        return error_to_http_response(e);
+    }
+    if let Err(e) = data.user_rate_limiter.check(&request.name.to_lowercase()) {
+        return HttpResponse::from_error(e.into());
+    }
+    let name = match data
+        .backend_handler
+        .get_user_id_for_login(&request.name)
+        .await
+    {
+        Ok(name) => name,
+        Err(e) => return error_to_http_response(e),
+    };
+    if let Err(e) = data
+        .backend_handler
+        .bind(BindRequest {
+            name: name.clone(),
+            password: request.into_inner().password,
+        })
+        .await
+    {
         return error_to_http_response(e);
     }
+    record_login(&data, &http_request, &name, "web").await;
     get_login_successful_response(&data, &name).await
 }
 
+/// Whether the request comes directly from one of the trusted reverse proxies.
+fn is_from_trusted_proxy(trusted_proxies: &[IpAddr], peer_addr: Option<SocketAddr>) -> bool {
+    peer_addr
+        .map(|addr| trusted_proxies.contains(&addr.ip()))
+        .unwrap_or(false)
+}
+
+/// Log in the user whose ID was set in the trusted header by an authenticating reverse proxy.
+async fn get_trusted_header_login<Backend>(
+    data: web::Data<AppState<Backend>>,
+    request: HttpRequest,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let header = match &data.trusted_header {
+        Some(h) => h,
+        None => return HttpResponse::NotFound().body("Trusted header authentication is disabled"),
+    };
+    if !is_from_trusted_proxy(&data.trusted_proxies, request.peer_addr()) {
+        return HttpResponse::Unauthorized().body("Request not coming from a trusted proxy");
+    }
+    let user_id = match request.headers().get(header).map(|h| h.to_str()) {
+        Some(Ok(user_id)) if !user_id.is_empty() => user_id,
+        _ => return HttpResponse::Unauthorized().body(format!("Missing or invalid {}", header)),
+    };
+    let user = match data.backend_handler.get_user_details(user_id).await {
+        Ok(user) => user,
+        Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)) => {
+            return HttpResponse::Unauthorized().body(format!("Unknown user {}", user_id))
+        }
+        Err(e) => return error_to_http_response(e),
+    };
+    if !user.is_active() {
+        return HttpResponse::Unauthorized().body("User is disabled or expired");
+    }
+    record_login(&data, &request, &user.user_id, "trusted_header").await;
+    get_login_successful_response(&data, &user.user_id).await
+}
+
+/// Whether the user authenticated with `validation_result` can change the password of `user`.
+async fn can_change_password<Backend>(
+    data: &web::Data<AppState<Backend>>,
+    validation_result: &ValidationResults,
+    user: &str,
+) -> std::result::Result<bool, DomainError>
+where
+    Backend: BackendHandler,
+{
+    if validation_result.user == user || validation_result.is_admin() {
+        return Ok(true);
+    }
+    let user_is_admin = data
+        .backend_handler
+        .get_user_groups(user)
+        .await?
+        .iter()
+        .any(|g| g.1 == Role::Admin.group_name());
+    Ok(validation_result.can_change_password(user, user_is_admin))
+}
+
 async fn opaque_register_start<Backend>(
     data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
     request: web::Json<registration::ClientRegistrationStartRequest>,
 ) -> ApiResult<registration::ServerRegistrationStartResponse>
 where
-    Backend: OpaqueHandler + 'static,
+    Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + 'static,
 {
+    let validation_result = match check_if_token_is_valid(&data, bearer.token()).await {
+        Ok(v) => v,
+        Err(e) => return ApiResult::Right(HttpResponse::from_error(e)),
+    };
+    match can_change_password(&data, &validation_result, &request.username).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return ApiResult::Right(
+                HttpResponse::Unauthorized().body("Not authorized to change the user's password"),
+            )
+        }
+        Err(e) => return error_to_api_response(e),
+    }
     data.backend_handler
         .registration_start(request.into_inner())
         .await
@@ -269,11 +511,16 @@ where
 
 async fn opaque_register_finish<Backend>(
     data: web::Data<AppState<Backend>>,
+    bearer: Option<BearerAuth>,
     request: web::Json<registration::ClientRegistrationFinishRequest>,
 ) -> HttpResponse
 where
     Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + 'static,
 {
+    let caller = match &bearer {
+        Some(bearer) => check_if_token_is_valid(&data, bearer.token()).await.ok(),
+        None => None,
+    };
     if let Err(e) = data
         .backend_handler
         .registration_finish(request.into_inner())
@@ -281,9 +528,130 @@ where
     {
         return error_to_http_response(e);
     }
+    // Changing the password revokes all the sessions of the user: if it's the caller's own
+    // password, they get a new one instead of being logged out.
+    if let (Some(caller), Some(bearer)) = (caller, bearer) {
+        if check_if_token_is_valid(&data, bearer.token())
+            .await
+            .is_err()
+        {
+            return get_login_successful_response(&data, &caller.user).await;
+        }
+    }
     HttpResponse::Ok().finish()
 }
 
+#[derive(serde::Deserialize)]
+struct CreateInviteRequest {
+    username: String,
+}
+
+/// Creates an invite link for `username` to set their password, returned as the token to put in
+/// the link.
+async fn post_invite<Backend>(
+    data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
+    bearer: BearerAuth,
+    request: web::Json<CreateInviteRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let validation_result = match check_if_token_is_valid(&data, bearer.token()).await {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+    let username = request.into_inner().username;
+    match can_change_password(&data, &validation_result, &username).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::Unauthorized().body("Not authorized to invite the user");
+        }
+        Err(e) => return error_to_http_response(e),
+    }
+    let session_generation = match data.backend_handler.get_session_generation(&username).await {
+        Ok(g) => g,
+        Err(e) => return error_to_http_response(e),
+    };
+    let token = create_invite(
+        &data.jwt_keys,
+        data.invite_duration,
+        username.clone(),
+        validation_result.user.clone(),
+        session_generation,
+    );
+    if let Err(e) = data
+        .backend_handler
+        .add_audit_log_entry(CreateAuditLogEntryRequest {
+            actor: validation_result.user,
+            source: http_request
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            action: "createInvite".to_string(),
+            target: format!("user:{}", username),
+            details: None,
+        })
+        .await
+    {
+        log::error!(
+            "Could not add the invite of {} to the audit log: {}",
+            username,
+            e
+        );
+    }
+    HttpResponse::Ok().body(token)
+}
+
+/// Checks an invite link, and returns the invited user.
+async fn check_invite<Backend>(
+    state: &AppState<Backend>,
+    token_str: &str,
+) -> Result<String, actix_web::Error>
+where
+    Backend: TcpBackendHandler,
+{
+    let claims = verify_signature::<InviteClaims>(&state.jwt_keys, token_str)?;
+    if claims.exp.lt(&Utc::now()) {
+        return Err(ErrorUnauthorized("The invite link expired"));
+    }
+    let session_generation = state
+        .backend_handler
+        .get_session_generation(&claims.user)
+        .await
+        .map_err(|e| ErrorUnauthorized(e.to_string()))?;
+    if claims.session_generation != session_generation {
+        return Err(ErrorUnauthorized("The invite link was already used"));
+    }
+    Ok(claims.user)
+}
+
+/// Same as `opaque_register_start`, authorized by an invite link instead of a JWT. The
+/// registration then finishes as usual.
+async fn invite_register_start<Backend>(
+    data: web::Data<AppState<Backend>>,
+    token: web::Path<String>,
+    request: web::Json<registration::ClientRegistrationStartRequest>,
+) -> ApiResult<registration::ServerRegistrationStartResponse>
+where
+    Backend: TcpBackendHandler + OpaqueHandler + 'static,
+{
+    match check_invite(&data, &token).await {
+        Ok(user) if user == request.username => (),
+        Ok(_) => {
+            return ApiResult::Right(
+                HttpResponse::Unauthorized().body("The invite link is for another user"),
+            )
+        }
+        Err(e) => return ApiResult::Right(HttpResponse::from_error(e)),
+    }
+    data.backend_handler
+        .registration_start(request.into_inner())
+        .await
+        .map(|res| ApiResult::Left(web::Json(res)))
+        .unwrap_or_else(error_to_api_response)
+}
+
 pub struct CookieToHeaderTranslatorFactory;
 
 impl<S> Transform<S, ServiceRequest> for CookieToHeaderTranslatorFactory
@@ -342,7 +710,7 @@ where
 
 pub struct ValidationResults {
     pub user: String,
-    pub is_admin: bool,
+    pub roles: HashSet<Role>,
 }
 
 impl ValidationResults {
@@ -350,30 +718,77 @@ impl ValidationResults {
     pub fn admin() -> Self {
         Self {
             user: "admin".to_string(),
-            is_admin: true,
+            roles: [Role::Admin].iter().copied().collect(),
         }
     }
 
+    pub fn from_groups<'a>(user: String, groups: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            user,
+            roles: groups
+                .into_iter()
+                .filter_map(Role::from_group_name)
+                .collect(),
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+
+    /// Whether the user can see all the users and groups.
+    pub fn can_read_all(&self) -> bool {
+        !self.roles.is_empty()
+    }
+
     pub fn can_access(&self, user: &str) -> bool {
-        self.is_admin || self.user == user
+        self.can_read_all() || self.user == user
+    }
+
+    /// Whether the user can create users and edit the non-admin ones.
+    pub fn can_manage_users(&self) -> bool {
+        self.is_admin() || self.roles.contains(&Role::UserManager)
     }
+
+    /// Whether the user can modify the details of `user`, given whether `user` is an admin.
+    pub fn can_write(&self, user: &str, user_is_admin: bool) -> bool {
+        self.user == user || self.is_admin() || (self.can_manage_users() && !user_is_admin)
+    }
+
+    /// Whether the user can reset the passwords of the non-admin users.
+    pub fn can_reset_passwords(&self) -> bool {
+        self.can_manage_users() || self.roles.contains(&Role::PasswordManager)
+    }
+
+    /// Whether the user can reset the password of `user`, given whether `user` is an admin.
+    pub fn can_change_password(&self, user: &str, user_is_admin: bool) -> bool {
+        self.can_write(user, user_is_admin) || (self.can_reset_passwords() && !user_is_admin)
+    }
+}
+
+/// Checks the signature of a token signed with one of the JWT keys (a JWT or an invite), and
+/// returns its claims.
+pub(crate) fn verify_signature<C>(
+    keys: &JwtKeyStore,
+    token_str: &str,
+) -> Result<C, actix_web::Error>
+where
+    C: serde::de::DeserializeOwned + Clone,
+{
+    keys.verify(token_str).map_err(ErrorUnauthorized)
 }
 
-pub(crate) fn check_if_token_is_valid<Backend>(
+pub(crate) async fn check_if_token_is_valid<Backend>(
     state: &AppState<Backend>,
     token_str: &str,
-) -> Result<ValidationResults, actix_web::Error> {
-    let token: Token<_> = VerifyWithKey::verify_with_key(token_str, &state.jwt_key)
-        .map_err(|_| ErrorUnauthorized("Invalid JWT"))?;
-    if token.claims().exp.lt(&Utc::now()) {
+) -> Result<ValidationResults, actix_web::Error>
+where
+    Backend: TcpBackendHandler,
+{
+    let claims = verify_signature::<JWTClaims>(&state.jwt_keys, token_str)?;
+    if claims.exp.lt(&Utc::now()) {
         return Err(ErrorUnauthorized("Expired JWT"));
     }
-    if token.header().algorithm != jwt::AlgorithmType::Hs512 {
-        return Err(ErrorUnauthorized(format!(
-            "Unsupported JWT algorithm: '{:?}'. Supported ones are: ['HS512']",
-            token.header().algorithm
-        )));
-    }
     let jwt_hash = {
         let mut s = DefaultHasher::new();
         token_str.hash(&mut s);
@@ -382,11 +797,44 @@ pub(crate) fn check_if_token_is_valid<Backend>(
     if state.jwt_blacklist.read().unwrap().contains(&jwt_hash) {
         return Err(ErrorUnauthorized("JWT was logged out"));
     }
-    let is_admin = token.claims().groups.contains("lldap_admin");
-    Ok(ValidationResults {
-        user: token.claims().user.clone(),
-        is_admin,
-    })
+    let session_generation = state
+        .backend_handler
+        .get_session_generation(&claims.user)
+        .await
+        .map_err(|e| ErrorUnauthorized(e.to_string()))?;
+    if claims.session_generation != session_generation {
+        return Err(ErrorUnauthorized("JWT was revoked by a password change"));
+    }
+    logging::record_user(&claims.user);
+    Ok(ValidationResults::from_groups(
+        claims.user.clone(),
+        claims.groups.iter().map(String::as_str),
+    ))
+}
+
+pub(crate) async fn check_if_api_token_is_valid<Backend>(
+    state: &AppState<Backend>,
+    token_str: &str,
+) -> Result<ValidationResults, actix_web::Error>
+where
+    Backend: TcpBackendHandler + BackendHandler,
+{
+    let user = state
+        .backend_handler
+        .get_api_token_user(token_str)
+        .await
+        .map_err(|e| ErrorUnauthorized(e.to_string()))?
+        .ok_or_else(|| ErrorUnauthorized("Invalid API token"))?;
+    logging::record_user(&user);
+    let groups = state
+        .backend_handler
+        .get_user_groups(&user)
+        .await
+        .map_err(|e| ErrorUnauthorized(e.to_string()))?;
+    Ok(ValidationResults::from_groups(
+        user,
+        groups.iter().map(|g| g.1.as_str()),
+    ))
 }
 
 pub fn configure_server<Backend>(cfg: &mut web::ServiceConfig)
@@ -410,6 +858,73 @@ where
             web::resource("/opaque/register/finish")
                 .route(web::post().to(opaque_register_finish::<Backend>)),
         )
+        .service(web::resource("/invite").route(web::post().to(post_invite::<Backend>)))
+        .service(
+            web::resource("/invite/{token}/register/start")
+                .route(web::post().to(invite_register_start::<Backend>)),
+        )
+        .service(web::resource("/proof_of_work").route(web::get().to(get_proof_of_work::<Backend>)))
+        .service(
+            web::resource("/trusted_header")
+                .route(web::get().to(get_trusted_header_login::<Backend>)),
+        )
         .service(web::resource("/refresh").route(web::get().to(get_refresh::<Backend>)))
         .service(web::resource("/logout").route(web::get().to(get_logout::<Backend>)));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_from_trusted_proxy() {
+        let proxies: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+        assert!(is_from_trusted_proxy(
+            &proxies,
+            Some("10.0.0.1:1234".parse().unwrap())
+        ));
+        assert!(is_from_trusted_proxy(
+            &proxies,
+            Some("[::1]:1234".parse().unwrap())
+        ));
+        assert!(!is_from_trusted_proxy(
+            &proxies,
+            Some("10.0.0.2:1234".parse().unwrap())
+        ));
+        assert!(!is_from_trusted_proxy(&proxies, None));
+        assert!(!is_from_trusted_proxy(
+            &[],
+            Some("10.0.0.1:1234".parse().unwrap())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invite_is_not_a_jwt() {
+        let sql_pool = crate::domain::sql_tables::get_test_pool().await;
+        crate::infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .unwrap();
+        let config = crate::infra::configuration::ConfigurationBuilder::default()
+            .build()
+            .unwrap();
+        let keys = JwtKeyStore::load(&config, sql_pool).await.unwrap();
+        let invite = create_invite(
+            &keys,
+            chrono::Duration::hours(1),
+            "bob".to_string(),
+            "admin".to_string(),
+            0,
+        );
+        let claims = verify_signature::<InviteClaims>(&keys, &invite).unwrap();
+        assert_eq!(claims.user, "bob");
+        assert!(verify_signature::<JWTClaims>(&keys, &invite).is_err());
+        let jwt = create_jwt(
+            &keys,
+            chrono::Duration::hours(1),
+            "bob".to_string(),
+            HashSet::new(),
+            0,
+        );
+        assert!(verify_signature::<InviteClaims>(&keys, &jwt).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! `lldap bench`: load test of a running server. Creates synthetic users and groups through the
+//! API, then replays a mix of LDAP binds, LDAP searches and GraphQL queries from concurrent clients,
+//! and reports the latency percentiles of each kind of request.
+
+use crate::infra::{
+    api_client::{get_token, parse_response, ApiError},
+    cli::{BenchOpts, CommandOutput},
+};
+use anyhow::{bail, Context, Result};
+use ldap3::{exop::PasswordModify, Ldap, LdapConnAsync, Scope};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+const USER_PREFIX: &str = "bench_user_";
+const GROUP_PREFIX: &str = "bench_group_";
+/// The password of the synthetic users.
+const USER_PASSWORD: &str = "lldap-bench-password";
+/// The environment variable read when there is no `--admin-password`.
+const ADMIN_PASSWORD_VARIABLE: &str = "LLDAP_LDAP_USER_PASS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Bind,
+    Search,
+    GraphQL,
+}
+
+const OPERATIONS: [Operation; 3] = [Operation::Bind, Operation::Search, Operation::GraphQL];
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Bind => "bind",
+            Operation::Search => "search",
+            Operation::GraphQL => "graphql",
+        }
+    }
+}
+
+/// The operations with their relative weight, e.g. "bind=1,search=2,graphql=1".
+#[derive(Debug, PartialEq, Eq)]
+struct Mix(Vec<(Operation, u32)>);
+
+impl std::str::FromStr for Mix {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut weights = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut fields = part.splitn(2, '=');
+            let (name, weight) = match (fields.next(), fields.next()) {
+                (Some(name), Some(weight)) => (name.trim(), weight.trim()),
+                _ => bail!("Invalid mix `{}`, expected e.g. bind=1", part),
+            };
+            let operation = match OPERATIONS.iter().find(|o| o.name() == name) {
+                Some(operation) => *operation,
+                None => bail!(
+                    "Unknown operation `{}` in the mix, expected bind, search or graphql",
+                    name
+                ),
+            };
+            let weight = weight
+                .parse::<u32>()
+                .with_context(|| format!("Invalid weight for `{}`", name))?;
+            weights.push((operation, weight));
+        }
+        let mix = Mix(weights);
+        if mix.total() == 0 {
+            bail!("The mix doesn't have any operation");
+        }
+        Ok(mix)
+    }
+}
+
+impl Mix {
+    fn total(&self) -> u32 {
+        self.0.iter().map(|(_, weight)| weight).sum()
+    }
+
+    /// The operation of `value`, between 0 and `total()`.
+    fn pick(&self, mut value: u32) -> Operation {
+        for (operation, weight) in &self.0 {
+            if value < *weight {
+                return *operation;
+            }
+            value -= weight;
+        }
+        unreachable!("{} is beyond the total of the mix", value)
+    }
+}
+
+/// The nearest-rank percentile of the sorted durations.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (sorted.len() * percent + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+fn format_ms(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+struct Target {
+    graphql_url: String,
+    token: String,
+    ldap_url: String,
+    base_dn: String,
+    admin_dn: String,
+    admin_password: String,
+    http: reqwest::Client,
+}
+
+impl Target {
+    fn user_dn(&self, user_id: &str) -> String {
+        format!("uid={},ou=people,{}", user_id, self.base_dn)
+    }
+
+    async fn query(&self, query: &str, variables: Value) -> Result<Value> {
+        let response = self
+            .http
+            .post(&self.graphql_url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()
+            .await
+            .map_err(|e| ApiError::Unreachable(e.to_string()))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ApiError::Unauthorized(response.text().await.unwrap_or_default()).into());
+        }
+        if !status.is_success() {
+            return Err(ApiError::Rejected(format!(
+                "{}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ))
+            .into());
+        }
+        Ok(parse_response(
+            response
+                .json()
+                .await
+                .context("Invalid response from the server")?,
+        )?)
+    }
+
+    /// A new LDAP connection, bound as the admin if `as_admin`.
+    async fn connect(&self, as_admin: bool) -> Result<Ldap> {
+        let (connection, mut ldap) = LdapConnAsync::new(&self.ldap_url)
+            .await
+            .with_context(|| format!("Could not connect to {}", self.ldap_url))?;
+        ldap3::drive!(connection);
+        if as_admin {
+            ldap.simple_bind(&self.admin_dn, &self.admin_password)
+                .await?
+                .success()
+                .with_context(|| format!("Could not bind as {}", self.admin_dn))?;
+        }
+        Ok(ldap)
+    }
+}
+
+/// Creates the missing synthetic users and groups, and sets the users' password.
+async fn setup(target: &Target, users: usize, groups: usize) -> Result<()> {
+    let data = target
+        .query(
+            "{ users { id } groups { id displayName users { id } } }",
+            json!({}),
+        )
+        .await?;
+    let existing_users: HashSet<&str> = data["users"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|u| u["id"].as_str())
+        .collect();
+    for i in 0..users {
+        let user_id = format!("{}{}", USER_PREFIX, i);
+        if !existing_users.contains(user_id.as_str()) {
+            target
+                .query(
+                    "mutation($user: CreateUserInput!) { createUser(user: $user) { id } }",
+                    json!({ "user": {
+                        "id": user_id,
+                        "email": format!("{}@bench.example.com", user_id),
+                        "displayName": format!("Bench user {}", i),
+                    }}),
+                )
+                .await?;
+        }
+    }
+    let existing_groups = data["groups"].as_array().cloned().unwrap_or_default();
+    for j in 0..groups {
+        let name = format!("{}{}", GROUP_PREFIX, j);
+        let (group_id, members) = match existing_groups.iter().find(|g| g["displayName"] == name) {
+            Some(group) => (group["id"].clone(), group["users"].clone()),
+            None => {
+                let data = target
+                    .query(
+                        "mutation($name: String!) { createGroup(name: $name) { id } }",
+                        json!({ "name": name }),
+                    )
+                    .await?;
+                (data["createGroup"]["id"].clone(), json!([]))
+            }
+        };
+        let members: HashSet<&str> = members
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|u| u["id"].as_str())
+            .collect();
+        for i in (j..users).step_by(groups) {
+            let user_id = format!("{}{}", USER_PREFIX, i);
+            if !members.contains(user_id.as_str()) {
+                target
+                    .query(
+                        "mutation($userId: String!, $groupId: Int!) { addUserToGroup(userId: $userId, groupId: $groupId) { ok } }",
+                        json!({ "userId": user_id, "groupId": group_id }),
+                    )
+                    .await?;
+            }
+        }
+    }
+    let mut ldap = target.connect(true).await?;
+    for i in 0..users {
+        let user_dn = target.user_dn(&format!("{}{}", USER_PREFIX, i));
+        ldap.extended(PasswordModify {
+            user_id: Some(&user_dn),
+            old_pass: None,
+            new_pass: Some(USER_PASSWORD),
+        })
+        .await?
+        .success()
+        .with_context(|| format!("Could not set the password of {}", user_dn))?;
+    }
+    ldap.unbind().await?;
+    Ok(())
+}
+
+/// Deletes the synthetic users and groups.
+async fn cleanup(target: &Target) -> Result<()> {
+    let data = target
+        .query("{ users { id } groups { id displayName } }", json!({}))
+        .await?;
+    for user in data["users"].as_array().into_iter().flatten() {
+        if let Some(user_id) = user["id"].as_str().filter(|id| id.starts_with(USER_PREFIX)) {
+            target
+                .query(
+                    "mutation($userId: String!) { deleteUser(userId: $userId) { ok } }",
+                    json!({ "userId": user_id }),
+                )
+                .await?;
+        }
+    }
+    for group in data["groups"].as_array().into_iter().flatten() {
+        if group["displayName"]
+            .as_str()
+            .map(|name| name.starts_with(GROUP_PREFIX))
+            .unwrap_or(false)
+        {
+            target
+                .query(
+                    "mutation($groupId: Int!) { deleteGroup(groupId: $groupId) { ok } }",
+                    json!({ "groupId": group["id"] }),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_operation(
+    target: &Target,
+    admin: &mut Ldap,
+    user: &mut Ldap,
+    operation: Operation,
+    user_id: &str,
+) -> Result<()> {
+    match operation {
+        Operation::Bind => {
+            user.simple_bind(&target.user_dn(user_id), USER_PASSWORD)
+                .await?
+                .success()?;
+        }
+        Operation::Search => {
+            admin
+                .search(
+                    &format!("ou=people,{}", target.base_dn),
+                    Scope::Subtree,
+                    &format!("(&(objectClass=person)(uid={}))", user_id),
+                    vec!["uid", "mail", "cn", "memberOf"],
+                )
+                .await?
+                .success()?;
+        }
+        Operation::GraphQL => {
+            target
+                .query(
+                    "query($userId: String!) { user(userId: $userId) { id email displayName groups { displayName } } }",
+                    json!({ "userId": user_id }),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// One client: sends `requests` requests one after the other.
+async fn run_client(
+    target: &Target,
+    mix: &Mix,
+    users: usize,
+    requests: usize,
+) -> Result<Vec<(Operation, Result<Duration>)>> {
+    let mut admin = target.connect(true).await?;
+    let mut user = target.connect(false).await?;
+    let mut rng = SmallRng::from_entropy();
+    let mut results = Vec::with_capacity(requests);
+    for _ in 0..requests {
+        let operation = mix.pick(rng.gen_range(0..mix.total()));
+        let user_id = format!("{}{}", USER_PREFIX, rng.gen_range(0..users));
+        let start = Instant::now();
+        let result = run_operation(target, &mut admin, &mut user, operation, &user_id).await;
+        results.push((operation, result.map(|()| start.elapsed())));
+    }
+    let _ = admin.unbind().await;
+    let _ = user.unbind().await;
+    Ok(results)
+}
+
+async fn run_bench(opts: BenchOpts) -> Result<CommandOutput> {
+    let mix: Mix = opts.mix.parse()?;
+    if opts.users == 0 || opts.groups == 0 || opts.concurrency == 0 {
+        bail!("`--users`, `--groups` and `--concurrency` must be positive");
+    }
+    let admin_password = match &opts.admin_password {
+        Some(password) => password.clone(),
+        None => std::env::var(ADMIN_PASSWORD_VARIABLE).with_context(|| {
+            format!(
+                "The admin password is needed, with --admin-password or {}",
+                ADMIN_PASSWORD_VARIABLE
+            )
+        })?,
+    };
+    let target = Target {
+        graphql_url: format!("{}/api/graphql", opts.api.server_url.trim_end_matches('/')),
+        token: get_token(&opts.api)?,
+        ldap_url: opts.ldap_url.clone(),
+        base_dn: opts.base_dn.clone(),
+        admin_dn: format!("uid={},ou=people,{}", opts.admin_user, opts.base_dn),
+        admin_password,
+        http: reqwest::Client::new(),
+    };
+
+    setup(&target, opts.users, opts.groups)
+        .await
+        .context("Could not create the synthetic users and groups")?;
+
+    // The requests are spread over the clients, the first ones taking the remainder.
+    let start = Instant::now();
+    let clients = (0..opts.concurrency).map(|i| {
+        let requests = opts.requests / opts.concurrency
+            + if i < opts.requests % opts.concurrency {
+                1
+            } else {
+                0
+            };
+        run_client(&target, &mix, opts.users, requests)
+    });
+    let results = futures::future::try_join_all(clients).await?;
+    let elapsed = start.elapsed();
+
+    if opts.cleanup {
+        cleanup(&target)
+            .await
+            .context("Could not delete the synthetic users and groups")?;
+    }
+
+    let mut samples: Vec<(Operation, Samples)> = OPERATIONS
+        .iter()
+        .map(|o| (*o, Samples::default()))
+        .collect();
+    for (operation, result) in results.into_iter().flatten() {
+        let entry = &mut samples.iter_mut().find(|(o, _)| *o == operation).unwrap().1;
+        match result {
+            Ok(latency) => entry.latencies.push(latency),
+            Err(_) => entry.errors += 1,
+        }
+    }
+    let mut lines = vec![format!(
+        "{} requests from {} clients in {:.1}s ({:.0} requests/s)",
+        opts.requests,
+        opts.concurrency,
+        elapsed.as_secs_f64(),
+        opts.requests as f64 / elapsed.as_secs_f64()
+    )];
+    let mut report = serde_json::Map::new();
+    for (operation, mut samples) in samples {
+        if samples.latencies.is_empty() && samples.errors == 0 {
+            continue;
+        }
+        samples.latencies.sort();
+        let percentiles = [50, 90, 99, 100]
+            .iter()
+            .map(|p| (*p, percentile(&samples.latencies, *p)))
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "{:<8} {:>7} ok {:>5} errors  {}",
+            operation.name(),
+            samples.latencies.len(),
+            samples.errors,
+            percentiles
+                .iter()
+                .map(|(p, d)| format!(
+                    "{} {}",
+                    if *p == 100 {
+                        "max".to_string()
+                    } else {
+                        format!("p{}", p)
+                    },
+                    format_ms(*d)
+                ))
+                .collect::<Vec<_>>()
+                .join("  ")
+        ));
+        let mut entry = serde_json::Map::new();
+        entry.insert("count".to_string(), samples.latencies.len().into());
+        entry.insert("errors".to_string(), samples.errors.into());
+        for (p, d) in percentiles {
+            let key = if p == 100 {
+                "max_ms".to_string()
+            } else {
+                format!("p{}_ms", p)
+            };
+            entry.insert(key, (d.as_secs_f64() * 1000.0).into());
+        }
+        report.insert(operation.name().to_string(), entry.into());
+    }
+    let mut result = CommandOutput::default();
+    result.text = Some(lines.join("\n"));
+    result
+        .data
+        .insert("duration_seconds".to_string(), elapsed.as_secs_f64().into());
+    result
+        .data
+        .insert("requests".to_string(), opts.requests.into());
+    result.data.insert("operations".to_string(), report.into());
+    Ok(result)
+}
+
+pub fn bench_command(opts: BenchOpts) -> Result<CommandOutput> {
+    actix_rt::System::new().block_on(run_bench(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        let mix: Mix = "bind=1, search=2,graphql=0".parse().unwrap();
+        assert_eq!(
+            mix,
+            Mix(vec![
+                (Operation::Bind, 1),
+                (Operation::Search, 2),
+                (Operation::GraphQL, 0)
+            ])
+        );
+        assert_eq!(mix.total(), 3);
+        assert_eq!(mix.pick(0), Operation::Bind);
+        assert_eq!(mix.pick(1), Operation::Search);
+        assert_eq!(mix.pick(2), Operation::Search);
+        assert!("bind=1,modify=1".parse::<Mix>().is_err());
+        assert!("bind".parse::<Mix>().is_err());
+        assert!("bind=0".parse::<Mix>().is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let durations: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&durations, 50), Duration::from_millis(50));
+        assert_eq!(percentile(&durations, 99), Duration::from_millis(99));
+        assert_eq!(percentile(&durations, 100), Duration::from_millis(100));
+        assert_eq!(percentile(&durations[..1], 90), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50), Duration::default());
+    }
+}
@@ -0,0 +1,174 @@
+//! The branding of the web app: `/api/branding` returns the name, accent color and login message
+//! configured in `[branding]`, and `/api/branding/logo` the logo. Both are public, for the login page.
+
+use crate::infra::{configuration::BrandingConfig, tcp_server::AppState};
+use actix_web::{http::header, web, HttpResponse};
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+
+/// The longest product name, to fit in the header.
+const MAX_PRODUCT_NAME_LENGTH: usize = 64;
+/// The logo is kept in memory.
+const MAX_LOGO_SIZE: usize = 1024 * 1024;
+const MAX_LOGIN_MESSAGE_LENGTH: usize = 2000;
+const LOGO_PATH: &str = "/api/branding/logo";
+
+pub struct Branding {
+    product_name: String,
+    accent_color: Option<String>,
+    /// The content type and the content of the logo.
+    logo: Option<(&'static str, Vec<u8>)>,
+    login_message: Option<String>,
+    login_message_everywhere: bool,
+}
+
+fn is_valid_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn logo_content_type(file: &str) -> Option<&'static str> {
+    let extension = std::path::Path::new(file)
+        .extension()?
+        .to_str()?
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+impl Branding {
+    /// Checks the configuration, and reads the logo.
+    pub fn new(config: &BrandingConfig) -> Result<Self> {
+        let product_name = config.product_name.trim().to_string();
+        if product_name.is_empty() || product_name.chars().count() > MAX_PRODUCT_NAME_LENGTH {
+            bail!(
+                "`branding.product_name` should have between 1 and {} characters",
+                MAX_PRODUCT_NAME_LENGTH
+            );
+        }
+        if let Some(color) = &config.accent_color {
+            if !is_valid_color(color) {
+                bail!(
+                    "Invalid `branding.accent_color` `{}`, expected \"#rrggbb\"",
+                    color
+                );
+            }
+        }
+        let login_message = config
+            .login_message
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(str::to_string);
+        if let Some(message) = &login_message {
+            if message.chars().count() > MAX_LOGIN_MESSAGE_LENGTH {
+                bail!(
+                    "`branding.login_message` should have at most {} characters",
+                    MAX_LOGIN_MESSAGE_LENGTH
+                );
+            }
+        }
+        let logo = match &config.logo_file {
+            None => None,
+            Some(file) => {
+                let content_type = logo_content_type(file).with_context(|| {
+                    format!(
+                        "Unsupported `branding.logo_file` `{}`, expected a PNG, JPEG or SVG file",
+                        file
+                    )
+                })?;
+                let content = std::fs::read(file)
+                    .with_context(|| format!("Could not read `branding.logo_file` `{}`", file))?;
+                if content.len() > MAX_LOGO_SIZE {
+                    bail!("`branding.logo_file` is bigger than 1MB");
+                }
+                Some((content_type, content))
+            }
+        };
+        Ok(Self {
+            product_name,
+            accent_color: config.accent_color.clone(),
+            logo,
+            login_message,
+            login_message_everywhere: config.login_message_everywhere,
+        })
+    }
+}
+
+async fn get_branding<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse {
+    let branding = &data.branding;
+    HttpResponse::Ok().json(json!({
+        "productName": branding.product_name,
+        "accentColor": branding.accent_color,
+        "logoUrl": branding.logo.as_ref().map(|_| LOGO_PATH),
+        "loginMessage": branding.login_message,
+        "loginMessageEverywhere": branding.login_message_everywhere,
+    }))
+}
+
+async fn get_logo<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse {
+    match &data.branding.logo {
+        // The logo only changes with a restart.
+        Some((content_type, content)) => HttpResponse::Ok()
+            .content_type(*content_type)
+            .insert_header((header::CACHE_CONTROL, "public, max-age=3600"))
+            .body(content.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: 'static,
+{
+    cfg.service(web::resource("/branding").route(web::get().to(get_branding::<Backend>)))
+        .service(web::resource("/branding/logo").route(web::get().to(get_logo::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_branding() {
+        assert!(Branding::new(&BrandingConfig::default()).is_ok());
+        let config = |product_name: &str, accent_color: Option<&str>| BrandingConfig {
+            product_name: product_name.to_string(),
+            logo_file: None,
+            accent_color: accent_color.map(str::to_string),
+            ..BrandingConfig::default()
+        };
+        assert!(Branding::new(&config("Example Corp", Some("#6f42c1"))).is_ok());
+        assert!(Branding::new(&config(" ", None)).is_err());
+        assert!(Branding::new(&config("Example Corp", Some("6f42c1"))).is_err());
+        assert!(Branding::new(&config("Example Corp", Some("#6f42cg"))).is_err());
+        assert!(Branding::new(&config("Example Corp", Some("red"))).is_err());
+    }
+
+    #[test]
+    fn test_login_message() {
+        let branding = |message: &str| {
+            Branding::new(&BrandingConfig {
+                login_message: Some(message.to_string()),
+                ..BrandingConfig::default()
+            })
+        };
+        assert_eq!(
+            branding("  Authorized use only\n").unwrap().login_message,
+            Some("Authorized use only".to_string())
+        );
+        assert_eq!(branding(" ").unwrap().login_message, None);
+        assert!(branding(&"a".repeat(2001)).is_err());
+    }
+
+    #[test]
+    fn test_logo_content_type() {
+        assert_eq!(logo_content_type("/data/logo.SVG"), Some("image/svg+xml"));
+        assert_eq!(logo_content_type("logo.jpg"), Some("image/jpeg"));
+        assert_eq!(logo_content_type("logo.gif"), None);
+        assert_eq!(logo_content_type("logo"), None);
+    }
+}
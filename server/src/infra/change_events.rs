@@ -0,0 +1,177 @@
+//! The changes of the users and groups, pushed to the web app: `/api/changes` is a WebSocket that
+//! sends a JSON message for each change, for the lists to stay up to date when other admins
+//! change the entries.
+
+use crate::{
+    domain::handler::{BackendHandler, ChangeEvent, ChangeNotifier, ChangeType, ChangedEntry},
+    infra::{rest_api::authenticate, tcp_backend_handler::TcpBackendHandler, tcp_server::AppState},
+};
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+/// Keeps the connection open through the proxies, which close the idle ones.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct ChangeMessage {
+    /// "add", "delete", "modify" or "rename".
+    change_type: &'static str,
+    /// "user" or "group".
+    entry_type: &'static str,
+    /// The ID of the user, or the name of the group.
+    id: String,
+    /// For the renames, the ID before.
+    previous_id: Option<String>,
+}
+
+impl From<ChangeEvent> for ChangeMessage {
+    fn from(event: ChangeEvent) -> Self {
+        let (entry_type, id) = match event.entry {
+            ChangedEntry::User(id) => ("user", id),
+            ChangedEntry::Group(name) => ("group", name),
+        };
+        Self {
+            change_type: match event.change_type {
+                ChangeType::Add => "add",
+                ChangeType::Delete => "delete",
+                ChangeType::Modify => "modify",
+                ChangeType::ModifyDn => "rename",
+            },
+            entry_type,
+            id,
+            previous_id: event.previous_entry.map(|entry| match entry {
+                ChangedEntry::User(id) | ChangedEntry::Group(id) => id,
+            }),
+        }
+    }
+}
+
+/// The connection of a client, forwarding the changes until the client leaves or its session
+/// expires.
+struct ChangeEventSession {
+    changes: Option<broadcast::Receiver<ChangeEvent>>,
+    /// The client reconnects with a fresh session afterwards.
+    session_duration: Duration,
+}
+
+impl Actor for ChangeEventSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(changes) = self.changes.take() {
+            ctx.add_stream(BroadcastStream::new(changes));
+        }
+        ctx.run_interval(HEARTBEAT_INTERVAL, |_, ctx| ctx.ping(b""));
+        ctx.run_later(self.session_duration, |_, ctx| {
+            ctx.close(Some(ws::CloseCode::Policy.into()));
+            ctx.stop();
+        });
+    }
+}
+
+impl StreamHandler<Result<ChangeEvent, BroadcastStreamRecvError>> for ChangeEventSession {
+    fn handle(
+        &mut self,
+        change: Result<ChangeEvent, BroadcastStreamRecvError>,
+        ctx: &mut Self::Context,
+    ) {
+        let message = match change {
+            Ok(change) => serde_json::to_string(&ChangeMessage::from(change)),
+            // Too slow: the client has to reload everything.
+            Err(BroadcastStreamRecvError::Lagged(_)) => {
+                serde_json::to_string(&serde_json::json!({ "changeType": "reset" }))
+            }
+        };
+        match message {
+            Ok(message) => ctx.text(message),
+            Err(e) => log::error!("Could not serialize the change: {:#}", e),
+        }
+    }
+}
+
+/// The client only answers the pings, and closes the connection.
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChangeEventSession {
+    fn handle(&mut self, message: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match message {
+            Ok(ws::Message::Ping(payload)) => ctx.pong(&payload),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Err(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+async fn get_changes<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    request: HttpRequest,
+    stream: web::Payload,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + ChangeNotifier + 'static,
+{
+    match authenticate(&data, &bearer).await {
+        Ok(v) if v.can_read_all() => (),
+        Ok(_) => {
+            return HttpResponse::Forbidden()
+                .body("Only the admins can follow the changes of all the entries")
+        }
+        Err(response) => return response,
+    }
+    let session = ChangeEventSession {
+        changes: Some(data.backend_handler.subscribe_to_changes()),
+        session_duration: data
+            .jwt_duration
+            .to_std()
+            .unwrap_or(Duration::from_secs(3600)),
+    };
+    ws::start(session, &request, stream).unwrap_or_else(HttpResponse::from_error)
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + ChangeNotifier + 'static,
+{
+    cfg.service(web::resource("/changes").route(web::get().to(get_changes::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_message() {
+        let message = ChangeMessage::from(ChangeEvent {
+            change_type: ChangeType::ModifyDn,
+            entry: ChangedEntry::User("robert".to_string()),
+            previous_entry: Some(ChangedEntry::User("bob".to_string())),
+        });
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "changeType": "rename",
+                "entryType": "user",
+                "id": "robert",
+                "previousId": "bob",
+            })
+        );
+        let message = ChangeMessage::from(ChangeEvent {
+            change_type: ChangeType::Delete,
+            entry: ChangedEntry::Group("admins".to_string()),
+            previous_entry: None,
+        });
+        assert_eq!(message.change_type, "delete");
+        assert_eq!(message.entry_type, "group");
+        assert_eq!(message.previous_id, None);
+    }
+}
@@ -1,14 +1,162 @@
+use crate::{domain::error::DomainError, infra::api_client::ApiError};
 use clap::Clap;
+use serde_json::json;
 
 /// lldap is a lightweight LDAP server
 #[derive(Debug, Clap, Clone)]
 #[clap(version = "0.1", author = "The LLDAP team")]
 pub struct CLIOpts {
+    /// Output format of the command results and errors: "text" or "json".
+    #[clap(long, global = true, default_value = "text")]
+    pub output: OutputFormat,
+
     /// Export
     #[clap(subcommand)]
     pub command: Command,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format: '{}'", s)),
+        }
+    }
+}
+
+/// Exit codes of the commands. They are part of the CLI's stable interface for scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any error not covered by another code.
+    Error = 1,
+    /// The requested entity (user, group, file...) doesn't exist.
+    NotFound = 3,
+    /// Invalid credentials, or the operation is not allowed.
+    PermissionDenied = 4,
+    /// The operation could be retried later: database or network unavailable, timeout...
+    Transient = 5,
+    /// The request was invalid, e.g. a bad argument value.
+    InvalidRequest = 6,
+}
+
+impl ExitCode {
+    /// Name of the error kind in the JSON output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitCode::Success => "success",
+            ExitCode::Error => "error",
+            ExitCode::NotFound => "not_found",
+            ExitCode::PermissionDenied => "permission_denied",
+            ExitCode::Transient => "transient",
+            ExitCode::InvalidRequest => "invalid_request",
+        }
+    }
+
+    fn from_sqlx_error(error: &sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => ExitCode::NotFound,
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                ExitCode::Transient
+            }
+            _ => ExitCode::Error,
+        }
+    }
+
+    fn from_io_error(error: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match error.kind() {
+            ErrorKind::NotFound => ExitCode::NotFound,
+            ErrorKind::PermissionDenied => ExitCode::PermissionDenied,
+            ErrorKind::TimedOut
+            | ErrorKind::Interrupted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted => ExitCode::Transient,
+            _ => ExitCode::Error,
+        }
+    }
+
+    /// Classifies an error, looking at the first known error type in its chain of causes.
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<DomainError>() {
+                return match e {
+                    DomainError::AuthenticationError(_)
+                    | DomainError::UserDisabled(_)
+                    | DomainError::UserLocked(_)
+                    | DomainError::AuthenticationProtocolError(_) => ExitCode::PermissionDenied,
+                    DomainError::DatabaseError(e) => Self::from_sqlx_error(e),
+                    DomainError::InvalidRequest(_) => ExitCode::InvalidRequest,
+                    _ => ExitCode::Error,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<ApiError>() {
+                return match e {
+                    ApiError::Unreachable(_) => ExitCode::Transient,
+                    ApiError::Unauthorized(_) => ExitCode::PermissionDenied,
+                    ApiError::NotFound(_) => ExitCode::NotFound,
+                    ApiError::Rejected(_) => ExitCode::InvalidRequest,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+                return Self::from_sqlx_error(e);
+            }
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return Self::from_io_error(e);
+            }
+        }
+        ExitCode::Error
+    }
+}
+
+/// The result of a successful command.
+#[derive(Debug, Default)]
+pub struct CommandOutput {
+    /// Printed as is in text mode.
+    pub text: Option<String>,
+    /// Fields added to the JSON object printed in JSON mode.
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Prints the result of the command in the requested format, and returns the exit code.
+pub fn report_result(format: OutputFormat, result: anyhow::Result<CommandOutput>) -> ExitCode {
+    let exit_code = match &result {
+        Ok(_) => ExitCode::Success,
+        Err(e) => ExitCode::from_error(e),
+    };
+    match (format, result) {
+        (OutputFormat::Text, Ok(output)) => {
+            if let Some(text) = output.text {
+                println!("{}", text);
+            }
+        }
+        (OutputFormat::Text, Err(e)) => eprintln!("Error: {:#}", e),
+        (OutputFormat::Json, Ok(output)) => {
+            let mut object = output.data;
+            object.insert("status".to_string(), json!("ok"));
+            println!("{}", serde_json::Value::Object(object));
+        }
+        (OutputFormat::Json, Err(e)) => println!(
+            "{}",
+            json!({
+                "status": "error",
+                "error_kind": exit_code.name(),
+                "message": format!("{:#}", e),
+            })
+        ),
+    }
+    exit_code
+}
+
 #[derive(Debug, Clap, Clone)]
 pub enum Command {
     /// Export the GraphQL schema to *.graphql.
@@ -17,6 +165,36 @@ pub enum Command {
     /// Run the LDAP and GraphQL server.
     #[clap(name = "run")]
     Run(RunOpts),
+    /// Show or change the version of the database schema.
+    #[clap(name = "migrate")]
+    Migrate(MigrateOpts),
+    /// Export all the users and groups, e.g. to migrate to another LDAP server.
+    #[clap(name = "export")]
+    Export(ExportOpts),
+    /// Create or update users and groups from an LDIF or CSV file.
+    #[clap(name = "import")]
+    Import(ImportOpts),
+    /// Replace the database with a backup. The server must be stopped.
+    #[clap(name = "restore")]
+    Restore(RestoreOpts),
+    /// Set a new password for the admin, directly in the database, and restore their access.
+    #[clap(name = "reset-admin-password")]
+    ResetAdminPassword(ResetAdminPasswordOpts),
+    /// Add, delete or list the users, through the API of a running server.
+    #[clap(name = "user")]
+    User(UserOpts),
+    /// Add, delete or list the groups and their members, through the API of a running server.
+    #[clap(name = "group")]
+    Group(GroupOpts),
+    /// Copy the data of an SQLite database to the MySQL database of the configuration, which must
+    /// be empty.
+    /// Load test a running server: create synthetic users and groups, send a mix of LDAP binds,
+    /// LDAP searches and GraphQL queries, and report their latency.
+    #[clap(name = "bench")]
+    Bench(BenchOpts),
+    #[cfg(feature = "mysql")]
+    #[clap(name = "migrate_from_sqlite")]
+    MigrateFromSqlite(MigrateFromSqliteOpts),
 }
 
 #[derive(Debug, Clap, Clone)]
@@ -38,6 +216,266 @@ pub struct RunOpts {
     pub verbose: bool,
 }
 
+#[derive(Debug, Clap, Clone)]
+pub struct MigrateOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    #[clap(subcommand)]
+    pub action: MigrateAction,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub enum MigrateAction {
+    /// Show the current version of the schema and the pending migrations.
+    #[clap(name = "status")]
+    Status,
+    /// Apply all the pending migrations.
+    #[clap(name = "up")]
+    Up,
+    /// Revert the last applied migration.
+    #[clap(name = "down")]
+    Down,
+    /// Apply or revert the migrations to reach the given version. 0 drops all the tables.
+    #[clap(name = "to-version")]
+    ToVersion {
+        /// The target version of the schema.
+        version: i32,
+    },
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct ExportOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    /// Format of the export. Only "ldif" is supported.
+    #[clap(long, default_value = "ldif")]
+    pub format: ExportFormat,
+
+    /// Output to a file, only readable by its owner and encrypted with `backup_encryption_key` if
+    /// set. If not specified, the export is printed to the standard output, in clear.
+    #[clap(short, long)]
+    pub output_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Ldif,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ldif" => Ok(ExportFormat::Ldif),
+            _ => Err(format!("Unknown export format: '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct ImportOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    /// Format of the input file: "ldif" or "csv".
+    #[clap(long)]
+    pub format: ImportFormat,
+
+    /// The file to import, decrypted with `backup_encryption_key` if it is an encrypted export.
+    #[clap(short, long)]
+    pub input_file: String,
+
+    /// Where to read a user field, as "field=source": the attribute (LDIF) or column (CSV) name.
+    /// Can be repeated, e.g. `--map user_id=sAMAccountName --map groups=roles`.
+    #[clap(long = "map")]
+    pub mappings: Vec<String>,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct ResetAdminPasswordOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    /// The user to reset, by default the admin of the configuration (`ldap_user_dn`).
+    #[clap(long)]
+    pub user: Option<String>,
+
+    /// The new password. Without it or --generate, it is asked interactively.
+    #[clap(long, conflicts_with = "generate")]
+    pub password: Option<String>,
+
+    /// Generate a random password, and print it.
+    #[clap(long)]
+    pub generate: bool,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct ApiClientOpts {
+    /// The URL of the server's HTTP port.
+    #[clap(long, default_value = "http://localhost:17170")]
+    pub server_url: String,
+
+    /// An API token of an admin. Defaults to the LLDAP_API_TOKEN environment variable, which
+    /// doesn't show in the process list.
+    #[clap(long)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct UserOpts {
+    #[clap(flatten)]
+    pub api: ApiClientOpts,
+
+    #[clap(subcommand)]
+    pub action: UserAction,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub enum UserAction {
+    /// Create a user, without password: send them an invite link or set it from the web UI.
+    #[clap(name = "add")]
+    Add {
+        user_id: String,
+        #[clap(long)]
+        email: String,
+        #[clap(long)]
+        display_name: Option<String>,
+        #[clap(long)]
+        first_name: Option<String>,
+        #[clap(long)]
+        last_name: Option<String>,
+    },
+    /// Delete a user.
+    #[clap(name = "delete")]
+    Delete { user_id: String },
+    /// List the users, or only the members of a group.
+    #[clap(name = "list")]
+    List {
+        #[clap(long)]
+        group: Option<String>,
+    },
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct GroupOpts {
+    #[clap(flatten)]
+    pub api: ApiClientOpts,
+
+    #[clap(subcommand)]
+    pub action: GroupAction,
+}
+
+#[derive(Debug, Clap, Clone)]
+pub enum GroupAction {
+    /// Create a group.
+    #[clap(name = "add")]
+    Add { name: String },
+    /// Delete a group, by name.
+    #[clap(name = "delete")]
+    Delete { name: String },
+    /// List the groups, with their number of members.
+    #[clap(name = "list")]
+    List,
+    /// Add a user to a group.
+    #[clap(name = "add-member")]
+    AddMember { name: String, user_id: String },
+    /// Remove a user from a group.
+    #[clap(name = "remove-member")]
+    RemoveMember { name: String, user_id: String },
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct BenchOpts {
+    #[clap(flatten)]
+    pub api: ApiClientOpts,
+
+    /// The URL of the server's LDAP port.
+    #[clap(long, default_value = "ldap://localhost:3890")]
+    pub ldap_url: String,
+
+    /// The base DN of the server.
+    #[clap(long, default_value = "dc=example,dc=com")]
+    pub base_dn: String,
+
+    /// The LDAP admin, to run the searches and set the password of the synthetic users.
+    #[clap(long, default_value = "admin")]
+    pub admin_user: String,
+
+    /// The password of the LDAP admin. Defaults to the LLDAP_LDAP_USER_PASS environment variable.
+    #[clap(long)]
+    pub admin_password: Option<String>,
+
+    /// Number of synthetic users, created if missing.
+    #[clap(long, default_value = "100")]
+    pub users: usize,
+
+    /// Number of synthetic groups, among which the users are spread.
+    #[clap(long, default_value = "10")]
+    pub groups: usize,
+
+    /// Total number of requests.
+    #[clap(long, default_value = "1000")]
+    pub requests: usize,
+
+    /// Number of clients sending requests at the same time.
+    #[clap(long, default_value = "10")]
+    pub concurrency: usize,
+
+    /// Relative weight of each kind of request.
+    #[clap(long, default_value = "bind=1,search=1,graphql=1")]
+    pub mix: String,
+
+    /// Delete the synthetic users and groups at the end.
+    #[clap(long)]
+    pub cleanup: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Ldif,
+    Csv,
+}
+
+impl std::str::FromStr for ImportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ldif" => Ok(ImportFormat::Ldif),
+            "csv" => Ok(ImportFormat::Csv),
+            _ => Err(format!("Unknown import format: '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clap, Clone)]
+pub struct RestoreOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    /// The backup to restore, from `backup_directory`.
+    #[clap(long)]
+    pub backup_file: String,
+}
+
+#[cfg(feature = "mysql")]
+#[derive(Debug, Clap, Clone)]
+pub struct MigrateFromSqliteOpts {
+    /// Change config file name
+    #[clap(short, long, default_value = "lldap_config.toml")]
+    pub config_file: String,
+
+    /// The SQLite database to copy, e.g. "sqlite://users.db".
+    #[clap(long)]
+    pub sqlite_url: String,
+}
+
 #[derive(Debug, Clap, Clone)]
 pub struct ExportGraphQLSchemaOpts {
     /// Output to a file. If not specified, the config is printed to the standard output.
@@ -48,3 +486,32 @@ pub struct ExportGraphQLSchemaOpts {
 pub fn init() -> CLIOpts {
     CLIOpts::parse()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_from_error() {
+        let not_found = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("unable to open 'schema.graphql'");
+        assert_eq!(ExitCode::from_error(&not_found), ExitCode::NotFound);
+        let auth = anyhow::Error::new(DomainError::AuthenticationError("bob".to_string()));
+        assert_eq!(ExitCode::from_error(&auth), ExitCode::PermissionDenied);
+        let db = anyhow::Error::new(DomainError::DatabaseError(sqlx::Error::PoolTimedOut));
+        assert_eq!(ExitCode::from_error(&db), ExitCode::Transient);
+        let api = anyhow::Error::new(ApiError::Unauthorized("Invalid token".to_string()))
+            .context("While listing the users");
+        assert_eq!(ExitCode::from_error(&api), ExitCode::PermissionDenied);
+        assert_eq!(
+            ExitCode::from_error(&anyhow::anyhow!("something")),
+            ExitCode::Error
+        );
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
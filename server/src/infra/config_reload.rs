@@ -0,0 +1,176 @@
+//! Reloads the configuration on SIGHUP. Only the log levels and the HTTP rate limits can change
+//! without a restart: the other settings are baked into the running servers, and a change to them
+//! is only reported.
+
+use crate::infra::{
+    cli::RunOpts, configuration::Configuration, logging, rate_limit::HttpRateLimiters,
+};
+use tracing::{error, info, warn};
+
+/// The settings that differ between the two configurations, apart from the ones applied on the
+/// fly.
+fn settings_needing_restart(old: &Configuration, new: &Configuration) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    macro_rules! compare {
+        ($($field:ident),*) => {
+            $(
+                if old.$field != new.$field {
+                    changed.push(stringify!($field));
+                }
+            )*
+        };
+    }
+    compare!(
+        ldap_port,
+        ldap_addresses,
+        ldaps_port,
+        http_port,
+        http_addresses,
+        https_port,
+        tls_certificate_file,
+        tls_private_key_file,
+        acme_domain,
+        acme_email,
+        acme_directory_url,
+        acme_storage_directory,
+        jwt_secret,
+        ldap_base_dn,
+        ldap_user_dn,
+        database_url,
+        database_max_connections,
+        database_acquire_timeout_seconds,
+        database_statement_timeout_seconds,
+        backup_directory,
+        backup_interval_hours,
+        backup_retention_count,
+        backup_encryption_key,
+        disabled_jobs,
+        job_jitter_seconds,
+        log_format,
+        key_file,
+        trusted_header,
+        trusted_proxies,
+        cors_allowed_origins,
+        cors_allowed_headers,
+        enforce_unique_emails,
+        allow_email_login,
+        jwt_duration_minutes,
+        refresh_token_duration_days,
+        jwt_algorithm,
+        jwt_private_key_file,
+        jwt_key_rotation_days,
+        invite_duration_hours,
+        deleted_user_retention_days,
+        login_lockout_threshold,
+        login_lockout_duration_minutes,
+        login_proof_of_work_difficulty,
+        ldap_cache_ttl_seconds,
+        oidc_issuer,
+        upstream_sync
+    );
+    changed
+}
+
+fn reload(opts: &RunOpts, current: &mut Configuration, rate_limiters: &HttpRateLimiters) {
+    match crate::infra::configuration::init(opts.clone()) {
+        Ok(new_config) => apply(current, new_config, rate_limiters),
+        Err(e) => error!(
+            "Could not reload the configuration, keeping the current one: {:#}",
+            e
+        ),
+    }
+}
+
+fn apply(current: &mut Configuration, new_config: Configuration, rate_limiters: &HttpRateLimiters) {
+    if new_config.log_levels != current.log_levels {
+        match logging::set_log_levels(&new_config.log_levels) {
+            Ok(()) => info!("Log levels set to `{}`", new_config.log_levels),
+            Err(e) => error!("Could not apply the new log levels: {:#}", e),
+        }
+    }
+    if new_config.http_rate_limit_per_ip != current.http_rate_limit_per_ip
+        || new_config.http_rate_limit_per_user != current.http_rate_limit_per_user
+    {
+        rate_limiters.set_limits(&new_config);
+        info!(
+            "HTTP rate limits set to {} per IP and {} per user",
+            new_config.http_rate_limit_per_ip, new_config.http_rate_limit_per_user
+        );
+    }
+    let changed = settings_needing_restart(current, &new_config);
+    if !changed.is_empty() {
+        warn!(
+            "These settings changed but need a restart to apply: {}",
+            changed.join(", ")
+        );
+    }
+    info!("Configuration reloaded");
+    *current = new_config;
+}
+
+/// Listens for SIGHUP in the background, and reloads the configuration from the same sources as
+/// on startup. The open connections are not affected.
+#[cfg(unix)]
+pub fn reload_on_sighup(
+    opts: RunOpts,
+    config: Configuration,
+    rate_limiters: HttpRateLimiters,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = signal(SignalKind::hangup())?;
+    actix_rt::spawn(async move {
+        let mut config = config;
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the configuration");
+            crate::infra::systemd::notify("RELOADING=1");
+            reload(&opts, &mut config, &rate_limiters);
+            crate::infra::systemd::notify("READY=1");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(
+    _opts: RunOpts,
+    _config: Configuration,
+    _rate_limiters: HttpRateLimiters,
+) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::configuration::ConfigurationBuilder;
+
+    #[test]
+    fn test_settings_needing_restart() {
+        let old = ConfigurationBuilder::default().build().unwrap();
+        let mut new = old.clone();
+        assert!(settings_needing_restart(&old, &new).is_empty());
+        new.log_levels = "ldap=debug".to_string();
+        assert!(settings_needing_restart(&old, &new).is_empty());
+        new.http_port = 8080;
+        new.login_lockout_threshold = 5;
+        assert_eq!(
+            settings_needing_restart(&old, &new),
+            vec!["http_port", "login_lockout_threshold"]
+        );
+        new.http_rate_limit_per_ip = old.http_rate_limit_per_ip + 10;
+        assert_eq!(settings_needing_restart(&old, &new).len(), 2);
+    }
+
+    #[test]
+    fn test_apply_rate_limits() {
+        let mut current = ConfigurationBuilder::default().build().unwrap();
+        let rate_limiters = HttpRateLimiters::new(&current);
+        let mut new = current.clone();
+        new.http_rate_limit_per_ip = current.http_rate_limit_per_ip + 10;
+        new.http_rate_limit_per_user = 0;
+        apply(&mut current, new, &rate_limiters);
+        assert_eq!(rate_limiters.per_ip.limit(), current.http_rate_limit_per_ip);
+        assert_eq!(rate_limiters.per_user.limit(), 0);
+        assert_eq!(current.http_rate_limit_per_user, 0);
+    }
+}
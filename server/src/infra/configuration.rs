@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
@@ -6,8 +6,12 @@ use figment::{
 use lldap_auth::opaque::{server::ServerSetup, KeyPair};
 use log::*;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
-use crate::infra::cli::RunOpts;
+use crate::{
+    domain::sql_tables::DATABASE_URL_SCHEME,
+    infra::{cli::RunOpts, db_cleaner::JOB_NAMES, listeners::parse_listen_address},
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
 #[builder(
@@ -17,20 +21,202 @@ use crate::infra::cli::RunOpts;
 )]
 pub struct Configuration {
     pub ldap_port: u16,
+    /// Addresses of the LDAP server, as "IP" or "IP:port", e.g. ["127.0.0.1", "[::1]:389"]. Empty
+    /// listens on all the interfaces, IPv6 and IPv4.
+    pub ldap_addresses: Vec<String>,
+    /// Port of the LDAPS server, on the `ldap_addresses`. Only listens when TLS is set up.
     pub ldaps_port: u16,
     pub http_port: u16,
+    /// Addresses of the HTTP server, like `ldap_addresses`.
+    pub http_addresses: Vec<String>,
+    /// Port of the HTTPS server, on the `http_addresses`. Only listens when TLS is set up.
+    pub https_port: u16,
+    /// PEM certificate chain of the LDAPS and HTTPS servers, starting with the certificate of the
+    /// server. Unset, without `acme_domain`, disables them.
+    pub tls_certificate_file: Option<String>,
+    /// PEM private key of `tls_certificate_file`.
+    pub tls_private_key_file: Option<String>,
+    /// Domain to obtain the certificate for from the ACME server, e.g. "lldap.example.com", instead
+    /// of `tls_certificate_file`. Port 80 of the domain must reach the HTTP server.
+    pub acme_domain: Option<String>,
+    /// Contact of the ACME account, for the expiry notices.
+    pub acme_email: Option<String>,
+    /// Directory URL of the ACME server. Let's Encrypt by default.
+    pub acme_directory_url: String,
+    /// Where the ACME account key, the certificate and its private key are kept.
+    pub acme_storage_directory: String,
     pub jwt_secret: String,
     pub ldap_base_dn: String,
     pub ldap_user_dn: String,
     pub ldap_user_pass: String,
     pub database_url: String,
+    /// Apply the pending schema migrations when starting. Unset, the server refuses to start until
+    /// they are applied with `lldap migrate up`.
+    pub auto_migrate: bool,
+    /// Maximum number of connections to the database: beyond that, the requests wait for one.
+    pub database_max_connections: u32,
+    /// How long a request waits for a free database connection before failing.
+    pub database_acquire_timeout_seconds: u64,
+    /// Longest a statement can run before the database interrupts it, 0 for no limit. On SQLite,
+    /// bounds the wait for a locked database instead.
+    pub database_statement_timeout_seconds: u64,
+    /// Directory of the scheduled database backups. Unset disables the backups.
+    pub backup_directory: Option<String>,
+    /// Minimum time between two scheduled backups.
+    pub backup_interval_hours: i64,
+    /// Number of backups to keep, the older ones are deleted. 0 keeps them all.
+    pub backup_retention_count: usize,
+    /// Passphrase encrypting the backups and the exports written to a file. Unset leaves them in
+    /// clear.
+    pub backup_encryption_key: Option<String>,
+    /// Background jobs that don't run on schedule, e.g. ["upstream_sync"]. They start paused.
+    pub disabled_jobs: Vec<String>,
+    /// Maximum random delay of the jobs after their scheduled time.
+    pub job_jitter_seconds: u64,
     pub verbose: bool,
+    /// Per-subsystem log levels, e.g. "ldap=debug,sql=warn,http=info".
+    pub log_levels: String,
+    /// "text" for human-readable logs, or "json" for one JSON object per line.
+    pub log_format: String,
     pub key_file: String,
+    /// Header set by an authenticating reverse proxy with the user ID, e.g. "Remote-User".
+    pub trusted_header: Option<String>,
+    /// Addresses of the reverse proxies allowed to set `trusted_header`.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Origins allowed to call the API and the login endpoints from a browser, e.g.
+    /// ["https://portal.example.com"], or ["*"] for any. Empty disables CORS.
+    pub cors_allowed_origins: Vec<String>,
+    /// Request headers allowed from these origins, on top of Authorization and Content-Type.
+    pub cors_allowed_headers: Vec<String>,
+    /// Reject the creation or update of a user with the email of another user.
+    pub enforce_unique_emails: bool,
+    /// Let the users log in (web app or LDAP bind) with their email instead of their user ID.
+    pub allow_email_login: bool,
+    /// Lifetime of the JWTs, renewed with the refresh token before they expire.
+    pub jwt_duration_minutes: i64,
+    /// Lifetime of the refresh tokens, i.e. of the web sessions.
+    pub refresh_token_duration_days: i64,
+    /// "HS512" to sign the JWTs with `jwt_secret`, or "RS256" or "EdDSA" with
+    /// `jwt_private_key_file`.
+    pub jwt_algorithm: String,
+    /// PEM file with the private key signing the JWTs: RSA for "RS256", Ed25519 for "EdDSA".
+    pub jwt_private_key_file: Option<String>,
+    /// Replace the JWT signing key with a new random one every N days. 0 disables the rotation.
+    pub jwt_key_rotation_days: i64,
+    /// How long the invite links, for new users to set their password, stay valid.
+    pub invite_duration_hours: i64,
+    /// How long the deleted users can be restored before they are purged. 0 deletes them for good
+    /// right away.
+    pub deleted_user_retention_days: i64,
+    /// Number of consecutive failed logins (web or LDAP) after which a user is locked. 0 disables
+    /// the lockout.
+    pub login_lockout_threshold: i32,
+    /// How long a user stays locked.
+    pub login_lockout_duration_minutes: i64,
+    /// Number of leading zero bits of the proof of work asked before the web logins and on the
+    /// password change page. Each bit doubles the work. 0 disables the proof of work.
+    pub login_proof_of_work_difficulty: u32,
+    /// Requests per minute from a client IP to the login, password change and API endpoints,
+    /// above which they are answered with a 429 status. 0 disables the limit.
+    pub http_rate_limit_per_ip: u32,
+    /// Same, for the login attempts on a user and the API requests of a user.
+    pub http_rate_limit_per_user: u32,
+    /// How long the bind targets, user details and group memberships looked up by the LDAP server
+    /// are kept in memory. 0 disables the cache.
+    pub ldap_cache_ttl_seconds: u64,
+    /// Public URL of LLDAP, e.g. "https://lldap.example.com", to act as OpenID Connect provider.
+    /// Unset disables the provider.
+    pub oidc_issuer: Option<String>,
+    /// OpenID Connect clients, on top of the ones registered through the API.
+    pub oidc_clients: Vec<OidcClientConfig>,
+    /// Public URL of the web app, e.g. "https://lldap.example.com", for the users to register
+    /// security keys (WebAuthn). Unset disables them.
+    pub webauthn_origin: Option<String>,
+    /// Upstream LDAP server or Active Directory to import the users and groups from, every hour.
+    /// Unset disables the sync.
+    pub upstream_sync: Option<UpstreamSyncConfig>,
+    /// Name, logo and color shown by the web app instead of the LLDAP ones.
+    pub branding: BrandingConfig,
     #[serde(skip)]
     #[builder(field(private), setter(strip_option))]
     server_setup: Option<ServerSetup>,
 }
 
+/// A client of the OpenID Connect provider, declared in the configuration.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OidcClientConfig {
+    pub client_id: String,
+    /// Unset for the public clients (e.g. native apps), only protected by PKCE.
+    pub client_secret: Option<String>,
+    /// Shown on the login page. Defaults to the client ID.
+    pub display_name: Option<String>,
+    /// Where the users can be sent back after logging in, checked exactly.
+    pub redirect_uris: Vec<String>,
+}
+
+/// The branding of the web app, fetched by the app when it starts.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BrandingConfig {
+    /// Shown in the header and in the title of the pages.
+    #[serde(default = "default_product_name")]
+    pub product_name: String,
+    /// PNG, JPEG or SVG file shown next to the name.
+    #[serde(default)]
+    pub logo_file: Option<String>,
+    /// Color of the buttons and links, as "#rrggbb".
+    #[serde(default)]
+    pub accent_color: Option<String>,
+    /// Plain text shown on the login page, e.g. "Authorized use only".
+    #[serde(default)]
+    pub login_message: Option<String>,
+    /// Show the `login_message` above the other pages too.
+    #[serde(default)]
+    pub login_message_everywhere: bool,
+}
+
+fn default_product_name() -> String {
+    "LLDAP".to_string()
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        Self {
+            product_name: default_product_name(),
+            logo_file: None,
+            accent_color: None,
+            login_message: None,
+            login_message_everywhere: false,
+        }
+    }
+}
+
+/// The upstream directory of the `upstream_sync` job.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpstreamSyncConfig {
+    /// e.g. "ldaps://ad.example.com" or "ldap://ldap.example.com:389".
+    pub url: String,
+    pub bind_dn: String,
+    pub bind_password: String,
+    /// Where the users and groups are searched.
+    pub base_dn: String,
+    #[serde(default = "default_upstream_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_upstream_group_filter")]
+    pub group_filter: String,
+    /// Overrides of the attributes read, as `field=attribute`, e.g. "user_id=sAMAccountName".
+    #[serde(default)]
+    pub attribute_mappings: Vec<String>,
+}
+
+fn default_upstream_user_filter() -> String {
+    "(objectClass=person)".to_string()
+}
+
+fn default_upstream_group_filter() -> String {
+    "(|(objectClass=groupOfNames)(objectClass=groupOfUniqueNames)(objectClass=posixGroup)(objectClass=group))"
+        .to_string()
+}
+
 impl ConfigurationBuilder {
     #[cfg(test)]
     pub fn build(self) -> Result<Configuration> {
@@ -75,16 +261,61 @@ impl Configuration {
     pub(super) fn default() -> Self {
         Configuration {
             ldap_port: 3890,
+            ldap_addresses: Vec::new(),
             ldaps_port: 6360,
             http_port: 17170,
+            http_addresses: Vec::new(),
+            https_port: 17171,
+            tls_certificate_file: None,
+            tls_private_key_file: None,
+            acme_domain: None,
+            acme_email: None,
+            acme_directory_url: String::from("https://acme-v02.api.letsencrypt.org/directory"),
+            acme_storage_directory: String::from("acme"),
             jwt_secret: String::from("secretjwtsecret"),
             ldap_base_dn: String::from("dc=example,dc=com"),
             // cn=admin,dc=example,dc=com
             ldap_user_dn: String::from("admin"),
             ldap_user_pass: String::from("password"),
             database_url: String::from("sqlite://users.db?mode=rwc"),
+            auto_migrate: true,
+            database_max_connections: 5,
+            database_acquire_timeout_seconds: 30,
+            database_statement_timeout_seconds: 0,
+            backup_directory: None,
+            backup_interval_hours: 24,
+            backup_retention_count: 7,
+            backup_encryption_key: None,
+            disabled_jobs: Vec::new(),
+            job_jitter_seconds: 0,
             verbose: false,
+            log_levels: String::new(),
+            log_format: String::from("text"),
             key_file: String::from("server_key"),
+            trusted_header: None,
+            trusted_proxies: Vec::new(),
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_headers: Vec::new(),
+            enforce_unique_emails: false,
+            allow_email_login: false,
+            jwt_duration_minutes: 15,
+            refresh_token_duration_days: 30,
+            jwt_algorithm: String::from("HS512"),
+            jwt_private_key_file: None,
+            jwt_key_rotation_days: 0,
+            invite_duration_hours: 72,
+            deleted_user_retention_days: 30,
+            login_lockout_threshold: 0,
+            login_lockout_duration_minutes: 15,
+            login_proof_of_work_difficulty: 0,
+            http_rate_limit_per_ip: 0,
+            http_rate_limit_per_user: 0,
+            ldap_cache_ttl_seconds: 0,
+            oidc_issuer: None,
+            oidc_clients: Vec::new(),
+            webauthn_origin: None,
+            upstream_sync: None,
+            branding: BrandingConfig::default(),
             server_setup: None,
         }
     }
@@ -108,15 +339,206 @@ fn get_server_setup(file_path: &str) -> Result<ServerSetup> {
     }
 }
 
+/// Settings holding a secret. Each can instead be read from the file given by `<setting>_file`
+/// (or `LLDAP_<SETTING>_FILE`), e.g. to use Docker or Kubernetes secrets.
+const SECRET_SETTINGS: [&str; 5] = [
+    "jwt_secret",
+    "ldap_user_pass",
+    "database_url",
+    "backup_encryption_key",
+    "upstream_sync.bind_password",
+];
+
+fn read_secret_files(mut figment: Figment) -> Result<Figment> {
+    for setting in SECRET_SETTINGS.iter() {
+        let file_setting = format!("{}_file", setting);
+        if let Ok(file_path) = figment.extract_inner::<String>(&file_setting) {
+            let secret = std::fs::read_to_string(&file_path).context(format!(
+                "Could not read `{}` from file `{}`",
+                setting, file_path
+            ))?;
+            // Files written by editors or `echo` end with a newline that isn't part of the secret.
+            let secret = secret.trim_end_matches(&['\r', '\n'][..]).to_string();
+            figment = figment.merge(Serialized::default(setting, secret));
+        }
+    }
+    Ok(figment)
+}
+
 pub fn init(cli_opts: RunOpts) -> Result<Configuration> {
     let config_file = cli_opts.config_file.clone();
 
     info!("Loading configuration from {}", cli_opts.config_file);
 
-    let config: Configuration = Figment::from(Serialized::defaults(Configuration::default()))
-        .merge(Toml::file(config_file))
-        .merge(Env::prefixed("LLDAP_"))
-        .extract()?;
+    let config: Configuration = read_secret_files(
+        Figment::from(Serialized::defaults(Configuration::default()))
+            .merge(Toml::file(config_file))
+            .merge(Env::prefixed("LLDAP_")),
+    )?
+    .extract()?;
+
+    if !config.database_url.starts_with(DATABASE_URL_SCHEME) {
+        bail!(
+            "`database_url` must start with `{}`: the other databases need another build of LLDAP",
+            DATABASE_URL_SCHEME
+        );
+    }
+
+    if config.jwt_duration_minutes <= 0 {
+        bail!("`jwt_duration_minutes` must be positive");
+    }
+
+    if config.refresh_token_duration_days <= 0 {
+        bail!("`refresh_token_duration_days` must be positive");
+    }
+
+    if config.jwt_key_rotation_days < 0 {
+        bail!("`jwt_key_rotation_days` can't be negative");
+    }
+
+    match config.jwt_algorithm.as_str() {
+        "HS512" => (),
+        algorithm if algorithm == "RS256" || algorithm == "EdDSA" => {
+            if config.jwt_private_key_file.is_none() {
+                bail!(
+                    "`jwt_algorithm` is {} but `jwt_private_key_file` is not set",
+                    algorithm
+                );
+            }
+            if config.jwt_key_rotation_days != 0 {
+                bail!(
+                    "The {} keypair can't be rotated, unset `jwt_key_rotation_days`",
+                    algorithm
+                );
+            }
+        }
+        algorithm => bail!(
+            "Unsupported `jwt_algorithm` `{}`, expected HS512, RS256 or EdDSA",
+            algorithm
+        ),
+    }
+
+    for address in &config.ldap_addresses {
+        parse_listen_address(address, config.ldap_port).context("Invalid `ldap_addresses`")?;
+    }
+    for address in &config.http_addresses {
+        parse_listen_address(address, config.http_port).context("Invalid `http_addresses`")?;
+    }
+
+    if config.tls_certificate_file.is_some() != config.tls_private_key_file.is_some() {
+        bail!("`tls_certificate_file` and `tls_private_key_file` go together");
+    }
+
+    if config.acme_domain.is_some() {
+        if config.tls_certificate_file.is_some() {
+            bail!("The certificate comes either from `acme_domain` or from `tls_certificate_file`, not both");
+        }
+        if !config.acme_directory_url.starts_with("https://") {
+            bail!("`acme_directory_url` must start with https://");
+        }
+    }
+
+    if config.log_format != "text" && config.log_format != "json" {
+        bail!(
+            "Unsupported `log_format` `{}`, expected text or json",
+            config.log_format
+        );
+    }
+
+    if config.database_max_connections == 0 || config.database_acquire_timeout_seconds == 0 {
+        bail!("`database_max_connections` and `database_acquire_timeout_seconds` must be positive");
+    }
+
+    if config.backup_interval_hours <= 0 {
+        bail!("`backup_interval_hours` must be positive");
+    }
+
+    if let Some(job) = config
+        .disabled_jobs
+        .iter()
+        .find(|job| !JOB_NAMES.contains(&job.as_str()))
+    {
+        bail!(
+            "Unknown job `{}` in `disabled_jobs`, expected one of {}",
+            job,
+            JOB_NAMES.join(", ")
+        );
+    }
+
+    if config.invite_duration_hours <= 0 {
+        bail!("`invite_duration_hours` must be positive");
+    }
+
+    if config.deleted_user_retention_days < 0 {
+        bail!("`deleted_user_retention_days` can't be negative");
+    }
+
+    if config.login_lockout_threshold < 0 || config.login_lockout_duration_minutes <= 0 {
+        bail!("`login_lockout_threshold` can't be negative, and `login_lockout_duration_minutes` must be positive");
+    }
+
+    if config.login_proof_of_work_difficulty > 24 {
+        bail!("`login_proof_of_work_difficulty` is too high, the browsers would take minutes to log in");
+    }
+
+    if let Some(issuer) = &config.oidc_issuer {
+        if config.jwt_algorithm == "HS512" {
+            bail!("The OpenID Connect provider needs `jwt_algorithm` to be RS256 or EdDSA, for the clients to check the ID tokens");
+        }
+        if issuer.ends_with('/') {
+            bail!("`oidc_issuer` shouldn't end with a slash");
+        }
+    }
+
+    if let Some(client) = config
+        .oidc_clients
+        .iter()
+        .find(|c| c.client_id.is_empty() || c.redirect_uris.is_empty())
+    {
+        bail!(
+            "The OpenID Connect client `{}` needs an ID and at least one redirect URI",
+            client.client_id
+        );
+    }
+
+    if let Some(origin) = &config.webauthn_origin {
+        if origin.ends_with('/') {
+            bail!("`webauthn_origin` shouldn't end with a slash");
+        }
+    }
+
+    if let Some(upstream_sync) = &config.upstream_sync {
+        if !upstream_sync.url.starts_with("ldap://") && !upstream_sync.url.starts_with("ldaps://") {
+            bail!("`upstream_sync.url` must start with ldap:// or ldaps://");
+        }
+        crate::infra::upstream_sync::get_mapping(upstream_sync)
+            .context("Invalid `upstream_sync.attribute_mappings`")?;
+    }
+
+    for origin in &config.cors_allowed_origins {
+        if origin != "*"
+            && (!(origin.starts_with("https://") || origin.starts_with("http://"))
+                || origin.ends_with('/'))
+        {
+            bail!(
+                "Invalid origin `{}` in `cors_allowed_origins`, expected e.g. \"https://portal.example.com\" or \"*\"",
+                origin
+            );
+        }
+    }
+    if let Some(header) = config
+        .cors_allowed_headers
+        .iter()
+        .find(|h| http::header::HeaderName::from_bytes(h.as_bytes()).is_err())
+    {
+        bail!("Invalid header `{}` in `cors_allowed_headers`", header);
+    }
+
+    if config.trusted_header.is_some() && config.trusted_proxies.is_empty() {
+        bail!(
+            "`trusted_header` is set but `trusted_proxies` is empty: no request would be trusted"
+        );
+    }
 
     let mut config = config.merge_with_cli(cli_opts);
     config.server_setup = Some(get_server_setup(&config.key_file)?);
@@ -1,17 +1,107 @@
 use crate::{
-    domain::sql_tables::{DbQueryBuilder, Pool},
-    infra::jwt_sql_tables::{JwtRefreshStorage, JwtStorage},
+    domain::sql_tables::{ApiTokens, DbQueryBuilder, DeletedUsers, LoginHistory, Pool, Users},
+    infra::{
+        acme::AcmeClient,
+        backup::{self, BackupSchedule},
+        configuration::Configuration,
+        jwt_keys::JwtKeyStore,
+        jwt_sql_tables::{JwtRefreshStorage, JwtStorage},
+        upstream_sync::UpstreamSync,
+    },
 };
 use actix::prelude::*;
-use chrono::Local;
+use chrono::{DateTime, Local, Utc};
 use cron::Schedule;
+use futures::future::LocalBoxFuture;
+use rand::Rng;
 use sea_query::{Expr, Query};
-use std::{str::FromStr, time::Duration};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
-// Define actor
+/// Name of the job cleaning up the expired tokens.
+pub const DB_CLEANUP_JOB: &str = "db_cleanup";
+/// Name of the job disabling the users whose account expired.
+pub const DISABLE_EXPIRED_USERS_JOB: &str = "disable_expired_users";
+/// Name of the job clearing the lockouts that ended.
+pub const EXPIRE_LOCKOUTS_JOB: &str = "expire_lockouts";
+/// Name of the job rotating the JWT signing key, when it's due. A manual run always rotates it.
+pub const ROTATE_JWT_KEY_JOB: &str = "rotate_jwt_key";
+/// Name of the job backing up the database, when it's due. A manual run always backs it up.
+pub const BACKUP_JOB: &str = "backup";
+/// Name of the job importing the users and groups of the upstream directory.
+pub const UPSTREAM_SYNC_JOB: &str = "upstream_sync";
+/// Name of the job renewing the ACME certificate, when it's due. A manual run always renews it.
+pub const RENEW_CERTIFICATE_JOB: &str = "renew_certificate";
+
+/// How long the logins of the users are kept.
+pub const LOGIN_HISTORY_RETENTION_DAYS: i64 = 90;
+
+pub const JOB_NAMES: [&str; 7] = [
+    DB_CLEANUP_JOB,
+    DISABLE_EXPIRED_USERS_JOB,
+    EXPIRE_LOCKOUTS_JOB,
+    ROTATE_JWT_KEY_JOB,
+    BACKUP_JOB,
+    UPSTREAM_SYNC_JOB,
+    RENEW_CERTIFICATE_JOB,
+];
+
+/// The state of a background job, for the admins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub name: String,
+    /// Paused jobs are skipped on schedule, but can still be triggered manually.
+    pub paused: bool,
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobRun {
+    pub start: DateTime<Utc>,
+    pub duration: chrono::Duration,
+    /// Set if the run failed.
+    pub error: Option<String>,
+}
+
+/// Returns the status of all the jobs.
+#[derive(Message)]
+#[rtype(result = "Vec<JobStatus>")]
+pub struct ListJobs;
+
+/// Runs a job now, unless it's already running.
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct TriggerJob(pub String);
+
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct SetJobPaused {
+    pub name: String,
+    pub paused: bool,
+}
+
+#[derive(Default)]
+struct JobState {
+    paused: bool,
+    running: bool,
+    last_run: Option<JobRun>,
+}
+
+// Define actor. All the jobs run on the same schedule.
 pub struct Scheduler {
     schedule: Schedule,
     sql_pool: Pool,
+    jwt_keys: JwtKeyStore,
+    backup_schedule: Option<BackupSchedule>,
+    /// The deleted users are purged after this many days.
+    deleted_user_retention_days: i64,
+    upstream_sync: Option<UpstreamSync>,
+    acme: Option<AcmeClient>,
+    /// Maximum random delay of each job after the scheduled time, so that they don't all hit the
+    /// database at once.
+    jitter: Duration,
+    jobs: HashMap<&'static str, JobState>,
 }
 
 // Provide Actor implementation for our actor
@@ -19,7 +109,12 @@ impl Actor for Scheduler {
     type Context = Context<Self>;
 
     fn started(&mut self, context: &mut Context<Self>) {
-        log::info!("DB Cleanup Cron started");
+        log::info!("Job scheduler started");
+        if self.acme.is_some() {
+            // Without a certificate, LDAPS and HTTPS would refuse the connections until the next
+            // run.
+            self.run_scheduled_job(RENEW_CERTIFICATE_JOB, context);
+        }
 
         context.run_later(self.duration_until_next(), move |this, ctx| {
             this.schedule_task(ctx)
@@ -27,50 +122,363 @@ impl Actor for Scheduler {
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
-        log::info!("DB Cleanup stopped");
+        log::info!("Job scheduler stopped");
+    }
+}
+
+impl Handler<ListJobs> for Scheduler {
+    type Result = Vec<JobStatus>;
+
+    fn handle(&mut self, _: ListJobs, _ctx: &mut Context<Self>) -> Self::Result {
+        let next_run = self.schedule.upcoming(Utc).next();
+        JOB_NAMES
+            .iter()
+            .map(|name| {
+                let job = &self.jobs[*name];
+                JobStatus {
+                    name: name.to_string(),
+                    paused: job.paused,
+                    running: job.running,
+                    last_run: job.last_run.clone(),
+                    next_run,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Handler<TriggerJob> for Scheduler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, TriggerJob(name): TriggerJob, ctx: &mut Context<Self>) -> Self::Result {
+        let name = check_job_name(&name)?;
+        if self.jobs[name].running {
+            return Err(format!("Job `{}` is already running", name));
+        }
+        log::info!("Job `{}` triggered manually", name);
+        self.run_job(name, true, ctx);
+        Ok(())
+    }
+}
+
+impl Handler<SetJobPaused> for Scheduler {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, request: SetJobPaused, _ctx: &mut Context<Self>) -> Self::Result {
+        let name = check_job_name(&request.name)?;
+        log::info!(
+            "Job `{}` {}",
+            name,
+            if request.paused { "paused" } else { "resumed" }
+        );
+        self.jobs.get_mut(name).unwrap().paused = request.paused;
+        Ok(())
     }
 }
 
+fn check_job_name(name: &str) -> Result<&'static str, String> {
+    JOB_NAMES
+        .iter()
+        .find(|job| **job == name)
+        .copied()
+        .ok_or_else(|| format!("Unknown job `{}`", name))
+}
+
 impl Scheduler {
-    pub fn new(cron_expression: &str, sql_pool: Pool) -> Self {
+    /// The jobs in `disabled_jobs` start paused.
+    pub fn new(
+        cron_expression: &str,
+        sql_pool: Pool,
+        jwt_keys: JwtKeyStore,
+        config: &Configuration,
+        upstream_sync: Option<UpstreamSync>,
+        acme: Option<AcmeClient>,
+    ) -> Self {
         let schedule = Schedule::from_str(cron_expression).unwrap();
-        Self { schedule, sql_pool }
+        Self {
+            schedule,
+            sql_pool,
+            jwt_keys,
+            backup_schedule: BackupSchedule::from_config(config),
+            deleted_user_retention_days: config.deleted_user_retention_days,
+            upstream_sync,
+            acme,
+            jitter: Duration::from_secs(config.job_jitter_seconds),
+            jobs: JOB_NAMES
+                .iter()
+                .map(|name| {
+                    let state = JobState {
+                        paused: config.disabled_jobs.iter().any(|job| job == name),
+                        ..Default::default()
+                    };
+                    (*name, state)
+                })
+                .collect(),
+        }
     }
 
-    fn schedule_task(&self, ctx: &mut Context<Self>) {
-        log::info!("Cleaning DB");
-        let future = actix::fut::wrap_future::<_, Self>(Self::cleanup_db(self.sql_pool.clone()));
-        ctx.spawn(future);
+    fn schedule_task(&mut self, ctx: &mut Context<Self>) {
+        for name in JOB_NAMES.iter().copied() {
+            let delay = if self.jitter.as_secs() == 0 {
+                Duration::from_secs(0)
+            } else {
+                Duration::from_secs(rand::thread_rng().gen_range(0..=self.jitter.as_secs()))
+            };
+            ctx.run_later(delay, move |this, ctx| this.run_scheduled_job(name, ctx));
+        }
 
         ctx.run_later(self.duration_until_next(), move |this, ctx| {
             this.schedule_task(ctx)
         });
     }
 
-    async fn cleanup_db(sql_pool: Pool) {
+    fn run_scheduled_job(&mut self, name: &'static str, ctx: &mut Context<Self>) {
+        let job = &self.jobs[name];
+        if job.paused {
+            log::info!("Job `{}` is paused, skipping", name);
+        } else if job.running {
+            log::warn!("Job `{}` is still running, skipping", name);
+        } else {
+            self.run_job(name, false, ctx);
+        }
+    }
+
+    fn run_job(&mut self, name: &'static str, manual: bool, ctx: &mut Context<Self>) {
+        self.jobs.get_mut(name).unwrap().running = true;
+        let start = Utc::now();
+        let sql_pool = self.sql_pool.clone();
+        let job: LocalBoxFuture<'static, Result<(), String>> = match name {
+            DB_CLEANUP_JOB => {
+                Box::pin(Self::cleanup_db(sql_pool, self.deleted_user_retention_days))
+            }
+            DISABLE_EXPIRED_USERS_JOB => Box::pin(Self::disable_expired_users(sql_pool)),
+            EXPIRE_LOCKOUTS_JOB => Box::pin(Self::expire_lockouts(sql_pool)),
+            ROTATE_JWT_KEY_JOB => Box::pin(Self::rotate_jwt_key(self.jwt_keys.clone(), manual)),
+            BACKUP_JOB => Box::pin(Self::backup(sql_pool, self.backup_schedule.clone(), manual)),
+            UPSTREAM_SYNC_JOB => Box::pin(Self::sync_upstream(self.upstream_sync.clone(), manual)),
+            RENEW_CERTIFICATE_JOB => Box::pin(Self::renew_certificate(self.acme.clone(), manual)),
+            _ => unreachable!(),
+        };
+        log::debug!("Job `{}` started", name);
+        let future = job.into_actor(self).map(move |result, this, _| {
+            let duration = Utc::now() - start;
+            match &result {
+                Ok(()) => log::info!("Job `{}` done in {} ms", name, duration.num_milliseconds()),
+                Err(e) => log::warn!(
+                    "Job `{}` failed after {} ms: {}",
+                    name,
+                    duration.num_milliseconds(),
+                    e
+                ),
+            }
+            let job = this.jobs.get_mut(name).unwrap();
+            job.running = false;
+            job.last_run = Some(JobRun {
+                start,
+                duration,
+                error: result.err(),
+            });
+        });
+        ctx.spawn(future);
+    }
+
+    async fn cleanup_db(sql_pool: Pool, deleted_user_retention_days: i64) -> Result<(), String> {
+        log::info!("Cleaning DB");
+        let mut errors = Vec::new();
         if let Err(e) = sqlx::query(
             &Query::delete()
                 .from_table(JwtRefreshStorage::Table)
-                .and_where(Expr::col(JwtRefreshStorage::ExpiryDate).lt(Local::now().naive_utc()))
+                .and_where(Expr::col(JwtRefreshStorage::ExpiryDate).lt(Utc::now().naive_utc()))
                 .to_string(DbQueryBuilder {}),
         )
         .execute(&sql_pool)
         .await
         {
             log::error!("DB error while cleaning up JWT refresh tokens: {}", e);
+            errors.push(format!("JWT refresh tokens: {}", e));
         };
         if let Err(e) = sqlx::query(
             &Query::delete()
                 .from_table(JwtStorage::Table)
-                .and_where(Expr::col(JwtStorage::ExpiryDate).lt(Local::now().naive_utc()))
+                .and_where(Expr::col(JwtStorage::ExpiryDate).lt(Utc::now().naive_utc()))
                 .to_string(DbQueryBuilder {}),
         )
         .execute(&sql_pool)
         .await
         {
             log::error!("DB error while cleaning up JWT storage: {}", e);
+            errors.push(format!("JWT storage: {}", e));
+        };
+        if let Err(e) = sqlx::query(
+            &Query::delete()
+                .from_table(ApiTokens::Table)
+                .and_where(Expr::col(ApiTokens::ExpiryDate).lt(Utc::now().naive_utc()))
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&sql_pool)
+        .await
+        {
+            log::error!("DB error while cleaning up API tokens: {}", e);
+            errors.push(format!("API tokens: {}", e));
+        };
+        if let Err(e) = sqlx::query(
+            &Query::delete()
+                .from_table(LoginHistory::Table)
+                .and_where(Expr::col(LoginHistory::Timestamp).lt(
+                    (Utc::now() - chrono::Duration::days(LOGIN_HISTORY_RETENTION_DAYS)).naive_utc(),
+                ))
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&sql_pool)
+        .await
+        {
+            log::error!("DB error while cleaning up the login history: {}", e);
+            errors.push(format!("Login history: {}", e));
+        };
+        if let Err(e) = sqlx::query(
+            &Query::delete()
+                .from_table(DeletedUsers::Table)
+                .and_where(Expr::col(DeletedUsers::DeletedAt).lt(
+                    (Utc::now() - chrono::Duration::days(deleted_user_retention_days)).naive_utc(),
+                ))
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&sql_pool)
+        .await
+        {
+            log::error!("DB error while purging the deleted users: {}", e);
+            errors.push(format!("Deleted users: {}", e));
+        };
+        if errors.is_empty() {
+            log::info!("DB cleaned!");
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Flags the expired accounts as disabled. They already can't log in, but this makes it
+    /// visible, and keeps them disabled if the expiry date is removed.
+    async fn disable_expired_users(sql_pool: Pool) -> Result<(), String> {
+        let result = sqlx::query(
+            &Query::update()
+                .table(Users::Table)
+                .values(vec![(Users::Enabled, false.into())])
+                .and_where(Expr::col(Users::Enabled).eq(true))
+                .and_where(Expr::col(Users::ValidUntil).lt(Utc::now().naive_utc()))
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&sql_pool)
+        .await
+        .map_err(|e| {
+            log::error!("DB error while disabling expired users: {}", e);
+            e.to_string()
+        })?;
+        if result.rows_affected() > 0 {
+            log::info!("Disabled {} expired users", result.rows_affected());
+        }
+        Ok(())
+    }
+
+    /// Clears the end date of the lockouts that are over, so that the users don't show as locked.
+    async fn expire_lockouts(sql_pool: Pool) -> Result<(), String> {
+        let result = sqlx::query(
+            &Query::update()
+                .table(Users::Table)
+                .values(vec![(Users::LockedUntil, sea_query::Value::Null)])
+                .and_where(Expr::col(Users::LockedUntil).lt(Utc::now().naive_utc()))
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&sql_pool)
+        .await
+        .map_err(|e| {
+            log::error!("DB error while expiring the lockouts: {}", e);
+            e.to_string()
+        })?;
+        if result.rows_affected() > 0 {
+            log::info!("Cleared {} expired lockouts", result.rows_affected());
+        }
+        Ok(())
+    }
+
+    async fn rotate_jwt_key(jwt_keys: JwtKeyStore, manual: bool) -> Result<(), String> {
+        let result = if manual {
+            jwt_keys.rotate().await.map(|_| ())
+        } else {
+            jwt_keys.rotate_if_due().await
+        };
+        result.map_err(|e| {
+            log::error!("Error while rotating the JWT signing key: {:#}", e);
+            format!("{:#}", e)
+        })
+    }
+
+    async fn backup(
+        sql_pool: Pool,
+        schedule: Option<BackupSchedule>,
+        manual: bool,
+    ) -> Result<(), String> {
+        let schedule = match schedule {
+            Some(schedule) => schedule,
+            None if manual => {
+                return Err("Set `backup_directory` to enable the backups".to_string())
+            }
+            None => return Ok(()),
         };
-        log::info!("DB cleaned!");
+        backup::backup_if_due(&sql_pool, &schedule, manual)
+            .await
+            .map_err(|e| {
+                log::error!("Error while backing up the database: {:#}", e);
+                format!("{:#}", e)
+            })
+    }
+
+    async fn sync_upstream(
+        upstream_sync: Option<UpstreamSync>,
+        manual: bool,
+    ) -> Result<(), String> {
+        let upstream_sync = match upstream_sync {
+            Some(upstream_sync) => upstream_sync,
+            None if manual => return Err("Set `upstream_sync` to enable the sync".to_string()),
+            None => return Ok(()),
+        };
+        let report = upstream_sync.run().await.map_err(|e| {
+            log::error!("Error while syncing from the upstream directory: {:#}", e);
+            format!("{:#}", e)
+        })?;
+        log::info!(
+            "Upstream sync: users created: {}, updated: {}. Groups created: {}. Memberships added: {}, removed: {}.",
+            report.import.users_created,
+            report.import.users_updated,
+            report.import.groups_created,
+            report.import.memberships_added,
+            report.memberships_removed
+        );
+        for error in &report.import.errors {
+            log::warn!("Could not sync {}: {}", error.entry, error.error);
+        }
+        if report.import.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} entries could not be synced, see the logs",
+                report.import.errors.len()
+            ))
+        }
+    }
+
+    async fn renew_certificate(acme: Option<AcmeClient>, manual: bool) -> Result<(), String> {
+        let acme = match acme {
+            Some(acme) => acme,
+            None if manual => {
+                return Err("Set `acme_domain` to enable the certificate renewal".to_string())
+            }
+            None => return Ok(()),
+        };
+        acme.renew_if_due(manual).await.map_err(|e| {
+            log::error!("Error while renewing the TLS certificate: {:#}", e);
+            format!("{:#}", e)
+        })
     }
 
     fn duration_until_next(&self) -> Duration {
@@ -80,3 +488,67 @@ impl Scheduler {
         duration_until.to_std().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_tables::{get_test_pool, init_deleted_users_table, init_table};
+
+    #[actix_rt::test]
+    async fn test_trigger_and_pause_job() {
+        let sql_pool = get_test_pool().await;
+        init_table(&sql_pool).await.unwrap();
+        init_deleted_users_table(&sql_pool).await.unwrap();
+        crate::infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .unwrap();
+        let config = crate::infra::configuration::ConfigurationBuilder::default()
+            .disabled_jobs(vec![BACKUP_JOB.to_string()])
+            .build()
+            .unwrap();
+        let jwt_keys = JwtKeyStore::load(&config, sql_pool.clone()).await.unwrap();
+        let scheduler =
+            Scheduler::new("0 0 * * * * *", sql_pool, jwt_keys, &config, None, None).start();
+        let jobs = scheduler.send(ListJobs).await.unwrap();
+        assert_eq!(jobs.len(), 7);
+        assert_eq!(jobs[0].name, DB_CLEANUP_JOB);
+        assert_eq!(jobs[1].name, DISABLE_EXPIRED_USERS_JOB);
+        assert_eq!(jobs[2].name, EXPIRE_LOCKOUTS_JOB);
+        assert_eq!(jobs[3].name, ROTATE_JWT_KEY_JOB);
+        assert_eq!(jobs[4].name, BACKUP_JOB);
+        assert_eq!(jobs[5].name, UPSTREAM_SYNC_JOB);
+        assert_eq!(jobs[6].name, RENEW_CERTIFICATE_JOB);
+        assert!(jobs[4].paused);
+        assert!(!jobs[0].paused);
+        assert_eq!(jobs[0].last_run, None);
+
+        scheduler
+            .send(TriggerJob("unknown".to_string()))
+            .await
+            .unwrap()
+            .unwrap_err();
+        scheduler
+            .send(TriggerJob(DB_CLEANUP_JOB.to_string()))
+            .await
+            .unwrap()
+            .unwrap();
+        let last_run = loop {
+            let job = scheduler.send(ListJobs).await.unwrap().remove(0);
+            match job.last_run {
+                Some(run) if !job.running => break run,
+                _ => actix_rt::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(last_run.error, None);
+
+        scheduler
+            .send(SetJobPaused {
+                name: DB_CLEANUP_JOB.to_string(),
+                paused: true,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(scheduler.send(ListJobs).await.unwrap()[0].paused);
+    }
+}
@@ -0,0 +1,139 @@
+//! The connection pool of the server, sized and bounded by the `database_*` settings. Its use is
+//! sampled every second, to warn when all the connections are busy and the requests queue up.
+
+use crate::{
+    domain::sql_tables::{Pool, PoolOptions},
+    infra::configuration::Configuration,
+};
+use log::*;
+use sqlx::Executor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How often the use of the pool is checked.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Opens the pool of the server.
+pub async fn connect(config: &Configuration) -> sqlx::Result<Pool> {
+    let statement_timeout = config.database_statement_timeout_seconds;
+    PoolOptions::new()
+        .max_connections(config.database_max_connections)
+        .connect_timeout(Duration::from_secs(config.database_acquire_timeout_seconds))
+        .after_connect(move |conn| {
+            Box::pin(async move {
+                if statement_timeout > 0 {
+                    set_statement_timeout(conn, statement_timeout).await?;
+                }
+                Ok(())
+            })
+        })
+        .connect(&config.database_url)
+        .await
+}
+
+#[cfg(not(feature = "mysql"))]
+async fn set_statement_timeout(
+    conn: &mut sqlx::SqliteConnection,
+    seconds: u64,
+) -> sqlx::Result<()> {
+    // SQLite runs in the process and can't interrupt a statement: the time is lost waiting for
+    // the other writers.
+    (&mut *conn)
+        .execute(format!("PRAGMA busy_timeout = {}", seconds * 1000).as_str())
+        .await?;
+    Ok(())
+}
+
+#[cfg(feature = "mysql")]
+async fn set_statement_timeout(conn: &mut sqlx::MySqlConnection, seconds: u64) -> sqlx::Result<()> {
+    // MariaDB's setting, in seconds, or else MySQL's, in milliseconds and only for the reads.
+    if (&mut *conn)
+        .execute(format!("SET SESSION max_statement_time = {}", seconds).as_str())
+        .await
+        .is_err()
+    {
+        (&mut *conn)
+            .execute(format!("SET SESSION max_execution_time = {}", seconds * 1000).as_str())
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub max_connections: u32,
+    /// Open connections, idle or not.
+    pub connections: u32,
+    pub idle_connections: usize,
+    /// Seconds during which all the connections were busy, since the start.
+    pub saturated_seconds: u64,
+}
+
+impl PoolStats {
+    pub fn is_saturated(&self) -> bool {
+        self.connections >= self.max_connections && self.idle_connections == 0
+    }
+}
+
+/// Counts the time spent with all the connections busy.
+#[derive(Debug, Default)]
+pub struct PoolMonitor {
+    saturated_seconds: AtomicU64,
+}
+
+impl PoolMonitor {
+    pub fn get_stats(&self, pool: &Pool, max_connections: u32) -> PoolStats {
+        PoolStats {
+            max_connections,
+            connections: pool.size(),
+            idle_connections: pool.num_idle(),
+            saturated_seconds: self.saturated_seconds.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Samples the pool until the server stops, and logs when it becomes saturated, and again
+    /// when it recovers.
+    pub async fn watch(&self, pool: Pool, max_connections: u32) {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut was_saturated = false;
+        loop {
+            interval.tick().await;
+            if pool.is_closed() {
+                return;
+            }
+            let saturated = self.get_stats(&pool, max_connections).is_saturated();
+            if saturated {
+                self.saturated_seconds
+                    .fetch_add(SAMPLE_INTERVAL.as_secs(), Ordering::Relaxed);
+            }
+            if saturated && !was_saturated {
+                warn!(
+                    "All the {} database connections are in use, the requests are waiting: consider raising `database_max_connections`",
+                    max_connections
+                );
+            } else if !saturated && was_saturated {
+                info!("Database connections available again");
+            }
+            was_saturated = saturated;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_tables::get_test_pool;
+
+    #[actix_rt::test]
+    async fn test_pool_stats() {
+        let pool = get_test_pool().await;
+        let monitor = PoolMonitor::default();
+        let connection = pool.acquire().await.unwrap();
+        let stats = monitor.get_stats(&pool, 1);
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.idle_connections, 0);
+        assert!(stats.is_saturated());
+        drop(connection);
+        assert!(!monitor.get_stats(&pool, 10).is_saturated());
+    }
+}
@@ -0,0 +1,139 @@
+//! Encryption at rest of the backups and of the exports, with the passphrase in
+//! `backup_encryption_key`. The key is derived from the passphrase with Argon2i and a random salt,
+//! and the content is sealed with XChaCha20-Poly1305: a wrong passphrase or a tampered file fails
+//! to decrypt.
+//!
+//! An encrypted file starts with `MAGIC`, then the salt, then the sealed content (nonce included).
+
+use anyhow::{bail, Context, Result};
+use orion::{aead, kdf};
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"LLDAP-ENCRYPTED-1\n";
+const SALT_LENGTH: usize = 16;
+/// Argon2i parameters: the passes, and the memory in KiB.
+const KDF_ITERATIONS: u32 = 3;
+const KDF_MEMORY: u32 = 1 << 16;
+
+/// The suffix of the names of the encrypted files.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+fn derive_key(passphrase: &str, salt: &kdf::Salt) -> Result<aead::SecretKey> {
+    let password = kdf::Password::from_slice(passphrase.as_bytes())?;
+    Ok(kdf::derive_key(
+        &password,
+        salt,
+        KDF_ITERATIONS,
+        KDF_MEMORY,
+        32,
+    )?)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let salt = kdf::Salt::generate(SALT_LENGTH)?;
+    let key = derive_key(passphrase, &salt)?;
+    let mut output = MAGIC.to_vec();
+    output.extend_from_slice(salt.as_ref());
+    output.extend(aead::seal(&key, plaintext)?);
+    Ok(output)
+}
+
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .context("The file is not encrypted")?;
+    if data.len() < SALT_LENGTH {
+        bail!("The encrypted file is truncated");
+    }
+    let (salt, sealed) = data.split_at(SALT_LENGTH);
+    let key = derive_key(passphrase, &kdf::Salt::from_slice(salt)?)?;
+    aead::open(&key, sealed)
+        .ok()
+        .context("Could not decrypt the file: wrong passphrase, or corrupted file")
+}
+
+/// The content of the file, decrypted with the passphrase if it is encrypted.
+pub fn read_file(path: &Path, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).context(format!("unable to read {:?}", path))?;
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    match passphrase {
+        Some(passphrase) => decrypt(passphrase, &data),
+        None => bail!(
+            "{:?} is encrypted, set `backup_encryption_key` to decrypt it",
+            path
+        ),
+    }
+}
+
+/// Creates a file only readable by its owner, since the backups and the exports hold the personal
+/// data of the users and the password hashes. Fails if the file exists.
+pub fn create_private_file(path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .context(format!("unable to create {:?}", path))
+}
+
+/// Writes the data in a new private file, encrypted if there is a passphrase.
+pub fn write_private_file(path: &Path, data: &[u8], passphrase: Option<&str>) -> Result<()> {
+    let data = match passphrase {
+        Some(passphrase) => encrypt(passphrase, data)?,
+        None => data.to_vec(),
+    };
+    create_private_file(path)?
+        .write_all(&data)
+        .context(format!("unable to write in {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let encrypted = encrypt("passphrase", b"dn: uid=bob").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(b"dn: uid=bob"));
+        assert_eq!(decrypt("passphrase", &encrypted).unwrap(), b"dn: uid=bob");
+        decrypt("wrong", &encrypted).unwrap_err();
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        decrypt("passphrase", &tampered).unwrap_err();
+        decrypt("passphrase", &encrypted[..MAGIC.len() + 4]).unwrap_err();
+        // The salt is random.
+        assert_ne!(encrypt("passphrase", b"dn: uid=bob").unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_private_file() {
+        let path =
+            std::env::temp_dir().join(format!("lldap_encryption_test_{}", rand::random::<u64>()));
+        write_private_file(&path, b"secret", Some("passphrase")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        assert_eq!(read_file(&path, Some("passphrase")).unwrap(), b"secret");
+        read_file(&path, None).unwrap_err();
+        // Doesn't overwrite.
+        write_private_file(&path, b"other", None).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{
-    domain::handler::BackendHandler,
+    domain::handler::{BackendHandler, API_TOKEN_PREFIX},
     infra::{
-        auth_service::{check_if_token_is_valid, ValidationResults},
-        cli::ExportGraphQLSchemaOpts,
+        auth_service::{check_if_api_token_is_valid, check_if_token_is_valid, ValidationResults},
+        cli::{CommandOutput, ExportGraphQLSchemaOpts},
+        db_cleaner::Scheduler,
+        rest_api::authenticate,
+        tcp_backend_handler::TcpBackendHandler,
         tcp_server::AppState,
     },
 };
@@ -16,6 +19,12 @@ use super::{mutation::Mutation, query::Query};
 pub struct Context<Handler: BackendHandler> {
     pub handler: Box<Handler>,
     pub validation_result: ValidationResults,
+    /// Where the request comes from, for the audit log.
+    pub source: String,
+    /// Runs the background jobs. Only missing when there is no server, e.g. in tests.
+    pub scheduler: Option<actix::Addr<Scheduler>>,
+    /// Base DN of the LDAP entries, to show their DNs.
+    pub base_dn: String,
 }
 
 impl<Handler: BackendHandler> juniper::Context for Context<Handler> {}
@@ -31,12 +40,18 @@ fn schema<Handler: BackendHandler + Sync>() -> Schema<Handler> {
     )
 }
 
-pub fn export_schema(opts: ExportGraphQLSchemaOpts) -> anyhow::Result<()> {
+pub fn export_schema(opts: ExportGraphQLSchemaOpts) -> anyhow::Result<CommandOutput> {
     use crate::domain::sql_backend_handler::SqlBackendHandler;
     use anyhow::Context;
     let output = schema::<SqlBackendHandler>().as_schema_language();
+    let mut result = CommandOutput::default();
     match opts.output_file {
-        None => println!("{}", output),
+        None => {
+            result
+                .data
+                .insert("schema".to_string(), output.clone().into());
+            result.text = Some(output);
+        }
         Some(path) => {
             use std::fs::File;
             use std::io::prelude::*;
@@ -46,36 +61,75 @@ pub fn export_schema(opts: ExportGraphQLSchemaOpts) -> anyhow::Result<()> {
                 File::create(&path).context(format!("unable to open '{}'", path.display()))?;
             file.write_all(output.as_bytes())
                 .context(format!("unable to write in '{}'", path.display()))?;
+            result
+                .data
+                .insert("output_file".to_string(), path.display().to_string().into());
         }
     }
-    Ok(())
+    Ok(result)
 }
 
-async fn graphiql_route() -> Result<HttpResponse, Error> {
-    graphiql_handler("/api/graphql", None).await
+/// Only the admins get the explorers. Opened from the web app, they are authenticated by the
+/// session cookie, like the queries they send.
+async fn check_is_admin<Handler: BackendHandler + TcpBackendHandler + Sync + 'static>(
+    data: &AppState<Handler>,
+    bearer: &BearerAuth,
+) -> Result<(), HttpResponse> {
+    match authenticate(data, bearer).await? {
+        v if v.is_admin() => Ok(()),
+        _ => Err(HttpResponse::Forbidden().body("Only the admins can explore the GraphQL API")),
+    }
 }
-async fn playground_route() -> Result<HttpResponse, Error> {
-    playground_handler("/api/graphql", None).await
+
+async fn graphiql_route<Handler: BackendHandler + TcpBackendHandler + Sync + 'static>(
+    data: web::Data<AppState<Handler>>,
+    bearer: BearerAuth,
+) -> Result<HttpResponse, Error> {
+    match check_is_admin(&data, &bearer).await {
+        Ok(()) => graphiql_handler("/api/graphql", None).await,
+        Err(response) => Ok(response),
+    }
+}
+
+async fn playground_route<Handler: BackendHandler + TcpBackendHandler + Sync + 'static>(
+    data: web::Data<AppState<Handler>>,
+    bearer: BearerAuth,
+) -> Result<HttpResponse, Error> {
+    match check_is_admin(&data, &bearer).await {
+        Ok(()) => playground_handler("/api/graphql", None).await,
+        Err(response) => Ok(response),
+    }
 }
 
-async fn graphql_route<Handler: BackendHandler + Sync>(
+async fn graphql_route<Handler: BackendHandler + TcpBackendHandler + Sync>(
     req: actix_web::HttpRequest,
     mut payload: actix_web::web::Payload,
     data: web::Data<AppState<Handler>>,
 ) -> Result<HttpResponse, Error> {
     use actix_web::FromRequest;
     let bearer = BearerAuth::from_request(&req, &mut payload.0).await?;
-    let validation_result = check_if_token_is_valid(&data, bearer.token())?;
+    let validation_result = if bearer.token().starts_with(API_TOKEN_PREFIX) {
+        check_if_api_token_is_valid(&data, bearer.token()).await?
+    } else {
+        check_if_token_is_valid(&data, bearer.token()).await?
+    };
+    data.user_rate_limiter.check(&validation_result.user)?;
     let context = Context::<Handler> {
         handler: Box::new(data.backend_handler.clone()),
         validation_result,
+        source: req
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string()),
+        scheduler: Some(data.scheduler.clone()),
+        base_dn: data.ldap_base_dn.clone(),
     };
     graphql_handler(&schema(), &context, req, payload).await
 }
 
 pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
 where
-    Backend: BackendHandler + Sync + 'static,
+    Backend: BackendHandler + TcpBackendHandler + Sync + 'static,
 {
     let json_config = web::JsonConfig::default()
         .limit(4096)
@@ -95,6 +149,194 @@ where
             .route(web::post().to(graphql_route::<Backend>))
             .route(web::get().to(graphql_route::<Backend>)),
     );
-    cfg.service(web::resource("/graphql/playground").route(web::get().to(playground_route)));
-    cfg.service(web::resource("/graphql/graphiql").route(web::get().to(graphiql_route)));
+    cfg.service(
+        web::resource("/graphql/playground").route(web::get().to(playground_route::<Backend>)),
+    );
+    cfg.service(web::resource("/graphql/graphiql").route(web::get().to(graphiql_route::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::SqlBackendHandler;
+    use graphql_parser::schema::{
+        parse_schema, Definition, Document, Field, InputValue, Type, TypeDefinition,
+    };
+    use std::collections::HashMap;
+
+    fn get_types<'a>(
+        document: &'a Document<'a, String>,
+    ) -> HashMap<&'a str, &'a TypeDefinition<'a, String>> {
+        document
+            .definitions
+            .iter()
+            .filter_map(|d| match d {
+                Definition::TypeDefinition(t) => Some(t),
+                _ => None,
+            })
+            .map(|t| {
+                let name = match t {
+                    TypeDefinition::Scalar(t) => &t.name,
+                    TypeDefinition::Object(t) => &t.name,
+                    TypeDefinition::Interface(t) => &t.name,
+                    TypeDefinition::Union(t) => &t.name,
+                    TypeDefinition::Enum(t) => &t.name,
+                    TypeDefinition::InputObject(t) => &t.name,
+                };
+                (name.as_str(), t)
+            })
+            .collect()
+    }
+
+    fn is_deprecated(field: &Field<String>) -> bool {
+        field.directives.iter().any(|d| d.name == "deprecated")
+    }
+
+    /// Checks that the values accepted by `old` are still accepted by `new`: existing values keep
+    /// their type, and new values are optional.
+    fn check_input_values(
+        context: &str,
+        old: &[InputValue<String>],
+        new: &[InputValue<String>],
+        errors: &mut Vec<String>,
+    ) {
+        for old_value in old {
+            match new.iter().find(|v| v.name == old_value.name) {
+                None => errors.push(format!("{}: '{}' was removed", context, old_value.name)),
+                Some(v) if v.value_type != old_value.value_type => errors.push(format!(
+                    "{}: '{}' changed type from {} to {}",
+                    context, old_value.name, old_value.value_type, v.value_type
+                )),
+                _ => (),
+            }
+        }
+        for new_value in new {
+            if matches!(new_value.value_type, Type::NonNullType(_))
+                && new_value.default_value.is_none()
+                && !old.iter().any(|v| v.name == new_value.name)
+            {
+                errors.push(format!(
+                    "{}: new '{}' is mandatory",
+                    context, new_value.name
+                ));
+            }
+        }
+    }
+
+    fn check_fields(
+        type_name: &str,
+        old: &[Field<String>],
+        new: &[Field<String>],
+        errors: &mut Vec<String>,
+    ) {
+        for old_field in old {
+            match new.iter().find(|f| f.name == old_field.name) {
+                // Fields can only be removed once they have been deprecated for a version.
+                None if is_deprecated(old_field) => (),
+                None => errors.push(format!(
+                    "{}.{} was removed without being deprecated first",
+                    type_name, old_field.name
+                )),
+                Some(f) => {
+                    if f.field_type != old_field.field_type {
+                        errors.push(format!(
+                            "{}.{} changed type from {} to {}",
+                            type_name, old_field.name, old_field.field_type, f.field_type
+                        ));
+                    }
+                    check_input_values(
+                        &format!("{}.{}", type_name, old_field.name),
+                        &old_field.arguments,
+                        &f.arguments,
+                        errors,
+                    );
+                }
+            }
+        }
+    }
+
+    fn get_incompatibilities(old: &str, new: &str) -> Vec<String> {
+        let old = parse_schema::<String>(old).unwrap();
+        let new = parse_schema::<String>(new).unwrap();
+        let new_types = get_types(&new);
+        let mut errors = Vec::new();
+        for (name, old_type) in get_types(&old) {
+            let new_type = match new_types.get(name) {
+                None => {
+                    errors.push(format!("Type {} was removed", name));
+                    continue;
+                }
+                Some(t) => t,
+            };
+            match (old_type, new_type) {
+                (TypeDefinition::Object(o), TypeDefinition::Object(n)) => {
+                    check_fields(name, &o.fields, &n.fields, &mut errors)
+                }
+                (TypeDefinition::Interface(o), TypeDefinition::Interface(n)) => {
+                    check_fields(name, &o.fields, &n.fields, &mut errors)
+                }
+                (TypeDefinition::InputObject(o), TypeDefinition::InputObject(n)) => {
+                    check_input_values(name, &o.fields, &n.fields, &mut errors)
+                }
+                (TypeDefinition::Enum(o), TypeDefinition::Enum(n)) => {
+                    for value in &o.values {
+                        if !n.values.iter().any(|v| v.name == value.name) {
+                            errors.push(format!("{}.{} was removed", name, value.name));
+                        }
+                    }
+                }
+                (TypeDefinition::Scalar(_), TypeDefinition::Scalar(_))
+                | (TypeDefinition::Union(_), TypeDefinition::Union(_)) => (),
+                _ => errors.push(format!("Type {} changed kind", name)),
+            }
+        }
+        errors.sort();
+        errors
+    }
+
+    #[test]
+    fn schema_is_compatible_with_previous_version() {
+        let errors = get_incompatibilities(
+            include_str!("previous_schema.graphql"),
+            &schema::<SqlBackendHandler>().as_schema_language(),
+        );
+        assert!(
+            errors.is_empty(),
+            "The GraphQL schema is not backwards compatible with the previous version:\n{}",
+            errors.join("\n")
+        );
+    }
+
+    #[test]
+    fn detects_incompatibilities() {
+        const OLD: &str = r#"
+            type Query {
+              user(id: String!): User!
+              old: String! @deprecated(reason: "use user")
+            }
+            type User {
+              id: String!
+              email: String
+            }
+        "#;
+        assert!(get_incompatibilities(OLD, OLD).is_empty());
+        assert_eq!(
+            get_incompatibilities(
+                OLD,
+                r#"
+            type Query {
+              user(id: String!, filter: String!): User!
+            }
+            type User {
+              id: Int!
+            }
+        "#
+            ),
+            vec![
+                "Query.user: new 'filter' is mandatory".to_string(),
+                "User.email was removed without being deprecated first".to_string(),
+                "User.id changed type from String! to Int!".to_string(),
+            ]
+        );
+    }
 }
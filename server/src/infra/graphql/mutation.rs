@@ -1,7 +1,14 @@
-use crate::domain::handler::{
-    BackendHandler, CreateUserRequest, GroupId, UpdateGroupRequest, UpdateUserRequest,
+use crate::domain::{
+    error::DomainError,
+    handler::{
+        AttributeSchema, BackendHandler, CreateApiTokenRequest, CreateAuditLogEntryRequest,
+        CreateUserRequest, GroupId, OidcClient, Role, UpdateGroupRequest, UpdateUserRequest,
+    },
+    totp,
 };
+use crate::infra::db_cleaner::{SetJobPaused, TriggerJob};
 use juniper::{graphql_object, FieldResult, GraphQLInputObject, GraphQLObject};
+use std::future::Future;
 
 use super::api::Context;
 
@@ -24,9 +31,14 @@ impl<Handler: BackendHandler> Mutation<Handler> {
 pub struct CreateUserInput {
     id: String,
     email: String,
+    /// Defaults to the first and last names.
     display_name: Option<String>,
     first_name: Option<String>,
     last_name: Option<String>,
+    /// Base64-encoded JPEG picture, scaled down to 512x512.
+    avatar: Option<String>,
+    /// The groups the user is added to once created.
+    group_ids: Option<Vec<i32>>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -34,9 +46,12 @@ pub struct CreateUserInput {
 pub struct UpdateUserInput {
     id: String,
     email: Option<String>,
+    /// An empty string resets it to the first and last names.
     display_name: Option<String>,
     first_name: Option<String>,
     last_name: Option<String>,
+    /// Base64-encoded JPEG picture, scaled down to 512x512. An empty string removes the avatar.
+    avatar: Option<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -44,6 +59,10 @@ pub struct UpdateUserInput {
 pub struct UpdateGroupInput {
     id: i32,
     display_name: Option<String>,
+    /// An empty string removes the description.
+    description: Option<String>,
+    /// An empty string removes the email.
+    email: Option<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLObject)]
@@ -51,21 +70,259 @@ pub struct Success {
     ok: bool,
 }
 
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A newly created API token, along with its secret value.
+pub struct CreatedApiToken {
+    api_token: super::query::ApiToken,
+    /// The secret token. It cannot be retrieved again later.
+    token: String,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A newly registered OpenID Connect client, along with its secret.
+pub struct CreatedOidcClient {
+    client: super::query::OidcClient,
+    /// The client secret. It cannot be retrieved again later.
+    client_secret: String,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A new TOTP secret, to add to an authenticator app before confirming it with a code.
+pub struct TotpEnrollment {
+    /// Base32-encoded, to type in the app if the QR code can't be scanned.
+    secret: String,
+    /// The `otpauth://` URI to show as a QR code.
+    uri: String,
+}
+
 impl Success {
     fn new() -> Self {
         Self { ok: true }
     }
 }
 
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The outcome of a bulk action for one of the users.
+pub struct BulkResult {
+    user_id: String,
+    /// Why the action failed for this user, if it did.
+    error: Option<String>,
+}
+
+/// The most users a bulk action can apply to at once.
+const MAX_BULK_USERS: usize = 1000;
+
+/// Applies the action to each of the users in turn: a failure for one user doesn't stop the
+/// others, and is reported in its result.
+async fn for_each_user<F, Fut>(user_ids: Vec<String>, action: F) -> FieldResult<Vec<BulkResult>>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = FieldResult<Success>>,
+{
+    if user_ids.len() > MAX_BULK_USERS {
+        return Err(format!("At most {} users can be changed at once", MAX_BULK_USERS).into());
+    }
+    let mut results = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        let error = action(user_id.clone())
+            .await
+            .err()
+            .map(|e| e.message().to_string());
+        results.push(BulkResult { user_id, error });
+    }
+    Ok(results)
+}
+
+/// Records a successful change in the audit log. The change is already done, so failing to record
+/// it is only logged.
+async fn audit<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    action: &str,
+    target: String,
+    details: Option<String>,
+) {
+    if let Err(e) = context
+        .handler
+        .add_audit_log_entry(CreateAuditLogEntryRequest {
+            actor: context.validation_result.user.clone(),
+            source: context.source.clone(),
+            action: action.to_string(),
+            target: target.clone(),
+            details,
+        })
+        .await
+    {
+        log::error!(
+            "Could not add `{}` on {} to the audit log: {}",
+            action,
+            target,
+            e
+        );
+    }
+}
+
+async fn is_user_admin<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    user_id: &str,
+) -> FieldResult<bool> {
+    Ok(context
+        .handler
+        .get_user_groups(user_id)
+        .await?
+        .iter()
+        .any(|g| g.1 == Role::Admin.group_name()))
+}
+
+/// Admins can manage all the groups, user managers only the groups that don't grant a role, and
+/// group managers only their groups. The members of a group get the roles of the groups it is
+/// nested in: these groups count as granting a role too.
+async fn can_manage_group_members<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    group_id: i32,
+) -> FieldResult<bool> {
+    if context.validation_result.is_admin() {
+        return Ok(true);
+    }
+    if !context.validation_result.can_manage_users()
+        && !super::query::is_group_manager(context, group_id).await?
+    {
+        return Ok(false);
+    }
+    Ok(!context
+        .handler
+        .get_group_ancestors(GroupId(group_id))
+        .await?
+        .iter()
+        .any(|g| Role::from_group_name(&g.1).is_some()))
+}
+
+/// Admins can disable any other user, user managers only the non-admin ones.
+async fn set_user_enabled<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    user_id: String,
+    enabled: bool,
+) -> FieldResult<Success> {
+    if !context.validation_result.can_manage_users()
+        || (!context.validation_result.is_admin() && is_user_admin(context, &user_id).await?)
+    {
+        return Err("Unauthorized user activation modification".into());
+    }
+    if context.validation_result.user == user_id {
+        return Err("Cannot disable current user".into());
+    }
+    let target = format!("user:{}", user_id);
+    context
+        .handler
+        .update_user(UpdateUserRequest {
+            user_id,
+            enabled: Some(enabled),
+            ..Default::default()
+        })
+        .await?;
+    let action = if enabled { "enableUser" } else { "disableUser" };
+    audit(context, action, target, None).await;
+    Ok(Success::new())
+}
+
+async fn add_membership<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    user_id: String,
+    group_id: i32,
+) -> FieldResult<Success> {
+    if !can_manage_group_members(context, group_id).await? {
+        return Err("Unauthorized group membership modification".into());
+    }
+    context
+        .handler
+        .add_user_to_group(&user_id, GroupId(group_id))
+        .await?;
+    audit(
+        context,
+        "addUserToGroup",
+        format!("user:{}", user_id),
+        Some(format!("group:{}", group_id)),
+    )
+    .await;
+    Ok(Success::new())
+}
+
+async fn remove_membership<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    user_id: String,
+    group_id: i32,
+) -> FieldResult<Success> {
+    if !can_manage_group_members(context, group_id).await? {
+        return Err("Unauthorized group membership modification".into());
+    }
+    if context.validation_result.user == user_id && group_id == 1 {
+        return Err("Cannot remove admin rights for current user".into());
+    }
+    context
+        .handler
+        .remove_user_from_group(&user_id, GroupId(group_id))
+        .await?;
+    audit(
+        context,
+        "removeUserFromGroup",
+        format!("user:{}", user_id),
+        Some(format!("group:{}", group_id)),
+    )
+    .await;
+    Ok(Success::new())
+}
+
+async fn remove_user<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    user_id: String,
+) -> FieldResult<Success> {
+    if !context.validation_result.is_admin() {
+        return Err("Unauthorized user deletion".into());
+    }
+    if context.validation_result.user == user_id {
+        return Err("Cannot delete current user".into());
+    }
+    context.handler.delete_user(&user_id).await?;
+    audit(context, "deleteUser", format!("user:{}", user_id), None).await;
+    Ok(Success::new())
+}
+
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler + Sync> Mutation<Handler> {
     async fn create_user(
         context: &Context<Handler>,
         user: CreateUserInput,
     ) -> FieldResult<super::query::User<Handler>> {
-        if !context.validation_result.is_admin {
+        if !context.validation_result.can_manage_users() {
             return Err("Unauthorized user creation".into());
         }
+        // Checked first, not to create the user only halfway.
+        let avatar = user
+            .avatar
+            .filter(|avatar| !avatar.is_empty())
+            .map(base64::decode)
+            .transpose()
+            .map_err(|e| format!("Invalid base64 avatar: {}", e))?;
+        let mut group_ids = user.group_ids.unwrap_or_default();
+        group_ids.sort_unstable();
+        group_ids.dedup();
+        for group_id in &group_ids {
+            // Fails if the group doesn't exist.
+            if let Err(e) = context
+                .handler
+                .get_group_ancestors(GroupId(*group_id))
+                .await
+            {
+                return Err(match e {
+                    DomainError::DatabaseError(sqlx::Error::RowNotFound) => {
+                        format!("Group {} not found", group_id).into()
+                    }
+                    e => e.into(),
+                });
+            }
+            if !can_manage_group_members(context, *group_id).await? {
+                return Err(format!("Unauthorized addition to the group {}", group_id).into());
+            }
+        }
         context
             .handler
             .create_user(CreateUserRequest {
@@ -76,6 +333,20 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
                 last_name: user.last_name,
             })
             .await?;
+        audit(context, "createUser", format!("user:{}", user.id), None).await;
+        if avatar.is_some() {
+            context
+                .handler
+                .update_user(UpdateUserRequest {
+                    user_id: user.id.clone(),
+                    avatar,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        for group_id in group_ids {
+            add_membership(context, user.id.clone(), group_id).await?;
+        }
         Ok(context
             .handler
             .get_user_details(&user.id)
@@ -87,10 +358,17 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
         context: &Context<Handler>,
         name: String,
     ) -> FieldResult<super::query::Group<Handler>> {
-        if !context.validation_result.is_admin {
+        if !context.validation_result.is_admin() {
             return Err("Unauthorized group creation".into());
         }
         let group_id = context.handler.create_group(&name).await?;
+        audit(
+            context,
+            "createGroup",
+            format!("group:{}", group_id.0),
+            Some(name),
+        )
+        .await;
         Ok(context
             .handler
             .get_group_details(group_id)
@@ -102,9 +380,30 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
         context: &Context<Handler>,
         user: UpdateUserInput,
     ) -> FieldResult<Success> {
-        if !context.validation_result.can_access(&user.id) {
+        if !context
+            .validation_result
+            .can_write(&user.id, is_user_admin(context, &user.id).await?)
+        {
             return Err("Unauthorized user update".into());
         }
+        let avatar = user
+            .avatar
+            .map(base64::decode)
+            .transpose()
+            .map_err(|e| format!("Invalid base64 avatar: {}", e))?;
+        let updated_fields = [
+            ("email", user.email.is_some()),
+            ("displayName", user.display_name.is_some()),
+            ("firstName", user.first_name.is_some()),
+            ("lastName", user.last_name.is_some()),
+            ("avatar", avatar.is_some()),
+        ]
+        .iter()
+        .filter(|(_, updated)| *updated)
+        .map(|(field, _)| *field)
+        .collect::<Vec<_>>()
+        .join(", ");
+        let target = format!("user:{}", user.id);
         context
             .handler
             .update_user(UpdateUserRequest {
@@ -113,8 +412,100 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
                 display_name: user.display_name,
                 first_name: user.first_name,
                 last_name: user.last_name,
+                avatar,
+                enabled: None,
+                valid_until: None,
             })
             .await?;
+        audit(context, "updateUser", target, Some(updated_fields)).await;
+        Ok(Success::new())
+    }
+
+    /// Changes the ID of a user, keeping their details and groups. They have to log in again.
+    async fn rename_user(
+        context: &Context<Handler>,
+        user_id: String,
+        new_user_id: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized user rename".into());
+        }
+        if context.validation_result.user == user_id {
+            return Err("Cannot rename current user".into());
+        }
+        context.handler.rename_user(&user_id, &new_user_id).await?;
+        audit(
+            context,
+            "renameUser",
+            format!("user:{}", user_id),
+            Some(new_user_id),
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Prevents the user from logging in, without deleting their data.
+    async fn disable_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        set_user_enabled(context, user_id, false).await
+    }
+
+    async fn enable_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        set_user_enabled(context, user_id, true).await
+    }
+
+    /// Disables the users, with the outcome for each user.
+    async fn disable_users(
+        context: &Context<Handler>,
+        user_ids: Vec<String>,
+    ) -> FieldResult<Vec<BulkResult>> {
+        for_each_user(user_ids, |user_id| {
+            set_user_enabled(context, user_id, false)
+        })
+        .await
+    }
+
+    /// Lets a user locked after too many failed logins log in again.
+    async fn unlock_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        if !context.validation_result.can_manage_users()
+            || (!context.validation_result.is_admin() && is_user_admin(context, &user_id).await?)
+        {
+            return Err("Unauthorized user unlock".into());
+        }
+        context.handler.unlock_user(&user_id).await?;
+        audit(context, "unlockUser", format!("user:{}", user_id), None).await;
+        Ok(Success::new())
+    }
+
+    /// Sets the date after which the user can't log in, or removes it if null.
+    async fn set_user_valid_until(
+        context: &Context<Handler>,
+        user_id: String,
+        valid_until: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.can_manage_users()
+            || (!context.validation_result.is_admin() && is_user_admin(context, &user_id).await?)
+        {
+            return Err("Unauthorized user expiry modification".into());
+        }
+        if context.validation_result.user == user_id {
+            return Err("Cannot set the expiry of current user".into());
+        }
+        let target = format!("user:{}", user_id);
+        context
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id,
+                valid_until: Some(valid_until),
+                ..Default::default()
+            })
+            .await?;
+        audit(
+            context,
+            "setUserValidUntil",
+            target,
+            valid_until.map(|date| date.to_rfc3339()),
+        )
+        .await;
         Ok(Success::new())
     }
 
@@ -122,19 +513,47 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
         context: &Context<Handler>,
         group: UpdateGroupInput,
     ) -> FieldResult<Success> {
-        if !context.validation_result.is_admin {
+        if !context.validation_result.is_admin() {
             return Err("Unauthorized group update".into());
         }
-        if group.id == 1 {
+        if group.id == 1 && group.display_name.is_some() {
             return Err("Cannot change admin group details".into());
         }
+        let updated_fields = [
+            group
+                .display_name
+                .as_ref()
+                .map(|name| format!("displayName: {}", name)),
+            group
+                .description
+                .as_ref()
+                .map(|_| "description".to_string()),
+            group
+                .email
+                .as_ref()
+                .map(|email| format!("email: {}", email)),
+        ]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
         context
             .handler
             .update_group(UpdateGroupRequest {
                 group_id: GroupId(group.id),
                 display_name: group.display_name,
+                description: group.description,
+                email: group.email,
             })
             .await?;
+        audit(
+            context,
+            "updateGroup",
+            format!("group:{}", group.id),
+            Some(updated_fields).filter(|fields| !fields.is_empty()),
+        )
+        .await;
         Ok(Success::new())
     }
 
@@ -143,53 +562,591 @@ impl<Handler: BackendHandler + Sync> Mutation<Handler> {
         user_id: String,
         group_id: i32,
     ) -> FieldResult<Success> {
-        if !context.validation_result.is_admin {
-            return Err("Unauthorized group membership modification".into());
+        add_membership(context, user_id, group_id).await
+    }
+
+    async fn remove_user_from_group(
+        context: &Context<Handler>,
+        user_id: String,
+        group_id: i32,
+    ) -> FieldResult<Success> {
+        remove_membership(context, user_id, group_id).await
+    }
+
+    /// Adds the users to the group, with the outcome for each user.
+    async fn add_users_to_group(
+        context: &Context<Handler>,
+        user_ids: Vec<String>,
+        group_id: i32,
+    ) -> FieldResult<Vec<BulkResult>> {
+        for_each_user(user_ids, |user_id| {
+            add_membership(context, user_id, group_id)
+        })
+        .await
+    }
+
+    /// Removes the users from the group, with the outcome for each user.
+    async fn remove_users_from_group(
+        context: &Context<Handler>,
+        user_ids: Vec<String>,
+        group_id: i32,
+    ) -> FieldResult<Vec<BulkResult>> {
+        for_each_user(user_ids, |user_id| {
+            remove_membership(context, user_id, group_id)
+        })
+        .await
+    }
+
+    /// Lets the user add and remove the members of the group, which must not grant a role.
+    async fn add_group_manager(
+        context: &Context<Handler>,
+        user_id: String,
+        group_id: i32,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.can_manage_users() {
+            return Err("Unauthorized group manager modification".into());
         }
         context
             .handler
-            .add_user_to_group(&user_id, GroupId(group_id))
+            .add_group_manager(&user_id, GroupId(group_id))
             .await?;
+        audit(
+            context,
+            "addGroupManager",
+            format!("user:{}", user_id),
+            Some(format!("group:{}", group_id)),
+        )
+        .await;
         Ok(Success::new())
     }
 
-    async fn remove_user_from_group(
+    async fn remove_group_manager(
         context: &Context<Handler>,
         user_id: String,
         group_id: i32,
     ) -> FieldResult<Success> {
-        if !context.validation_result.is_admin {
-            return Err("Unauthorized group membership modification".into());
+        if !context.validation_result.can_manage_users() {
+            return Err("Unauthorized group manager modification".into());
+        }
+        context
+            .handler
+            .remove_group_manager(&user_id, GroupId(group_id))
+            .await?;
+        audit(
+            context,
+            "removeGroupManager",
+            format!("user:{}", user_id),
+            Some(format!("group:{}", group_id)),
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    async fn add_group_to_group(
+        context: &Context<Handler>,
+        child_group_id: i32,
+        parent_group_id: i32,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized group nesting modification".into());
         }
-        if context.validation_result.user == user_id && group_id == 1 {
-            return Err("Cannot remove admin rights for current user".into());
+        context
+            .handler
+            .add_group_to_group(GroupId(child_group_id), GroupId(parent_group_id))
+            .await?;
+        audit(
+            context,
+            "addGroupToGroup",
+            format!("group:{}", child_group_id),
+            Some(format!("group:{}", parent_group_id)),
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    async fn remove_group_from_group(
+        context: &Context<Handler>,
+        child_group_id: i32,
+        parent_group_id: i32,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized group nesting modification".into());
         }
         context
             .handler
-            .remove_user_from_group(&user_id, GroupId(group_id))
+            .remove_group_from_group(GroupId(child_group_id), GroupId(parent_group_id))
             .await?;
+        audit(
+            context,
+            "removeGroupFromGroup",
+            format!("group:{}", child_group_id),
+            Some(format!("group:{}", parent_group_id)),
+        )
+        .await;
         Ok(Success::new())
     }
 
     async fn delete_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
-        if !context.validation_result.is_admin {
-            return Err("Unauthorized user deletion".into());
+        remove_user(context, user_id).await
+    }
+
+    /// Deletes the users, with the outcome for each user.
+    async fn delete_users(
+        context: &Context<Handler>,
+        user_ids: Vec<String>,
+    ) -> FieldResult<Vec<BulkResult>> {
+        for_each_user(user_ids, |user_id| remove_user(context, user_id)).await
+    }
+
+    /// Restores a deleted user, with their password and the groups that still exist.
+    async fn restore_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized user restoration".into());
         }
-        if context.validation_result.user == user_id {
-            return Err("Cannot delete current user".into());
+        context.handler.restore_user(&user_id).await?;
+        audit(context, "restoreUser", format!("user:{}", user_id), None).await;
+        Ok(Success::new())
+    }
+
+    /// Permanently deletes a deleted user, before the end of the retention period.
+    async fn purge_deleted_user(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized deleted user purge".into());
         }
-        context.handler.delete_user(&user_id).await?;
+        context.handler.purge_deleted_user(&user_id).await?;
+        audit(
+            context,
+            "purgeDeletedUser",
+            format!("user:{}", user_id),
+            None,
+        )
+        .await;
         Ok(Success::new())
     }
 
     async fn delete_group(context: &Context<Handler>, group_id: i32) -> FieldResult<Success> {
-        if !context.validation_result.is_admin {
+        if !context.validation_result.is_admin() {
             return Err("Unauthorized group deletion".into());
         }
         if group_id == 1 {
             return Err("Cannot delete admin group".into());
         }
         context.handler.delete_group(GroupId(group_id)).await?;
+        audit(context, "deleteGroup", format!("group:{}", group_id), None).await;
+        Ok(Success::new())
+    }
+
+    /// Starts the enrollment of the current user in the two-factor authentication, with a new
+    /// secret.
+    async fn start_totp_enrollment(context: &Context<Handler>) -> FieldResult<TotpEnrollment> {
+        let user_id = &context.validation_result.user;
+        let secret = context.handler.start_totp_enrollment(user_id).await?;
+        Ok(TotpEnrollment {
+            uri: totp::provisioning_uri(totp::TOTP_ISSUER, user_id, &secret),
+            secret,
+        })
+    }
+
+    /// Enables the two-factor authentication of the current user, if the code matches the new
+    /// secret. Returns the recovery codes, which cannot be retrieved again later.
+    async fn confirm_totp_enrollment(
+        context: &Context<Handler>,
+        code: String,
+    ) -> FieldResult<Vec<String>> {
+        let user_id = context.validation_result.user.clone();
+        let recovery_codes = context
+            .handler
+            .confirm_totp_enrollment(&user_id, &code)
+            .await?;
+        audit(context, "enableTotp", format!("user:{}", user_id), None).await;
+        Ok(recovery_codes)
+    }
+
+    /// Disables the two-factor authentication of the user, e.g. after they lost their device.
+    async fn disable_totp(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
+        if context.validation_result.user != user_id && !context.validation_result.is_admin() {
+            return Err("Unauthorized two-factor authentication removal".into());
+        }
+        context.handler.disable_totp(&user_id).await?;
+        audit(context, "disableTotp", format!("user:{}", user_id), None).await;
+        Ok(Success::new())
+    }
+
+    /// Removes a security key of the user.
+    async fn delete_webauthn_credential(
+        context: &Context<Handler>,
+        user_id: String,
+        credential_id: String,
+    ) -> FieldResult<Success> {
+        if context.validation_result.user != user_id && !context.validation_result.is_admin() {
+            return Err("Unauthorized security key removal".into());
+        }
+        context
+            .handler
+            .delete_webauthn_credential(&user_id, &credential_id)
+            .await?;
+        audit(
+            context,
+            "deleteWebauthnCredential",
+            format!("user:{}", user_id),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    async fn create_api_token(
+        context: &Context<Handler>,
+        user_id: String,
+        name: String,
+        expiry_date: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> FieldResult<CreatedApiToken> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized API token creation".into());
+        }
+        let (api_token, token) = context
+            .handler
+            .create_api_token(CreateApiTokenRequest {
+                user_id,
+                name,
+                expiry_date,
+            })
+            .await?;
+        audit(
+            context,
+            "createApiToken",
+            format!("user:{}", api_token.user_id),
+            Some(format!("apiToken:{}", api_token.token_id)),
+        )
+        .await;
+        Ok(CreatedApiToken {
+            api_token: api_token.into(),
+            token,
+        })
+    }
+
+    async fn delete_api_token(context: &Context<Handler>, token_id: i32) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized API token deletion".into());
+        }
+        context.handler.delete_api_token(token_id).await?;
+        audit(
+            context,
+            "deleteApiToken",
+            format!("apiToken:{}", token_id),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Registers a client of the OpenID Connect provider, allowed to send the users back to one of
+    /// the redirect URIs after they log in.
+    async fn create_oidc_client(
+        context: &Context<Handler>,
+        client_id: String,
+        display_name: String,
+        redirect_uris: Vec<String>,
+    ) -> FieldResult<CreatedOidcClient> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized OpenID Connect client creation".into());
+        }
+        let client = OidcClient {
+            client_id,
+            display_name,
+            redirect_uris,
+        };
+        let client_secret = context.handler.create_oidc_client(client.clone()).await?;
+        audit(
+            context,
+            "createOidcClient",
+            format!("oidcClient:{}", client.client_id),
+            Some(client.redirect_uris.join(" ")),
+        )
+        .await;
+        Ok(CreatedOidcClient {
+            client: client.into(),
+            client_secret,
+        })
+    }
+
+    async fn delete_oidc_client(
+        context: &Context<Handler>,
+        client_id: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized OpenID Connect client deletion".into());
+        }
+        context.handler.delete_oidc_client(&client_id).await?;
+        audit(
+            context,
+            "deleteOidcClient",
+            format!("oidcClient:{}", client_id),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Defines a custom attribute of the users. The name can't be one of the built-in attributes.
+    async fn add_user_attribute(
+        context: &Context<Handler>,
+        name: String,
+        attribute_type: super::query::AttributeType,
+        is_list: bool,
+        is_editable: bool,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized attribute creation".into());
+        }
+        context
+            .handler
+            .add_user_attribute(AttributeSchema {
+                name: name.clone(),
+                attribute_type: attribute_type.into(),
+                is_list,
+                is_editable,
+            })
+            .await?;
+        audit(
+            context,
+            "addUserAttribute",
+            format!("attribute:{}", name),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Removes a custom attribute, with its values for all the users.
+    async fn delete_user_attribute(
+        context: &Context<Handler>,
+        name: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized attribute deletion".into());
+        }
+        context.handler.delete_user_attribute(&name).await?;
+        audit(
+            context,
+            "deleteUserAttribute",
+            format!("attribute:{}", name),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Replaces the values of a custom attribute for the user. No values removes it. The users
+    /// can only change their own editable attributes.
+    async fn set_user_attribute(
+        context: &Context<Handler>,
+        user_id: String,
+        name: String,
+        values: Vec<String>,
+    ) -> FieldResult<Success> {
+        let schema = context
+            .handler
+            .list_user_attribute_schema()
+            .await?
+            .into_iter()
+            .find(|schema| schema.name == name)
+            .ok_or_else(|| format!("Unknown attribute `{}`", name))?;
+        let validation_result = &context.validation_result;
+        let is_self = validation_result.user == user_id;
+        let can_manage = validation_result.is_admin()
+            || (!is_self
+                && validation_result.can_write(&user_id, is_user_admin(context, &user_id).await?));
+        if !can_manage && !(is_self && schema.is_editable) {
+            return Err("Unauthorized attribute update".into());
+        }
+        context
+            .handler
+            .set_user_attribute(&user_id, &name, values)
+            .await?;
+        audit(
+            context,
+            "setUserAttribute",
+            format!("user:{}", user_id),
+            Some(name),
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Sets an imported bcrypt, sha512-crypt or Argon2 hash as password, migrated on the first login.
+    async fn import_password_hash(
+        context: &Context<Handler>,
+        user_id: String,
+        password_hash: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized password hash import".into());
+        }
+        context
+            .handler
+            .import_password_hash(&user_id, &password_hash)
+            .await?;
+        audit(
+            context,
+            "importPasswordHash",
+            format!("user:{}", user_id),
+            None,
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Replaces the per-subsystem log levels until the server restarts, e.g. "ldap=debug,sql=warn".
+    async fn set_log_levels(
+        context: &Context<Handler>,
+        log_levels: String,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized log level modification".into());
+        }
+        crate::infra::logging::set_log_levels(&log_levels)?;
+        audit(
+            context,
+            "setLogLevels",
+            "server".to_string(),
+            Some(log_levels),
+        )
+        .await;
+        Ok(Success::new())
+    }
+
+    /// Runs a background job now, even if it is paused.
+    async fn trigger_job(context: &Context<Handler>, name: String) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized job trigger".into());
+        }
+        let scheduler = context
+            .scheduler
+            .as_ref()
+            .ok_or("The background jobs are not running")?;
+        scheduler.send(TriggerJob(name.clone())).await??;
+        audit(context, "triggerJob", format!("job:{}", name), None).await;
+        Ok(Success::new())
+    }
+
+    /// Pauses or resumes the scheduled runs of a background job.
+    async fn set_job_paused(
+        context: &Context<Handler>,
+        name: String,
+        paused: bool,
+    ) -> FieldResult<Success> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized job modification".into());
+        }
+        let scheduler = context
+            .scheduler
+            .as_ref()
+            .ok_or("The background jobs are not running")?;
+        scheduler
+            .send(SetJobPaused {
+                name: name.clone(),
+                paused,
+            })
+            .await??;
+        audit(
+            context,
+            "setJobPaused",
+            format!("job:{}", name),
+            Some(paused.to_string()),
+        )
+        .await;
         Ok(Success::new())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::handler::{GroupIdAndName, MockTestBackendHandler},
+        infra::{auth_service::ValidationResults, graphql::query::Query},
+    };
+    use juniper::{execute, EmptySubscription, RootNode, Variables};
+    use mockall::predicate::eq;
+
+    #[tokio::test]
+    async fn group_manager_cannot_grant_a_role_through_a_subgroup() {
+        const MUTATION: &str = r#"mutation {
+          addUserToGroup(userId: "mallory", groupId: 5) {
+            ok
+          }
+        }"#;
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_group_managers()
+            .with(eq(GroupId(5)))
+            .return_once(|_| Ok(vec!["bob".to_string()]));
+        // The team is nested in the admin group.
+        mock.expect_get_group_ancestors()
+            .with(eq(GroupId(5)))
+            .return_once(|_| {
+                Ok(vec![
+                    GroupIdAndName(GroupId(1), "lldap_admin".to_string()),
+                    GroupIdAndName(GroupId(5), "Admin team".to_string()),
+                ])
+            });
+        mock.expect_add_user_to_group().never();
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::from_groups("bob".to_string(), vec![]),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+        let schema = RootNode::new(
+            Query::<MockTestBackendHandler>::new(),
+            Mutation::<MockTestBackendHandler>::new(),
+            EmptySubscription::<Context<MockTestBackendHandler>>::new(),
+        );
+        let (_, errors) = execute(MUTATION, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized group membership modification"
+        );
+    }
+
+    #[tokio::test]
+    async fn create_user_checks_the_groups_first() {
+        const MUTATION: &str = r#"mutation {
+          createUser(user: {id: "bob", email: "bob@example.com", groupIds: [3, 42]}) {
+            id
+          }
+        }"#;
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_group_ancestors()
+            .with(eq(GroupId(3)))
+            .return_once(|_| Ok(vec![GroupIdAndName(GroupId(3), "team".to_string())]));
+        mock.expect_get_group_ancestors()
+            .with(eq(GroupId(42)))
+            .return_once(|_| Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)));
+        mock.expect_create_user().never();
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+        let schema = RootNode::new(
+            Query::<MockTestBackendHandler>::new(),
+            Mutation::<MockTestBackendHandler>::new(),
+            EmptySubscription::<Context<MockTestBackendHandler>>::new(),
+        );
+        let (_, errors) = execute(MUTATION, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error().message(), "Group 42 not found");
+    }
+}
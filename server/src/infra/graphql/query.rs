@@ -1,13 +1,44 @@
-use crate::domain::handler::{BackendHandler, GroupId, GroupIdAndName};
-use juniper::{graphql_object, FieldResult, GraphQLInputObject};
+use crate::{
+    domain::{
+        error::DomainError,
+        handler::{BackendHandler, GroupDetails, GroupId, GroupIdAndName, UserPageRequest},
+    },
+    infra::{
+        db_cleaner::{JobStatus, ListJobs},
+        ldap_handler::{group_dn, user_dn},
+    },
+};
+use juniper::{
+    graphql_object, Executor, FieldError, FieldResult, GraphQLEnum, GraphQLInputObject,
+    GraphQLObject, LookAheadMethods,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 
 type DomainRequestFilter = crate::domain::handler::RequestFilter;
 type DomainUser = crate::domain::handler::User;
 type DomainGroup = crate::domain::handler::Group;
+type DomainApiToken = crate::domain::handler::ApiToken;
+type DomainOidcClient = crate::domain::handler::OidcClient;
+type DomainAuditLogEntry = crate::domain::handler::AuditLogEntry;
+type DomainLoginHistoryEntry = crate::domain::handler::LoginHistoryEntry;
+type DomainDeletedUser = crate::domain::handler::DeletedUser;
+type DomainWebauthnCredential = crate::domain::handler::WebauthnCredential;
+type DomainUserColumn = crate::domain::handler::UserColumn;
+type DomainAttributeType = crate::domain::handler::AttributeType;
+type DomainAttributeSchema = crate::domain::handler::AttributeSchema;
+type DomainAttributeValue = crate::domain::handler::AttributeValue;
 use super::api::Context;
 
+/// Reports the missing entities as "not found", for the web app to show its "not found" page.
+fn not_found_error(error: DomainError, message: impl FnOnce() -> String) -> FieldError {
+    match error {
+        DomainError::DatabaseError(sqlx::Error::RowNotFound) => message().into(),
+        e => e.into(),
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
 /// A filter for requests, specifying a boolean expression based on field constraints. Only one of
 /// the fields can be set at a time.
@@ -18,6 +49,10 @@ pub struct RequestFilter {
     eq: Option<EqualityConstraint>,
     member_of: Option<String>,
     member_of_id: Option<i32>,
+    /// The users whose account expires before this date.
+    expires_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// The users whose ID, email or display name contain this text, ignoring case.
+    search: Option<String>,
 }
 
 impl TryInto<DomainRequestFilter> for RequestFilter {
@@ -42,6 +77,12 @@ impl TryInto<DomainRequestFilter> for RequestFilter {
         if self.member_of_id.is_some() {
             field_count += 1;
         }
+        if self.expires_before.is_some() {
+            field_count += 1;
+        }
+        if self.search.is_some() {
+            field_count += 1;
+        }
         if field_count == 0 {
             return Err("No field specified in request filter".to_string());
         }
@@ -74,6 +115,12 @@ impl TryInto<DomainRequestFilter> for RequestFilter {
         if let Some(group_id) = self.member_of_id {
             return Ok(DomainRequestFilter::MemberOfId(GroupId(group_id)));
         }
+        if let Some(date) = self.expires_before {
+            return Ok(DomainRequestFilter::ValidUntilBefore(date));
+        }
+        if let Some(text) = self.search {
+            return Ok(DomainRequestFilter::Search(text));
+        }
         unreachable!();
     }
 }
@@ -84,6 +131,84 @@ pub struct EqualityConstraint {
     value: String,
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+/// The columns the users can be sorted by.
+pub enum UserColumn {
+    UserId,
+    Email,
+    DisplayName,
+    CreationDate,
+}
+
+impl From<UserColumn> for DomainUserColumn {
+    fn from(column: UserColumn) -> Self {
+        match column {
+            UserColumn::UserId => DomainUserColumn::UserId,
+            UserColumn::Email => DomainUserColumn::Email,
+            UserColumn::DisplayName => DomainUserColumn::DisplayName,
+            UserColumn::CreationDate => DomainUserColumn::CreationDate,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, GraphQLEnum)]
+/// The type of the values of a custom attribute.
+pub enum AttributeType {
+    String,
+    Integer,
+    /// RFC 3339, e.g. "2021-10-15T14:03:00Z".
+    DateTime,
+    /// Base64-encoded JPEG picture.
+    JpegPhoto,
+}
+
+impl From<AttributeType> for DomainAttributeType {
+    fn from(attribute_type: AttributeType) -> Self {
+        match attribute_type {
+            AttributeType::String => DomainAttributeType::String,
+            AttributeType::Integer => DomainAttributeType::Integer,
+            AttributeType::DateTime => DomainAttributeType::DateTime,
+            AttributeType::JpegPhoto => DomainAttributeType::JpegPhoto,
+        }
+    }
+}
+
+impl From<DomainAttributeType> for AttributeType {
+    fn from(attribute_type: DomainAttributeType) -> Self {
+        match attribute_type {
+            DomainAttributeType::String => AttributeType::String,
+            DomainAttributeType::Integer => AttributeType::Integer,
+            DomainAttributeType::DateTime => AttributeType::DateTime,
+            DomainAttributeType::JpegPhoto => AttributeType::JpegPhoto,
+        }
+    }
+}
+
+pub use lldap_auth::SCHEMA_VERSION;
+/// SHA-256 of `schema.graphql` at `SCHEMA_VERSION` (in `lldap_auth`, shared with the frontend): a
+/// test fails when the schema changes until both are updated.
+pub const SCHEMA_HASH: &str = "b017254a90c9078b7483dac7700cd34f251949d3b444af17cdf42c48dbff0cc5";
+
+/// Whether the current user is one of the managers of the group.
+pub(super) async fn is_group_manager<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    group_id: i32,
+) -> FieldResult<bool> {
+    Ok(context
+        .handler
+        .list_group_managers(GroupId(group_id))
+        .await?
+        .contains(&context.validation_result.user))
+}
+
+/// The group managers can see their groups, and all the users to pick the members.
+async fn can_read_group<Handler: BackendHandler + Sync>(
+    context: &Context<Handler>,
+    group_id: i32,
+) -> FieldResult<bool> {
+    Ok(context.validation_result.can_read_all() || is_group_manager(context, group_id).await?)
+}
+
 #[derive(PartialEq, Eq, Debug)]
 /// The top-level GraphQL query type.
 pub struct Query<Handler: BackendHandler> {
@@ -100,62 +225,262 @@ impl<Handler: BackendHandler> Query<Handler> {
 
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler + Sync> Query<Handler> {
+    #[graphql(deprecated = "Use schemaVersion instead")]
     fn api_version() -> &'static str {
         "1.0"
     }
 
+    /// The version of the schema, bumped on every schema change.
+    fn schema_version() -> i32 {
+        SCHEMA_VERSION
+    }
+
+    /// The version of the LLDAP server.
+    fn server_version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
     pub async fn user(context: &Context<Handler>, user_id: String) -> FieldResult<User<Handler>> {
         if !context.validation_result.can_access(&user_id) {
             return Err("Unauthorized access to user data".into());
         }
-        Ok(context
+        context
             .handler
             .get_user_details(&user_id)
             .await
-            .map(Into::into)?)
+            .map(Into::into)
+            .map_err(|e| not_found_error(e, || format!("User `{}` not found", user_id)))
     }
 
+    /// The users matching the filters. When `offset`, `limit` or `sort` is given, only that page
+    /// of the users sorted by the column (by ID otherwise) is returned.
     async fn users(
         context: &Context<Handler>,
+        executor: &Executor<'_, '_, Context<Handler>>,
         #[graphql(name = "where")] filters: Option<RequestFilter>,
+        offset: Option<i32>,
+        limit: Option<i32>,
+        sort: Option<UserColumn>,
+        descending: Option<bool>,
     ) -> FieldResult<Vec<User<Handler>>> {
-        if !context.validation_result.is_admin {
+        if !context.validation_result.can_read_all()
+            && context
+                .handler
+                .list_managed_groups(&context.validation_result.user)
+                .await?
+                .is_empty()
+        {
+            return Err("Unauthorized access to user list".into());
+        }
+        let filters = filters.map(TryInto::try_into).transpose()?;
+        let users = if offset.is_none() && limit.is_none() && sort.is_none() {
+            context.handler.list_users(filters).await?
+        } else {
+            context
+                .handler
+                .list_users_page(UserPageRequest {
+                    filters,
+                    sort: sort.unwrap_or(UserColumn::UserId).into(),
+                    descending: descending.unwrap_or(false),
+                    offset: offset.unwrap_or(0).into(),
+                    limit: limit.map(i64::from).unwrap_or(i64::MAX),
+                })
+                .await?
+        };
+        let mut users: Vec<User<Handler>> = users.into_iter().map(Into::into).collect();
+        if executor.look_ahead().select_child("groups").is_some() {
+            prefetch_user_groups(&*context.handler, &mut users).await?;
+        }
+        Ok(users)
+    }
+
+    /// The number of users matching the filters, cheaper than listing them.
+    async fn user_count(
+        context: &Context<Handler>,
+        #[graphql(name = "where")] filters: Option<RequestFilter>,
+    ) -> FieldResult<i32> {
+        if !context.validation_result.can_read_all() {
+            return Err("Unauthorized access to user list".into());
+        }
+        Ok(context
+            .handler
+            .count_users(filters.map(TryInto::try_into).transpose()?)
+            .await?
+            .try_into()?)
+    }
+
+    /// The users sharing their email with another user (ignoring case), sorted by email.
+    async fn users_with_duplicate_emails(
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<User<Handler>>> {
+        if !context.validation_result.can_read_all() {
             return Err("Unauthorized access to user list".into());
         }
         Ok(context
             .handler
-            .list_users(filters.map(TryInto::try_into).transpose()?)
+            .list_users_with_duplicate_emails()
             .await
             .map(|v| v.into_iter().map(Into::into).collect())?)
     }
 
-    async fn groups(context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
-        if !context.validation_result.is_admin {
+    async fn groups(
+        context: &Context<Handler>,
+        executor: &Executor<'_, '_, Context<Handler>>,
+    ) -> FieldResult<Vec<Group<Handler>>> {
+        if !context.validation_result.can_read_all() {
+            return Err("Unauthorized access to group list".into());
+        }
+        let groups = context.handler.list_groups().await?;
+        if executor.look_ahead().select_child("users").is_none() {
+            return Ok(groups.into_iter().map(Into::into).collect());
+        }
+        // A single listing of the users for all the groups, instead of one query per group.
+        let users: HashMap<String, DomainUser> = context
+            .handler
+            .list_users(None)
+            .await?
+            .into_iter()
+            .map(|user| (user.user_id.clone(), user))
+            .collect();
+        Ok(groups
+            .into_iter()
+            .map(|group| {
+                let members = group
+                    .users
+                    .iter()
+                    .filter_map(|user_id| users.get(user_id))
+                    .cloned()
+                    .map(Into::into)
+                    .collect();
+                Group {
+                    users: Some(members),
+                    ..Group::from(group)
+                }
+            })
+            .collect())
+    }
+
+    /// The number of groups.
+    async fn group_count(context: &Context<Handler>) -> FieldResult<i32> {
+        if !context.validation_result.can_read_all() {
             return Err("Unauthorized access to group list".into());
         }
+        Ok(context.handler.count_groups().await?.try_into()?)
+    }
+
+    async fn group(context: &Context<Handler>, group_id: i32) -> FieldResult<Group<Handler>> {
+        if !can_read_group(context, group_id).await? {
+            return Err("Unauthorized access to group data".into());
+        }
+        context
+            .handler
+            .get_group_details(GroupId(group_id))
+            .await
+            .map(Into::into)
+            .map_err(|e| not_found_error(e, || format!("Group {} not found", group_id)))
+    }
+
+    /// The actions allowed for the current user.
+    fn permissions(context: &Context<Handler>) -> Permissions {
+        let validation_result = &context.validation_result;
+        Permissions {
+            is_admin: validation_result.is_admin(),
+            can_read_all: validation_result.can_read_all(),
+            can_manage_users: validation_result.can_manage_users(),
+            can_reset_passwords: validation_result.can_reset_passwords(),
+        }
+    }
+
+    async fn api_tokens(context: &Context<Handler>) -> FieldResult<Vec<ApiToken>> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized access to API token list".into());
+        }
         Ok(context
             .handler
-            .list_groups()
+            .list_api_tokens()
             .await
             .map(|v| v.into_iter().map(Into::into).collect())?)
     }
 
-    async fn group(context: &Context<Handler>, group_id: i32) -> FieldResult<Group<Handler>> {
-        if !context.validation_result.is_admin {
-            return Err("Unauthorized access to group data".into());
+    /// The OpenID Connect clients registered through the API, without the ones from the
+    /// configuration.
+    async fn oidc_clients(context: &Context<Handler>) -> FieldResult<Vec<OidcClient>> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized access to OpenID Connect client list".into());
         }
         Ok(context
             .handler
-            .get_group_details(GroupId(group_id))
+            .list_oidc_clients()
             .await
-            .map(Into::into)?)
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The administrative changes, most recent first. At most 100 entries are returned at once.
+    async fn audit_log(
+        context: &Context<Handler>,
+        offset: Option<i32>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<AuditLogEntry>> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized access to audit log".into());
+        }
+        Ok(context
+            .handler
+            .list_audit_log(
+                offset.unwrap_or(0).into(),
+                limit.unwrap_or(50).min(100).into(),
+            )
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The users deleted recently, which can still be restored. Most recent first.
+    async fn deleted_users(context: &Context<Handler>) -> FieldResult<Vec<DeletedUser>> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized access to deleted user list".into());
+        }
+        Ok(context
+            .handler
+            .list_deleted_users()
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The custom attributes of the users, defined by the admins, sorted by name.
+    async fn user_attribute_schema(
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<AttributeSchema>> {
+        Ok(context
+            .handler
+            .list_user_attribute_schema()
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The background jobs, with the result of their last run.
+    async fn jobs(context: &Context<Handler>) -> FieldResult<Vec<Job>> {
+        if !context.validation_result.is_admin() {
+            return Err("Unauthorized access to jobs".into());
+        }
+        let scheduler = context
+            .scheduler
+            .as_ref()
+            .ok_or("The background jobs are not running")?;
+        Ok(scheduler
+            .send(ListJobs)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 /// Represents a single user.
 pub struct User<Handler: BackendHandler> {
     user: DomainUser,
+    /// The groups, if already fetched with the other users. Fetched on demand otherwise.
+    groups: Option<Vec<Group<Handler>>>,
     _phantom: std::marker::PhantomData<Box<Handler>>,
 }
 
@@ -163,6 +488,7 @@ impl<Handler: BackendHandler> Default for User<Handler> {
     fn default() -> Self {
         Self {
             user: DomainUser::default(),
+            groups: None,
             _phantom: std::marker::PhantomData,
         }
     }
@@ -174,6 +500,11 @@ impl<Handler: BackendHandler + Sync> User<Handler> {
         &self.user.user_id
     }
 
+    /// The DN of the user's entry over LDAP.
+    fn dn(&self, context: &Context<Handler>) -> String {
+        user_dn(&self.user.user_id, &context.base_dn)
+    }
+
     fn email(&self) -> &str {
         &self.user.email
     }
@@ -182,46 +513,154 @@ impl<Handler: BackendHandler + Sync> User<Handler> {
         &self.user.display_name
     }
 
+    /// The `givenName` attribute over LDAP.
     fn first_name(&self) -> &str {
         &self.user.first_name
     }
 
+    /// The `sn` attribute over LDAP.
     fn last_name(&self) -> &str {
         &self.user.last_name
     }
 
+    /// Base64-encoded JPEG picture.
+    fn avatar(&self) -> Option<String> {
+        self.user.avatar.as_ref().map(base64::encode)
+    }
+
     fn creation_date(&self) -> chrono::DateTime<chrono::Utc> {
         self.user.creation_date
     }
 
-    /// The groups to which this user belongs.
+    /// Disabled users can't log in.
+    fn enabled(&self) -> bool {
+        self.user.enabled
+    }
+
+    /// After this date, the user can't log in anymore.
+    fn valid_until(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.user.valid_until
+    }
+
+    /// Set while the user is locked after too many failed logins.
+    fn locked_until(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.user
+            .locked_until
+            .filter(|date| *date > chrono::Utc::now())
+    }
+
+    /// Whether the user logs in with a code from an authenticator app.
+    async fn totp_enabled(&self, context: &Context<Handler>) -> FieldResult<bool> {
+        Ok(context.handler.is_totp_enabled(&self.user.user_id).await?)
+    }
+
+    /// The groups to which this user belongs, directly or through subgroups.
     async fn groups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+        if let Some(groups) = &self.groups {
+            return Ok(groups.clone());
+        }
         Ok(context
             .handler
             .get_user_groups(&self.user.user_id)
             .await
             .map(|set| set.into_iter().map(Into::into).collect())?)
     }
+
+    /// The groups whose members this user can manage.
+    async fn managed_groups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+        Ok(context
+            .handler
+            .list_managed_groups(&self.user.user_id)
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The last successful logins of the user, most recent first. Only visible to the user
+    /// themselves and to the admins. At most 100 entries are returned.
+    async fn login_history(
+        &self,
+        context: &Context<Handler>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<LoginHistoryEntry>> {
+        if context.validation_result.user != self.user.user_id
+            && !context.validation_result.is_admin()
+        {
+            return Err("Unauthorized access to login history".into());
+        }
+        Ok(context
+            .handler
+            .list_login_history(&self.user.user_id, limit.unwrap_or(20).min(100).into())
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The security keys of the user. Only visible to the user themselves and to the admins.
+    async fn webauthn_credentials(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<WebauthnCredential>> {
+        if context.validation_result.user != self.user.user_id
+            && !context.validation_result.is_admin()
+        {
+            return Err("Unauthorized access to security keys".into());
+        }
+        Ok(context
+            .handler
+            .list_webauthn_credentials(&self.user.user_id)
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+
+    /// The custom attributes the user has a value for, sorted by name.
+    async fn attributes(&self, context: &Context<Handler>) -> FieldResult<Vec<AttributeValue>> {
+        Ok(context
+            .handler
+            .get_user_attributes(&self.user.user_id)
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
 }
 
 impl<Handler: BackendHandler> From<DomainUser> for User<Handler> {
     fn from(user: DomainUser) -> Self {
         Self {
             user,
+            groups: None,
             _phantom: std::marker::PhantomData,
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 /// Represents a single group.
 pub struct Group<Handler: BackendHandler> {
     group_id: i32,
     display_name: String,
+    /// The description and email, if known. Fetched on demand otherwise.
+    attributes: Option<(Option<String>, Option<String>)>,
+    /// The IDs of the members, if listed with the other groups.
     members: Option<Vec<String>>,
+    /// The members, if already fetched with the other groups. Fetched on demand otherwise.
+    users: Option<Vec<User<Handler>>>,
     _phantom: std::marker::PhantomData<Box<Handler>>,
 }
 
+impl<Handler: BackendHandler + Sync> Group<Handler> {
+    async fn get_attributes(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<(Option<String>, Option<String>)> {
+        if let Some(attributes) = &self.attributes {
+            return Ok(attributes.clone());
+        }
+        let details = context
+            .handler
+            .get_group_details(GroupId(self.group_id))
+            .await?;
+        Ok((details.description, details.email))
+    }
+}
+
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler + Sync> Group<Handler> {
     fn id(&self) -> i32 {
@@ -230,11 +669,25 @@ impl<Handler: BackendHandler + Sync> Group<Handler> {
     fn display_name(&self) -> String {
         self.display_name.clone()
     }
-    /// The groups to which this user belongs.
+    /// The DN of the group's entry over LDAP.
+    fn dn(&self, context: &Context<Handler>) -> String {
+        group_dn(&self.display_name, &context.base_dn)
+    }
+    async fn description(&self, context: &Context<Handler>) -> FieldResult<Option<String>> {
+        Ok(self.get_attributes(context).await?.0)
+    }
+    /// The address of the group's mailing list, as `mail` over LDAP.
+    async fn email(&self, context: &Context<Handler>) -> FieldResult<Option<String>> {
+        Ok(self.get_attributes(context).await?.1)
+    }
+    /// The members of the group, including the members of its subgroups.
     async fn users(&self, context: &Context<Handler>) -> FieldResult<Vec<User<Handler>>> {
-        if !context.validation_result.is_admin {
+        if !can_read_group(context, self.group_id).await? {
             return Err("Unauthorized access to group data".into());
         }
+        if let Some(users) = &self.users {
+            return Ok(users.clone());
+        }
         Ok(context
             .handler
             .list_users(Some(DomainRequestFilter::MemberOfId(GroupId(
@@ -243,6 +696,82 @@ impl<Handler: BackendHandler + Sync> Group<Handler> {
             .await
             .map(|v| v.into_iter().map(Into::into).collect())?)
     }
+    /// The number of members of the group, including the members of its subgroups.
+    async fn member_count(&self, context: &Context<Handler>) -> FieldResult<i32> {
+        if !can_read_group(context, self.group_id).await? {
+            return Err("Unauthorized access to group data".into());
+        }
+        // Already counted when listing all the groups at once.
+        if let Some(members) = &self.members {
+            return Ok(members.len() as i32);
+        }
+        Ok(context
+            .handler
+            .list_users(Some(DomainRequestFilter::MemberOfId(GroupId(
+                self.group_id,
+            ))))
+            .await?
+            .len() as i32)
+    }
+    /// The users who can manage the members of this group, without any role.
+    async fn managers(&self, context: &Context<Handler>) -> FieldResult<Vec<User<Handler>>> {
+        if !can_read_group(context, self.group_id).await? {
+            return Err("Unauthorized access to group data".into());
+        }
+        let mut managers = Vec::new();
+        for user_id in context
+            .handler
+            .list_group_managers(GroupId(self.group_id))
+            .await?
+        {
+            managers.push(context.handler.get_user_details(&user_id).await?.into());
+        }
+        Ok(managers)
+    }
+    /// The groups nested in this group: their members are also members of this group.
+    async fn subgroups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+        if !context.validation_result.can_read_all() {
+            return Err("Unauthorized access to group data".into());
+        }
+        Ok(context
+            .handler
+            .list_subgroups(GroupId(self.group_id))
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+}
+
+/// Fills the groups of the users with a single listing of the groups, instead of one query per
+/// user.
+async fn prefetch_user_groups<Handler: BackendHandler>(
+    handler: &Handler,
+    users: &mut [User<Handler>],
+) -> FieldResult<()> {
+    let user_ids: HashSet<&str> = users.iter().map(|u| u.user.user_id.as_str()).collect();
+    let mut groups_by_user: HashMap<String, Vec<Group<Handler>>> = HashMap::new();
+    for group in handler.list_groups().await? {
+        for user_id in group.users.iter().filter(|u| user_ids.contains(u.as_str())) {
+            groups_by_user
+                .entry(user_id.clone())
+                .or_default()
+                .push(Group {
+                    group_id: group.id.0,
+                    display_name: group.display_name.clone(),
+                    attributes: Some((group.description.clone(), group.email.clone())),
+                    members: None,
+                    users: None,
+                    _phantom: std::marker::PhantomData,
+                });
+        }
+    }
+    for user in users.iter_mut() {
+        user.groups = Some(
+            groups_by_user
+                .remove(&user.user.user_id)
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
 }
 
 impl<Handler: BackendHandler> From<GroupIdAndName> for Group<Handler> {
@@ -250,7 +779,22 @@ impl<Handler: BackendHandler> From<GroupIdAndName> for Group<Handler> {
         Self {
             group_id: group_id_and_name.0 .0,
             display_name: group_id_and_name.1,
+            attributes: None,
             members: None,
+            users: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<Handler: BackendHandler> From<GroupDetails> for Group<Handler> {
+    fn from(group: GroupDetails) -> Self {
+        Self {
+            group_id: group.group_id.0,
+            display_name: group.display_name,
+            attributes: Some((group.description, group.email)),
+            members: None,
+            users: None,
             _phantom: std::marker::PhantomData,
         }
     }
@@ -261,12 +805,229 @@ impl<Handler: BackendHandler> From<DomainGroup> for Group<Handler> {
         Self {
             group_id: group.id.0,
             display_name: group.display_name,
+            attributes: Some((group.description, group.email)),
             members: Some(group.users.into_iter().map(Into::into).collect()),
+            users: None,
             _phantom: std::marker::PhantomData,
         }
     }
 }
 
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The actions allowed for a user, depending on their roles.
+pub struct Permissions {
+    /// Full access, including group management.
+    is_admin: bool,
+    /// Can see all the users and groups.
+    can_read_all: bool,
+    /// Can create and edit non-admin users, and manage the members of non-role groups.
+    can_manage_users: bool,
+    /// Can reset the passwords of non-admin users.
+    can_reset_passwords: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A long-lived API token. The token itself is only returned on creation.
+pub struct ApiToken {
+    id: i32,
+    name: String,
+    user_id: String,
+    creation_date: chrono::DateTime<chrono::Utc>,
+    expiry_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<DomainApiToken> for ApiToken {
+    fn from(token: DomainApiToken) -> Self {
+        Self {
+            id: token.token_id,
+            name: token.name,
+            user_id: token.user_id,
+            creation_date: token.creation_date,
+            expiry_date: token.expiry_date,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A client application of the OpenID Connect provider. Its secret is only returned on creation.
+pub struct OidcClient {
+    client_id: String,
+    display_name: String,
+    redirect_uris: Vec<String>,
+}
+
+impl From<DomainOidcClient> for OidcClient {
+    fn from(client: DomainOidcClient) -> Self {
+        Self {
+            client_id: client.client_id,
+            display_name: client.display_name,
+            redirect_uris: client.redirect_uris,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// An administrative change.
+pub struct AuditLogEntry {
+    id: i32,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    /// The user who made the change.
+    actor: String,
+    /// The IP address of the client.
+    source: String,
+    /// The mutation, e.g. "deleteUser".
+    action: String,
+    /// The modified entry, e.g. "user:bob" or "group:3".
+    target: String,
+    details: Option<String>,
+}
+
+impl From<DomainAuditLogEntry> for AuditLogEntry {
+    fn from(entry: DomainAuditLogEntry) -> Self {
+        Self {
+            id: entry.entry_id,
+            timestamp: entry.timestamp,
+            actor: entry.actor,
+            source: entry.source,
+            action: entry.action,
+            target: entry.target,
+            details: entry.details,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A successful login.
+pub struct LoginHistoryEntry {
+    timestamp: chrono::DateTime<chrono::Utc>,
+    /// "web", "trusted_header" or "ldap".
+    method: String,
+    /// The IP address of the client.
+    ip: String,
+    /// The browser, for the web logins.
+    user_agent: Option<String>,
+}
+
+impl From<DomainLoginHistoryEntry> for LoginHistoryEntry {
+    fn from(entry: DomainLoginHistoryEntry) -> Self {
+        Self {
+            timestamp: entry.timestamp,
+            method: entry.method,
+            ip: entry.ip,
+            user_agent: entry.user_agent,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A custom attribute of the users, defined by an admin on top of the built-in ones.
+pub struct AttributeSchema {
+    name: String,
+    attribute_type: AttributeType,
+    /// Whether a user can have several values.
+    is_list: bool,
+    /// Whether the users can change their own value, instead of only the admins.
+    is_editable: bool,
+}
+
+impl From<DomainAttributeSchema> for AttributeSchema {
+    fn from(schema: DomainAttributeSchema) -> Self {
+        Self {
+            name: schema.name,
+            attribute_type: schema.attribute_type.into(),
+            is_list: schema.is_list,
+            is_editable: schema.is_editable,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The values of a custom attribute for a user.
+pub struct AttributeValue {
+    name: String,
+    /// In the format of the type of the attribute. A single one, unless it's a list.
+    values: Vec<String>,
+}
+
+impl From<DomainAttributeValue> for AttributeValue {
+    fn from(value: DomainAttributeValue) -> Self {
+        Self {
+            name: value.name,
+            values: value.values,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A security key registered by a user.
+pub struct WebauthnCredential {
+    /// Base64url-encoded.
+    id: String,
+    name: String,
+    creation_date: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<DomainWebauthnCredential> for WebauthnCredential {
+    fn from(credential: DomainWebauthnCredential) -> Self {
+        Self {
+            id: credential.credential_id,
+            name: credential.name,
+            creation_date: credential.creation_date,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A deleted user, kept until `deleted_user_retention_days` have passed.
+pub struct DeletedUser {
+    id: String,
+    email: String,
+    display_name: String,
+    deleted_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<DomainDeletedUser> for DeletedUser {
+    fn from(user: DomainDeletedUser) -> Self {
+        Self {
+            id: user.user_id,
+            email: user.email,
+            display_name: user.display_name,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A background job, run on a schedule.
+pub struct Job {
+    name: String,
+    /// Paused jobs are skipped on schedule, but can still be triggered.
+    paused: bool,
+    running: bool,
+    last_run_start: Option<chrono::DateTime<chrono::Utc>>,
+    last_run_duration_ms: Option<i32>,
+    /// Set if the last run failed.
+    last_run_error: Option<String>,
+    next_run: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<JobStatus> for Job {
+    fn from(job: JobStatus) -> Self {
+        Self {
+            name: job.name,
+            paused: job.paused,
+            running: job.running,
+            last_run_start: job.last_run.as_ref().map(|r| r.start),
+            last_run_duration_ms: job
+                .last_run
+                .as_ref()
+                .map(|r| r.duration.num_milliseconds().try_into().unwrap_or(i32::MAX)),
+            last_run_error: job.last_run.and_then(|r| r.error),
+            next_run: job.next_run,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,7 +1037,6 @@ mod tests {
         RootNode, Variables,
     };
     use mockall::predicate::eq;
-    use std::collections::HashSet;
 
     fn schema<'q, C, Q>(query_root: Q) -> RootNode<'q, Q, EmptyMutation<C>, EmptySubscription<C>>
     where
@@ -294,9 +1054,11 @@ mod tests {
         const QUERY: &str = r#"{
           user(userId: "bob") {
             id
+            dn
             email
             groups {
               id
+              dn
             }
           }
         }"#;
@@ -320,6 +1082,9 @@ mod tests {
         let context = Context::<MockTestBackendHandler> {
             handler: Box::new(mock),
             validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
         };
 
         let schema = schema(Query::<MockTestBackendHandler>::new());
@@ -330,8 +1095,9 @@ mod tests {
                 {
                     "user": {
                         "id": "bob",
+                        "dn": "cn=bob,ou=people,dc=example,dc=com",
                         "email": "bob@bobbers.on",
-                        "groups": [{"id": 3}]
+                        "groups": [{"id": 3, "dn": "cn=Bobbersons,ou=groups,dc=example,dc=com"}]
                     }
                 }),
                 vec![]
@@ -339,6 +1105,35 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn get_missing_user() {
+        const QUERY: &str = r#"{
+          user(userId: "bob") {
+            id
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_details()
+            .with(eq("bob"))
+            .return_once(|_| Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)));
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let (data, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert!(data.is_null());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error().message(), "User `bob` not found");
+    }
+
     #[tokio::test]
     async fn list_users() {
         const QUERY: &str = r#"{
@@ -383,6 +1178,9 @@ mod tests {
         let context = Context::<MockTestBackendHandler> {
             handler: Box::new(mock),
             validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
         };
 
         let schema = schema(Query::<MockTestBackendHandler>::new());
@@ -406,4 +1204,371 @@ mod tests {
             ))
         );
     }
+
+    #[tokio::test]
+    async fn list_users_with_groups_in_one_query() {
+        const QUERY: &str = r#"{
+          users {
+            id
+            groups {
+              displayName
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().times(1).return_once(|_| {
+            Ok(vec![
+                DomainUser {
+                    user_id: "bob".to_string(),
+                    ..Default::default()
+                },
+                DomainUser {
+                    user_id: "john".to_string(),
+                    ..Default::default()
+                },
+            ])
+        });
+        mock.expect_list_groups().times(1).return_once(|| {
+            Ok(vec![DomainGroup {
+                id: GroupId(3),
+                display_name: "Bobbersons".to_string(),
+                description: None,
+                email: None,
+                users: vec!["bob".to_string()],
+            }])
+        });
+        mock.expect_get_user_groups().never();
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "users": [
+                        {"id": "bob", "groups": [{"displayName": "Bobbersons"}]},
+                        {"id": "john", "groups": []},
+                    ]
+                }),
+                vec![]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_groups_with_users_in_one_query() {
+        const QUERY: &str = r#"{
+          groups {
+            id
+            users {
+              id
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().times(1).return_once(|| {
+            Ok(vec![
+                DomainGroup {
+                    id: GroupId(3),
+                    display_name: "Bobbersons".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["bob".to_string()],
+                },
+                DomainGroup {
+                    id: GroupId(4),
+                    display_name: "Empty".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec![],
+                },
+            ])
+        });
+        mock.expect_list_users()
+            .with(eq(None))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![DomainUser {
+                    user_id: "bob".to_string(),
+                    ..Default::default()
+                }])
+            });
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "groups": [
+                        {"id": 3, "users": [{"id": "bob"}]},
+                        {"id": 4, "users": []},
+                    ]
+                }),
+                vec![]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_groups_with_member_counts() {
+        const QUERY: &str = r#"{
+          groups {
+            id
+            memberCount
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().times(1).return_once(|| {
+            Ok(vec![
+                DomainGroup {
+                    id: GroupId(3),
+                    display_name: "Bobbersons".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["bob".to_string(), "john".to_string()],
+                },
+                DomainGroup {
+                    id: GroupId(4),
+                    display_name: "Empty".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec![],
+                },
+            ])
+        });
+        // The counts come from the listing of the groups: no other query.
+        mock.expect_list_users().times(0);
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "groups": [
+                        {"id": 3, "memberCount": 2},
+                        {"id": 4, "memberCount": 0},
+                    ]
+                }),
+                vec![]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn get_permissions() {
+        const QUERY: &str = r#"{
+          permissions {
+            isAdmin
+            canReadAll
+            canManageUsers
+            canResetPasswords
+          }
+        }"#;
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(MockTestBackendHandler::new()),
+            validation_result: ValidationResults::from_groups(
+                "helpdesk".to_string(),
+                vec!["lldap_password_manager", "helpdesk"],
+            ),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "permissions": {
+                        "isAdmin": false,
+                        "canReadAll": true,
+                        "canManageUsers": false,
+                        "canResetPasswords": true,
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(MockTestBackendHandler::new()),
+            validation_result: ValidationResults::from_groups(
+                "viewer".to_string(),
+                vec!["lldap_readonly"],
+            ),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "permissions": {
+                        "isAdmin": false,
+                        "canReadAll": true,
+                        "canManageUsers": false,
+                        "canResetPasswords": false,
+                    }
+                }),
+                vec![]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn count_users_and_groups() {
+        const QUERY: &str = r#"{
+          userCount(filters: {memberOf: "admins"})
+          groupCount
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        use crate::domain::handler::RequestFilter;
+        mock.expect_count_users()
+            .with(eq(Some(RequestFilter::MemberOf("admins".to_string()))))
+            .return_once(|_| Ok(2));
+        mock.expect_count_groups().return_once(|| Ok(5));
+
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::admin(),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "userCount": 2,
+                    "groupCount": 5,
+                }),
+                vec![]
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn group_managers_can_read_their_group() {
+        const QUERY: &str = r#"{
+          group(groupId: 3) {
+            displayName
+            managers {
+              id
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_group_managers()
+            .with(eq(GroupId(3)))
+            .returning(|_| Ok(vec!["teamlead".to_string()]));
+        mock.expect_get_group_details()
+            .with(eq(GroupId(3)))
+            .return_once(|_| {
+                Ok(GroupDetails {
+                    group_id: GroupId(3),
+                    display_name: "Team".to_string(),
+                    description: None,
+                    email: None,
+                })
+            });
+        mock.expect_get_user_details()
+            .with(eq("teamlead"))
+            .return_once(|_| {
+                Ok(DomainUser {
+                    user_id: "teamlead".to_string(),
+                    ..Default::default()
+                })
+            });
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::from_groups("teamlead".to_string(), vec![]),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "group": {
+                        "displayName": "Team",
+                        "managers": [{"id": "teamlead"}]
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_group_managers()
+            .with(eq(GroupId(3)))
+            .returning(|_| Ok(vec!["teamlead".to_string()]));
+        let context = Context::<MockTestBackendHandler> {
+            handler: Box::new(mock),
+            validation_result: ValidationResults::from_groups("bob".to_string(), vec![]),
+            source: "127.0.0.1".to_string(),
+            scheduler: None,
+            base_dn: "dc=example,dc=com".to_string(),
+        };
+        let (data, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert!(data.is_null());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn schema_version_is_bumped_with_the_schema() {
+        use sha2::{Digest, Sha256};
+        let hash = format!(
+            "{:x}",
+            Sha256::digest(include_str!("../../../../schema.graphql").as_bytes())
+        );
+        assert_eq!(
+            hash, SCHEMA_HASH,
+            "schema.graphql changed: bump SCHEMA_VERSION, and set SCHEMA_HASH to the new hash"
+        );
+    }
 }
@@ -0,0 +1,152 @@
+//! Probes for the container orchestrators: `/health` answers as long as the HTTP server runs, and
+//! `/ready` checks that the database and the LDAP server can be reached. `/metrics` reports the use
+//! of the database connections, in the Prometheus format.
+
+use crate::infra::{
+    db_pool::PoolStats, tcp_backend_handler::TcpBackendHandler, tcp_server::AppState,
+};
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+use std::{net::SocketAddr, time::Duration};
+
+/// How long a readiness check can take before it fails.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn get_health() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+async fn check_ldap(address: SocketAddr) -> Result<(), String> {
+    match tokio::time::timeout(CHECK_TIMEOUT, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timeout".to_string()),
+    }
+}
+
+async fn check_database<Backend: TcpBackendHandler>(
+    backend_handler: &Backend,
+) -> Result<(), String> {
+    match tokio::time::timeout(CHECK_TIMEOUT, backend_handler.check_database()).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("timeout".to_string()),
+    }
+}
+
+fn check_status(result: &Result<(), String>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+async fn get_ready<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse
+where
+    Backend: TcpBackendHandler + 'static,
+{
+    let (database, ldap) = futures::join!(
+        check_database(&data.backend_handler),
+        check_ldap(data.ldap_address)
+    );
+    let ready = database.is_ok() && ldap.is_ok();
+    if !ready {
+        log::warn!(
+            "Not ready: database {}, LDAP {}",
+            check_status(&database),
+            check_status(&ldap)
+        );
+    }
+    let body = json!({
+        "status": if ready { "ok" } else { "error" },
+        "checks": {
+            "database": check_status(&database),
+            "ldap": check_status(&ldap),
+        },
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+fn format_metrics(stats: &PoolStats) -> String {
+    let metrics: [(&str, &str, &str, String); 4] = [
+        (
+            "lldap_db_pool_max_connections",
+            "gauge",
+            "Maximum number of database connections.",
+            stats.max_connections.to_string(),
+        ),
+        (
+            "lldap_db_pool_connections",
+            "gauge",
+            "Open database connections, idle or not.",
+            stats.connections.to_string(),
+        ),
+        (
+            "lldap_db_pool_idle_connections",
+            "gauge",
+            "Idle database connections.",
+            stats.idle_connections.to_string(),
+        ),
+        (
+            "lldap_db_pool_saturated_seconds_total",
+            "counter",
+            "Seconds during which all the database connections were in use.",
+            stats.saturated_seconds.to_string(),
+        ),
+    ];
+    metrics
+        .iter()
+        .map(|(name, kind, help, value)| {
+            format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n",
+                name = name,
+                kind = kind,
+                help = help,
+                value = value
+            )
+        })
+        .collect()
+}
+
+async fn get_metrics<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse
+where
+    Backend: TcpBackendHandler + 'static,
+{
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(format_metrics(&data.backend_handler.get_pool_stats()))
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + 'static,
+{
+    cfg.service(web::resource("/health").route(web::get().to(get_health)))
+        .service(web::resource("/ready").route(web::get().to(get_ready::<Backend>)))
+        .service(web::resource("/metrics").route(web::get().to(get_metrics::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_metrics() {
+        let metrics = format_metrics(&PoolStats {
+            max_connections: 5,
+            connections: 5,
+            idle_connections: 0,
+            saturated_seconds: 12,
+        });
+        assert!(metrics.contains("\nlldap_db_pool_max_connections 5\n"));
+        assert!(metrics.contains("\nlldap_db_pool_idle_connections 0\n"));
+        assert!(metrics.contains("# TYPE lldap_db_pool_saturated_seconds_total counter\n"));
+        assert!(metrics.ends_with("lldap_db_pool_saturated_seconds_total 12\n"));
+    }
+}
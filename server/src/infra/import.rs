@@ -0,0 +1,678 @@
+//! Import of the users and groups from another directory, as LDIF or CSV. The import can be run
+//! several times: the existing users are updated, and the existing groups and memberships are
+//! kept. An entry that can't be imported doesn't stop the others, its error is reported at the end.
+
+use crate::{
+    domain::{
+        error::DomainError,
+        handler::{BackendHandler, CreateUserRequest, GroupId, UpdateUserRequest},
+        sql_backend_handler::SqlBackendHandler,
+        sql_tables::PoolOptions,
+    },
+    infra::{
+        cli::{CommandOutput, ImportFormat, ImportOpts, RunOpts},
+        configuration, encryption,
+        ldif::{parse_ldif, LdifEntry},
+    },
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Where to read each field of the users: the attribute (LDIF) or column (CSV) names, which are
+/// case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub user_id: String,
+    pub email: String,
+    pub display_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub avatar: String,
+    pub password_hash: String,
+    /// The groups of the user: DNs in LDIF, names separated by semicolons in CSV.
+    pub groups: String,
+}
+
+impl Mapping {
+    pub fn for_format(format: ImportFormat) -> Self {
+        match format {
+            ImportFormat::Ldif => Self {
+                user_id: "uid".to_string(),
+                email: "mail".to_string(),
+                display_name: "displayName".to_string(),
+                first_name: "givenName".to_string(),
+                last_name: "sn".to_string(),
+                avatar: "jpegPhoto".to_string(),
+                password_hash: "userPassword".to_string(),
+                groups: "memberOf".to_string(),
+            },
+            ImportFormat::Csv => Self {
+                user_id: "user_id".to_string(),
+                email: "email".to_string(),
+                display_name: "display_name".to_string(),
+                first_name: "first_name".to_string(),
+                last_name: "last_name".to_string(),
+                avatar: "avatar".to_string(),
+                password_hash: "password_hash".to_string(),
+                groups: "groups".to_string(),
+            },
+        }
+    }
+
+    /// Applies a `field=source` override, e.g. `email=mailPrimaryAddress`.
+    pub fn set(&mut self, mapping: &str) -> Result<()> {
+        let (field, source) = mapping
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected `field=source`, got `{}`", mapping))?;
+        let target = match field {
+            "user_id" => &mut self.user_id,
+            "email" => &mut self.email,
+            "display_name" => &mut self.display_name,
+            "first_name" => &mut self.first_name,
+            "last_name" => &mut self.last_name,
+            "avatar" => &mut self.avatar,
+            "password_hash" => &mut self.password_hash,
+            "groups" => &mut self.groups,
+            _ => bail!("Unknown user field `{}`", field),
+        };
+        *target = source.to_string();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportedUser {
+    pub user_id: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub avatar: Option<Vec<u8>>,
+    pub password_hash: Option<String>,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportedGroup {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+/// The error of an entry, identified by its DN, line or user ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryError {
+    pub entry: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportData {
+    pub users: Vec<ImportedUser>,
+    pub groups: Vec<ImportedGroup>,
+    /// The entries that couldn't be read.
+    pub errors: Vec<EntryError>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub users_created: usize,
+    pub users_updated: usize,
+    pub groups_created: usize,
+    pub memberships_added: usize,
+    pub errors: Vec<EntryError>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+/// The value of the first component of the DN, e.g. "bob" for "uid=bob,ou=people,dc=example".
+fn first_rdn_value(dn: &str) -> Option<String> {
+    let rdn = dn.split(',').next()?;
+    let (_, value) = rdn.split_once('=')?;
+    Some(value.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn has_object_class(entry: &LdifEntry, classes: &[&str]) -> bool {
+    entry.get("objectClass").any(|class| {
+        classes
+            .iter()
+            .any(|c| c.as_bytes().eq_ignore_ascii_case(class))
+    })
+}
+
+pub fn read_ldif_user(entry: &LdifEntry, mapping: &Mapping) -> Result<ImportedUser> {
+    let user_id = non_empty(entry.get_string(&mapping.user_id))
+        .ok_or_else(|| anyhow!("Missing `{}`", mapping.user_id))?;
+    let email = non_empty(entry.get_string(&mapping.email))
+        .ok_or_else(|| anyhow!("Missing `{}`", mapping.email))?;
+    Ok(ImportedUser {
+        user_id,
+        email,
+        display_name: non_empty(entry.get_string(&mapping.display_name))
+            .or_else(|| non_empty(entry.get_string("cn"))),
+        first_name: non_empty(entry.get_string(&mapping.first_name)),
+        last_name: non_empty(entry.get_string(&mapping.last_name)),
+        avatar: entry.get(&mapping.avatar).next().map(<[u8]>::to_vec),
+        password_hash: non_empty(entry.get_string(&mapping.password_hash)),
+        groups: entry
+            .get(&mapping.groups)
+            .filter_map(|dn| first_rdn_value(&String::from_utf8_lossy(dn)))
+            .collect(),
+    })
+}
+
+pub fn read_ldif_group(entry: &LdifEntry) -> Result<ImportedGroup> {
+    let name = non_empty(entry.get_string("cn")).ok_or_else(|| anyhow!("Missing `cn`"))?;
+    let mut members: Vec<String> = entry
+        .get("member")
+        .chain(entry.get("uniqueMember"))
+        .filter_map(|dn| first_rdn_value(&String::from_utf8_lossy(dn)))
+        .collect();
+    members.extend(
+        entry
+            .get("memberUid")
+            .map(|uid| String::from_utf8_lossy(uid).into_owned()),
+    );
+    Ok(ImportedGroup { name, members })
+}
+
+/// Reads the users (the entries with a person class) and the groups. The other entries, like the
+/// organizational units, are skipped.
+pub fn read_ldif(input: &str, mapping: &Mapping) -> Result<ImportData> {
+    let mut data = ImportData::default();
+    for entry in parse_ldif(input)? {
+        let result = if has_object_class(
+            &entry,
+            &["groupOfNames", "groupOfUniqueNames", "posixGroup"],
+        ) {
+            read_ldif_group(&entry).map(|group| data.groups.push(group))
+        } else if has_object_class(
+            &entry,
+            &[
+                "person",
+                "organizationalPerson",
+                "inetOrgPerson",
+                "posixAccount",
+            ],
+        ) {
+            read_ldif_user(&entry, mapping).map(|user| data.users.push(user))
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            data.errors.push(EntryError {
+                entry: entry.dn.clone(),
+                error: e.to_string(),
+            });
+        }
+    }
+    Ok(data)
+}
+
+/// Reads the users, one per line, with a header line naming the columns.
+pub fn read_csv(input: &str, mapping: &Mapping) -> Result<ImportData> {
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    let headers = reader
+        .headers()
+        .context("Could not read the CSV header")?
+        .clone();
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let user_id_column = column(&mapping.user_id)
+        .ok_or_else(|| anyhow!("No `{}` column in the CSV file", mapping.user_id))?;
+    let email_column = column(&mapping.email)
+        .ok_or_else(|| anyhow!("No `{}` column in the CSV file", mapping.email))?;
+    let display_name_column = column(&mapping.display_name);
+    let first_name_column = column(&mapping.first_name);
+    let last_name_column = column(&mapping.last_name);
+    let avatar_column = column(&mapping.avatar);
+    let password_hash_column = column(&mapping.password_hash);
+    let groups_column = column(&mapping.groups);
+    let mut data = ImportData::default();
+    for (index, record) in reader.records().enumerate() {
+        // The header is line 1.
+        let line = format!("line {}", index + 2);
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                data.errors.push(EntryError {
+                    entry: line,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let get = |column: Option<usize>| {
+            non_empty(
+                column
+                    .and_then(|c| record.get(c))
+                    .map(|v| v.trim().to_string()),
+            )
+        };
+        let (user_id, email) = match (get(Some(user_id_column)), get(Some(email_column))) {
+            (Some(user_id), Some(email)) => (user_id, email),
+            (None, _) => {
+                data.errors.push(EntryError {
+                    entry: line,
+                    error: format!("Missing `{}`", mapping.user_id),
+                });
+                continue;
+            }
+            (Some(user_id), None) => {
+                data.errors.push(EntryError {
+                    entry: user_id,
+                    error: format!("Missing `{}`", mapping.email),
+                });
+                continue;
+            }
+        };
+        let avatar = match get(avatar_column).map(base64::decode).transpose() {
+            Ok(avatar) => avatar,
+            Err(e) => {
+                data.errors.push(EntryError {
+                    entry: user_id,
+                    error: format!("Invalid base64 avatar: {}", e),
+                });
+                continue;
+            }
+        };
+        data.users.push(ImportedUser {
+            user_id,
+            email,
+            display_name: get(display_name_column),
+            first_name: get(first_name_column),
+            last_name: get(last_name_column),
+            avatar,
+            password_hash: get(password_hash_column),
+            groups: get(groups_column)
+                .map(|groups| {
+                    groups
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|g| !g.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        });
+    }
+    Ok(data)
+}
+
+/// Creates the user, or updates it if it exists. Returns whether it was created.
+async fn import_user<Backend: BackendHandler>(
+    backend_handler: &Backend,
+    user: &ImportedUser,
+) -> Result<bool> {
+    let created = match backend_handler.get_user_details(&user.user_id).await {
+        Ok(_) => false,
+        Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)) => {
+            backend_handler
+                .create_user(CreateUserRequest {
+                    user_id: user.user_id.clone(),
+                    email: user.email.clone(),
+                    display_name: user.display_name.clone(),
+                    first_name: user.first_name.clone(),
+                    last_name: user.last_name.clone(),
+                })
+                .await?;
+            // The hash is only imported once: the user may have changed their password since.
+            if let Some(hash) = &user.password_hash {
+                backend_handler
+                    .import_password_hash(&user.user_id, hash)
+                    .await?;
+            }
+            true
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let update = UpdateUserRequest {
+        user_id: user.user_id.clone(),
+        avatar: user.avatar.clone(),
+        ..Default::default()
+    };
+    let update = if created {
+        update
+    } else {
+        UpdateUserRequest {
+            email: Some(user.email.clone()),
+            display_name: user.display_name.clone(),
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            ..update
+        }
+    };
+    if update
+        != (UpdateUserRequest {
+            user_id: user.user_id.clone(),
+            ..Default::default()
+        })
+    {
+        backend_handler.update_user(update).await?;
+    }
+    Ok(created)
+}
+
+/// Creates the missing users, groups and memberships, and updates the existing users.
+pub async fn import<Backend: BackendHandler>(
+    backend_handler: &Backend,
+    data: ImportData,
+) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        errors: data.errors,
+        ..Default::default()
+    };
+    let mut groups: HashMap<String, (GroupId, HashSet<String>)> = backend_handler
+        .list_groups()
+        .await?
+        .into_iter()
+        .map(|g| (g.display_name, (g.id, g.users.into_iter().collect())))
+        .collect();
+    let mut memberships: Vec<(String, String)> = Vec::new();
+    for user in &data.users {
+        match import_user(backend_handler, user).await {
+            Ok(true) => report.users_created += 1,
+            Ok(false) => report.users_updated += 1,
+            Err(e) => {
+                report.errors.push(EntryError {
+                    entry: user.user_id.clone(),
+                    error: format!("{:#}", e),
+                });
+                continue;
+            }
+        }
+        memberships.extend(
+            user.groups
+                .iter()
+                .map(|group| (group.clone(), user.user_id.clone())),
+        );
+    }
+    for group in &data.groups {
+        memberships.extend(
+            group
+                .members
+                .iter()
+                .map(|member| (group.name.clone(), member.clone())),
+        );
+    }
+    // The groups of the users are created as well, e.g. for the CSV files.
+    let group_names = data
+        .groups
+        .iter()
+        .map(|group| &group.name)
+        .chain(memberships.iter().map(|(group, _)| group));
+    let mut failed_groups = HashSet::new();
+    for name in group_names {
+        if groups.contains_key(name) || failed_groups.contains(name) {
+            continue;
+        }
+        match backend_handler.create_group(name).await {
+            Ok(id) => {
+                report.groups_created += 1;
+                groups.insert(name.clone(), (id, HashSet::new()));
+            }
+            Err(e) => {
+                report.errors.push(EntryError {
+                    entry: name.clone(),
+                    error: e.to_string(),
+                });
+                failed_groups.insert(name.clone());
+            }
+        }
+    }
+    for (group_name, user_id) in memberships {
+        // The errors of the groups that couldn't be created are already reported.
+        let (group_id, members) = match groups.get_mut(&group_name) {
+            Some(group) => group,
+            None => continue,
+        };
+        if members.contains(&user_id) {
+            continue;
+        }
+        let entry = format!("{} in {}", user_id, group_name);
+        match backend_handler.add_user_to_group(&user_id, *group_id).await {
+            Ok(()) => {
+                members.insert(user_id);
+                report.memberships_added += 1;
+            }
+            Err(e) => report.errors.push(EntryError {
+                entry,
+                error: e.to_string(),
+            }),
+        }
+    }
+    Ok(report)
+}
+
+async fn run_import(opts: ImportOpts) -> Result<CommandOutput> {
+    let config = configuration::init(RunOpts {
+        config_file: opts.config_file,
+        ldap_port: None,
+        ldaps_port: None,
+        verbose: false,
+    })?;
+    crate::infra::logging::init(config.clone())?;
+    let mut mapping = Mapping::for_format(opts.format);
+    for m in &opts.mappings {
+        mapping.set(m)?;
+    }
+    let input = encryption::read_file(
+        std::path::Path::new(&opts.input_file),
+        config.backup_encryption_key.as_deref(),
+    )?;
+    let input =
+        String::from_utf8(input).context(format!("'{}' is not valid UTF-8", opts.input_file))?;
+    let data = match opts.format {
+        ImportFormat::Ldif => read_ldif(&input, &mapping)?,
+        ImportFormat::Csv => read_csv(&input, &mapping)?,
+    };
+    let pool = PoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .context("Could not connect to the database")?;
+    let backend_handler = SqlBackendHandler::new(config, pool);
+    let report = import(&backend_handler, data).await?;
+    let summary = format!(
+        "Users created: {}, updated: {}. Groups created: {}. Memberships added: {}.",
+        report.users_created, report.users_updated, report.groups_created, report.memberships_added
+    );
+    if !report.errors.is_empty() {
+        let errors: Vec<String> = report
+            .errors
+            .iter()
+            .map(|e| format!("  {}: {}", e.entry, e.error))
+            .collect();
+        bail!(
+            "{} entries could not be imported:\n{}\n{}",
+            report.errors.len(),
+            errors.join("\n"),
+            summary
+        );
+    }
+    let mut result = CommandOutput::default();
+    result.text = Some(summary);
+    result
+        .data
+        .insert("users_created".to_string(), report.users_created.into());
+    result
+        .data
+        .insert("users_updated".to_string(), report.users_updated.into());
+    result
+        .data
+        .insert("groups_created".to_string(), report.groups_created.into());
+    result.data.insert(
+        "memberships_added".to_string(),
+        report.memberships_added.into(),
+    );
+    Ok(result)
+}
+
+pub fn import_command(opts: ImportOpts) -> Result<CommandOutput> {
+    actix_rt::System::new().block_on(run_import(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::handler::{Group, MockTestBackendHandler, User};
+    use mockall::predicate::eq;
+
+    #[test]
+    fn test_read_ldif() {
+        let input = r#"version: 1
+
+dn: ou=people,dc=example,dc=com
+objectClass: organizationalUnit
+ou: people
+
+dn: uid=bob,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+uid: bob
+mail: bob@bob.bob
+cn: Bob Bobbers
+givenName: Bob
+userPassword: {CRYPT}$6$salt$hash
+
+dn: uid=john,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+uid: john
+
+dn: cn=admins,ou=groups,dc=example,dc=com
+objectClass: groupOfNames
+cn: admins
+member: uid=bob,ou=people,dc=example,dc=com
+"#;
+        let data = read_ldif(input, &Mapping::for_format(ImportFormat::Ldif)).unwrap();
+        assert_eq!(
+            data.users,
+            vec![ImportedUser {
+                user_id: "bob".to_string(),
+                email: "bob@bob.bob".to_string(),
+                display_name: Some("Bob Bobbers".to_string()),
+                first_name: Some("Bob".to_string()),
+                password_hash: Some("{CRYPT}$6$salt$hash".to_string()),
+                ..Default::default()
+            }]
+        );
+        assert_eq!(
+            data.groups,
+            vec![ImportedGroup {
+                name: "admins".to_string(),
+                members: vec!["bob".to_string()],
+            }]
+        );
+        assert_eq!(
+            data.errors,
+            vec![EntryError {
+                entry: "uid=john,ou=people,dc=example,dc=com".to_string(),
+                error: "Missing `mail`".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_csv_with_mapping() {
+        let input = "login,Email,groups\nbob,bob@bob.bob,admins; devs\n,nobody@bob.bob,\n";
+        let mut mapping = Mapping::for_format(ImportFormat::Csv);
+        mapping.set("user_id=login").unwrap();
+        mapping.set("unknown=column").unwrap_err();
+        let data = read_csv(input, &mapping).unwrap();
+        assert_eq!(
+            data.users,
+            vec![ImportedUser {
+                user_id: "bob".to_string(),
+                email: "bob@bob.bob".to_string(),
+                groups: vec!["admins".to_string(), "devs".to_string()],
+                ..Default::default()
+            }]
+        );
+        assert_eq!(data.errors.len(), 1);
+        assert_eq!(data.errors[0].entry, "line 3");
+    }
+
+    #[actix_rt::test]
+    async fn test_import() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().return_once(|| {
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "admins".to_string(),
+                description: None,
+                email: None,
+                users: vec!["bob".to_string()],
+            }])
+        });
+        mock.expect_get_user_details()
+            .with(eq("bob"))
+            .return_once(|_| Ok(User::default()));
+        mock.expect_update_user()
+            .with(eq(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                email: Some("bob@bob.bob".to_string()),
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        mock.expect_get_user_details()
+            .with(eq("john"))
+            .return_once(|_| Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)));
+        mock.expect_create_user()
+            .with(eq(CreateUserRequest {
+                user_id: "john".to_string(),
+                email: "john@bob.bob".to_string(),
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        mock.expect_create_group()
+            .with(eq("devs"))
+            .times(1)
+            .return_once(|_| Ok(GroupId(2)));
+        mock.expect_add_user_to_group()
+            .with(eq("john"), eq(GroupId(2)))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_add_user_to_group()
+            .with(eq("ghost"), eq(GroupId(2)))
+            .times(1)
+            .return_once(|_, _| Err(DomainError::InternalError("no such user".to_string())));
+        let report = import(
+            &mock,
+            ImportData {
+                users: vec![
+                    ImportedUser {
+                        user_id: "bob".to_string(),
+                        email: "bob@bob.bob".to_string(),
+                        groups: vec!["admins".to_string()],
+                        ..Default::default()
+                    },
+                    ImportedUser {
+                        user_id: "john".to_string(),
+                        email: "john@bob.bob".to_string(),
+                        groups: vec!["devs".to_string()],
+                        ..Default::default()
+                    },
+                ],
+                groups: vec![ImportedGroup {
+                    name: "devs".to_string(),
+                    members: vec!["john".to_string(), "ghost".to_string()],
+                }],
+                errors: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.users_created, 1);
+        assert_eq!(report.users_updated, 1);
+        assert_eq!(report.groups_created, 1);
+        assert_eq!(report.memberships_added, 1);
+        assert_eq!(
+            report.errors,
+            vec![EntryError {
+                entry: "ghost in devs".to_string(),
+                error: "Internal error: `no such user`".to_string(),
+            }]
+        );
+    }
+}
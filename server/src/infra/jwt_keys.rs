@@ -0,0 +1,574 @@
+//! The keys signing the JWTs. The configured `jwt_secret` signs them until the first rotation;
+//! the next keys are random, and stored in the database. After a rotation, the previous keys
+//! still validate the JWTs they signed until these expire, so nobody gets logged out.
+//!
+//! With `jwt_algorithm = "RS256"` or `"EdDSA"`, the JWTs are signed with an external RSA or
+//! Ed25519 keypair instead, so that other services can validate them with the public key only.
+//! It is not rotated.
+
+use crate::infra::{
+    configuration::Configuration,
+    jwt_sql_tables::{DbQueryBuilder, JwtSigningKeys, Pool},
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, NewMac};
+use jwt::{
+    algorithm::openssl::PKeyWithDigest, AlgorithmType, SignWithKey, SigningAlgorithm,
+    VerifyWithKey, VerifyingAlgorithm,
+};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{Id, PKey, Private, Public},
+    sign::{Signer, Verifier},
+};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sea_query::{Expr, Iden, Order, Query};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha512;
+use sqlx::Row;
+use std::sync::{Arc, RwLock};
+
+struct JwtKey {
+    /// `None` for the configured secret, which signs without key ID.
+    id: Option<String>,
+    key: Hmac<Sha512>,
+    /// `None` for the configured secret.
+    created_at: Option<DateTime<Utc>>,
+    /// When the next key replaced it for signing.
+    retired_at: Option<DateTime<Utc>>,
+}
+
+/// An external RSA keypair, loaded from `jwt_private_key_file`.
+pub struct RsaKeys {
+    private: PKeyWithDigest<Private>,
+    public: PKeyWithDigest<Public>,
+}
+
+impl RsaKeys {
+    fn from_pem(pem: &[u8]) -> Result<Self> {
+        let private = PKey::private_key_from_pem(pem)?;
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem()?)?;
+        if private.rsa().is_err() {
+            bail!("The JWT private key is not an RSA key");
+        }
+        Ok(Self {
+            private: PKeyWithDigest {
+                digest: MessageDigest::sha256(),
+                key: private,
+            },
+            public: PKeyWithDigest {
+                digest: MessageDigest::sha256(),
+                key: public,
+            },
+        })
+    }
+}
+
+/// An external Ed25519 keypair, loaded from `jwt_private_key_file`. The `jwt` crate doesn't
+/// know EdDSA, so these JWTs are signed and checked by hand.
+pub struct Ed25519Keys {
+    private: PKey<Private>,
+    public: PKey<Public>,
+}
+
+/// The `alg` header of the JWTs signed with Ed25519.
+const EDDSA: &str = "EdDSA";
+
+fn encode_base64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+impl Ed25519Keys {
+    fn from_pem(pem: &[u8]) -> Result<Self> {
+        let private = PKey::private_key_from_pem(pem)?;
+        if private.id() != Id::ED25519 {
+            bail!("The JWT private key is not an Ed25519 key");
+        }
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem()?)?;
+        Ok(Self { private, public })
+    }
+
+    fn sign<C: Serialize>(&self, claims: &C) -> Result<String> {
+        let header = serde_json::json!({ "alg": EDDSA, "typ": "JWT" });
+        let message = format!(
+            "{}.{}",
+            encode_base64(&serde_json::to_vec(&header)?),
+            encode_base64(&serde_json::to_vec(claims)?)
+        );
+        let signature =
+            Signer::new_without_digest(&self.private)?.sign_oneshot_to_vec(message.as_bytes())?;
+        Ok(format!("{}.{}", message, encode_base64(&signature)))
+    }
+
+    fn verify<C: DeserializeOwned>(&self, token_str: &str) -> std::result::Result<C, String> {
+        let decode = |part: &str| {
+            base64::decode_config(part, base64::URL_SAFE_NO_PAD).map_err(|_| "Invalid JWT")
+        };
+        let (message, signature) = token_str.rsplit_once('.').ok_or("Invalid JWT")?;
+        let (header, claims) = message.split_once('.').ok_or("Invalid JWT")?;
+        let header: serde_json::Value =
+            serde_json::from_slice(&decode(header)?).map_err(|_| "Invalid JWT")?;
+        if header["alg"] != EDDSA {
+            return Err(format!(
+                "Unsupported JWT algorithm: {}. Supported ones are: [\"{}\"]",
+                header["alg"], EDDSA
+            ));
+        }
+        if !header["kid"].is_null() {
+            return Err("JWT signed with an unknown or retired key".to_string());
+        }
+        let signature = decode(signature)?;
+        let is_valid = Verifier::new_without_digest(&self.public)
+            .and_then(|mut v| v.verify_oneshot(&signature, message.as_bytes()))
+            .map_err(|_| "Invalid JWT")?;
+        if !is_valid {
+            return Err("Invalid JWT".to_string());
+        }
+        serde_json::from_slice(&decode(claims)?).map_err(|_| "Invalid JWT".to_string())
+    }
+}
+
+/// The key signing the new JWTs.
+#[derive(Clone)]
+pub enum SigningKey {
+    Hmac(Hmac<Sha512>),
+    Rsa(Arc<RsaKeys>),
+}
+
+impl SigningAlgorithm for SigningKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            SigningKey::Hmac(key) => SigningAlgorithm::algorithm_type(key),
+            SigningKey::Rsa(keys) => SigningAlgorithm::algorithm_type(&keys.private),
+        }
+    }
+
+    fn sign(&self, header: &str, claims: &str) -> Result<String, jwt::Error> {
+        match self {
+            SigningKey::Hmac(key) => key.sign(header, claims),
+            SigningKey::Rsa(keys) => keys.private.sign(header, claims),
+        }
+    }
+}
+
+/// A key validating the JWTs.
+#[derive(Clone)]
+pub enum VerifyingKey {
+    Hmac(Hmac<Sha512>),
+    Rsa(Arc<RsaKeys>),
+}
+
+impl VerifyingAlgorithm for VerifyingKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            VerifyingKey::Hmac(key) => VerifyingAlgorithm::algorithm_type(key),
+            VerifyingKey::Rsa(keys) => VerifyingAlgorithm::algorithm_type(&keys.public),
+        }
+    }
+
+    fn verify_bytes(
+        &self,
+        header: &str,
+        claims: &str,
+        signature: &[u8],
+    ) -> Result<bool, jwt::Error> {
+        match self {
+            VerifyingKey::Hmac(key) => key.verify_bytes(header, claims, signature),
+            VerifyingKey::Rsa(keys) => keys.public.verify_bytes(header, claims, signature),
+        }
+    }
+}
+
+/// The signing keys, shared by the HTTP server and the job rotating them.
+#[derive(Clone)]
+pub struct JwtKeyStore {
+    sql_pool: Pool,
+    config_secret: String,
+    /// How long the retired keys keep validating: the lifetime of the JWTs they signed.
+    grace_period: chrono::Duration,
+    /// 0 disables the scheduled rotation.
+    rotation_days: i64,
+    /// Sorted by creation date, the last one signs.
+    keys: Arc<RwLock<Vec<JwtKey>>>,
+    /// Replaces the HMAC keys when set.
+    rsa_keys: Option<Arc<RsaKeys>>,
+    /// Replaces the HMAC keys when set.
+    ed25519_keys: Option<Arc<Ed25519Keys>>,
+}
+
+fn make_key(secret: &str) -> Hmac<Sha512> {
+    Hmac::new_varkey(secret.as_bytes()).unwrap()
+}
+
+fn random_string(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+impl JwtKeyStore {
+    /// This needs the JWT tables to be initialized.
+    pub async fn load(config: &Configuration, sql_pool: Pool) -> Result<Self> {
+        let read_private_key = |path: &String| {
+            std::fs::read(path).context(format!("Could not read the JWT private key `{}`", path))
+        };
+        let load_error = |path: &String| format!("Could not load the JWT private key `{}`", path);
+        let (rsa_keys, ed25519_keys) = match &config.jwt_private_key_file {
+            Some(path) if config.jwt_algorithm == "RS256" => (
+                Some(Arc::new(
+                    RsaKeys::from_pem(&read_private_key(path)?).context(load_error(path))?,
+                )),
+                None,
+            ),
+            Some(path) if config.jwt_algorithm == EDDSA => (
+                None,
+                Some(Arc::new(
+                    Ed25519Keys::from_pem(&read_private_key(path)?).context(load_error(path))?,
+                )),
+            ),
+            _ => (None, None),
+        };
+        let store = Self {
+            sql_pool,
+            config_secret: config.jwt_secret.clone(),
+            grace_period: chrono::Duration::minutes(config.jwt_duration_minutes),
+            rotation_days: config.jwt_key_rotation_days,
+            keys: Arc::new(RwLock::new(Vec::new())),
+            rsa_keys,
+            ed25519_keys,
+        };
+        store
+            .reload()
+            .await
+            .context("Could not load the JWT signing keys")?;
+        Ok(store)
+    }
+
+    async fn reload(&self) -> sqlx::Result<()> {
+        let query = Query::select()
+            .column(JwtSigningKeys::KeyId)
+            .column(JwtSigningKeys::Secret)
+            .column(JwtSigningKeys::CreationDate)
+            .from(JwtSigningKeys::Table)
+            .order_by(JwtSigningKeys::CreationDate, Order::Asc)
+            .to_string(DbQueryBuilder {});
+        let rows = sqlx::query(&query).fetch_all(&self.sql_pool).await?;
+        let mut keys = vec![JwtKey {
+            id: None,
+            key: make_key(&self.config_secret),
+            created_at: None,
+            retired_at: None,
+        }];
+        for row in rows {
+            let created_at =
+                row.get::<DateTime<Utc>, _>(&*JwtSigningKeys::CreationDate.to_string());
+            keys.last_mut().unwrap().retired_at = Some(created_at);
+            keys.push(JwtKey {
+                id: Some(row.get::<String, _>(&*JwtSigningKeys::KeyId.to_string())),
+                key: make_key(&row.get::<String, _>(&*JwtSigningKeys::Secret.to_string())),
+                created_at: Some(created_at),
+                retired_at: None,
+            });
+        }
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Signs the claims with the current key, as a JWT.
+    pub fn sign<C: Serialize>(&self, claims: &C) -> String {
+        if let Some(ed25519_keys) = &self.ed25519_keys {
+            return ed25519_keys.sign(claims).unwrap();
+        }
+        let (key_id, key) = self.signing_key();
+        let header = jwt::Header {
+            algorithm: key.algorithm_type(),
+            key_id,
+            ..Default::default()
+        };
+        jwt::Token::new(header, claims)
+            .sign_with_key(&key)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    /// Checks the signature of a token signed with one of the keys, and returns its claims.
+    pub fn verify<C: DeserializeOwned + Clone>(
+        &self,
+        token_str: &str,
+    ) -> std::result::Result<C, String> {
+        if let Some(ed25519_keys) = &self.ed25519_keys {
+            return ed25519_keys.verify(token_str);
+        }
+        let header =
+            jwt::Token::<jwt::Header, C, jwt::token::Unverified>::parse_unverified(token_str)
+                .map_err(|_| "Invalid JWT")?
+                .header()
+                .clone();
+        let key = self
+            .verification_key(header.key_id.as_deref())
+            .ok_or("JWT signed with an unknown or retired key")?;
+        if header.algorithm != key.algorithm_type() {
+            return Err(format!(
+                "Unsupported JWT algorithm: '{:?}'. Supported ones are: ['{:?}']",
+                header.algorithm,
+                key.algorithm_type()
+            ));
+        }
+        let token: jwt::Token<jwt::Header, C, _> =
+            VerifyWithKey::verify_with_key(token_str, &key).map_err(|_| "Invalid JWT")?;
+        Ok(token.claims().clone())
+    }
+
+    /// The key to sign the new JWTs with, and its ID.
+    fn signing_key(&self) -> (Option<String>, SigningKey) {
+        if let Some(rsa_keys) = &self.rsa_keys {
+            return (None, SigningKey::Rsa(rsa_keys.clone()));
+        }
+        let keys = self.keys.read().unwrap();
+        let key = keys.last().unwrap();
+        (key.id.clone(), SigningKey::Hmac(key.key.clone()))
+    }
+
+    /// The `alg` of the JWTs that can be checked with the public key.
+    pub fn public_algorithm(&self) -> Option<&'static str> {
+        if self.ed25519_keys.is_some() {
+            Some(EDDSA)
+        } else if self.rsa_keys.is_some() {
+            Some("RS256")
+        } else {
+            None
+        }
+    }
+
+    /// The public key as a JSON Web Key Set, for the OpenID Connect clients to check the ID
+    /// tokens. Only with RS256 or EdDSA: the HMAC keys are secret.
+    pub fn public_jwks(&self) -> Option<serde_json::Value> {
+        if let Some(ed25519_keys) = &self.ed25519_keys {
+            return Some(serde_json::json!({
+                "keys": [{
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "use": "sig",
+                    "alg": EDDSA,
+                    "x": encode_base64(&ed25519_keys.public.raw_public_key().ok()?),
+                }]
+            }));
+        }
+        let rsa = self.rsa_keys.as_ref()?.public.key.rsa().ok()?;
+        let encode =
+            |n: &openssl::bn::BigNumRef| base64::encode_config(n.to_vec(), base64::URL_SAFE_NO_PAD);
+        Some(serde_json::json!({
+            "keys": [{
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "n": encode(rsa.n()),
+                "e": encode(rsa.e()),
+            }]
+        }))
+    }
+
+    /// The key that signed a JWT, from its `kid` header, unless it was retired for too long.
+    fn verification_key(&self, key_id: Option<&str>) -> Option<VerifyingKey> {
+        if let Some(rsa_keys) = &self.rsa_keys {
+            return match key_id {
+                None => Some(VerifyingKey::Rsa(rsa_keys.clone())),
+                Some(_) => None,
+            };
+        }
+        let now = Utc::now();
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|k| k.id.as_deref() == key_id)
+            .filter(|k| match k.retired_at {
+                Some(retired_at) => retired_at + self.grace_period > now,
+                None => true,
+            })
+            .map(|k| VerifyingKey::Hmac(k.key.clone()))
+    }
+
+    /// Replaces the signing key with a new random one, and deletes the keys that can't validate
+    /// anything anymore. Returns the ID of the new key.
+    pub async fn rotate(&self) -> Result<String> {
+        if self.rsa_keys.is_some() || self.ed25519_keys.is_some() {
+            bail!("The keypair signing the JWTs is external, it can't be rotated");
+        }
+        let key_id = random_string(16);
+        let now = Utc::now();
+        let query = Query::insert()
+            .into_table(JwtSigningKeys::Table)
+            .columns(vec![
+                JwtSigningKeys::KeyId,
+                JwtSigningKeys::Secret,
+                JwtSigningKeys::CreationDate,
+            ])
+            .values_panic(vec![
+                key_id.as_str().into(),
+                random_string(64).into(),
+                now.naive_utc().into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&self.sql_pool).await?;
+        let expired_keys = self
+            .keys
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|k| match k.retired_at {
+                Some(retired_at) => retired_at + self.grace_period <= now,
+                None => false,
+            })
+            .filter_map(|k| k.id.clone())
+            .collect::<Vec<_>>();
+        if !expired_keys.is_empty() {
+            let query = Query::delete()
+                .from_table(JwtSigningKeys::Table)
+                .and_where(Expr::col(JwtSigningKeys::KeyId).is_in(expired_keys))
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(&self.sql_pool).await?;
+        }
+        self.reload().await?;
+        log::info!("Rotated the JWT signing key, new key: {}", key_id);
+        Ok(key_id)
+    }
+
+    /// Rotates the key if it's older than `jwt_key_rotation_days`. The configured secret has no
+    /// age: it is replaced at the first check.
+    pub async fn rotate_if_due(&self) -> Result<()> {
+        if self.rotation_days == 0 {
+            return Ok(());
+        }
+        let created_at = self.keys.read().unwrap().last().unwrap().created_at;
+        let is_due = match created_at {
+            Some(created_at) => {
+                created_at + chrono::Duration::days(self.rotation_days) <= Utc::now()
+            }
+            None => true,
+        };
+        if is_due {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_tables::{get_test_pool, init_table};
+    use crate::infra::configuration::ConfigurationBuilder;
+
+    async fn get_store(config: Configuration) -> JwtKeyStore {
+        let sql_pool = get_test_pool().await;
+        init_table(&sql_pool).await.unwrap();
+        crate::infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .unwrap();
+        JwtKeyStore::load(&config, sql_pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let store = get_store(ConfigurationBuilder::default().build().unwrap()).await;
+        assert_eq!(store.signing_key().0, None);
+        assert!(store.verification_key(None).is_some());
+        assert!(store.verification_key(Some("unknown")).is_none());
+
+        let first_key = store.rotate().await.unwrap();
+        assert_eq!(store.signing_key().0.as_deref(), Some(first_key.as_str()));
+        // The configured secret still validates during the grace period.
+        assert!(store.verification_key(None).is_some());
+        assert!(store.verification_key(Some(&first_key)).is_some());
+
+        let second_key = store.rotate().await.unwrap();
+        assert_ne!(first_key, second_key);
+        assert_eq!(store.signing_key().0.as_deref(), Some(second_key.as_str()));
+        assert!(store.verification_key(Some(&first_key)).is_some());
+        assert_eq!(store.keys.read().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_if_due() {
+        let store = get_store(ConfigurationBuilder::default().build().unwrap()).await;
+        store.rotate_if_due().await.unwrap();
+        assert_eq!(store.signing_key().0, None);
+
+        let store = get_store(
+            ConfigurationBuilder::default()
+                .jwt_key_rotation_days(30)
+                .build()
+                .unwrap(),
+        )
+        .await;
+        store.rotate_if_due().await.unwrap();
+        let key = store.signing_key().0.unwrap();
+        // The new key is not due yet.
+        store.rotate_if_due().await.unwrap();
+        assert_eq!(store.signing_key().0, Some(key));
+    }
+
+    #[test]
+    fn test_rsa_keys() {
+        let pem = openssl::rsa::Rsa::generate(2048)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        let keys = Arc::new(RsaKeys::from_pem(&pem).unwrap());
+        let signing_key = SigningKey::Rsa(keys.clone());
+        assert_eq!(
+            SigningAlgorithm::algorithm_type(&signing_key),
+            AlgorithmType::Rs256
+        );
+        let signature = signing_key.sign("header", "claims").unwrap();
+        let verifying_key = VerifyingKey::Rsa(keys);
+        assert!(verifying_key
+            .verify("header", "claims", &signature)
+            .unwrap());
+        assert!(!verifying_key
+            .verify("header", "other claims", &signature)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_ed25519_keys() {
+        let pem = PKey::generate_ed25519()
+            .unwrap()
+            .private_key_to_pem_pkcs8()
+            .unwrap();
+        let keys = Ed25519Keys::from_pem(&pem).unwrap();
+        let claims = serde_json::json!({"user": "bob"});
+        let token = keys.sign(&claims).unwrap();
+        assert_eq!(keys.verify::<serde_json::Value>(&token).unwrap(), claims);
+
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let (header, _) = message.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}.{}",
+            header,
+            encode_base64(br#"{"user":"admin"}"#),
+            signature
+        );
+        assert!(keys.verify::<serde_json::Value>(&forged).is_err());
+
+        let other_keys = Ed25519Keys::from_pem(
+            &PKey::generate_ed25519()
+                .unwrap()
+                .private_key_to_pem_pkcs8()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(other_keys.verify::<serde_json::Value>(&token).is_err());
+
+        let rsa_pem = openssl::rsa::Rsa::generate(2048)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        assert!(Ed25519Keys::from_pem(&rsa_pem).is_err());
+    }
+}
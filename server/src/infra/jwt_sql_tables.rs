@@ -9,6 +9,8 @@ pub enum JwtRefreshStorage {
     RefreshTokenHash,
     UserId,
     ExpiryDate,
+    /// The `SessionGeneration` of the user when the token was created.
+    SessionGeneration,
 }
 
 /// Contains the blacklisted JWT that haven't expired yet.
@@ -21,6 +23,17 @@ pub enum JwtStorage {
     Blacklisted,
 }
 
+/// The keys signing the JWTs, once the configured `jwt_secret` was rotated. The newest one signs
+/// the new JWTs, the others only validate the JWTs they signed.
+#[derive(Iden)]
+pub enum JwtSigningKeys {
+    Table,
+    /// Sent in the `kid` header of the JWTs.
+    KeyId,
+    Secret,
+    CreationDate,
+}
+
 /// This needs to be initialized after the domain tables are.
 pub async fn init_table(pool: &Pool) -> sqlx::Result<()> {
     sqlx::query(
@@ -43,6 +56,12 @@ pub async fn init_table(pool: &Pool) -> sqlx::Result<()> {
                     .date_time()
                     .not_null(),
             )
+            .col(
+                ColumnDef::new(JwtRefreshStorage::SessionGeneration)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
             .foreign_key(
                 ForeignKey::create()
                     .name("JwtRefreshStorageUserForeignKey")
@@ -55,6 +74,21 @@ pub async fn init_table(pool: &Pool) -> sqlx::Result<()> {
     )
     .execute(pool)
     .await?;
+    // Databases created before the sessions were invalidated on password change don't have the
+    // column yet. If it already exists, this fails and is ignored.
+    let _ = sqlx::query(
+        &Table::alter()
+            .table(JwtRefreshStorage::Table)
+            .add_column(
+                ColumnDef::new(JwtRefreshStorage::SessionGeneration)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .to_string(DbQueryBuilder {}),
+    )
+    .execute(pool)
+    .await;
 
     sqlx::query(
         &Table::create()
@@ -95,5 +129,30 @@ pub async fn init_table(pool: &Pool) -> sqlx::Result<()> {
     .execute(pool)
     .await?;
 
+    sqlx::query(
+        &Table::create()
+            .table(JwtSigningKeys::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(JwtSigningKeys::KeyId)
+                    .string_len(32)
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(
+                ColumnDef::new(JwtSigningKeys::Secret)
+                    .string_len(255)
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(JwtSigningKeys::CreationDate)
+                    .date_time()
+                    .not_null(),
+            )
+            .to_string(DbQueryBuilder {}),
+    )
+    .execute(pool)
+    .await?;
+
     Ok(())
 }
@@ -0,0 +1,569 @@
+//! Support for the LDAP features that `ldap3_server` doesn't know about.
+//!
+//! The codec wraps `LdapCodec`:
+//!   - the controls handled here are extracted from the raw messages before they are decoded, and
+//!     appended to the responses after they are encoded.
+//!   - the values of binary attributes (e.g. `jpegPhoto`) can't be represented in a
+//!     `LdapPartialAttribute`, so they are given base64-encoded, and decoded when the response is
+//!     encoded.
+
+use anyhow::{bail, Result};
+use bytes::BytesMut;
+use ldap3_server::{
+    proto::{LdapMsg, LdapOp, LdapSearchResultEntry},
+    LdapCodec,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::domain::handler::ChangeType;
+
+pub const PERSISTENT_SEARCH_OID: &str = "2.16.840.1.113730.3.4.3";
+pub const ENTRY_CHANGE_NOTIFICATION_OID: &str = "2.16.840.1.113730.3.4.7";
+pub const PASSWORD_POLICY_OID: &str = "1.3.6.1.4.1.42.2.27.8.5.1";
+
+/// The request controls extracted by the codec, the other ones are left to `LdapCodec`.
+const HANDLED_CONTROLS: [&str; 2] = [PERSISTENT_SEARCH_OID, PASSWORD_POLICY_OID];
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_SEARCH_RESULT_ENTRY: u8 = 0x64;
+const TAG_CONTROLS: u8 = 0xa0;
+const TAG_PASSWORD_POLICY_ERROR: u8 = 0x81;
+
+/// The largest message accepted from a client, to stop it from announcing a huge message and
+/// making the server buffer it. The avatars are well below that.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// The attributes with binary values, given base64-encoded in the search results.
+pub const BINARY_ATTRIBUTES: [&str; 1] = ["jpegPhoto"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawControl {
+    pub oid: String,
+    pub criticality: bool,
+    pub value: Option<Vec<u8>>,
+}
+
+/// The options of a persistent search (draft-ietf-ldapext-psearch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PersistentSearch {
+    /// Bit mask of the `ChangeType`s to report.
+    pub change_types: i32,
+    /// If set, the entries matching the search initially are not returned.
+    pub changes_only: bool,
+    /// Whether to attach an entry change notification control to the changed entries.
+    pub return_entry_change_controls: bool,
+}
+
+impl PersistentSearch {
+    pub fn from_control(control: &RawControl) -> Result<Self> {
+        let value = match &control.value {
+            Some(v) => v,
+            None => bail!("Missing persistent search control value"),
+        };
+        let (tag, content, _) = read_tlv(value)?;
+        if tag != TAG_SEQUENCE {
+            bail!("Invalid persistent search control value");
+        }
+        let (change_types, rest) = read_integer(content, TAG_INTEGER)?;
+        let (changes_only, rest) = read_boolean(rest)?;
+        let (return_entry_change_controls, _) = read_boolean(rest)?;
+        Ok(PersistentSearch {
+            change_types: change_types as i32,
+            changes_only,
+            return_entry_change_controls,
+        })
+    }
+
+    pub fn reports(&self, change_type: ChangeType) -> bool {
+        self.change_types & change_type as i32 != 0
+    }
+}
+
+/// The control attached to the entries returned by a persistent search, describing the change.
+pub fn make_entry_change_notification(
+    change_type: ChangeType,
+    previous_dn: Option<&str>,
+) -> RawControl {
+    let mut content = write_tlv(TAG_ENUMERATED, &[change_type as u8]);
+    if let Some(dn) = previous_dn {
+        content.extend(write_tlv(TAG_OCTET_STRING, dn.as_bytes()));
+    }
+    RawControl {
+        oid: ENTRY_CHANGE_NOTIFICATION_OID.to_string(),
+        criticality: false,
+        value: Some(write_tlv(TAG_SEQUENCE, &content)),
+    }
+}
+
+/// The errors of the password policy response control (draft-behera-ldap-password-policy) that
+/// can happen here, with their value from the draft.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordPolicyError {
+    AccountLocked = 1,
+}
+
+/// The password policy control returned to the clients that sent it with their request. Without
+/// an error, the value is an empty sequence.
+pub fn make_password_policy_response(error: Option<PasswordPolicyError>) -> RawControl {
+    let content = error
+        .map(|e| write_tlv(TAG_PASSWORD_POLICY_ERROR, &[e as u8]))
+        .unwrap_or_default();
+    RawControl {
+        oid: PASSWORD_POLICY_OID.to_string(),
+        criticality: false,
+        value: Some(write_tlv(TAG_SEQUENCE, &content)),
+    }
+}
+
+/// Returns the tag, the content and the rest of the buffer after the first TLV.
+fn read_tlv(buf: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    match read_header(buf)? {
+        Some((tag, header_len, content_len)) if buf.len() >= header_len + content_len => Ok((
+            tag,
+            &buf[header_len..header_len + content_len],
+            &buf[header_len + content_len..],
+        )),
+        _ => bail!("Truncated BER value"),
+    }
+}
+
+/// Returns the tag, header length and content length of the first TLV, if the header is complete.
+fn read_header(buf: &[u8]) -> Result<Option<(u8, usize, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let first_length_byte = buf[1] as usize;
+    if first_length_byte < 0x80 {
+        return Ok(Some((buf[0], 2, first_length_byte)));
+    }
+    let length_bytes = first_length_byte & 0x7f;
+    if length_bytes == 0 || length_bytes > 4 {
+        bail!("Unsupported BER length encoding");
+    }
+    if buf.len() < 2 + length_bytes {
+        return Ok(None);
+    }
+    let length = buf[2..2 + length_bytes]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+    Ok(Some((buf[0], 2 + length_bytes, length)))
+}
+
+fn write_length(length: usize) -> Vec<u8> {
+    if length < 0x80 {
+        return vec![length as u8];
+    }
+    let bytes = (length as u32).to_be_bytes();
+    let significant = bytes
+        .iter()
+        .skip_while(|b| **b == 0)
+        .copied()
+        .collect::<Vec<_>>();
+    let mut result = vec![0x80 | significant.len() as u8];
+    result.extend(significant);
+    result
+}
+
+fn write_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut result = vec![tag];
+    result.extend(write_length(content.len()));
+    result.extend_from_slice(content);
+    result
+}
+
+fn read_integer(buf: &[u8], expected_tag: u8) -> Result<(i64, &[u8])> {
+    let (tag, content, rest) = read_tlv(buf)?;
+    if tag != expected_tag || content.is_empty() || content.len() > 8 {
+        bail!("Invalid BER integer");
+    }
+    // Two's complement, big endian.
+    let initial = if content[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok((
+        content
+            .iter()
+            .fold(initial, |acc, b| (acc << 8) | *b as i64),
+        rest,
+    ))
+}
+
+fn write_integer(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Skip the redundant leading bytes, keeping the sign bit.
+    let mut start = 0;
+    while start < 3
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    write_tlv(TAG_INTEGER, &bytes[start..])
+}
+
+fn read_boolean(buf: &[u8]) -> Result<(bool, &[u8])> {
+    let (tag, content, rest) = read_tlv(buf)?;
+    if tag != TAG_BOOLEAN || content.len() != 1 {
+        bail!("Invalid BER boolean");
+    }
+    Ok((content[0] != 0, rest))
+}
+
+fn read_control(buf: &[u8]) -> Result<(RawControl, &[u8])> {
+    let (tag, content, rest) = read_tlv(buf)?;
+    if tag != TAG_SEQUENCE {
+        bail!("Invalid LDAP control");
+    }
+    let (tag, oid, mut fields) = read_tlv(content)?;
+    if tag != TAG_OCTET_STRING {
+        bail!("Invalid LDAP control type");
+    }
+    let mut control = RawControl {
+        oid: String::from_utf8_lossy(oid).to_string(),
+        criticality: false,
+        value: None,
+    };
+    if fields.first() == Some(&TAG_BOOLEAN) {
+        let (criticality, rest) = read_boolean(fields)?;
+        control.criticality = criticality;
+        fields = rest;
+    }
+    if !fields.is_empty() {
+        let (tag, value, _) = read_tlv(fields)?;
+        if tag != TAG_OCTET_STRING {
+            bail!("Invalid LDAP control value");
+        }
+        control.value = Some(value.to_vec());
+    }
+    Ok((control, rest))
+}
+
+fn write_control(control: &RawControl) -> Vec<u8> {
+    let mut content = write_tlv(TAG_OCTET_STRING, control.oid.as_bytes());
+    if control.criticality {
+        content.extend(write_tlv(TAG_BOOLEAN, &[0xff]));
+    }
+    if let Some(value) = &control.value {
+        content.extend(write_tlv(TAG_OCTET_STRING, value));
+    }
+    write_tlv(TAG_SEQUENCE, &content)
+}
+
+/// Splits the controls of an LDAP message into the ones handled by this module, and the rest of
+/// the message (with the other controls).
+fn extract_controls(message: &[u8]) -> Result<(Vec<RawControl>, Vec<u8>)> {
+    let (tag, content, _) = read_tlv(message)?;
+    if tag != TAG_SEQUENCE {
+        bail!("Invalid LDAP message");
+    }
+    let (_, _, after_message_id) = read_tlv(content)?;
+    let (_, _, after_op) = read_tlv(after_message_id)?;
+    if after_op.is_empty() || after_op[0] != TAG_CONTROLS {
+        return Ok((Vec::new(), message.to_vec()));
+    }
+    let (_, mut controls_buf, _) = read_tlv(after_op)?;
+    let mut extracted = Vec::new();
+    let mut kept = Vec::new();
+    while !controls_buf.is_empty() {
+        let (control, rest) = read_control(controls_buf)?;
+        if HANDLED_CONTROLS.contains(&control.oid.as_str()) {
+            extracted.push(control);
+        } else {
+            kept.extend_from_slice(&controls_buf[..controls_buf.len() - rest.len()]);
+        }
+        controls_buf = rest;
+    }
+    let mut new_content = content[..content.len() - after_op.len()].to_vec();
+    if !kept.is_empty() {
+        new_content.extend(write_tlv(TAG_CONTROLS, &kept));
+    }
+    Ok((extracted, write_tlv(TAG_SEQUENCE, &new_content)))
+}
+
+/// Adds the controls at the end of an encoded LDAP message without controls.
+fn append_controls(message: &[u8], controls: &[RawControl]) -> Result<Vec<u8>> {
+    let (tag, content, _) = read_tlv(message)?;
+    if tag != TAG_SEQUENCE {
+        bail!("Invalid LDAP message");
+    }
+    let mut new_content = content.to_vec();
+    new_content.extend(write_tlv(
+        TAG_CONTROLS,
+        &controls.iter().flat_map(write_control).collect::<Vec<_>>(),
+    ));
+    Ok(write_tlv(TAG_SEQUENCE, &new_content))
+}
+
+fn has_binary_attributes(entry: &LdapSearchResultEntry) -> bool {
+    entry
+        .attributes
+        .iter()
+        .any(|a| BINARY_ATTRIBUTES.contains(&a.atype.as_str()))
+}
+
+/// Encodes a search result entry, with the binary attributes decoded from base64.
+fn encode_search_result_entry(msgid: i32, entry: &LdapSearchResultEntry) -> Vec<u8> {
+    let attributes = entry
+        .attributes
+        .iter()
+        .flat_map(|attribute| {
+            let is_binary = BINARY_ATTRIBUTES.contains(&attribute.atype.as_str());
+            let values = attribute
+                .vals
+                .iter()
+                .flat_map(|value| {
+                    let bytes = if is_binary {
+                        base64::decode(value).unwrap_or_else(|_| value.as_bytes().to_vec())
+                    } else {
+                        value.as_bytes().to_vec()
+                    };
+                    write_tlv(TAG_OCTET_STRING, &bytes)
+                })
+                .collect::<Vec<_>>();
+            write_tlv(
+                TAG_SEQUENCE,
+                &[
+                    write_tlv(TAG_OCTET_STRING, attribute.atype.as_bytes()),
+                    write_tlv(TAG_SET, &values),
+                ]
+                .concat(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let op = write_tlv(
+        TAG_SEARCH_RESULT_ENTRY,
+        &[
+            write_tlv(TAG_OCTET_STRING, entry.dn.as_bytes()),
+            write_tlv(TAG_SEQUENCE, &attributes),
+        ]
+        .concat(),
+    );
+    write_tlv(TAG_SEQUENCE, &[write_integer(msgid), op].concat())
+}
+
+fn to_io_error(e: anyhow::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// An `LdapCodec` that also handles the controls listed in this module.
+pub struct ExtendedLdapCodec;
+
+impl Decoder for ExtendedLdapCodec {
+    type Item = (LdapMsg, Vec<RawControl>);
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let message_len = match read_header(buf).map_err(to_io_error)? {
+            Some((_, _, content_len)) if content_len > MAX_MESSAGE_LEN => {
+                return Err(to_io_error(anyhow::anyhow!(
+                    "LDAP message too large: {} bytes, the maximum is {}",
+                    content_len,
+                    MAX_MESSAGE_LEN
+                )))
+            }
+            Some((_, header_len, content_len)) if buf.len() >= header_len + content_len => {
+                header_len + content_len
+            }
+            // Wait for the rest of the message.
+            _ => return Ok(None),
+        };
+        let (controls, message) = extract_controls(&buf[..message_len]).map_err(to_io_error)?;
+        let _ = buf.split_to(message_len);
+        let mut message = BytesMut::from(message.as_slice());
+        match LdapCodec.decode(&mut message)? {
+            Some(msg) => Ok(Some((msg, controls))),
+            None => Err(to_io_error(anyhow::anyhow!(
+                "Could not decode LDAP message"
+            ))),
+        }
+    }
+}
+
+impl Encoder<(LdapMsg, Vec<RawControl>)> for ExtendedLdapCodec {
+    type Error = std::io::Error;
+
+    fn encode(
+        &mut self,
+        (msg, controls): (LdapMsg, Vec<RawControl>),
+        buf: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        let message = match &msg.op {
+            LdapOp::SearchResultEntry(entry) if has_binary_attributes(entry) => {
+                BytesMut::from(encode_search_result_entry(msg.msgid, entry).as_slice())
+            }
+            _ => {
+                if controls.is_empty() {
+                    return LdapCodec.encode(msg, buf);
+                }
+                let mut message = BytesMut::new();
+                LdapCodec.encode(msg, &mut message)?;
+                message
+            }
+        };
+        if controls.is_empty() {
+            buf.extend_from_slice(&message);
+        } else {
+            buf.extend_from_slice(&append_controls(&message, &controls).map_err(to_io_error)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_persistent_search_control() -> Vec<u8> {
+        let value = write_tlv(
+            TAG_SEQUENCE,
+            &[
+                write_tlv(TAG_INTEGER, &[0x0f]),
+                write_tlv(TAG_BOOLEAN, &[0xff]),
+                write_tlv(TAG_BOOLEAN, &[0x00]),
+            ]
+            .concat(),
+        );
+        write_control(&RawControl {
+            oid: PERSISTENT_SEARCH_OID.to_string(),
+            criticality: true,
+            value: Some(value),
+        })
+    }
+
+    #[test]
+    fn test_extract_persistent_search() {
+        let message_id = write_tlv(TAG_INTEGER, &[0x02]);
+        // An abandon request, for a short operation.
+        let op = write_tlv(0x50, &[0x01]);
+        let other_control = write_control(&RawControl {
+            oid: "1.2.3".to_string(),
+            criticality: false,
+            value: None,
+        });
+        let message = write_tlv(
+            TAG_SEQUENCE,
+            &[
+                message_id.clone(),
+                op.clone(),
+                write_tlv(
+                    TAG_CONTROLS,
+                    &[make_persistent_search_control(), other_control.clone()].concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let (controls, rest) = extract_controls(&message).unwrap();
+        assert_eq!(controls.len(), 1);
+        assert_eq!(
+            PersistentSearch::from_control(&controls[0]).unwrap(),
+            PersistentSearch {
+                change_types: 15,
+                changes_only: true,
+                return_entry_change_controls: false,
+            }
+        );
+        assert_eq!(
+            rest,
+            write_tlv(
+                TAG_SEQUENCE,
+                &[message_id, op, write_tlv(TAG_CONTROLS, &other_control)].concat()
+            )
+        );
+    }
+
+    #[test]
+    fn test_append_controls() {
+        let content = [write_tlv(TAG_INTEGER, &[0x02]), vec![0x42; 200]].concat();
+        let message = write_tlv(TAG_SEQUENCE, &content);
+        let control = make_entry_change_notification(ChangeType::ModifyDn, Some("cn=old"));
+        let with_controls = append_controls(&message, &[control.clone()]).unwrap();
+        let (tag, new_content, rest) = read_tlv(&with_controls).unwrap();
+        assert_eq!(tag, TAG_SEQUENCE);
+        assert!(rest.is_empty());
+        assert_eq!(&new_content[..content.len()], content.as_slice());
+        let (tag, controls_content, _) = read_tlv(&new_content[content.len()..]).unwrap();
+        assert_eq!(tag, TAG_CONTROLS);
+        assert_eq!(read_control(controls_content).unwrap().0, control);
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_message() {
+        // A sequence announcing 4 GiB, with only the header sent.
+        let mut buf = BytesMut::from(&[TAG_SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff][..]);
+        let error = ExtendedLdapCodec.decode(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // Still waiting for the rest of a message under the limit.
+        let mut buf = BytesMut::from(&[TAG_SEQUENCE, 0x83, 0x01, 0x00, 0x00][..]);
+        assert!(ExtendedLdapCodec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_write_integer() {
+        assert_eq!(write_integer(0), vec![TAG_INTEGER, 1, 0]);
+        assert_eq!(write_integer(127), vec![TAG_INTEGER, 1, 0x7f]);
+        assert_eq!(write_integer(128), vec![TAG_INTEGER, 2, 0, 0x80]);
+        assert_eq!(write_integer(-1), vec![TAG_INTEGER, 1, 0xff]);
+        assert_eq!(
+            read_integer(&write_integer(70000), TAG_INTEGER).unwrap().0,
+            70000
+        );
+    }
+
+    #[test]
+    fn test_password_policy_response() {
+        assert_eq!(
+            make_password_policy_response(None).value,
+            Some(vec![TAG_SEQUENCE, 0])
+        );
+        assert_eq!(
+            make_password_policy_response(Some(PasswordPolicyError::AccountLocked)).value,
+            Some(vec![TAG_SEQUENCE, 3, TAG_PASSWORD_POLICY_ERROR, 1, 1])
+        );
+    }
+
+    #[test]
+    fn test_encode_binary_attribute() {
+        use ldap3_server::proto::LdapPartialAttribute;
+        let entry = LdapSearchResultEntry {
+            dn: "cn=bob".to_string(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "jpegPhoto".to_string(),
+                vals: vec![base64::encode(&[0xff, 0xd8, 0xff])],
+            }],
+        };
+        assert_eq!(
+            encode_search_result_entry(3, &entry),
+            write_tlv(
+                TAG_SEQUENCE,
+                &[
+                    write_tlv(TAG_INTEGER, &[3]),
+                    write_tlv(
+                        TAG_SEARCH_RESULT_ENTRY,
+                        &[
+                            write_tlv(TAG_OCTET_STRING, b"cn=bob"),
+                            write_tlv(
+                                TAG_SEQUENCE,
+                                &write_tlv(
+                                    TAG_SEQUENCE,
+                                    &[
+                                        write_tlv(TAG_OCTET_STRING, b"jpegPhoto"),
+                                        write_tlv(
+                                            TAG_SET,
+                                            &write_tlv(TAG_OCTET_STRING, &[0xff, 0xd8, 0xff])
+                                        ),
+                                    ]
+                                    .concat()
+                                )
+                            ),
+                        ]
+                        .concat()
+                    ),
+                ]
+                .concat()
+            )
+        );
+    }
+}
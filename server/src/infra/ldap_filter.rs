@@ -0,0 +1,230 @@
+//! Parses the LDAP filters written as strings (RFC 4515), e.g. `(&(objectClass=person)(uid=b*))`,
+//! into the filters of the LDAP server.
+
+use anyhow::{anyhow, bail, Context, Result};
+use ldap3_server::proto::{LdapFilter, LdapSubstringFilter};
+
+/// Parses the filter. The parentheses around a single item are optional, as with `ldapsearch`.
+pub fn parse_filter(filter: &str) -> Result<LdapFilter> {
+    let filter = filter.trim();
+    if filter.is_empty() {
+        bail!("Empty filter");
+    }
+    let wrapped;
+    let filter = if filter.starts_with('(') {
+        filter
+    } else {
+        wrapped = format!("({})", filter);
+        &wrapped
+    };
+    let mut parser = Parser {
+        input: filter,
+        position: 0,
+    };
+    let result = parser.parse_filter()?;
+    if parser.position != filter.len() {
+        bail!(
+            "Unexpected `{}` after the end of the filter",
+            &filter[parser.position..]
+        );
+    }
+    Ok(result)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    /// In bytes.
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => bail!(
+                "Expected `{}` at position {}, found `{}`",
+                expected,
+                self.position,
+                c
+            ),
+            None => bail!("Expected `{}` at the end of the filter", expected),
+        }
+    }
+
+    fn parse_filter(&mut self) -> Result<LdapFilter> {
+        self.expect('(')?;
+        let filter = match self.peek() {
+            Some('&') => {
+                self.position += 1;
+                LdapFilter::And(self.parse_list()?)
+            }
+            Some('|') => {
+                self.position += 1;
+                LdapFilter::Or(self.parse_list()?)
+            }
+            Some('!') => {
+                self.position += 1;
+                LdapFilter::Not(Box::new(self.parse_filter()?))
+            }
+            _ => self.parse_item()?,
+        };
+        self.expect(')')?;
+        Ok(filter)
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<LdapFilter>> {
+        let mut filters = Vec::new();
+        while self.peek() == Some('(') {
+            filters.push(self.parse_filter()?);
+        }
+        Ok(filters)
+    }
+
+    fn parse_item(&mut self) -> Result<LdapFilter> {
+        let rest = &self.input[self.position..];
+        // The parentheses are escaped in the values.
+        let end = rest
+            .find(')')
+            .ok_or_else(|| anyhow!("Missing `)` at the end of the filter"))?;
+        let item = &rest[..end];
+        let (attribute, value) = item
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected `attribute=value`, found `{}`", item))?;
+        if let Some(operator) = attribute.chars().last().filter(|c| "<>~:".contains(*c)) {
+            bail!("The `{}=` operator is not supported", operator);
+        }
+        if attribute.is_empty()
+            || !attribute
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ';' || c == '.')
+        {
+            bail!("Invalid attribute `{}`", attribute);
+        }
+        self.position += end;
+        let attribute = attribute.to_string();
+        if value == "*" {
+            return Ok(LdapFilter::Present(attribute));
+        }
+        if !value.contains('*') {
+            return Ok(LdapFilter::Equality(attribute, unescape(value)?));
+        }
+        let parts: Vec<&str> = value.split('*').collect();
+        let non_empty = |part: &str| -> Result<Option<String>> {
+            if part.is_empty() {
+                Ok(None)
+            } else {
+                unescape(part).map(Some)
+            }
+        };
+        Ok(LdapFilter::Substring(
+            attribute,
+            LdapSubstringFilter {
+                initial: non_empty(parts[0])?,
+                any: parts[1..parts.len() - 1]
+                    .iter()
+                    .filter(|p| !p.is_empty())
+                    .map(|p| unescape(p))
+                    .collect::<Result<_>>()?,
+                final_: non_empty(parts[parts.len() - 1])?,
+            },
+        ))
+    }
+}
+
+/// Replaces the `\XX` escapes with their bytes.
+fn unescape(value: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'\\' {
+            let hex = tail
+                .get(..2)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid escape in `{}`, expected `\\XX`", value))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else if byte == b'(' {
+            bail!("Unescaped `(` in `{}`", value);
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).context("The escaped value is not UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equality(attribute: &str, value: &str) -> LdapFilter {
+        LdapFilter::Equality(attribute.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(parse_filter("uid=bob").unwrap(), equality("uid", "bob"));
+        assert_eq!(
+            parse_filter(" (&(objectClass=person)(|(uid=bob)(!(mail=*)))) ").unwrap(),
+            LdapFilter::And(vec![
+                equality("objectClass", "person"),
+                LdapFilter::Or(vec![
+                    equality("uid", "bob"),
+                    LdapFilter::Not(Box::new(LdapFilter::Present("mail".to_string())))
+                ])
+            ])
+        );
+        assert_eq!(
+            parse_filter(r"(cn=Bob \28admin\29)").unwrap(),
+            equality("cn", "Bob (admin)")
+        );
+        assert_eq!(parse_filter("(&)").unwrap(), LdapFilter::And(vec![]));
+    }
+
+    #[test]
+    fn test_parse_substring_filter() {
+        assert_eq!(
+            parse_filter("(cn=b*o**b\\2a)").unwrap(),
+            LdapFilter::Substring(
+                "cn".to_string(),
+                LdapSubstringFilter {
+                    initial: Some("b".to_string()),
+                    any: vec!["o".to_string()],
+                    final_: Some("b*".to_string()),
+                }
+            )
+        );
+        assert_eq!(
+            parse_filter("(cn=*ob)").unwrap(),
+            LdapFilter::Substring(
+                "cn".to_string(),
+                LdapSubstringFilter {
+                    initial: None,
+                    any: vec![],
+                    final_: Some("ob".to_string()),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_filter() {
+        parse_filter("").unwrap_err();
+        parse_filter("(uid=bob").unwrap_err();
+        parse_filter("(uid=bob))").unwrap_err();
+        parse_filter("(&(uid=bob)").unwrap_err();
+        parse_filter("(uid)").unwrap_err();
+        parse_filter("(=bob)").unwrap_err();
+        parse_filter("(uidNumber>=1000)").unwrap_err();
+        parse_filter(r"(cn=\2)").unwrap_err();
+        parse_filter("(cn=a(b)").unwrap_err();
+    }
+}
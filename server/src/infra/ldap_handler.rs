@@ -1,12 +1,18 @@
-use crate::domain::{
-    handler::{
-        BackendHandler, BindRequest, Group, GroupIdAndName, LoginHandler, RequestFilter, User,
+use crate::{
+    domain::{
+        error::DomainError,
+        handler::{
+            BackendHandler, BindRequest, ChangeEvent, ChangeType, ChangedEntry,
+            CreateLoginHistoryEntryRequest, Group, LoginHandler, RequestFilter, User,
+        },
+        opaque_handler::OpaqueHandler,
+    },
+    infra::ldap_codec::{
+        PasswordPolicyError, PersistentSearch, PASSWORD_POLICY_OID, PERSISTENT_SEARCH_OID,
     },
-    opaque_handler::OpaqueHandler,
 };
 use anyhow::{bail, Result};
-use futures::stream::StreamExt;
-use futures_util::TryStreamExt;
+use futures::stream::{LocalBoxStream, StreamExt};
 use ldap3_server::proto::{
     LdapBindCred, LdapBindRequest, LdapBindResponse, LdapExtendedRequest, LdapExtendedResponse,
     LdapFilter, LdapOp, LdapPartialAttribute, LdapPasswordModifyRequest, LdapResult,
@@ -14,6 +20,7 @@ use ldap3_server::proto::{
 };
 use log::*;
 use std::convert::TryFrom;
+use std::net::IpAddr;
 
 fn make_dn_pair<I>(mut iter: I) -> Result<(String, String)>
 where
@@ -96,6 +103,14 @@ fn get_user_id_from_distinguished_name(
     }
 }
 
+fn non_empty(value: &str) -> Vec<String> {
+    if value.is_empty() {
+        Vec::new()
+    } else {
+        vec![value.to_string()]
+    }
+}
+
 fn get_user_attribute(user: &User, attribute: &str, dn: &str) -> Result<Vec<String>> {
     match attribute {
         "objectClass" => Ok(vec![
@@ -107,20 +122,33 @@ fn get_user_attribute(user: &User, attribute: &str, dn: &str) -> Result<Vec<Stri
         "dn" => Ok(vec![dn.to_string()]),
         "uid" => Ok(vec![user.user_id.clone()]),
         "mail" => Ok(vec![user.email.clone()]),
-        "givenName" => Ok(vec![user.first_name.clone()]),
-        "sn" => Ok(vec![user.last_name.clone()]),
+        // The names are optional, the attributes are left out when they're empty.
+        "givenName" => Ok(non_empty(&user.first_name)),
+        "sn" => Ok(non_empty(&user.last_name)),
         "cn" => Ok(vec![user.display_name.clone()]),
         "displayName" => Ok(vec![user.display_name.clone()]),
+        // Binary attribute, decoded by the codec.
+        "jpegPhoto" => Ok(user.avatar.iter().map(base64::encode).collect()),
         _ => bail!("Unsupported user attribute: {}", attribute),
     }
 }
 
-fn make_ldap_search_user_result_entry(
+/// The DN of the entry of the user.
+pub fn user_dn(user_id: &str, base_dn_str: &str) -> String {
+    format!("cn={},ou=people,{}", user_id, base_dn_str)
+}
+
+/// The DN of the entry of the group.
+pub fn group_dn(display_name: &str, base_dn_str: &str) -> String {
+    format!("cn={},ou=groups,{}", display_name, base_dn_str)
+}
+
+pub(crate) fn make_ldap_search_user_result_entry(
     user: User,
     base_dn_str: &str,
     attributes: &[String],
 ) -> Result<LdapSearchResultEntry> {
-    let dn = format!("cn={},ou=people,{}", user.user_id, base_dn_str);
+    let dn = user_dn(&user.user_id, base_dn_str);
     Ok(LdapSearchResultEntry {
         dn: dn.clone(),
         attributes: attributes
@@ -138,27 +166,26 @@ fn make_ldap_search_user_result_entry(
 fn get_group_attribute(group: &Group, base_dn_str: &str, attribute: &str) -> Result<Vec<String>> {
     match attribute {
         "objectClass" => Ok(vec!["groupOfUniqueNames".to_string()]),
-        "dn" => Ok(vec![format!(
-            "cn={},ou=groups,{}",
-            group.display_name, base_dn_str
-        )]),
+        "dn" => Ok(vec![group_dn(&group.display_name, base_dn_str)]),
         "cn" => Ok(vec![group.display_name.clone()]),
+        "description" => Ok(group.description.iter().cloned().collect()),
+        "mail" => Ok(group.email.iter().cloned().collect()),
         "member" | "uniqueMember" => Ok(group
             .users
             .iter()
-            .map(|u| format!("cn={},ou=people,{}", u, base_dn_str))
+            .map(|u| user_dn(u, base_dn_str))
             .collect()),
         _ => bail!("Unsupported group attribute: {}", attribute),
     }
 }
 
-fn make_ldap_search_group_result_entry(
+pub(crate) fn make_ldap_search_group_result_entry(
     group: Group,
     base_dn_str: &str,
     attributes: &[String],
 ) -> Result<LdapSearchResultEntry> {
     Ok(LdapSearchResultEntry {
-        dn: format!("cn={},ou=groups,{}", group.display_name, base_dn_str),
+        dn: group_dn(&group.display_name, base_dn_str),
         attributes: attributes
             .iter()
             .map(|a| {
@@ -217,6 +244,18 @@ fn make_search_error(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
+pub fn make_bind_response(code: LdapResultCode, message: String) -> LdapOp {
+    LdapOp::BindResponse(LdapBindResponse {
+        res: LdapResult {
+            code,
+            matcheddn: "".to_string(),
+            message,
+            referral: vec![],
+        },
+        saslcreds: None,
+    })
+}
+
 fn make_extended_response(code: LdapResultCode, message: String) -> LdapOp {
     LdapOp::ExtendedResponse(LdapExtendedResponse {
         res: LdapResult {
@@ -254,6 +293,13 @@ fn root_dse_response(base_dn: &str) -> LdapOp {
                 atype: "supportedExtension".to_string(),
                 vals: vec!["1.3.6.1.4.1.4203.1.11.1".to_string()],
             },
+            LdapPartialAttribute {
+                atype: "supportedControl".to_string(),
+                vals: vec![
+                    PERSISTENT_SEARCH_OID.to_string(),
+                    PASSWORD_POLICY_OID.to_string(),
+                ],
+            },
             LdapPartialAttribute {
                 atype: "defaultnamingcontext".to_string(),
                 vals: vec![base_dn.to_string()],
@@ -262,12 +308,27 @@ fn root_dse_response(base_dn: &str) -> LdapOp {
     })
 }
 
+/// An entry returned by a persistent search after it changed.
+#[derive(Debug, PartialEq)]
+pub struct ChangeNotification {
+    /// The id of the persistent search request.
+    pub msgid: i32,
+    pub entry: LdapSearchResultEntry,
+    pub change_type: ChangeType,
+    pub previous_dn: Option<String>,
+    pub return_entry_change_control: bool,
+}
+
 pub struct LdapHandler<Backend: BackendHandler + LoginHandler + OpaqueHandler> {
     dn: String,
     backend_handler: Backend,
     pub base_dn: Vec<(String, String)>,
     base_dn_str: String,
     ldap_user_dn: String,
+    /// The ongoing persistent searches, with their message id.
+    persistent_searches: Vec<(i32, LdapSearchRequest, PersistentSearch)>,
+    /// The address of the client, for the login history.
+    peer_address: Option<IpAddr>,
 }
 
 impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend> {
@@ -283,10 +344,38 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             }),
             ldap_user_dn: format!("cn={},ou=people,{}", ldap_user_dn, &ldap_base_dn),
             base_dn_str: ldap_base_dn,
+            persistent_searches: Vec::new(),
+            peer_address: None,
         }
     }
 
+    pub fn with_peer_address(mut self, peer_address: Option<IpAddr>) -> Self {
+        self.peer_address = peer_address;
+        self
+    }
+
+    /// Binds the session as the LDAP admin without a password, for the searches run by the admins
+    /// of the web app, who are already authenticated.
+    pub fn with_admin_session(mut self) -> Self {
+        self.dn = self.ldap_user_dn.clone();
+        self
+    }
+
+    /// The DN the session is bound as, "Unauthenticated" before a successful bind.
+    pub fn bound_dn(&self) -> &str {
+        &self.dn
+    }
+
     pub async fn do_bind(&mut self, request: &LdapBindRequest) -> (LdapResultCode, String) {
+        let (code, message, _) = self.do_bind_with_password_policy(request).await;
+        (code, message)
+    }
+
+    /// Same as `do_bind`, with the error to report in the password policy response control.
+    pub async fn do_bind_with_password_policy(
+        &mut self,
+        request: &LdapBindRequest,
+    ) -> (LdapResultCode, String, Option<PasswordPolicyError>) {
         info!(r#"Received bind request for "{}""#, &request.dn);
         let user_id = match get_user_id_from_distinguished_name(
             &request.dn,
@@ -294,22 +383,59 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             &self.base_dn_str,
         ) {
             Ok(s) => s,
-            Err(e) => return (LdapResultCode::NamingViolation, e.to_string()),
+            Err(e) => return (LdapResultCode::NamingViolation, e.to_string(), None),
+        };
+        let login_name = user_id;
+        let user_id = match self
+            .backend_handler
+            .get_user_id_for_login(&login_name)
+            .await
+        {
+            Ok(user_id) => user_id,
+            Err(_) => return (LdapResultCode::InvalidCredentials, "".to_string(), None),
         };
         let LdapBindCred::Simple(password) = &request.cred;
         match self
             .backend_handler
             .bind(BindRequest {
-                name: user_id,
+                name: user_id.clone(),
                 password: password.clone(),
             })
             .await
         {
             Ok(()) => {
-                self.dn = request.dn.clone();
-                (LdapResultCode::Success, "".to_string())
+                if let Err(e) = self
+                    .backend_handler
+                    .add_login_history_entry(CreateLoginHistoryEntryRequest {
+                        user_id: user_id.clone(),
+                        method: "ldap".to_string(),
+                        ip: self
+                            .peer_address
+                            .map(|ip| ip.to_string())
+                            .unwrap_or_else(|| "unknown".to_string()),
+                        user_agent: None,
+                    })
+                    .await
+                {
+                    error!(
+                        "Could not add the login of {} to the history: {}",
+                        user_id, e
+                    );
+                }
+                // Logged in with the email: the session is the user's.
+                self.dn = if user_id == login_name {
+                    request.dn.clone()
+                } else {
+                    user_dn(&user_id, &self.base_dn_str)
+                };
+                (LdapResultCode::Success, "".to_string(), None)
             }
-            Err(_) => (LdapResultCode::InvalidCredentials, "".to_string()),
+            Err(DomainError::UserDisabled(_)) | Err(DomainError::UserLocked(_)) => (
+                LdapResultCode::InvalidCredentials,
+                "".to_string(),
+                Some(PasswordPolicyError::AccountLocked),
+            ),
+            Err(_) => (LdapResultCode::InvalidCredentials, "".to_string(), None),
         }
     }
 
@@ -380,84 +506,202 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
     }
 
     pub async fn do_search(&mut self, request: &LdapSearchRequest) -> Vec<LdapOp> {
+        self.stream_search(request).collect().await
+    }
+
+    /// The responses to a search, as they come: the users are sent as they are read from the
+    /// database, instead of holding them all in memory.
+    pub fn stream_search<'a>(
+        &'a self,
+        request: &'a LdapSearchRequest,
+    ) -> LocalBoxStream<'a, LdapOp> {
+        let responses = |ops: Vec<LdapOp>| futures::stream::iter(ops).boxed_local();
         if self.dn != self.ldap_user_dn {
-            return vec![make_search_error(
+            return responses(vec![make_search_error(
                 LdapResultCode::InsufficentAccessRights,
                 format!(
                     r#"Current user `{}` is not allowed to query LDAP, expected {}"#,
                     &self.dn, &self.ldap_user_dn
                 ),
-            )];
+            )]);
         }
         if request.base.is_empty()
             && request.scope == LdapSearchScope::Base
             && request.filter == LdapFilter::Present("objectClass".to_string())
         {
             info!("Received rootDSE request");
-            return vec![root_dse_response(&self.base_dn_str), make_search_success()];
+            return responses(vec![
+                root_dse_response(&self.base_dn_str),
+                make_search_success(),
+            ]);
         }
         info!("Received search request: {:?}", &request);
         let dn_parts = match parse_distinguished_name(&request.base) {
             Ok(dn) => dn,
             Err(_) => {
-                return vec![make_search_error(
+                return responses(vec![make_search_error(
                     LdapResultCode::OperationsError,
                     format!(r#"Could not parse base DN: "{}""#, request.base),
-                )]
+                )])
             }
         };
         if !is_subtree(&dn_parts, &self.base_dn) {
             // Search path is not in our tree, just return an empty success.
-            return vec![make_search_success()];
+            return responses(vec![make_search_success()]);
         }
-        let mut results = Vec::new();
         if dn_parts.len() == self.base_dn.len()
             || (dn_parts.len() == self.base_dn.len() + 1
                 && dn_parts[0] == ("ou".to_string(), "people".to_string()))
         {
-            results.extend(self.get_user_list(request).await);
+            return self.stream_user_list(request);
         }
         if dn_parts.len() == self.base_dn.len() + 1
             && dn_parts[0] == ("ou".to_string(), "groups".to_string())
         {
-            results.extend(self.get_groups_list(request).await);
+            return futures::stream::once(self.get_groups_list(request))
+                .flat_map(futures::stream::iter)
+                .boxed_local();
+        }
+        responses(Vec::new())
+    }
+
+    /// Starts a search that stays active after the initial results, to report the changes of the
+    /// matching entries (see `get_change_notifications`) until it is abandoned.
+    pub async fn do_persistent_search(
+        &mut self,
+        msgid: i32,
+        request: LdapSearchRequest,
+        options: PersistentSearch,
+    ) -> Vec<LdapOp> {
+        let mut results = self.do_search(&request).await;
+        match results.pop() {
+            Some(LdapOp::SearchResultDone(result)) if result.code == LdapResultCode::Success => {
+                self.persistent_searches.push((msgid, request, options));
+                if options.changes_only {
+                    results.clear();
+                }
+                // The search is not done.
+                results
+            }
+            // The search failed, no need to keep it.
+            done => results.into_iter().chain(done).collect(),
+        }
+    }
+
+    pub fn has_persistent_searches(&self) -> bool {
+        !self.persistent_searches.is_empty()
+    }
+
+    fn get_entry_dn(&self, entry: &ChangedEntry) -> String {
+        match entry {
+            ChangedEntry::User(user_id) => {
+                format!("cn={},ou=people,{}", user_id, self.base_dn_str)
+            }
+            ChangedEntry::Group(name) => format!("cn={},ou=groups,{}", name, self.base_dn_str),
         }
-        results
     }
 
-    async fn get_user_list(&self, request: &LdapSearchRequest) -> Vec<LdapOp> {
+    /// Returns the entries to send to the persistent searches interested in the change.
+    pub async fn get_change_notifications(
+        &mut self,
+        change: &ChangeEvent,
+    ) -> Vec<ChangeNotification> {
+        let dn = self.get_entry_dn(&change.entry);
+        let previous_dn = change.previous_entry.as_ref().map(|e| self.get_entry_dn(e));
+        let mut notifications = Vec::new();
+        for (msgid, request, options) in self.persistent_searches.clone() {
+            if !options.reports(change.change_type) {
+                continue;
+            }
+            let entry = if change.change_type == ChangeType::Delete {
+                // The entry is gone, it can't be matched against the filter anymore.
+                match (
+                    parse_distinguished_name(&dn),
+                    parse_distinguished_name(&request.base),
+                ) {
+                    (Ok(entry_dn), Ok(base_dn)) if is_subtree(&entry_dn, &base_dn) => {
+                        LdapSearchResultEntry {
+                            dn: dn.clone(),
+                            attributes: Vec::new(),
+                        }
+                    }
+                    _ => continue,
+                }
+            } else {
+                let mut entry_request = request.clone();
+                if let ChangedEntry::User(user_id) = &change.entry {
+                    // Only look at the changed user.
+                    entry_request.filter = LdapFilter::And(vec![
+                        request.filter.clone(),
+                        LdapFilter::Equality("uid".to_string(), user_id.clone()),
+                    ]);
+                }
+                match self
+                    .do_search(&entry_request)
+                    .await
+                    .into_iter()
+                    .find_map(|op| match op {
+                        LdapOp::SearchResultEntry(entry) if entry.dn == dn => Some(entry),
+                        _ => None,
+                    }) {
+                    Some(entry) => entry,
+                    None => continue,
+                }
+            };
+            notifications.push(ChangeNotification {
+                msgid,
+                entry,
+                change_type: change.change_type,
+                previous_dn: previous_dn.clone(),
+                return_entry_change_control: options.return_entry_change_controls,
+            });
+        }
+        notifications
+    }
+
+    fn stream_user_list<'a>(
+        &'a self,
+        request: &'a LdapSearchRequest,
+    ) -> LocalBoxStream<'a, LdapOp> {
         let filters = match self.convert_user_filter(&request.filter) {
             Ok(f) => Some(f),
             Err(e) => {
-                return vec![make_search_error(
+                return futures::stream::iter(vec![make_search_error(
                     LdapResultCode::UnwillingToPerform,
                     format!("Unsupported user filter: {}", e),
-                )]
-            }
-        };
-        let users = match self.backend_handler.list_users(filters).await {
-            Ok(users) => users,
-            Err(e) => {
-                return vec![make_search_error(
-                    LdapResultCode::Other,
-                    format!(r#"Error during searching user "{}": {}"#, request.base, e),
-                )]
+                )])
+                .boxed_local()
             }
         };
 
-        users
-            .into_iter()
-            .map(|u| make_ldap_search_user_result_entry(u, &self.base_dn_str, &request.attrs))
-            .map(|entry| Ok(LdapOp::SearchResultEntry(entry?)))
+        self.backend_handler
+            .stream_users(filters)
+            // The errors are the final response.
+            .map(move |user| -> std::result::Result<LdapOp, LdapOp> {
+                let user = user.map_err(|e| {
+                    make_search_error(
+                        LdapResultCode::Other,
+                        format!(r#"Error during searching user "{}": {}"#, request.base, e),
+                    )
+                })?;
+                make_ldap_search_user_result_entry(user, &self.base_dn_str, &request.attrs)
+                    .map(LdapOp::SearchResultEntry)
+                    .map_err(|e| make_search_error(LdapResultCode::NoSuchAttribute, e.to_string()))
+            })
             // If the processing succeeds, add a success message at the end.
-            .chain(std::iter::once(Ok(make_search_success())))
-            .collect::<Result<Vec<_>>>()
-            .unwrap_or_else(|e| {
-                vec![make_search_error(
-                    LdapResultCode::NoSuchAttribute,
-                    e.to_string(),
-                )]
+            .chain(futures::stream::once(async { Ok(make_search_success()) }))
+            // Stop after the first error.
+            .scan(false, |failed, response| {
+                futures::future::ready(if *failed {
+                    None
+                } else {
+                    Some(response.unwrap_or_else(|error| {
+                        *failed = true;
+                        error
+                    }))
+                })
             })
+            .boxed_local()
     }
 
     async fn get_groups_list(&self, request: &LdapSearchRequest) -> Vec<LdapOp> {
@@ -471,20 +715,6 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             }
         };
 
-        async fn get_users_for_group<Backend: BackendHandler>(
-            backend_handler: &Backend,
-            g: &GroupIdAndName,
-        ) -> Result<Group> {
-            let users = backend_handler
-                .list_users(Some(RequestFilter::MemberOfId(g.0)))
-                .await?;
-            Ok(Group {
-                id: g.0,
-                display_name: g.1.clone(),
-                users: users.into_iter().map(|u| u.user_id).collect(),
-            })
-        }
-
         let groups: Vec<Group> = if let Some(user) = for_user {
             let groups_without_users = match self.backend_handler.get_user_groups(&user).await {
                 Ok(groups) => groups,
@@ -498,16 +728,20 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                     )]
                 }
             };
-            match tokio_stream::iter(groups_without_users.iter())
-                .then(|g| async move { get_users_for_group::<Backend>(&self.backend_handler, g).await })
-                .try_collect::<Vec<Group>>()
-                .await
-            {
-                Ok(groups) => groups,
+            // All the groups with their members in one query, rather than the members of each
+            // group one after the other.
+            match self.backend_handler.list_groups().await {
+                Ok(groups) => groups
+                    .into_iter()
+                    .filter(|g| groups_without_users.iter().any(|u| u.0 == g.id))
+                    .collect(),
                 Err(e) => {
                     return vec![make_search_error(
                         LdapResultCode::Other,
-                        format!(r#"Error while listing user groups: "{}": {}"#, request.base, e),
+                        format!(
+                            r#"Error while listing user groups: "{}": {}"#,
+                            request.base, e
+                        ),
                     )]
                 }
             }
@@ -542,15 +776,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         Some(match ldap_op {
             LdapOp::BindRequest(request) => {
                 let (code, message) = self.do_bind(&request).await;
-                vec![LdapOp::BindResponse(LdapBindResponse {
-                    res: LdapResult {
-                        code,
-                        matcheddn: "".to_string(),
-                        message,
-                        referral: vec![],
-                    },
-                    saslcreds: None,
-                })]
+                vec![make_bind_response(code, message)]
             }
             LdapOp::SearchRequest(request) => self.do_search(&request).await,
             LdapOp::UnbindRequest => {
@@ -559,6 +785,11 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 return None;
             }
             LdapOp::ExtendedRequest(request) => self.do_extended_request(&request).await,
+            LdapOp::AbandonRequest(msgid) => {
+                self.persistent_searches.retain(|(id, _, _)| *id != msgid);
+                // Abandon requests have no response.
+                vec![]
+            }
             op => vec![make_extended_response(
                 LdapResultCode::UnwillingToPerform,
                 format!("Unsupported operation: {:#?}", op),
@@ -661,18 +892,57 @@ mod tests {
         #[async_trait]
         impl BackendHandler for TestBackendHandler {
             async fn list_users(&self, filters: Option<RequestFilter>) -> Result<Vec<User>>;
+            async fn count_users(&self, filters: Option<RequestFilter>) -> Result<i64>;
+            async fn list_users_with_duplicate_emails(&self) -> Result<Vec<User>>;
             async fn list_groups(&self) -> Result<Vec<Group>>;
+            async fn count_groups(&self) -> Result<i64>;
             async fn get_user_details(&self, user_id: &str) -> Result<User>;
-            async fn get_group_details(&self, group_id: GroupId) -> Result<GroupIdAndName>;
+            async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails>;
             async fn get_user_groups(&self, user: &str) -> Result<HashSet<GroupIdAndName>>;
             async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
             async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
+            async fn rename_user(&self, user_id: &str, new_user_id: &str) -> Result<()>;
+            async fn unlock_user(&self, user_id: &str) -> Result<()>;
             async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
             async fn delete_user(&self, user_id: &str) -> Result<()>;
+            async fn list_deleted_users(&self) -> Result<Vec<DeletedUser>>;
+            async fn restore_user(&self, user_id: &str) -> Result<()>;
+            async fn purge_deleted_user(&self, user_id: &str) -> Result<()>;
             async fn create_group(&self, group_name: &str) -> Result<GroupId>;
             async fn delete_group(&self, group_id: GroupId) -> Result<()>;
             async fn add_user_to_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
             async fn remove_user_from_group(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+            async fn add_group_to_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+            async fn remove_group_from_group(&self, child: GroupId, parent: GroupId) -> Result<()>;
+            async fn list_subgroups(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+            async fn get_group_ancestors(&self, group_id: GroupId) -> Result<Vec<GroupIdAndName>>;
+            async fn add_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+            async fn remove_group_manager(&self, user_id: &str, group_id: GroupId) -> Result<()>;
+            async fn list_group_managers(&self, group_id: GroupId) -> Result<Vec<String>>;
+            async fn list_managed_groups(&self, user_id: &str) -> Result<Vec<GroupIdAndName>>;
+            async fn create_api_token(&self, request: CreateApiTokenRequest) -> Result<(ApiToken, String)>;
+            async fn list_api_tokens(&self) -> Result<Vec<ApiToken>>;
+            async fn delete_api_token(&self, token_id: i32) -> Result<()>;
+            async fn create_oidc_client(&self, client: OidcClient) -> Result<String>;
+            async fn list_oidc_clients(&self) -> Result<Vec<OidcClient>>;
+            async fn delete_oidc_client(&self, client_id: &str) -> Result<()>;
+            async fn import_password_hash(&self, user_id: &str, password_hash: &str) -> Result<()>;
+            async fn add_audit_log_entry(&self, request: CreateAuditLogEntryRequest) -> Result<()>;
+            async fn list_audit_log(&self, offset: i64, limit: i64) -> Result<Vec<AuditLogEntry>>;
+            async fn add_login_history_entry(&self, request: CreateLoginHistoryEntryRequest) -> Result<()>;
+            async fn list_login_history(&self, user_id: &str, limit: i64) -> Result<Vec<LoginHistoryEntry>>;
+            async fn is_totp_enabled(&self, user_id: &str) -> Result<bool>;
+            async fn start_totp_enrollment(&self, user_id: &str) -> Result<String>;
+            async fn confirm_totp_enrollment(&self, user_id: &str, code: &str) -> Result<Vec<String>>;
+            async fn disable_totp(&self, user_id: &str) -> Result<()>;
+            async fn list_webauthn_credentials(&self, user_id: &str) -> Result<Vec<WebauthnCredential>>;
+            async fn add_webauthn_credential(&self, request: CreateWebauthnCredentialRequest) -> Result<()>;
+            async fn delete_webauthn_credential(&self, user_id: &str, credential_id: &str) -> Result<()>;
+            async fn list_user_attribute_schema(&self) -> Result<Vec<AttributeSchema>>;
+            async fn add_user_attribute(&self, schema: AttributeSchema) -> Result<()>;
+            async fn delete_user_attribute(&self, name: &str) -> Result<()>;
+            async fn get_user_attributes(&self, user_id: &str) -> Result<Vec<AttributeValue>>;
+            async fn set_user_attribute(&self, user_id: &str, name: &str, values: Vec<String>) -> Result<()>;
         }
         #[async_trait]
         impl OpaqueHandler for TestBackendHandler {
@@ -725,6 +995,8 @@ mod tests {
                 password: "pass".to_string(),
             }))
             .return_once(|_| Ok(()));
+        mock.expect_add_login_history_entry()
+            .return_once(|_| Ok(()));
         let mut ldap_handler =
             LdapHandler::new(mock, "dc=example,dc=com".to_string(), "test".to_string());
         let request = LdapBindRequest {
@@ -748,8 +1020,18 @@ mod tests {
             }))
             .times(1)
             .return_once(|_| Ok(()));
+        mock.expect_add_login_history_entry()
+            .with(eq(CreateLoginHistoryEntryRequest {
+                user_id: "bob".to_string(),
+                method: "ldap".to_string(),
+                ip: "192.168.1.2".to_string(),
+                user_agent: None,
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
         let mut ldap_handler =
-            LdapHandler::new(mock, "dc=example,dc=com".to_string(), "test".to_string());
+            LdapHandler::new(mock, "dc=example,dc=com".to_string(), "test".to_string())
+                .with_peer_address(Some("192.168.1.2".parse().unwrap()));
 
         let request = LdapBindRequest {
             dn: "cn=bob,ou=people,dc=example,dc=com".to_string(),
@@ -771,6 +1053,8 @@ mod tests {
             }))
             .times(1)
             .return_once(|_| Ok(()));
+        mock.expect_add_login_history_entry()
+            .return_once(|_| Ok(()));
         let mut ldap_handler =
             LdapHandler::new(mock, "dc=example,dc=com".to_string(), "test".to_string());
 
@@ -794,6 +1078,8 @@ mod tests {
             }))
             .times(1)
             .return_once(|_| Ok(()));
+        mock.expect_add_login_history_entry()
+            .return_once(|_| Ok(()));
         let mut ldap_handler =
             LdapHandler::new(mock, "dc=example,dc=com".to_string(), "admin".to_string());
 
@@ -816,6 +1102,28 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_bind_disabled_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_bind()
+            .return_once(|r| Err(DomainError::UserDisabled(r.name)));
+        let mut ldap_handler =
+            LdapHandler::new(mock, "dc=example,dc=com".to_string(), "admin".to_string());
+
+        let request = LdapBindRequest {
+            dn: "cn=bob,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple("pass".to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_bind_with_password_policy(&request).await,
+            (
+                LdapResultCode::InvalidCredentials,
+                "".to_string(),
+                Some(PasswordPolicyError::AccountLocked)
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_bind_invalid_dn() {
         let mock = MockTestBackendHandler::new();
@@ -988,11 +1296,15 @@ mod tests {
                 Group {
                     id: GroupId(1),
                     display_name: "group_1".to_string(),
+                    description: None,
+                    email: None,
                     users: vec!["bob".to_string(), "john".to_string()],
                 },
                 Group {
                     id: GroupId(3),
                     display_name: "bestgroup".to_string(),
+                    description: None,
+                    email: None,
                     users: vec!["john".to_string()],
                 },
             ])
@@ -1067,15 +1379,24 @@ mod tests {
                 set.insert(GroupIdAndName(GroupId(1), "group_1".to_string()));
                 Ok(set)
             });
-        mock.expect_list_users()
-            .with(eq(Some(RequestFilter::MemberOfId(GroupId(1)))))
-            .times(1)
-            .return_once(|_| {
-                Ok(vec![User {
-                    user_id: "bob".to_string(),
-                    ..Default::default()
-                }])
-            });
+        mock.expect_list_groups().times(1).return_once(|| {
+            Ok(vec![
+                Group {
+                    id: GroupId(1),
+                    display_name: "group_1".to_string(),
+                    description: None,
+                    email: Some("group_1@example.com".to_string()),
+                    users: vec!["bob".to_string()],
+                },
+                Group {
+                    id: GroupId(2),
+                    display_name: "group_2".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["john".to_string()],
+                },
+            ])
+        });
         let mut ldap_handler = setup_bound_handler(mock).await;
         let request = make_search_request(
             "ou=groups,dc=example,dc=com",
@@ -1083,17 +1404,27 @@ mod tests {
                 "uniqueMember".to_string(),
                 "cn=bob,ou=people,dc=example,dc=com".to_string(),
             ),
-            vec!["cn"],
+            vec!["cn", "description", "mail"],
         );
         assert_eq!(
             ldap_handler.do_search(&request).await,
             vec![
                 LdapOp::SearchResultEntry(LdapSearchResultEntry {
                     dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
-                    attributes: vec![LdapPartialAttribute {
-                        atype: "cn".to_string(),
-                        vals: vec!["group_1".to_string()]
-                    },],
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec!["group_1".to_string()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "description".to_string(),
+                            vals: vec![]
+                        },
+                        LdapPartialAttribute {
+                            atype: "mail".to_string(),
+                            vals: vec!["group_1@example.com".to_string()]
+                        },
+                    ],
                 }),
                 make_search_success(),
             ]
@@ -1144,6 +1475,28 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_with_admin_session() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(RequestFilter::And(vec![]))))
+            .times(1)
+            .return_once(|_| Ok(vec![]));
+        let mut ldap_handler =
+            LdapHandler::new(mock, "dc=example,dc=com".to_string(), "test".to_string());
+        assert_eq!(ldap_handler.bound_dn(), "Unauthenticated");
+        ldap_handler = ldap_handler.with_admin_session();
+        assert_eq!(
+            ldap_handler.bound_dn(),
+            "cn=test,ou=people,dc=example,dc=com"
+        );
+        let request = make_user_search_request::<String>(LdapFilter::And(vec![]), vec![]);
+        assert_eq!(
+            ldap_handler.do_search(&request).await,
+            vec![make_search_success()]
+        );
+    }
+
     #[tokio::test]
     async fn test_search_root_dse() {
         let mut ldap_handler = setup_bound_handler(MockTestBackendHandler::new()).await;
@@ -1165,4 +1518,84 @@ mod tests {
             ]
         );
     }
+
+    #[tokio::test]
+    async fn test_persistent_search() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(RequestFilter::And(vec![]))))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![User {
+                    user_id: "bob".to_string(),
+                    ..Default::default()
+                }])
+            });
+        mock.expect_list_users()
+            .with(eq(Some(RequestFilter::And(vec![
+                RequestFilter::And(vec![]),
+                RequestFilter::Equality("user_id".to_string(), "jim".to_string()),
+            ]))))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![User {
+                    user_id: "jim".to_string(),
+                    ..Default::default()
+                }])
+            });
+        let mut ldap_handler = setup_bound_handler(mock).await;
+        let request = make_user_search_request(
+            LdapFilter::Equality("objectClass".to_string(), "person".to_string()),
+            vec!["uid"],
+        );
+        let make_entry = |uid: &str| LdapSearchResultEntry {
+            dn: format!("cn={},ou=people,dc=example,dc=com", uid),
+            attributes: vec![LdapPartialAttribute {
+                atype: "uid".to_string(),
+                vals: vec![uid.to_string()],
+            }],
+        };
+        let options = PersistentSearch {
+            change_types: 15,
+            changes_only: false,
+            return_entry_change_controls: true,
+        };
+        // The initial results are not followed by a SearchResultDone.
+        assert_eq!(
+            ldap_handler.do_persistent_search(2, request, options).await,
+            vec![LdapOp::SearchResultEntry(make_entry("bob"))]
+        );
+        assert!(ldap_handler.has_persistent_searches());
+        assert_eq!(
+            ldap_handler
+                .get_change_notifications(&ChangeEvent {
+                    change_type: ChangeType::Add,
+                    entry: ChangedEntry::User("jim".to_string()),
+                    previous_entry: None,
+                })
+                .await,
+            vec![ChangeNotification {
+                msgid: 2,
+                entry: make_entry("jim"),
+                change_type: ChangeType::Add,
+                previous_dn: None,
+                return_entry_change_control: true,
+            }]
+        );
+        // Groups are not in the search base.
+        assert_eq!(
+            ldap_handler
+                .get_change_notifications(&ChangeEvent {
+                    change_type: ChangeType::Delete,
+                    entry: ChangedEntry::Group("family".to_string()),
+                    previous_entry: None,
+                })
+                .await,
+            vec![]
+        );
+        ldap_handler
+            .handle_ldap_message(LdapOp::AbandonRequest(2))
+            .await;
+        assert!(!ldap_handler.has_persistent_searches());
+    }
 }
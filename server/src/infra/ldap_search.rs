@@ -0,0 +1,175 @@
+//! The LDAP query tester of the admins: `/api/ldap/search` runs a search through the LDAP server,
+//! as its admin, and returns the entries that a client would get, to debug the filters of the
+//! clients without `ldapsearch`.
+
+use crate::{
+    domain::{handler::BackendHandler, handler::LoginHandler, opaque_handler::OpaqueHandler},
+    infra::{
+        ldap_filter::parse_filter, ldap_handler::LdapHandler, rest_api::authenticate,
+        tcp_backend_handler::TcpBackendHandler, tcp_server::AppState,
+    },
+};
+use actix_web::{web, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use futures::StreamExt;
+use ldap3_server::proto::{LdapDerefAliases, LdapOp, LdapSearchRequest, LdapSearchScope};
+use serde::{Deserialize, Serialize};
+
+/// The entries past this one are dropped, to keep the page usable.
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequest {
+    base_dn: String,
+    filter: String,
+    /// "base", "one" or "sub".
+    #[serde(default = "default_scope")]
+    scope: String,
+    /// All the attributes when empty, like `ldapsearch`.
+    #[serde(default)]
+    attributes: Vec<String>,
+}
+
+fn default_scope() -> String {
+    "sub".to_string()
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SearchAttribute {
+    name: String,
+    values: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SearchEntry {
+    dn: String,
+    attributes: Vec<SearchAttribute>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResponse {
+    entries: Vec<SearchEntry>,
+    /// Whether there were more than `MAX_ENTRIES` entries.
+    truncated: bool,
+    /// The LDAP result code, e.g. "Success".
+    result_code: String,
+    message: String,
+}
+
+fn parse_scope(scope: &str) -> Option<LdapSearchScope> {
+    match scope {
+        "base" => Some(LdapSearchScope::Base),
+        "one" => Some(LdapSearchScope::OneLevel),
+        "sub" => Some(LdapSearchScope::Subtree),
+        _ => None,
+    }
+}
+
+/// Runs the search in a session of the LDAP admin, and collects its responses.
+async fn run_search<Backend>(
+    session: &LdapHandler<Backend>,
+    request: &LdapSearchRequest,
+) -> SearchResponse
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler,
+{
+    let mut response = SearchResponse {
+        entries: Vec::new(),
+        truncated: false,
+        result_code: "Other".to_string(),
+        message: "The search returned no result".to_string(),
+    };
+    let mut responses = session.stream_search(request);
+    while let Some(op) = responses.next().await {
+        match op {
+            LdapOp::SearchResultEntry(_) if response.entries.len() == MAX_ENTRIES => {
+                response.truncated = true;
+            }
+            LdapOp::SearchResultEntry(entry) => response.entries.push(SearchEntry {
+                dn: entry.dn,
+                attributes: entry
+                    .attributes
+                    .into_iter()
+                    .map(|a| SearchAttribute {
+                        name: a.atype,
+                        values: a.vals,
+                    })
+                    .collect(),
+            }),
+            LdapOp::SearchResultDone(result) => {
+                response.result_code = format!("{:?}", result.code);
+                response.message = result.message;
+            }
+            _ => (),
+        }
+    }
+    response
+}
+
+async fn post_search<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    request: web::Json<SearchRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + 'static,
+{
+    match authenticate(&data, &bearer).await {
+        Ok(v) if v.is_admin() => (),
+        Ok(_) => return HttpResponse::Forbidden().body("Only the admins can test LDAP searches"),
+        Err(response) => return response,
+    }
+    let request = request.into_inner();
+    let filter = match parse_filter(&request.filter) {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid filter: {:#}", e)),
+    };
+    let scope = match parse_scope(&request.scope) {
+        Some(scope) => scope,
+        None => {
+            return HttpResponse::BadRequest().body(format!(
+                "Invalid scope `{}`, expected base, one or sub",
+                request.scope
+            ))
+        }
+    };
+    let search_request = LdapSearchRequest {
+        base: request.base_dn.trim().to_string(),
+        scope,
+        aliases: LdapDerefAliases::Never,
+        sizelimit: 0,
+        timelimit: 0,
+        typesonly: false,
+        filter,
+        attrs: request.attributes,
+    };
+    let session = LdapHandler::new(
+        data.backend_handler.clone(),
+        data.ldap_base_dn.clone(),
+        data.ldap_user_dn.clone(),
+    )
+    .with_admin_session();
+    HttpResponse::Ok().json(run_search(&session, &search_request).await)
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + 'static,
+{
+    cfg.service(web::resource("/ldap/search").route(web::post().to(post_search::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scope() {
+        assert_eq!(parse_scope("sub"), Some(LdapSearchScope::Subtree));
+        assert_eq!(parse_scope("one"), Some(LdapSearchScope::OneLevel));
+        assert_eq!(parse_scope("base"), Some(LdapSearchScope::Base));
+        assert_eq!(parse_scope("subtree"), None);
+    }
+}
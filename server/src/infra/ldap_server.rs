@@ -1,98 +1,404 @@
 use crate::{
     domain::{
-        handler::{BackendHandler, LoginHandler},
+        handler::{BackendHandler, ChangeEvent, ChangeNotifier, LoginHandler},
         opaque_handler::OpaqueHandler,
     },
-    infra::{configuration::Configuration, ldap_handler::LdapHandler},
+    infra::{
+        configuration::Configuration,
+        ldap_codec::{
+            make_entry_change_notification, make_password_policy_response, ExtendedLdapCodec,
+            PersistentSearch, RawControl, PASSWORD_POLICY_OID, PERSISTENT_SEARCH_OID,
+        },
+        ldap_handler::{make_bind_response, LdapHandler},
+        logging,
+        tls::TlsStore,
+    },
 };
 use actix_rt::net::TcpStream;
-use actix_server::ServerBuilder;
+use actix_server::{ServerBuilder, ServiceFactory};
 use actix_service::{fn_service, ServiceFactoryExt};
 use anyhow::{anyhow, bail, Result};
 use futures_util::future::ok;
-use ldap3_server::{proto::LdapMsg, LdapCodec};
+use ldap3_server::proto::{LdapMsg, LdapOp, LdapResult, LdapResultCode, LdapSearchRequest};
 use log::*;
-use tokio::net::tcp::WriteHalf;
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::broadcast,
+};
 use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::Instrument;
+
+/// The write half of the connection, in clear or TLS.
+type ResponseWriter<W> = FramedWrite<W, ExtendedLdapCodec>;
+
+async fn send_responses<W: AsyncWrite + Unpin>(
+    resp: &mut ResponseWriter<W>,
+    responses: impl IntoIterator<Item = (LdapMsg, Vec<RawControl>)>,
+) -> Result<()> {
+    use futures_util::SinkExt;
+    for response in responses {
+        if let Err(e) = resp.send(response).await {
+            bail!("Error while sending a response: {:?}", e);
+        }
+    }
+    if let Err(e) = resp.flush().await {
+        bail!("Error while flushing responses: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Sends the results of a search as they are produced, without holding them all in memory. Returns
+/// the result code, for the access log.
+async fn send_search_responses<Backend, W: AsyncWrite + Unpin>(
+    resp: &mut ResponseWriter<W>,
+    msgid: i32,
+    request: &LdapSearchRequest,
+    session: &LdapHandler<Backend>,
+) -> Result<String>
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler,
+{
+    use futures_util::{SinkExt, StreamExt};
+    let mut status = "NoResponse".to_string();
+    let mut responses = session.stream_search(request);
+    while let Some(op) = responses.next().await {
+        if let LdapOp::SearchResultDone(_) = &op {
+            status = ldap_result_status(std::slice::from_ref(&op));
+        }
+        let msg = LdapMsg {
+            msgid,
+            op,
+            ctrl: vec![],
+        };
+        // Only flushed when the buffer is full, or at the end.
+        if let Err(e) = resp.feed((msg, vec![])).await {
+            bail!("Error while sending a response: {:?}", e);
+        }
+    }
+    if let Err(e) = resp.flush().await {
+        bail!("Error while flushing responses: {:?}", e);
+    }
+    Ok(status)
+}
+
+fn get_persistent_search(controls: &[RawControl]) -> Option<Result<PersistentSearch>> {
+    controls
+        .iter()
+        .find(|c| c.oid == PERSISTENT_SEARCH_OID)
+        .map(PersistentSearch::from_control)
+}
+
+/// Numbers the connections, for the request IDs of the LDAP operations.
+static CONNECTION_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-async fn handle_incoming_message<Backend>(
-    msg: Result<LdapMsg, std::io::Error>,
-    resp: &mut FramedWrite<WriteHalf<'_>, LdapCodec>,
+fn ldap_operation_name(op: &LdapOp) -> &'static str {
+    match op {
+        LdapOp::BindRequest(_) => "bind",
+        LdapOp::UnbindRequest => "unbind",
+        LdapOp::SearchRequest(_) => "search",
+        LdapOp::ExtendedRequest(_) => "extended",
+        LdapOp::AbandonRequest(_) => "abandon",
+        _ => "unsupported",
+    }
+}
+
+/// The result code of the last response, for the access log.
+fn ldap_result_status(responses: &[LdapOp]) -> String {
+    match responses.last() {
+        Some(LdapOp::BindResponse(response)) => format!("{:?}", response.res.code),
+        Some(LdapOp::ExtendedResponse(response)) => format!("{:?}", response.res.code),
+        Some(LdapOp::SearchResultDone(result)) => format!("{:?}", result.code),
+        _ => "NoResponse".to_string(),
+    }
+}
+
+async fn handle_incoming_message<Backend, W: AsyncWrite + Unpin>(
+    (msg, controls): (LdapMsg, Vec<RawControl>),
+    resp: &mut ResponseWriter<W>,
     session: &mut LdapHandler<Backend>,
+    connection_id: u64,
 ) -> Result<bool>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler,
 {
-    use futures_util::SinkExt;
-    let msg = msg.map_err(|e| anyhow!("Error while receiving LDAP op: {:#}", e))?;
-    match session.handle_ldap_message(msg.op).await {
+    let user = match &msg.op {
+        LdapOp::BindRequest(request) => request.dn.clone(),
+        _ => session.bound_dn().to_string(),
+    };
+    let span = tracing::info_span!(
+        "ldap_request",
+        request_id = %format!("{}-{}", connection_id, msg.msgid),
+        user = %user,
+        operation = ldap_operation_name(&msg.op),
+    );
+    let start = std::time::Instant::now();
+    let msgid = msg.msgid;
+    if let LdapOp::SearchRequest(request) = &msg.op {
+        if get_persistent_search(&controls).is_none() {
+            let status = send_search_responses(resp, msgid, request, session)
+                .instrument(span.clone())
+                .await?;
+            span.in_scope(|| logging::log_access(&status, start.elapsed()));
+            return Ok(true);
+        }
+    }
+    let (result, response_controls) = process_message(msg.op, msgid, &controls, session)
+        .instrument(span.clone())
+        .await;
+    span.in_scope(|| {
+        let status = match &result {
+            Some(responses) => ldap_result_status(responses),
+            None => "Unbound".to_string(),
+        };
+        logging::log_access(&status, start.elapsed())
+    });
+    let result = match result {
         None => return Ok(false),
-        Some(result) => {
-            for result_op in result.into_iter() {
-                if let Err(e) = resp
-                    .send(LdapMsg {
-                        msgid: msg.msgid,
-                        op: result_op,
-                        ctrl: vec![],
-                    })
-                    .await
-                {
-                    bail!("Error while sending a response: {:?}", e);
-                }
-            }
+        Some(result) => result,
+    };
+    send_responses(
+        resp,
+        result.into_iter().map(|op| {
+            (
+                LdapMsg {
+                    msgid,
+                    op,
+                    ctrl: vec![],
+                },
+                response_controls.clone(),
+            )
+        }),
+    )
+    .await?;
+    Ok(true)
+}
 
-            if let Err(e) = resp.flush().await {
-                bail!("Error while flushing responses: {:?}", e);
-            }
+/// Returns the responses, `None` to close the connection, and the controls to attach to the
+/// responses.
+async fn process_message<Backend>(
+    op: LdapOp,
+    msgid: i32,
+    controls: &[RawControl],
+    session: &mut LdapHandler<Backend>,
+) -> (Option<Vec<LdapOp>>, Vec<RawControl>)
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler,
+{
+    // The controls to attach to the responses.
+    let mut response_controls = vec![];
+    let result = match (op, get_persistent_search(controls)) {
+        (LdapOp::SearchRequest(request), Some(Ok(options))) => {
+            session.do_persistent_search(msgid, request, options).await
+        }
+        (LdapOp::BindRequest(request), _)
+            if controls.iter().any(|c| c.oid == PASSWORD_POLICY_OID) =>
+        {
+            let (code, message, error) = session.do_bind_with_password_policy(&request).await;
+            response_controls.push(make_password_policy_response(error));
+            vec![make_bind_response(code, message)]
         }
+        (LdapOp::SearchRequest(_), Some(Err(e))) => {
+            vec![LdapOp::SearchResultDone(LdapResult {
+                code: LdapResultCode::ProtocolError,
+                matcheddn: "".to_string(),
+                message: format!("Invalid persistent search control: {:#}", e),
+                referral: vec![],
+            })]
+        }
+        (op, _) => match session.handle_ldap_message(op).await {
+            None => return (None, response_controls),
+            Some(result) => result,
+        },
+    };
+    (Some(result), response_controls)
+}
+
+/// Sends the changed entries to the persistent searches that are interested in them.
+async fn handle_change<Backend, W: AsyncWrite + Unpin>(
+    change: ChangeEvent,
+    resp: &mut ResponseWriter<W>,
+    session: &mut LdapHandler<Backend>,
+) -> Result<()>
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler,
+{
+    let notifications = session.get_change_notifications(&change).await;
+    send_responses(
+        resp,
+        notifications.into_iter().map(|notification| {
+            let controls = if notification.return_entry_change_control {
+                vec![make_entry_change_notification(
+                    notification.change_type,
+                    notification.previous_dn.as_deref(),
+                )]
+            } else {
+                vec![]
+            };
+            (
+                LdapMsg {
+                    msgid: notification.msgid,
+                    op: LdapOp::SearchResultEntry(notification.entry),
+                    ctrl: vec![],
+                },
+                controls,
+            )
+        }),
+    )
+    .await
+}
+
+async fn next_change(
+    changes: &mut Option<broadcast::Receiver<ChangeEvent>>,
+) -> std::result::Result<ChangeEvent, broadcast::error::RecvError> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        // No persistent search, nothing to wait for.
+        None => futures_util::future::pending().await,
     }
-    Ok(true)
 }
 
-pub fn build_ldap_server<Backend>(
-    config: &Configuration,
+/// Serves the LDAP requests of a connection, in clear or after the TLS handshake, until it's closed.
+async fn handle_connection<Backend, S>(
+    stream: S,
+    peer_address: Option<IpAddr>,
     backend_handler: Backend,
-    server_builder: ServerBuilder,
-) -> Result<ServerBuilder>
+    ldap_base_dn: String,
+    ldap_user_dn: String,
+) -> Result<()>
 where
-    Backend: BackendHandler + LoginHandler + OpaqueHandler + 'static,
+    Backend: BackendHandler + LoginHandler + OpaqueHandler + ChangeNotifier,
+    S: AsyncRead + AsyncWrite + Unpin,
 {
     use futures_util::StreamExt;
 
+    let connection_id = CONNECTION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    // Configure the codec etc.
+    let (r, w) = tokio::io::split(stream);
+    let mut requests = FramedRead::new(r, ExtendedLdapCodec);
+    let mut resp = FramedWrite::new(w, ExtendedLdapCodec);
+
+    let mut session = LdapHandler::new(backend_handler.clone(), ldap_base_dn, ldap_user_dn)
+        .with_peer_address(peer_address);
+    // Only subscribed to the changes while there are persistent searches.
+    let mut changes = None;
+
+    loop {
+        tokio::select! {
+            msg = requests.next() => {
+                let msg = match msg {
+                    None => break,
+                    Some(msg) => msg
+                        .map_err(|e| anyhow!("Error while receiving LDAP op: {:#}", e))?,
+                };
+                if changes.is_none() && get_persistent_search(&msg.1).is_some() {
+                    // Subscribe before the initial search to avoid missing changes.
+                    changes = Some(backend_handler.subscribe_to_changes());
+                }
+                if !handle_incoming_message(msg, &mut resp, &mut session, connection_id).await? {
+                    break;
+                }
+                if !session.has_persistent_searches() {
+                    changes = None;
+                }
+            }
+            change = next_change(&mut changes) => match change {
+                Ok(change) => handle_change(change, &mut resp, &mut session).await?,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Persistent search missed {} changes", count)
+                }
+                Err(broadcast::error::RecvError::Closed) => changes = None,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The service of the LDAP listener, or of the LDAPS one with `tls`.
+fn ldap_service_factory<Backend>(
+    config: &Configuration,
+    backend_handler: Backend,
+    tls: Option<TlsStore>,
+) -> impl ServiceFactory<TcpStream>
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler + ChangeNotifier + 'static,
+{
     let ldap_base_dn = config.ldap_base_dn.clone();
     let ldap_user_dn = config.ldap_user_dn.clone();
-    Ok(
-        server_builder.bind("ldap", ("0.0.0.0", config.ldap_port), move || {
+    move || {
+        let backend_handler = backend_handler.clone();
+        let ldap_base_dn = ldap_base_dn.clone();
+        let ldap_user_dn = ldap_user_dn.clone();
+        let tls = tls.clone();
+        fn_service(move |stream: TcpStream| {
             let backend_handler = backend_handler.clone();
             let ldap_base_dn = ldap_base_dn.clone();
             let ldap_user_dn = ldap_user_dn.clone();
-            fn_service(move |mut stream: TcpStream| {
-                let backend_handler = backend_handler.clone();
-                let ldap_base_dn = ldap_base_dn.clone();
-                let ldap_user_dn = ldap_user_dn.clone();
-                async move {
-                    // Configure the codec etc.
-                    let (r, w) = stream.split();
-                    let mut requests = FramedRead::new(r, LdapCodec);
-                    let mut resp = FramedWrite::new(w, LdapCodec);
-
-                    let mut session = LdapHandler::new(backend_handler, ldap_base_dn, ldap_user_dn);
-
-                    while let Some(msg) = requests.next().await {
-                        if !handle_incoming_message(msg, &mut resp, &mut session).await? {
-                            break;
-                        }
+            let tls = tls.clone();
+            async move {
+                let peer_address = stream.peer_addr().ok().map(|addr| addr.ip());
+                match tls {
+                    None => {
+                        handle_connection(
+                            stream,
+                            peer_address,
+                            backend_handler,
+                            ldap_base_dn,
+                            ldap_user_dn,
+                        )
+                        .await
+                    }
+                    Some(tls) => {
+                        let stream = tls.accept(stream).await?;
+                        handle_connection(
+                            stream,
+                            peer_address,
+                            backend_handler,
+                            ldap_base_dn,
+                            ldap_user_dn,
+                        )
+                        .await
                     }
-
-                    Ok(stream)
                 }
-            })
-            .map_err(|err: anyhow::Error| error!("Service Error: {:?}", err))
-            // catch
-            .and_then(move |_| {
-                // finally
-                ok(())
-            })
-        })?,
-    )
+            }
+        })
+        .map_err(|err: anyhow::Error| error!("Service Error: {:?}", err))
+        // catch
+        .and_then(move |_| {
+            // finally
+            ok(())
+        })
+    }
+}
+
+/// Listens for LDAP on `ldap_port`, and for LDAPS on `ldaps_port` when TLS is enabled.
+pub fn build_ldap_server<Backend>(
+    config: &Configuration,
+    backend_handler: Backend,
+    tls: Option<&TlsStore>,
+    server_builder: ServerBuilder,
+) -> Result<ServerBuilder>
+where
+    Backend: BackendHandler + LoginHandler + OpaqueHandler + ChangeNotifier + 'static,
+{
+    let server_builder = crate::infra::listeners::bind(
+        server_builder,
+        "ldap",
+        &config.ldap_addresses,
+        config.ldap_port,
+        ldap_service_factory(config, backend_handler.clone(), None),
+    )?;
+    match tls {
+        None => Ok(server_builder),
+        Some(tls) => crate::infra::listeners::bind(
+            server_builder,
+            "ldaps",
+            &config.ldap_addresses,
+            config.ldaps_port,
+            ldap_service_factory(config, backend_handler, Some(tls.clone())),
+        ),
+    }
 }
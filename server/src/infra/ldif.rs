@@ -0,0 +1,417 @@
+//! Export of the users and groups as LDIF (RFC 2849), with the same entries and attributes as the
+//! LDAP server, to migrate to another LDAP server or feed offline tools. The parser reads the LDIF
+//! files of the other servers for the import.
+
+use crate::{
+    domain::{
+        error::{DomainError, Result},
+        handler::{BackendHandler, API_TOKEN_PREFIX},
+        sql_backend_handler::SqlBackendHandler,
+        sql_tables::PoolOptions,
+    },
+    infra::{
+        auth_service::{check_if_api_token_is_valid, check_if_token_is_valid},
+        cli::{CommandOutput, ExportFormat, ExportOpts, RunOpts},
+        configuration, encryption,
+        ldap_handler::{make_ldap_search_group_result_entry, make_ldap_search_user_result_entry},
+        tcp_backend_handler::TcpBackendHandler,
+        tcp_server::{error_to_http_response, AppState},
+    },
+};
+use actix_web::{web, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use ldap3_server::proto::LdapSearchResultEntry;
+
+const USER_ATTRIBUTES: [&str; 8] = [
+    "objectClass",
+    "uid",
+    "mail",
+    "givenName",
+    "sn",
+    "cn",
+    "displayName",
+    "jpegPhoto",
+];
+const GROUP_ATTRIBUTES: [&str; 5] = ["objectClass", "cn", "description", "mail", "uniqueMember"];
+/// The attributes whose values are already base64-encoded by the LDAP handler.
+const BINARY_ATTRIBUTES: [&str; 1] = ["jpegPhoto"];
+/// Maximum length of a line, the rest is folded on the next lines.
+const MAX_LINE_LENGTH: usize = 76;
+
+/// Whether the value can be written as is, or must be base64-encoded (SAFE-STRING in the RFC).
+fn is_safe_string(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b.is_ascii() && b != b'\0' && b != b'\n' && b != b'\r')
+        && !value.starts_with(&[' ', ':', '<'][..])
+        && !value.ends_with(' ')
+}
+
+/// Writes the line, folded: the continuation lines start with a space.
+fn push_line(output: &mut String, line: &str) {
+    // The lines are ASCII: the unsafe values are base64-encoded.
+    let mut rest = line;
+    let mut max_length = MAX_LINE_LENGTH;
+    while rest.len() > max_length {
+        let (start, end) = rest.split_at(max_length);
+        output.push_str(start);
+        output.push_str("\n ");
+        rest = end;
+        max_length = MAX_LINE_LENGTH - 1;
+    }
+    output.push_str(rest);
+    output.push('\n');
+}
+
+fn push_attribute(output: &mut String, name: &str, value: &str) {
+    if BINARY_ATTRIBUTES.contains(&name) {
+        push_line(output, &format!("{}:: {}", name, value));
+    } else if is_safe_string(value) {
+        push_line(output, &format!("{}: {}", name, value));
+    } else {
+        push_line(output, &format!("{}:: {}", name, base64::encode(value)));
+    }
+}
+
+fn push_entry(output: &mut String, entry: &LdapSearchResultEntry) {
+    push_attribute(output, "dn", &entry.dn);
+    for attribute in &entry.attributes {
+        for value in &attribute.vals {
+            push_attribute(output, &attribute.atype, value);
+        }
+    }
+    output.push('\n');
+}
+
+fn organizational_unit(name: &str, base_dn: &str) -> LdapSearchResultEntry {
+    use ldap3_server::proto::LdapPartialAttribute;
+    LdapSearchResultEntry {
+        dn: format!("ou={},{}", name, base_dn),
+        attributes: vec![
+            LdapPartialAttribute {
+                atype: "objectClass".to_string(),
+                vals: vec!["organizationalUnit".to_string()],
+            },
+            LdapPartialAttribute {
+                atype: "ou".to_string(),
+                vals: vec![name.to_string()],
+            },
+        ],
+    }
+}
+
+/// All the users and groups, under `base_dn`. The base entry itself is left out, the target server
+/// usually has it already.
+pub async fn export_ldif<Backend: BackendHandler>(
+    backend_handler: &Backend,
+    base_dn: &str,
+) -> Result<String> {
+    let to_internal_error = |e: anyhow::Error| DomainError::InternalError(e.to_string());
+    let user_attributes: Vec<String> = USER_ATTRIBUTES.iter().map(|a| a.to_string()).collect();
+    let group_attributes: Vec<String> = GROUP_ATTRIBUTES.iter().map(|a| a.to_string()).collect();
+    let mut output = "version: 1\n\n".to_string();
+    push_entry(&mut output, &organizational_unit("people", base_dn));
+    push_entry(&mut output, &organizational_unit("groups", base_dn));
+    for user in backend_handler.list_users(None).await? {
+        let entry = make_ldap_search_user_result_entry(user, base_dn, &user_attributes)
+            .map_err(to_internal_error)?;
+        push_entry(&mut output, &entry);
+    }
+    for group in backend_handler.list_groups().await? {
+        let entry = make_ldap_search_group_result_entry(group, base_dn, &group_attributes)
+            .map_err(to_internal_error)?;
+        push_entry(&mut output, &entry);
+    }
+    Ok(output)
+}
+
+/// An entry of an LDIF file. The values are raw bytes, since they can be binary (e.g. photos).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdifEntry {
+    pub dn: String,
+    pub attributes: Vec<(String, Vec<u8>)>,
+}
+
+impl LdifEntry {
+    /// The values of the attribute, whose name is case-insensitive.
+    pub fn get<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.attributes
+            .iter()
+            .filter(move |(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The first value of the attribute, as text.
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .next()
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+}
+
+/// Parses the content records of an LDIF file. The change records are not supported.
+pub fn parse_ldif(input: &str) -> anyhow::Result<Vec<LdifEntry>> {
+    use anyhow::{bail, Context};
+    // Unfold the lines first, skipping the comments. The entries are separated by empty lines.
+    let mut records: Vec<Vec<(usize, String)>> = vec![Vec::new()];
+    let mut in_comment = false;
+    for (number, line) in input.lines().enumerate() {
+        let number = number + 1;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let Some(continuation) = line.strip_prefix(' ') {
+            if !in_comment {
+                match records.last_mut().unwrap().last_mut() {
+                    Some((_, previous)) => previous.push_str(continuation),
+                    None => bail!("Line {}: continuation of nothing", number),
+                }
+            }
+        } else if line.is_empty() {
+            in_comment = false;
+            if !records.last().unwrap().is_empty() {
+                records.push(Vec::new());
+            }
+        } else if line.starts_with('#') {
+            in_comment = true;
+        } else {
+            in_comment = false;
+            records.last_mut().unwrap().push((number, line.to_string()));
+        }
+    }
+    let mut entries = Vec::new();
+    for record in records {
+        let mut attributes = Vec::new();
+        for (number, line) in record {
+            let (name, value) = line
+                .split_once(':')
+                .context(format!("Line {}: expected `attribute: value`", number))?;
+            let value = if let Some(encoded) = value.strip_prefix(':') {
+                base64::decode(encoded.trim())
+                    .context(format!("Line {}: invalid base64", number))?
+            } else if value.starts_with('<') {
+                bail!("Line {}: values from URLs are not supported", number);
+            } else {
+                value.trim_start_matches(' ').as_bytes().to_vec()
+            };
+            attributes.push((name.to_string(), value));
+        }
+        if matches!(attributes.first(), Some((name, _)) if name == "version") {
+            attributes.remove(0);
+        }
+        match attributes.first() {
+            None => continue,
+            Some((name, _)) if !name.eq_ignore_ascii_case("dn") => {
+                bail!("Entry starting with `{}` instead of its `dn`", name)
+            }
+            _ => (),
+        }
+        let dn = String::from_utf8(attributes.remove(0).1).context("Invalid DN")?;
+        if attributes
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("changetype"))
+        {
+            bail!("Entry `{}`: the change records are not supported", dn);
+        }
+        entries.push(LdifEntry { dn, attributes });
+    }
+    Ok(entries)
+}
+
+async fn get_ldif<Backend>(data: web::Data<AppState<Backend>>, bearer: BearerAuth) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let validation_result = if bearer.token().starts_with(API_TOKEN_PREFIX) {
+        check_if_api_token_is_valid(&data, bearer.token()).await
+    } else {
+        check_if_token_is_valid(&data, bearer.token()).await
+    };
+    match validation_result {
+        Ok(v) if v.is_admin() => (),
+        Ok(_) => return HttpResponse::Forbidden().body("Only the admins can export the data"),
+        Err(e) => return HttpResponse::from_error(e),
+    }
+    match export_ldif(&data.backend_handler, &data.ldap_base_dn).await {
+        Ok(ldif) => HttpResponse::Ok()
+            .content_type("text/x-ldif; charset=utf-8")
+            .insert_header(("Content-Disposition", "attachment; filename=\"lldap.ldif\""))
+            .body(ldif),
+        Err(e) => error_to_http_response(e),
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    cfg.service(web::resource("/export.ldif").route(web::get().to(get_ldif::<Backend>)));
+}
+
+async fn run_export(opts: ExportOpts) -> anyhow::Result<CommandOutput> {
+    use anyhow::Context;
+    let config = configuration::init(RunOpts {
+        config_file: opts.config_file,
+        ldap_port: None,
+        ldaps_port: None,
+        verbose: false,
+    })?;
+    crate::infra::logging::init(config.clone())?;
+    let pool = PoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .context("Could not connect to the database")?;
+    let backend_handler = SqlBackendHandler::new(config.clone(), pool);
+    let output = match opts.format {
+        ExportFormat::Ldif => export_ldif(&backend_handler, &config.ldap_base_dn).await?,
+    };
+    let mut result = CommandOutput::default();
+    match opts.output_file {
+        None => {
+            result
+                .data
+                .insert("ldif".to_string(), output.clone().into());
+            // The LDIF already ends with an empty line.
+            result.text = Some(output.trim_end().to_string());
+        }
+        Some(path) => {
+            // Replaces the previous export, with a new private file.
+            let file = std::path::Path::new(&path);
+            if file.exists() {
+                std::fs::remove_file(file).context(format!("unable to replace '{}'", path))?;
+            }
+            encryption::write_private_file(
+                file,
+                output.as_bytes(),
+                config.backup_encryption_key.as_deref(),
+            )?;
+            result.data.insert("output_file".to_string(), path.into());
+        }
+    }
+    Ok(result)
+}
+
+pub fn export_command(opts: ExportOpts) -> anyhow::Result<CommandOutput> {
+    actix_rt::System::new().block_on(run_export(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::handler::{Group, GroupId, MockTestBackendHandler, User};
+
+    #[test]
+    fn test_push_attribute() {
+        let mut output = String::new();
+        push_attribute(&mut output, "cn", "Bob");
+        push_attribute(&mut output, "cn", "Bébé");
+        push_attribute(&mut output, "description", " leading space");
+        push_attribute(&mut output, "jpegPhoto", "/9j/");
+        assert_eq!(
+            output,
+            "cn: Bob\ncn:: QsOpYsOp\ndescription:: IGxlYWRpbmcgc3BhY2U=\njpegPhoto:: /9j/\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_ldif() {
+        let input = "version: 1
+
+# A comment,
+  folded
+dn: uid=bob,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+uid: bob
+cn:: QsO0Yg==
+description: a long
+  description
+
+dn: cn=admins,ou=groups,dc=example,dc=com
+member: uid=bob,ou=people,dc=example,dc=com
+";
+        let entries = parse_ldif(input).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].dn, "uid=bob,ou=people,dc=example,dc=com");
+        assert_eq!(entries[0].get_string("UID"), Some("bob".to_string()));
+        assert_eq!(entries[0].get_string("cn"), Some("Bôb".to_string()));
+        assert_eq!(
+            entries[0].get_string("description"),
+            Some("a long description".to_string())
+        );
+        assert_eq!(entries[1].get("member").count(), 1);
+        parse_ldif("uid: bob\n").unwrap_err();
+        parse_ldif("dn: uid=bob\nchangetype: delete\n").unwrap_err();
+    }
+
+    #[test]
+    fn test_export_parses_back() {
+        let mut output = String::new();
+        push_line(&mut output, "dn: cn=bob,ou=people,dc=example,dc=com");
+        push_attribute(&mut output, "description", &"é".repeat(60));
+        let entries = parse_ldif(&output).unwrap();
+        assert_eq!(entries[0].get_string("description"), Some("é".repeat(60)));
+    }
+
+    #[test]
+    fn test_push_line_folds() {
+        let mut output = String::new();
+        push_line(&mut output, &"a".repeat(200));
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 76);
+        assert_eq!(lines[1], format!(" {}", "a".repeat(75)));
+        assert_eq!(lines[2], format!(" {}", "a".repeat(49)));
+    }
+
+    #[actix_rt::test]
+    async fn test_export_ldif() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().return_once(|_| {
+            Ok(vec![User {
+                user_id: "bob".to_string(),
+                email: "bob@bobmail.bob".to_string(),
+                display_name: "Bôb".to_string(),
+                first_name: "Bob".to_string(),
+                ..Default::default()
+            }])
+        });
+        mock.expect_list_groups().return_once(|| {
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "lldap_admin".to_string(),
+                description: None,
+                email: None,
+                users: vec!["bob".to_string()],
+            }])
+        });
+        assert_eq!(
+            export_ldif(&mock, "dc=example,dc=com").await.unwrap(),
+            r#"version: 1
+
+dn: ou=people,dc=example,dc=com
+objectClass: organizationalUnit
+ou: people
+
+dn: ou=groups,dc=example,dc=com
+objectClass: organizationalUnit
+ou: groups
+
+dn: cn=bob,ou=people,dc=example,dc=com
+objectClass: inetOrgPerson
+objectClass: posixAccount
+objectClass: mailAccount
+objectClass: person
+uid: bob
+mail: bob@bobmail.bob
+givenName: Bob
+cn:: QsO0Yg==
+displayName:: QsO0Yg==
+
+dn: cn=lldap_admin,ou=groups,dc=example,dc=com
+objectClass: groupOfUniqueNames
+cn: lldap_admin
+uniqueMember: cn=bob,ou=people,dc=example,dc=com
+
+"#
+        );
+    }
+}
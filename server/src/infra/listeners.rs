@@ -0,0 +1,165 @@
+//! The addresses the LDAP and HTTP servers listen on, from `ldap_addresses` and `http_addresses`.
+
+use actix_rt::net::TcpStream;
+use actix_server::{ServerBuilder, ServiceFactory};
+use anyhow::{anyhow, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tracing::info;
+
+/// Parses "IP" or "IP:port" (with brackets for IPv6, e.g. "[::1]:3890"). Without a port, the
+/// default one is used.
+pub fn parse_listen_address(address: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(socket_address) = address.parse::<SocketAddr>() {
+        return Ok(socket_address);
+    }
+    let ip = address.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, default_port))
+        .map_err(|_| {
+            anyhow!(
+                "Invalid listen address `{}`, expected an IP address with an optional port",
+                address
+            )
+        })
+}
+
+/// Whether the host can listen on IPv6: it's often disabled in containers.
+fn ipv6_available() -> bool {
+    std::net::TcpListener::bind((Ipv6Addr::UNSPECIFIED, 0)).is_ok()
+}
+
+/// The configured addresses. By default, the IPv6 wildcard, which also accepts the IPv4
+/// connections on dual-stack hosts, or the IPv4 wildcard when IPv6 is not available.
+pub fn listen_addresses(configured: &[String], port: u16) -> Result<Vec<SocketAddr>> {
+    default_or_configured_addresses(configured, port, ipv6_available)
+}
+
+fn default_or_configured_addresses(
+    configured: &[String],
+    port: u16,
+    ipv6_available: impl FnOnce() -> bool,
+) -> Result<Vec<SocketAddr>> {
+    if configured.is_empty() {
+        let ip: IpAddr = if ipv6_available() {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        };
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    configured
+        .iter()
+        .map(|address| parse_listen_address(address, port))
+        .collect()
+}
+
+/// An address to reach a server listening on `address` from the same host.
+pub fn local_address(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), address.port())
+        }
+        _ => address,
+    }
+}
+
+/// Binds the server to the socket passed by systemd if there is one, or else to each configured
+/// address.
+pub fn bind<F>(
+    server_builder: ServerBuilder,
+    name: &str,
+    configured: &[String],
+    port: u16,
+    factory: F,
+) -> Result<ServerBuilder>
+where
+    F: ServiceFactory<TcpStream>,
+{
+    if let Some(listener) = crate::infra::systemd::take_listener(name) {
+        info!("Using the {} socket passed by systemd", name);
+        return server_builder
+            .listen(name, listener, factory)
+            .with_context(|| format!("While listening on the {} socket passed by systemd", name));
+    }
+    let mut server_builder = server_builder;
+    for address in listen_addresses(configured, port)? {
+        server_builder = server_builder
+            .bind(name, address, factory.clone())
+            .with_context(|| format!("While listening for {} on {}", name, address))?;
+        info!("Listening for {} on {}", name, address);
+    }
+    Ok(server_builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_address() {
+        assert_eq!(
+            parse_listen_address("127.0.0.1", 3890).unwrap(),
+            "127.0.0.1:3890".parse().unwrap()
+        );
+        assert_eq!(
+            parse_listen_address("127.0.0.1:389", 3890).unwrap(),
+            "127.0.0.1:389".parse().unwrap()
+        );
+        assert_eq!(
+            parse_listen_address("::1", 3890).unwrap(),
+            "[::1]:3890".parse().unwrap()
+        );
+        assert_eq!(
+            parse_listen_address("[::1]", 3890).unwrap(),
+            "[::1]:3890".parse().unwrap()
+        );
+        assert_eq!(
+            parse_listen_address("[::1]:389", 3890).unwrap(),
+            "[::1]:389".parse().unwrap()
+        );
+        assert!(parse_listen_address("localhost", 3890).is_err());
+    }
+
+    #[test]
+    fn test_listen_addresses() {
+        assert_eq!(
+            default_or_configured_addresses(&[], 3890, || true).unwrap(),
+            vec!["[::]:3890".parse().unwrap()]
+        );
+        assert_eq!(
+            default_or_configured_addresses(&[], 3890, || false).unwrap(),
+            vec!["0.0.0.0:3890".parse().unwrap()]
+        );
+        assert_eq!(
+            default_or_configured_addresses(
+                &["192.168.1.2".to_string(), "::1".to_string()],
+                3890,
+                || panic!("Not needed with configured addresses")
+            )
+            .unwrap(),
+            vec![
+                "192.168.1.2:3890".parse().unwrap(),
+                "[::1]:3890".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_address() {
+        assert_eq!(
+            local_address("[::]:3890".parse().unwrap()),
+            "[::1]:3890".parse().unwrap()
+        );
+        assert_eq!(
+            local_address("0.0.0.0:3890".parse().unwrap()),
+            "127.0.0.1:3890".parse().unwrap()
+        );
+        assert_eq!(
+            local_address("192.168.1.2:3890".parse().unwrap()),
+            "192.168.1.2:3890".parse().unwrap()
+        );
+    }
+}
@@ -1,25 +1,182 @@
 use crate::infra::configuration::Configuration;
-use anyhow::Context;
+use anyhow::{bail, Context};
+use once_cell::sync::OnceCell;
 use tracing::subscriber::set_global_default;
 use tracing_log::LogTracer;
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload, EnvFilter, Registry};
+
+/// Target of the access log: one event per HTTP request or LDAP operation, with its status and
+/// latency. The request ID, user and operation are fields of the enclosing request span.
+pub const ACCESS_LOG_TARGET: &str = "lldap::access";
+
+/// Handle to change the log filter of the running server, with the default level.
+static FILTER_HANDLE: OnceCell<(reload::Handle<EnvFilter, Registry>, tracing::Level)> =
+    OnceCell::new();
+
+/// Short names for the subsystems, expanded to the targets (module paths) that they log from.
+const SUBSYSTEMS: [(&str, &[&str]); 4] = [
+    (
+        "ldap",
+        &[
+            "lldap::infra::ldap_handler",
+            "lldap::infra::ldap_server",
+            "lldap::infra::ldap_codec",
+            "ldap3_server",
+        ],
+    ),
+    ("sql", &["lldap::domain", "sqlx"]),
+    (
+        "http",
+        &[
+            "lldap::infra::tcp_server",
+            "lldap::infra::auth_service",
+            "lldap::infra::graphql",
+            "lldap::infra::reset_page",
+            "actix_web",
+            "actix_http",
+            "actix_server",
+            "tracing_actix_web",
+        ],
+    ),
+    ("access", &[ACCESS_LOG_TARGET]),
+];
+
+const LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
 
 pub fn init(config: Configuration) -> anyhow::Result<()> {
-    let max_log_level = log_level_from_config(config);
-    let subscriber = tracing_subscriber::fmt()
-        .with_timer(tracing_subscriber::fmt::time::time())
-        .with_target(false)
-        .with_level(true)
-        .with_max_level(max_log_level)
-        .finish();
-    LogTracer::init().context("Failed to set logger")?;
-    set_global_default(subscriber).context("Failed to set subscriber")?;
+    let default_level = log_level_from_config(&config);
+    let filter = make_filter(default_level, &config.log_levels)
+        .context("Invalid `log_levels` configuration")?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let subscriber = Registry::default().with(filter);
+    if config.log_format == "json" {
+        // One object per line, with the fields of the event at the top level and the fields of
+        // the request span (request ID, user, operation) under "span".
+        install(
+            subscriber.with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(false),
+            ),
+        )?;
+    } else {
+        install(
+            subscriber.with(
+                fmt::layer()
+                    .with_timer(fmt::time::time())
+                    .with_target(false)
+                    .with_level(true),
+            ),
+        )?;
+    }
+    if FILTER_HANDLE.set((handle, default_level)).is_err() {
+        bail!("Logging was already initialized");
+    }
     Ok(())
 }
 
-fn log_level_from_config(config: Configuration) -> tracing::Level {
+fn install(subscriber: impl tracing::Subscriber + Send + Sync + 'static) -> anyhow::Result<()> {
+    LogTracer::init().context("Failed to set logger")?;
+    set_global_default(subscriber).context("Failed to set subscriber")
+}
+
+/// Records the authenticated user in the current request span.
+pub fn record_user(user: &str) {
+    tracing::Span::current().record("user", &user);
+}
+
+/// Logs the end of a request, from within its span.
+pub fn log_access(status: &str, latency: std::time::Duration) {
+    let latency_ms = latency.as_millis() as u64;
+    tracing::info!(
+        target: ACCESS_LOG_TARGET,
+        status,
+        latency_ms,
+        "{} in {}ms",
+        status,
+        latency_ms
+    );
+}
+
+/// Replaces the per-subsystem log levels of the running server, e.g. "ldap=debug,sql=warn".
+pub fn set_log_levels(log_levels: &str) -> anyhow::Result<()> {
+    let (handle, default_level) = FILTER_HANDLE.get().context("Logging is not initialized")?;
+    handle
+        .reload(make_filter(*default_level, log_levels)?)
+        .context("Could not update the log levels")
+}
+
+fn make_filter(default_level: tracing::Level, log_levels: &str) -> anyhow::Result<EnvFilter> {
+    let directives = std::iter::once(Ok(default_level.to_string().to_lowercase()))
+        .chain(
+            log_levels
+                .split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(expand_directive),
+        )
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(EnvFilter::try_new(directives.join(","))?)
+}
+
+/// Turns "subsystem=level" into the directives for all the targets of the subsystem. Other
+/// targets are kept as is.
+fn expand_directive(directive: &str) -> anyhow::Result<String> {
+    let (target, level) = match directive.split_once('=') {
+        Some((target, level)) => (target.trim(), level.trim().to_lowercase()),
+        None => bail!("Expected `target=level`, got `{}`", directive),
+    };
+    if target.is_empty() || !LEVELS.contains(&level.as_str()) {
+        bail!("Invalid log level directive `{}`", directive);
+    }
+    Ok(
+        match SUBSYSTEMS
+            .iter()
+            .find(|(subsystem, _)| *subsystem == target)
+        {
+            Some((_, targets)) => targets
+                .iter()
+                .map(|t| format!("{}={}", t, level))
+                .collect::<Vec<_>>()
+                .join(","),
+            None => format!("{}={}", target, level),
+        },
+    )
+}
+
+fn log_level_from_config(config: &Configuration) -> tracing::Level {
     if config.verbose {
         tracing::Level::DEBUG
     } else {
         tracing::Level::INFO
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_directive() {
+        assert_eq!(
+            expand_directive("sql=WARN").unwrap(),
+            "lldap::domain=warn,sqlx=warn"
+        );
+        assert_eq!(
+            expand_directive("lldap::infra::cli = debug").unwrap(),
+            "lldap::infra::cli=debug"
+        );
+        expand_directive("ldap").unwrap_err();
+        expand_directive("ldap=loud").unwrap_err();
+        expand_directive("=debug").unwrap_err();
+    }
+
+    #[test]
+    fn test_make_filter() {
+        make_filter(tracing::Level::INFO, "").unwrap();
+        make_filter(tracing::Level::INFO, "ldap=debug, sql=warn, http=info").unwrap();
+        make_filter(tracing::Level::INFO, "ldap=debug,sql").unwrap_err();
+    }
+}
@@ -1,12 +1,42 @@
+pub mod acme;
+pub mod api_client;
 pub mod auth_service;
+pub mod backup;
+pub mod bench;
+pub mod branding;
+pub mod change_events;
 pub mod cli;
+pub mod config_reload;
 pub mod configuration;
 pub mod db_cleaner;
+pub mod db_pool;
+pub mod encryption;
 pub mod graphql;
+pub mod health;
+pub mod import;
+pub mod jwt_keys;
 pub mod jwt_sql_tables;
+pub mod ldap_codec;
+pub mod ldap_filter;
 pub mod ldap_handler;
+pub mod ldap_search;
 pub mod ldap_server;
+pub mod ldif;
+pub mod listeners;
 pub mod logging;
+pub mod oidc;
+pub mod proof_of_work;
+pub mod rate_limit;
+pub mod reset_admin_password;
+pub mod reset_page;
+pub mod rest_api;
 pub mod sql_backend_handler;
+pub mod sql_migrations;
+#[cfg(feature = "mysql")]
+pub mod sqlite_migration;
+pub mod systemd;
 pub mod tcp_backend_handler;
 pub mod tcp_server;
+pub mod tls;
+pub mod upstream_sync;
+pub mod webauthn;
@@ -0,0 +1,675 @@
+//! Minimal OpenID Connect provider, for other applications to log their users in with LLDAP.
+//!
+//! Only the authorization code flow with PKCE (S256) is supported. The ID tokens are signed with
+//! the RS256 or EdDSA key of the JWTs, published at `/oidc/jwks`. The clients are declared in the
+//! configuration or registered through the GraphQL API.
+//!
+//! The users log in through the login page of the web app: `/oidc/authorize` sends them there
+//! when they have no session, or haven't agreed yet to share the requested scopes with the client.
+//! The app records the consent with `/oidc/consent`, and sends them back.
+
+use crate::{
+    domain::{error::DomainError, handler::BackendHandler, sql_backend_handler::hash_api_token},
+    infra::{
+        auth_service::{check_if_token_is_valid, verify_signature},
+        configuration::OidcClientConfig,
+        tcp_backend_handler::TcpBackendHandler,
+        tcp_server::{error_to_http_response, AppState},
+    },
+};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::{basic::BasicAuth, bearer::BearerAuth};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How long the client has to exchange an authorization code for the tokens.
+const CODE_LIFETIME_MINUTES: i64 = 5;
+
+/// An authorization code, waiting to be exchanged for the tokens by the client.
+struct AuthorizationCode {
+    client_id: String,
+    redirect_uri: String,
+    user: String,
+    scopes: Vec<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+    expiry: DateTime<Utc>,
+}
+
+/// The state of the provider, shared by all the HTTP workers.
+#[derive(Clone)]
+pub struct OidcProvider {
+    /// The public URL of LLDAP, without trailing slash.
+    issuer: String,
+    /// The clients from the configuration, on top of the ones registered through the API.
+    clients: Vec<OidcClientConfig>,
+    codes: Arc<Mutex<HashMap<String, AuthorizationCode>>>,
+}
+
+impl OidcProvider {
+    pub fn new(issuer: String, clients: Vec<OidcClientConfig>) -> Self {
+        Self {
+            issuer,
+            clients,
+            codes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/oidc/{}", self.issuer, path)
+    }
+}
+
+enum ClientSecret {
+    /// A public client, e.g. a native app: PKCE is its only protection.
+    None,
+    Configured(String),
+    /// Checked against the hash in the database.
+    Registered,
+}
+
+/// A client, from the configuration or registered through the API.
+struct Client {
+    client_id: String,
+    display_name: String,
+    redirect_uris: Vec<String>,
+    secret: ClientSecret,
+}
+
+async fn find_client<Backend>(
+    backend_handler: &Backend,
+    provider: &OidcProvider,
+    client_id: &str,
+) -> Result<Option<Client>, DomainError>
+where
+    Backend: BackendHandler,
+{
+    if let Some(client) = provider.clients.iter().find(|c| c.client_id == client_id) {
+        return Ok(Some(Client {
+            client_id: client.client_id.clone(),
+            display_name: client
+                .display_name
+                .clone()
+                .unwrap_or_else(|| client.client_id.clone()),
+            redirect_uris: client.redirect_uris.clone(),
+            secret: match &client.client_secret {
+                Some(secret) => ClientSecret::Configured(secret.clone()),
+                None => ClientSecret::None,
+            },
+        }));
+    }
+    Ok(backend_handler
+        .list_oidc_clients()
+        .await?
+        .into_iter()
+        .find(|c| c.client_id == client_id)
+        .map(|c| Client {
+            client_id: c.client_id,
+            display_name: c.display_name,
+            redirect_uris: c.redirect_uris,
+            secret: ClientSecret::Registered,
+        }))
+}
+
+async fn authenticate_client<Backend>(
+    backend_handler: &Backend,
+    client: &Client,
+    secret: Option<&str>,
+) -> Result<bool, DomainError>
+where
+    Backend: TcpBackendHandler,
+{
+    match (&client.secret, secret) {
+        (ClientSecret::None, _) => Ok(true),
+        // Compare the hashes, so that the time taken doesn't leak the secret.
+        (ClientSecret::Configured(expected), Some(secret)) => {
+            Ok(hash_api_token(expected) == hash_api_token(secret))
+        }
+        (ClientSecret::Registered, Some(secret)) => {
+            backend_handler
+                .check_oidc_client_secret(&client.client_id, secret)
+                .await
+        }
+        (_, None) => Ok(false),
+    }
+}
+
+/// Whether the PKCE `code_verifier` matches the `code_challenge` (S256 method).
+fn verify_pkce(code_challenge: &str, code_verifier: &str) -> bool {
+    use sha2::{Digest, Sha256};
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    ) == code_challenge
+}
+
+fn random_string(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+/// Appends the parameters to the query of `uri`.
+fn add_query_params(uri: &str, params: &[(&str, &str)]) -> String {
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}{}",
+        uri,
+        separator,
+        serde_urlencoded::to_string(params).unwrap()
+    )
+}
+
+fn redirect(location: String) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Sends the user to the login page, which asks them to log in if needed, then to agree to
+/// continue to the client, and sends them back to the authorization request. The name of the
+/// client isn't in the URL, which anyone can write: the app looks it up with `/oidc/client`.
+fn redirect_to_login_page(http_request: &HttpRequest) -> HttpResponse {
+    let continue_url = format!("/oidc/authorize?{}", http_request.query_string());
+    redirect(add_query_params(
+        "/login",
+        &[("oidc_continue", continue_url.as_str())],
+    ))
+}
+
+/// Whether the user has to be asked before sharing the `requested` scopes with the client, given
+/// the ones they agreed to share before, if any.
+fn needs_consent(granted: Option<&[String]>, requested: &[String]) -> bool {
+    match granted {
+        None => true,
+        Some(granted) => requested.iter().any(|s| !granted.contains(s)),
+    }
+}
+
+/// The claims about the user allowed by the scopes, for the ID token and the userinfo endpoint.
+async fn get_user_claims<Backend>(
+    backend_handler: &Backend,
+    user_id: &str,
+    scopes: &[String],
+) -> Result<Map<String, Value>, DomainError>
+where
+    Backend: BackendHandler,
+{
+    let user = backend_handler.get_user_details(user_id).await?;
+    if !user.is_active() {
+        return Err(DomainError::AuthenticationError(
+            "User is disabled or expired".to_string(),
+        ));
+    }
+    let mut claims = Map::new();
+    claims.insert("sub".to_string(), json!(user.user_id));
+    if scopes.iter().any(|s| s == "profile") {
+        claims.insert("preferred_username".to_string(), json!(user.user_id));
+        claims.insert("name".to_string(), json!(user.display_name));
+        claims.insert("given_name".to_string(), json!(user.first_name));
+        claims.insert("family_name".to_string(), json!(user.last_name));
+    }
+    if scopes.iter().any(|s| s == "email") {
+        claims.insert("email".to_string(), json!(user.email));
+    }
+    if scopes.iter().any(|s| s == "groups") {
+        let mut groups = backend_handler
+            .get_user_groups(user_id)
+            .await?
+            .into_iter()
+            .map(|g| g.1)
+            .collect::<Vec<_>>();
+        groups.sort();
+        claims.insert("groups".to_string(), json!(groups));
+    }
+    Ok(claims)
+}
+
+/// The claims of the access tokens, only valid for the userinfo endpoint.
+#[derive(Clone, Serialize, Deserialize)]
+struct AccessTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    exp: i64,
+    iat: i64,
+    scope: String,
+}
+
+async fn get_discovery<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse
+where
+    Backend: 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    HttpResponse::Ok().json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": provider.endpoint("authorize"),
+        "token_endpoint": provider.endpoint("token"),
+        "userinfo_endpoint": provider.endpoint("userinfo"),
+        "jwks_uri": provider.endpoint("jwks"),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": data.jwt_keys.public_algorithm().into_iter().collect::<Vec<_>>(),
+        "scopes_supported": ["openid", "profile", "email", "groups"],
+        "claims_supported": [
+            "sub", "preferred_username", "name", "given_name", "family_name", "email", "groups",
+        ],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn get_jwks<Backend>(data: web::Data<AppState<Backend>>) -> HttpResponse
+where
+    Backend: 'static,
+{
+    match (&data.oidc, data.jwt_keys.public_jwks()) {
+        (Some(_), Some(jwks)) => HttpResponse::Ok().json(jwks),
+        _ => HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuthorizeRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    prompt: Option<String>,
+}
+
+async fn get_authorize<Backend>(
+    data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
+    request: web::Query<AuthorizeRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    let request = request.into_inner();
+    // Until the redirect URI is validated, the errors can't be sent back to the client.
+    let client = match find_client(&data.backend_handler, provider, &request.client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return HttpResponse::BadRequest().body("Unknown client"),
+        Err(e) => return error_to_http_response(e),
+    };
+    if !client.redirect_uris.contains(&request.redirect_uri) {
+        return HttpResponse::BadRequest().body("Unregistered redirect URI");
+    }
+    let state = request.state.as_deref().unwrap_or_default();
+    let error = |error: &str, description: &str| {
+        let mut params = vec![("error", error), ("error_description", description)];
+        if !state.is_empty() {
+            params.push(("state", state));
+        }
+        redirect(add_query_params(&request.redirect_uri, &params))
+    };
+    if request.response_type != "code" {
+        return error(
+            "unsupported_response_type",
+            "Only the authorization code flow is supported",
+        );
+    }
+    let scopes = request
+        .scope
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !scopes.iter().any(|s| s == "openid") {
+        return error("invalid_scope", "The openid scope is required");
+    }
+    let code_challenge = match (&request.code_challenge, &request.code_challenge_method) {
+        (Some(challenge), Some(method)) if method == "S256" => challenge.clone(),
+        _ => return error("invalid_request", "PKCE with the S256 method is required"),
+    };
+    let user = match http_request.cookie("token") {
+        Some(token) => check_if_token_is_valid(&data, token.value())
+            .await
+            .ok()
+            .map(|v| v.user),
+        None => None,
+    };
+    let user = match user {
+        Some(user) => user,
+        None if request.prompt.as_deref() == Some("none") => {
+            return error("login_required", "The user is not logged in")
+        }
+        None => return redirect_to_login_page(&http_request),
+    };
+    let consent = match data
+        .backend_handler
+        .get_oidc_consent(&user, &client.client_id)
+        .await
+    {
+        Ok(consent) => consent,
+        Err(e) => return error_to_http_response(e),
+    };
+    if needs_consent(consent.as_deref(), &scopes) {
+        if request.prompt.as_deref() == Some("none") {
+            return error(
+                "consent_required",
+                "The user hasn't agreed to share these scopes yet",
+            );
+        }
+        return redirect_to_login_page(&http_request);
+    }
+    let code = random_string(32);
+    let now = Utc::now();
+    {
+        let mut codes = provider.codes.lock().unwrap();
+        codes.retain(|_, c| c.expiry > now);
+        codes.insert(
+            code.clone(),
+            AuthorizationCode {
+                client_id: client.client_id,
+                redirect_uri: request.redirect_uri.clone(),
+                user,
+                scopes,
+                nonce: request.nonce.clone(),
+                code_challenge,
+                expiry: now + chrono::Duration::minutes(CODE_LIFETIME_MINUTES),
+            },
+        );
+    }
+    let mut params = vec![("code", code.as_str())];
+    if !state.is_empty() {
+        params.push(("state", state));
+    }
+    redirect(add_query_params(&request.redirect_uri, &params))
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    code_verifier: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+fn token_error(error: &str, description: &str) -> HttpResponse {
+    let body = json!({ "error": error, "error_description": description });
+    if error == "invalid_client" {
+        HttpResponse::Unauthorized().json(body)
+    } else {
+        HttpResponse::BadRequest().json(body)
+    }
+}
+
+async fn post_token<Backend>(
+    data: web::Data<AppState<Backend>>,
+    basic: Option<BasicAuth>,
+    request: web::Form<TokenRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    let request = request.into_inner();
+    if request.grant_type != "authorization_code" {
+        return token_error(
+            "unsupported_grant_type",
+            "Only the authorization_code grant is supported",
+        );
+    }
+    let (client_id, client_secret) = match &basic {
+        Some(basic) => (
+            basic.user_id().to_string(),
+            basic.password().map(|p| p.to_string()),
+        ),
+        None => match request.client_id {
+            Some(client_id) => (client_id, request.client_secret),
+            None => return token_error("invalid_client", "Missing client ID"),
+        },
+    };
+    let client = match find_client(&data.backend_handler, provider, &client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return token_error("invalid_client", "Unknown client"),
+        Err(e) => return error_to_http_response(e),
+    };
+    match authenticate_client(&data.backend_handler, &client, client_secret.as_deref()).await {
+        Ok(true) => (),
+        Ok(false) => return token_error("invalid_client", "Invalid client secret"),
+        Err(e) => return error_to_http_response(e),
+    }
+    // The codes can only be used once, even if the exchange fails.
+    let code = match provider.codes.lock().unwrap().remove(&request.code) {
+        Some(code) if code.expiry > Utc::now() => code,
+        _ => return token_error("invalid_grant", "Invalid or expired code"),
+    };
+    if code.client_id != client.client_id || code.redirect_uri != request.redirect_uri {
+        return token_error("invalid_grant", "The code was issued to another client");
+    }
+    if !verify_pkce(&code.code_challenge, &request.code_verifier) {
+        return token_error("invalid_grant", "Invalid code verifier");
+    }
+    let mut id_claims = match get_user_claims(&data.backend_handler, &code.user, &code.scopes).await
+    {
+        Ok(claims) => claims,
+        Err(e) => return error_to_http_response(e),
+    };
+    let now = Utc::now();
+    let exp = (now + data.jwt_duration).timestamp();
+    id_claims.insert("iss".to_string(), json!(provider.issuer));
+    id_claims.insert("aud".to_string(), json!(client.client_id));
+    id_claims.insert("iat".to_string(), json!(now.timestamp()));
+    id_claims.insert("exp".to_string(), json!(exp));
+    if let Some(nonce) = code.nonce {
+        id_claims.insert("nonce".to_string(), json!(nonce));
+    }
+    let access_token = data.jwt_keys.sign(&AccessTokenClaims {
+        iss: provider.issuer.clone(),
+        sub: code.user,
+        aud: client.client_id,
+        exp,
+        iat: now.timestamp(),
+        scope: code.scopes.join(" "),
+    });
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": data.jwt_duration.num_seconds(),
+            "scope": code.scopes.join(" "),
+            "id_token": data.jwt_keys.sign(&id_claims),
+        }))
+}
+
+async fn get_userinfo<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    let claims = match verify_signature::<AccessTokenClaims>(&data.jwt_keys, bearer.token()) {
+        Ok(claims) => claims,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+    if claims.iss != provider.issuer || claims.exp < Utc::now().timestamp() {
+        return HttpResponse::Unauthorized().body("Invalid or expired access token");
+    }
+    let scopes = claims
+        .scope
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    match get_user_claims(&data.backend_handler, &claims.sub, &scopes).await {
+        Ok(claims) => HttpResponse::Ok().json(claims),
+        Err(e) => error_to_http_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ClientRequest {
+    client_id: String,
+}
+
+#[derive(Serialize)]
+struct ClientResponse {
+    client_id: String,
+    display_name: String,
+}
+
+/// The name of a client to show on the login and consent pages.
+async fn get_client<Backend>(
+    data: web::Data<AppState<Backend>>,
+    request: web::Query<ClientRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    match find_client(&data.backend_handler, provider, &request.client_id).await {
+        Ok(Some(client)) => HttpResponse::Ok().json(ClientResponse {
+            client_id: client.client_id,
+            display_name: client.display_name,
+        }),
+        Ok(None) => HttpResponse::NotFound().body("Unknown client"),
+        Err(e) => error_to_http_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConsentRequest {
+    client_id: String,
+    #[serde(default)]
+    scope: String,
+}
+
+/// Records that the logged in user agreed to share the scopes with the client, from the consent
+/// page of the web app. The session cookie is `SameSite=Strict`, so other sites can't consent on
+/// behalf of the user.
+async fn post_consent<Backend>(
+    data: web::Data<AppState<Backend>>,
+    http_request: HttpRequest,
+    request: web::Json<ConsentRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.oidc {
+        Some(p) => p,
+        None => return HttpResponse::NotFound().body("The OpenID Connect provider is disabled"),
+    };
+    let user = match http_request.cookie("token") {
+        Some(token) => match check_if_token_is_valid(&data, token.value()).await {
+            Ok(validation_result) => validation_result.user,
+            Err(e) => return HttpResponse::from_error(e),
+        },
+        None => return HttpResponse::Unauthorized().body("The user is not logged in"),
+    };
+    match find_client(&data.backend_handler, provider, &request.client_id).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return HttpResponse::BadRequest().body("Unknown client"),
+        Err(e) => return error_to_http_response(e),
+    }
+    let scopes = request
+        .scope
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    match data
+        .backend_handler
+        .set_oidc_consent(&user, &request.client_id, &scopes)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => error_to_http_response(e),
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    cfg.service(
+        web::resource("/.well-known/openid-configuration")
+            .route(web::get().to(get_discovery::<Backend>)),
+    )
+    .service(
+        web::scope("/oidc")
+            .service(web::resource("/authorize").route(web::get().to(get_authorize::<Backend>)))
+            .service(web::resource("/client").route(web::get().to(get_client::<Backend>)))
+            .service(web::resource("/consent").route(web::post().to(post_consent::<Backend>)))
+            .service(web::resource("/token").route(web::post().to(post_token::<Backend>)))
+            .service(
+                web::resource("/userinfo")
+                    .route(web::get().to(get_userinfo::<Backend>))
+                    .route(web::post().to(get_userinfo::<Backend>)),
+            )
+            .service(web::resource("/jwks").route(web::get().to(get_jwks::<Backend>))),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_pkce() {
+        // From RFC 7636, appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_pkce(challenge, verifier));
+        assert!(!verify_pkce(challenge, "another verifier"));
+    }
+
+    #[test]
+    fn test_needs_consent() {
+        let scopes = |scopes: &[&str]| scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let requested = scopes(&["openid", "email"]);
+        assert!(needs_consent(None, &requested));
+        assert!(!needs_consent(
+            Some(&scopes(&["openid", "email", "groups"])),
+            &requested
+        ));
+        assert!(needs_consent(Some(&scopes(&["openid"])), &requested));
+    }
+
+    #[test]
+    fn test_add_query_params() {
+        assert_eq!(
+            add_query_params(
+                "https://app.example.com/cb",
+                &[("code", "a b"), ("state", "x")]
+            ),
+            "https://app.example.com/cb?code=a+b&state=x"
+        );
+        assert_eq!(
+            add_query_params("https://app.example.com/cb?app=1", &[("code", "c")]),
+            "https://app.example.com/cb?app=1&code=c"
+        );
+    }
+}
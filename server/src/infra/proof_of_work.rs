@@ -0,0 +1,141 @@
+//! The proof of work asked before the logins, with `login_proof_of_work_difficulty`.
+//!
+//! The challenges are signed rather than stored, so that requesting them costs nothing to the
+//! server: only the solved ones are remembered, until they expire, so that each one is used once.
+
+use crate::domain::error::DomainError;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use lldap_auth::proof_of_work::{is_solution, parse_header, Challenge};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// How long the browser has to solve a challenge and log in.
+const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+/// Issues and checks the challenges, shared by all the HTTP workers.
+#[derive(Clone)]
+pub struct ProofOfWork {
+    /// 0 disables the proof of work.
+    difficulty: u32,
+    /// Random for each run of the server: a restart invalidates the pending challenges.
+    key: Hmac<Sha256>,
+    /// The used challenges, with their expiry.
+    used: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+fn random_string(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            key: Hmac::new_varkey(random_string(32).as_bytes()).unwrap(),
+            used: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn sign(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = self.key.clone();
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// A new challenge, as "<expiry>.<random>.<signature>".
+    pub fn create_challenge(&self) -> Challenge {
+        if self.difficulty == 0 {
+            return Challenge {
+                challenge: String::new(),
+                difficulty: 0,
+            };
+        }
+        let expiry = Utc::now() + chrono::Duration::minutes(CHALLENGE_LIFETIME_MINUTES);
+        let payload = format!("{}.{}", expiry.timestamp(), random_string(16));
+        let signature = base64::encode_config(
+            self.sign(&payload).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        Challenge {
+            challenge: format!("{}.{}", payload, signature),
+            difficulty: self.difficulty,
+        }
+    }
+
+    /// Checks the solution sent in the proof of work header, and marks the challenge as used.
+    pub fn check(&self, solution: Option<&str>) -> Result<(), DomainError> {
+        if self.difficulty == 0 {
+            return Ok(());
+        }
+        let invalid =
+            || DomainError::AuthenticationError("Missing or invalid proof of work".into());
+        let (challenge, nonce) = solution.and_then(parse_header).ok_or_else(invalid)?;
+        let (payload, signature) = challenge.rsplit_once('.').ok_or_else(invalid)?;
+        let signature =
+            base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        self.sign(payload)
+            .verify(&signature)
+            .map_err(|_| invalid())?;
+        let expiry = payload
+            .split('.')
+            .next()
+            .and_then(|t| t.parse::<i64>().ok())
+            .map(|t| DateTime::<Utc>::from_utc(chrono::NaiveDateTime::from_timestamp(t, 0), Utc))
+            .ok_or_else(invalid)?;
+        let now = Utc::now();
+        if expiry < now || !is_solution(challenge, nonce, self.difficulty) {
+            return Err(invalid());
+        }
+        let mut used = self.used.lock().unwrap();
+        used.retain(|_, expiry| *expiry > now);
+        if used.insert(challenge.to_string(), expiry).is_some() {
+            return Err(DomainError::AuthenticationError(
+                "The proof of work was already used".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lldap_auth::proof_of_work::to_header;
+
+    #[test]
+    fn test_proof_of_work() {
+        let proof_of_work = ProofOfWork::new(8);
+        let challenge = proof_of_work.create_challenge();
+        assert_eq!(challenge.difficulty, 8);
+        let nonce = (0..)
+            .find(|n| is_solution(&challenge.challenge, *n, 8))
+            .unwrap();
+        let wrong_nonce = (0..)
+            .find(|n| !is_solution(&challenge.challenge, *n, 8))
+            .unwrap();
+        assert!(proof_of_work.check(None).is_err());
+        assert!(proof_of_work
+            .check(Some(&to_header(&challenge.challenge, wrong_nonce)))
+            .is_err());
+        let solution = to_header(&challenge.challenge, nonce);
+        proof_of_work.check(Some(&solution)).unwrap();
+        // Each challenge can only be used once.
+        assert!(proof_of_work.check(Some(&solution)).is_err());
+        // Another server (or a restart) doesn't accept it.
+        assert!(ProofOfWork::new(8).check(Some(&solution)).is_err());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let proof_of_work = ProofOfWork::new(0);
+        assert_eq!(proof_of_work.create_challenge().difficulty, 0);
+        proof_of_work.check(None).unwrap();
+    }
+}
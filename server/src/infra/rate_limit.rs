@@ -0,0 +1,248 @@
+//! Rate limits of the HTTP endpoints open to password guessing and abuse: the logins, the password
+//! changes, the GraphQL API and the REST API. The requests are counted per client IP (`http_rate_limit_per_ip`)
+//! and per user (`http_rate_limit_per_user`), in windows of a minute. The counts are kept in
+//! memory, shared by the HTTP workers: a restart resets them. The limits are reloaded on SIGHUP.
+
+use crate::infra::configuration::Configuration;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::{header, HeaderMap, StatusCode},
+    HttpResponse, ResponseError,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+
+/// Above this many keys, the expired windows are dropped.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Sent with a 429 status and a `Retry-After` header.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Too many requests, retry in {retry_after} seconds")]
+pub struct RateLimited {
+    pub retry_after: i64,
+}
+
+impl ResponseError for RateLimited {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header((header::RETRY_AFTER, self.retry_after.to_string()))
+            .body(self.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Requests per minute for each key, 0 disables the limit.
+    limit: Arc<AtomicU32>,
+    /// The start of the current window of each key, and its number of requests.
+    windows: Arc<Mutex<HashMap<String, (DateTime<Utc>, u32)>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit: Arc::new(AtomicU32::new(limit)),
+            windows: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the limit of all the clones, keeping the counts of the current windows.
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Counts a request for the key, or fails if the limit is reached.
+    pub fn check(&self, key: &str) -> Result<(), RateLimited> {
+        self.check_at(key, Utc::now())
+    }
+
+    fn check_at(&self, key: &str, now: DateTime<Utc>) -> Result<(), RateLimited> {
+        let limit = self.limit();
+        if limit == 0 {
+            return Ok(());
+        }
+        let window_length = Duration::minutes(1);
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > MAX_TRACKED_KEYS {
+            windows.retain(|_, (start, _)| now - *start < window_length);
+        }
+        let (start, count) = windows.entry(key.to_string()).or_insert((now, 0));
+        if now - *start >= window_length {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            return Err(RateLimited {
+                // Rounded up, to not come back a bit too early.
+                retry_after: (*start + window_length - now).num_seconds() + 1,
+            });
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// The limits per IP and per user of the HTTP server, kept by the configuration reload.
+#[derive(Clone)]
+pub struct HttpRateLimiters {
+    pub per_ip: RateLimiter,
+    pub per_user: RateLimiter,
+}
+
+impl HttpRateLimiters {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            per_ip: RateLimiter::new(config.http_rate_limit_per_ip),
+            per_user: RateLimiter::new(config.http_rate_limit_per_user),
+        }
+    }
+
+    pub fn set_limits(&self, config: &Configuration) {
+        self.per_ip.set_limit(config.http_rate_limit_per_ip);
+        self.per_user.set_limit(config.http_rate_limit_per_user);
+    }
+}
+
+/// The IP of the client: the last address of `X-Forwarded-For` when the request comes from one of
+/// the trusted reverse proxies, or else the address of the peer.
+pub fn client_ip(
+    peer_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpAddr],
+) -> String {
+    let peer_ip = match peer_addr {
+        Some(addr) => addr.ip(),
+        None => return "unknown".to_string(),
+    };
+    if trusted_proxies.contains(&peer_ip) {
+        if let Some(forwarded_ip) = headers
+            .get("X-Forwarded-For")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        {
+            return forwarded_ip.to_string();
+        }
+    }
+    peer_ip.to_string()
+}
+
+/// The logins, password changes, GraphQL and REST requests. The session refreshes and logouts are left
+/// out: the app sends them on its own.
+fn is_limited_path(path: &str) -> bool {
+    ((path == "/auth" || path.starts_with("/auth/"))
+        && path != "/auth/refresh"
+        && path != "/auth/logout")
+        || path == "/api/graphql"
+        || path == "/api/users"
+        || path.starts_with("/api/users/")
+        || path == "/api/groups"
+        || path == "/reset"
+}
+
+/// Middleware rejecting the requests of the client IPs above their limit.
+pub fn limit_request<S, B>(
+    limiter: &RateLimiter,
+    trusted_proxies: &[IpAddr],
+    request: ServiceRequest,
+    service: &S,
+) -> LocalBoxFuture<'static, actix_web::Result<ServiceResponse<B>>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    if is_limited_path(request.path()) {
+        let ip = client_ip(request.peer_addr(), request.headers(), trusted_proxies);
+        if let Err(e) = limiter.check(&ip) {
+            log::warn!("Rate limit reached for {} on {}", ip, request.path());
+            return Box::pin(futures::future::ready(Err(e.into())));
+        }
+    }
+    Box::pin(service.call(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let start = Utc::now();
+        limiter.check_at("bob", start).unwrap();
+        limiter.check_at("bob", start).unwrap();
+        limiter.check_at("john", start).unwrap();
+        assert_eq!(
+            limiter.check_at("bob", start + Duration::seconds(20)),
+            Err(RateLimited { retry_after: 41 })
+        );
+        limiter
+            .check_at("bob", start + Duration::minutes(1))
+            .unwrap();
+
+        let disabled = RateLimiter::new(0);
+        for _ in 0..10 {
+            disabled.check_at("bob", start).unwrap();
+        }
+
+        // The clones share the new limit.
+        let clone = limiter.clone();
+        limiter.set_limit(3);
+        clone.check_at("bob", start + Duration::minutes(1)).unwrap();
+        clone.check_at("bob", start + Duration::minutes(1)).unwrap();
+        assert!(clone.check_at("bob", start + Duration::minutes(1)).is_err());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxies: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_static("1.2.3.4, 5.6.7.8"),
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.1:1234".parse().unwrap()), &headers, &proxies),
+            "5.6.7.8"
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.2:1234".parse().unwrap()), &headers, &proxies),
+            "10.0.0.2"
+        );
+        assert_eq!(
+            client_ip(
+                Some("10.0.0.1:1234".parse().unwrap()),
+                &HeaderMap::new(),
+                &proxies
+            ),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_is_limited_path() {
+        assert!(is_limited_path("/auth"));
+        assert!(is_limited_path("/auth/opaque/login/start"));
+        assert!(is_limited_path("/api/graphql"));
+        assert!(is_limited_path("/reset"));
+        assert!(is_limited_path("/api/users/bob"));
+        assert!(!is_limited_path("/auth/refresh"));
+        assert!(!is_limited_path("/authorize"));
+        assert!(!is_limited_path("/pkg/main.js"));
+    }
+}
@@ -0,0 +1,214 @@
+//! The `reset-admin-password` command, to get back in without the server: it sets a new password
+//! directly in the database, and lifts whatever keeps the admin out.
+
+use crate::{
+    domain::{
+        handler::{BackendHandler, CreateAuditLogEntryRequest, Role, UpdateUserRequest},
+        opaque_handler::OpaqueHandler,
+        sql_backend_handler::SqlBackendHandler,
+        sql_opaque_handler::register_password,
+        sql_tables::PoolOptions,
+    },
+    infra::{
+        cli::{CommandOutput, ResetAdminPasswordOpts, RunOpts},
+        configuration, sql_migrations,
+    },
+};
+use anyhow::{bail, Context, Result};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const GENERATED_PASSWORD_LENGTH: usize = 20;
+
+fn generate_password() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn check_password(password: &str) -> Result<()> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        bail!(
+            "The password must be at least {} characters long",
+            MIN_PASSWORD_LENGTH
+        );
+    }
+    Ok(())
+}
+
+fn prompt_password() -> Result<String> {
+    let password = rpassword::read_password_from_tty(Some("New password: "))
+        .context("Could not read the password, use --password or --generate")?;
+    check_password(&password)?;
+    let confirmation = rpassword::read_password_from_tty(Some("Confirm the password: "))
+        .context("Could not read the password confirmation")?;
+    if password != confirmation {
+        bail!("The passwords don't match");
+    }
+    Ok(password)
+}
+
+/// Sets the password of the user, and makes sure they can log in as an admin: enabled, not
+/// expired, not locked and in the admin group.
+async fn reset_password<Handler: BackendHandler + OpaqueHandler>(
+    handler: &Handler,
+    user_id: &str,
+    password: &str,
+) -> Result<()> {
+    // Fails with a "not found" error before any change.
+    handler.get_user_details(user_id).await?;
+    register_password(handler, user_id, password)
+        .await
+        .context("Could not set the password")?;
+    handler
+        .update_user(UpdateUserRequest {
+            user_id: user_id.to_string(),
+            enabled: Some(true),
+            valid_until: Some(None),
+            ..Default::default()
+        })
+        .await?;
+    handler.unlock_user(user_id).await?;
+    let admin_group = Role::Admin.group_name();
+    let group_id = match handler
+        .list_groups()
+        .await?
+        .into_iter()
+        .find(|g| g.display_name == admin_group)
+    {
+        Some(group) => group.id,
+        None => handler.create_group(admin_group).await?,
+    };
+    if !handler
+        .get_user_groups(user_id)
+        .await?
+        .iter()
+        .any(|g| g.0 == group_id)
+    {
+        handler.add_user_to_group(user_id, group_id).await?;
+    }
+    handler
+        .add_audit_log_entry(CreateAuditLogEntryRequest {
+            actor: user_id.to_string(),
+            source: "cli".to_string(),
+            action: "resetAdminPassword".to_string(),
+            target: format!("user:{}", user_id),
+            details: None,
+        })
+        .await?;
+    Ok(())
+}
+
+async fn run_reset_admin_password(opts: ResetAdminPasswordOpts) -> Result<CommandOutput> {
+    let config = configuration::init(RunOpts {
+        config_file: opts.config_file,
+        ldap_port: None,
+        ldaps_port: None,
+        verbose: false,
+    })?;
+    crate::infra::logging::init(config.clone())?;
+    let user_id = opts.user.unwrap_or_else(|| config.ldap_user_dn.clone());
+    let (password, generated) = match (opts.password, opts.generate) {
+        (Some(password), _) => {
+            check_password(&password)?;
+            (password, false)
+        }
+        (None, true) => (generate_password(), true),
+        (None, false) => (prompt_password()?, false),
+    };
+    let pool = PoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .context("Could not connect to the database")?;
+    sql_migrations::migrate_on_startup(&pool, config.auto_migrate).await?;
+    let is_ldap_admin = user_id == config.ldap_user_dn;
+    let handler = SqlBackendHandler::new(config, pool);
+    reset_password(&handler, &user_id, &password).await?;
+    let mut text = if generated {
+        format!("New password for {}: {}", user_id, password)
+    } else {
+        format!("Password reset for {}", user_id)
+    };
+    if is_ldap_admin {
+        text.push_str(
+            "\nThe LDAP binds as this user still use `ldap_user_pass` from the configuration.",
+        );
+    }
+    let mut result = CommandOutput::default();
+    result.text = Some(text);
+    result.data.insert("user_id".to_string(), user_id.into());
+    if generated {
+        result.data.insert("password".to_string(), password.into());
+    }
+    Ok(result)
+}
+
+pub fn reset_admin_password_command(opts: ResetAdminPasswordOpts) -> Result<CommandOutput> {
+    actix_rt::System::new().block_on(run_reset_admin_password(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            handler::{BindRequest, CreateUserRequest, LoginHandler},
+            sql_tables::get_test_pool,
+        },
+        infra::configuration::ConfigurationBuilder,
+    };
+
+    #[test]
+    fn test_passwords() {
+        assert!(check_password("short").is_err());
+        assert!(check_password("long enough").is_ok());
+        let password = generate_password();
+        assert_eq!(password.len(), GENERATED_PASSWORD_LENGTH);
+        assert!(check_password(&password).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_reset_password() {
+        let pool = get_test_pool().await;
+        sql_migrations::migrate_to(&pool, sql_migrations::latest_version())
+            .await
+            .unwrap();
+        let handler =
+            SqlBackendHandler::new(ConfigurationBuilder::default().build().unwrap(), pool);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: "bob".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: "bob".to_string(),
+                enabled: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        reset_password(&handler, "bob", "new password")
+            .await
+            .unwrap();
+
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "new password".to_string(),
+            })
+            .await
+            .unwrap();
+        let groups = handler.get_user_groups("bob").await.unwrap();
+        assert!(groups.iter().any(|g| g.1 == Role::Admin.group_name()));
+        assert!(reset_password(&handler, "nobody", "new password")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Standalone password change page, rendered by the server.
+//!
+//! It doesn't load the WASM app, so it's quick to open on a phone from a link in an email. Since
+//! the OPAQUE client runs in the app, the passwords are sent to the server (over HTTPS, as for
+//! LDAP binds) which does both sides of the protocol.
+
+use crate::{
+    domain::{
+        error::DomainError,
+        handler::{BindRequest, LoginHandler},
+        opaque_handler::OpaqueHandler,
+        sql_opaque_handler::register_password,
+    },
+    infra::tcp_server::AppState,
+};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordForm {
+    username: String,
+    old_password: String,
+    new_password: String,
+    confirm_password: String,
+    /// Set by the script of the page when the server asks for a proof of work.
+    proof_of_work: Option<String>,
+}
+
+/// Solves the proof of work asked by the server (if any) before sending the form.
+const PROOF_OF_WORK_SCRIPT: &str = r#"<script>
+  const form = document.getElementById("reset-form");
+  form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    const { challenge, difficulty } = await (await fetch("/auth/proof_of_work")).json();
+    const encoder = new TextEncoder();
+    for (let nonce = 0; difficulty > 0; nonce++) {
+      const hash = new Uint8Array(
+        await crypto.subtle.digest("SHA-256", encoder.encode(challenge + ":" + nonce)));
+      let bits = 0;
+      for (const byte of hash) {
+        bits += byte === 0 ? 8 : Math.clz32(byte) - 24;
+        if (byte !== 0) break;
+      }
+      if (bits >= difficulty) {
+        form.proof_of_work.value = challenge + ":" + nonce;
+        break;
+      }
+    }
+    form.submit();
+  });
+</script>"#;
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render_page(username: &str, message: Option<(&str, &str)>) -> HttpResponse {
+    let message = message
+        .map(|(class, text)| {
+            format!(
+                r#"<div class="alert alert-{}">{}</div>"#,
+                class,
+                html_escape(text)
+            )
+        })
+        .unwrap_or_default();
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>LLDAP - Change password</title>
+  <link
+    href="https://cdn.jsdelivr.net/npm/bootstrap@5.0.1/dist/css/bootstrap.min.css"
+    rel="stylesheet"
+    integrity="sha384-+0n0xVW2eSR5OomGNYDnhzAbDsOXxcvSN1TPprVMTNDbiYZCxYbOOl7+AMvyTG2x"
+    crossorigin="anonymous" />
+</head>
+<body>
+  <div class="container py-3" style="max-width: 500px">
+    <h1>Change password</h1>
+    {message}
+    <form method="post" action="/reset" id="reset-form">
+      <input type="hidden" name="proof_of_work" />
+      <div class="mb-3">
+        <label for="username" class="form-label">User name</label>
+        <input type="text" class="form-control" id="username" name="username"
+          autocomplete="username" required value="{username}" />
+      </div>
+      <div class="mb-3">
+        <label for="old_password" class="form-label">Current password</label>
+        <input type="password" class="form-control" id="old_password" name="old_password"
+          autocomplete="current-password" required />
+      </div>
+      <div class="mb-3">
+        <label for="new_password" class="form-label">New password</label>
+        <input type="password" class="form-control" id="new_password" name="new_password"
+          autocomplete="new-password" required minlength="{min_length}" />
+      </div>
+      <div class="mb-3">
+        <label for="confirm_password" class="form-label">Confirm new password</label>
+        <input type="password" class="form-control" id="confirm_password"
+          name="confirm_password" autocomplete="new-password" required
+          minlength="{min_length}" />
+      </div>
+      <button type="submit" class="btn btn-primary">Change password</button>
+    </form>
+  </div>
+  {script}
+</body>
+</html>
+"#,
+            script = PROOF_OF_WORK_SCRIPT,
+            message = message,
+            username = html_escape(username),
+            min_length = MIN_PASSWORD_LENGTH,
+        ))
+}
+
+enum ChangePasswordError {
+    InvalidCredentials,
+    Internal(DomainError),
+}
+
+/// Checks the current password and sets the new one. The login can be an email, like for the
+/// binds: the password is set on the user it resolves to.
+async fn change_password<Backend>(
+    backend_handler: &Backend,
+    login: &str,
+    old_password: String,
+    new_password: &str,
+) -> Result<(), ChangePasswordError>
+where
+    Backend: LoginHandler + OpaqueHandler,
+{
+    let user_id = backend_handler
+        .get_user_id_for_login(login)
+        .await
+        .map_err(|_| ChangePasswordError::InvalidCredentials)?;
+    backend_handler
+        .bind(BindRequest {
+            name: user_id.clone(),
+            password: old_password,
+        })
+        .await
+        .map_err(|_| ChangePasswordError::InvalidCredentials)?;
+    register_password(backend_handler, &user_id, new_password)
+        .await
+        .map_err(ChangePasswordError::Internal)
+}
+
+async fn get_reset_page() -> HttpResponse {
+    render_page("", None)
+}
+
+async fn post_reset_page<Backend>(
+    data: web::Data<AppState<Backend>>,
+    form: web::Form<ChangePasswordForm>,
+) -> HttpResponse
+where
+    Backend: LoginHandler + OpaqueHandler + 'static,
+{
+    let form = form.into_inner();
+    if let Err(e) = data.user_rate_limiter.check(&form.username.to_lowercase()) {
+        return HttpResponse::from_error(e.into());
+    }
+    if data
+        .proof_of_work
+        .check(form.proof_of_work.as_deref())
+        .is_err()
+    {
+        return render_page(
+            &form.username,
+            Some(("danger", "Missing or invalid proof of work, try again")),
+        );
+    }
+    if form.new_password != form.confirm_password {
+        return render_page(
+            &form.username,
+            Some(("danger", "The passwords don't match")),
+        );
+    }
+    if form.new_password.len() < MIN_PASSWORD_LENGTH {
+        return render_page(
+            &form.username,
+            Some((
+                "danger",
+                &format!(
+                    "The new password should be at least {} characters long",
+                    MIN_PASSWORD_LENGTH
+                ),
+            )),
+        );
+    }
+    match change_password(
+        &data.backend_handler,
+        &form.username,
+        form.old_password,
+        &form.new_password,
+    )
+    .await
+    {
+        Ok(()) => render_page(
+            &form.username,
+            Some(("success", "Your password was changed")),
+        ),
+        Err(ChangePasswordError::InvalidCredentials) => render_page(
+            &form.username,
+            Some(("danger", "Invalid user name or password")),
+        ),
+        Err(ChangePasswordError::Internal(e)) => {
+            log::error!(
+                "Error while changing the password of {}: {}",
+                form.username,
+                e
+            );
+            render_page(
+                &form.username,
+                Some(("danger", "Error while changing the password")),
+            )
+        }
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: LoginHandler + OpaqueHandler + 'static,
+{
+    cfg.service(
+        web::resource("/reset")
+            .route(web::get().to(get_reset_page))
+            .route(web::post().to(post_reset_page::<Backend>)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            handler::{BackendHandler, CreateUserRequest},
+            sql_backend_handler::SqlBackendHandler,
+            sql_tables::{get_test_pool, init_table},
+        },
+        infra::configuration::ConfigurationBuilder,
+    };
+
+    #[tokio::test]
+    async fn test_change_password_with_email() {
+        let sql_pool = get_test_pool().await;
+        init_table(&sql_pool).await.unwrap();
+        let config = ConfigurationBuilder::default()
+            .allow_email_login(true)
+            .build()
+            .unwrap();
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: "bob".to_string(),
+                email: "bob@bob.bob".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        register_password(&handler, "bob", "password1")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            change_password(&handler, "bob@bob.bob", "wrong".to_string(), "password2").await,
+            Err(ChangePasswordError::InvalidCredentials)
+        ));
+        assert!(change_password(
+            &handler,
+            "bob@bob.bob",
+            "password1".to_string(),
+            "password2"
+        )
+        .await
+        .is_ok());
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "password2".to_string(),
+            })
+            .await
+            .unwrap();
+        handler
+            .bind(BindRequest {
+                name: "bob".to_string(),
+                password: "password1".to_string(),
+            })
+            .await
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,321 @@
+//! Small read-only JSON API, for the scripts and integrations that don't want a GraphQL client:
+//! `GET /api/users`, `/api/users/{id}` and `/api/groups`. The fields are kept stable: add new ones,
+//! don't rename them. Authenticated like GraphQL, with a JWT or an API token, and the same rights.
+
+use crate::{
+    domain::{
+        error::DomainError,
+        handler::{BackendHandler, Group, User, API_TOKEN_PREFIX},
+    },
+    infra::{
+        auth_service::{check_if_api_token_is_valid, check_if_token_is_valid, ValidationResults},
+        tcp_backend_handler::TcpBackendHandler,
+        tcp_server::{error_to_http_response, AppState},
+    },
+};
+use actix_web::{web, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct RestUser {
+    pub id: String,
+    pub email: String,
+    pub display_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub creation_date: chrono::DateTime<chrono::Utc>,
+    /// Enabled, and not expired.
+    pub active: bool,
+    /// Only filled for a single user: the names of their groups, sorted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+}
+
+impl From<User> for RestUser {
+    fn from(user: User) -> Self {
+        Self {
+            active: user.is_active(),
+            id: user.user_id,
+            email: user.email,
+            display_name: user.display_name,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            creation_date: user.creation_date,
+            groups: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct RestGroup {
+    pub id: i32,
+    pub display_name: String,
+    pub description: Option<String>,
+    pub email: Option<String>,
+    /// The user IDs of the members, including the members of the subgroups.
+    pub members: Vec<String>,
+}
+
+impl From<Group> for RestGroup {
+    fn from(group: Group) -> Self {
+        Self {
+            id: group.id.0,
+            display_name: group.display_name,
+            description: group.description,
+            email: group.email,
+            members: group.users,
+        }
+    }
+}
+
+fn to_http_response(error: DomainError) -> HttpResponse {
+    match error {
+        DomainError::DatabaseError(sqlx::Error::RowNotFound) => {
+            HttpResponse::NotFound().body("Not found")
+        }
+        e => error_to_http_response(e),
+    }
+}
+
+async fn list_users<Backend: BackendHandler>(
+    handler: &Backend,
+    validation_result: &ValidationResults,
+) -> Result<Vec<RestUser>, HttpResponse> {
+    if !validation_result.can_read_all() {
+        return Err(HttpResponse::Forbidden().body("Unauthorized access to user list"));
+    }
+    Ok(handler
+        .list_users(None)
+        .await
+        .map_err(to_http_response)?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
+async fn get_user<Backend: BackendHandler>(
+    handler: &Backend,
+    validation_result: &ValidationResults,
+    user_id: &str,
+) -> Result<RestUser, HttpResponse> {
+    if !validation_result.can_access(user_id) {
+        return Err(HttpResponse::Forbidden().body("Unauthorized access to user data"));
+    }
+    let mut user = RestUser::from(
+        handler
+            .get_user_details(user_id)
+            .await
+            .map_err(to_http_response)?,
+    );
+    let mut groups: Vec<String> = handler
+        .get_user_groups(user_id)
+        .await
+        .map_err(to_http_response)?
+        .into_iter()
+        .map(|g| g.1)
+        .collect();
+    groups.sort();
+    user.groups = Some(groups);
+    Ok(user)
+}
+
+async fn list_groups<Backend: BackendHandler>(
+    handler: &Backend,
+    validation_result: &ValidationResults,
+) -> Result<Vec<RestGroup>, HttpResponse> {
+    if !validation_result.can_read_all() {
+        return Err(HttpResponse::Forbidden().body("Unauthorized access to group list"));
+    }
+    Ok(handler
+        .list_groups()
+        .await
+        .map_err(to_http_response)?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
+pub(crate) async fn authenticate<Backend>(
+    data: &AppState<Backend>,
+    bearer: &BearerAuth,
+) -> Result<ValidationResults, HttpResponse>
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let validation_result = if bearer.token().starts_with(API_TOKEN_PREFIX) {
+        check_if_api_token_is_valid(data, bearer.token()).await
+    } else {
+        check_if_token_is_valid(data, bearer.token()).await
+    }
+    .map_err(HttpResponse::from_error)?;
+    data.user_rate_limiter
+        .check(&validation_result.user)
+        .map_err(HttpResponse::from_error)?;
+    Ok(validation_result)
+}
+
+fn to_json<T: Serialize>(result: Result<T, HttpResponse>) -> HttpResponse {
+    match result {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(response) => response,
+    }
+}
+
+async fn get_users_route<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    match authenticate(&data, &bearer).await {
+        Ok(v) => to_json(list_users(&data.backend_handler, &v).await),
+        Err(response) => response,
+    }
+}
+
+async fn get_user_route<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    user_id: web::Path<String>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    match authenticate(&data, &bearer).await {
+        Ok(v) => to_json(get_user(&data.backend_handler, &v, &user_id).await),
+        Err(response) => response,
+    }
+}
+
+async fn get_groups_route<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    match authenticate(&data, &bearer).await {
+        Ok(v) => to_json(list_groups(&data.backend_handler, &v).await),
+        Err(response) => response,
+    }
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    cfg.service(web::resource("/users").route(web::get().to(get_users_route::<Backend>)))
+        .service(web::resource("/users/{user_id}").route(web::get().to(get_user_route::<Backend>)))
+        .service(web::resource("/groups").route(web::get().to(get_groups_route::<Backend>)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::handler::{GroupId, GroupIdAndName, MockTestBackendHandler};
+    use mockall::predicate::eq;
+    use std::collections::HashSet;
+
+    fn regular_user(user: &str) -> ValidationResults {
+        ValidationResults::from_groups(user.to_string(), std::iter::empty())
+    }
+
+    #[actix_rt::test]
+    async fn test_get_user_with_groups() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_details()
+            .with(eq("bob"))
+            .return_once(|_| {
+                Ok(User {
+                    user_id: "bob".to_string(),
+                    email: "bob@example.com".to_string(),
+                    enabled: true,
+                    ..Default::default()
+                })
+            });
+        mock.expect_get_user_groups()
+            .with(eq("bob"))
+            .return_once(|_| {
+                let mut groups = HashSet::new();
+                groups.insert(GroupIdAndName(GroupId(3), "media".to_string()));
+                groups.insert(GroupIdAndName(GroupId(2), "family".to_string()));
+                Ok(groups)
+            });
+        let user = get_user(&mock, &regular_user("bob"), "bob").await.unwrap();
+        assert_eq!(user.id, "bob");
+        assert!(user.active);
+        assert_eq!(
+            user.groups,
+            Some(vec!["family".to_string(), "media".to_string()])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_regular_users_only_see_themselves() {
+        let mock = MockTestBackendHandler::new();
+        let validation_result = regular_user("bob");
+        assert_eq!(
+            get_user(&mock, &validation_result, "john")
+                .await
+                .unwrap_err()
+                .status(),
+            403
+        );
+        assert_eq!(
+            list_users(&mock, &validation_result)
+                .await
+                .unwrap_err()
+                .status(),
+            403
+        );
+        assert_eq!(
+            list_groups(&mock, &validation_result)
+                .await
+                .unwrap_err()
+                .status(),
+            403
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_unknown_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_details()
+            .return_once(|_| Err(DomainError::DatabaseError(sqlx::Error::RowNotFound)));
+        assert_eq!(
+            get_user(&mock, &ValidationResults::admin(), "nobody")
+                .await
+                .unwrap_err()
+                .status(),
+            404
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_list_groups() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().return_once(|| {
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "lldap_admin".to_string(),
+                description: None,
+                email: None,
+                users: vec!["admin".to_string()],
+            }])
+        });
+        assert_eq!(
+            list_groups(&mock, &ValidationResults::admin())
+                .await
+                .unwrap(),
+            vec![RestGroup {
+                id: 1,
+                display_name: "lldap_admin".to_string(),
+                description: None,
+                email: None,
+                members: vec!["admin".to_string()],
+            }]
+        );
+    }
+}
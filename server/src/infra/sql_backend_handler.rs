@@ -1,5 +1,9 @@
-use super::{jwt_sql_tables::*, tcp_backend_handler::*};
-use crate::domain::{error::*, sql_backend_handler::SqlBackendHandler};
+use super::{db_pool::PoolStats, jwt_sql_tables::*, tcp_backend_handler::*};
+use crate::domain::{
+    error::*,
+    sql_backend_handler::{hash_api_token, is_active_user, SqlBackendHandler},
+    sql_tables::{ApiTokens, OidcClients, OidcConsents, Users},
+};
 use async_trait::async_trait;
 use futures_util::StreamExt;
 use sea_query::{Expr, Iden, Query, SimpleExpr};
@@ -41,18 +45,21 @@ impl TcpBackendHandler for SqlBackendHandler {
             refresh_token.hash(&mut s);
             s.finish()
         };
-        let duration = chrono::Duration::days(30);
+        let duration = chrono::Duration::days(self.config.refresh_token_duration_days);
+        let session_generation = self.get_session_generation(user).await?;
         let query = Query::insert()
             .into_table(JwtRefreshStorage::Table)
             .columns(vec![
                 JwtRefreshStorage::RefreshTokenHash,
                 JwtRefreshStorage::UserId,
                 JwtRefreshStorage::ExpiryDate,
+                JwtRefreshStorage::SessionGeneration,
             ])
             .values_panic(vec![
                 (refresh_token_hash as i64).into(),
                 user.into(),
                 (chrono::Utc::now() + duration).naive_utc().into(),
+                session_generation.into(),
             ])
             .to_string(DbQueryBuilder {});
         sqlx::query(&query).execute(&self.sql_pool).await?;
@@ -63,8 +70,23 @@ impl TcpBackendHandler for SqlBackendHandler {
         let query = Query::select()
             .expr(SimpleExpr::Value(1.into()))
             .from(JwtRefreshStorage::Table)
+            .inner_join(
+                Users::Table,
+                Expr::tbl(JwtRefreshStorage::Table, JwtRefreshStorage::UserId)
+                    .equals(Users::Table, Users::UserId),
+            )
             .and_where(Expr::col(JwtRefreshStorage::RefreshTokenHash).eq(refresh_token_hash as i64))
-            .and_where(Expr::col(JwtRefreshStorage::UserId).eq(user))
+            .and_where(Expr::tbl(JwtRefreshStorage::Table, JwtRefreshStorage::UserId).eq(user))
+            // Disabled or expired users can't refresh their session.
+            .and_where(is_active_user())
+            // Nor can they after changing their password.
+            .and_where(
+                Expr::tbl(
+                    JwtRefreshStorage::Table,
+                    JwtRefreshStorage::SessionGeneration,
+                )
+                .equals(Users::Table, Users::SessionGeneration),
+            )
             .to_string(DbQueryBuilder {});
         Ok(sqlx::query(&query)
             .fetch_optional(&self.sql_pool)
@@ -102,4 +124,116 @@ impl TcpBackendHandler for SqlBackendHandler {
         sqlx::query(&query).execute(&self.sql_pool).await?;
         Ok(())
     }
+
+    async fn get_api_token_user(&self, token: &str) -> DomainResult<Option<String>> {
+        let query = Query::select()
+            .column((ApiTokens::Table, ApiTokens::UserId))
+            .from(ApiTokens::Table)
+            .inner_join(
+                Users::Table,
+                Expr::tbl(ApiTokens::Table, ApiTokens::UserId).equals(Users::Table, Users::UserId),
+            )
+            .and_where(is_active_user())
+            .and_where(Expr::col(ApiTokens::TokenHash).eq(hash_api_token(token)))
+            .and_where(
+                Expr::col(ApiTokens::ExpiryDate)
+                    .is_null()
+                    .or(Expr::col(ApiTokens::ExpiryDate).gt(chrono::Utc::now().naive_utc())),
+            )
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| row.get::<String, _>(&*ApiTokens::UserId.to_string()))
+            .fetch_optional(&self.sql_pool)
+            .await?)
+    }
+
+    async fn check_oidc_client_secret(&self, client_id: &str, secret: &str) -> DomainResult<bool> {
+        let query = Query::select()
+            .column(OidcClients::ClientId)
+            .from(OidcClients::Table)
+            .and_where(Expr::col(OidcClients::ClientId).eq(client_id))
+            .and_where(Expr::col(OidcClients::SecretHash).eq(hash_api_token(secret)))
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .fetch_optional(&self.sql_pool)
+            .await?
+            .is_some())
+    }
+
+    async fn get_oidc_consent(
+        &self,
+        user: &str,
+        client_id: &str,
+    ) -> DomainResult<Option<Vec<String>>> {
+        let query = Query::select()
+            .column(OidcConsents::Scopes)
+            .from(OidcConsents::Table)
+            .and_where(Expr::col(OidcConsents::UserId).eq(user))
+            .and_where(Expr::col(OidcConsents::ClientId).eq(client_id))
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| {
+                row.get::<String, _>(&*OidcConsents::Scopes.to_string())
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect()
+            })
+            .fetch_optional(&self.sql_pool)
+            .await?)
+    }
+
+    async fn set_oidc_consent(
+        &self,
+        user: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> DomainResult<()> {
+        let mut transaction = self.sql_pool.begin().await?;
+        let query = Query::delete()
+            .from_table(OidcConsents::Table)
+            .and_where(Expr::col(OidcConsents::UserId).eq(user))
+            .and_where(Expr::col(OidcConsents::ClientId).eq(client_id))
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&mut transaction).await?;
+        let query = Query::insert()
+            .into_table(OidcConsents::Table)
+            .columns(vec![
+                OidcConsents::UserId,
+                OidcConsents::ClientId,
+                OidcConsents::Scopes,
+                OidcConsents::CreationDate,
+            ])
+            .values_panic(vec![
+                user.into(),
+                client_id.into(),
+                scopes.join(" ").into(),
+                chrono::Utc::now().naive_utc().into(),
+            ])
+            .to_string(DbQueryBuilder {});
+        sqlx::query(&query).execute(&mut transaction).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_session_generation(&self, user: &str) -> DomainResult<i32> {
+        let query = Query::select()
+            .column(Users::SessionGeneration)
+            .from(Users::Table)
+            .and_where(Expr::col(Users::UserId).eq(user))
+            .to_string(DbQueryBuilder {});
+        Ok(sqlx::query(&query)
+            .map(|row: DbRow| row.get::<i32, _>(&*Users::SessionGeneration.to_string()))
+            .fetch_one(&self.sql_pool)
+            .await?)
+    }
+
+    async fn check_database(&self) -> DomainResult<()> {
+        sqlx::query("SELECT 1").execute(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    fn get_pool_stats(&self) -> PoolStats {
+        self.pool_monitor
+            .get_stats(&self.sql_pool, self.config.database_max_connections)
+    }
 }
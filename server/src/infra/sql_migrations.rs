@@ -0,0 +1,566 @@
+//! The versions of the database schema. Each migration brings the schema from the previous version
+//! to its own, and back for the down-migrations. The applied ones are recorded in the database.
+
+use crate::{
+    domain::sql_tables::{self, *},
+    infra::{
+        cli::{CommandOutput, MigrateAction, MigrateOpts, RunOpts},
+        configuration,
+        jwt_sql_tables::{self, JwtRefreshStorage, JwtSigningKeys, JwtStorage},
+    },
+};
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use log::*;
+use sea_query::{Alias, ColumnDef, Expr, Iden, Order, Query, Table};
+use sqlx::Row;
+
+/// The applied migrations.
+#[derive(Iden)]
+pub enum SchemaMigrations {
+    Table,
+    Version,
+    Description,
+    AppliedAt,
+}
+
+type MigrationStep = for<'a> fn(&'a Pool) -> BoxFuture<'a, sqlx::Result<()>>;
+
+struct Migration {
+    version: i32,
+    description: &'static str,
+    up: MigrationStep,
+    down: MigrationStep,
+}
+
+/// All the migrations, in order. A released migration must not change: add a new one instead.
+fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            description: "Initial schema",
+            up: initial_schema_up,
+            down: initial_schema_down,
+        },
+        Migration {
+            version: 2,
+            description: "Deleted users",
+            up: deleted_users_up,
+            down: deleted_users_down,
+        },
+        Migration {
+            version: 3,
+            description: "User and membership indexes",
+            up: indexes_up,
+            down: indexes_down,
+        },
+        Migration {
+            version: 4,
+            description: "TOTP recovery codes",
+            up: totp_recovery_codes_up,
+            down: totp_recovery_codes_down,
+        },
+        Migration {
+            version: 5,
+            description: "Security keys",
+            up: webauthn_credentials_up,
+            down: webauthn_credentials_down,
+        },
+        Migration {
+            version: 6,
+            description: "Custom user attributes",
+            up: user_attributes_up,
+            down: user_attributes_down,
+        },
+        Migration {
+            version: 7,
+            description: "OpenID Connect consents",
+            up: oidc_consents_up,
+            down: oidc_consents_down,
+        },
+    ]
+}
+
+/// The version of the schema expected by this version of LLDAP.
+pub fn latest_version() -> i32 {
+    migrations().last().map(|m| m.version).unwrap_or(0)
+}
+
+fn initial_schema_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    // Also brings the databases created before the schema was versioned up to date: the tables
+    // are only created if they don't exist, and the missing columns are added.
+    Box::pin(async move {
+        sql_tables::init_table(pool).await?;
+        jwt_sql_tables::init_table(pool).await
+    })
+}
+
+fn initial_schema_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        // The tables referencing others first.
+        let tables = [
+            JwtSigningKeys::Table.to_string(),
+            JwtStorage::Table.to_string(),
+            JwtRefreshStorage::Table.to_string(),
+            LoginHistory::Table.to_string(),
+            AuditLog::Table.to_string(),
+            OidcClients::Table.to_string(),
+            ApiTokens::Table.to_string(),
+            GroupManagers::Table.to_string(),
+            SubGroups::Table.to_string(),
+            Memberships::Table.to_string(),
+            Groups::Table.to_string(),
+            Users::Table.to_string(),
+        ];
+        for table in tables.iter() {
+            sqlx::query(
+                &Table::drop()
+                    .table(Alias::new(table))
+                    .if_exists()
+                    .to_string(DbQueryBuilder {}),
+            )
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    })
+}
+
+fn deleted_users_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_deleted_users_table(pool))
+}
+
+fn deleted_users_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        sqlx::query(
+            &Table::drop()
+                .table(DeletedUsers::Table)
+                .if_exists()
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    })
+}
+
+fn indexes_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_indexes(pool))
+}
+
+fn indexes_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::drop_indexes(pool))
+}
+
+fn totp_recovery_codes_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_totp_recovery_codes_table(pool))
+}
+
+fn totp_recovery_codes_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        sqlx::query(
+            &Table::drop()
+                .table(TotpRecoveryCodes::Table)
+                .if_exists()
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    })
+}
+
+fn webauthn_credentials_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_webauthn_credentials_table(pool))
+}
+
+fn webauthn_credentials_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        sqlx::query(
+            &Table::drop()
+                .table(WebauthnCredentials::Table)
+                .if_exists()
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    })
+}
+
+fn user_attributes_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_user_attributes_tables(pool))
+}
+
+fn user_attributes_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        // The values first, for their foreign key.
+        for table in [
+            UserAttributes::Table.to_string(),
+            UserAttributeSchema::Table.to_string(),
+        ]
+        .iter()
+        {
+            sqlx::query(
+                &Table::drop()
+                    .table(Alias::new(table))
+                    .if_exists()
+                    .to_string(DbQueryBuilder {}),
+            )
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    })
+}
+
+fn oidc_consents_up(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(sql_tables::init_oidc_consents_table(pool))
+}
+
+fn oidc_consents_down(pool: &Pool) -> BoxFuture<'_, sqlx::Result<()>> {
+    Box::pin(async move {
+        sqlx::query(
+            &Table::drop()
+                .table(OidcConsents::Table)
+                .if_exists()
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    })
+}
+
+async fn init_migrations_table(pool: &Pool) -> sqlx::Result<()> {
+    sqlx::query(
+        &Table::create()
+            .table(SchemaMigrations::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(SchemaMigrations::Version)
+                    .integer()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(
+                ColumnDef::new(SchemaMigrations::Description)
+                    .string_len(255)
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(SchemaMigrations::AppliedAt)
+                    .date_time()
+                    .not_null(),
+            )
+            .to_string(DbQueryBuilder {}),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The applied migrations, with when they were applied.
+async fn get_applied_migrations(
+    pool: &Pool,
+) -> sqlx::Result<Vec<(i32, chrono::DateTime<chrono::Utc>)>> {
+    let query = Query::select()
+        .column(SchemaMigrations::Version)
+        .column(SchemaMigrations::AppliedAt)
+        .from(SchemaMigrations::Table)
+        .order_by(SchemaMigrations::Version, Order::Asc)
+        .to_string(DbQueryBuilder {});
+    Ok(sqlx::query(&query)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            (
+                row.get::<i32, _>(&*SchemaMigrations::Version.to_string()),
+                row.get::<chrono::DateTime<chrono::Utc>, _>(
+                    &*SchemaMigrations::AppliedAt.to_string(),
+                ),
+            )
+        })
+        .collect())
+}
+
+/// The version of the schema of the database, 0 for an empty (or unversioned) one.
+pub async fn get_current_version(pool: &Pool) -> sqlx::Result<i32> {
+    init_migrations_table(pool).await?;
+    Ok(get_applied_migrations(pool)
+        .await?
+        .last()
+        .map(|(version, _)| *version)
+        .unwrap_or(0))
+}
+
+/// Applies or reverts the migrations to bring the schema to `target_version`, and returns the
+/// applied or reverted versions.
+pub async fn migrate_to(pool: &Pool, target_version: i32) -> Result<Vec<i32>> {
+    let current_version = get_current_version(pool).await?;
+    let latest_version = latest_version();
+    if current_version > latest_version {
+        bail!(
+            "The database schema is at version {}, from a newer version of LLDAP: this one only knows up to version {}",
+            current_version,
+            latest_version
+        );
+    }
+    if target_version < 0 || target_version > latest_version {
+        bail!(
+            "Unknown schema version {}, expected 0 to {}",
+            target_version,
+            latest_version
+        );
+    }
+    let mut changed = Vec::new();
+    if target_version >= current_version {
+        for migration in migrations()
+            .into_iter()
+            .filter(|m| m.version > current_version && m.version <= target_version)
+        {
+            info!(
+                "Applying migration {}: {}",
+                migration.version, migration.description
+            );
+            (migration.up)(pool)
+                .await
+                .context(format!("Could not apply migration {}", migration.version))?;
+            let query = Query::insert()
+                .into_table(SchemaMigrations::Table)
+                .columns(vec![
+                    SchemaMigrations::Version,
+                    SchemaMigrations::Description,
+                    SchemaMigrations::AppliedAt,
+                ])
+                .values_panic(vec![
+                    migration.version.into(),
+                    migration.description.into(),
+                    chrono::Utc::now().naive_utc().into(),
+                ])
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(pool).await?;
+            changed.push(migration.version);
+        }
+    } else {
+        for migration in migrations()
+            .into_iter()
+            .rev()
+            .filter(|m| m.version <= current_version && m.version > target_version)
+        {
+            warn!(
+                "Reverting migration {}: {}",
+                migration.version, migration.description
+            );
+            (migration.down)(pool)
+                .await
+                .context(format!("Could not revert migration {}", migration.version))?;
+            let query = Query::delete()
+                .from_table(SchemaMigrations::Table)
+                .and_where(Expr::col(SchemaMigrations::Version).eq(migration.version))
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query).execute(pool).await?;
+            changed.push(migration.version);
+        }
+    }
+    Ok(changed)
+}
+
+/// Brings the schema to the latest version when starting the server, unless `auto_migrate` is
+/// unset: then the server refuses to start until the schema is migrated with `lldap migrate`.
+pub async fn migrate_on_startup(pool: &Pool, auto_migrate: bool) -> Result<()> {
+    let current_version = get_current_version(pool).await?;
+    let latest_version = latest_version();
+    if current_version < latest_version && !auto_migrate {
+        bail!(
+            "The database schema is at version {} but this version of LLDAP needs version {}: run `lldap migrate up`, or set `auto_migrate`",
+            current_version,
+            latest_version
+        );
+    }
+    migrate_to(pool, latest_version).await?;
+    Ok(())
+}
+
+async fn run_migrate_command(opts: MigrateOpts) -> Result<CommandOutput> {
+    let config = configuration::init(RunOpts {
+        config_file: opts.config_file,
+        ldap_port: None,
+        ldaps_port: None,
+        verbose: false,
+    })?;
+    crate::infra::logging::init(config.clone())?;
+    let pool = PoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .context("Could not connect to the database")?;
+    let current_version = get_current_version(&pool).await?;
+    let target_version = match opts.action {
+        MigrateAction::Status => {
+            let applied = get_applied_migrations(&pool).await?;
+            let mut lines = vec![format!(
+                "Schema version: {} (latest: {})",
+                current_version,
+                latest_version()
+            )];
+            let mut statuses = Vec::new();
+            for migration in migrations() {
+                let applied_at = applied
+                    .iter()
+                    .find(|(version, _)| *version == migration.version)
+                    .map(|(_, date)| *date);
+                lines.push(format!(
+                    "{:>4}  {:<40} {}",
+                    migration.version,
+                    migration.description,
+                    applied_at
+                        .map(|date| format!("applied on {}", date.to_rfc3339()))
+                        .unwrap_or_else(|| "pending".to_string())
+                ));
+                statuses.push(serde_json::json!({
+                    "version": migration.version,
+                    "description": migration.description,
+                    "applied_at": applied_at.map(|date| date.to_rfc3339()),
+                }));
+            }
+            let mut result = CommandOutput::default();
+            result.text = Some(lines.join("\n"));
+            result
+                .data
+                .insert("version".to_string(), current_version.into());
+            result
+                .data
+                .insert("latest_version".to_string(), latest_version().into());
+            result
+                .data
+                .insert("migrations".to_string(), statuses.into());
+            return Ok(result);
+        }
+        MigrateAction::Up => latest_version(),
+        MigrateAction::Down => (current_version - 1).max(0),
+        MigrateAction::ToVersion { version } => version,
+    };
+    let changed = migrate_to(&pool, target_version).await?;
+    let mut result = CommandOutput::default();
+    result.text = Some(if changed.is_empty() {
+        format!("The schema is already at version {}", target_version)
+    } else {
+        format!(
+            "Migrated the schema from version {} to version {}",
+            current_version, target_version
+        )
+    });
+    result
+        .data
+        .insert("version".to_string(), target_version.into());
+    result.data.insert(
+        "changed_versions".to_string(),
+        serde_json::Value::from(changed),
+    );
+    Ok(result)
+}
+
+pub fn migrate_command(opts: MigrateOpts) -> Result<CommandOutput> {
+    actix_rt::System::new().block_on(run_migrate_command(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn count_users(pool: &Pool) -> sqlx::Result<i64> {
+        Ok(sqlx::query(&format!(
+            "SELECT COUNT(*) FROM {}",
+            Users::Table.to_string()
+        ))
+        .fetch_one(pool)
+        .await?
+        .get::<i64, _>(0))
+    }
+
+    #[actix_rt::test]
+    async fn test_migrate_up_and_down() {
+        let pool = get_test_pool().await;
+        assert_eq!(get_current_version(&pool).await.unwrap(), 0);
+        assert_eq!(
+            migrate_to(&pool, latest_version()).await.unwrap(),
+            (1..=latest_version()).collect::<Vec<_>>()
+        );
+        assert_eq!(get_current_version(&pool).await.unwrap(), latest_version());
+        assert_eq!(count_users(&pool).await.unwrap(), 0);
+        // Already there.
+        assert_eq!(
+            migrate_to(&pool, latest_version()).await.unwrap(),
+            Vec::<i32>::new()
+        );
+        migrate_to(&pool, 0).await.unwrap();
+        assert_eq!(get_current_version(&pool).await.unwrap(), 0);
+        count_users(&pool).await.unwrap_err();
+        migrate_to(&pool, latest_version() + 1).await.unwrap_err();
+    }
+
+    #[actix_rt::test]
+    async fn test_migrate_unversioned_database() {
+        let pool = get_test_pool().await;
+        // Created before the schema was versioned.
+        sql_tables::init_table(&pool).await.unwrap();
+        jwt_sql_tables::init_table(&pool).await.unwrap();
+        sqlx::query(
+            &Query::insert()
+                .into_table(Users::Table)
+                .columns(vec![
+                    Users::UserId,
+                    Users::Email,
+                    Users::DisplayName,
+                    Users::FirstName,
+                    Users::LastName,
+                    Users::CreationDate,
+                ])
+                .values_panic(vec![
+                    "bob".into(),
+                    "bob@bob".into(),
+                    "Bob".into(),
+                    "".into(),
+                    "".into(),
+                    chrono::Utc::now().naive_utc().into(),
+                ])
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        migrate_on_startup(&pool, false).await.unwrap_err();
+        migrate_on_startup(&pool, true).await.unwrap();
+        assert_eq!(get_current_version(&pool).await.unwrap(), latest_version());
+        assert_eq!(count_users(&pool).await.unwrap(), 1);
+        // Nothing left to do.
+        migrate_on_startup(&pool, false).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_refuse_newer_schema() {
+        let pool = get_test_pool().await;
+        migrate_to(&pool, latest_version()).await.unwrap();
+        sqlx::query(
+            &Query::insert()
+                .into_table(SchemaMigrations::Table)
+                .columns(vec![
+                    SchemaMigrations::Version,
+                    SchemaMigrations::Description,
+                    SchemaMigrations::AppliedAt,
+                ])
+                .values_panic(vec![
+                    (latest_version() + 1).into(),
+                    "From the future".into(),
+                    chrono::Utc::now().naive_utc().into(),
+                ])
+                .to_string(DbQueryBuilder {}),
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        migrate_on_startup(&pool, true).await.unwrap_err();
+    }
+}
@@ -0,0 +1,180 @@
+//! Copies the data of an SQLite database of LLDAP to the MySQL/MariaDB one, to switch an existing
+//! installation to the MySQL build.
+
+use crate::{
+    domain::sql_tables::{
+        ApiTokens, AuditLog, DbQueryBuilder, DeletedUsers, GroupManagers, Groups, LoginHistory,
+        Memberships, OidcClients, OidcConsents, PoolOptions, SubGroups, TotpRecoveryCodes,
+        UserAttributeSchema, UserAttributes, Users, WebauthnCredentials,
+    },
+    infra::{
+        self,
+        cli::{CommandOutput, MigrateFromSqliteOpts, RunOpts},
+        configuration,
+        jwt_sql_tables::{JwtRefreshStorage, JwtSigningKeys, JwtStorage},
+        sql_migrations,
+    },
+};
+use anyhow::{bail, Context, Result};
+use log::*;
+use sea_query::{Alias, Iden, Query, Value};
+use sqlx::{sqlite::SqliteRow, Column, Row, TypeInfo, ValueRef};
+use std::collections::HashMap;
+
+/// The tables to copy, the referenced ones first.
+fn tables() -> Vec<String> {
+    vec![
+        Users::Table.to_string(),
+        TotpRecoveryCodes::Table.to_string(),
+        WebauthnCredentials::Table.to_string(),
+        UserAttributeSchema::Table.to_string(),
+        UserAttributes::Table.to_string(),
+        Groups::Table.to_string(),
+        Memberships::Table.to_string(),
+        SubGroups::Table.to_string(),
+        GroupManagers::Table.to_string(),
+        ApiTokens::Table.to_string(),
+        OidcClients::Table.to_string(),
+        OidcConsents::Table.to_string(),
+        AuditLog::Table.to_string(),
+        LoginHistory::Table.to_string(),
+        DeletedUsers::Table.to_string(),
+        JwtRefreshStorage::Table.to_string(),
+        JwtStorage::Table.to_string(),
+        JwtSigningKeys::Table.to_string(),
+    ]
+}
+
+/// The column of the SQLite row, converted for the MySQL column of type `mysql_type`. SQLite
+/// stores the dates as text, in a format that MariaDB doesn't always accept.
+fn get_value(row: &SqliteRow, index: usize, mysql_type: &str) -> Result<Value> {
+    let raw = row.try_get_raw(index)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+    let type_name = raw.type_info().name().to_string();
+    Ok(match type_name.as_str() {
+        "INTEGER" | "BIGINT" | "BOOLEAN" => row.try_get::<i64, _>(index)?.into(),
+        "REAL" => row.try_get::<f64, _>(index)?.into(),
+        "BLOB" => row.try_get::<Vec<u8>, _>(index)?.into(),
+        _ if mysql_type == "datetime" => row
+            .try_get::<chrono::DateTime<chrono::Utc>, _>(index)?
+            .naive_utc()
+            .into(),
+        _ => row.try_get::<String, _>(index)?.into(),
+    })
+}
+
+async fn migrate(opts: MigrateFromSqliteOpts) -> Result<CommandOutput> {
+    let config = configuration::init(RunOpts {
+        config_file: opts.config_file,
+        ldap_port: None,
+        ldaps_port: None,
+        verbose: false,
+    })?;
+    infra::logging::init(config.clone())?;
+    let source = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(&opts.sqlite_url)
+        .await
+        .context(format!(
+            "Could not open the SQLite database `{}`",
+            opts.sqlite_url
+        ))?;
+    let target = PoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .context("Could not connect to the MySQL database")?;
+    sql_migrations::migrate_to(&target, sql_migrations::latest_version()).await?;
+    let existing_users: i64 = sqlx::query(&format!(
+        "SELECT COUNT(*) FROM {}",
+        Users::Table.to_string()
+    ))
+    .fetch_one(&target)
+    .await?
+    .get(0);
+    if existing_users != 0 {
+        bail!("The MySQL database already has users, the migration needs an empty database");
+    }
+    let source_tables: Vec<String> =
+        sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(&source)
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+    // Everything is copied in a single transaction, without checking the foreign keys until the
+    // end: a failure leaves the MySQL database empty.
+    let mut transaction = target.begin().await?;
+    sqlx::query("SET FOREIGN_KEY_CHECKS = 0")
+        .execute(&mut transaction)
+        .await?;
+    let mut copied = serde_json::Map::new();
+    for table in tables() {
+        if !source_tables.contains(&table) {
+            warn!("No table `{}` in the SQLite database, skipping it", table);
+            continue;
+        }
+        let column_types: HashMap<String, String> = sqlx::query(
+            "SELECT COLUMN_NAME, DATA_TYPE FROM information_schema.COLUMNS \
+             WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?",
+        )
+        .bind(&table)
+        .fetch_all(&mut transaction)
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get::<String, _>(1).to_lowercase()))
+        .collect();
+        let rows = sqlx::query(&format!("SELECT * FROM {}", table))
+            .fetch_all(&source)
+            .await?;
+        for row in &rows {
+            let mut columns = Vec::new();
+            let mut values = Vec::new();
+            for (index, column) in row.columns().iter().enumerate() {
+                // Columns dropped since, that MySQL doesn't have.
+                let mysql_type = match column_types.get(column.name()) {
+                    Some(mysql_type) => mysql_type,
+                    None => continue,
+                };
+                columns.push(Alias::new(column.name()));
+                values.push(get_value(row, index, mysql_type).context(format!(
+                    "Could not read the column `{}` of `{}`",
+                    column.name(),
+                    table
+                ))?);
+            }
+            let query = Query::insert()
+                .into_table(Alias::new(&table))
+                .columns(columns)
+                .values_panic(values)
+                .to_string(DbQueryBuilder {});
+            sqlx::query(&query)
+                .execute(&mut transaction)
+                .await
+                .context(format!("Could not copy a row of `{}`", table))?;
+        }
+        info!("Copied {} rows of `{}`", rows.len(), table);
+        copied.insert(table, rows.len().into());
+    }
+    sqlx::query("SET FOREIGN_KEY_CHECKS = 1")
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    let total: u64 = copied.values().filter_map(|count| count.as_u64()).sum();
+    let mut result = CommandOutput::default();
+    result.text = Some(format!(
+        "Copied {} rows from {} tables to the MySQL database",
+        total,
+        copied.len()
+    ));
+    result.data.insert("rows".to_string(), copied.into());
+    Ok(result)
+}
+
+pub fn migrate_from_sqlite(opts: MigrateFromSqliteOpts) -> Result<CommandOutput> {
+    actix_rt::System::new().block_on(migrate(opts))
+}
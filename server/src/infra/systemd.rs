@@ -0,0 +1,120 @@
+//! Integration with systemd: socket activation of the LDAP and HTTP listeners, and readiness
+//! notification for `Type=notify` units. Outside of systemd, none of the variables are set and
+//! this does nothing.
+
+use listenfd::ListenFd;
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, net::TcpListener, sync::Mutex};
+use tracing::{debug, warn};
+
+/// The sockets passed by systemd, with their index by name. Without `FileDescriptorName=`, the
+/// first one is the LDAP listener and the second one the HTTP listener.
+static ACTIVATED_SOCKETS: Lazy<Mutex<(ListenFd, HashMap<String, usize>)>> = Lazy::new(|| {
+    let indices = parse_listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    );
+    let sockets = ListenFd::from_env();
+    // Not meant for the children.
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    debug!("Sockets passed by systemd: {:?}", indices);
+    Mutex::new((sockets, indices))
+});
+
+fn parse_listen_fds(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+    pid: u32,
+) -> HashMap<String, usize> {
+    if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+        return HashMap::new();
+    }
+    let count = match listen_fds.and_then(|n| n.parse::<usize>().ok()) {
+        Some(count) if count > 0 => count,
+        _ => return HashMap::new(),
+    };
+    let names: Vec<&str> = match listen_fdnames {
+        Some(names) if !names.is_empty() => names.split(':').collect(),
+        _ => vec!["ldap", "http"],
+    };
+    (0..count)
+        .zip(names)
+        .map(|(index, name)| (name.to_string(), index))
+        .collect()
+}
+
+/// Takes the listener passed by systemd under that name, if any.
+pub fn take_listener(name: &str) -> Option<TcpListener> {
+    let mut sockets = ACTIVATED_SOCKETS.lock().unwrap();
+    let index = sockets.1.remove(name)?;
+    let listener = match sockets.0.take_tcp_listener(index) {
+        Ok(Some(listener)) => listener,
+        Ok(None) => return None,
+        Err(e) => {
+            warn!(
+                "The `{}` socket passed by systemd is not a TCP listener: {}",
+                name, e
+            );
+            return None;
+        }
+    };
+    if let Err(e) = listener.set_nonblocking(true) {
+        warn!(
+            "Could not use the `{}` socket passed by systemd: {}",
+            name, e
+        );
+        return None;
+    }
+    Some(listener)
+}
+
+/// Sends a state change (e.g. `READY=1`) to systemd, when started from a `Type=notify` unit.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let socket_path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    if socket_path.starts_with('@') {
+        warn!("Abstract notification sockets are not supported, not notifying systemd");
+        return;
+    }
+    let result = std::os::unix::net::UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(state.as_bytes(), &socket_path));
+    if let Err(e) = result {
+        warn!("Could not notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_fds_default_names() {
+        let sockets = parse_listen_fds(Some("42"), Some("2"), None, 42);
+        assert_eq!(sockets.get("ldap"), Some(&0));
+        assert_eq!(sockets.get("http"), Some(&1));
+    }
+
+    #[test]
+    fn test_parse_listen_fds_named() {
+        let sockets = parse_listen_fds(Some("42"), Some("1"), Some("http"), 42);
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets.get("http"), Some(&0));
+    }
+
+    #[test]
+    fn test_parse_listen_fds_other_process() {
+        assert!(parse_listen_fds(Some("41"), Some("2"), None, 42).is_empty());
+        assert!(parse_listen_fds(None, None, None, 42).is_empty());
+    }
+}
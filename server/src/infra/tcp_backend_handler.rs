@@ -1,3 +1,4 @@
+use crate::infra::db_pool::PoolStats;
 use async_trait::async_trait;
 use std::collections::HashSet;
 
@@ -10,6 +11,31 @@ pub trait TcpBackendHandler {
     async fn check_token(&self, refresh_token_hash: u64, user: &str) -> DomainResult<bool>;
     async fn blacklist_jwts(&self, user: &str) -> DomainResult<HashSet<u64>>;
     async fn delete_refresh_token(&self, refresh_token_hash: u64) -> DomainResult<()>;
+    /// Returns the user the API token belongs to, if the token exists and hasn't expired.
+    async fn get_api_token_user(&self, token: &str) -> DomainResult<Option<String>>;
+    /// Whether `secret` is the secret of the OpenID Connect client registered through the API.
+    async fn check_oidc_client_secret(&self, client_id: &str, secret: &str) -> DomainResult<bool>;
+    /// The scopes the user agreed to share with the OpenID Connect client, if they were asked.
+    async fn get_oidc_consent(
+        &self,
+        user: &str,
+        client_id: &str,
+    ) -> DomainResult<Option<Vec<String>>>;
+    /// Records that the user agreed to share the scopes with the client, replacing the previous
+    /// consent.
+    async fn set_oidc_consent(
+        &self,
+        user: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> DomainResult<()>;
+    /// Changes on every password change of the user. The tokens created with a previous value
+    /// are no longer valid.
+    async fn get_session_generation(&self, user: &str) -> DomainResult<i32>;
+    /// Fails if the database can't be reached, for the readiness probe.
+    async fn check_database(&self) -> DomainResult<()>;
+    /// The use of the database connections, for the metrics.
+    fn get_pool_stats(&self) -> PoolStats;
 }
 
 #[cfg(test)]
@@ -27,18 +53,57 @@ mockall::mock! {
     #[async_trait]
     impl BackendHandler for TestTcpBackendHandler {
         async fn list_users(&self, filters: Option<RequestFilter>) -> DomainResult<Vec<User>>;
+        async fn count_users(&self, filters: Option<RequestFilter>) -> DomainResult<i64>;
+        async fn list_users_with_duplicate_emails(&self) -> DomainResult<Vec<User>>;
         async fn list_groups(&self) -> DomainResult<Vec<Group>>;
+        async fn count_groups(&self) -> DomainResult<i64>;
         async fn get_user_details(&self, user_id: &str) -> DomainResult<User>;
-        async fn get_group_details(&self, group_id: GroupId) -> DomainResult<GroupIdAndName>;
+        async fn get_group_details(&self, group_id: GroupId) -> DomainResult<GroupDetails>;
         async fn get_user_groups(&self, user: &str) -> DomainResult<HashSet<GroupIdAndName>>;
         async fn create_user(&self, request: CreateUserRequest) -> DomainResult<()>;
         async fn update_user(&self, request: UpdateUserRequest) -> DomainResult<()>;
+        async fn rename_user(&self, user_id: &str, new_user_id: &str) -> DomainResult<()>;
+        async fn unlock_user(&self, user_id: &str) -> DomainResult<()>;
         async fn update_group(&self, request: UpdateGroupRequest) -> DomainResult<()>;
         async fn delete_user(&self, user_id: &str) -> DomainResult<()>;
+        async fn list_deleted_users(&self) -> DomainResult<Vec<DeletedUser>>;
+        async fn restore_user(&self, user_id: &str) -> DomainResult<()>;
+        async fn purge_deleted_user(&self, user_id: &str) -> DomainResult<()>;
         async fn create_group(&self, group_name: &str) -> DomainResult<GroupId>;
         async fn delete_group(&self, group_id: GroupId) -> DomainResult<()>;
         async fn add_user_to_group(&self, user_id: &str, group_id: GroupId) -> DomainResult<()>;
         async fn remove_user_from_group(&self, user_id: &str, group_id: GroupId) -> DomainResult<()>;
+        async fn add_group_to_group(&self, child: GroupId, parent: GroupId) -> DomainResult<()>;
+        async fn remove_group_from_group(&self, child: GroupId, parent: GroupId) -> DomainResult<()>;
+        async fn list_subgroups(&self, group_id: GroupId) -> DomainResult<Vec<GroupIdAndName>>;
+        async fn get_group_ancestors(&self, group_id: GroupId) -> DomainResult<Vec<GroupIdAndName>>;
+        async fn add_group_manager(&self, user_id: &str, group_id: GroupId) -> DomainResult<()>;
+        async fn remove_group_manager(&self, user_id: &str, group_id: GroupId) -> DomainResult<()>;
+        async fn list_group_managers(&self, group_id: GroupId) -> DomainResult<Vec<String>>;
+        async fn list_managed_groups(&self, user_id: &str) -> DomainResult<Vec<GroupIdAndName>>;
+        async fn create_api_token(&self, request: CreateApiTokenRequest) -> DomainResult<(ApiToken, String)>;
+        async fn list_api_tokens(&self) -> DomainResult<Vec<ApiToken>>;
+        async fn delete_api_token(&self, token_id: i32) -> DomainResult<()>;
+        async fn create_oidc_client(&self, client: OidcClient) -> DomainResult<String>;
+        async fn list_oidc_clients(&self) -> DomainResult<Vec<OidcClient>>;
+        async fn delete_oidc_client(&self, client_id: &str) -> DomainResult<()>;
+        async fn import_password_hash(&self, user_id: &str, password_hash: &str) -> DomainResult<()>;
+        async fn add_audit_log_entry(&self, request: CreateAuditLogEntryRequest) -> DomainResult<()>;
+        async fn list_audit_log(&self, offset: i64, limit: i64) -> DomainResult<Vec<AuditLogEntry>>;
+        async fn add_login_history_entry(&self, request: CreateLoginHistoryEntryRequest) -> DomainResult<()>;
+        async fn list_login_history(&self, user_id: &str, limit: i64) -> DomainResult<Vec<LoginHistoryEntry>>;
+        async fn is_totp_enabled(&self, user_id: &str) -> DomainResult<bool>;
+        async fn start_totp_enrollment(&self, user_id: &str) -> DomainResult<String>;
+        async fn confirm_totp_enrollment(&self, user_id: &str, code: &str) -> DomainResult<Vec<String>>;
+        async fn disable_totp(&self, user_id: &str) -> DomainResult<()>;
+        async fn list_webauthn_credentials(&self, user_id: &str) -> DomainResult<Vec<WebauthnCredential>>;
+        async fn add_webauthn_credential(&self, request: CreateWebauthnCredentialRequest) -> DomainResult<()>;
+        async fn delete_webauthn_credential(&self, user_id: &str, credential_id: &str) -> DomainResult<()>;
+        async fn list_user_attribute_schema(&self) -> DomainResult<Vec<AttributeSchema>>;
+        async fn add_user_attribute(&self, schema: AttributeSchema) -> DomainResult<()>;
+        async fn delete_user_attribute(&self, name: &str) -> DomainResult<()>;
+        async fn get_user_attributes(&self, user_id: &str) -> DomainResult<Vec<AttributeValue>>;
+        async fn set_user_attribute(&self, user_id: &str, name: &str, values: Vec<String>) -> DomainResult<()>;
     }
     #[async_trait]
     impl TcpBackendHandler for TestTcpBackendHandler {
@@ -47,5 +112,12 @@ mockall::mock! {
         async fn check_token(&self, refresh_token_hash: u64, user: &str) -> DomainResult<bool>;
         async fn blacklist_jwts(&self, user: &str) -> DomainResult<HashSet<u64>>;
         async fn delete_refresh_token(&self, refresh_token_hash: u64) -> DomainResult<()>;
+        async fn get_api_token_user(&self, token: &str) -> DomainResult<Option<String>>;
+        async fn check_oidc_client_secret(&self, client_id: &str, secret: &str) -> DomainResult<bool>;
+        async fn get_oidc_consent(&self, user: &str, client_id: &str) -> DomainResult<Option<Vec<String>>>;
+        async fn set_oidc_consent(&self, user: &str, client_id: &str, scopes: &[String]) -> DomainResult<()>;
+        async fn get_session_generation(&self, user: &str) -> DomainResult<i32>;
+        async fn check_database(&self) -> DomainResult<()>;
+        fn get_pool_stats(&self) -> PoolStats;
     }
 }
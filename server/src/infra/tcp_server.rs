@@ -1,22 +1,50 @@
 use crate::{
     domain::{
         error::DomainError,
-        handler::{BackendHandler, LoginHandler},
+        handler::{BackendHandler, ChangeNotifier, LoginHandler},
         opaque_handler::OpaqueHandler,
     },
-    infra::{auth_service, configuration::Configuration, tcp_backend_handler::*},
+    infra::{
+        acme::AcmeClient,
+        auth_service,
+        branding::Branding,
+        configuration::Configuration,
+        db_cleaner::Scheduler,
+        jwt_keys::JwtKeyStore,
+        listeners::{listen_addresses, local_address},
+        logging,
+        oidc::OidcProvider,
+        proof_of_work::ProofOfWork,
+        rate_limit::{self, HttpRateLimiters},
+        tcp_backend_handler::*,
+        tls::TlsStore,
+        webauthn::WebauthnProvider,
+    },
 };
+use actix::Addr;
+use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
-use actix_http::HttpServiceBuilder;
+use actix_http::{error::DispatchError, HttpServiceBuilder, Protocol};
+use actix_rt::net::TcpStream;
 use actix_server::ServerBuilder;
-use actix_service::map_config;
-use actix_web::{dev::AppConfig, web, App, HttpRequest, HttpResponse};
-use anyhow::{Context, Result};
-use hmac::{Hmac, NewMac};
-use sha2::Sha512;
+use actix_service::{fn_service, map_config, ServiceFactoryExt};
+use actix_web::{
+    dev::{AppConfig, Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderName, HeaderValue},
+    middleware::Condition,
+    web, App, HttpRequest, HttpResponse,
+};
+use anyhow::Result;
+use futures::future::Future;
 use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+use tracing::Instrument;
+
+/// Header with the `SCHEMA_VERSION` of the server, sent with every response, for the frontend to
+/// detect that it's outdated. The package version doesn't change with each build.
+pub const VERSION_HEADER: &str = "X-LLDAP-Schema-Version";
 
 async fn index(req: HttpRequest) -> actix_web::Result<NamedFile> {
     let mut path = PathBuf::new();
@@ -26,86 +54,304 @@ async fn index(req: HttpRequest) -> actix_web::Result<NamedFile> {
     Ok(NamedFile::open(path)?)
 }
 
+/// Header with the ID of the request, taken from the reverse proxy if it sets one.
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Runs the request in a span with its ID, user and operation, and logs it in the access log.
+fn log_request<S, B>(
+    request: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = actix_web::Result<ServiceResponse<B>>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    use rand::{distributions::Alphanumeric, Rng};
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect()
+        });
+    let span = tracing::info_span!(
+        "http_request",
+        request_id = %request_id,
+        user = tracing::field::Empty,
+        operation = %format!("{} {}", request.method(), request.path()),
+    );
+    let start = std::time::Instant::now();
+    let response = span.in_scope(|| service.call(request));
+    async move {
+        let response = response.instrument(span.clone()).await;
+        span.in_scope(|| match &response {
+            Ok(response) => logging::log_access(response.status().as_str(), start.elapsed()),
+            Err(e) => logging::log_access(
+                e.as_response_error().status_code().as_str(),
+                start.elapsed(),
+            ),
+        });
+        response.map(|mut response| {
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static("x-request-id"), value);
+            }
+            response
+        })
+    }
+}
+
 pub(crate) fn error_to_http_response(error: DomainError) -> HttpResponse {
     match error {
-        DomainError::AuthenticationError(_) | DomainError::AuthenticationProtocolError(_) => {
-            HttpResponse::Unauthorized()
-        }
+        DomainError::AuthenticationError(_)
+        | DomainError::UserDisabled(_)
+        | DomainError::UserLocked(_)
+        | DomainError::AuthenticationProtocolError(_) => HttpResponse::Unauthorized(),
         DomainError::DatabaseError(_)
         | DomainError::InternalError(_)
         | DomainError::UnknownCryptoError(_) => HttpResponse::InternalServerError(),
-        DomainError::Base64DecodeError(_) | DomainError::BinarySerializationError(_) => {
-            HttpResponse::BadRequest()
-        }
+        DomainError::Base64DecodeError(_)
+        | DomainError::BinarySerializationError(_)
+        | DomainError::InvalidRequest(_) => HttpResponse::BadRequest(),
     }
     .body(error.to_string())
 }
 
+/// Lets the browsers call the API from `cors_allowed_origins`, with a bearer token: the cookies
+/// are not sent cross-origin.
+fn cors(allowed_origins: &[String], allowed_headers: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST"])
+        .allowed_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
+        .allowed_headers(allowed_headers.iter().map(String::as_str))
+        .expose_headers(vec![VERSION_HEADER, "Retry-After"])
+        .max_age(3600);
+    for origin in allowed_origins {
+        cors = if origin == "*" {
+            cors.allow_any_origin()
+        } else {
+            cors.allowed_origin(origin)
+        };
+    }
+    cors
+}
+
 fn http_config<Backend>(
     cfg: &mut web::ServiceConfig,
-    backend_handler: Backend,
-    jwt_secret: String,
-    jwt_blacklist: HashSet<u64>,
+    app_state: AppState<Backend>,
+    cors_origins: &[String],
+    cors_headers: &[String],
 ) where
-    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Sync + 'static,
+    Backend: TcpBackendHandler
+        + BackendHandler
+        + LoginHandler
+        + OpaqueHandler
+        + ChangeNotifier
+        + Sync
+        + 'static,
 {
-    cfg.app_data(web::Data::new(AppState::<Backend> {
-        backend_handler,
-        jwt_key: Hmac::new_varkey(jwt_secret.as_bytes()).unwrap(),
-        jwt_blacklist: RwLock::new(jwt_blacklist),
-    }))
-    // Serve index.html and main.js, and default to index.html.
-    .route(
-        "/{filename:(index\\.html|main\\.js|style\\.css)?}",
-        web::get().to(index),
-    )
-    .service(web::scope("/auth").configure(auth_service::configure_server::<Backend>))
-    // API endpoint.
-    .service(
-        web::scope("/api")
-            .wrap(auth_service::CookieToHeaderTranslatorFactory)
-            .configure(super::graphql::api::configure_endpoint::<Backend>),
-    )
-    // Serve the /pkg path with the compiled WASM app.
-    .service(Files::new("/pkg", "./app/pkg"))
-    // Default to serve index.html for unknown routes, to support routing.
-    .service(web::scope("/").route("/.*", web::get().to(index)));
+    let cors_enabled = !cors_origins.is_empty();
+    cfg.app_data(web::Data::new(app_state))
+        // Serve index.html and main.js, and default to index.html.
+        .route(
+            "/{filename:(index\\.html|main\\.js|style\\.css)?}",
+            web::get().to(index),
+        )
+        .service(
+            web::scope("/auth")
+                .wrap(auth_service::CookieToHeaderTranslatorFactory)
+                .wrap(Condition::new(
+                    cors_enabled,
+                    cors(cors_origins, cors_headers),
+                ))
+                .configure(auth_service::configure_server::<Backend>),
+        )
+        // API endpoint.
+        .service(
+            web::scope("/api")
+                .wrap(auth_service::CookieToHeaderTranslatorFactory)
+                .wrap(Condition::new(
+                    cors_enabled,
+                    cors(cors_origins, cors_headers),
+                ))
+                .configure(super::branding::configure_endpoint::<Backend>)
+                .configure(super::change_events::configure_endpoint::<Backend>)
+                .configure(super::graphql::api::configure_endpoint::<Backend>)
+                .configure(super::ldif::configure_endpoint::<Backend>)
+                .configure(super::ldap_search::configure_endpoint::<Backend>)
+                .configure(super::rest_api::configure_endpoint::<Backend>)
+                .configure(super::webauthn::configure_endpoint::<Backend>),
+        )
+        // Standalone password change page, without the WASM app.
+        .configure(super::reset_page::configure_endpoint::<Backend>)
+        .configure(super::oidc::configure_endpoint::<Backend>)
+        // Probes for Docker and Kubernetes.
+        .configure(super::health::configure_endpoint::<Backend>)
+        .configure(super::acme::configure_endpoint::<Backend>)
+        // Serve the /pkg path with the compiled WASM app.
+        .service(Files::new("/pkg", "./app/pkg"))
+        // Default to serve index.html for unknown routes, to support routing.
+        .service(web::scope("/").route("/.*", web::get().to(index)));
 }
 
 pub(crate) struct AppState<Backend> {
     pub backend_handler: Backend,
-    pub jwt_key: Hmac<Sha512>,
+    pub jwt_keys: JwtKeyStore,
     pub jwt_blacklist: RwLock<HashSet<u64>>,
+    /// Lifetime of the JWTs.
+    pub jwt_duration: chrono::Duration,
+    /// Lifetime of the invite links.
+    pub invite_duration: chrono::Duration,
+    /// Header containing the user ID, set by the reverse proxies in `trusted_proxies`.
+    pub trusted_header: Option<String>,
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Challenges the logins, when `login_proof_of_work_difficulty` is set.
+    pub proof_of_work: ProofOfWork,
+    /// Limits the logins and API requests of each user, with `http_rate_limit_per_user`.
+    pub user_rate_limiter: RateLimiter,
+    /// The OpenID Connect provider, when `oidc_issuer` is set.
+    pub oidc: Option<OidcProvider>,
+    /// The registration of the security keys, when `webauthn_origin` is set.
+    pub webauthn: Option<WebauthnProvider>,
+    /// Name, logo and color of the web app.
+    pub branding: Arc<Branding>,
+    /// Runs the background jobs.
+    pub scheduler: Addr<Scheduler>,
+    /// Base DN of the LDAP entries, for the LDIF export.
+    pub ldap_base_dn: String,
+    /// Name of the LDAP admin, whose session runs the searches of the LDAP query tester.
+    pub ldap_user_dn: String,
+    /// Address of the LDAP server, checked by the readiness probe.
+    pub ldap_address: SocketAddr,
+    /// Answers the ACME challenges, when `acme_domain` is set.
+    pub acme: Option<AcmeClient>,
 }
 
 pub async fn build_tcp_server<Backend>(
     config: &Configuration,
     backend_handler: Backend,
+    jwt_keys: JwtKeyStore,
+    scheduler: Addr<Scheduler>,
+    rate_limiters: HttpRateLimiters,
+    tls: Option<&TlsStore>,
+    acme: Option<AcmeClient>,
     server_builder: ServerBuilder,
 ) -> Result<ServerBuilder>
 where
-    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Sync + 'static,
+    Backend: TcpBackendHandler
+        + BackendHandler
+        + LoginHandler
+        + OpaqueHandler
+        + ChangeNotifier
+        + Sync
+        + 'static,
 {
-    let jwt_secret = config.jwt_secret.clone();
     let jwt_blacklist = backend_handler.get_jwt_blacklist().await?;
-    server_builder
-        .bind("http", ("0.0.0.0", config.http_port), move || {
-            let backend_handler = backend_handler.clone();
-            let jwt_secret = jwt_secret.clone();
-            let jwt_blacklist = jwt_blacklist.clone();
-            HttpServiceBuilder::new()
-                .finish(map_config(
-                    App::new().configure(move |cfg| {
-                        http_config(cfg, backend_handler, jwt_secret, jwt_blacklist)
-                    }),
-                    |_| AppConfig::default(),
-                ))
-                .tcp()
-        })
-        .with_context(|| {
-            format!(
-                "While bringing up the TCP server with port {}",
-                config.http_port
-            )
-        })
+    let jwt_duration = chrono::Duration::minutes(config.jwt_duration_minutes);
+    let invite_duration = chrono::Duration::hours(config.invite_duration_hours);
+    let trusted_header = config.trusted_header.clone();
+    let trusted_proxies = config.trusted_proxies.clone();
+    let cors_origins = config.cors_allowed_origins.clone();
+    let cors_headers = config.cors_allowed_headers.clone();
+    let proof_of_work = ProofOfWork::new(config.login_proof_of_work_difficulty);
+    let HttpRateLimiters {
+        per_ip: ip_rate_limiter,
+        per_user: user_rate_limiter,
+    } = rate_limiters;
+    let oidc = config
+        .oidc_issuer
+        .clone()
+        .map(|issuer| OidcProvider::new(issuer, config.oidc_clients.clone()));
+    let webauthn = config
+        .webauthn_origin
+        .as_deref()
+        .map(WebauthnProvider::new)
+        .transpose()?;
+    let branding = Arc::new(Branding::new(&config.branding)?);
+    let ldap_base_dn = config.ldap_base_dn.clone();
+    let ldap_user_dn = config.ldap_user_dn.clone();
+    let ldap_address = local_address(
+        listen_addresses(&config.ldap_addresses, config.ldap_port)?
+            .into_iter()
+            .next()
+            .expect("There is always at least one address"),
+    );
+    let app = move || {
+        let app_state = AppState::<Backend> {
+            backend_handler: backend_handler.clone(),
+            jwt_keys: jwt_keys.clone(),
+            jwt_blacklist: RwLock::new(jwt_blacklist.clone()),
+            jwt_duration,
+            invite_duration,
+            trusted_header: trusted_header.clone(),
+            trusted_proxies: trusted_proxies.clone(),
+            proof_of_work: proof_of_work.clone(),
+            user_rate_limiter: user_rate_limiter.clone(),
+            oidc: oidc.clone(),
+            webauthn: webauthn.clone(),
+            branding: branding.clone(),
+            scheduler: scheduler.clone(),
+            ldap_base_dn: ldap_base_dn.clone(),
+            ldap_user_dn: ldap_user_dn.clone(),
+            ldap_address,
+            acme: acme.clone(),
+        };
+        let ip_rate_limiter = ip_rate_limiter.clone();
+        let proxies = trusted_proxies.clone();
+        let cors_origins = cors_origins.clone();
+        let cors_headers = cors_headers.clone();
+        map_config(
+            App::new()
+                .wrap(
+                    actix_web::middleware::DefaultHeaders::new()
+                        .header(VERSION_HEADER, lldap_auth::SCHEMA_VERSION.to_string()),
+                )
+                .wrap_fn(move |request, service| {
+                    rate_limit::limit_request(&ip_rate_limiter, &proxies, request, service)
+                })
+                .wrap_fn(log_request)
+                .configure(move |cfg| http_config(cfg, app_state, &cors_origins, &cors_headers)),
+            |_| AppConfig::default(),
+        )
+    };
+    let server_builder = crate::infra::listeners::bind(
+        server_builder,
+        "http",
+        &config.http_addresses,
+        config.http_port,
+        {
+            let app = app.clone();
+            move || HttpServiceBuilder::new().finish(app()).tcp()
+        },
+    )?;
+    let tls = match tls {
+        None => return Ok(server_builder),
+        Some(tls) => tls.clone(),
+    };
+    crate::infra::listeners::bind(
+        server_builder,
+        "https",
+        &config.http_addresses,
+        config.https_port,
+        move || {
+            let tls = tls.clone();
+            fn_service(move |stream: TcpStream| {
+                let tls = tls.clone();
+                async move {
+                    let peer_address = stream.peer_addr().ok();
+                    let stream = tls.accept(stream).await.map_err(|e| {
+                        tracing::debug!("HTTPS connection dropped: {:#}", e);
+                        DispatchError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
+                    })?;
+                    Ok((stream, Protocol::Http1, peer_address))
+                }
+            })
+            .and_then(HttpServiceBuilder::new().finish(app()))
+        },
+    )
 }
@@ -0,0 +1,164 @@
+//! TLS of the LDAPS and HTTPS listeners, with the certificate of `tls_certificate_file` or the one
+//! obtained from the ACME server. The certificate can be replaced while the servers run, when it's
+//! renewed: the new connections use the new one.
+
+use crate::infra::{acme, configuration::Configuration};
+use anyhow::{Context, Result};
+use openssl::pkey::PKey;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_native_tls::{TlsAcceptor, TlsStream};
+use tracing::warn;
+
+/// How long a client has to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The current certificate, shared by the listeners. Empty until the ACME server issues one.
+#[derive(Clone, Default)]
+pub struct TlsStore(Arc<RwLock<Option<TlsAcceptor>>>);
+
+impl TlsStore {
+    /// `None` when TLS is disabled. With ACME, the certificate obtained before is loaded if there
+    /// is one.
+    pub fn from_config(config: &Configuration) -> Result<Option<Self>> {
+        if let (Some(certificate_file), Some(key_file)) =
+            (&config.tls_certificate_file, &config.tls_private_key_file)
+        {
+            let store = Self::default();
+            store.load_files(Path::new(certificate_file), Path::new(key_file))?;
+            return Ok(Some(store));
+        }
+        if config.acme_domain.is_some() {
+            let store = Self::default();
+            let (certificate_file, key_file) =
+                acme::certificate_files(Path::new(&config.acme_storage_directory));
+            if certificate_file.exists() {
+                if let Err(e) = store.load_files(&certificate_file, &key_file) {
+                    warn!(
+                        "Could not load the ACME certificate, requesting a new one: {:#}",
+                        e
+                    );
+                }
+            }
+            return Ok(Some(store));
+        }
+        Ok(None)
+    }
+
+    pub fn load_files(&self, certificate_file: &Path, key_file: &Path) -> Result<()> {
+        let certificate = std::fs::read(certificate_file).context(format!(
+            "Could not read the TLS certificate {:?}",
+            certificate_file
+        ))?;
+        let key = std::fs::read(key_file)
+            .context(format!("Could not read the TLS private key {:?}", key_file))?;
+        self.load(&certificate, &key)
+    }
+
+    /// Replaces the certificate with a PEM chain, starting with the certificate of the server,
+    /// and its PEM private key.
+    pub fn load(&self, certificate: &[u8], key: &[u8]) -> Result<()> {
+        // native-tls only reads the PKCS#8 keys, not the RSA or EC ones.
+        let key = PKey::private_key_from_pem(key)
+            .context("Invalid TLS private key")?
+            .private_key_to_pem_pkcs8()?;
+        let identity = native_tls::Identity::from_pkcs8(certificate, &key)
+            .context("Invalid TLS certificate, or it doesn't match the private key")?;
+        let acceptor = native_tls::TlsAcceptor::new(identity)?;
+        *self.0.write().unwrap() = Some(acceptor.into());
+        Ok(())
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.0.read().unwrap().is_some()
+    }
+
+    /// Runs the TLS handshake on a new connection. Fails while there is no certificate.
+    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let acceptor = self
+            .0
+            .read()
+            .unwrap()
+            .clone()
+            .context("No TLS certificate yet")?;
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .context("TLS handshake timed out")?
+            .context("TLS handshake failed")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        rsa::Rsa,
+        x509::{X509NameBuilder, X509},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A self-signed certificate for `domain`, valid for `days`, and its private key, in PEM.
+    pub fn self_signed_certificate(domain: &str, days: u32) -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", domain).unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(days).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (
+            builder.build().to_pem().unwrap(),
+            key.rsa().unwrap().private_key_to_pem().unwrap(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn test_accept() {
+        let store = TlsStore::default();
+        let (_client, server) = tokio::io::duplex(64 * 1024);
+        store.accept(server).await.unwrap_err();
+
+        let (certificate, key) = self_signed_certificate("lldap.example.com", 90);
+        store.load(&certificate, b"not a key").unwrap_err();
+        assert!(!store.is_loaded());
+        // A PKCS#1 RSA key, converted for native-tls.
+        store.load(&certificate, &key).unwrap();
+        assert!(store.is_loaded());
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let connector: tokio_native_tls::TlsConnector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .into();
+        let (client, server) = futures::join!(
+            connector.connect("lldap.example.com", client),
+            store.accept(server)
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        client.write_all(b"ping").await.unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+    }
+}
@@ -0,0 +1,333 @@
+//! One-way sync from an upstream LDAP server or Active Directory, to use LLDAP during a gradual
+//! migration: the hourly `upstream_sync` job imports the users and groups like `lldap import`, and
+//! makes the members of the upstream groups match. The users deleted upstream are kept.
+
+use crate::{
+    domain::{handler::BackendHandler, sql_backend_handler::SqlBackendHandler},
+    infra::{
+        cli::ImportFormat,
+        configuration::{Configuration, UpstreamSyncConfig},
+        import::{self, EntryError, ImportData, ImportReport, Mapping},
+        ldif::LdifEntry,
+    },
+};
+use anyhow::{Context, Result};
+use ldap3::{
+    adapters::{Adapter, EntriesOnly, PagedResults},
+    LdapConnAsync, Scope, SearchEntry,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Number of entries per page of results: Active Directory returns at most 1000 at once.
+const PAGE_SIZE: i32 = 500;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub import: ImportReport,
+    pub memberships_removed: usize,
+}
+
+#[derive(Clone)]
+pub struct UpstreamSync {
+    config: UpstreamSyncConfig,
+    handler: SqlBackendHandler,
+}
+
+impl UpstreamSync {
+    /// `None` if the sync is not configured.
+    pub fn from_config(config: &Configuration, handler: SqlBackendHandler) -> Option<Self> {
+        config
+            .upstream_sync
+            .clone()
+            .map(|config| Self { config, handler })
+    }
+
+    pub async fn run(&self) -> Result<SyncReport> {
+        let mapping = get_mapping(&self.config)?;
+        let (users, groups) = fetch_entries(&self.config, &mapping).await?;
+        sync(&self.handler, read_entries(&users, &groups, &mapping)).await
+    }
+}
+
+/// The LDIF mapping, with the overrides of `attribute_mappings`.
+pub fn get_mapping(config: &UpstreamSyncConfig) -> Result<Mapping> {
+    let mut mapping = Mapping::for_format(ImportFormat::Ldif);
+    for m in &config.attribute_mappings {
+        mapping.set(m)?;
+    }
+    Ok(mapping)
+}
+
+fn to_ldif_entry(entry: SearchEntry) -> LdifEntry {
+    let mut attributes = Vec::new();
+    for (name, values) in entry.attrs {
+        attributes.extend(values.into_iter().map(|v| (name.clone(), v.into_bytes())));
+    }
+    for (name, values) in entry.bin_attrs {
+        attributes.extend(values.into_iter().map(|v| (name.clone(), v)));
+    }
+    LdifEntry {
+        dn: entry.dn,
+        attributes,
+    }
+}
+
+/// Returns the users and the groups.
+async fn fetch_entries(
+    config: &UpstreamSyncConfig,
+    mapping: &Mapping,
+) -> Result<(Vec<LdifEntry>, Vec<LdifEntry>)> {
+    let (connection, mut ldap) = LdapConnAsync::new(&config.url)
+        .await
+        .with_context(|| format!("Could not connect to {}", config.url))?;
+    ldap3::drive!(connection);
+    ldap.simple_bind(&config.bind_dn, &config.bind_password)
+        .await?
+        .success()
+        .with_context(|| format!("Could not bind to {} as {}", config.url, config.bind_dn))?;
+    let mut results = Vec::new();
+    for filter in [&config.user_filter, &config.group_filter].iter() {
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(PAGE_SIZE)),
+        ];
+        // `memberOf` is an operational attribute in OpenLDAP, not returned by "*".
+        let attributes = vec!["*".to_string(), mapping.groups.clone()];
+        let mut search = ldap
+            .streaming_search_with(
+                adapters,
+                &config.base_dn,
+                Scope::Subtree,
+                filter,
+                attributes,
+            )
+            .await?;
+        let mut entries = Vec::new();
+        while let Some(entry) = search.next().await? {
+            entries.push(to_ldif_entry(SearchEntry::construct(entry)));
+        }
+        search
+            .finish()
+            .await
+            .success()
+            .with_context(|| format!("Could not search for `{}`", filter))?;
+        results.push(entries);
+    }
+    ldap.unbind().await?;
+    let groups = results.pop().unwrap();
+    let users = results.pop().unwrap();
+    Ok((users, groups))
+}
+
+/// Lowercase, without the spaces around the components, to compare the DNs.
+fn normalize_dn(dn: &[u8]) -> String {
+    String::from_utf8_lossy(dn)
+        .split(',')
+        .map(|component| component.trim().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Reads the users and groups, keeping only the memberships between them. The members are resolved
+/// from their DN, whose first component isn't always the user ID, e.g. "CN=Bob Smith" in Active
+/// Directory.
+fn read_entries(users: &[LdifEntry], groups: &[LdifEntry], mapping: &Mapping) -> ImportData {
+    let mut data = ImportData::default();
+    let mut group_names = HashMap::new();
+    let mut group_entries = Vec::new();
+    for entry in groups {
+        match import::read_ldif_group(entry) {
+            Ok(group) => {
+                group_names.insert(normalize_dn(entry.dn.as_bytes()), group.name.clone());
+                group_entries.push((entry, group));
+            }
+            Err(e) => data.errors.push(EntryError {
+                entry: entry.dn.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    let mut user_ids = HashMap::new();
+    for entry in users {
+        match import::read_ldif_user(entry, mapping) {
+            Ok(mut user) => {
+                user.groups = entry
+                    .get(&mapping.groups)
+                    .filter_map(|dn| group_names.get(&normalize_dn(dn)).cloned())
+                    .collect();
+                user_ids.insert(normalize_dn(entry.dn.as_bytes()), user.user_id.clone());
+                data.users.push(user);
+            }
+            Err(e) => data.errors.push(EntryError {
+                entry: entry.dn.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    let synced_users: HashSet<&String> = user_ids.values().collect();
+    for (entry, mut group) in group_entries {
+        group.members = entry
+            .get("member")
+            .chain(entry.get("uniqueMember"))
+            .filter_map(|dn| user_ids.get(&normalize_dn(dn)).cloned())
+            .chain(
+                entry
+                    .get("memberUid")
+                    .map(|uid| String::from_utf8_lossy(uid).into_owned())
+                    .filter(|uid| synced_users.contains(uid)),
+            )
+            .collect();
+        data.groups.push(group);
+    }
+    data
+}
+
+/// Imports the users and groups, then removes the members of the upstream groups that are not
+/// members upstream anymore.
+async fn sync<Backend: BackendHandler>(handler: &Backend, data: ImportData) -> Result<SyncReport> {
+    let mut upstream_members: HashMap<String, HashSet<String>> = data
+        .groups
+        .iter()
+        .map(|group| (group.name.clone(), group.members.iter().cloned().collect()))
+        .collect();
+    for user in &data.users {
+        for group in &user.groups {
+            upstream_members
+                .entry(group.clone())
+                .or_default()
+                .insert(user.user_id.clone());
+        }
+    }
+    let mut report = SyncReport {
+        import: import::import(handler, data).await?,
+        ..Default::default()
+    };
+    for group in handler.list_groups().await? {
+        let members = match upstream_members.get(&group.display_name) {
+            Some(members) => members,
+            None => continue,
+        };
+        for user_id in group.users.iter().filter(|u| !members.contains(*u)) {
+            match handler.remove_user_from_group(user_id, group.id).await {
+                Ok(()) => report.memberships_removed += 1,
+                Err(e) => report.import.errors.push(EntryError {
+                    entry: format!("{} in {}", user_id, group.display_name),
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::handler::{Group, GroupId, MockTestBackendHandler, User};
+    use mockall::predicate::eq;
+
+    fn entry(dn: &str, attributes: &[(&str, &str)]) -> LdifEntry {
+        LdifEntry {
+            dn: dn.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_read_active_directory_entries() {
+        let mut mapping = Mapping::for_format(ImportFormat::Ldif);
+        mapping.set("user_id=sAMAccountName").unwrap();
+        let users = vec![
+            entry(
+                "CN=Bob Smith,OU=People,DC=example,DC=com",
+                &[
+                    ("sAMAccountName", "bob"),
+                    ("mail", "bob@example.com"),
+                    ("memberOf", "CN=Devs, OU=Groups,DC=example,DC=com"),
+                    ("memberOf", "CN=Unsynced,OU=Other,DC=example,DC=com"),
+                ],
+            ),
+            entry(
+                "CN=No Mail,OU=People,DC=example,DC=com",
+                &[("sAMAccountName", "nomail")],
+            ),
+        ];
+        let groups = vec![entry(
+            "CN=Devs,OU=Groups,DC=example,DC=com",
+            &[
+                ("cn", "Devs"),
+                ("member", "cn=bob smith,ou=people,dc=example,dc=com"),
+                ("member", "CN=Other Group,OU=Groups,DC=example,DC=com"),
+            ],
+        )];
+        let data = read_entries(&users, &groups, &mapping);
+        assert_eq!(data.users.len(), 1);
+        assert_eq!(data.users[0].user_id, "bob");
+        assert_eq!(data.users[0].groups, vec!["Devs".to_string()]);
+        assert_eq!(data.groups.len(), 1);
+        assert_eq!(data.groups[0].members, vec!["bob".to_string()]);
+        assert_eq!(data.errors.len(), 1);
+        assert_eq!(
+            data.errors[0].entry,
+            "CN=No Mail,OU=People,DC=example,DC=com"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_sync_removes_former_members() {
+        let mut mock = MockTestBackendHandler::new();
+        let groups = || {
+            vec![
+                Group {
+                    id: GroupId(2),
+                    display_name: "Devs".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["bob".to_string(), "john".to_string()],
+                },
+                Group {
+                    id: GroupId(3),
+                    display_name: "Local".to_string(),
+                    description: None,
+                    email: None,
+                    users: vec!["john".to_string()],
+                },
+            ]
+        };
+        mock.expect_list_groups()
+            .times(2)
+            .returning(move || Ok(groups()));
+        mock.expect_get_user_details()
+            .with(eq("bob"))
+            .return_once(|_| Ok(User::default()));
+        mock.expect_update_user().times(1).return_once(|_| Ok(()));
+        mock.expect_remove_user_from_group()
+            .with(eq("john"), eq(GroupId(2)))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let report = sync(
+            &mock,
+            ImportData {
+                users: vec![import::ImportedUser {
+                    user_id: "bob".to_string(),
+                    email: "bob@example.com".to_string(),
+                    groups: vec!["Devs".to_string()],
+                    ..Default::default()
+                }],
+                groups: vec![import::ImportedGroup {
+                    name: "Devs".to_string(),
+                    members: vec!["bob".to_string()],
+                }],
+                errors: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.import.users_updated, 1);
+        assert_eq!(report.memberships_removed, 1);
+        assert!(report.import.errors.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! Registration of the security keys (WebAuthn credentials) of the users, from their user page.
+//!
+//! The registration takes two requests: `/api/webauthn/register/start` returns the options to
+//! pass to `navigator.credentials.create()`, and `/api/webauthn/register/finish` checks and stores
+//! the credential created by the browser. The keys are listed and removed through GraphQL.
+
+use crate::{
+    domain::handler::{
+        BackendHandler, CreateAuditLogEntryRequest, CreateWebauthnCredentialRequest,
+    },
+    infra::{
+        rest_api::authenticate,
+        tcp_backend_handler::TcpBackendHandler,
+        tcp_server::{error_to_http_response, AppState},
+    },
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use webauthn_rs::{
+    proto::RegisterPublicKeyCredential, RegistrationState, Webauthn, WebauthnConfig,
+};
+
+/// How long the user has to touch their key, between the two requests.
+const REGISTRATION_LIFETIME_MINUTES: i64 = 5;
+/// The longest name of a key, to keep the list readable.
+const MAX_NAME_LENGTH: usize = 64;
+/// The attestations are larger than the default JSON limit.
+const MAX_REQUEST_SIZE: usize = 65536;
+
+/// The relying party is LLDAP itself, identified by the domain of `webauthn_origin`.
+struct RelyingParty {
+    id: String,
+    origin: url::Url,
+}
+
+impl WebauthnConfig for RelyingParty {
+    fn get_relying_party_name(&self) -> &str {
+        "LLDAP"
+    }
+
+    fn get_origin(&self) -> &url::Url {
+        &self.origin
+    }
+
+    fn get_relying_party_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// A registration waiting for the credential created by the browser.
+struct PendingRegistration {
+    name: String,
+    state: RegistrationState,
+    expiry: DateTime<Utc>,
+}
+
+/// The state of the registrations, shared by all the HTTP workers.
+#[derive(Clone)]
+pub struct WebauthnProvider {
+    webauthn: Arc<Webauthn<RelyingParty>>,
+    /// At most one per user, by user ID.
+    pending: Arc<Mutex<HashMap<String, PendingRegistration>>>,
+}
+
+impl WebauthnProvider {
+    pub fn new(origin: &str) -> Result<Self> {
+        let origin = url::Url::parse(origin).context("Invalid `webauthn_origin`")?;
+        let id = origin
+            .domain()
+            .ok_or_else(|| anyhow!("`webauthn_origin` should have a domain name"))?
+            .to_string();
+        Ok(Self {
+            webauthn: Arc::new(Webauthn::new(RelyingParty { id, origin })),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+}
+
+fn disabled_response() -> HttpResponse {
+    HttpResponse::NotFound().body("Security keys are not enabled: set `webauthn_origin`")
+}
+
+#[derive(Deserialize)]
+pub struct RegisterStartRequest {
+    name: String,
+}
+
+async fn post_register_start<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    request: web::Json<RegisterStartRequest>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.webauthn {
+        Some(p) => p,
+        None => return disabled_response(),
+    };
+    let user_id = match authenticate(&data, &bearer).await {
+        Ok(v) => v.user,
+        Err(response) => return response,
+    };
+    let name = request.into_inner().name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return HttpResponse::BadRequest().body(format!(
+            "The name of the key should have between 1 and {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    let existing = match data
+        .backend_handler
+        .list_webauthn_credentials(&user_id)
+        .await
+    {
+        Ok(credentials) => credentials,
+        Err(e) => return error_to_http_response(e),
+    };
+    // The browser refuses to register a key twice.
+    let exclude_credentials = existing
+        .iter()
+        .filter_map(|c| base64::decode_config(&c.credential_id, base64::URL_SAFE_NO_PAD).ok())
+        .collect();
+    let (challenge, state) = match provider.webauthn.generate_challenge_register_options(
+        user_id.as_bytes().to_vec(),
+        user_id.clone(),
+        user_id.clone(),
+        Some(exclude_credentials),
+        None,
+        None,
+    ) {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!(
+                "Could not start the registration of a security key: {:?}",
+                e
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let now = Utc::now();
+    let mut pending = provider.pending.lock().unwrap();
+    pending.retain(|_, p| p.expiry > now);
+    pending.insert(
+        user_id,
+        PendingRegistration {
+            name,
+            state,
+            expiry: now + chrono::Duration::minutes(REGISTRATION_LIFETIME_MINUTES),
+        },
+    );
+    HttpResponse::Ok().json(challenge)
+}
+
+async fn post_register_finish<Backend>(
+    data: web::Data<AppState<Backend>>,
+    bearer: BearerAuth,
+    http_request: HttpRequest,
+    credential: web::Json<RegisterPublicKeyCredential>,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let provider = match &data.webauthn {
+        Some(p) => p,
+        None => return disabled_response(),
+    };
+    let user_id = match authenticate(&data, &bearer).await {
+        Ok(v) => v.user,
+        Err(response) => return response,
+    };
+    let pending = match provider.pending.lock().unwrap().remove(&user_id) {
+        Some(p) if p.expiry > Utc::now() => p,
+        _ => {
+            return HttpResponse::BadRequest()
+                .body("No registration in progress, or it expired: try again")
+        }
+    };
+    let (credential, _) =
+        match provider
+            .webauthn
+            .register_credential(&credential, &pending.state, |_| Ok(false))
+        {
+            Ok(registered) => registered,
+            Err(e) => {
+                return HttpResponse::BadRequest()
+                    .body(format!("The security key could not be verified: {:?}", e))
+            }
+        };
+    let serialized = match serde_json::to_vec(&credential) {
+        Ok(serialized) => serialized,
+        Err(e) => {
+            log::error!("Could not serialize a security key: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let credential_id = base64::encode_config(&credential.cred_id, base64::URL_SAFE_NO_PAD);
+    if let Err(e) = data
+        .backend_handler
+        .add_webauthn_credential(CreateWebauthnCredentialRequest {
+            credential_id: credential_id.clone(),
+            user_id: user_id.clone(),
+            name: pending.name.clone(),
+            credential: serialized,
+        })
+        .await
+    {
+        return error_to_http_response(e);
+    }
+    // The key is stored, so failing to record it is only logged.
+    if let Err(e) = data
+        .backend_handler
+        .add_audit_log_entry(CreateAuditLogEntryRequest {
+            actor: user_id.clone(),
+            source: http_request
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            action: "addWebauthnCredential".to_string(),
+            target: format!("user:{}", user_id),
+            details: Some(pending.name),
+        })
+        .await
+    {
+        log::error!(
+            "Could not add `addWebauthnCredential` on user:{} to the audit log: {}",
+            user_id,
+            e
+        );
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "id": credential_id }))
+}
+
+pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    cfg.service(
+        web::scope("/webauthn/register")
+            .app_data(web::JsonConfig::default().limit(MAX_REQUEST_SIZE))
+            .service(web::resource("/start").route(web::post().to(post_register_start::<Backend>)))
+            .service(
+                web::resource("/finish").route(web::post().to(post_register_finish::<Backend>)),
+            ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_provider() {
+        assert!(WebauthnProvider::new("https://lldap.example.com").is_ok());
+        assert!(WebauthnProvider::new("http://localhost:17170").is_ok());
+        assert!(WebauthnProvider::new("https://127.0.0.1").is_err());
+        assert!(WebauthnProvider::new("lldap.example.com").is_err());
+    }
+}
@@ -3,10 +3,9 @@
 
 use crate::{
     domain::{
-        handler::{BackendHandler, CreateUserRequest},
+        handler::{BackendHandler, CreateUserRequest, Role, READ_ONLY_GROUP_ALIAS},
         sql_backend_handler::SqlBackendHandler,
         sql_opaque_handler::register_password,
-        sql_tables::PoolOptions,
     },
     infra::{cli::*, configuration::Configuration, db_cleaner::Scheduler},
 };
@@ -34,7 +33,7 @@ async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration)
         .await
         .context("Error creating admin user")?;
     let admin_group_id = handler
-        .create_group("lldap_admin")
+        .create_group(Role::Admin.group_name())
         .await
         .context("Error creating admin group")?;
     handler
@@ -43,35 +42,80 @@ async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration)
         .context("Error adding admin user to group")
 }
 
-async fn run_server(config: Configuration) -> Result<()> {
-    let sql_pool = PoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await?;
-    domain::sql_tables::init_table(&sql_pool).await?;
+async fn create_role_groups(handler: &SqlBackendHandler) -> Result<()> {
+    let groups = handler.list_groups().await?;
+    let names = Role::ALL
+        .iter()
+        .map(Role::group_name)
+        .chain(std::iter::once(READ_ONLY_GROUP_ALIAS));
+    for name in names {
+        if !groups.iter().any(|g| g.display_name == name) {
+            handler
+                .create_group(name)
+                .await
+                .context(format!("Error creating group {}", name))?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_server(config: Configuration, opts: RunOpts) -> Result<()> {
+    let sql_pool = infra::db_pool::connect(&config).await?;
+    infra::sql_migrations::migrate_on_startup(&sql_pool, config.auto_migrate).await?;
     let backend_handler = SqlBackendHandler::new(config.clone(), sql_pool.clone());
+    {
+        let pool_monitor = backend_handler.pool_monitor.clone();
+        let (sql_pool, max_connections) = (sql_pool.clone(), config.database_max_connections);
+        actix_rt::spawn(async move { pool_monitor.watch(sql_pool, max_connections).await });
+    }
     if let Err(e) = backend_handler.get_user_details(&config.ldap_user_dn).await {
         warn!("Could not get admin user, trying to create it: {:#}", e);
         create_admin_user(&backend_handler, &config)
             .await
             .map_err(|e| anyhow!("Error setting up admin login/account: {:#}", e))?;
     }
+    create_role_groups(&backend_handler)
+        .await
+        .map_err(|e| anyhow!("Error setting up the role groups: {:#}", e))?;
+    let tls = infra::tls::TlsStore::from_config(&config)?;
+    let acme = infra::acme::AcmeClient::from_config(&config, tls.as_ref());
     let server_builder = infra::ldap_server::build_ldap_server(
         &config,
         backend_handler.clone(),
+        tls.as_ref(),
         actix_server::Server::build(),
     )?;
-    infra::jwt_sql_tables::init_table(&sql_pool).await?;
-    let server_builder =
-        infra::tcp_server::build_tcp_server(&config, backend_handler, server_builder).await?;
+    let jwt_keys = infra::jwt_keys::JwtKeyStore::load(&config, sql_pool.clone()).await?;
     // Run every hour.
-    let scheduler = Scheduler::new("0 0 * * * * *", sql_pool);
-    scheduler.start();
-    server_builder.workers(1).run().await?;
+    let scheduler = Scheduler::new(
+        "0 0 * * * * *",
+        sql_pool,
+        jwt_keys.clone(),
+        &config,
+        infra::upstream_sync::UpstreamSync::from_config(&config, backend_handler.clone()),
+        acme.clone(),
+    )
+    .start();
+    let rate_limiters = infra::rate_limit::HttpRateLimiters::new(&config);
+    let server_builder = infra::tcp_server::build_tcp_server(
+        &config,
+        backend_handler,
+        jwt_keys,
+        scheduler,
+        rate_limiters.clone(),
+        tls.as_ref(),
+        acme,
+        server_builder,
+    )
+    .await?;
+    infra::config_reload::reload_on_sighup(opts, config, rate_limiters)?;
+    let server = server_builder.workers(1).run();
+    infra::systemd::notify("READY=1");
+    server.await?;
     Ok(())
 }
 
-fn run_server_command(opts: RunOpts) -> Result<()> {
+fn run_server_command(opts: RunOpts) -> Result<CommandOutput> {
     let config = infra::configuration::init(opts.clone())?;
     infra::logging::init(config.clone())?;
 
@@ -81,17 +125,31 @@ fn run_server_command(opts: RunOpts) -> Result<()> {
     debug!("Configuration: {:#?}", config);
 
     actix::run(
-        run_server(config).unwrap_or_else(|e| error!("Could not bring up the servers: {:?}", e)),
+        run_server(config, opts)
+            .unwrap_or_else(|e| error!("Could not bring up the servers: {:?}", e)),
     )?;
 
     info!("End.");
-    Ok(())
+    Ok(CommandOutput::default())
 }
 
-fn main() -> Result<()> {
+fn main() {
     let cli_opts = infra::cli::init();
-    match cli_opts.command {
+    let result = match cli_opts.command {
         Command::ExportGraphQLSchema(opts) => infra::graphql::api::export_schema(opts),
         Command::Run(opts) => run_server_command(opts),
-    }
+        Command::Migrate(opts) => infra::sql_migrations::migrate_command(opts),
+        Command::Export(opts) => infra::ldif::export_command(opts),
+        Command::Import(opts) => infra::import::import_command(opts),
+        Command::Restore(opts) => infra::backup::restore_command(opts),
+        Command::ResetAdminPassword(opts) => {
+            infra::reset_admin_password::reset_admin_password_command(opts)
+        }
+        Command::User(opts) => infra::api_client::user_command(opts),
+        Command::Group(opts) => infra::api_client::group_command(opts),
+        Command::Bench(opts) => infra::bench::bench_command(opts),
+        #[cfg(feature = "mysql")]
+        Command::MigrateFromSqlite(opts) => infra::sqlite_migration::migrate_from_sqlite(opts),
+    };
+    std::process::exit(report_result(cli_opts.output, result) as i32)
 }